@@ -3,22 +3,33 @@ use rand::seq::SliceRandom;
 use stract::{
     bangs::Bangs,
     config::{
-        ApiConfig, ApiThresholds, CollectorConfig, CorrectionConfig, LLMConfig, SnippetConfig,
-        WidgetsConfig,
+        AnnotationsConfig, ApiConfig, ApiThresholds, CollectorConfig, CorrectionConfig, LLMConfig,
+        RankingPipelineConfig, ShardLoadConfig, SnippetConfig, WidgetsConfig,
     },
     image_store::Image,
     index::Index,
     inverted_index::RetrievedWebpage,
-    ranking::{inbound_similarity::InboundSimilarity, pipeline::RetrievedWebpageRanking},
-    searcher::{api::ApiSearcher, live::LiveSearcher, LocalSearcher, SearchQuery, ShardId},
+    ranking::{
+        inbound_similarity::InboundSimilarity, models::reloadable::Reloadable,
+        pipeline::RetrievedWebpageRanking,
+    },
+    searcher::{
+        api::ApiSearcher, budget::Deadline, live::LiveSearcher, LocalSearcher, SearchQuery, ShardId,
+    },
     Result,
 };
 struct Searcher(LocalSearcher<Index>);
 
 impl stract::searcher::distributed::SearchClient for Searcher {
+    async fn index_generation(&self) -> Option<u64> {
+        // the preindexed index is never swapped while the example runs
+        Some(0)
+    }
+
     async fn search_initial(
         &self,
         query: &SearchQuery,
+        _deadline: Option<Deadline>,
     ) -> Vec<stract::searcher::InitialSearchResultShard> {
         let res = self.0.search_initial(query, true).unwrap();
 
@@ -32,6 +43,7 @@ impl stract::searcher::distributed::SearchClient for Searcher {
         &self,
         top_websites: &[(usize, stract::searcher::ScoredWebsitePointer)],
         query: &str,
+        _deadline: Option<Deadline>,
     ) -> Vec<(usize, stract::ranking::pipeline::RetrievedWebpageRanking)> {
         let pointers = top_websites
             .iter()
@@ -83,6 +95,24 @@ impl stract::searcher::distributed::SearchClient for Searcher {
     async fn search_entity(&self, _query: &str) -> Option<stract::entity_index::EntityMatch> {
         None
     }
+
+    async fn sample(
+        &self,
+        num_docs: usize,
+        fields: &[String],
+        seed: Option<u64>,
+    ) -> stract::searcher::sample::IndexSample {
+        self.0.sample(num_docs, fields, seed).unwrap()
+    }
+
+    async fn get_cached_page(&self, url: &str) -> Option<stract::cached_page::CachedPage> {
+        self.0.get_cached_page(url)
+    }
+
+    async fn delete_urls(&self, _urls: &[url::Url]) -> Result<usize> {
+        // the preindexed example index is read-only
+        Ok(0)
+    }
 }
 
 #[tokio::main]
@@ -106,11 +136,14 @@ pub async fn main() {
         cluster_id: "api".to_string(),
         gossip_seed_nodes: None,
         gossip_addr: "0.0.0.0:8002".parse().unwrap(),
+        trusted_proxies: Vec::new(),
         collector: collector_conf.clone(),
         thresholds: ApiThresholds::default(),
         widgets: WidgetsConfig {
             thesaurus_paths: vec!["data/english-wordnet-2022-subset.ttl".to_string()],
             calculator_fetch_currencies_exchange: false,
+            weather: None,
+            stock: None,
         },
         correction_config: CorrectionConfig::default(),
         llm: LLMConfig {
@@ -118,6 +151,22 @@ pub async fn main() {
             model: "data/mistral-7b-instruct-v0.2.Q4_K_M.gguf".to_string(),
             api_key: None,
         },
+        annotations: AnnotationsConfig::default(),
+        feedback: None,
+        model_reload_interval_sec: None,
+        search_budget_ms: None,
+        moderation: None,
+        experiment: None,
+        default_optics: Vec::new(),
+        shard_load: ShardLoadConfig::default(),
+        click_log: None,
+        admin: None,
+        audit_log: None,
+        ranking_pipeline: RankingPipelineConfig::default(),
+        host_autosuggest_path: None,
+        result_cache: None,
+        favicon_store_path: None,
+        task_queue: None,
     };
 
     let mut queries = stract::autosuggest::Autosuggest::load_csv(&config.queries_csv_path)
@@ -141,8 +190,14 @@ pub async fn main() {
 
     let searcher = Searcher(searcher);
 
-    let searcher: ApiSearcher<Searcher, LiveSearcher> =
-        ApiSearcher::new(searcher, None, None, None, bangs, config);
+    let searcher: ApiSearcher<Searcher, LiveSearcher> = ApiSearcher::new(
+        searcher,
+        None,
+        Reloadable::default(),
+        Reloadable::default(),
+        bangs,
+        config,
+    );
 
     for query in &queries {
         let mut desc = "search '".to_string();
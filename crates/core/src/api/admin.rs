@@ -0,0 +1,474 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Endpoints for operators of the search engine.
+//! These are not part of the public api documentation.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{self, OriginalUri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http::{header, Method, StatusCode};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    audit::{AuditEvent, AuditQuery, AuditRecord, Verification},
+    distributed::load::ShardLoad,
+    feedback::{AggregatedFeedback, TrainingLabel},
+    moderation::{Action, AuditEntry, ModerationStore, ReviewItem, Target, TargetDecision},
+    ranking::models::lambdamart::FeatureImportance,
+    schema::Field,
+    searcher::sample::DEFAULT_SAMPLE_FIELDS,
+};
+
+use super::State;
+
+/// Requests with larger bodies are rejected when the audit log is enabled.
+const MAX_AUDITED_BODY_SIZE: usize = 1024 * 1024;
+
+/// The value an operation replaced. Handlers add it to the response extensions
+/// so it can be included in the audit log.
+#[derive(Debug, Clone)]
+pub struct AuditPrevious(pub serde_json::Value);
+
+/// The configured operator a request to the admin api was authenticated as.
+#[derive(Debug, Clone)]
+pub struct Operator(pub String);
+
+/// Reject requests to the admin api that don't carry the bearer token of a configured operator.
+pub async fn authenticate(
+    extract::State(state): extract::State<Arc<State>>,
+    mut request: extract::Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let Some(token) = token else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    // compare digests so the time taken doesn't depend on how much of a token matches
+    let token = digest::digest(&digest::SHA256, token.trim().as_bytes());
+
+    let operator = state.config.admin.as_ref().and_then(|admin| {
+        admin.operators.iter().find(|operator| {
+            digest::digest(&digest::SHA256, operator.token.as_bytes()).as_ref() == token.as_ref()
+        })
+    });
+
+    match operator {
+        Some(operator) => {
+            request
+                .extensions_mut()
+                .insert(Operator(operator.name.clone()));
+            next.run(request).await
+        }
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Record all mutating requests to the admin api in the audit log.
+pub async fn audit(
+    extract::State(state): extract::State<Arc<State>>,
+    request: extract::Request,
+    next: Next,
+) -> Response {
+    let Some(audit_log) = state.audit_log.clone() else {
+        return next.run(request).await;
+    };
+
+    if [Method::GET, Method::HEAD, Method::OPTIONS].contains(request.method()) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+
+    let bytes = match axum::body::to_bytes(body, MAX_AUDITED_BODY_SIZE).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+
+    let request_body = if bytes.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+            serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+        }))
+    };
+
+    // the operator is set by `authenticate`, which runs before this
+    let Some(Operator(operator)) = parts.extensions.get::<Operator>().cloned() else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let method = parts.method.to_string();
+    let path = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| parts.uri.path().to_string());
+
+    let response = next
+        .run(extract::Request::from_parts(parts, Body::from(bytes)))
+        .await;
+
+    let event = AuditEvent {
+        operator,
+        method,
+        path,
+        status: response.status().as_u16(),
+        request: request_body,
+        previous: response
+            .extensions()
+            .get::<AuditPrevious>()
+            .map(|previous| previous.0.clone()),
+    };
+
+    match tokio::task::spawn_blocking(move || audit_log.append(event)).await {
+        Ok(Ok(_)) => response,
+        Ok(Err(err)) => {
+            tracing::error!("failed to append to the audit log: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "the operation was performed but could not be added to the audit log",
+            )
+                .into_response()
+        }
+        Err(err) => {
+            tracing::error!("failed to append to the audit log: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Mutating operations on the admin api, newest first.
+pub async fn audit_log(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Query(query): extract::Query<AuditQuery>,
+) -> Result<Json<Vec<AuditRecord>>, StatusCode> {
+    let audit_log = state.audit_log.clone().ok_or(StatusCode::NOT_FOUND)?;
+
+    tokio::task::spawn_blocking(move || audit_log.records(&query))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Check that the audit log has not been tampered with.
+pub async fn audit_verify(
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<Json<Verification>, StatusCode> {
+    let audit_log = state.audit_log.clone().ok_or(StatusCode::NOT_FOUND)?;
+
+    tokio::task::spawn_blocking(move || audit_log.verify())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Validate and swap the ranking models without restarting the server. The models
+/// are always loaded from the paths in the config, so deploy new models by replacing
+/// the files. The currently loaded models are kept if any of the new models fail validation.
+pub async fn reload_models(
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<(Extension<AuditPrevious>, StatusCode), (StatusCode, String)> {
+    let lambda_model_path = state.config.lambda_model_path.clone();
+    let cross_encoder_model_path = state.config.crossencoder_model_path.clone();
+
+    let lambda_model = state.searcher.lambda_model().clone();
+    let cross_encoder = state.searcher.cross_encoder().clone();
+
+    let previous = AuditPrevious(serde_json::json!({
+        "lambdaModelPath": lambda_model.path(),
+        "crossEncoderModelPath": cross_encoder.path(),
+    }));
+
+    tokio::task::spawn_blocking(move || {
+        if let Some(path) = lambda_model_path {
+            lambda_model.reload(path)?;
+        }
+
+        if let Some(path) = cross_encoder_model_path {
+            cross_encoder.reload(path)?;
+        }
+
+        Ok::<_, anyhow::Error>(())
+    })
+    .await
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+    .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    Ok((Extension(previous), StatusCode::OK))
+}
+
+/// Split counts and gain per signal of the deployed LambdaMART model.
+pub async fn feature_importance(
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<Json<Vec<FeatureImportance>>, StatusCode> {
+    state
+        .searcher
+        .feature_importance()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedbackQueueParams {
+    pub min_reports: Option<u64>,
+}
+
+/// Results flagged by users, most reported first.
+pub async fn feedback_queue(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Query(params): extract::Query<FeedbackQueueParams>,
+) -> Result<Json<Vec<AggregatedFeedback>>, StatusCode> {
+    let (Some(store), Some(config)) = (state.feedback.as_ref(), state.config.feedback.as_ref())
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let min_reports = params.min_reports.unwrap_or(config.min_reports);
+
+    Ok(Json(store.review_queue(min_reports)))
+}
+
+/// Flagged results as negative labels that can be added to the training data of the ranking models.
+pub async fn feedback_labels(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Query(params): extract::Query<FeedbackQueueParams>,
+) -> Result<Json<Vec<TrainingLabel>>, StatusCode> {
+    let (Some(store), Some(config)) = (state.feedback.as_ref(), state.config.feedback.as_ref())
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let min_reports = params.min_reports.unwrap_or(config.min_reports);
+
+    Ok(Json(store.training_labels(min_reports)))
+}
+
+fn moderation(state: &State) -> Result<&ModerationStore, StatusCode> {
+    state.moderation.as_deref().ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Hosts and pages flagged by users or classifiers that have not been reviewed yet.
+pub async fn moderation_queue(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Query(params): extract::Query<FeedbackQueueParams>,
+) -> Result<Json<Vec<ReviewItem>>, StatusCode> {
+    let min_reports = params
+        .min_reports
+        .or_else(|| state.config.feedback.as_ref().map(|c| c.min_reports))
+        .unwrap_or(1);
+
+    Ok(Json(
+        moderation(&state)?.review_queue(state.feedback.as_deref(), min_reports),
+    ))
+}
+
+pub async fn moderation_decisions(
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<Json<Vec<TargetDecision>>, StatusCode> {
+    Ok(Json(moderation(&state)?.decisions()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecideParams {
+    pub target: Target,
+    pub action: Action,
+    pub reason: Option<String>,
+}
+
+/// Penalize, remove or whitelist a host or page.
+pub async fn moderation_decide(
+    extract::State(state): extract::State<Arc<State>>,
+    Extension(Operator(operator)): Extension<Operator>,
+    extract::Json(params): extract::Json<DecideParams>,
+) -> Result<(Extension<AuditPrevious>, Json<AuditEntry>), (StatusCode, String)> {
+    let moderation = moderation(&state).map_err(|status| (status, String::new()))?;
+
+    let entry = moderation
+        .decide(params.target, params.action, operator, params.reason)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    state.searcher.invalidate(&entry);
+
+    Ok((
+        Extension(AuditPrevious(serde_json::json!(entry.previous))),
+        Json(entry),
+    ))
+}
+
+pub async fn moderation_audit(
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<Json<Vec<AuditEntry>>, StatusCode> {
+    moderation(&state)?
+        .audit_log()
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Pages that should be deleted from the index.
+pub async fn moderation_removed(
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<Json<Vec<String>>, StatusCode> {
+    Ok(Json(moderation(&state)?.removed_pages()))
+}
+
+/// QPS and latency of the search shards and their replicas.
+pub async fn shard_load(extract::State(state): extract::State<Arc<State>>) -> Json<Vec<ShardLoad>> {
+    Json(state.shard_load.loads())
+}
+
+const DEFAULT_SAMPLE_SIZE: usize = 100;
+const MAX_SAMPLE_SIZE: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexSampleParams {
+    pub num_docs: Option<usize>,
+    /// Comma separated names of the stored fields to return. Defaults to the url and title.
+    pub fields: Option<String>,
+    /// Seed for a reproducible sample, given the index has not changed.
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexSampleResponse {
+    /// Number of documents in the index the sample was drawn from.
+    pub num_docs: u64,
+    pub documents: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// Uniform random sample of the indexed documents for audits of the corpus.
+pub async fn index_sample(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Query(params): extract::Query<IndexSampleParams>,
+) -> Result<Json<IndexSampleResponse>, (StatusCode, String)> {
+    let num_docs = params
+        .num_docs
+        .unwrap_or(DEFAULT_SAMPLE_SIZE)
+        .min(MAX_SAMPLE_SIZE);
+
+    let fields: Vec<String> = match params.fields.as_deref() {
+        Some(fields) if !fields.trim().is_empty() => fields
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect(),
+        _ => DEFAULT_SAMPLE_FIELDS
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+    };
+
+    if let Some(unknown) = fields
+        .iter()
+        .find(|name| !Field::all().any(|field| field.name() == name.as_str()))
+    {
+        return Err((StatusCode::BAD_REQUEST, format!("unknown field: {unknown}")));
+    }
+
+    let sample = state
+        .searcher
+        .sample_index(num_docs, &fields, params.seed)
+        .await;
+
+    Ok(Json(IndexSampleResponse {
+        num_docs: sample.num_docs,
+        documents: sample
+            .documents
+            .into_iter()
+            .map(|doc| doc.into_iter().map(|(k, v)| (k, v.into())).collect())
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IndexUrlsParams {
+    pub urls: Vec<String>,
+}
+
+impl IndexUrlsParams {
+    fn urls(&self) -> Result<Vec<Url>, (StatusCode, String)> {
+        self.urls
+            .iter()
+            .map(|url| {
+                Url::parse(url.trim())
+                    .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid url {url}: {err}")))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexDeleteResponse {
+    pub num_deleted: usize,
+}
+
+/// Remove pages from all shards, e.g. for takedown requests.
+pub async fn index_delete(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Json(params): extract::Json<IndexUrlsParams>,
+) -> Result<Json<IndexDeleteResponse>, (StatusCode, String)> {
+    let urls = params.urls()?;
+
+    let num_deleted = state
+        .searcher
+        .delete_urls(&urls)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(Json(IndexDeleteResponse { num_deleted }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexReindexResponse {
+    pub num_queued: usize,
+}
+
+/// Replace stale pages with freshly crawled versions from the live index.
+pub async fn index_reindex(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::Json(params): extract::Json<IndexUrlsParams>,
+) -> Result<Json<IndexReindexResponse>, (StatusCode, String)> {
+    let urls = params.urls()?;
+
+    let num_queued = state
+        .searcher
+        .reindex(&urls)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(Json(IndexReindexResponse { num_queued }))
+}
@@ -28,6 +28,7 @@ use utoipa_swagger_ui::SwaggerUi;
             search::spellcheck,
             webgraph::host::similar,
             webgraph::host::knows,
+            webgraph::host::path,
             webgraph::host::ingoing_hosts,
             webgraph::host::outgoing_hosts,
             webgraph::page::ingoing_pages,
@@ -47,8 +48,16 @@ use utoipa_swagger_ui::SwaggerUi;
                 search::SidebarQuery,
                 search::SpellcheckQuery,
                 crate::searcher::WebsitesResult,
+                crate::ranking::experiment::ExperimentArm,
                 crate::search_prettifier::HighlightedSpellCorrection,
                 crate::search_prettifier::DisplayedWebpage,
+                crate::search_prettifier::DisplayedCode,
+                crate::search_prettifier::RichResult,
+                crate::search_prettifier::Rating,
+                crate::search_prettifier::Price,
+                crate::search_prettifier::Annotation,
+                crate::search_prettifier::PrettifierOptions,
+                crate::search_prettifier::RelatedQuestion,
                 crate::search_prettifier::DisplayedEntity,
                 crate::search_prettifier::DisplayedAnswer,
                 crate::search_prettifier::DisplayedSidebar,
@@ -75,6 +84,11 @@ use utoipa_swagger_ui::SwaggerUi;
                 crate::widgets::thesaurus::Example,
                 crate::widgets::thesaurus::PartOfSpeech,
                 crate::widgets::thesaurus::PartOfSpeechMeaning,
+                crate::widgets::weather::WeatherWidget,
+                crate::widgets::weather::CurrentWeather,
+                crate::widgets::weather::DailyForecast,
+                crate::widgets::weather::WeatherCondition,
+                crate::widgets::stock::StockWidget,
 
                 crate::ranking::signal::SignalScore,
                 crate::bangs::BangHit,
@@ -82,6 +96,7 @@ use utoipa_swagger_ui::SwaggerUi;
 
                 webgraph::host::SimilarHostsParams,
                 webgraph::KnowsHost,
+                webgraph::HostPath,
                 crate::entrypoint::webgraph_server::ScoredHost,
 
                 autosuggest::Suggestion,
@@ -0,0 +1,53 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use axum::{extract, response::IntoResponse};
+use http::{header, StatusCode};
+use serde::Deserialize;
+
+use super::State;
+use crate::image_store::ImageStore;
+
+/// Browsers may cache the favicons for a day.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Deserialize, Debug)]
+pub struct FaviconParams {
+    pub site: String,
+}
+
+/// The stored favicon of the site as a png image. Serving them from here
+/// means the frontend doesn't load images from the hosts in the results.
+pub async fn route(
+    extract::Query(params): extract::Query<FaviconParams>,
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let favicon = state
+        .favicons
+        .as_ref()
+        .and_then(|favicons| favicons.get(&params.site))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+        ],
+        favicon.as_raw_bytes(),
+    ))
+}
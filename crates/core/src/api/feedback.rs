@@ -0,0 +1,44 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{extract, http::HeaderMap};
+use http::StatusCode;
+
+use crate::feedback::{self, Feedback};
+
+use super::{client_ip, State};
+
+/// Flag a result as spam, irrelevant or offensive for the query.
+pub async fn report(
+    extract::State(state): extract::State<Arc<State>>,
+    extract::ConnectInfo(addr): extract::ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    extract::Json(feedback): extract::Json<Feedback>,
+) -> StatusCode {
+    let Some(store) = state.feedback.as_ref() else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    let client = client_ip(&headers, addr, &state.config.trusted_proxies);
+
+    match store.report(client, feedback) {
+        Ok(()) => StatusCode::OK,
+        Err(feedback::Error::RateLimited) => StatusCode::TOO_MANY_REQUESTS,
+        Err(feedback::Error::EmptyQuery) => StatusCode::BAD_REQUEST,
+    }
+}
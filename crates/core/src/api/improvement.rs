@@ -21,7 +21,10 @@ use serde::Deserialize;
 use url::Url;
 use uuid::Uuid;
 
-use crate::improvement::{ImprovementEvent, StoredQuery};
+use crate::{
+    improvement::{ImprovementEvent, StoredQuery},
+    ranking::experiment::ExperimentArm,
+};
 
 use super::State;
 
@@ -35,12 +38,21 @@ pub struct ClickParams {
 pub struct StoreParams {
     pub query: String,
     pub urls: Vec<String>,
+    #[serde(default)]
+    pub experiment: Option<ExperimentArm>,
 }
 
 pub async fn click(
     extract::Query(params): extract::Query<ClickParams>,
     extract::State(state): extract::State<Arc<State>>,
 ) {
+    if let Some(clicks) = state.clicks.clone() {
+        let (qid, idx) = (params.qid, params.click);
+        tokio::task::spawn_blocking(move || clicks.click(qid, idx))
+            .await
+            .ok();
+    }
+
     if let Some(q) = state.improvement_queue.as_ref() {
         q.lock().await.push(ImprovementEvent::Click {
             qid: params.qid,
@@ -57,7 +69,7 @@ impl TryFrom<StoreParams> for StoredQuery {
             urls.push(Url::parse(&url)?);
         }
 
-        Ok(StoredQuery::new(params.query, urls))
+        Ok(StoredQuery::new(params.query, urls, params.experiment))
     }
 }
 
@@ -65,16 +77,27 @@ pub async fn store(
     extract::State(state): extract::State<Arc<State>>,
     extract::Json(params): extract::Json<StoreParams>,
 ) -> impl IntoResponse {
-    match state.improvement_queue.as_ref() {
-        Some(q) => match StoredQuery::try_from(params) {
-            Ok(query) => {
-                let qid = *query.qid();
-                q.lock().await.push(ImprovementEvent::StoreQuery(query));
+    if state.improvement_queue.is_none() && state.clicks.is_none() {
+        return String::new();
+    }
+
+    match StoredQuery::try_from(params) {
+        Ok(query) => {
+            let qid = *query.qid();
 
-                qid.to_string()
+            if let Some(clicks) = state.clicks.clone() {
+                let (search, urls) = (query.query().to_string(), query.urls().to_vec());
+                tokio::task::spawn_blocking(move || clicks.impressions(qid, &search, &urls))
+                    .await
+                    .ok();
             }
-            Err(_) => String::new(),
-        },
-        None => String::new(),
+
+            if let Some(q) = state.improvement_queue.as_ref() {
+                q.lock().await.push(ImprovementEvent::StoreQuery(query));
+            }
+
+            qid.to_string()
+        }
+        Err(_) => String::new(),
     }
 }
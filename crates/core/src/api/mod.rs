@@ -22,29 +22,38 @@ use tokio::sync::Mutex;
 use tower_http::compression::CompressionLayer;
 
 use crate::{
-    autosuggest::Autosuggest,
+    audit::AuditLog,
+    autosuggest::{Autosuggest, HostAutosuggest},
     bangs::Bangs,
+    clicks::ClickStore,
     config::ApiConfig,
     distributed::{
         cluster::Cluster,
+        load::LoadTracker,
         member::{Member, Service},
     },
+    feedback::FeedbackStore,
+    image_store::FaviconStore,
     improvement::{store_improvements_loop, ImprovementEvent},
     leaky_queue::LeakyQueue,
-    ranking::models::lambdamart::LambdaMART,
+    moderation::ModerationStore,
+    ranking::{experiment::Experiment, models::reloadable::Reloadable},
+    safe_browsing,
     searcher::{api::ApiSearcher, live::LiveSearcher, DistributedSearcher},
+    task_queue::TaskQueue,
 };
 
-use crate::{ranking::models::cross_encoder::CrossEncoderModel, summarizer::Summarizer};
+use crate::summarizer::Summarizer;
 
 use anyhow::Result;
 use std::{
     net::{IpAddr, SocketAddr},
     sync::Arc,
+    time::Duration,
 };
 
 use axum::{
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     routing::post,
@@ -52,9 +61,12 @@ use axum::{
 
 use self::webgraph::RemoteWebgraph;
 
+mod admin;
 mod autosuggest;
 mod docs;
 mod explore;
+mod favicons;
+mod feedback;
 mod hosts;
 pub mod improvement;
 mod metrics;
@@ -78,7 +90,13 @@ pub struct State {
     pub counters: Counters,
     pub summarizer: Arc<Summarizer>,
     pub improvement_queue: Option<Arc<Mutex<LeakyQueue<ImprovementEvent>>>>,
+    pub feedback: Option<Arc<FeedbackStore>>,
+    pub clicks: Option<Arc<ClickStore>>,
+    pub moderation: Option<Arc<ModerationStore>>,
+    pub audit_log: Option<Arc<AuditLog>>,
     pub cluster: Arc<Cluster>,
+    pub shard_load: Arc<LoadTracker>,
+    pub favicons: Option<Arc<FaviconStore>>,
 }
 
 pub async fn favicon() -> impl IntoResponse {
@@ -91,12 +109,13 @@ pub async fn favicon() -> impl IntoResponse {
 }
 
 pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
-    let autosuggest = Autosuggest::load_csv(&config.queries_csv_path)?;
+    let mut autosuggest = Autosuggest::load_csv(&config.queries_csv_path)?;
 
-    let lambda_model = match &config.lambda_model_path {
-        Some(path) => Some(LambdaMART::open(path)?),
-        None => None,
-    };
+    if let Some(path) = &config.host_autosuggest_path {
+        autosuggest = autosuggest.with_hosts(HostAutosuggest::open(path)?);
+    }
+
+    let lambda_model = Reloadable::open_optional(config.lambda_model_path.as_ref())?;
 
     let query_store_queue = config.query_store_db_host.clone().map(|db_host| {
         let query_store_queue = Arc::new(Mutex::new(LeakyQueue::new(10_000)));
@@ -104,6 +123,31 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
         query_store_queue
     });
 
+    let feedback = match &config.feedback {
+        Some(feedback_config) => Some(Arc::new(FeedbackStore::from_config(feedback_config)?)),
+        None => None,
+    };
+
+    let clicks = config
+        .click_log
+        .as_ref()
+        .map(|click_log| Arc::new(ClickStore::from_config(click_log)));
+
+    let moderation = match &config.moderation {
+        Some(moderation_config) => Some(Arc::new(ModerationStore::open(&moderation_config.path)?)),
+        None => None,
+    };
+
+    let audit_log = match &config.audit_log {
+        Some(audit_log_config) => Some(Arc::new(AuditLog::from_config(audit_log_config)?)),
+        None => None,
+    };
+
+    let favicons = config
+        .favicon_store_path
+        .as_ref()
+        .map(|path| Arc::new(FaviconStore::open(path)));
+
     let bangs = Bangs::from_path(&config.bangs_path);
 
     let cluster = Arc::new(
@@ -119,17 +163,17 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
     );
     let remote_webgraph = RemoteWebgraph::new(cluster.clone());
 
-    let dist_searcher = DistributedSearcher::new(Arc::clone(&cluster));
+    let shard_load = Arc::new(LoadTracker::new(config.shard_load.clone()));
+    tokio::spawn(shard_load.clone().monitor());
+
+    let dist_searcher =
+        DistributedSearcher::new(Arc::clone(&cluster)).with_load_tracker(shard_load.clone());
     let live_searcher = LiveSearcher::new(Arc::clone(&cluster));
 
     let state = {
-        let mut cross_encoder = None;
-
-        if let Some(path) = config.crossencoder_model_path.as_ref() {
-            cross_encoder = Some(CrossEncoderModel::open(path)?);
-        }
+        let cross_encoder = Reloadable::open_optional(config.crossencoder_model_path.as_ref())?;
 
-        let searcher = ApiSearcher::new(
+        let mut searcher = ApiSearcher::new(
             dist_searcher,
             Some(live_searcher),
             cross_encoder,
@@ -138,6 +182,37 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
             config.clone(),
         );
 
+        if let Some(moderation) = moderation.as_ref() {
+            searcher.set_moderation(moderation.clone());
+        }
+
+        if let Some(experiment) = &config.experiment {
+            searcher.set_experiment(Experiment::from_config(experiment)?);
+        }
+
+        if let Some(interval) = config.model_reload_interval_sec {
+            let interval = Duration::from_secs(interval);
+            tokio::spawn(searcher.lambda_model().clone().watch(interval));
+            tokio::spawn(searcher.cross_encoder().clone().watch(interval));
+
+            for arm in searcher.experiment().map(|e| e.arms()).unwrap_or_default() {
+                if let Some(model) = arm.lambda_model() {
+                    tokio::spawn(model.clone().watch(interval));
+                }
+            }
+        }
+
+        if let Some(task_queue) = &config.task_queue {
+            let task_queue = Arc::new(TaskQueue::from_config(task_queue)?);
+            let blocklists = searcher.annotator().blocklists().to_vec();
+
+            tokio::spawn(task_queue.recurring(
+                safe_browsing::SYNC_QUEUE,
+                safe_browsing::SYNC_INTERVAL,
+                move || safe_browsing::sync(blocklists.clone()),
+            ));
+        }
+
         Arc::new(State {
             config: config.clone(),
             searcher,
@@ -151,11 +226,17 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
                 config.llm.api_key.clone(),
             )?),
             improvement_queue: query_store_queue,
+            feedback,
+            clicks,
+            moderation,
+            audit_log,
             cluster,
+            shard_load,
+            favicons,
         })
     };
 
-    Ok(Router::new()
+    let mut router = Router::new()
         .merge(
             Router::new()
                 .route("/beta/api/search", post(search::search))
@@ -163,10 +244,17 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
                 .layer(cors_layer()),
         )
         .route("/favicon.ico", get(favicon))
+        .merge(
+            Router::new()
+                .route("/cached", get(search::cached))
+                .route("/favicon", get(favicons::route))
+                .layer(cors_layer()),
+        )
         .merge(
             Router::new()
                 .route("/improvement/click", post(improvement::click))
                 .route("/improvement/store", post(improvement::store))
+                .route("/feedback", post(feedback::report))
                 .layer(cors_layer()),
         )
         .layer(CompressionLayer::new())
@@ -182,6 +270,7 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
                 .route("/api/summarize", get(summarize::summarize_route))
                 .route("/api/webgraph/host/similar", post(webgraph::host::similar))
                 .route("/api/webgraph/host/knows", post(webgraph::host::knows))
+                .route("/api/webgraph/host/path", post(webgraph::host::path))
                 .route(
                     "/api/webgraph/host/ingoing",
                     post(webgraph::host::ingoing_hosts),
@@ -202,8 +291,49 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
                 .route("/api/explore/export", post(explore::explore_export_optic))
                 .route("/api/entity_image", get(search::entity_image))
                 .layer(cors_layer()),
-        )
-        .with_state(state))
+        );
+
+    if let Some(admin) = &config.admin {
+        anyhow::ensure!(
+            admin
+                .operators
+                .iter()
+                .all(|operator| !operator.token.is_empty()),
+            "admin operators must have a token"
+        );
+
+        router = router.nest(
+            "/admin",
+            Router::new()
+                .route(
+                    "/ranking/feature_importance",
+                    get(admin::feature_importance),
+                )
+                .route("/ranking/reload", post(admin::reload_models))
+                .route("/feedback", get(admin::feedback_queue))
+                .route("/feedback/labels", get(admin::feedback_labels))
+                .route("/moderation/queue", get(admin::moderation_queue))
+                .route(
+                    "/moderation/decisions",
+                    get(admin::moderation_decisions).post(admin::moderation_decide),
+                )
+                .route("/moderation/audit", get(admin::moderation_audit))
+                .route("/moderation/removed", get(admin::moderation_removed))
+                .route("/shards/load", get(admin::shard_load))
+                .route("/index/sample", get(admin::index_sample))
+                .route("/index/delete", post(admin::index_delete))
+                .route("/index/reindex", post(admin::index_reindex))
+                .route("/audit", get(admin::audit_log))
+                .route("/audit/verify", get(admin::audit_verify))
+                .route_layer(middleware::from_fn_with_state(state.clone(), admin::audit))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    admin::authenticate,
+                )),
+        );
+    }
+
+    Ok(router.with_state(state))
 }
 
 /// Enables CORS for development where the API and frontend are on
@@ -221,6 +351,38 @@ pub fn metrics_router(registry: crate::metrics::PrometheusRegistry) -> Router {
         .with_state(Arc::new(registry))
 }
 
+/// The address of the client. The `x-forwarded-for` header is only used when the
+/// request comes from a trusted proxy, and the right-most address in it that isn't
+/// a trusted proxy is the client, since clients can put anything in the header.
+fn client_ip(headers: &HeaderMap, addr: SocketAddr, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = addr.ip();
+
+    if !trusted_proxies.contains(&client) {
+        return client;
+    }
+
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .collect::<Vec<_>>();
+
+    for hop in forwarded_for.into_iter().rev() {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+
+        client = hop;
+
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+    }
+
+    client
+}
+
 async fn search_metric(
     extract::State(state): extract::State<Arc<State>>,
     extract::ConnectInfo(addr): extract::ConnectInfo<SocketAddr>,
@@ -229,18 +391,7 @@ async fn search_metric(
 ) -> Response {
     // It is very important that the ip address is not stored. It is only used
     // for a probabilistic estimate of the number of unique users using a hyperloglog datastructure.
-    let mut ip = None;
-
-    if let Some(forwarded_for) = request.headers().get("x-forwarded-for") {
-        let forwarded_for = forwarded_for.to_str().unwrap_or_default();
-        if let Some(client_ip) = forwarded_for.split(',').next() {
-            if let Ok(client_ip) = client_ip.trim().parse::<IpAddr>() {
-                ip = Some(client_ip);
-            }
-        }
-    }
-
-    let ip = ip.unwrap_or_else(|| addr.ip());
+    let ip = client_ip(request.headers(), addr, &state.config.trusted_proxies);
     state.counters.daily_active_users.inc(&ip).ok();
 
     let response = next.run(request).await;
@@ -253,3 +404,28 @@ async fn search_metric(
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_client_ip() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let addr = SocketAddr::new(ip("10.0.0.1"), 1234);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+
+        assert_eq!(client_ip(&headers, addr, &[]), ip("10.0.0.1"));
+        assert_eq!(client_ip(&headers, addr, &[ip("10.0.0.1")]), ip("2.2.2.2"));
+        assert_eq!(
+            client_ip(&headers, addr, &[ip("10.0.0.1"), ip("2.2.2.2")]),
+            ip("1.1.1.1")
+        );
+        assert_eq!(
+            client_ip(&HeaderMap::new(), addr, &[ip("10.0.0.1")]),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -15,9 +15,11 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::config::defaults;
-use http::StatusCode;
+use chrono::{DateTime, TimeZone, Utc};
+use http::{HeaderMap, StatusCode};
 use optics::{HostRankings, Optic};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use url::Url;
 use utoipa::ToSchema;
 
 use axum::Json;
@@ -25,7 +27,13 @@ use axum_macros::debug_handler;
 
 use crate::{
     bangs::BangHit,
-    searcher::{self, SearchQuery, SearchResult, WebsitesResult},
+    moderation::Action,
+    search_prettifier::PrettifierOptions,
+    searcher::{
+        self,
+        budget::{SearchBudget, BUDGET_HEADER},
+        SearchQuery, SearchResult, WebsitesResult,
+    },
     webpage::region::Region,
 };
 
@@ -45,6 +53,10 @@ pub struct ApiSearchQuery {
     pub host_rankings: Option<HostRankings>,
     pub safe_search: Option<bool>,
 
+    /// Only return pages that pass the basic accessibility checks.
+    #[serde(default)]
+    pub accessible_only: bool,
+
     #[serde(default = "defaults::SearchQuery::return_ranking_signals")]
     pub return_ranking_signals: bool,
 
@@ -53,6 +65,14 @@ pub struct ApiSearchQuery {
 
     #[serde(default = "defaults::SearchQuery::count_results")]
     pub count_results: bool,
+
+    #[serde(default)]
+    pub prettifier: PrettifierOptions,
+
+    /// Random identifier that the client keeps between searches. Searches with the
+    /// same client id are always ranked by the same arm of the ranking experiment.
+    /// Searches without one are assigned to an arm by their query.
+    pub client_id: Option<String>,
 }
 
 impl TryFrom<ApiSearchQuery> for SearchQuery {
@@ -73,10 +93,15 @@ impl TryFrom<ApiSearchQuery> for SearchQuery {
             num_results: api.num_results.unwrap_or(default.num_results),
             selected_region: api.selected_region,
             optic,
+            default_optics: default.default_optics,
             host_rankings: api.host_rankings,
             return_ranking_signals: api.return_ranking_signals,
             safe_search: api.safe_search.unwrap_or(default.safe_search),
+            accessible_only: api.accessible_only,
             count_results: api.count_results,
+            prettifier: api.prettifier,
+            client_id: api.client_id,
+            budget: default.budget,
         })
     }
 }
@@ -102,14 +127,30 @@ impl From<SearchResult> for ApiSearchResult {
     post,
     path = "/beta/api/search",
     request_body(content = ApiSearchQuery),
+    params(
+        ("x-search-budget-ms" = Option<u64>, Header, description = "Milliseconds the caller is willing to wait for the results. Shards that cannot respond in time are left out of the results. Defaults to the budget configured for the api."),
+    ),
     responses(
         (status = 200, description = "Search results", body = ApiSearchResult),
     )
 )]
 pub async fn search(
     extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
     extract::Json(query): extract::Json<ApiSearchQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
+    // start the budget before anything else, so parsing the query counts towards it
+    let budget = headers
+        .get(BUDGET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(SearchBudget::from_header)
+        .or_else(|| {
+            state
+                .config
+                .search_budget_ms
+                .map(|ms| SearchBudget::new(Duration::from_millis(ms)))
+        });
+
     tracing::debug!(?query);
     let flatten_result = query.flatten_response;
     let query = SearchQuery::try_from(query);
@@ -121,6 +162,7 @@ pub async fn search(
     let mut query = query.unwrap();
 
     query.num_results = query.num_results.min(100);
+    query.budget = budget;
 
     match state.searcher.search(&query).await {
         Ok(result) => {
@@ -249,3 +291,49 @@ pub async fn entity_image(
         }
     }
 }
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CachedParams {
+    pub url: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedPageResponse {
+    pub url: String,
+    pub title: Option<String>,
+    pub text: String,
+    /// When the page was crawled.
+    pub fetched_at: Option<DateTime<Utc>>,
+}
+
+/// The text of the page as it was when it was crawled.
+pub async fn cached(
+    extract::Query(params): extract::Query<CachedParams>,
+    extract::State(state): extract::State<Arc<State>>,
+) -> Result<Json<CachedPageResponse>, StatusCode> {
+    let url = Url::parse(params.url.trim()).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let removed = state
+        .moderation
+        .as_ref()
+        .and_then(|moderation| moderation.action_for_url(url.as_str()))
+        == Some(Action::Remove);
+
+    if removed || state.searcher.annotator().is_filtered(&url) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let page = state
+        .searcher
+        .get_cached_page(url.as_str())
+        .await
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(CachedPageResponse {
+        fetched_at: Utc.timestamp_millis_opt(page.fetch_time_ms as i64).single(),
+        url: page.url,
+        title: page.title,
+        text: page.text,
+    }))
+}
@@ -21,7 +21,7 @@ use http::StatusCode;
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    config::WebgraphGranularity,
+    config::{defaults, WebgraphGranularity},
     distributed::{cluster::Cluster, member::Service, retry_strategy::ExponentialBackoff, sonic},
     webgraph::{FullEdge, Node},
 };
@@ -67,10 +67,23 @@ pub mod host {
         pub host: String,
     }
 
+    #[derive(serde::Deserialize, IntoParams)]
+    #[serde(rename_all = "camelCase")]
+    pub struct HostPathParams {
+        pub from: String,
+        pub to: String,
+        #[serde(default = "defaults::WebgraphLinks::max_depth")]
+        pub max_depth: u8,
+    }
+
     #[derive(serde::Deserialize, IntoParams)]
     #[serde(rename_all = "camelCase")]
     pub struct HostLinksParams {
         pub host: String,
+        #[serde(default)]
+        pub offset: usize,
+        #[serde(default = "defaults::WebgraphLinks::limit")]
+        pub limit: usize,
     }
 
     #[utoipa::path(post,
@@ -166,11 +179,72 @@ pub mod host {
         }
     }
 
+    #[utoipa::path(post,
+        path = "/beta/api/webgraph/host/path",
+        params(HostPathParams),
+        responses(
+            (status = 200, description = "Shortest path of links from one host to another", body = HostPath),
+        )
+    )]
+    pub async fn path(
+        extract::State(state): extract::State<Arc<State>>,
+        extract::Query(params): extract::Query<HostPathParams>,
+    ) -> std::result::Result<impl IntoResponse, StatusCode> {
+        let host_node = |host: &str| {
+            Url::parse(&("http://".to_string() + host))
+                .map(|url| Node::from(url).into_host())
+                .map_err(|_| StatusCode::BAD_REQUEST)
+        };
+
+        let from = host_node(&params.from)?;
+        let to = host_node(&params.to)?;
+
+        let host = state
+            .remote_webgraph
+            .host(WebgraphGranularity::Host)
+            .await
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let retry = ExponentialBackoff::from_millis(30)
+            .with_limit(Duration::from_millis(200))
+            .take(5);
+
+        let conn = sonic::service::ResilientConnection::create_with_timeout(
+            host,
+            Duration::from_secs(30),
+            retry,
+        )
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        match conn
+            .send_with_timeout(
+                &crate::entrypoint::webgraph_server::ShortestPath {
+                    from,
+                    to,
+                    max_depth: params.max_depth,
+                },
+                Duration::from_secs(60),
+            )
+            .await
+        {
+            Ok(Some(path)) => Ok(Json(HostPath::Found {
+                distance: path.len().saturating_sub(1),
+                path,
+            })),
+            Ok(None) => Ok(Json(HostPath::NotFound)),
+            Err(err) => {
+                tracing::error!("Failed to send request to webgraph: {}", err);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
     #[utoipa::path(post,
         path = "/beta/api/webgraph/host/ingoing",
         params(HostLinksParams),
         responses(
-            (status = 200, description = "Incoming links for a particular host", body = Vec<FullEdge>),
+            (status = 200, description = "Incoming links for a particular host, most central linking hosts first", body = Vec<FullEdge>),
         )
     )]
     pub async fn ingoing_hosts(
@@ -180,12 +254,18 @@ pub mod host {
         let url = Url::parse(&("http://".to_string() + params.host.as_str()))
             .map_err(|_| StatusCode::BAD_REQUEST)?;
         let node = Node::from(url).into_host();
-        let links = ingoing_links(state, node, WebgraphGranularity::Host)
-            .await
-            .map_err(|_| {
-                tracing::error!("Failed to send request to webgraph");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+        let links = ingoing_links(
+            state,
+            node,
+            params.offset,
+            params.limit,
+            WebgraphGranularity::Host,
+        )
+        .await
+        .map_err(|_| {
+            tracing::error!("Failed to send request to webgraph");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
         Ok(Json(links))
     }
@@ -194,7 +274,7 @@ pub mod host {
         path = "/beta/api/webgraph/host/outgoing",
         params(HostLinksParams),
         responses(
-            (status = 200, description = "Outgoing links for a particular host", body = Vec<FullEdge>),
+            (status = 200, description = "Outgoing links for a particular host, most central linked hosts first", body = Vec<FullEdge>),
         )
     )]
     pub async fn outgoing_hosts(
@@ -204,12 +284,18 @@ pub mod host {
         let url = Url::parse(&("http://".to_string() + params.host.as_str()))
             .map_err(|_| StatusCode::BAD_REQUEST)?;
         let node = Node::from(url).into_host();
-        let links = outgoing_links(state, node, WebgraphGranularity::Host)
-            .await
-            .map_err(|_| {
-                tracing::error!("Failed to send request to webgraph");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+        let links = outgoing_links(
+            state,
+            node,
+            params.offset,
+            params.limit,
+            WebgraphGranularity::Host,
+        )
+        .await
+        .map_err(|_| {
+            tracing::error!("Failed to send request to webgraph");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
         Ok(Json(links))
     }
@@ -222,13 +308,17 @@ pub mod page {
     #[serde(rename_all = "camelCase")]
     pub struct PageLinksParams {
         pub page: String,
+        #[serde(default)]
+        pub offset: usize,
+        #[serde(default = "defaults::WebgraphLinks::limit")]
+        pub limit: usize,
     }
 
     #[utoipa::path(post,
         path = "/beta/api/webgraph/page/ingoing",
         params(PageLinksParams),
         responses(
-            (status = 200, description = "Incoming links for a particular page", body = Vec<FullEdge>),
+            (status = 200, description = "Incoming links for a particular page, most central linking pages first", body = Vec<FullEdge>),
         )
     )]
     pub async fn ingoing_pages(
@@ -236,12 +326,18 @@ pub mod page {
         extract::Query(params): extract::Query<PageLinksParams>,
     ) -> std::result::Result<impl IntoResponse, StatusCode> {
         let node = Node::from(params.page);
-        let links = ingoing_links(state, node, WebgraphGranularity::Page)
-            .await
-            .map_err(|_| {
-                tracing::error!("Failed to send request to webgraph");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+        let links = ingoing_links(
+            state,
+            node,
+            params.offset,
+            params.limit,
+            WebgraphGranularity::Page,
+        )
+        .await
+        .map_err(|_| {
+            tracing::error!("Failed to send request to webgraph");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
         Ok(Json(links))
     }
@@ -250,7 +346,7 @@ pub mod page {
         path = "/beta/api/webgraph/page/outgoing",
         params(PageLinksParams),
         responses(
-            (status = 200, description = "Outgoing links for a particular page", body = Vec<FullEdge>),
+            (status = 200, description = "Outgoing links for a particular page, most central linked pages first", body = Vec<FullEdge>),
         )
     )]
     pub async fn outgoing_pages(
@@ -258,12 +354,18 @@ pub mod page {
         extract::Query(params): extract::Query<PageLinksParams>,
     ) -> std::result::Result<impl IntoResponse, StatusCode> {
         let node = Node::from(params.page);
-        let links = outgoing_links(state, node, WebgraphGranularity::Page)
-            .await
-            .map_err(|_| {
-                tracing::error!("Failed to send request to webgraph");
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+        let links = outgoing_links(
+            state,
+            node,
+            params.offset,
+            params.limit,
+            WebgraphGranularity::Page,
+        )
+        .await
+        .map_err(|_| {
+            tracing::error!("Failed to send request to webgraph");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
         Ok(Json(links))
     }
@@ -272,6 +374,8 @@ pub mod page {
 async fn ingoing_links(
     state: Arc<State>,
     node: Node,
+    offset: usize,
+    limit: usize,
     level: WebgraphGranularity,
 ) -> anyhow::Result<Vec<FullEdge>> {
     let host = state
@@ -295,7 +399,11 @@ async fn ingoing_links(
 
     Ok(conn
         .send_with_timeout(
-            &crate::entrypoint::webgraph_server::IngoingLinks { node },
+            &crate::entrypoint::webgraph_server::IngoingLinks {
+                node,
+                offset,
+                limit,
+            },
             Duration::from_secs(60),
         )
         .await?)
@@ -304,6 +412,8 @@ async fn ingoing_links(
 async fn outgoing_links(
     state: Arc<State>,
     node: Node,
+    offset: usize,
+    limit: usize,
     level: WebgraphGranularity,
 ) -> anyhow::Result<Vec<FullEdge>> {
     let host = state
@@ -327,7 +437,11 @@ async fn outgoing_links(
 
     Ok(conn
         .send_with_timeout(
-            &crate::entrypoint::webgraph_server::OutgoingLinks { node },
+            &crate::entrypoint::webgraph_server::OutgoingLinks {
+                node,
+                offset,
+                limit,
+            },
             Duration::from_secs(60),
         )
         .await?)
@@ -339,3 +453,10 @@ pub enum KnowsHost {
     Known { host: String },
     Unknown,
 }
+
+#[derive(serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum HostPath {
+    Found { distance: usize, path: Vec<Node> },
+    NotFound,
+}
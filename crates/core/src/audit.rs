@@ -0,0 +1,415 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Append-only log of the mutating operations performed through the admin api.
+//!
+//! Every record is signed with HMAC-SHA256 over its content and the signature of
+//! the previous record, so the records form a chain. Modifying, removing or reordering
+//! any record breaks the chain from that point, which is detected by [`AuditLog::verify`].
+//! Removing records from the end of the log can only be detected by comparing
+//! with a previously exported head of the chain.
+//!
+//! The signing key must be stored outside the folder of the log, as anyone
+//! who can read the key can re-sign a modified log.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{prelude::BASE64_STANDARD as BASE64_ENGINE, Engine};
+use chrono::Utc;
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AuditLogConfig;
+
+const LOG_FILE: &str = "audit.jsonl";
+const KEY_LEN: usize = 32;
+
+/// An operation performed by an operator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub operator: String,
+    pub method: String,
+    pub path: String,
+    /// Http status of the response to the operation.
+    pub status: u16,
+    /// The body of the request.
+    pub request: Option<serde_json::Value>,
+    /// The value before the operation, if known by the endpoint.
+    pub previous: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    pub operator: Option<String>,
+    /// Only return operations on paths that start with this prefix.
+    pub path: Option<String>,
+    /// Unix timestamp in seconds.
+    pub since: Option<i64>,
+    /// Unix timestamp in seconds.
+    pub until: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        self.operator
+            .as_ref()
+            .map_or(true, |operator| &record.event.operator == operator)
+            && self
+                .path
+                .as_ref()
+                .map_or(true, |path| record.event.path.starts_with(path.as_str()))
+            && self.since.map_or(true, |since| record.timestamp >= since)
+            && self.until.map_or(true, |until| record.timestamp <= until)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verification {
+    pub num_records: u64,
+    pub valid: bool,
+    /// Sequence number of the first record that does not match the chain.
+    pub first_invalid: Option<u64>,
+    /// Signature of the last record. Store it elsewhere to detect truncation of the log.
+    pub head: Option<String>,
+}
+
+struct Head {
+    next_seq: u64,
+    signature: String,
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    key: hmac::Key,
+    head: Mutex<Head>,
+}
+
+fn load_or_create_key(path: &Path) -> Result<hmac::Key> {
+    let key = if path.exists() {
+        BASE64_ENGINE.decode(std::fs::read_to_string(path)?.trim())?
+    } else {
+        tracing::warn!(
+            "no audit log signing key found, generating a new one at {}",
+            path.display()
+        );
+
+        let mut key = vec![0; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow!("failed to generate audit log signing key"))?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, BASE64_ENGINE.encode(&key))?;
+
+        key
+    };
+
+    Ok(hmac::Key::new(hmac::HMAC_SHA256, &key))
+}
+
+fn is_inside(path: &Path, folder: &Path) -> Result<bool> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    Ok(parent.canonicalize()?.starts_with(folder.canonicalize()?))
+}
+
+fn sign(
+    key: &hmac::Key,
+    seq: u64,
+    timestamp: i64,
+    event: &AuditEvent,
+    previous_signature: &str,
+) -> Result<String> {
+    let payload = serde_json::to_vec(&(seq, timestamp, event, previous_signature))?;
+
+    Ok(BASE64_ENGINE.encode(hmac::sign(key, &payload).as_ref()))
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>, K: AsRef<Path>>(path: P, key_path: K) -> Result<Self> {
+        let path = path.as_ref();
+        let key_path = key_path.as_ref();
+        std::fs::create_dir_all(path)?;
+
+        let key = load_or_create_key(key_path)?;
+
+        if is_inside(key_path, path)? {
+            return Err(anyhow!(
+                "the audit log signing key {} must be stored outside the folder of the log",
+                key_path.display()
+            ));
+        }
+
+        let path = path.join(LOG_FILE);
+
+        let head = match Self::read(&path)?.last() {
+            Some(record) => Head {
+                next_seq: record.seq + 1,
+                signature: record.signature.clone(),
+            },
+            None => Head {
+                next_seq: 0,
+                signature: String::new(),
+            },
+        };
+
+        Ok(Self {
+            path,
+            key,
+            head: Mutex::new(head),
+        })
+    }
+
+    pub fn from_config(config: &AuditLogConfig) -> Result<Self> {
+        Self::open(&config.path, &config.key_path)
+    }
+
+    fn read(path: &Path) -> Result<Vec<AuditRecord>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        BufReader::new(File::open(path)?)
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    /// Sign the event and append it to the log. The record is synced to disk before returning.
+    pub fn append(&self, event: AuditEvent) -> Result<AuditRecord> {
+        let mut head = self.head.lock().unwrap_or_else(|e| e.into_inner());
+
+        let timestamp = Utc::now().timestamp();
+        let signature = sign(&self.key, head.next_seq, timestamp, &event, &head.signature)?;
+
+        let record = AuditRecord {
+            seq: head.next_seq,
+            timestamp,
+            event,
+            signature,
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        file.sync_data()?;
+
+        head.next_seq += 1;
+        head.signature = record.signature.clone();
+
+        Ok(record)
+    }
+
+    /// Records that match the query, newest first.
+    pub fn records(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>> {
+        let mut records: Vec<_> = Self::read(&self.path)?
+            .into_iter()
+            .filter(|record| query.matches(record))
+            .collect();
+
+        records.reverse();
+
+        if let Some(limit) = query.limit {
+            records.truncate(limit);
+        }
+
+        Ok(records)
+    }
+
+    /// Check the signatures and sequence numbers of the entire chain.
+    pub fn verify(&self) -> Result<Verification> {
+        let records = {
+            // make sure we don't read a partially written record
+            let _head = self.head.lock().unwrap_or_else(|e| e.into_inner());
+            Self::read(&self.path)?
+        };
+
+        let mut previous_signature = String::new();
+        let mut first_invalid = None;
+
+        for (expected_seq, record) in records.iter().enumerate() {
+            let signature = sign(
+                &self.key,
+                record.seq,
+                record.timestamp,
+                &record.event,
+                &previous_signature,
+            )?;
+
+            if record.seq != expected_seq as u64 || signature != record.signature {
+                first_invalid = Some(expected_seq as u64);
+                break;
+            }
+
+            previous_signature = signature;
+        }
+
+        Ok(Verification {
+            num_records: records.len() as u64,
+            valid: first_invalid.is_none(),
+            first_invalid,
+            head: records.last().map(|record| record.signature.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(path: &Path) -> Result<AuditLog> {
+        AuditLog::open(path.join("log"), path.join("secrets").join("audit.key"))
+    }
+
+    fn event(operator: &str, path: &str) -> AuditEvent {
+        AuditEvent {
+            operator: operator.to_string(),
+            method: "POST".to_string(),
+            path: path.to_string(),
+            status: 200,
+            request: Some(serde_json::json!({ "action": "remove" })),
+            previous: None,
+        }
+    }
+
+    #[test]
+    fn append_and_query() {
+        let log = open(&crate::gen_temp_path()).unwrap();
+
+        log.append(event("alice", "/admin/moderation/decisions"))
+            .unwrap();
+        log.append(event("bob", "/admin/ranking/reload")).unwrap();
+        log.append(event("alice", "/admin/ranking/reload")).unwrap();
+
+        let all = log.records(&AuditQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].seq, 2);
+
+        let alice = log
+            .records(&AuditQuery {
+                operator: Some("alice".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(alice.len(), 2);
+
+        let ranking = log
+            .records(&AuditQuery {
+                path: Some("/admin/ranking".to_string()),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(ranking.len(), 1);
+        assert_eq!(ranking[0].event.operator, "alice");
+
+        assert!(log.verify().unwrap().valid);
+    }
+
+    #[test]
+    fn chain_continues_after_reopen() {
+        let path = crate::gen_temp_path();
+
+        let log = open(&path).unwrap();
+        log.append(event("alice", "/admin/a")).unwrap();
+        drop(log);
+
+        let log = open(&path).unwrap();
+        let record = log.append(event("alice", "/admin/b")).unwrap();
+
+        assert_eq!(record.seq, 1);
+        assert_eq!(log.verify().unwrap().num_records, 2);
+        assert!(log.verify().unwrap().valid);
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let path = crate::gen_temp_path();
+        let log = open(&path).unwrap();
+
+        for operator in ["alice", "bob", "carol"] {
+            log.append(event(operator, "/admin/moderation/decisions"))
+                .unwrap();
+        }
+
+        let log_path = path.join("log").join(LOG_FILE);
+        let content = std::fs::read_to_string(&log_path).unwrap();
+        std::fs::write(&log_path, content.replacen("bob", "mallory", 1)).unwrap();
+
+        let verification = log.verify().unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid, Some(1));
+
+        // removing a record breaks the chain as well
+        let lines: Vec<_> = content.lines().collect();
+        std::fs::write(&log_path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+
+        let verification = log.verify().unwrap();
+        assert_eq!(verification.first_invalid, Some(1));
+    }
+
+    #[test]
+    fn key_must_be_outside_log() {
+        let path = crate::gen_temp_path();
+
+        assert!(AuditLog::open(&path, path.join("audit.key")).is_err());
+        assert!(AuditLog::open(&path, path.join("keys").join("audit.key")).is_err());
+        assert!(open(&path).is_ok());
+    }
+
+    #[test]
+    fn signatures_depend_on_key() {
+        let a = open(&crate::gen_temp_path()).unwrap();
+        let b = open(&crate::gen_temp_path()).unwrap();
+
+        let record_a = a.append(event("alice", "/admin/a")).unwrap();
+        let record_b = b.append(event("alice", "/admin/a")).unwrap();
+
+        assert_ne!(record_a.signature, record_b.signature);
+    }
+}
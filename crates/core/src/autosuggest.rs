@@ -18,14 +18,160 @@
 //! when you type something into the search bar and queries are suggested.
 //! It uses a finite state transducer (fst) to store popular queries
 //! and performs a prefix search on the fst to find suggestions.
+//!
+//! When the user is typing a `site:` operator, the hostname is instead completed
+//! from a dictionary of the hosts in the index, ranked by their centrality.
 
-use fst::{automaton::Str, Automaton, IntoStreamer};
+use fst::{automaton::Str, Automaton, IntoStreamer, Streamer};
+use tantivy::{columnar::ColumnValues, schema::IndexRecordOption, DocSet, TERMINATED};
 
-use crate::Result;
-use std::path::Path;
+use crate::{
+    schema::{FastField, TextField},
+    Result,
+};
+use std::{collections::BTreeMap, path::Path};
+
+const NUM_SUGGESTIONS: usize = 10;
+const SITE_OPERATOR: &str = "site:";
+
+/// Short prefixes can match a large part of the host dictionary, so only
+/// this many hosts are ranked to keep the latency of the endpoint bounded.
+const MAX_HOSTS_CONSIDERED: usize = 100_000;
+
+/// Hostnames in the index and their host centrality.
+pub struct HostAutosuggest {
+    hosts: fst::Map<Vec<u8>>,
+}
+
+impl HostAutosuggest {
+    /// Build the dictionary from `(host, centrality)` pairs. If a host
+    /// occurs multiple times, the highest centrality is kept.
+    pub fn from_hosts<I>(hosts: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (String, u64)>,
+    {
+        let mut map: BTreeMap<String, u64> = BTreeMap::new();
+
+        for (host, centrality) in hosts {
+            let entry = map.entry(host.to_ascii_lowercase()).or_default();
+            *entry = (*entry).max(centrality);
+        }
+
+        Ok(Self {
+            hosts: fst::Map::from_iter(map)?,
+        })
+    }
+
+    /// Build the dictionary from the hosts of all documents in the index.
+    /// This should be rebuilt whenever a new generation of the index is built.
+    pub fn from_index(searcher: &tantivy::Searcher) -> Result<Self> {
+        let site_field = searcher
+            .schema()
+            .get_field(TextField::SiteNoTokenizer.name())?;
+
+        let mut hosts = Vec::new();
+
+        for segment in searcher.segment_readers() {
+            let inverted_index = segment.inverted_index(site_field)?;
+            let centrality = segment
+                .fast_fields()
+                .u64(FastField::HostCentrality.name())?;
+
+            let mut terms = inverted_index.terms().stream()?;
+
+            while terms.advance() {
+                let Ok(host) = std::str::from_utf8(terms.key()) else {
+                    continue;
+                };
+
+                if host.is_empty() {
+                    continue;
+                }
+
+                let mut postings = inverted_index
+                    .read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)?;
+
+                // all documents of a host have the same host centrality,
+                // so we only need the first document that has not been deleted.
+                let mut doc = postings.doc();
+                if let Some(alive) = segment.alive_bitset() {
+                    while doc != TERMINATED && alive.is_deleted(doc) {
+                        doc = postings.advance();
+                    }
+                }
+
+                if doc == TERMINATED {
+                    continue;
+                }
+
+                hosts.push((host.to_string(), centrality.values.get_val(doc)));
+            }
+        }
+
+        Self::from_hosts(hosts)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            hosts: fst::Map::new(std::fs::read(path)?)?,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.hosts.as_fst().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// The hosts that start with `prefix`, most central first.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut stream = self
+            .hosts
+            .search(Str::new(prefix.as_str()).starts_with())
+            .into_stream();
+
+        let mut hosts = Vec::new();
+        while let Some((host, centrality)) = stream.next() {
+            if let Ok(host) = std::str::from_utf8(host) {
+                hosts.push((centrality, host.to_string()));
+            }
+
+            if hosts.len() >= MAX_HOSTS_CONSIDERED {
+                break;
+            }
+        }
+
+        hosts.sort_by(|(a_centrality, a_host), (b_centrality, b_host)| {
+            b_centrality
+                .cmp(a_centrality)
+                .then_with(|| a_host.cmp(b_host))
+        });
+
+        hosts
+            .into_iter()
+            .take(NUM_SUGGESTIONS)
+            .map(|(_, host)| host)
+            .collect()
+    }
+}
+
+/// Split the query into the text before a trailing `site:` operator and
+/// the hostname prefix that has been typed so far.
+fn site_prefix(query: &str) -> Option<(&str, &str)> {
+    let start = query.rfind(' ').map_or(0, |idx| idx + 1);
+    let prefix = query[start..].strip_prefix(SITE_OPERATOR)?;
+
+    if prefix.is_empty() {
+        return None;
+    }
+
+    Some((&query[..start], prefix))
+}
 
 pub struct Autosuggest {
     queries: fst::Set<Vec<u8>>,
+    hosts: Option<HostAutosuggest>,
 }
 
 impl Autosuggest {
@@ -44,11 +190,29 @@ impl Autosuggest {
 
         let queries = fst::Set::from_iter(queries)?;
 
-        Ok(Self { queries })
+        Ok(Self {
+            queries,
+            hosts: None,
+        })
+    }
+
+    /// Complete `site:` operators using the hosts in the dictionary.
+    pub fn with_hosts(mut self, hosts: HostAutosuggest) -> Self {
+        self.hosts = Some(hosts);
+        self
     }
 
     pub fn suggestions(&self, query: &str) -> Result<Vec<String>> {
         let query = query.to_ascii_lowercase();
+
+        if let (Some(hosts), Some((before, prefix))) = (&self.hosts, site_prefix(&query)) {
+            return Ok(hosts
+                .complete(prefix)
+                .into_iter()
+                .map(|host| format!("{before}{SITE_OPERATOR}{host}"))
+                .collect());
+        }
+
         let q = Str::new(query.as_str()).starts_with();
 
         Ok(self
@@ -57,7 +221,7 @@ impl Autosuggest {
             .into_stream()
             .into_strs()?
             .into_iter()
-            .take(10)
+            .take(NUM_SUGGESTIONS)
             .collect())
     }
 
@@ -65,3 +229,93 @@ impl Autosuggest {
         Ok(self.queries.into_stream().into_strs()?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        index::Index,
+        webpage::{Html, Webpage},
+    };
+
+    use super::*;
+
+    fn hosts() -> HostAutosuggest {
+        HostAutosuggest::from_hosts(vec![
+            ("github.com".to_string(), 10),
+            ("github.io".to_string(), 20),
+            ("gitlab.com".to_string(), 30),
+            ("example.com".to_string(), 40),
+            ("github.com".to_string(), 5),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn complete_by_centrality() {
+        let hosts = hosts();
+
+        assert_eq!(hosts.complete("gith"), vec!["github.io", "github.com"]);
+        assert_eq!(
+            hosts.complete("git"),
+            vec!["gitlab.com", "github.io", "github.com"]
+        );
+        assert!(hosts.complete("stract").is_empty());
+    }
+
+    #[test]
+    fn site_operator() {
+        assert_eq!(site_prefix("site:gith"), Some(("", "gith")));
+        assert_eq!(site_prefix("rust site:gith"), Some(("rust ", "gith")));
+        assert_eq!(site_prefix("site:"), None);
+        assert_eq!(site_prefix("site:github.com rust"), None);
+
+        let autosuggest = Autosuggest {
+            queries: fst::Set::from_iter(vec!["site:github.com"]).unwrap(),
+            hosts: Some(hosts()),
+        };
+
+        assert_eq!(
+            autosuggest.suggestions("rust site:Gith").unwrap(),
+            vec!["rust site:github.io", "rust site:github.com"]
+        );
+    }
+
+    #[test]
+    fn hosts_from_index() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, centrality) in [
+            ("https://www.github.com/a", 0.5),
+            ("https://www.github.com/b", 0.5),
+            ("https://github.io", 0.9),
+            ("https://example.com", 0.1),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        "<html><head><title>Test</title></head><body>body</body></html>",
+                        url,
+                    )
+                    .unwrap(),
+                    host_centrality: centrality,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let hosts = HostAutosuggest::from_index(&index.inverted_index.tv_searcher()).unwrap();
+
+        assert_eq!(hosts.complete("git"), vec!["github.io", "github.com"]);
+        assert_eq!(hosts.complete("exa"), vec!["example.com"]);
+
+        let path = crate::gen_temp_path();
+        std::fs::create_dir_all(&path).unwrap();
+        let path = path.join("hosts.fst");
+
+        hosts.save(&path).unwrap();
+        let hosts = HostAutosuggest::open(&path).unwrap();
+        assert_eq!(hosts.complete("gith"), vec!["github.io", "github.com"]);
+    }
+}
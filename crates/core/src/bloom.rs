@@ -14,58 +14,269 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+//! Set membership for `u64` items with a configurable false positive rate.
+//!
+//! Small sets are kept exactly, so they never report false positives. Larger sets
+//! use a bloom filter with the number of bits and hash functions chosen for the
+//! expected number of items and the false positive rate.
+
+use std::collections::HashSet;
+
 use bitvec::vec::BitVec;
 
+/// Filters for at most this many expected items are exact.
+pub const EXACT_THRESHOLD: u64 = 100_000;
+
+/// Upper bound on the number of hash functions of a bloom filter. Filters with a very
+/// low false positive rate would otherwise spend most of their time hashing.
+const MAX_NUM_HASHES: u32 = 16;
+
+#[derive(Clone)]
+enum Inner {
+    Exact(HashSet<u64>),
+    Bloom {
+        bit_vec: BitVec,
+        num_bits: u64,
+        num_hashes: u32,
+    },
+}
+
 #[derive(Clone)]
 pub struct BloomFilter {
-    bit_vec: BitVec,
-    num_bits: u64,
+    inner: Inner,
+}
+
+/// The splitmix64 finalizer. Consecutive node ids must end up on unrelated bits.
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58476d1ce4e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    x
 }
 
 impl BloomFilter {
+    /// Exact if at most [`EXACT_THRESHOLD`] items are expected, otherwise a bloom filter
+    /// with a false positive rate of `fp` when `estimated_items` have been inserted.
     pub fn new(estimated_items: u64, fp: f64) -> Self {
-        let num_bits = Self::num_bits(estimated_items, fp);
+        if estimated_items <= EXACT_THRESHOLD {
+            Self::exact()
+        } else {
+            Self::probabilistic(estimated_items, fp)
+        }
+    }
+
+    pub fn exact() -> Self {
+        Self {
+            inner: Inner::Exact(HashSet::new()),
+        }
+    }
+
+    pub fn probabilistic(estimated_items: u64, fp: f64) -> Self {
+        let (num_bits, num_hashes) = Self::dimensions(estimated_items, fp);
+
         Self {
-            bit_vec: BitVec::repeat(false, num_bits as usize),
-            num_bits,
+            inner: Inner::Bloom {
+                bit_vec: BitVec::repeat(false, num_bits as usize),
+                num_bits,
+                num_hashes,
+            },
         }
     }
 
-    fn num_bits(estimated_items: u64, fp: f64) -> u64 {
-        ((estimated_items as f64) * fp.ln() / (-8.0 * 2.0_f64.ln().powi(2))).ceil() as u64
+    /// Optimal number of bits `m = -n ln(p) / ln(2)^2` and
+    /// hash functions `k = m / n ln(2)`.
+    fn dimensions(estimated_items: u64, fp: f64) -> (u64, u32) {
+        let n = estimated_items.max(1) as f64;
+        let fp = fp.clamp(f64::MIN_POSITIVE, 0.5);
+
+        let num_bits = (-n * fp.ln() / 2.0_f64.ln().powi(2)).ceil().max(1.0);
+        let num_hashes = ((num_bits / n) * 2.0_f64.ln()).round() as u32;
+
+        (num_bits as u64, num_hashes.clamp(1, MAX_NUM_HASHES))
+    }
+
+    /// Bit positions of the item using double hashing.
+    fn positions(item: u64, num_bits: u64, num_hashes: u32) -> impl Iterator<Item = usize> {
+        let h1 = mix(item);
+        let h2 = mix(item ^ 0x9e3779b97f4a7c15) | 1;
+
+        (0..num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
     }
 
-    fn hash(item: &u64) -> usize {
-        item.wrapping_mul(11400714819323198549) as usize
+    pub fn is_exact(&self) -> bool {
+        matches!(self.inner, Inner::Exact(_))
     }
 
     pub fn insert(&mut self, item: u64) {
-        let h = Self::hash(&item);
-        self.bit_vec.set(h % self.num_bits as usize, true);
+        match &mut self.inner {
+            Inner::Exact(set) => {
+                set.insert(item);
+            }
+            Inner::Bloom {
+                bit_vec,
+                num_bits,
+                num_hashes,
+            } => {
+                for pos in Self::positions(item, *num_bits, *num_hashes) {
+                    bit_vec.set(pos, true);
+                }
+            }
+        }
     }
 
     pub fn contains(&self, item: &u64) -> bool {
-        let h = Self::hash(item);
-        self.bit_vec[h % self.num_bits as usize]
+        match &self.inner {
+            Inner::Exact(set) => set.contains(item),
+            Inner::Bloom {
+                bit_vec,
+                num_bits,
+                num_hashes,
+            } => Self::positions(*item, *num_bits, *num_hashes).all(|pos| bit_vec[pos]),
+        }
     }
 
+    /// Estimated number of distinct items in the filter.
     pub fn estimate_card(&self) -> u64 {
-        let num_ones = self.bit_vec.count_ones() as u64;
+        match &self.inner {
+            Inner::Exact(set) => set.len() as u64,
+            Inner::Bloom {
+                bit_vec,
+                num_bits,
+                num_hashes,
+            } => {
+                let num_ones = bit_vec.count_ones() as u64;
+
+                if num_ones == 0 {
+                    return 0;
+                }
 
-        if num_ones == 0 || self.num_bits == 0 {
-            return 0;
+                if num_ones == *num_bits {
+                    return u64::MAX;
+                }
+
+                let m = *num_bits as f64;
+                let estimate = -(m / *num_hashes as f64) * (1.0 - num_ones as f64 / m).ln();
+
+                estimate.round() as u64
+            }
         }
+    }
 
-        if num_ones == self.num_bits {
-            return u64::MAX;
+    /// Union of the two filters. Bloom filters must have been created with the
+    /// same expected number of items and false positive rate.
+    pub fn merge(&mut self, other: Self) {
+        match (&mut self.inner, other.inner) {
+            (Inner::Exact(set), Inner::Exact(other)) => set.extend(other),
+            (Inner::Bloom { .. }, Inner::Exact(other)) => {
+                for item in other {
+                    self.insert(item);
+                }
+            }
+            (Inner::Exact(set), other @ Inner::Bloom { .. }) => {
+                let items = std::mem::take(set);
+                self.inner = other;
+
+                for item in items {
+                    self.insert(item);
+                }
+            }
+            (
+                Inner::Bloom {
+                    bit_vec,
+                    num_bits,
+                    num_hashes,
+                },
+                Inner::Bloom {
+                    bit_vec: other_bit_vec,
+                    num_bits: other_num_bits,
+                    num_hashes: other_num_hashes,
+                },
+            ) => {
+                assert_eq!(
+                    (*num_bits, *num_hashes),
+                    (other_num_bits, other_num_hashes),
+                    "cannot merge bloom filters with different dimensions"
+                );
+
+                *bit_vec |= other_bit_vec;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions() {
+        let (num_bits, num_hashes) = BloomFilter::dimensions(1_000_000, 0.01);
+
+        // ~9.6 bits and ~7 hashes per item
+        assert_eq!(num_bits, 9_585_059);
+        assert_eq!(num_hashes, 7);
+    }
+
+    #[test]
+    fn small_filters_are_exact() {
+        let mut filter = BloomFilter::new(100, 0.5);
+        assert!(filter.is_exact());
+
+        for i in 0..100 {
+            filter.insert(i * 2);
         }
 
-        (-(self.num_bits as i64) * (1.0 - (num_ones as f64) / (self.num_bits as f64)).ln() as i64)
-            .try_into()
-            .unwrap_or_default()
+        assert_eq!(filter.estimate_card(), 100);
+        assert!((0..100).all(|i| filter.contains(&(i * 2))));
+        assert!((0..100).all(|i| !filter.contains(&(i * 2 + 1))));
     }
 
-    pub fn merge(&mut self, other: Self) {
-        self.bit_vec |= other.bit_vec;
+    #[test]
+    fn false_positive_rate() {
+        let n = 200_000;
+        let fp = 0.01;
+
+        let mut filter = BloomFilter::new(n, fp);
+        assert!(!filter.is_exact());
+
+        for i in 0..n {
+            filter.insert(i);
+        }
+
+        assert!((0..n).all(|i| filter.contains(&i)));
+
+        let false_positives = (n..2 * n).filter(|i| filter.contains(i)).count();
+        let rate = false_positives as f64 / n as f64;
+        assert!(rate < 2.0 * fp, "false positive rate {rate}");
+
+        let card = filter.estimate_card() as f64;
+        assert!((card - n as f64).abs() / (n as f64) < 0.05, "{card}");
+    }
+
+    #[test]
+    fn merge() {
+        let n = 200_000;
+
+        let mut a = BloomFilter::new(n, 0.01);
+        let mut b = BloomFilter::new(n, 0.01);
+
+        for i in 0..n / 2 {
+            a.insert(i);
+            b.insert(i + n / 2);
+        }
+
+        a.merge(b);
+        assert!((0..n).all(|i| a.contains(&i)));
+
+        let mut exact = BloomFilter::exact();
+        exact.insert(u64::MAX);
+        exact.merge(a);
+
+        assert!(!exact.is_exact());
+        assert!(exact.contains(&u64::MAX));
+        assert!((0..n).all(|i| exact.contains(&i)));
     }
 }
@@ -0,0 +1,184 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compressed copies of the text of the indexed pages, so users
+//! can view pages that are no longer available.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    webpage::Webpage,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub url: String,
+    pub title: Option<String>,
+    pub text: String,
+    pub fetch_time_ms: u64,
+}
+
+impl CachedPage {
+    /// The cleaned text of the webpage. Returns `None` if no text could be extracted.
+    pub fn from_webpage(webpage: &Webpage) -> Option<Self> {
+        let text = webpage.html.clean_text()?;
+
+        if text.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            url: webpage.html.url().to_string(),
+            title: webpage.html.title(),
+            text: text.clone(),
+            fetch_time_ms: webpage.fetch_time_ms,
+        })
+    }
+
+    fn compress(&self) -> Vec<u8> {
+        let bytes = bincode::serialize(self).expect("failed to serialize cached page");
+        lz4_flex::compress_prepend_size(&bytes)
+    }
+
+    fn decompress(bytes: &[u8]) -> Option<Self> {
+        let bytes = lz4_flex::decompress_size_prepended(bytes).ok()?;
+        bincode::deserialize(&bytes).ok()
+    }
+}
+
+/// Cached pages keyed by url. Only the most recently fetched version of a page is kept.
+pub struct CachedPageStore {
+    path: PathBuf,
+    store: RocksDbStore<String, Vec<u8>>,
+}
+
+impl CachedPageStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            store: RocksDbStore::open(path),
+        }
+    }
+
+    pub fn insert(&self, page: &CachedPage) {
+        self.store.insert(page.url.clone(), page.compress());
+    }
+
+    pub fn get(&self, url: &str) -> Option<CachedPage> {
+        self.store
+            .get(&url.to_string())
+            .and_then(|bytes| CachedPage::decompress(&bytes))
+    }
+
+    pub fn delete(&self, url: &str) {
+        self.store.delete(&url.to_string());
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+
+    /// Only keep the pages whose url satisfies the predicate.
+    pub fn retain<F>(&self, keep: F)
+    where
+        F: Fn(&str) -> bool,
+    {
+        let removed: Vec<String> = self
+            .store
+            .iter()
+            .map(|(url, _)| url)
+            .filter(|url| !keep(url))
+            .collect();
+
+        for url in removed {
+            self.store.delete(&url);
+        }
+
+        self.flush();
+    }
+
+    pub fn merge(self, other: Self) {
+        for (url, bytes) in other.store.iter() {
+            let is_newer = match (self.get(&url), CachedPage::decompress(&bytes)) {
+                (Some(existing), Some(page)) => page.fetch_time_ms > existing.fetch_time_ms,
+                (None, Some(_)) => true,
+                (_, None) => false,
+            };
+
+            if is_newer {
+                self.store.insert(url, bytes);
+            }
+        }
+
+        self.flush();
+
+        let other_path = other.path.clone();
+        drop(other);
+        std::fs::remove_dir_all(other_path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(url: &str, text: &str, fetch_time_ms: u64) -> CachedPage {
+        CachedPage {
+            url: url.to_string(),
+            title: Some("Title".to_string()),
+            text: text.to_string(),
+            fetch_time_ms,
+        }
+    }
+
+    #[test]
+    fn insert_get_delete() {
+        let store = CachedPageStore::open(crate::gen_temp_path());
+
+        let a = page("https://a.com/", "the text of a", 1);
+        store.insert(&a);
+
+        assert_eq!(store.get("https://a.com/"), Some(a));
+        assert_eq!(store.get("https://b.com/"), None);
+
+        store.delete("https://a.com/");
+        assert_eq!(store.get("https://a.com/"), None);
+    }
+
+    #[test]
+    fn merge_keeps_newest() {
+        let dir = crate::gen_temp_path();
+
+        let a = CachedPageStore::open(dir.join("a"));
+        a.insert(&page("https://a.com/", "old", 1));
+        a.insert(&page("https://b.com/", "b", 1));
+
+        let b = CachedPageStore::open(dir.join("b"));
+        b.insert(&page("https://a.com/", "new", 2));
+        b.insert(&page("https://b.com/", "stale", 0));
+        b.flush();
+
+        a.merge(b);
+        assert!(!dir.join("b").exists());
+
+        let a = CachedPageStore::open(dir.join("a"));
+        assert_eq!(a.get("https://a.com/").unwrap().text, "new");
+        assert_eq!(a.get("https://b.com/").unwrap().text, "b");
+    }
+}
@@ -0,0 +1,394 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Aggregated clicks and impressions of search results, used as training data for the ranking models.
+//!
+//! Only the number of impressions and clicks per (query, url) pair are stored.
+//! The results of a search are kept in memory for a short while so a click can be
+//! attributed to the query, but nothing that can link a search to the user is ever stored.
+//! Queries are only exported once they have been seen enough times that they are unlikely
+//! to identify a single user.
+//!
+//! The store does blocking disk io, so it should not be called directly from async code.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::{defaults, ClickLogConfig},
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    ttl_cache::TTLCache,
+};
+
+/// How long after a search a click on one of its results is attributed to the query.
+const CLICK_WINDOW: Duration = Duration::from_secs(30 * 60);
+const MAX_PENDING_SEARCHES: usize = 100_000;
+const MAX_QUERY_LEN: usize = 256;
+
+/// Click-through rates at which a result is assigned the next relevance grade.
+const RELEVANCE_CTR_THRESHOLDS: [f64; 4] = [0.0, 0.1, 0.3, 0.6];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct ClickKey {
+    query: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ClickCount {
+    impressions: u64,
+    clicks: u64,
+    /// Sum of the (zero-indexed) positions the url was shown at.
+    position_sum: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultClicks {
+    pub url: String,
+    pub impressions: u64,
+    pub clicks: u64,
+    pub avg_position: f64,
+}
+
+impl ResultClicks {
+    pub fn ctr(&self) -> f64 {
+        if self.impressions == 0 {
+            0.0
+        } else {
+            self.clicks as f64 / self.impressions as f64
+        }
+    }
+
+    /// Graded relevance between 0 and 4 derived from the click-through rate.
+    pub fn relevance(&self) -> u8 {
+        if self.clicks == 0 {
+            return 0;
+        }
+
+        RELEVANCE_CTR_THRESHOLDS
+            .iter()
+            .filter(|threshold| self.ctr() > **threshold)
+            .count() as u8
+    }
+}
+
+/// All results that have been shown for a query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryClicks {
+    pub query: String,
+    pub impressions: u64,
+    pub results: Vec<ResultClicks>,
+}
+
+pub fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .chars()
+        .take(MAX_QUERY_LEN)
+        .collect()
+}
+
+struct PendingSearch {
+    query: String,
+    urls: Vec<Url>,
+    /// Positions that have already been clicked, so repeated clicks are only counted once.
+    clicked: HashSet<usize>,
+}
+
+pub struct ClickStore {
+    store: RocksDbStore<ClickKey, ClickCount>,
+    num_pairs: AtomicUsize,
+    max_pairs: usize,
+    // guards the read-modify-write of the counts
+    write_lock: Mutex<()>,
+    pending: Mutex<TTLCache<Uuid, PendingSearch>>,
+}
+
+impl ClickStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let store = RocksDbStore::open(path);
+        let num_pairs = store.iter().count();
+
+        Self {
+            store,
+            num_pairs: AtomicUsize::new(num_pairs),
+            max_pairs: defaults::ClickLog::max_pairs(),
+            write_lock: Mutex::new(()),
+            pending: Mutex::new(TTLCache::with_ttl_and_max_size(
+                CLICK_WINDOW,
+                Some(MAX_PENDING_SEARCHES),
+            )),
+        }
+    }
+
+    pub fn with_max_pairs(mut self, max_pairs: usize) -> Self {
+        self.max_pairs = max_pairs;
+        self
+    }
+
+    pub fn from_config(config: &ClickLogConfig) -> Self {
+        Self::open(&config.path).with_max_pairs(config.max_pairs)
+    }
+
+    /// Update the count of an existing pair, or insert a new pair if the store is not full.
+    fn update(&self, key: ClickKey, f: impl FnOnce(&mut ClickCount)) {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut count = match self.store.get(&key) {
+            Some(count) => count,
+            None if self.num_pairs.load(Ordering::Relaxed) < self.max_pairs => {
+                self.num_pairs.fetch_add(1, Ordering::Relaxed);
+                ClickCount::default()
+            }
+            None => return,
+        };
+
+        f(&mut count);
+
+        self.store.insert(key, count);
+    }
+
+    /// Record that the urls were shown, in order, as the results for the query.
+    pub fn impressions(&self, qid: Uuid, query: &str, urls: &[Url]) {
+        let query = normalize_query(query);
+
+        if query.is_empty() {
+            return;
+        }
+
+        for (position, url) in urls.iter().enumerate() {
+            self.update(
+                ClickKey {
+                    query: query.clone(),
+                    url: url.to_string(),
+                },
+                |count| {
+                    count.impressions += 1;
+                    count.position_sum += position as u64;
+                },
+            );
+        }
+
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                qid,
+                PendingSearch {
+                    query,
+                    urls: urls.to_vec(),
+                    clicked: HashSet::new(),
+                },
+            );
+    }
+
+    /// Record a click on the result at `idx` of the search with id `qid`. Clicks on searches
+    /// that are no longer pending and repeated clicks on the same result are ignored.
+    pub fn click(&self, qid: Uuid, idx: usize) {
+        let key = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+
+            pending.get_mut(&qid).and_then(|search| {
+                let url = search.urls.get(idx)?;
+
+                if !search.clicked.insert(idx) {
+                    return None;
+                }
+
+                Some(ClickKey {
+                    query: search.query.clone(),
+                    url: url.to_string(),
+                })
+            })
+        };
+
+        if let Some(key) = key {
+            self.update(key, |count| count.clicks += 1);
+        }
+    }
+
+    /// The results of all queries that have been searched at least `min_query_impressions` times.
+    pub fn queries(&self, min_query_impressions: u64) -> Vec<QueryClicks> {
+        let mut queries: BTreeMap<String, Vec<ResultClicks>> = BTreeMap::new();
+
+        for (key, count) in self.store.iter() {
+            queries.entry(key.query).or_default().push(ResultClicks {
+                url: key.url,
+                impressions: count.impressions,
+                clicks: count.clicks,
+                avg_position: if count.impressions == 0 {
+                    0.0
+                } else {
+                    count.position_sum as f64 / count.impressions as f64
+                },
+            });
+        }
+
+        queries
+            .into_iter()
+            .filter_map(|(query, mut results)| {
+                // every search shows the top result, so this is the number of searches
+                let impressions = results.iter().map(|r| r.impressions).max().unwrap_or(0);
+
+                if impressions < min_query_impressions {
+                    return None;
+                }
+
+                results.sort_by(|a, b| {
+                    a.avg_position
+                        .total_cmp(&b.avg_position)
+                        .then(a.url.cmp(&b.url))
+                });
+
+                Some(QueryClicks {
+                    query,
+                    impressions,
+                    results,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> Vec<Url> {
+        vec![
+            Url::parse("https://a.com").unwrap(),
+            Url::parse("https://b.com").unwrap(),
+            Url::parse("https://c.com").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn aggregates_clicks() {
+        let store = ClickStore::open(crate::gen_temp_path());
+
+        for i in 0..10 {
+            let qid = Uuid::new_v4();
+            store.impressions(qid, "Best  Pizza", &urls());
+
+            if i < 5 {
+                store.click(qid, 1);
+            }
+
+            if i == 0 {
+                store.click(qid, 2);
+            }
+        }
+
+        let queries = store.queries(1);
+        assert_eq!(queries.len(), 1);
+
+        let query = &queries[0];
+        assert_eq!(query.query, "best pizza");
+        assert_eq!(query.impressions, 10);
+
+        let results: Vec<_> = query
+            .results
+            .iter()
+            .map(|r| (r.url.as_str(), r.clicks, r.relevance()))
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                ("https://a.com/", 0, 0),
+                ("https://b.com/", 5, 3),
+                ("https://c.com/", 1, 1),
+            ]
+        );
+        assert_eq!(query.results[1].avg_position, 1.0);
+    }
+
+    #[test]
+    fn rare_queries_are_not_exported() {
+        let store = ClickStore::open(crate::gen_temp_path());
+
+        store.impressions(Uuid::new_v4(), "rare query", &urls());
+
+        for _ in 0..5 {
+            store.impressions(Uuid::new_v4(), "common query", &urls());
+        }
+
+        let queries = store.queries(5);
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].query, "common query");
+    }
+
+    #[test]
+    fn repeated_clicks_are_counted_once() {
+        let store = ClickStore::open(crate::gen_temp_path());
+        let qid = Uuid::new_v4();
+
+        store.impressions(qid, "query", &urls());
+        store.click(qid, 0);
+        store.click(qid, 0);
+        store.click(qid, 1);
+
+        let clicks: Vec<_> = store.queries(0)[0]
+            .results
+            .iter()
+            .map(|r| r.clicks)
+            .collect();
+        assert_eq!(clicks, vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn store_is_capped() {
+        let store = ClickStore::open(crate::gen_temp_path()).with_max_pairs(4);
+
+        store.impressions(Uuid::new_v4(), "a", &urls());
+        store.impressions(Uuid::new_v4(), "b", &urls());
+        store.impressions(Uuid::new_v4(), "a", &urls());
+
+        let queries = store.queries(0);
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].impressions, 2);
+        assert_eq!(queries[0].results.len(), 3);
+        assert_eq!(queries[1].results.len(), 1);
+    }
+
+    #[test]
+    fn unknown_clicks_are_ignored() {
+        let store = ClickStore::open(crate::gen_temp_path());
+        let qid = Uuid::new_v4();
+
+        store.impressions(qid, "query", &urls());
+        store.click(Uuid::new_v4(), 0);
+        store.click(qid, 10);
+
+        assert!(store.queries(0)[0].results.iter().all(|r| r.clicks == 0));
+    }
+}
@@ -138,16 +138,55 @@ pub struct TopSegmentCollector {
     bucket_collector: BucketCollector<SegmentDoc>,
 }
 
-impl TopSegmentCollector {
-    fn get_hash(&self, doc: &DocId, field1: &FastField, field2: &FastField) -> Prehashed {
-        let field_reader = self.fastfield_segment_reader.get_field_reader(doc);
+fn get_hash(
+    fastfield_segment_reader: &fastfield_reader::SegmentReader,
+    doc: &DocId,
+    field1: &FastField,
+    field2: &FastField,
+) -> Prehashed {
+    let field_reader = fastfield_segment_reader.get_field_reader(doc);
+
+    let hash1 = field_reader.get(field1).into();
 
-        let hash1 = field_reader.get(field1).into();
+    let hash2 = field_reader.get(field2).into();
 
-        let hash2 = field_reader.get(field2).into();
+    let hash = [hash1, hash2];
+    combine_u64s(hash).into()
+}
 
-        let hash = [hash1, hash2];
-        combine_u64s(hash).into()
+/// Read the hashes of the document from the fast fields.
+pub fn hashes(fastfield_segment_reader: &fastfield_reader::SegmentReader, doc: DocId) -> Hashes {
+    let simhash: Option<u64> = fastfield_segment_reader
+        .get_field_reader(&doc)
+        .get(&FastField::SimHash)
+        .into();
+
+    Hashes {
+        site: get_hash(
+            fastfield_segment_reader,
+            &doc,
+            &FastField::SiteHash1,
+            &FastField::SiteHash2,
+        ),
+        title: get_hash(
+            fastfield_segment_reader,
+            &doc,
+            &FastField::TitleHash1,
+            &FastField::TitleHash2,
+        ),
+        url: get_hash(
+            fastfield_segment_reader,
+            &doc,
+            &FastField::UrlHash1,
+            &FastField::UrlHash2,
+        ),
+        url_without_tld: get_hash(
+            fastfield_segment_reader,
+            &doc,
+            &FastField::UrlWithoutTldHash1,
+            &FastField::UrlWithoutTldHash2,
+        ),
+        simhash: simhash.unwrap(),
     }
 }
 
@@ -167,24 +206,8 @@ impl TopSegmentCollector {
 
         self.num_docs_taken += 1;
 
-        let simhash: Option<u64> = self
-            .fastfield_segment_reader
-            .get_field_reader(&doc)
-            .get(&FastField::SimHash)
-            .into();
-
         self.bucket_collector.insert(SegmentDoc {
-            hashes: Hashes {
-                site: self.get_hash(&doc, &FastField::SiteHash1, &FastField::SiteHash2),
-                title: self.get_hash(&doc, &FastField::TitleHash1, &FastField::TitleHash2),
-                url: self.get_hash(&doc, &FastField::UrlHash1, &FastField::UrlHash2),
-                url_without_tld: self.get_hash(
-                    &doc,
-                    &FastField::UrlWithoutTldHash1,
-                    &FastField::UrlWithoutTldHash2,
-                ),
-                simhash: simhash.unwrap(),
-            },
+            hashes: hashes(&self.fastfield_segment_reader, doc),
             id: doc,
             segment: self.segment_ord,
             score,
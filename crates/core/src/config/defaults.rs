@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use super::RankingStageKind;
+
 pub struct Collector;
 
 impl Collector {
@@ -82,6 +84,74 @@ impl Snippet {
     }
 }
 
+pub struct Recrawl;
+
+impl Recrawl {
+    pub fn min_interval_hours() -> u64 {
+        24
+    }
+
+    pub fn max_interval_days() -> u64 {
+        90
+    }
+}
+
+pub struct CrawlCoordinator;
+
+impl CrawlCoordinator {
+    pub fn job_lease_timeout_sec() -> u64 {
+        6 * 60 * 60
+    }
+}
+
+pub struct SeedImport;
+
+impl SeedImport {
+    pub fn max_domains() -> usize {
+        10_000_000
+    }
+
+    pub fn max_seeds_per_domain() -> usize {
+        10_000
+    }
+}
+
+pub struct WarcOutput;
+
+impl WarcOutput {
+    pub fn max_file_size_mb() -> u64 {
+        1_000
+    }
+}
+
+pub struct Render;
+
+impl Render {
+    pub fn max_sessions() -> usize {
+        2
+    }
+
+    pub fn settle_ms() -> u64 {
+        1_000
+    }
+
+    pub fn timeout_seconds() -> u64 {
+        30
+    }
+}
+
+pub struct FetchLimits;
+
+impl FetchLimits {
+    pub fn max_concurrent_fetches_per_domain() -> usize {
+        1
+    }
+
+    pub fn url_retry_backoff_ms() -> u64 {
+        5_000
+    }
+}
+
 pub struct Crawler;
 
 impl Crawler {
@@ -105,6 +175,14 @@ impl Crawler {
         2048.0
     }
 
+    pub fn max_domain_requests_per_sec() -> f64 {
+        1.0
+    }
+
+    pub fn domain_request_burst() -> u32 {
+        1
+    }
+
     pub fn max_url_slowdown_retry() -> u8 {
         3
     }
@@ -116,6 +194,26 @@ impl Crawler {
     pub fn dry_run() -> bool {
         false
     }
+
+    pub fn dns_cache_ttl_sec() -> u64 {
+        5 * 60
+    }
+
+    pub fn dns_negative_cache_ttl_sec() -> u64 {
+        30
+    }
+
+    pub fn proxy_max_consecutive_failures() -> usize {
+        5
+    }
+
+    pub fn proxy_cooldown_sec() -> u64 {
+        5 * 60
+    }
+
+    pub fn proxy_health_check_interval_sec() -> u64 {
+        60
+    }
 }
 
 pub struct WebgraphServer;
@@ -126,6 +224,18 @@ impl WebgraphServer {
     }
 }
 
+pub struct WebgraphLinks;
+
+impl WebgraphLinks {
+    pub fn limit() -> usize {
+        50
+    }
+
+    pub fn max_depth() -> u8 {
+        4
+    }
+}
+
 pub struct SearchQuery;
 
 impl SearchQuery {
@@ -146,6 +256,14 @@ impl SearchQuery {
     }
 }
 
+pub struct Prettifier;
+
+impl Prettifier {
+    pub fn enabled() -> bool {
+        true
+    }
+}
+
 pub struct Correction;
 
 impl Correction {
@@ -162,10 +280,218 @@ impl Correction {
     }
 }
 
+pub struct Annotations;
+
+impl Annotations {
+    pub fn cache_ttl_sec() -> u64 {
+        60 * 60
+    }
+
+    pub fn timeout_ms() -> u64 {
+        200
+    }
+}
+
+pub struct ResultCache;
+
+impl ResultCache {
+    pub fn ttl_sec() -> u64 {
+        10 * 60
+    }
+
+    pub fn max_entries() -> usize {
+        10_000
+    }
+}
+
+pub struct Experiment;
+
+impl Experiment {
+    pub fn weight() -> u64 {
+        1
+    }
+
+    pub fn cross_encoder() -> bool {
+        true
+    }
+}
+
+pub struct ShardLoad;
+
+impl ShardLoad {
+    pub fn window_sec() -> u64 {
+        60
+    }
+
+    pub fn hot_factor() -> f64 {
+        2.0
+    }
+
+    pub fn min_qps() -> f64 {
+        1.0
+    }
+
+    pub fn check_interval_sec() -> u64 {
+        10
+    }
+}
+
+pub struct TaskQueue;
+
+impl TaskQueue {
+    pub fn max_attempts() -> u32 {
+        5
+    }
+
+    pub fn retry_backoff_ms() -> u64 {
+        1_000
+    }
+
+    pub fn max_retry_backoff_ms() -> u64 {
+        60 * 60 * 1_000
+    }
+
+    pub fn lease_timeout_sec() -> u64 {
+        5 * 60
+    }
+
+    pub fn poll_interval_ms() -> u64 {
+        1_000
+    }
+}
+
+pub struct ResultDiff;
+
+impl ResultDiff {
+    pub fn top_k() -> usize {
+        10
+    }
+}
+
+pub struct LtrTrainingData;
+
+impl LtrTrainingData {
+    pub fn min_query_impressions() -> u64 {
+        10
+    }
+
+    pub fn num_results() -> usize {
+        100
+    }
+
+    pub fn validation_fraction() -> f64 {
+        0.1
+    }
+}
+
+pub struct Freshness;
+
+impl Freshness {
+    pub fn half_life_days() -> f64 {
+        180.0
+    }
+
+    pub fn steepness() -> f64 {
+        1.5
+    }
+
+    pub fn news_half_life_days() -> f64 {
+        2.0
+    }
+}
+
+pub struct DenseRetrieval;
+
+impl DenseRetrieval {
+    pub fn min_lexical_hits() -> usize {
+        20
+    }
+
+    pub fn num_candidates() -> usize {
+        20
+    }
+
+    pub fn ef_search() -> usize {
+        64
+    }
+
+    pub fn min_similarity() -> f32 {
+        0.5
+    }
+}
+
+pub struct RankingPipeline;
+
+impl RankingPipeline {
+    pub fn recall() -> Vec<RankingStageKind> {
+        vec![
+            RankingStageKind::Recall,
+            RankingStageKind::LambdaMart,
+            RankingStageKind::Diversity,
+        ]
+    }
+
+    pub fn rerank() -> Vec<RankingStageKind> {
+        vec![
+            RankingStageKind::CrossEncoder,
+            RankingStageKind::LambdaMart,
+            RankingStageKind::Diversity,
+        ]
+    }
+}
+
+pub struct Feedback;
+
+impl Feedback {
+    pub fn max_reports_per_hour() -> usize {
+        20
+    }
+
+    pub fn min_reports() -> u64 {
+        3
+    }
+}
+
+pub struct ClickLog;
+
+impl ClickLog {
+    pub fn max_pairs() -> usize {
+        10_000_000
+    }
+}
+
 pub struct Widgets;
 
 impl Widgets {
     pub fn calculator_fetch_currencies_exchange() -> bool {
         true
     }
+
+    pub fn weather_cache_ttl_sec() -> u64 {
+        15 * 60
+    }
+
+    pub fn weather_negative_cache_ttl_sec() -> u64 {
+        60
+    }
+
+    pub fn open_meteo_geocoding_url() -> String {
+        "https://geocoding-api.open-meteo.com/v1/search".to_string()
+    }
+
+    pub fn open_meteo_forecast_url() -> String {
+        "https://api.open-meteo.com/v1/forecast".to_string()
+    }
+
+    pub fn stock_cache_ttl_sec() -> u64 {
+        60
+    }
+
+    pub fn stock_negative_cache_ttl_sec() -> u64 {
+        15
+    }
+
+    pub fn alpha_vantage_url() -> String {
+        "https://www.alphavantage.co/query".to_string()
+    }
 }
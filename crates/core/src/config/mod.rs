@@ -20,9 +20,10 @@ use super::Result;
 use crate::feed::scheduler::SplitId;
 use crate::searcher::ShardId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct IndexingLocalConfig {
@@ -38,6 +39,21 @@ pub struct IndexingLocalConfig {
     pub page_centrality_store_path: Option<String>,
     pub safety_classifier_path: Option<String>,
     pub minimum_clean_words: Option<usize>,
+    /// Embed the pages with this model and store the embeddings for semantic retrieval.
+    pub embedding_model_path: Option<String>,
+    /// Record the content hash and validators of every indexed page, so the
+    /// crawl planner can estimate how often the pages change.
+    pub recrawl_history_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DistributedHarmonicConfig {
+    /// Addresses of the harmonic centrality workers.
+    pub workers: Vec<String>,
+    /// Webgraphs that together contain all the edges of the graph. All the workers
+    /// must be able to open them.
+    pub shards: Vec<String>,
+    pub output_path: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -197,10 +213,17 @@ pub struct LLMConfig {
 pub struct ApiConfig {
     pub summarizer_path: String,
     pub queries_csv_path: String,
+    /// Hosts used to complete `site:` operators in autosuggest.
+    pub host_autosuggest_path: Option<String>,
     pub host: SocketAddr,
     pub prometheus_host: SocketAddr,
     pub crossencoder_model_path: Option<String>,
     pub lambda_model_path: Option<String>,
+    /// Check the ranking models for changes with this interval and reload them.
+    pub model_reload_interval_sec: Option<u64>,
+    /// Latency budget in milliseconds of searches without the `X-Search-Budget-Ms` header.
+    /// Searches without any budget wait for all shards.
+    pub search_budget_ms: Option<u64>,
     pub spell_checker_path: Option<String>,
     pub bangs_path: String,
     pub query_store_db_host: Option<String>,
@@ -208,18 +231,212 @@ pub struct ApiConfig {
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
     pub gossip_addr: SocketAddr,
 
+    /// Addresses of the reverse proxies in front of the api. The `x-forwarded-for`
+    /// header is only trusted from these.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+
     pub llm: LLMConfig,
 
     #[serde(default)]
     pub collector: CollectorConfig,
 
+    #[serde(default)]
+    pub ranking_pipeline: RankingPipelineConfig,
+
     #[serde(default)]
     pub thresholds: ApiThresholds,
 
     pub widgets: WidgetsConfig,
 
+    /// Paths to optics that are applied to every search
+    /// in addition to the optic supplied by the user.
+    #[serde(default)]
+    pub default_optics: Vec<String>,
+
     #[serde(default)]
     pub correction_config: CorrectionConfig,
+
+    #[serde(default)]
+    pub annotations: AnnotationsConfig,
+
+    pub feedback: Option<FeedbackConfig>,
+
+    pub click_log: Option<ClickLogConfig>,
+
+    pub moderation: Option<ModerationConfig>,
+
+    /// Operators of the `/admin` endpoints. The endpoints are not served without it.
+    pub admin: Option<AdminConfig>,
+
+    pub audit_log: Option<AuditLogConfig>,
+
+    pub experiment: Option<ExperimentConfig>,
+
+    #[serde(default)]
+    pub shard_load: ShardLoadConfig,
+
+    pub result_cache: Option<ResultCacheConfig>,
+
+    /// Favicons collected by the crawlers, served from `/favicon`.
+    pub favicon_store_path: Option<String>,
+
+    /// Queue for background jobs such as syncing the blocklists.
+    pub task_queue: Option<TaskQueueConfig>,
+}
+
+/// Cache of the ranked results per query. Entries are tied to the generation
+/// of the index and invalidated by moderation decisions.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResultCacheConfig {
+    #[serde(default = "defaults::ResultCache::ttl_sec")]
+    pub ttl_sec: u64,
+
+    #[serde(default = "defaults::ResultCache::max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for ResultCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_sec: defaults::ResultCache::ttl_sec(),
+            max_entries: defaults::ResultCache::max_entries(),
+        }
+    }
+}
+
+/// Detection of search shards that receive a disproportionate share of the load.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShardLoadConfig {
+    /// Length of the window used to compute the QPS of the replicas.
+    #[serde(default = "defaults::ShardLoad::window_sec")]
+    pub window_sec: u64,
+
+    /// A shard is a hotspot if its QPS per replica or latency is
+    /// this many times higher than the median of all shards.
+    #[serde(default = "defaults::ShardLoad::hot_factor")]
+    pub hot_factor: f64,
+
+    /// Shards with fewer queries per second per replica are never hotspots.
+    #[serde(default = "defaults::ShardLoad::min_qps")]
+    pub min_qps: f64,
+
+    #[serde(default = "defaults::ShardLoad::check_interval_sec")]
+    pub check_interval_sec: u64,
+}
+
+impl Default for ShardLoadConfig {
+    fn default() -> Self {
+        Self {
+            window_sec: defaults::ShardLoad::window_sec(),
+            hot_factor: defaults::ShardLoad::hot_factor(),
+            min_qps: defaults::ShardLoad::min_qps(),
+            check_interval_sec: defaults::ShardLoad::check_interval_sec(),
+        }
+    }
+}
+
+/// An A/B experiment between multiple ranking configurations.
+/// Each search is assigned to one of the arms based on its request id.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExperimentConfig {
+    pub name: String,
+    pub arms: Vec<ExperimentArmConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExperimentArmConfig {
+    pub name: String,
+
+    /// Share of the traffic assigned to the arm relative to the other arms.
+    #[serde(default = "defaults::Experiment::weight")]
+    pub weight: u64,
+
+    /// Use this model instead of the default LambdaMART model.
+    pub lambda_model_path: Option<String>,
+
+    /// Coefficients of the ranking signals. Coefficients
+    /// from the optic of the query take precedence.
+    #[serde(default)]
+    pub signal_coefficients: HashMap<String, f64>,
+
+    /// Whether to rerank the results with the cross encoder.
+    #[serde(default = "defaults::Experiment::cross_encoder")]
+    pub cross_encoder: bool,
+}
+
+/// Aggregated clicks and impressions used as training data for the ranking models.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClickLogConfig {
+    pub path: String,
+
+    /// Impressions of new (query, url) pairs are dropped once this many pairs are stored.
+    #[serde(default = "defaults::ClickLog::max_pairs")]
+    pub max_pairs: usize,
+}
+
+/// Persistent queue of background jobs.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskQueueConfig {
+    pub path: String,
+
+    /// Failed tasks are moved to the dead letters after this many attempts.
+    #[serde(default = "defaults::TaskQueue::max_attempts")]
+    pub max_attempts: u32,
+
+    /// Backoff before the first retry. The backoff doubles for every attempt.
+    #[serde(default = "defaults::TaskQueue::retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+
+    #[serde(default = "defaults::TaskQueue::max_retry_backoff_ms")]
+    pub max_retry_backoff_ms: u64,
+
+    /// Tasks that are neither completed nor failed within this time are handed out again.
+    #[serde(default = "defaults::TaskQueue::lease_timeout_sec")]
+    pub lease_timeout_sec: u64,
+
+    #[serde(default = "defaults::TaskQueue::poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModerationConfig {
+    /// Folder with the moderation decisions, flags and audit log.
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminConfig {
+    pub operators: Vec<OperatorConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OperatorConfig {
+    pub name: String,
+    /// Sent by the operator as a bearer token in the `Authorization` header.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLogConfig {
+    /// Folder with the audit log of the admin api.
+    pub path: String,
+
+    /// File with the key used to sign the log, e.g. from a mounted secret. It must be
+    /// outside the folder of the log. A new key is generated if the file does not exist.
+    pub key_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeedbackConfig {
+    pub path: String,
+
+    #[serde(default = "defaults::Feedback::max_reports_per_hour")]
+    pub max_reports_per_hour: usize,
+
+    /// Minimum number of reports before feedback is shown in the review queue.
+    #[serde(default = "defaults::Feedback::min_reports")]
+    pub min_reports: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -274,11 +491,142 @@ pub struct SearchServerConfig {
     pub lambda_model_path: Option<String>,
     pub host: SocketAddr,
 
+    /// Check the ranking models for changes with this interval and reload them.
+    pub model_reload_interval_sec: Option<u64>,
+
     #[serde(default)]
     pub collector: CollectorConfig,
 
     #[serde(default)]
     pub snippet: SnippetConfig,
+
+    #[serde(default)]
+    pub freshness: FreshnessConfig,
+
+    #[serde(default)]
+    pub ranking_pipeline: RankingPipelineConfig,
+
+    /// Model used to embed queries for semantic retrieval. Must be the
+    /// same model that was used to embed the pages of the index.
+    pub embedding_model_path: Option<String>,
+
+    #[serde(default)]
+    pub dense_retrieval: DenseRetrievalConfig,
+}
+
+/// Semantic candidates are added to the recall stage for queries where
+/// the inverted index finds few matches.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DenseRetrievalConfig {
+    /// Only add semantic candidates if the query has fewer lexical matches than this.
+    #[serde(default = "defaults::DenseRetrieval::min_lexical_hits")]
+    pub min_lexical_hits: usize,
+
+    /// Number of nearest neighbours to add to the candidates.
+    #[serde(default = "defaults::DenseRetrieval::num_candidates")]
+    pub num_candidates: usize,
+
+    /// Size of the candidate list during the graph search. Higher values
+    /// find the nearest neighbours more reliably but are slower.
+    #[serde(default = "defaults::DenseRetrieval::ef_search")]
+    pub ef_search: usize,
+
+    /// Neighbours with a lower cosine similarity to the query are not added.
+    #[serde(default = "defaults::DenseRetrieval::min_similarity")]
+    pub min_similarity: f32,
+}
+
+impl Default for DenseRetrievalConfig {
+    fn default() -> Self {
+        Self {
+            min_lexical_hits: defaults::DenseRetrieval::min_lexical_hits(),
+            num_candidates: defaults::DenseRetrieval::num_candidates(),
+            ef_search: defaults::DenseRetrieval::ef_search(),
+            min_similarity: defaults::DenseRetrieval::min_similarity(),
+        }
+    }
+}
+
+/// Decay curve of the freshness signal. A page that was updated `age` days ago
+/// gets the score `1 / (1 + (age / half_life_days)^steepness)`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FreshnessConfig {
+    /// Age in days where the score has decayed to 0.5.
+    #[serde(default = "defaults::Freshness::half_life_days")]
+    pub half_life_days: f64,
+
+    /// How quickly the score drops around the half life. Higher values
+    /// keep the score close to 1 for longer before dropping sharply.
+    #[serde(default = "defaults::Freshness::steepness")]
+    pub steepness: f64,
+
+    /// Half life in days of the recency boost that is given to pages
+    /// for queries that look for news. The boost decays exponentially.
+    #[serde(default = "defaults::Freshness::news_half_life_days")]
+    pub news_half_life_days: f64,
+}
+
+impl Default for FreshnessConfig {
+    fn default() -> Self {
+        Self {
+            half_life_days: defaults::Freshness::half_life_days(),
+            steepness: defaults::Freshness::steepness(),
+            news_half_life_days: defaults::Freshness::news_half_life_days(),
+        }
+    }
+}
+
+impl FreshnessConfig {
+    pub fn score(&self, age_days: f64) -> f64 {
+        if age_days <= 0.0 {
+            return 1.0;
+        }
+
+        1.0 / (1.0 + (age_days / self.half_life_days).powf(self.steepness))
+    }
+
+    pub fn news_score(&self, age_days: f64) -> f64 {
+        if age_days <= 0.0 {
+            return 1.0;
+        }
+
+        0.5f64.powf(age_days / self.news_half_life_days)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingStageKind {
+    /// Score by the weighted sum of the ranking signals.
+    Recall,
+    /// Score using the lambdamart model. Skipped if no model is loaded.
+    LambdaMart,
+    /// Add the cross encoder signals and score by the weighted sum of the signals.
+    /// Skipped if no cross encoder is loaded.
+    CrossEncoder,
+    /// Derank results that are similar to higher ranked results.
+    Diversity,
+}
+
+/// The stages of the ranking pipelines in the order they are applied.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RankingPipelineConfig {
+    /// Stages applied to the results of each shard.
+    #[serde(default = "defaults::RankingPipeline::recall")]
+    pub recall: Vec<RankingStageKind>,
+
+    /// Stages applied to the combined results of all the shards.
+    #[serde(default = "defaults::RankingPipeline::rerank")]
+    pub rerank: Vec<RankingStageKind>,
+}
+
+impl Default for RankingPipelineConfig {
+    fn default() -> Self {
+        Self {
+            recall: defaults::RankingPipeline::recall(),
+            rerank: defaults::RankingPipeline::rerank(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -294,6 +642,15 @@ pub struct EntitySearchServerConfig {
 pub struct CrawlCoordinatorConfig {
     pub job_queue: String,
     pub host: SocketAddr,
+
+    /// Jobs that are not reported as done within this time
+    /// are handed out to another worker.
+    #[serde(default = "defaults::CrawlCoordinator::job_lease_timeout_sec")]
+    pub job_lease_timeout_sec: u64,
+
+    /// Serve crawl statistics as json on `/stats`.
+    #[serde(default)]
+    pub stats_host: Option<SocketAddr>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -322,6 +679,14 @@ pub struct CrawlerConfig {
     #[serde(default = "defaults::Crawler::max_politeness_factor")]
     pub max_politeness_factor: f32,
 
+    /// Hard limit on the request rate to a single domain, independent of
+    /// the crawl delays, so an aggressive config can't overload small sites.
+    #[serde(default = "defaults::Crawler::max_domain_requests_per_sec")]
+    pub max_domain_requests_per_sec: f64,
+
+    #[serde(default = "defaults::Crawler::domain_request_burst")]
+    pub domain_request_burst: u32,
+
     #[serde(default = "defaults::Crawler::max_url_slowdown_retry")]
     pub max_url_slowdown_retry: u8,
 
@@ -331,9 +696,194 @@ pub struct CrawlerConfig {
     #[serde(default = "defaults::Crawler::dry_run")]
     pub dry_run: bool,
 
+    /// Which address families the crawler is allowed to connect to.
+    #[serde(default)]
+    pub ip_family: IpFamily,
+
+    /// How long resolved addresses are cached. Set to 0 to disable the cache.
+    #[serde(default = "defaults::Crawler::dns_cache_ttl_sec")]
+    pub dns_cache_ttl_sec: u64,
+
+    /// How long failed lookups are cached before the host is resolved again.
+    #[serde(default = "defaults::Crawler::dns_negative_cache_ttl_sec")]
+    pub dns_negative_cache_ttl_sec: u64,
+
     pub timeout_seconds: u64,
     pub s3: S3Config,
+
+    /// Addresses of the crawl routers. Both `host:port` and
+    /// bracketed ipv6 addresses (`[::1]:port`) are supported.
     pub router_hosts: Vec<String>,
+
+    pub prometheus_host: Option<SocketAddr>,
+
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+
+    /// Toml file with domains, domain suffixes and url patterns that must never
+    /// be crawled. The file is reloaded when it changes.
+    #[serde(default)]
+    pub url_filter_path: Option<String>,
+
+    #[serde(default)]
+    pub warc: WarcOutputConfig,
+
+    #[serde(default)]
+    pub limits: FetchLimitsConfig,
+
+    /// Render the pages of selected domains in a headless browser.
+    #[serde(default)]
+    pub render: Option<RenderConfig>,
+
+    /// Fetch the favicon of each crawled host and store it here.
+    /// The favicons are fetched on the task queue.
+    #[serde(default)]
+    pub favicon_store_path: Option<String>,
+
+    #[serde(default)]
+    pub task_queue: Option<TaskQueueConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenderConfig {
+    /// Url of a WebDriver server (e.g. chromedriver or selenium) that controls the browsers.
+    pub webdriver_url: String,
+
+    /// Pages on these domains are rendered before they are parsed.
+    pub domains: Vec<String>,
+
+    /// Max number of browser sessions used at the same time.
+    #[serde(default = "defaults::Render::max_sessions")]
+    pub max_sessions: usize,
+
+    /// Time to wait after the page has loaded, so scripts can finish building the page.
+    #[serde(default = "defaults::Render::settle_ms")]
+    pub settle_ms: u64,
+
+    #[serde(default = "defaults::Render::timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+/// Limits on the requests sent by a crawler process. They are shared by all
+/// worker threads, on top of the politeness rules of each domain.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FetchLimitsConfig {
+    /// Max number of requests in flight across all worker threads.
+    /// Defaults to one per worker thread.
+    #[serde(default)]
+    pub max_concurrent_fetches: Option<usize>,
+
+    /// Max number of requests in flight to a single domain. This also
+    /// applies if the same domain is crawled by several jobs at once.
+    #[serde(default = "defaults::FetchLimits::max_concurrent_fetches_per_domain")]
+    pub max_concurrent_fetches_per_domain: usize,
+
+    /// Ceiling on the total request rate of the process.
+    #[serde(default)]
+    pub max_requests_per_sec: Option<f64>,
+
+    /// Timeout for establishing a connection. Defaults to `timeout_seconds`.
+    #[serde(default)]
+    pub connect_timeout_seconds: Option<u64>,
+
+    /// Delay before a url that failed with a transient error, like a timeout or
+    /// a server error, is tried again later in the job. It doubles for each
+    /// following retry, up to `max_url_slowdown_retry` retries.
+    #[serde(default = "defaults::FetchLimits::url_retry_backoff_ms")]
+    pub url_retry_backoff_ms: u64,
+}
+
+impl Default for FetchLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_fetches: None,
+            max_concurrent_fetches_per_domain:
+                defaults::FetchLimits::max_concurrent_fetches_per_domain(),
+            max_requests_per_sec: None,
+            connect_timeout_seconds: None,
+            url_retry_backoff_ms: defaults::FetchLimits::url_retry_backoff_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WarcOutputConfig {
+    /// Write the WARC files to this folder instead of uploading them to S3.
+    #[serde(default)]
+    pub local_path: Option<String>,
+
+    /// A new WARC file is started once the current one reaches this (compressed) size.
+    #[serde(default = "defaults::WarcOutput::max_file_size_mb")]
+    pub max_file_size_mb: u64,
+}
+
+impl Default for WarcOutputConfig {
+    fn default() -> Self {
+        Self {
+            local_path: None,
+            max_file_size_mb: defaults::WarcOutput::max_file_size_mb(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProxyConfig {
+    /// Proxies to route crawl requests through. Both http(s)://
+    /// and socks5:// proxies are supported. Requests are sent directly
+    /// if the list is empty.
+    #[serde(default)]
+    pub urls: Vec<String>,
+
+    #[serde(default)]
+    pub assignment: ProxyAssignment,
+
+    /// Number of failed requests in a row before a proxy is taken out of rotation.
+    #[serde(default = "defaults::Crawler::proxy_max_consecutive_failures")]
+    pub max_consecutive_failures: usize,
+
+    #[serde(default = "defaults::Crawler::proxy_cooldown_sec")]
+    pub cooldown_sec: u64,
+
+    /// Url that is periodically requested through each proxy. Proxies that
+    /// fail the check are taken out of rotation and proxies that pass it
+    /// are put back in rotation before their cooldown ends.
+    #[serde(default)]
+    pub health_check_url: Option<String>,
+
+    #[serde(default = "defaults::Crawler::proxy_health_check_interval_sec")]
+    pub health_check_interval_sec: u64,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            assignment: ProxyAssignment::default(),
+            max_consecutive_failures: defaults::Crawler::proxy_max_consecutive_failures(),
+            cooldown_sec: defaults::Crawler::proxy_cooldown_sec(),
+            health_check_url: None,
+            health_check_interval_sec: defaults::Crawler::proxy_health_check_interval_sec(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyAssignment {
+    /// Rotate through the proxies for each new job.
+    #[default]
+    PerJob,
+    /// Always use the same proxy for a given domain.
+    PerDomain,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -364,6 +914,9 @@ pub struct WebgraphServerConfig {
     pub granularity: WebgraphGranularity,
     pub inbound_similarity_path: Option<String>,
 
+    /// Centrality of the nodes in the graph, used to rank the links of a node.
+    pub centrality_path: Option<String>,
+
     pub cluster_id: String,
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
     pub gossip_addr: SocketAddr,
@@ -378,6 +931,100 @@ pub struct WidgetsConfig {
 
     #[serde(default = "defaults::Widgets::calculator_fetch_currencies_exchange")]
     pub calculator_fetch_currencies_exchange: bool,
+
+    pub weather: Option<WeatherConfig>,
+
+    pub stock: Option<StockConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockConfig {
+    pub provider: StockProviderConfig,
+
+    #[serde(default = "defaults::Widgets::stock_cache_ttl_sec")]
+    pub cache_ttl_sec: u64,
+
+    /// How long failed lookups (e.g. unknown symbols) are cached.
+    #[serde(default = "defaults::Widgets::stock_negative_cache_ttl_sec")]
+    pub negative_cache_ttl_sec: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StockProviderConfig {
+    AlphaVantage {
+        api_key: String,
+        #[serde(default = "defaults::Widgets::alpha_vantage_url")]
+        url: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WeatherConfig {
+    #[serde(default)]
+    pub provider: WeatherProviderConfig,
+
+    #[serde(default = "defaults::Widgets::weather_cache_ttl_sec")]
+    pub cache_ttl_sec: u64,
+
+    /// How long failed lookups (e.g. unknown locations) are cached.
+    #[serde(default = "defaults::Widgets::weather_negative_cache_ttl_sec")]
+    pub negative_cache_ttl_sec: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WeatherProviderConfig {
+    OpenMeteo {
+        #[serde(default = "defaults::Widgets::open_meteo_geocoding_url")]
+        geocoding_url: String,
+        #[serde(default = "defaults::Widgets::open_meteo_forecast_url")]
+        forecast_url: String,
+    },
+}
+
+impl Default for WeatherProviderConfig {
+    fn default() -> Self {
+        Self::OpenMeteo {
+            geocoding_url: defaults::Widgets::open_meteo_geocoding_url(),
+            forecast_url: defaults::Widgets::open_meteo_forecast_url(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnnotationsConfig {
+    #[serde(default)]
+    pub providers: Vec<AnnotationProviderConfig>,
+
+    #[serde(default = "defaults::Annotations::cache_ttl_sec")]
+    pub cache_ttl_sec: u64,
+
+    #[serde(default = "defaults::Annotations::timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// Remove results flagged as malware or phishing instead of only warning about them.
+    #[serde(default)]
+    pub filter_dangerous: bool,
+}
+
+impl Default for AnnotationsConfig {
+    fn default() -> Self {
+        Self {
+            providers: Vec::new(),
+            cache_ttl_sec: defaults::Annotations::cache_ttl_sec(),
+            timeout_ms: defaults::Annotations::timeout_ms(),
+            filter_dangerous: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnnotationProviderConfig {
+    Http { url: String },
+    File { path: String },
+    Blocklist { path: String },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -394,6 +1041,117 @@ pub struct CrawlPlannerConfig {
     pub top_host_fraction: f64,
     pub wander_fraction: f64,
     pub top_n_hosts_surplus: usize,
+
+    /// Folder with synced malware/phishing hash lists. Hosts on the lists get a reduced budget.
+    #[serde(default)]
+    pub blocklist_path: Option<String>,
+
+    /// TrustRank of the hosts computed by `centrality trust-rank`. Hosts with little
+    /// trust get a reduced budget.
+    #[serde(default)]
+    pub host_trust_path: Option<String>,
+
+    /// Fetch history of previously crawled urls. Urls that are not yet due
+    /// for a recrawl are left out of the plan.
+    #[serde(default)]
+    pub recrawl_history_path: Option<String>,
+
+    #[serde(default)]
+    pub recrawl: RecrawlConfig,
+
+    #[serde(default)]
+    pub budget: CrawlBudgetConfig,
+
+    /// Same block and allow lists as the crawler's `url_filter_path`.
+    /// Blocked urls are never scheduled.
+    #[serde(default)]
+    pub url_filter_path: Option<String>,
+
+    /// Feed index built by the feed indexer from the crawled feeds. The known feeds
+    /// of a domain are added to its job so the worker can poll them.
+    #[serde(default)]
+    pub feed_index_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SeedImportConfig {
+    /// Where the seed files are downloaded from.
+    pub source: WarcSource,
+
+    /// Common Crawl url indexes (CDX or CDXJ) or plain url lists to import,
+    /// optionally gzipped. Defaults to the files listed by the source.
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    pub output_path: String,
+    pub num_job_queues: usize,
+
+    /// Number of urls the worker may discover on its own for each seeded domain.
+    #[serde(default)]
+    pub wander_budget: u64,
+
+    #[serde(default)]
+    pub budget: CrawlBudgetConfig,
+
+    /// Same block and allow lists as the crawler's `url_filter_path`.
+    #[serde(default)]
+    pub url_filter_path: Option<String>,
+
+    /// Max number of domains to import seeds for. Seeds for new domains
+    /// are skipped once this many domains have been seen.
+    #[serde(default = "defaults::SeedImport::max_domains")]
+    pub max_domains: usize,
+
+    /// Max number of seeds kept for a single domain. `budget.max_urls_per_domain`
+    /// lowers it further if set.
+    #[serde(default = "defaults::SeedImport::max_seeds_per_domain")]
+    pub max_seeds_per_domain: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct CrawlBudgetConfig {
+    /// Max number of urls (scheduled and wandering) in the job of a single domain.
+    #[serde(default)]
+    pub max_urls_per_domain: Option<u64>,
+
+    /// Max number of path segments in the urls that are crawled.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// Max number of urls for all domains under a public suffix, e.g. `xyz = 100000`.
+    #[serde(default)]
+    pub tld_caps: HashMap<String, u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecrawlCompactionConfig {
+    /// Fetch histories written by the indexers.
+    pub history_paths: Vec<String>,
+    pub output_path: String,
+
+    /// Urls that haven't been fetched for this many days are dropped.
+    #[serde(default)]
+    pub retention_days: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RecrawlConfig {
+    /// Urls are never recrawled more often than this, no matter how often they change.
+    #[serde(default = "defaults::Recrawl::min_interval_hours")]
+    pub min_interval_hours: u64,
+
+    /// Urls that never seem to change are still recrawled this often.
+    #[serde(default = "defaults::Recrawl::max_interval_days")]
+    pub max_interval_days: u64,
+}
+
+impl Default for RecrawlConfig {
+    fn default() -> Self {
+        Self {
+            min_interval_hours: defaults::Recrawl::min_interval_hours(),
+            max_interval_days: defaults::Recrawl::max_interval_days(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -417,6 +1175,8 @@ pub struct LiveIndexConfig {
     pub max_url_slowdown_retry: u8,
     #[serde(default = "defaults::Crawler::max_redirects")]
     pub max_redirects: usize,
+    #[serde(default)]
+    pub ip_family: IpFamily,
     pub timeout_seconds: u64,
 
     // indexer
@@ -440,6 +1200,9 @@ pub struct LiveIndexConfig {
     pub collector: CollectorConfig,
     #[serde(default)]
     pub snippet: SnippetConfig,
+
+    /// Urls sent to the live index for recrawling are queued here.
+    pub task_queue: TaskQueueConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -458,6 +1221,86 @@ pub struct FeedIndexingConfig {
     pub skip_warc_files: Option<usize>,
 }
 
+/// Configuration for building all artifacts needed to serve search
+/// (webgraphs, centrality, index, spell checker and autosuggest) in one run.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BuildAllConfig {
+    /// All artifacts, the build state and the manifest are written to this folder.
+    pub output_path: String,
+    pub warc_source: WarcSource,
+    pub limit_warc_files: Option<usize>,
+    pub skip_warc_files: Option<usize>,
+    pub batch_size: Option<usize>,
+
+    pub topics_path: Option<String>,
+    pub safety_classifier_path: Option<String>,
+    pub host_centrality_threshold: Option<f64>,
+    pub minimum_clean_words: Option<usize>,
+
+    /// Languages to build the spell checker for. The spell checker is skipped if empty.
+    #[serde(default)]
+    pub spell_languages: Vec<whatlang::Lang>,
+
+    /// Queries for autosuggest. Autosuggest is skipped if not set.
+    pub queries_csv_path: Option<String>,
+}
+
+/// Configuration for comparing the results of two searchers on the same queries,
+/// e.g. before deploying a new index generation, ranking model or collector config.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ResultDiffConfig {
+    /// File with one query per line.
+    pub queries_path: String,
+    /// The diff of every query and the summary metrics are written to this file as json.
+    pub output_path: String,
+    /// Number of top results to compare for each query.
+    #[serde(default = "defaults::ResultDiff::top_k")]
+    pub top_k: usize,
+    pub baseline: DiffSearcherConfig,
+    pub candidate: DiffSearcherConfig,
+}
+
+/// Configuration for building learning-to-rank training data from the clicks
+/// collected by the api and the ranking signals of an index.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LtrTrainingDataConfig {
+    pub click_log_path: String,
+    pub index_path: String,
+    pub host_centrality_store_path: Option<String>,
+    /// File with one query per line. If set, only these queries are used.
+    pub query_log_path: Option<String>,
+    /// The training and validation data is written to this folder.
+    pub output_path: String,
+    /// Only use queries that have been searched at least this many times.
+    #[serde(default = "defaults::LtrTrainingData::min_query_impressions")]
+    pub min_query_impressions: u64,
+    /// Number of results to compute the ranking signals for per query.
+    #[serde(default = "defaults::LtrTrainingData::num_results")]
+    pub num_results: usize,
+    /// Fraction of the queries that are used for validation instead of training.
+    #[serde(default = "defaults::LtrTrainingData::validation_fraction")]
+    pub validation_fraction: f64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DiffSearcherConfig {
+    pub index_path: String,
+    pub host_centrality_store_path: Option<String>,
+    pub linear_model_path: Option<String>,
+    pub lambda_model_path: Option<String>,
+    /// Optic that is applied to every query.
+    pub optic_path: Option<String>,
+
+    #[serde(default)]
+    pub collector: CollectorConfig,
+
+    #[serde(default)]
+    pub freshness: FreshnessConfig,
+
+    #[serde(default)]
+    pub ranking_pipeline: RankingPipelineConfig,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct WebSpellConfig {
     pub output_path: String,
@@ -0,0 +1,192 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reading and decoding of response bodies. The body is streamed so oversized
+//! responses are aborted as soon as they cross the size limit, and the charset
+//! is taken from (in order) a byte order mark, the content-type header or a
+//! charset declared in the document itself.
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use mime::Mime;
+use tokio_stream::StreamExt;
+
+use super::{Error, Result};
+
+/// Number of bytes searched for a `<meta charset>` or xml encoding declaration.
+const CHARSET_SNIFF_BYTES: usize = 1024;
+
+/// Read the body of the response and decode it to a string.
+/// Fails with [`Error::ContentTooLarge`] if the body is larger than `max_len` bytes.
+pub async fn read_body(res: reqwest::Response, max_len: usize) -> Result<String> {
+    if res.content_length().unwrap_or_default() as usize > max_len {
+        return Err(Error::ContentTooLarge.into());
+    }
+
+    let header_charset = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Mime>().ok())
+        .and_then(|mime| mime.get_param("charset").map(|charset| charset.to_string()));
+
+    let bytes = read_bytes(res, max_len).await?;
+
+    Ok(decode(&bytes, header_charset.as_deref()))
+}
+
+/// Read the raw bytes of the body without decoding them.
+/// Fails with [`Error::ContentTooLarge`] if the body is larger than `max_len` bytes.
+pub async fn read_bytes(res: reqwest::Response, max_len: usize) -> Result<Vec<u8>> {
+    if res.content_length().unwrap_or_default() as usize > max_len {
+        return Err(Error::ContentTooLarge.into());
+    }
+
+    let mut bytes = Vec::new();
+    let mut stream = res.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+
+        if bytes.len() + chunk.len() > max_len {
+            return Err(Error::ContentTooLarge.into());
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+/// Decode the body. Invalid sequences are replaced, so this never fails.
+pub fn decode(bytes: &[u8], header_charset: Option<&str>) -> String {
+    let declared = header_charset
+        .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+        .or_else(|| sniff_charset(bytes));
+
+    // `decode` lets a byte order mark override the given encoding.
+    let (text, _, had_errors) = declared.unwrap_or(UTF_8).decode(bytes);
+
+    if had_errors && declared.is_none() {
+        // pages without any charset information that aren't valid utf-8
+        // are most likely in the legacy default encoding of the web.
+        let (text, _, _) = WINDOWS_1252.decode(bytes);
+        return text.into_owned();
+    }
+
+    text.into_owned()
+}
+
+/// Find the charset declared by `<meta charset>`, `<meta http-equiv>`
+/// or an xml declaration at the start of the document.
+fn sniff_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(CHARSET_SNIFF_BYTES)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+
+    let declarations = head
+        .split("<meta")
+        .skip(1)
+        .map(|tag| ("charset", tag))
+        .chain(
+            head.trim_start()
+                .strip_prefix("<?xml")
+                .map(|decl| ("encoding", decl)),
+        );
+
+    for (attr, tag) in declarations {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+
+        let Some(pos) = tag.find(attr) else {
+            continue;
+        };
+
+        let Some(value) = tag[pos + attr.len()..].trim_start().strip_prefix('=') else {
+            continue;
+        };
+
+        let label: String = value
+            .trim_start()
+            .trim_start_matches(['"', '\''])
+            .chars()
+            .take_while(|c| !matches!(c, '"' | '\'' | ';' | '/' | '?') && !c.is_whitespace())
+            .collect();
+
+        if let Some(encoding) = Encoding::for_label(label.as_bytes()) {
+            // a document that can be read as ascii to find the declaration
+            // can't be utf-16, so the declaration is wrong.
+            if encoding == UTF_16LE || encoding == UTF_16BE {
+                return Some(UTF_8);
+            }
+
+            return Some(encoding);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_meta_charset() {
+        assert_eq!(
+            sniff_charset(br#"<html><head><meta charset="iso-8859-1"></head></html>"#),
+            Some(WINDOWS_1252)
+        );
+        assert_eq!(
+            sniff_charset(
+                br#"<html><head><META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=Shift_JIS"></head></html>"#
+            ),
+            Some(encoding_rs::SHIFT_JIS)
+        );
+        assert_eq!(
+            sniff_charset(br#"<meta name="description" content="charset"><meta charset=utf-8>"#),
+            Some(UTF_8)
+        );
+        assert_eq!(sniff_charset(br#"<meta charset="utf-16">"#), Some(UTF_8));
+        assert_eq!(sniff_charset(br#"<html><head></head></html>"#), None);
+    }
+
+    #[test]
+    fn sniff_xml_encoding() {
+        assert_eq!(
+            sniff_charset(br#"<?xml version="1.0" encoding="windows-1251"?><rss></rss>"#),
+            Some(encoding_rs::WINDOWS_1251)
+        );
+        assert_eq!(sniff_charset(br#"<?xml version="1.0"?><rss></rss>"#), None);
+    }
+
+    #[test]
+    fn decode_precedence() {
+        let latin1 = b"<meta charset=\"iso-8859-1\">caf\xe9";
+
+        assert_eq!(decode(latin1, None), "<meta charset=\"iso-8859-1\">café");
+        assert_eq!(
+            decode(latin1, Some("utf-8")),
+            "<meta charset=\"iso-8859-1\">caf\u{FFFD}"
+        );
+
+        let bom = b"\xef\xbb\xbfcaf\xc3\xa9";
+        assert_eq!(decode(bom, Some("iso-8859-1")), "café");
+    }
+
+    #[test]
+    fn undeclared_legacy_fallback() {
+        assert_eq!(decode("café".as_bytes(), None), "café");
+        assert_eq!(decode(b"caf\xe9", None), "café");
+    }
+}
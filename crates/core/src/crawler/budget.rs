@@ -0,0 +1,137 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits on how much of the crawl budget a single domain or top-level
+//! domain can take, and on how deep into a site the crawler goes.
+
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::config::CrawlBudgetConfig;
+
+use super::Domain;
+
+/// Number of non-empty path segments in the url.
+pub fn url_depth(url: &Url) -> usize {
+    url.path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).count())
+        .unwrap_or(0)
+}
+
+pub fn is_too_deep(url: &Url, max_depth: Option<usize>) -> bool {
+    match max_depth {
+        Some(max_depth) => url_depth(url) > max_depth,
+        None => false,
+    }
+}
+
+/// The public suffix of a domain, e.g. `co.uk` for `example.co.uk`.
+pub fn tld(domain: &Domain) -> &str {
+    domain
+        .as_str()
+        .split_once('.')
+        .map(|(_, suffix)| suffix)
+        .unwrap_or_default()
+}
+
+/// Cap the number of urls of each domain. Domains under a capped tld get budget in
+/// the order of their centrality until the tld cap is used up, so a spammy
+/// tld can't take more than its share of the crawl.
+///
+/// `domains` holds the wanted budget and the centrality of each domain.
+pub fn domain_caps(
+    domains: &[(Domain, u64, f64)],
+    config: &CrawlBudgetConfig,
+) -> HashMap<Domain, u64> {
+    let mut sorted: Vec<_> = domains.iter().collect();
+    sorted.sort_by(|(_, _, a), (_, _, b)| b.total_cmp(a));
+
+    let mut remaining_tld = config.tld_caps.clone();
+
+    sorted
+        .into_iter()
+        .map(|(domain, budget, _)| {
+            let mut cap = config.max_urls_per_domain.unwrap_or(u64::MAX);
+
+            if let Some(remaining) = remaining_tld.get_mut(tld(domain)) {
+                cap = cap.min(*remaining);
+                *remaining -= cap.min(*budget);
+            }
+
+            (domain.clone(), cap)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth() {
+        assert_eq!(url_depth(&Url::parse("https://example.com").unwrap()), 0);
+        assert_eq!(url_depth(&Url::parse("https://example.com/").unwrap()), 0);
+        assert_eq!(
+            url_depth(&Url::parse("https://example.com/a/b/").unwrap()),
+            2
+        );
+        assert_eq!(
+            url_depth(&Url::parse("https://example.com/a/b/c?d=e").unwrap()),
+            3
+        );
+
+        let url = Url::parse("https://example.com/a/b/c").unwrap();
+        assert!(!is_too_deep(&url, None));
+        assert!(!is_too_deep(&url, Some(3)));
+        assert!(is_too_deep(&url, Some(2)));
+    }
+
+    #[test]
+    fn tld_of_domain() {
+        assert_eq!(tld(&Domain::from("example.com".to_string())), "com");
+        assert_eq!(tld(&Domain::from("example.co.uk".to_string())), "co.uk");
+        assert_eq!(tld(&Domain::from("localhost".to_string())), "");
+    }
+
+    #[test]
+    fn caps() {
+        let config = CrawlBudgetConfig {
+            max_urls_per_domain: Some(100),
+            max_depth: None,
+            tld_caps: [("xyz".to_string(), 150)].into_iter().collect(),
+        };
+
+        let domains = vec![
+            (Domain::from("a.com".to_string()), 500, 1.0),
+            (Domain::from("b.com".to_string()), 50, 0.5),
+            (Domain::from("low.xyz".to_string()), 100, 0.1),
+            (Domain::from("high.xyz".to_string()), 100, 0.9),
+            (Domain::from("mid.xyz".to_string()), 100, 0.5),
+        ];
+
+        let caps = domain_caps(&domains, &config);
+
+        assert_eq!(caps[&Domain::from("a.com".to_string())], 100);
+        assert_eq!(caps[&Domain::from("b.com".to_string())], 100);
+        assert_eq!(caps[&Domain::from("high.xyz".to_string())], 100);
+        assert_eq!(caps[&Domain::from("mid.xyz".to_string())], 50);
+        assert_eq!(caps[&Domain::from("low.xyz".to_string())], 0);
+
+        let caps = domain_caps(&domains, &CrawlBudgetConfig::default());
+        assert!(caps.values().all(|cap| *cap == u64::MAX));
+    }
+}
@@ -14,21 +14,301 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::{file_queue::FileQueue, Job, Result};
-use std::{path::Path, sync::Mutex};
+use super::{file_queue::FileQueue, stats::CoordinatorStats, Job, Result};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const LEASES_FILE: &str = "leases";
+
+/// Jobs are dropped when their lease has expired this many times,
+/// as they most likely make the workers crash.
+const MAX_JOB_ATTEMPTS: u32 = 3;
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Lease {
+    job: Job,
+    expires: u64,
+    attempts: u32,
+}
+
+/// Jobs that have been handed out but not reported as done by a worker. The leases are
+/// persisted next to the job queue, so jobs that were in flight when the coordinator
+/// stopped are handed out again once their lease expires.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Leases {
+    active: BTreeMap<String, Lease>,
+    expired: VecDeque<Lease>,
+}
+
+impl Leases {
+    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if !path.as_ref().exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(bincode::deserialize(&std::fs::read(path)?)?)
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let tmp = path.as_ref().with_extension("tmp");
+        std::fs::write(&tmp, bincode::serialize(self)?)?;
+        std::fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Queue the jobs with expired leases to be handed out again.
+    /// Returns the number of jobs that were queued.
+    fn expire(&mut self, now: u64) -> usize {
+        let expired: Vec<_> = self
+            .active
+            .iter()
+            .filter(|(_, lease)| lease.expires <= now)
+            .map(|(domain, _)| domain.clone())
+            .collect();
+
+        let mut num_queued = 0;
+        for domain in &expired {
+            let lease = self.active.remove(domain).unwrap();
+
+            if lease.attempts >= MAX_JOB_ATTEMPTS {
+                tracing::warn!(
+                    "dropping job for {} after {} expired leases",
+                    domain,
+                    lease.attempts
+                );
+                continue;
+            }
+
+            self.expired.push_back(lease);
+            num_queued += 1;
+        }
+
+        num_queued
+    }
+
+    fn jobs(self) -> impl Iterator<Item = Job> {
+        self.active
+            .into_values()
+            .chain(self.expired)
+            .map(|lease| lease.job)
+    }
+}
+
+/// Jobs of the queue at `jobs_queue` that have been handed out, but not
+/// completed. These are in flight, or will be handed out again.
+pub(super) fn leased_jobs<P: AsRef<Path>>(jobs_queue: P) -> Result<Vec<Job>> {
+    Ok(Leases::open(jobs_queue.as_ref().join(LEASES_FILE))?
+        .jobs()
+        .collect())
+}
 
 pub struct CrawlCoordinator {
     jobs: Mutex<FileQueue<Job>>,
+    leases: Mutex<Leases>,
+    leases_path: PathBuf,
+    lease_timeout: Duration,
+    started: Instant,
+    dispatched_jobs: AtomicU64,
+    dispatched_urls: AtomicU64,
 }
 
 impl CrawlCoordinator {
-    pub fn new<P: AsRef<Path>>(jobs_queue: P) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(jobs_queue: P, lease_timeout: Duration) -> Result<Self> {
+        let leases_path = jobs_queue.as_ref().join(LEASES_FILE);
+        let jobs = FileQueue::new(jobs_queue)?;
+
+        let mut leases = Leases::open(&leases_path)?;
+        let expired = leases.expire(unix_now());
+        if expired > 0 {
+            tracing::info!(
+                "{} jobs will be handed out again as their leases expired",
+                expired
+            );
+        }
+        leases.save(&leases_path)?;
+
         Ok(Self {
-            jobs: Mutex::new(FileQueue::new(jobs_queue)?),
+            jobs: Mutex::new(jobs),
+            leases: Mutex::new(leases),
+            leases_path,
+            lease_timeout,
+            started: Instant::now(),
+            dispatched_jobs: AtomicU64::new(0),
+            dispatched_urls: AtomicU64::new(0),
         })
     }
 
+    /// Hand out the next job. Jobs with expired leases are handed out before new jobs.
     pub fn sample_job(&self) -> Result<Option<Job>> {
-        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).pop()
+        let now = unix_now();
+        let mut leases = self.leases.lock().unwrap_or_else(|e| e.into_inner());
+        leases.expire(now);
+
+        let (job, attempts) = match leases.expired.pop_front() {
+            Some(lease) => {
+                tracing::info!("handing out job for {:?} again", lease.job.domain);
+                (lease.job, lease.attempts + 1)
+            }
+            None => match self.jobs.lock().unwrap_or_else(|e| e.into_inner()).pop()? {
+                Some(job) => (job, 1),
+                None => return Ok(None),
+            },
+        };
+
+        leases.active.insert(
+            job.domain.as_str().to_string(),
+            Lease {
+                job: job.clone(),
+                expires: now + self.lease_timeout.as_secs(),
+                attempts,
+            },
+        );
+        leases.save(&self.leases_path)?;
+
+        self.dispatched_jobs.fetch_add(1, Ordering::Relaxed);
+        self.dispatched_urls
+            .fetch_add(job.urls.len() as u64, Ordering::Relaxed);
+
+        Ok(Some(job))
+    }
+
+    /// Release the lease of a job that a worker has finished.
+    pub fn complete_job(&self, domain: &str) -> Result<()> {
+        let mut leases = self.leases.lock().unwrap_or_else(|e| e.into_inner());
+
+        if leases.active.remove(domain).is_some() {
+            leases.save(&self.leases_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Summary of the pending jobs and the jobs handed out since the coordinator started.
+    /// The pending jobs are read from a snapshot of the queue, so jobs can still be
+    /// sampled while the stats are computed.
+    pub fn stats(&self) -> Result<CoordinatorStats> {
+        let pending = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pending();
+        let mut stats = CoordinatorStats::from_pending(pending)?;
+
+        let uptime = self.started.elapsed();
+
+        stats.dispatched_jobs = self.dispatched_jobs.load(Ordering::Relaxed);
+        stats.dispatched_urls = self.dispatched_urls.load(Ordering::Relaxed);
+        stats.uptime_secs = uptime.as_secs();
+        stats.jobs_per_minute = stats.dispatched_jobs as f64 / (uptime.as_secs_f64() / 60.0);
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::crawler::file_queue::FileQueueWriter;
+
+    use super::*;
+
+    fn queue(domains: &[&str]) -> PathBuf {
+        let path = crate::gen_temp_path();
+        let mut writer = FileQueueWriter::new(&path).unwrap();
+
+        for domain in domains {
+            writer
+                .push(Job {
+                    domain: domain.to_string().into(),
+                    urls: VecDeque::new(),
+                    wandering_urls: 10,
+                    max_depth: None,
+                    feeds: Vec::new(),
+                })
+                .unwrap();
+        }
+
+        writer.finalize().unwrap();
+
+        path
+    }
+
+    fn domain(job: Option<Job>) -> String {
+        job.unwrap().domain.as_str().to_string()
+    }
+
+    #[test]
+    fn completed_jobs_are_not_handed_out_again() {
+        let path = queue(&["a.com", "b.com"]);
+        let coordinator = CrawlCoordinator::new(&path, Duration::ZERO).unwrap();
+
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "a.com");
+        coordinator.complete_job("a.com").unwrap();
+
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "b.com");
+        coordinator.complete_job("b.com").unwrap();
+
+        assert!(coordinator.sample_job().unwrap().is_none());
+    }
+
+    #[test]
+    fn expired_leases_are_handed_out_again() {
+        let path = queue(&["a.com", "b.com"]);
+        let coordinator = CrawlCoordinator::new(&path, Duration::ZERO).unwrap();
+
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "a.com");
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "a.com");
+        coordinator.complete_job("a.com").unwrap();
+
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "b.com");
+        for _ in 1..MAX_JOB_ATTEMPTS {
+            assert_eq!(domain(coordinator.sample_job().unwrap()), "b.com");
+        }
+
+        assert!(coordinator.sample_job().unwrap().is_none());
+    }
+
+    #[test]
+    fn leases_survive_restart() {
+        let path = queue(&["a.com", "b.com"]);
+
+        let coordinator = CrawlCoordinator::new(&path, Duration::from_secs(3600)).unwrap();
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "a.com");
+        drop(coordinator);
+
+        // the lease has not expired yet, so the job is still in flight.
+        let coordinator = CrawlCoordinator::new(&path, Duration::from_secs(3600)).unwrap();
+        assert_eq!(domain(coordinator.sample_job().unwrap()), "b.com");
+        assert!(coordinator.sample_job().unwrap().is_none());
+        drop(coordinator);
+
+        let mut leases = Leases::open(path.join(LEASES_FILE)).unwrap();
+        for lease in leases.active.values_mut() {
+            lease.expires = 0;
+        }
+        leases.save(path.join(LEASES_FILE)).unwrap();
+
+        let coordinator = CrawlCoordinator::new(&path, Duration::from_secs(3600)).unwrap();
+        let mut recovered = vec![
+            domain(coordinator.sample_job().unwrap()),
+            domain(coordinator.sample_job().unwrap()),
+        ];
+        recovered.sort();
+
+        assert_eq!(recovered, vec!["a.com".to_string(), "b.com".to_string()]);
+        assert!(coordinator.sample_job().unwrap().is_none());
     }
 }
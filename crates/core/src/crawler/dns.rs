@@ -0,0 +1,370 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Dual-stack DNS resolution for the crawler.
+//!
+//! The resolver orders the resolved addresses by interleaving the address
+//! families (RFC 8305 section 4). The http connector uses the family of the first
+//! address as the preferred family and races the other family after a short delay,
+//! so the interleaving gives us happy-eyeballs connection establishment.
+//!
+//! Lookups are cached for all clients of the crawler. The system resolver doesn't
+//! tell us the ttl of the records, so addresses are cached for a configured time
+//! that should be below the ttl most sites use. Failed lookups are cached for a
+//! shorter time, so a broken domain doesn't hit the resolver for every url.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+use crate::{
+    config::{CrawlerConfig, IpFamily},
+    metrics::{Counter, Label, PrometheusRegistry},
+};
+
+/// The cache is cleared of expired entries when it reaches this size.
+const MAX_CACHED_HOSTS: usize = 100_000;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A failed lookup. Lets the crawler tell dns failures apart from other connection errors.
+#[derive(thiserror::Error, Debug)]
+#[error("dns lookup failed: {0}")]
+pub struct DnsError(String);
+
+#[derive(Default, Clone)]
+pub struct FamilyMetrics {
+    pub ipv4_addrs: Counter,
+    pub ipv6_addrs: Counter,
+    pub failed_lookups: Counter,
+    pub cache_hits: Counter,
+}
+
+impl FamilyMetrics {
+    pub fn register(&self, registry: &mut PrometheusRegistry) {
+        let group = registry
+            .new_group(
+                "stract_crawler_resolved_addrs".to_string(),
+                Some("Number of resolved addresses by address family.".to_string()),
+            )
+            .unwrap();
+
+        group.register(
+            self.ipv4_addrs.clone(),
+            vec![Label {
+                key: "family".to_string(),
+                val: "ipv4".to_string(),
+            }],
+        );
+        group.register(
+            self.ipv6_addrs.clone(),
+            vec![Label {
+                key: "family".to_string(),
+                val: "ipv6".to_string(),
+            }],
+        );
+
+        let group = registry
+            .new_group(
+                "stract_crawler_failed_lookups".to_string(),
+                Some("Number of failed dns lookups.".to_string()),
+            )
+            .unwrap();
+        group.register(self.failed_lookups.clone(), vec![]);
+
+        let group = registry
+            .new_group(
+                "stract_crawler_dns_cache_hits".to_string(),
+                Some("Number of dns lookups answered from the cache.".to_string()),
+            )
+            .unwrap();
+        group.register(self.cache_hits.clone(), vec![]);
+    }
+
+    fn record(&self, addrs: &[SocketAddr]) {
+        for addr in addrs {
+            match addr {
+                SocketAddr::V4(_) => self.ipv4_addrs.inc(),
+                SocketAddr::V6(_) => self.ipv6_addrs.inc(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CachedLookup {
+    Found(Vec<SocketAddr>),
+    Failed(String),
+}
+
+struct CacheEntry {
+    lookup: CachedLookup,
+    expires: Instant,
+}
+
+struct DnsCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl DnsCache {
+    fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            negative_ttl,
+        }
+    }
+
+    fn get(&self, host: &str, now: Instant) -> Option<CachedLookup> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(host)
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.lookup.clone())
+    }
+
+    fn insert(&self, host: String, lookup: CachedLookup, now: Instant) {
+        let ttl = match lookup {
+            CachedLookup::Found(_) => self.ttl,
+            CachedLookup::Failed(_) => self.negative_ttl,
+        };
+
+        if ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        if entries.len() >= MAX_CACHED_HOSTS {
+            entries.retain(|_, entry| entry.expires > now);
+
+            if entries.len() >= MAX_CACHED_HOSTS {
+                entries.clear();
+            }
+        }
+
+        entries.insert(
+            host,
+            CacheEntry {
+                lookup,
+                expires: now + ttl,
+            },
+        );
+    }
+}
+
+#[derive(Clone)]
+pub struct Resolver {
+    family: IpFamily,
+    metrics: FamilyMetrics,
+    cache: Arc<DnsCache>,
+}
+
+impl Resolver {
+    pub fn new(config: &CrawlerConfig, metrics: FamilyMetrics) -> Self {
+        Self {
+            family: config.ip_family,
+            metrics,
+            cache: Arc::new(DnsCache::new(
+                Duration::from_secs(config.dns_cache_ttl_sec),
+                Duration::from_secs(config.dns_negative_cache_ttl_sec),
+            )),
+        }
+    }
+
+    pub async fn lookup(&self, host: &str) -> Result<Vec<SocketAddr>, BoxError> {
+        let lookup = match self.cache.get(host, Instant::now()) {
+            Some(lookup) => {
+                self.metrics.cache_hits.inc();
+                lookup
+            }
+            None => {
+                let lookup = lookup(host, self.family, &self.metrics).await;
+                self.cache
+                    .insert(host.to_string(), lookup.clone(), Instant::now());
+                lookup
+            }
+        };
+
+        match lookup {
+            CachedLookup::Found(addrs) => Ok(addrs),
+            CachedLookup::Failed(err) => Err(Box::new(DnsError(err))),
+        }
+    }
+
+    /// Resolve the hosts in the background, so their addresses are
+    /// already cached when the urls are fetched.
+    pub fn prefetch(&self, hosts: impl IntoIterator<Item = String>) {
+        let now = Instant::now();
+
+        for host in hosts {
+            if self.cache.get(&host, now).is_some() {
+                continue;
+            }
+
+            let resolver = self.clone();
+            tokio::spawn(async move {
+                resolver.lookup(&host).await.ok();
+            });
+        }
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs = resolver.lookup(&host).await?;
+            Ok::<_, BoxError>(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn lookup(host: &str, family: IpFamily, metrics: &FamilyMetrics) -> CachedLookup {
+    let addrs = match tokio::net::lookup_host((host, 0)).await {
+        Ok(addrs) => addrs.collect::<Vec<_>>(),
+        Err(err) => {
+            metrics.failed_lookups.inc();
+            return CachedLookup::Failed(err.to_string());
+        }
+    };
+
+    let addrs = order_addrs(addrs, family);
+
+    if addrs.is_empty() {
+        metrics.failed_lookups.inc();
+        return CachedLookup::Failed(format!("no {family:?} addresses found for {host}"));
+    }
+
+    metrics.record(&addrs);
+
+    CachedLookup::Found(addrs)
+}
+
+/// Filter the addresses to the allowed families and interleave them
+/// so that the first address is ipv6 (if any) followed by an ipv4 address and so on.
+fn order_addrs(addrs: Vec<SocketAddr>, family: IpFamily) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+
+    match family {
+        IpFamily::Any => {}
+        IpFamily::V4 => v6.clear(),
+        IpFamily::V6 => v4.clear(),
+    }
+
+    let mut res = Vec::with_capacity(v4.len() + v6.len());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => res.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_expiry() {
+        let cache = DnsCache::new(Duration::from_secs(60), Duration::from_secs(10));
+        let now = Instant::now();
+        let found = CachedLookup::Found(vec!["1.1.1.1:0".parse().unwrap()]);
+        let failed = CachedLookup::Failed("no addresses".to_string());
+
+        cache.insert("found.com".to_string(), found.clone(), now);
+        cache.insert("failed.com".to_string(), failed.clone(), now);
+
+        assert_eq!(cache.get("found.com", now), Some(found.clone()));
+        assert_eq!(cache.get("failed.com", now), Some(failed));
+        assert_eq!(cache.get("other.com", now), None);
+
+        let later = now + Duration::from_secs(30);
+        assert_eq!(cache.get("found.com", later), Some(found));
+        assert_eq!(cache.get("failed.com", later), None);
+
+        assert_eq!(cache.get("found.com", now + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn zero_ttl_disables_cache() {
+        let cache = DnsCache::new(Duration::ZERO, Duration::ZERO);
+        let now = Instant::now();
+
+        cache.insert(
+            "example.com".to_string(),
+            CachedLookup::Failed("no addresses".to_string()),
+            now,
+        );
+
+        assert_eq!(cache.get("example.com", now), None);
+    }
+
+    #[test]
+    fn interleave_families() {
+        let addrs: Vec<SocketAddr> = vec![
+            "1.1.1.1:80".parse().unwrap(),
+            "2.2.2.2:80".parse().unwrap(),
+            "3.3.3.3:80".parse().unwrap(),
+            "[::1]:80".parse().unwrap(),
+            "[::2]:80".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            order_addrs(addrs.clone(), IpFamily::Any),
+            vec![
+                "[::1]:80".parse::<SocketAddr>().unwrap(),
+                "1.1.1.1:80".parse().unwrap(),
+                "[::2]:80".parse().unwrap(),
+                "2.2.2.2:80".parse().unwrap(),
+                "3.3.3.3:80".parse().unwrap(),
+            ]
+        );
+
+        assert_eq!(
+            order_addrs(addrs.clone(), IpFamily::V4),
+            vec![
+                "1.1.1.1:80".parse::<SocketAddr>().unwrap(),
+                "2.2.2.2:80".parse().unwrap(),
+                "3.3.3.3:80".parse().unwrap(),
+            ]
+        );
+
+        assert_eq!(
+            order_addrs(addrs, IpFamily::V6),
+            vec![
+                "[::1]:80".parse::<SocketAddr>().unwrap(),
+                "[::2]:80".parse().unwrap(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Classification of failed fetches. Transient failures, like timeouts or
+//! overloaded servers, are retried later in the job with an exponential backoff,
+//! while permanent failures are given up on right away.
+
+use std::time::Duration;
+
+use super::{dns::DnsError, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum FailureReason {
+    Dns,
+    Tls,
+    Timeout,
+    Connect,
+    RobotsDenied,
+    TooLarge,
+    InvalidContentType,
+    Parse,
+    Status(u16),
+    Other,
+}
+
+impl FailureReason {
+    pub const KINDS: [&'static str; 10] = [
+        "dns",
+        "tls",
+        "timeout",
+        "connect",
+        "robots_denied",
+        "too_large",
+        "invalid_content_type",
+        "parse",
+        "status",
+        "other",
+    ];
+
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        if let Some(err) = err.downcast_ref::<Error>() {
+            return match err {
+                Error::ContentTooLarge => FailureReason::TooLarge,
+                Error::InvalidContentType(_) => FailureReason::InvalidContentType,
+                Error::FetchFailed(status) => FailureReason::Status(status.as_u16()),
+                _ => FailureReason::Other,
+            };
+        }
+
+        let Some(err) = err.downcast_ref::<reqwest::Error>() else {
+            return FailureReason::Other;
+        };
+
+        if err.is_timeout() {
+            return FailureReason::Timeout;
+        }
+
+        // reqwest doesn't tell what part of the connection failed,
+        // so we have to look through the chain of errors.
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+        while let Some(err) = source {
+            if err.is::<DnsError>() {
+                return FailureReason::Dns;
+            }
+
+            let msg = err.to_string().to_ascii_lowercase();
+            if msg.contains("certificate") || msg.contains("tls") || msg.contains("ssl") {
+                return FailureReason::Tls;
+            }
+
+            source = err.source();
+        }
+
+        if err.is_connect() {
+            FailureReason::Connect
+        } else {
+            FailureReason::Other
+        }
+    }
+
+    /// Label of the reason in the crawler metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FailureReason::Dns => "dns",
+            FailureReason::Tls => "tls",
+            FailureReason::Timeout => "timeout",
+            FailureReason::Connect => "connect",
+            FailureReason::RobotsDenied => "robots_denied",
+            FailureReason::TooLarge => "too_large",
+            FailureReason::InvalidContentType => "invalid_content_type",
+            FailureReason::Parse => "parse",
+            FailureReason::Status(_) => "status",
+            FailureReason::Other => "other",
+        }
+    }
+
+    /// Whether the url might be fetched successfully if we try again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FailureReason::Timeout | FailureReason::Connect => true,
+            FailureReason::Status(status) => matches!(status, 429 | 500 | 502 | 503 | 504),
+            FailureReason::Dns
+            | FailureReason::Tls
+            | FailureReason::RobotsDenied
+            | FailureReason::TooLarge
+            | FailureReason::InvalidContentType
+            | FailureReason::Parse
+            | FailureReason::Other => false,
+        }
+    }
+
+    /// Time to wait before the next attempt after `retries` failed attempts.
+    /// Throttled urls are not delayed here, as the politeness of the domain
+    /// already slows down all requests to it.
+    pub fn retry_backoff(&self, retries: u8, base: Duration) -> Duration {
+        match self {
+            FailureReason::Status(429 | 503) => Duration::ZERO,
+            _ => base.saturating_mul(2u32.saturating_pow(retries as u32)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crawler_errors() {
+        assert_eq!(
+            FailureReason::from_error(&Error::ContentTooLarge.into()),
+            FailureReason::TooLarge
+        );
+        assert_eq!(
+            FailureReason::from_error(&Error::InvalidContentType("image/png".to_string()).into()),
+            FailureReason::InvalidContentType
+        );
+        assert_eq!(
+            FailureReason::from_error(&anyhow::anyhow!("something else")),
+            FailureReason::Other
+        );
+    }
+
+    #[test]
+    fn transient() {
+        assert!(FailureReason::Timeout.is_transient());
+        assert!(FailureReason::Status(503).is_transient());
+        assert!(FailureReason::Status(502).is_transient());
+        assert!(!FailureReason::Status(404).is_transient());
+        assert!(!FailureReason::Dns.is_transient());
+        assert!(!FailureReason::RobotsDenied.is_transient());
+    }
+
+    #[test]
+    fn backoff() {
+        let base = Duration::from_secs(1);
+
+        assert_eq!(
+            FailureReason::Timeout.retry_backoff(0, base),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            FailureReason::Timeout.retry_backoff(2, base),
+            Duration::from_secs(4)
+        );
+        assert_eq!(
+            FailureReason::Status(429).retry_backoff(2, base),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn kinds() {
+        for (reason, kind) in [
+            FailureReason::Dns,
+            FailureReason::Tls,
+            FailureReason::Timeout,
+            FailureReason::Connect,
+            FailureReason::RobotsDenied,
+            FailureReason::TooLarge,
+            FailureReason::InvalidContentType,
+            FailureReason::Parse,
+            FailureReason::Status(404),
+            FailureReason::Other,
+        ]
+        .iter()
+        .zip(FailureReason::KINDS)
+        {
+            assert_eq!(reason.kind(), kind);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Favicons are fetched in the background on the task queue. The jobs push a task
+//! for every host they crawl, and the fetcher downloads the favicons of the hosts
+//! that are not in the store yet.
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    image_store::{FaviconStore, Image, ImageStore, FAVICON_SIZE},
+    task_queue::Task,
+    webpage::url_ext::UrlExt,
+};
+
+use super::{body, limits::FetchLimiter, proxy::ProxyPool, Domain, Error, Result};
+
+/// Name of the task queue with the favicons to fetch.
+pub const QUEUE: &str = "favicons";
+
+/// Favicons larger than this are not stored.
+const MAX_FAVICON_LENGTH: usize = 512 * 1024; // 512 KB
+
+/// Favicons wider or taller than this are not decoded.
+const MAX_FAVICON_DIMENSION: u32 = 1024;
+
+/// Whether the favicon is on the same site as the host, so pages
+/// can't make the crawler request urls on other hosts.
+pub fn is_same_site(host: &str, favicon: &Url) -> bool {
+    let Ok(site) = Url::parse(&format!("http://{host}/")) else {
+        return false;
+    };
+
+    site.root_domain().is_some() && site.root_domain() == favicon.root_domain()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaviconTask {
+    pub host: String,
+    pub url: Url,
+}
+
+pub struct FaviconFetcher {
+    store: Arc<Mutex<FaviconStore>>,
+    client: reqwest::Client,
+    proxy_pool: Option<Arc<ProxyPool>>,
+    limiter: Arc<FetchLimiter>,
+}
+
+impl FaviconFetcher {
+    pub fn new(
+        store: Arc<Mutex<FaviconStore>>,
+        client: reqwest::Client,
+        proxy_pool: Option<Arc<ProxyPool>>,
+        limiter: Arc<FetchLimiter>,
+    ) -> Self {
+        Self {
+            store,
+            client,
+            proxy_pool,
+            limiter,
+        }
+    }
+
+    fn contains(&self, host: &str) -> bool {
+        self.store
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(host)
+    }
+
+    /// Fetch and store the favicon of the task's host. Only network errors fail the
+    /// task, since hosts without a usable favicon won't have one on the next attempt.
+    pub async fn handle(&self, task: Task<FaviconTask>) -> Result<()> {
+        let FaviconTask { host, url } = task.payload;
+
+        if self.contains(&host) || !is_same_site(&host, &url) {
+            return Ok(());
+        }
+
+        match self.fetch(url).await {
+            Ok(image) => self
+                .store
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(host, image),
+            Err(err) if err.is::<reqwest::Error>() => return Err(err),
+            Err(err) => tracing::debug!("failed to fetch favicon for {}: {}", host, err),
+        }
+
+        Ok(())
+    }
+
+    async fn fetch(&self, url: Url) -> Result<Image> {
+        let domain = Domain::from(&url);
+        let client = match &self.proxy_pool {
+            Some(pool) => pool.assign(&domain).client().clone(),
+            None => self.client.clone(),
+        };
+
+        let _permit = self.limiter.acquire(&domain).await;
+        let res = client.get(url.to_string()).send().await?;
+
+        if !res.status().is_success() {
+            return Err(Error::FetchFailed(res.status()).into());
+        }
+
+        let bytes = body::read_bytes(res, MAX_FAVICON_LENGTH).await?;
+
+        // decoding and resizing are cpu bound, so they run outside the async runtime
+        // and without the lock on the store
+        tokio::task::spawn_blocking(move || {
+            Image::from_untrusted_bytes(&bytes, MAX_FAVICON_DIMENSION, MAX_FAVICON_DIMENSION)
+                .map(|image| image.square(FAVICON_SIZE))
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_site() {
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert!(is_same_site(
+            "example.com",
+            &url("https://static.example.com/favicon.png")
+        ));
+        assert!(is_same_site(
+            "www.example.com",
+            &url("https://example.com/icon.ico")
+        ));
+        assert!(!is_same_site(
+            "example.com",
+            &url("https://other.com/favicon.ico")
+        ));
+        assert!(!is_same_site(
+            "example.com",
+            &url("http://169.254.169.254/latest")
+        ));
+    }
+}
@@ -20,6 +20,7 @@ use std::{
     fs::File,
     io::{BufWriter, Read, Seek, Write},
     path::PathBuf,
+    sync::Arc,
 };
 
 const POINTER_KEY: &str = "pointer";
@@ -85,7 +86,7 @@ where
 
         Ok(FileQueue {
             pointer: FilePointer::new(self.path)?,
-            file: unsafe { Mmap::map(&file)? },
+            file: Arc::new(unsafe { Mmap::map(&file)? }),
             _marker: std::marker::PhantomData,
         })
     }
@@ -132,7 +133,7 @@ impl FilePointer {
 
 pub struct FileQueue<T> {
     pointer: FilePointer,
-    file: Mmap,
+    file: Arc<Mmap>,
     _marker: std::marker::PhantomData<T>,
 }
 
@@ -150,7 +151,7 @@ where
 
         Ok(Self {
             pointer: FilePointer::new(path)?,
-            file,
+            file: Arc::new(file),
             _marker: std::marker::PhantomData,
         })
     }
@@ -175,6 +176,36 @@ where
 
         Ok(Some(item))
     }
+
+    /// Iterate the items that have not been popped yet without removing them.
+    /// The iterator reads from a snapshot of the queue, so the queue can be used
+    /// while it is consumed.
+    pub fn pending(&mut self) -> impl Iterator<Item = Result<T>> {
+        let mut cur_pointer = self.pointer.get();
+        let file = Arc::clone(&self.file);
+
+        std::iter::from_fn(move || {
+            if cur_pointer >= file.len() {
+                return None;
+            }
+
+            let header_size = std::mem::size_of::<Header>();
+            let header: Header =
+                match bincode::deserialize(&file[cur_pointer..cur_pointer + header_size]) {
+                    Ok(header) => header,
+                    Err(err) => {
+                        cur_pointer = file.len();
+                        return Some(Err(err.into()));
+                    }
+                };
+
+            let body =
+                &file[cur_pointer + header_size..cur_pointer + header_size + header.body_size];
+            cur_pointer += header_size + header.body_size;
+
+            Some(bincode::deserialize(body).map_err(|err| err.into()))
+        })
+    }
 }
 
 #[cfg(test)]
@@ -196,6 +227,39 @@ mod tests {
         assert_eq!(queue.pop().unwrap(), None);
     }
 
+    #[test]
+    fn pending() {
+        let mut writer = FileQueueWriter::new(crate::gen_temp_path()).unwrap();
+
+        writer.push("Hello".to_string()).unwrap();
+        writer.push("World".to_string()).unwrap();
+
+        let mut queue = writer.finalize().unwrap();
+        assert_eq!(queue.pop().unwrap().unwrap(), "Hello");
+
+        let pending: Vec<String> = queue.pending().map(|item| item.unwrap()).collect();
+        assert_eq!(pending, vec!["World".to_string()]);
+
+        assert_eq!(queue.pop().unwrap().unwrap(), "World");
+        assert_eq!(queue.pending().count(), 0);
+    }
+
+    #[test]
+    fn pending_is_a_snapshot() {
+        let mut writer = FileQueueWriter::new(crate::gen_temp_path()).unwrap();
+
+        writer.push("Hello".to_string()).unwrap();
+        writer.push("World".to_string()).unwrap();
+
+        let mut queue = writer.finalize().unwrap();
+        let pending = queue.pending();
+
+        assert_eq!(queue.pop().unwrap().unwrap(), "Hello");
+
+        let pending: Vec<String> = pending.map(|item| item.unwrap()).collect();
+        assert_eq!(pending, vec!["Hello".to_string(), "World".to_string()]);
+    }
+
     proptest! {
         #[test]
         fn prop(data: Vec<String>) {
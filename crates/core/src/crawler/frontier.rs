@@ -0,0 +1,356 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Export and import of the crawl frontier, so a crawl can be moved to another
+//! machine or inspected with external tools. The frontier consists of the jobs
+//! in the job queues that have not been completed yet, both the ones that are still
+//! queued and the ones the coordinators have leased to workers, and the fetch
+//! history of the crawled urls.
+//!
+//! A dump is a stream of records. Each record is its length in bytes as a
+//! little-endian u64 followed by the bincode encoded [`FrontierRecord`].
+//! The first record is always [`FrontierRecord::Queues`].
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use super::{
+    coordinator,
+    file_queue::{FileQueue, FileQueueWriter},
+    recrawl::{FetchHistory, RecrawlHistory},
+    Job, UrlString,
+};
+
+/// Records larger than this are rejected when reading a dump, so a corrupt
+/// length doesn't make us allocate an arbitrary amount of memory.
+const MAX_RECORD_LEN: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum FrontierRecord {
+    /// Number of job queues in the plan.
+    Queues(usize),
+    /// A job that has not been completed yet from the queue with the given index.
+    Job { queue: usize, job: Job },
+    History {
+        url: UrlString,
+        history: FetchHistory,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct FrontierStats {
+    pub jobs: u64,
+    pub urls: u64,
+    pub histories: u64,
+}
+
+struct FrontierWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> FrontierWriter<W> {
+    fn write(&mut self, record: &FrontierRecord) -> Result<()> {
+        let bytes = bincode::serialize(record)?;
+
+        self.writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+
+struct FrontierReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> Iterator for FrontierReader<R> {
+    type Item = Result<FrontierRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0u8; 8];
+
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err.into())),
+        }
+
+        let len = u64::from_le_bytes(len);
+        if len > MAX_RECORD_LEN {
+            return Some(Err(anyhow!(
+                "frontier record of {len} bytes exceeds the limit of {MAX_RECORD_LEN} bytes"
+            )));
+        }
+
+        let mut bytes = vec![0; len as usize];
+
+        Some(
+            self.reader
+                .read_exact(&mut bytes)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| Ok(bincode::deserialize(&bytes)?)),
+        )
+    }
+}
+
+/// The queues of a plan are stored as `<i>.queue` folders in the job queue folder.
+fn queue_paths(job_queue: &Path) -> Result<Vec<PathBuf>> {
+    let mut queues = Vec::new();
+
+    for entry in std::fs::read_dir(job_queue)? {
+        let path = entry?.path();
+
+        if let Some(idx) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".queue"))
+            .and_then(|idx| idx.parse::<usize>().ok())
+        {
+            queues.push((idx, path));
+        }
+    }
+
+    queues.sort_by_key(|(idx, _)| *idx);
+
+    if queues.iter().enumerate().any(|(i, (idx, _))| i != *idx) {
+        return Err(anyhow!(
+            "job queues in {:?} are not numbered 0..n",
+            job_queue
+        ));
+    }
+
+    Ok(queues.into_iter().map(|(_, path)| path).collect())
+}
+
+fn queue_path(job_queue: &Path, idx: usize) -> PathBuf {
+    job_queue.join(format!("{}.queue", idx))
+}
+
+/// Write the leased and pending jobs of the job queues and, if given,
+/// the fetch history to `output`. Leased jobs are written first, so they
+/// are handed out before the pending jobs after an import.
+pub fn export<P: AsRef<Path>, Q: AsRef<Path>>(
+    job_queue: P,
+    history: Option<&RecrawlHistory>,
+    output: Q,
+) -> Result<FrontierStats> {
+    if output.as_ref().exists() {
+        return Err(anyhow!("output path already exists"));
+    }
+
+    let queues = queue_paths(job_queue.as_ref())?;
+    let mut writer = FrontierWriter {
+        writer: BufWriter::new(File::create(output.as_ref())?),
+    };
+    let mut stats = FrontierStats::default();
+
+    writer.write(&FrontierRecord::Queues(queues.len()))?;
+
+    for (idx, path) in queues.iter().enumerate() {
+        let mut queue: FileQueue<Job> = FileQueue::new(path)?;
+        let leased = coordinator::leased_jobs(path)?;
+
+        for job in leased.into_iter().map(Ok).chain(queue.pending()) {
+            let job = job?;
+
+            stats.jobs += 1;
+            stats.urls += job.urls.len() as u64;
+
+            writer.write(&FrontierRecord::Job { queue: idx, job })?;
+        }
+    }
+
+    if let Some(history) = history {
+        for (url, history) in history.iter() {
+            stats.histories += 1;
+            writer.write(&FrontierRecord::History { url, history })?;
+        }
+    }
+
+    writer.writer.flush()?;
+
+    Ok(stats)
+}
+
+/// Recreate the job queues of an exported frontier in `job_queue`. The fetch history
+/// is imported into `history` if given and skipped otherwise.
+pub fn import<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    job_queue: Q,
+    history: Option<&RecrawlHistory>,
+) -> Result<FrontierStats> {
+    if job_queue.as_ref().exists() {
+        return Err(anyhow!("job queue path already exists"));
+    }
+
+    let mut records = FrontierReader {
+        reader: BufReader::new(File::open(input.as_ref())?),
+    };
+
+    let num_queues = match records.next() {
+        Some(Ok(FrontierRecord::Queues(num_queues))) => num_queues,
+        Some(Err(err)) => return Err(err),
+        _ => {
+            return Err(anyhow!(
+                "frontier dump does not start with the number of queues"
+            ))
+        }
+    };
+
+    let mut queues: Vec<FileQueueWriter<Job>> = (0..num_queues)
+        .map(|idx| FileQueueWriter::new(queue_path(job_queue.as_ref(), idx)))
+        .collect::<Result<_>>()?;
+
+    let mut stats = FrontierStats::default();
+    let mut skipped_histories = 0;
+
+    for record in records {
+        match record? {
+            FrontierRecord::Queues(_) => {
+                return Err(anyhow!("frontier dump contains multiple queue records"))
+            }
+            FrontierRecord::Job { queue, job } => {
+                stats.jobs += 1;
+                stats.urls += job.urls.len() as u64;
+
+                queues
+                    .get_mut(queue)
+                    .ok_or_else(|| anyhow!("job for unknown queue {queue}"))?
+                    .push(job)?;
+            }
+            FrontierRecord::History { url, history: h } => match history {
+                Some(history) => {
+                    stats.histories += 1;
+                    history.insert(url, h);
+                }
+                None => skipped_histories += 1,
+            },
+        }
+    }
+
+    for queue in queues {
+        queue.finalize()?;
+    }
+
+    if let Some(history) = history {
+        history.flush();
+    }
+
+    if skipped_histories > 0 {
+        tracing::warn!("skipped {skipped_histories} fetch histories as no history path was given");
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use url::Url;
+
+    use crate::crawler::{CrawlCoordinator, Domain, Validators, WeightedUrl};
+
+    use super::*;
+
+    fn job(domain: &str) -> Job {
+        Job {
+            domain: Domain::from(domain.to_string()),
+            urls: VecDeque::from(vec![WeightedUrl {
+                url: Url::parse(&format!("https://{domain}/")).unwrap(),
+                weight: 1.0,
+                validators: Validators::default(),
+            }]),
+            wandering_urls: 10,
+            max_depth: None,
+            feeds: Vec::new(),
+        }
+    }
+
+    fn pending(job_queue: &Path, idx: usize) -> Vec<String> {
+        let mut queue: FileQueue<Job> = FileQueue::new(queue_path(job_queue, idx)).unwrap();
+
+        queue
+            .pending()
+            .map(|job| job.unwrap().domain.as_str().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let plan = crate::gen_temp_path();
+
+        for (idx, domains) in [vec!["a.com", "b.com", "c.com"], vec![], vec!["d.com"]]
+            .into_iter()
+            .enumerate()
+        {
+            let mut writer = FileQueueWriter::new(queue_path(&plan, idx)).unwrap();
+            for domain in domains {
+                writer.push(job(domain)).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+
+        // the coordinator has handed out the first two jobs and the first one is done
+        let coordinator =
+            CrawlCoordinator::new(queue_path(&plan, 0), Duration::from_secs(60)).unwrap();
+        let done = coordinator.sample_job().unwrap().unwrap();
+        coordinator.complete_job(done.domain.as_str()).unwrap();
+        coordinator.sample_job().unwrap().unwrap();
+        drop(coordinator);
+
+        let history = RecrawlHistory::open(crate::gen_temp_path());
+        let url = Url::parse("https://a.com/").unwrap();
+        history.observe(&url, 100, "body", Validators::default());
+
+        let dump = crate::gen_temp_path();
+        let stats = export(&plan, Some(&history), &dump).unwrap();
+        assert_eq!(stats.jobs, 3);
+        assert_eq!(stats.histories, 1);
+
+        let imported_plan = crate::gen_temp_path();
+        let imported_history = RecrawlHistory::open(crate::gen_temp_path());
+        let stats = import(&dump, &imported_plan, Some(&imported_history)).unwrap();
+        assert_eq!(stats.jobs, 3);
+        assert_eq!(stats.urls, 3);
+        assert_eq!(stats.histories, 1);
+
+        assert_eq!(
+            pending(&imported_plan, 0),
+            vec!["b.com".to_string(), "c.com".to_string()]
+        );
+        assert!(pending(&imported_plan, 1).is_empty());
+        assert_eq!(pending(&imported_plan, 2), vec!["d.com".to_string()]);
+        assert_eq!(imported_history.get(&url), history.get(&url));
+    }
+
+    #[test]
+    fn oversized_record_is_rejected() {
+        let mut bytes = (MAX_RECORD_LEN + 1).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 16]);
+
+        let mut records = FrontierReader {
+            reader: bytes.as_slice(),
+        };
+
+        assert!(records.next().unwrap().is_err());
+    }
+}
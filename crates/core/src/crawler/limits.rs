@@ -0,0 +1,177 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Process wide limits on the requests sent by the crawler. Each job only
+//! knows about its own domain, so the limits that span jobs are enforced here
+//! and shared by all worker threads.
+
+use std::sync::{Arc, Mutex};
+
+use hashbrown::HashMap;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::CrawlerConfig;
+
+use super::{politeness::TokenBucket, Domain};
+
+/// Semaphores of idle domains are dropped once this many domains are tracked.
+const MAX_IDLE_DOMAINS: usize = 1_024;
+
+/// Held while a request is in flight.
+pub struct FetchPermit {
+    _domain: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+pub struct FetchLimiter {
+    global: Arc<Semaphore>,
+    rate: Option<Mutex<TokenBucket>>,
+    domains: Mutex<HashMap<Domain, Arc<Semaphore>>>,
+    per_domain: usize,
+}
+
+impl FetchLimiter {
+    pub fn new(config: &CrawlerConfig) -> Self {
+        let limits = &config.limits;
+
+        let global = limits
+            .max_concurrent_fetches
+            .unwrap_or(config.num_worker_threads)
+            .max(1);
+
+        let rate = limits
+            .max_requests_per_sec
+            .filter(|rate| rate.is_finite() && *rate > 0.0)
+            .map(|rate| Mutex::new(TokenBucket::new(rate, rate.ceil() as u32)));
+
+        Self {
+            global: Arc::new(Semaphore::new(global)),
+            rate,
+            domains: Mutex::new(HashMap::new()),
+            per_domain: limits.max_concurrent_fetches_per_domain.max(1),
+        }
+    }
+
+    fn domain_semaphore(&self, domain: &Domain) -> Arc<Semaphore> {
+        let mut domains = self.domains.lock().unwrap_or_else(|e| e.into_inner());
+
+        if domains.len() >= MAX_IDLE_DOMAINS {
+            domains.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+        }
+
+        Arc::clone(
+            domains
+                .entry(domain.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.per_domain))),
+        )
+    }
+
+    /// Wait until a request to the domain is allowed by all limits.
+    pub async fn acquire(&self, domain: &Domain) -> FetchPermit {
+        let domain = self
+            .domain_semaphore(domain)
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+
+        if let Some(rate) = &self.rate {
+            let wait = rate.lock().unwrap_or_else(|e| e.into_inner()).acquire();
+
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        let global = Arc::clone(&self.global)
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+
+        FetchPermit {
+            _domain: domain,
+            _global: global,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config(max_concurrent_fetches: usize, per_domain: usize) -> CrawlerConfig {
+        let mut config: CrawlerConfig = toml::from_str(
+            r#"
+            num_worker_threads = 1
+            timeout_seconds = 1
+            router_hosts = []
+
+            [user_agent]
+            full = "test"
+            token = "test"
+
+            [s3]
+            access_key = ""
+            bucket = ""
+            endpoint = ""
+            folder = ""
+            secret_key = ""
+            "#,
+        )
+        .unwrap();
+
+        config.limits.max_concurrent_fetches = Some(max_concurrent_fetches);
+        config.limits.max_concurrent_fetches_per_domain = per_domain;
+
+        config
+    }
+
+    async fn is_blocked(limiter: &FetchLimiter, domain: &Domain) -> bool {
+        tokio::time::timeout(Duration::from_millis(10), limiter.acquire(domain))
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn per_domain_concurrency() {
+        let limiter = FetchLimiter::new(&config(4, 1));
+        let a = Domain::from("a.com".to_string());
+        let b = Domain::from("b.com".to_string());
+
+        let permit = limiter.acquire(&a).await;
+        assert!(is_blocked(&limiter, &a).await);
+        assert!(!is_blocked(&limiter, &b).await);
+
+        drop(permit);
+        assert!(!is_blocked(&limiter, &a).await);
+    }
+
+    #[tokio::test]
+    async fn global_concurrency() {
+        let limiter = FetchLimiter::new(&config(2, 1));
+        let a = Domain::from("a.com".to_string());
+        let b = Domain::from("b.com".to_string());
+        let c = Domain::from("c.com".to_string());
+
+        let _a = limiter.acquire(&a).await;
+        let b = limiter.acquire(&b).await;
+        assert!(is_blocked(&limiter, &c).await);
+
+        drop(b);
+        assert!(!is_blocked(&limiter, &c).await);
+    }
+}
@@ -14,23 +14,65 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::VecDeque, future::Future, sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    future::IntoFuture,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use hashbrown::HashMap;
 
-use url::Url;
-
-use crate::{config::CrawlerConfig, warc, webpage::url_ext::UrlExt};
-
-use self::{warc_writer::WarcWriter, worker::WorkerThread};
+use url::{Host, Url};
+
+use crate::{
+    config::CrawlerConfig,
+    feed::Feed,
+    image_store::{FaviconStore, ImageStore},
+    ranking::models::reloadable::Reloadable,
+    task_queue::{Task, TaskQueue},
+    warc,
+    webpage::{protocol::ProtocolInfo, url_ext::UrlExt},
+};
+
+use self::{
+    favicons::{FaviconFetcher, FaviconTask},
+    proxy::ProxyPool,
+    render::RenderPool,
+    stats::FetchMetrics,
+    traps::TrapMetrics,
+    warc_writer::WarcWriter,
+    worker::WorkerThread,
+};
+pub use dns::{FamilyMetrics, Resolver};
+pub use failure::FailureReason;
+pub use limits::FetchLimiter;
+pub use url_filter::UrlFilter;
 pub use worker::JobExecutor;
 
+mod body;
+mod budget;
 pub mod coordinator;
+mod dns;
+mod failure;
+mod favicons;
+mod politeness;
+mod proxy;
+pub mod recrawl;
+mod render;
 mod robots_txt;
 pub mod router;
+pub mod seeds;
+pub mod stats;
+mod traps;
 pub use router::Router;
 mod file_queue;
+pub mod frontier;
+mod limits;
 pub mod planner;
+mod url_filter;
 mod wander_prirotiser;
 mod warc_writer;
 mod worker;
@@ -44,6 +86,8 @@ pub const MAX_DOMAIN_DISCOVERY_FACTOR: usize = 4;
 
 pub const MAX_OUTGOING_URLS_PER_PAGE: usize = 200;
 
+const MAX_CONTENT_LENGTH: usize = 32 * 1024 * 1024; // 32 MB
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("invalid content type: {0}")]
@@ -74,7 +118,13 @@ pub struct Domain(String);
 
 impl From<&Url> for Domain {
     fn from(url: &Url) -> Self {
-        Self(url.icann_domain().unwrap_or_default().to_string())
+        match url.host() {
+            // ip addresses have no icann domain, so the address itself is the domain
+            Some(Host::Ipv4(_)) | Some(Host::Ipv6(_)) => {
+                Self(url.host_str().unwrap_or_default().to_string())
+            }
+            _ => Self(url.icann_domain().unwrap_or_default().to_string()),
+        }
     }
 }
 
@@ -96,10 +146,27 @@ impl Domain {
     }
 }
 
+/// Validators from the last time a url was fetched. When present, the worker
+/// revalidates the url with a conditional request instead of downloading
+/// the page again.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct WeightedUrl {
     pub url: Url,
     pub weight: f64,
+    #[serde(default)]
+    pub validators: Validators,
 }
 
 /// All urls in a job must be from the same domain and only one job per domain.
@@ -109,13 +176,34 @@ pub struct Job {
     pub domain: Domain,
     pub urls: VecDeque<WeightedUrl>,
     pub wandering_urls: u64,
+    /// Discovered urls with more path segments than this are not wandered to.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Known RSS/Atom feeds of the domain. New entries are wandered to first.
+    #[serde(default)]
+    pub feeds: Vec<Feed>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum UrlResponse {
-    Success { url: Url },
-    Failed { url: Url, status_code: Option<u16> },
-    Redirected { url: Url, new_url: Url },
+    Success {
+        url: Url,
+    },
+    Failed {
+        url: Url,
+        status_code: Option<u16>,
+        reason: FailureReason,
+    },
+    Redirected {
+        url: Url,
+        new_url: Url,
+    },
+    NotModified {
+        url: Url,
+    },
+    NearDuplicate {
+        url: Url,
+    },
 }
 
 #[derive(
@@ -162,6 +250,8 @@ pub struct DomainCrawled {
 pub struct RetrieableUrl {
     weighted_url: WeightedUrl,
     retries: u8,
+    /// The url failed with a transient error and should not be tried again before this.
+    not_before: Option<Instant>,
 }
 
 impl RetrieableUrl {
@@ -175,6 +265,7 @@ impl From<WeightedUrl> for RetrieableUrl {
         Self {
             weighted_url,
             retries: 0,
+            not_before: None,
         }
     }
 }
@@ -183,6 +274,8 @@ pub struct WorkerJob {
     pub domain: Domain,
     pub urls: VecDeque<RetrieableUrl>,
     pub wandering_urls: u64,
+    pub max_depth: Option<usize>,
+    pub feeds: Vec<Feed>,
 }
 
 impl From<Job> for WorkerJob {
@@ -191,6 +284,8 @@ impl From<Job> for WorkerJob {
             domain: value.domain,
             urls: value.urls.into_iter().map(RetrieableUrl::from).collect(),
             wandering_urls: value.wandering_urls,
+            max_depth: value.max_depth,
+            feeds: value.feeds,
         }
     }
 }
@@ -202,33 +297,135 @@ pub struct CrawlDatum {
     pub payload_type: warc::PayloadType,
     pub body: String,
     pub fetch_time_ms: u64,
+    /// Unix timestamp (seconds) of when the fetch started.
+    pub fetched_at: u64,
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+    /// `<lastmod>` of the url in the sitemap of the site (rfc3339).
+    pub sitemap_lastmod: Option<String>,
+    pub protocol: ProtocolInfo,
 }
 
 pub struct Crawler {
     writer: Arc<WarcWriter>,
+    favicons: Option<Arc<Mutex<FaviconStore>>>,
     handles: Vec<tokio::task::JoinHandle<()>>,
 }
 
 impl Crawler {
     pub async fn new(config: CrawlerConfig) -> Result<Self> {
-        let writer = Arc::new(WarcWriter::new(config.s3.clone()));
+        let writer = Arc::new(WarcWriter::new(config.s3.clone(), config.warc.clone()));
         let mut handles = Vec::new();
         let mut router_hosts = Vec::new();
 
         for host in &config.router_hosts {
-            router_hosts.push(host.parse()?);
+            router_hosts.push(resolve_host(host).await?);
+        }
+
+        let dns_metrics = FamilyMetrics::default();
+        let trap_metrics = TrapMetrics::default();
+        let fetch_metrics = FetchMetrics::default();
+
+        let url_filter = Arc::new(Reloadable::<UrlFilter>::open_optional(
+            config.url_filter_path.as_ref(),
+        )?);
+        if config.url_filter_path.is_some() {
+            tokio::spawn(Arc::clone(&url_filter).watch(url_filter::RELOAD_INTERVAL));
+        }
+
+        if let Some(addr) = config.prometheus_host {
+            let mut registry = crate::metrics::PrometheusRegistry::default();
+            dns_metrics.register(&mut registry);
+            trap_metrics.register(&mut registry);
+            fetch_metrics.register(&mut registry);
+
+            let listener = tokio::net::TcpListener::bind(&addr).await?;
+            tracing::info!("prometheus exporter listening on {}", addr);
+
+            tokio::spawn(
+                axum::serve(
+                    listener,
+                    crate::api::metrics_router(registry).into_make_service(),
+                )
+                .into_future(),
+            );
+        }
+
+        let resolver = Resolver::new(&config, dns_metrics);
+        let limiter = Arc::new(FetchLimiter::new(&config));
+        let render_pool = config
+            .render
+            .as_ref()
+            .map(|render| RenderPool::new(render, &config.user_agent.full))
+            .transpose()?
+            .map(Arc::new);
+
+        // shared between the workers so they agree on which proxies are healthy
+        let proxy_pool = ProxyPool::new(&config, resolver.clone())?.map(Arc::new);
+        if let Some(pool) = &proxy_pool {
+            tokio::spawn(Arc::clone(pool).watch_health());
+        }
+
+        let favicons = match (&config.favicon_store_path, &config.task_queue) {
+            (Some(path), Some(task_queue)) => {
+                let mut store = FaviconStore::open(path);
+                store.prepare_writer();
+
+                Some((
+                    Arc::new(Mutex::new(store)),
+                    Arc::new(TaskQueue::from_config(task_queue)?),
+                ))
+            }
+            (Some(_), None) => {
+                return Err(anyhow::anyhow!(
+                    "favicons are fetched on the task queue, but no task queue is configured"
+                ))
+            }
+            _ => None,
+        };
+
+        if let Some((store, queue)) = &favicons {
+            let fetcher = Arc::new(FaviconFetcher::new(
+                Arc::clone(store),
+                reqwest_client(&config, resolver.clone())?,
+                proxy_pool.clone(),
+                Arc::clone(&limiter),
+            ));
+
+            tokio::spawn(Arc::clone(queue).process(
+                favicons::QUEUE,
+                move |task: Task<FaviconTask>| {
+                    let fetcher = Arc::clone(&fetcher);
+                    async move { fetcher.handle(task).await }
+                },
+            ));
         }
 
         for _ in 0..config.num_worker_threads {
-            let worker =
-                WorkerThread::new(Arc::clone(&writer), config.clone(), router_hosts.clone())?;
+            let worker = WorkerThread::new(
+                Arc::clone(&writer),
+                config.clone(),
+                router_hosts.clone(),
+                resolver.clone(),
+                trap_metrics.clone(),
+                fetch_metrics.clone(),
+                Arc::clone(&url_filter),
+            )?
+            .with_limiter(Arc::clone(&limiter))
+            .with_render_pool(render_pool.clone())
+            .with_proxy_pool(proxy_pool.clone())
+            .with_favicon_queue(favicons.as_ref().map(|(_, queue)| Arc::clone(queue)));
 
             handles.push(tokio::spawn(async move {
                 worker.run().await;
             }));
         }
 
-        Ok(Self { writer, handles })
+        Ok(Self {
+            writer,
+            favicons: favicons.map(|(store, _)| store),
+            handles,
+        })
     }
 
     pub async fn run(self) {
@@ -236,6 +433,10 @@ impl Crawler {
             handle.await.ok();
         }
 
+        if let Some(favicons) = &self.favicons {
+            favicons.lock().unwrap_or_else(|e| e.into_inner()).flush();
+        }
+
         self.writer.finish().await.unwrap();
     }
 }
@@ -245,25 +446,47 @@ pub trait DatumStream: Send + Sync {
     fn finish(&self) -> impl Future<Output = Result<()>> + Send;
 }
 
-pub fn reqwest_client(config: &CrawlerConfig) -> Result<reqwest::Client> {
+/// Resolve a `host:port` pair. Supports hostnames, ipv4 addresses
+/// and bracketed ipv6 addresses (e.g. `[::1]:8080`).
+pub async fn resolve_host(host: &str) -> Result<SocketAddr> {
+    tokio::net::lookup_host(host)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("could not resolve {host}"))
+}
+
+pub fn reqwest_client(config: &CrawlerConfig, resolver: Resolver) -> Result<reqwest::Client> {
+    Ok(client_builder(config, resolver).build()?)
+}
+
+fn client_builder(config: &CrawlerConfig, resolver: Resolver) -> reqwest::ClientBuilder {
     let timeout = Duration::from_secs(config.timeout_seconds);
 
     let mut headers = reqwest::header::HeaderMap::default();
     headers.insert(
         reqwest::header::ACCEPT,
-        reqwest::header::HeaderValue::from_static("text/html"),
+        reqwest::header::HeaderValue::from_static(
+            "text/html,application/xhtml+xml;q=0.9,application/rss+xml;q=0.8,application/atom+xml;q=0.8",
+        ),
     );
     headers.insert(
         reqwest::header::ACCEPT_LANGUAGE,
         reqwest::header::HeaderValue::from_static("en-US,en;q=0.9,*;q=0.8"),
     );
 
-    Ok(reqwest::Client::builder()
+    let connect_timeout = config
+        .limits
+        .connect_timeout_seconds
+        .map(Duration::from_secs)
+        .unwrap_or(timeout);
+
+    reqwest::Client::builder()
         .timeout(timeout)
-        .connect_timeout(timeout)
+        .connect_timeout(connect_timeout)
         .http2_keep_alive_interval(None)
         .default_headers(headers)
         .redirect(reqwest::redirect::Policy::limited(config.max_redirects))
+        .tls_info(true)
         .user_agent(&config.user_agent.full)
-        .build()?)
+        .dns_resolver(Arc::new(resolver))
 }
@@ -20,26 +20,42 @@ use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::{
-    collections::VecDeque,
-    path::Path,
-    sync::{atomic::AtomicUsize, Mutex},
-};
+use std::collections::HashSet;
+use std::{collections::VecDeque, path::Path, sync::Mutex};
 use url::Url;
 
-use crate::crawler::WeightedUrl;
+use crate::crawler::{recrawl::RecrawlHistory, UrlFilter, Validators, WeightedUrl};
+use crate::feed::{index::FeedIndex, Feed};
 use crate::webgraph::centrality::{top_hosts, TopHosts};
 use crate::{
     config::CrawlPlannerConfig,
     crawler::{file_queue::FileQueueWriter, Job},
     kv::{rocksdb_store::RocksDbStore, Kv},
+    safe_browsing::Blocklist,
     webgraph::{NodeID, Webgraph},
 };
 
-use super::Domain;
+use super::{budget, Domain};
 
 const MAX_SURPLUS_BUDGET_ITERATIONS: usize = 100;
 
+/// Number of domains planned in parallel before their jobs are written
+/// to the queues. Jobs are written in order of centrality, so the chunk
+/// bounds how many jobs are kept in memory at once.
+const JOB_ORDER_CHUNK_SIZE: usize = 10_000;
+
+/// Hosts found in the malware/phishing blocklists only get
+/// this fraction of their budget.
+const BLOCKLISTED_HOST_BUDGET_FACTOR: f64 = 0.1;
+
+/// Fraction of the budget a host without any trust gets. The budget of a host
+/// grows linearly with its trust up to the full budget for the most trusted host.
+const UNTRUSTED_HOST_BUDGET_FACTOR: f64 = 0.5;
+
+fn trust_budget_factor(trust: f64) -> f64 {
+    UNTRUSTED_HOST_BUDGET_FACTOR + (1.0 - UNTRUSTED_HOST_BUDGET_FACTOR) * trust.clamp(0.0, 1.0)
+}
+
 fn all_pages(
     page_centrality: &RocksDbStore<NodeID, f64>,
     page_graph: &Webgraph,
@@ -66,6 +82,36 @@ fn group_domain(hosts: &[NodeID], host_graph: &Webgraph) -> HashMap<Domain, Vec<
     domains
 }
 
+/// Known feeds of the hosts in each domain.
+fn domain_feeds(
+    index: &FeedIndex,
+    grouped: &HashMap<Domain, Vec<NodeID>>,
+    host_graph: &Webgraph,
+    url_filter: &UrlFilter,
+) -> HashMap<Domain, Vec<Feed>> {
+    let mut res = HashMap::new();
+
+    for (domain, hosts) in grouped {
+        let mut feeds: Vec<Feed> = hosts
+            .iter()
+            .filter_map(|host| host_graph.id2node(host))
+            .filter_map(|node| index.search(&node.name).ok())
+            .flatten()
+            .filter(|feed| Domain::from(&feed.url) == *domain)
+            .filter(|feed| url_filter.is_allowed(&feed.url))
+            .collect();
+
+        feeds.sort_by(|a, b| a.url.cmp(&b.url));
+        feeds.dedup();
+
+        if !feeds.is_empty() {
+            res.insert(domain.clone(), feeds);
+        }
+    }
+
+    res
+}
+
 fn check_config(config: &CrawlPlannerConfig) -> Result<()> {
     if !(0.0..=1.0).contains(&config.wander_fraction) {
         return Err(anyhow::anyhow!(
@@ -87,6 +133,7 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
     page_centrality: RocksDbStore<NodeID, f64>,
     host_graph: Webgraph,
     page_graph: Webgraph,
+    recrawl_history: Option<RecrawlHistory>,
     config: CrawlPlannerConfig,
     output: P,
 ) -> Result<()> {
@@ -113,6 +160,24 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
     let grouped = group_domain(&hosts, &host_graph);
     let num_groups = grouped.len();
 
+    let blocklist = match &config.blocklist_path {
+        Some(path) => Blocklist::open(path)?,
+        None => Blocklist::default(),
+    };
+    let host_trust: Option<RocksDbStore<NodeID, f64>> =
+        config.host_trust_path.as_ref().map(RocksDbStore::open);
+
+    let url_filter = match &config.url_filter_path {
+        Some(path) => UrlFilter::open(path)?,
+        None => UrlFilter::default(),
+    };
+
+    let feeds = match &config.feed_index_path {
+        Some(path) => domain_feeds(&FeedIndex::open(path)?, &grouped, &host_graph, &url_filter),
+        None => HashMap::new(),
+    };
+    tracing::info!("found feeds for {} domains", feeds.len());
+
     let job_queues: Vec<Mutex<FileQueueWriter<Job>>> = (0..config.num_job_queues)
         .map(|i| {
             let path = queue_path.join(format!("{}.queue", i));
@@ -125,7 +190,7 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
 
     let stats = Mutex::new(Vec::new());
 
-    let next_queue = AtomicUsize::new(0);
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
 
     let pool = ThreadPoolBuilder::new()
         .num_threads(num_cpus::get())
@@ -134,6 +199,18 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
         .build()?;
 
     pool.install(|| {
+        let blocklisted: HashSet<NodeID> = hosts
+            .par_iter()
+            .filter(|host| {
+                host_graph
+                    .id2node(host)
+                    .map(|node| blocklist.lookup_host(&node.name).is_some())
+                    .unwrap_or(false)
+            })
+            .copied()
+            .collect();
+        tracing::info!("deprioritizing {} blocklisted hosts", blocklisted.len());
+
         let host_pages: BTreeMap<_, _> = hosts
             .par_iter()
             .progress_count(num_hosts as u64)
@@ -149,10 +226,18 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
             .map(|host| {
                 let host_centrality = host_centrality.get(host).unwrap_or_default();
 
-                let host_budget = ((config.crawl_budget as f64 * host_centrality)
-                    / total_host_centrality)
-                    .round()
-                    .max(0.0) as u64;
+                let mut host_budget =
+                    (config.crawl_budget as f64 * host_centrality) / total_host_centrality;
+
+                if blocklisted.contains(host) {
+                    host_budget *= BLOCKLISTED_HOST_BUDGET_FACTOR;
+                }
+
+                if let Some(host_trust) = &host_trust {
+                    host_budget *= trust_budget_factor(host_trust.get(host).unwrap_or_default());
+                }
+
+                let host_budget = host_budget.round().max(0.0) as u64;
 
                 let num_pages = host_pages.get(host).copied().unwrap_or_default();
 
@@ -181,6 +266,10 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                     break;
                 }
 
+                if blocklisted.contains(host) {
+                    continue;
+                }
+
                 let num_pages = host_pages.get(host).copied().unwrap_or_default();
                 let host_budget = host_budgets.get_mut(host).unwrap();
 
@@ -213,96 +302,173 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
 
         tracing::info!("surplus done (remaining={})", surplus_budget);
 
-        grouped
-            .into_par_iter()
-            .progress_count(num_groups as u64)
-            .for_each(|(domain, hosts)| {
-                if domain.as_str().is_empty() {
-                    return;
-                }
-
-                let mut total_wander_budget = 0;
-                let mut total_schedule_budget = 0;
-                let mut total_scheduled_urls = 0;
-                let mut total_known_urls = 0;
-                let mut urls = VecDeque::new();
-
-                for host in &hosts {
-                    let mut pages = all_pages(&page_centrality, &page_graph, *host);
-                    total_known_urls += pages.len();
+        let domain_budgets: Vec<_> = grouped
+            .iter()
+            .map(|(domain, hosts)| {
+                let budget = hosts
+                    .iter()
+                    .map(|host| host_budgets.get(host).copied().unwrap_or_default())
+                    .sum::<u64>();
+                let centrality = hosts
+                    .iter()
+                    .filter_map(|host| host_centrality.get(host))
+                    .sum::<f64>();
+
+                (domain.clone(), budget, centrality)
+            })
+            .collect();
+        let domain_caps = budget::domain_caps(&domain_budgets, &config.budget);
 
-                    if pages.is_empty() {
-                        continue;
+        // the coordinators hand out jobs in the order they are written, so the most
+        // central domains are written first to have them crawled before the rest.
+        let domain_centrality: HashMap<_, _> = domain_budgets
+            .into_iter()
+            .map(|(domain, _, centrality)| (domain, centrality))
+            .collect();
+        let mut grouped: Vec<_> = grouped.into_iter().collect();
+        grouped.sort_by(|(a, _), (b, _)| {
+            let a = domain_centrality.get(a).copied().unwrap_or_default();
+            let b = domain_centrality.get(b).copied().unwrap_or_default();
+            b.total_cmp(&a)
+        });
+
+        let mut next_queue = 0;
+        let progress = indicatif::ProgressBar::new(num_groups as u64);
+
+        for chunk in grouped.chunks(JOB_ORDER_CHUNK_SIZE) {
+            let jobs: Vec<_> = chunk
+                .par_iter()
+                .filter_map(|(domain, hosts)| {
+                    progress.inc(1);
+
+                    if domain.as_str().is_empty() {
+                        return None;
                     }
 
-                    pages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
-                    tracing::debug!("num pages: {}", pages.len());
-                    let host_budget = host_budgets.get(host).copied().unwrap_or_default();
+                    let mut total_wander_budget = 0;
+                    let mut total_schedule_budget = 0;
+                    let mut total_scheduled_urls = 0;
+                    let mut total_known_urls = 0;
+                    let mut urls = VecDeque::new();
+
+                    for host in hosts {
+                        let mut pages = all_pages(&page_centrality, &page_graph, *host);
+                        total_known_urls += pages.len();
+
+                        if pages.is_empty() {
+                            continue;
+                        }
+
+                        pages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+                        tracing::debug!("num pages: {}", pages.len());
+                        let host_budget = host_budgets.get(host).copied().unwrap_or_default();
+
+                        tracing::debug!("host_budget: {host_budget}");
+                        let schedule_budget = (host_budget as f64 * (1.0 - config.wander_fraction))
+                            .round()
+                            .max(0.0) as u64;
+
+                        tracing::debug!("schedule_budget: {schedule_budget}");
+                        let wander_budget = (host_budget as f64 * config.wander_fraction)
+                            .max(0.0)
+                            .round() as u64;
+                        tracing::debug!("wander_budget: {wander_budget}");
+
+                        total_wander_budget += wander_budget;
+                        total_schedule_budget += schedule_budget;
+
+                        let before = urls.len();
+                        urls.extend(
+                            pages
+                                .into_iter()
+                                .filter_map(|(id, score)| {
+                                    page_graph.id2node(&id).map(|n| (n, score))
+                                })
+                                .map(|(n, score)| (n.name, score))
+                                .filter_map(|(n, score)| {
+                                    Url::parse(&format!("http://{n}")).ok().map(|u| (u, score))
+                                })
+                                .filter(|(url, _)| {
+                                    !budget::is_too_deep(url, config.budget.max_depth)
+                                })
+                                .filter(|(url, _)| url_filter.is_allowed(url))
+                                .filter_map(|(url, score)| {
+                                    match recrawl_history.as_ref().and_then(|h| h.get(&url)) {
+                                        // fetched recently enough that the page
+                                        // has most likely not changed yet.
+                                        Some(history) if !history.is_due(now, &config.recrawl) => {
+                                            None
+                                        }
+                                        Some(history) => Some(WeightedUrl {
+                                            url,
+                                            weight: score,
+                                            validators: history.validators().clone(),
+                                        }),
+                                        None => Some(WeightedUrl {
+                                            url,
+                                            weight: score,
+                                            validators: Validators::default(),
+                                        }),
+                                    }
+                                })
+                                .take(schedule_budget as usize),
+                        );
+
+                        total_scheduled_urls += urls.len() as u64 - before as u64;
+                    }
 
-                    tracing::debug!("host_budget: {host_budget}");
-                    let schedule_budget = (host_budget as f64 * (1.0 - config.wander_fraction))
-                        .round()
-                        .max(0.0) as u64;
+                    // scheduled urls are kept before wandering urls when the
+                    // domain or its tld is over budget.
+                    let cap = domain_caps.get(domain).copied().unwrap_or(u64::MAX);
+                    if urls.len() as u64 > cap {
+                        urls.truncate(cap as usize);
+                        total_scheduled_urls = urls.len() as u64;
+                    }
+                    total_wander_budget = total_wander_budget.min(cap - urls.len() as u64);
 
-                    tracing::debug!("schedule_budget: {schedule_budget}");
-                    let wander_budget = (host_budget as f64 * config.wander_fraction)
-                        .max(0.0)
-                        .round() as u64;
-                    tracing::debug!("wander_budget: {wander_budget}");
-
-                    total_wander_budget += wander_budget;
-                    total_schedule_budget += schedule_budget;
-
-                    let before = urls.len();
-                    urls.extend(
-                        pages
-                            .into_iter()
-                            .filter_map(|(id, score)| page_graph.id2node(&id).map(|n| (n, score)))
-                            .map(|(n, score)| (n.name, score))
-                            .filter_map(|(n, score)| {
-                                Url::parse(&format!("http://{n}")).ok().map(|u| (u, score))
-                            })
-                            .map(|(url, score)| WeightedUrl { url, weight: score })
-                            .take(schedule_budget as usize),
+                    tracing::trace!(
+                        "domain: {:#?} hosts: {:#?} urls: {:#?}",
+                        domain,
+                        hosts,
+                        urls
                     );
 
-                    total_scheduled_urls += urls.len() as u64 - before as u64;
-                }
-
-                tracing::trace!(
-                    "domain: {:#?} hosts: {:#?} urls: {:#?}",
-                    domain,
-                    hosts,
-                    urls
-                );
-
-                let job = Job {
-                    domain: domain.clone(),
-                    urls,
-                    wandering_urls: total_wander_budget,
-                };
-
-                let domain_stats = DomainStats {
-                    domain,
-                    num_hosts: hosts.len(),
-                    schedule_budget: total_schedule_budget,
-                    wander_budget: total_wander_budget,
-                    scheduled_urls: total_scheduled_urls,
-                    known_urls: total_known_urls,
-                };
-                stats
-                    .lock()
-                    .unwrap_or_else(|e| e.into_inner())
-                    .push(domain_stats);
-
-                // `fetch_add` wraps around on overflow
-                let queue_index = next_queue.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                job_queues[queue_index % job_queues.len()]
+                    let job = Job {
+                        domain: domain.clone(),
+                        urls,
+                        wandering_urls: total_wander_budget,
+                        max_depth: config.budget.max_depth,
+                        feeds: feeds.get(domain).cloned().unwrap_or_default(),
+                    };
+
+                    let domain_stats = DomainStats {
+                        domain: domain.clone(),
+                        num_hosts: hosts.len(),
+                        schedule_budget: total_schedule_budget,
+                        wander_budget: total_wander_budget,
+                        scheduled_urls: total_scheduled_urls,
+                        known_urls: total_known_urls,
+                    };
+                    stats
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push(domain_stats);
+
+                    Some(job)
+                })
+                .collect();
+
+            for job in jobs {
+                job_queues[next_queue % job_queues.len()]
                     .lock()
                     .unwrap_or_else(|e| e.into_inner())
                     .push(job)
                     .unwrap();
-            })
+                next_queue += 1;
+            }
+        }
+
+        progress.finish();
     });
 
     for queue in job_queues {
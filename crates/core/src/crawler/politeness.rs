@@ -0,0 +1,303 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::CrawlerConfig;
+
+/// Number of successful requests in a row before the politeness factor
+/// is lowered again after the domain has throttled us.
+const SUCCESSES_BEFORE_RECOVERY: u32 = 16;
+
+/// Limits the request rate to a domain. The bucket refills at a fixed rate
+/// and each request takes a token, so short bursts are allowed while the
+/// long term rate can never exceed the refill rate.
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(refill_per_sec: f64, capacity: u32) -> Self {
+        let capacity = capacity.max(1) as f64;
+
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Take a token and return how long the caller must wait before
+    /// sending the request. A token that is not yet available is
+    /// reserved, so the next caller waits for the one after it.
+    pub fn acquire(&mut self) -> Duration {
+        self.acquire_at(Instant::now())
+    }
+
+    fn acquire_at(&mut self, now: Instant) -> Duration {
+        if !(self.refill_per_sec.is_finite() && self.refill_per_sec > 0.0) {
+            return Duration::ZERO;
+        }
+
+        self.refill(now);
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.refill_per_sec)
+        }
+    }
+}
+
+/// Politeness state of the domain that a job is crawling.
+pub struct DomainState {
+    politeness_factor: f32,
+    min_politeness_factor: f32,
+    max_politeness_factor: f32,
+    consecutive_successes: u32,
+    backoff_until: Option<Instant>,
+    bucket: TokenBucket,
+    min_crawl_delay: Duration,
+    max_crawl_delay: Duration,
+}
+
+impl DomainState {
+    pub fn new(config: &CrawlerConfig) -> Self {
+        Self {
+            politeness_factor: config.politeness_factor,
+            min_politeness_factor: config.politeness_factor,
+            max_politeness_factor: config.max_politeness_factor,
+            consecutive_successes: 0,
+            backoff_until: None,
+            bucket: TokenBucket::new(
+                config.max_domain_requests_per_sec,
+                config.domain_request_burst,
+            ),
+            min_crawl_delay: Duration::from_millis(config.min_crawl_delay_ms),
+            max_crawl_delay: Duration::from_millis(config.max_crawl_delay_ms),
+        }
+    }
+
+    pub fn politeness_factor(&self) -> f32 {
+        self.politeness_factor
+    }
+
+    /// Slowly return to the configured politeness once the domain
+    /// has answered successfully for a while.
+    pub fn on_success(&mut self) {
+        self.consecutive_successes += 1;
+
+        if self.consecutive_successes >= SUCCESSES_BEFORE_RECOVERY
+            && self.politeness_factor > self.min_politeness_factor
+        {
+            self.politeness_factor = (self.politeness_factor / 2.0).max(self.min_politeness_factor);
+            self.consecutive_successes = 0;
+        }
+    }
+
+    /// The domain answered with 429 or 503.
+    pub fn on_throttled(&mut self, retry_after: Option<Duration>) {
+        self.consecutive_successes = 0;
+        self.politeness_factor = (self.politeness_factor * 2.0).min(self.max_politeness_factor);
+
+        if let Some(retry_after) = retry_after {
+            self.backoff_until = Some(Instant::now() + retry_after.min(self.max_crawl_delay));
+        }
+    }
+
+    /// How long to wait before the next request to the domain.
+    pub fn wait_before_request(&mut self) -> Duration {
+        let mut wait = self.bucket.acquire();
+
+        if let Some(backoff_until) = self.backoff_until.take() {
+            wait = wait.max(backoff_until.saturating_duration_since(Instant::now()));
+        }
+
+        wait
+    }
+
+    /// How long to wait after a request. Slow responses are a sign of a
+    /// loaded server, so the delay grows with the fetch time. The crawl-delay
+    /// from robots.txt is respected, but everything is capped by the
+    /// max crawl delay.
+    pub fn delay_after_request(
+        &self,
+        fetch_time: Duration,
+        robots_crawl_delay: Option<Duration>,
+    ) -> Duration {
+        let mut delay = fetch_time.max(self.min_crawl_delay);
+
+        if let Some(robots_crawl_delay) = robots_crawl_delay {
+            delay = delay.max(robots_crawl_delay);
+        }
+
+        delay
+            .mul_f32(self.politeness_factor)
+            .min(self.max_crawl_delay)
+    }
+}
+
+/// Parse the `Retry-After` header, which is either a number of seconds
+/// or an http date.
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CrawlerConfig {
+        toml::from_str(
+            r#"
+            num_worker_threads = 1
+            timeout_seconds = 1
+            router_hosts = []
+            politeness_factor = 1.0
+            max_politeness_factor = 8.0
+            min_crawl_delay_ms = 1000
+            max_crawl_delay_ms = 10000
+
+            [user_agent]
+            full = "test"
+            token = "test"
+
+            [s3]
+            access_key = ""
+            bucket = ""
+            endpoint = ""
+            folder = ""
+            secret_key = ""
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn token_bucket_limits_rate() {
+        let mut bucket = TokenBucket::new(2.0, 2);
+        let now = bucket.last_refill;
+
+        assert_eq!(bucket.acquire_at(now), Duration::ZERO);
+        assert_eq!(bucket.acquire_at(now), Duration::ZERO);
+        assert_eq!(bucket.acquire_at(now), Duration::from_millis(500));
+        assert_eq!(bucket.acquire_at(now), Duration::from_millis(1000));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bucket.acquire_at(later), Duration::ZERO);
+        assert_eq!(bucket.acquire_at(later), Duration::ZERO);
+        assert!(bucket.acquire_at(later) > Duration::ZERO);
+    }
+
+    #[test]
+    fn unlimited_bucket() {
+        let mut bucket = TokenBucket::new(0.0, 1);
+
+        for _ in 0..10 {
+            assert_eq!(bucket.acquire(), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn backoff_and_recovery() {
+        let mut state = DomainState::new(&config());
+
+        state.on_throttled(None);
+        state.on_throttled(None);
+        assert_eq!(state.politeness_factor(), 4.0);
+
+        for _ in 0..10 {
+            state.on_throttled(None);
+        }
+        assert_eq!(state.politeness_factor(), 8.0);
+
+        for _ in 0..SUCCESSES_BEFORE_RECOVERY {
+            state.on_success();
+        }
+        assert_eq!(state.politeness_factor(), 4.0);
+
+        for _ in 0..(3 * SUCCESSES_BEFORE_RECOVERY) {
+            state.on_success();
+        }
+        assert_eq!(state.politeness_factor(), 1.0);
+    }
+
+    #[test]
+    fn delay_after_request() {
+        let mut state = DomainState::new(&config());
+
+        assert_eq!(
+            state.delay_after_request(Duration::from_millis(10), None),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            state.delay_after_request(Duration::from_millis(10), Some(Duration::from_secs(3))),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            state.delay_after_request(Duration::from_millis(10), Some(Duration::from_secs(60))),
+            Duration::from_secs(10)
+        );
+
+        state.on_throttled(None);
+        assert_eq!(
+            state.delay_after_request(Duration::from_secs(2), None),
+            Duration::from_secs(4)
+        );
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        let date = (Utc::now() + chrono::Duration::seconds(300)).to_rfc2822();
+        headers.insert(reqwest::header::RETRY_AFTER, date.parse().unwrap());
+        let res = retry_after(&headers).unwrap();
+        assert!(res > Duration::from_secs(290) && res <= Duration::from_secs(300));
+
+        headers.insert(reqwest::header::RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+}
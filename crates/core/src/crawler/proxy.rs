@@ -0,0 +1,364 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pool of http and socks5 proxies that the crawler routes its requests through.
+//! Each proxy gets its own client. Jobs are assigned a proxy either round-robin
+//! or by hashing the domain of the job. Proxies that fail too many times in a row
+//! are taken out of rotation for a cooldown period. If a health check url is configured,
+//! the pool also probes each proxy periodically so blocked proxies are detected
+//! before jobs hit them and recovered proxies return to rotation early.
+
+use std::{
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+
+use crate::config::{CrawlerConfig, ProxyAssignment};
+
+use super::{client_builder, Domain, Resolver, Result};
+
+struct Proxy {
+    url: String,
+    client: reqwest::Client,
+    consecutive_failures: AtomicUsize,
+    disabled_until: Mutex<Option<Instant>>,
+}
+
+impl Proxy {
+    fn is_available(&self, now: Instant) -> bool {
+        match *self
+            .disabled_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+        {
+            Some(until) => now >= until,
+            None => true,
+        }
+    }
+
+    fn disable(&self, cooldown: Duration) {
+        *self
+            .disabled_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + cooldown);
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    fn enable(&self) {
+        *self
+            .disabled_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+pub struct AssignedProxy {
+    idx: usize,
+    client: reqwest::Client,
+}
+
+impl AssignedProxy {
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
+
+pub struct ProxyPool {
+    proxies: Vec<Proxy>,
+    assignment: ProxyAssignment,
+    max_consecutive_failures: usize,
+    cooldown: Duration,
+    next: AtomicUsize,
+    health_check_url: Option<String>,
+    health_check_interval: Duration,
+}
+
+impl ProxyPool {
+    /// Returns `None` if no proxies are configured.
+    pub fn new(config: &CrawlerConfig, resolver: Resolver) -> Result<Option<Self>> {
+        if config.proxy.urls.is_empty() {
+            return Ok(None);
+        }
+
+        let mut proxies = Vec::with_capacity(config.proxy.urls.len());
+
+        for url in &config.proxy.urls {
+            let client = client_builder(config, resolver.clone())
+                .proxy(reqwest::Proxy::all(url.as_str())?)
+                .build()?;
+
+            proxies.push(Proxy {
+                url: url.clone(),
+                client,
+                consecutive_failures: AtomicUsize::new(0),
+                disabled_until: Mutex::new(None),
+            });
+        }
+
+        Ok(Some(Self {
+            proxies,
+            assignment: config.proxy.assignment,
+            max_consecutive_failures: config.proxy.max_consecutive_failures,
+            cooldown: Duration::from_secs(config.proxy.cooldown_sec),
+            next: AtomicUsize::new(0),
+            health_check_url: config.proxy.health_check_url.clone(),
+            health_check_interval: Duration::from_secs(config.proxy.health_check_interval_sec),
+        }))
+    }
+
+    fn start_idx(&self, domain: &Domain) -> usize {
+        match self.assignment {
+            ProxyAssignment::PerJob => self.next.fetch_add(1, Ordering::Relaxed),
+            ProxyAssignment::PerDomain => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                domain.hash(&mut hasher);
+                hasher.finish() as usize
+            }
+        }
+    }
+
+    /// Assign a proxy to a job for the domain. Unavailable proxies are skipped.
+    /// If all proxies are in cooldown we still return a proxy, as requests
+    /// should never bypass the pool.
+    pub fn assign(&self, domain: &Domain) -> AssignedProxy {
+        let start = self.start_idx(domain);
+        let now = Instant::now();
+
+        let idx = (0..self.proxies.len())
+            .map(|offset| (start + offset) % self.proxies.len())
+            .find(|idx| self.proxies[*idx].is_available(now))
+            .unwrap_or(start % self.proxies.len());
+
+        AssignedProxy {
+            idx,
+            client: self.proxies[idx].client.clone(),
+        }
+    }
+
+    pub fn report_success(&self, proxy: &AssignedProxy) {
+        self.proxies[proxy.idx]
+            .consecutive_failures
+            .store(0, Ordering::Relaxed);
+    }
+
+    /// Register a failed request through the proxy. Returns true if the proxy
+    /// was taken out of rotation and the caller should get a new proxy.
+    pub fn report_failure(&self, proxy: &AssignedProxy) -> bool {
+        let p = &self.proxies[proxy.idx];
+        let failures = p.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if failures >= self.max_consecutive_failures {
+            tracing::warn!(
+                "proxy {} failed {} times in a row. disabling it for {:?}",
+                p.url,
+                failures,
+                self.cooldown
+            );
+
+            p.disable(self.cooldown);
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Replace a failed proxy with the next available one.
+    pub fn rotate(&self, proxy: &AssignedProxy) -> AssignedProxy {
+        let now = Instant::now();
+
+        let idx = (1..=self.proxies.len())
+            .map(|offset| (proxy.idx + offset) % self.proxies.len())
+            .find(|idx| self.proxies[*idx].is_available(now))
+            .unwrap_or(proxy.idx);
+
+        AssignedProxy {
+            idx,
+            client: self.proxies[idx].client.clone(),
+        }
+    }
+
+    fn apply_health(&self, idx: usize, healthy: bool) {
+        let p = &self.proxies[idx];
+        let was_available = p.is_available(Instant::now());
+
+        if healthy {
+            if !was_available {
+                tracing::info!("proxy {} passed health check. enabling it again", p.url);
+            }
+
+            p.enable();
+        } else {
+            if was_available {
+                tracing::warn!(
+                    "proxy {} failed health check. disabling it for {:?}",
+                    p.url,
+                    self.cooldown
+                );
+            }
+
+            p.disable(self.cooldown);
+        }
+    }
+
+    /// Request the health check url through every proxy and update
+    /// which proxies are in rotation.
+    pub async fn check_health(&self) {
+        let Some(url) = &self.health_check_url else {
+            return;
+        };
+
+        let checks = self.proxies.iter().map(|p| async move {
+            match p.client.get(url).send().await {
+                Ok(res) => res.status().is_success(),
+                Err(_) => false,
+            }
+        });
+
+        for (idx, healthy) in join_all(checks).await.into_iter().enumerate() {
+            self.apply_health(idx, healthy);
+        }
+    }
+
+    /// Periodically check the health of the proxies. Returns immediately
+    /// if no health check url is configured.
+    pub async fn watch_health(self: Arc<Self>) {
+        if self.health_check_url.is_none() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(self.health_check_interval);
+
+        loop {
+            interval.tick().await;
+            self.check_health().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::ProxyConfig, crawler::FamilyMetrics};
+
+    use super::*;
+
+    fn pool(config: &CrawlerConfig) -> Option<ProxyPool> {
+        ProxyPool::new(config, Resolver::new(config, FamilyMetrics::default())).unwrap()
+    }
+
+    fn config(assignment: ProxyAssignment) -> CrawlerConfig {
+        let mut config: CrawlerConfig = toml::from_str(
+            r#"
+            num_worker_threads = 1
+            timeout_seconds = 1
+            router_hosts = []
+
+            [user_agent]
+            full = "test"
+            token = "test"
+
+            [s3]
+            access_key = ""
+            bucket = ""
+            endpoint = ""
+            folder = ""
+            secret_key = ""
+            "#,
+        )
+        .unwrap();
+
+        config.proxy = ProxyConfig {
+            urls: vec![
+                "http://127.0.0.1:3128".to_string(),
+                "socks5://127.0.0.1:1080".to_string(),
+                "http://127.0.0.1:3129".to_string(),
+            ],
+            assignment,
+            max_consecutive_failures: 2,
+            cooldown_sec: 60,
+            ..Default::default()
+        };
+
+        config
+    }
+
+    #[test]
+    fn no_proxies() {
+        let mut config = config(ProxyAssignment::PerJob);
+        config.proxy.urls.clear();
+
+        assert!(pool(&config).is_none());
+    }
+
+    #[test]
+    fn per_job_round_robin() {
+        let pool = pool(&config(ProxyAssignment::PerJob)).unwrap();
+        let domain = Domain::from("example.com".to_string());
+
+        let assigned: Vec<_> = (0..4).map(|_| pool.assign(&domain).idx).collect();
+        assert_eq!(assigned, vec![0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn per_domain_is_stable() {
+        let pool = pool(&config(ProxyAssignment::PerDomain)).unwrap();
+        let domain = Domain::from("example.com".to_string());
+
+        let first = pool.assign(&domain).idx;
+        for _ in 0..10 {
+            assert_eq!(pool.assign(&domain).idx, first);
+        }
+    }
+
+    #[test]
+    fn failing_proxy_is_rotated_out() {
+        let pool = pool(&config(ProxyAssignment::PerDomain)).unwrap();
+        let domain = Domain::from("example.com".to_string());
+
+        let proxy = pool.assign(&domain);
+
+        assert!(!pool.report_failure(&proxy));
+        pool.report_success(&proxy);
+        assert!(!pool.report_failure(&proxy));
+        assert!(pool.report_failure(&proxy));
+
+        let new = pool.rotate(&proxy);
+        assert_ne!(new.idx, proxy.idx);
+        assert_eq!(pool.assign(&domain).idx, new.idx);
+    }
+
+    #[test]
+    fn health_check_updates_rotation() {
+        let pool = pool(&config(ProxyAssignment::PerDomain)).unwrap();
+        let domain = Domain::from("example.com".to_string());
+
+        let proxy = pool.assign(&domain);
+
+        pool.apply_health(proxy.idx, false);
+        assert_ne!(pool.assign(&domain).idx, proxy.idx);
+
+        pool.apply_health(proxy.idx, true);
+        assert_eq!(pool.assign(&domain).idx, proxy.idx);
+    }
+}
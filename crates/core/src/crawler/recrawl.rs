@@ -0,0 +1,429 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-url fetch history used to decide when a url should be recrawled.
+//!
+//! Every time a page is fetched, its content hash is compared to the hash from
+//! the previous fetch. The number of detected changes over the observed revisit
+//! intervals gives an estimate of how often the page changes, and pages
+//! that change often are recrawled sooner.
+//!
+//! Each indexer records the history of the pages it indexed in its own store.
+//! [`RecrawlHistory::compact`] merges these stores into one for the planner and
+//! drops urls that haven't been fetched for a long time.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use url::Url;
+
+use crate::{
+    config::RecrawlConfig,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+};
+
+use super::{UrlString, Validators};
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FetchHistory {
+    /// Unix timestamp (seconds) of the most recent fetch.
+    last_fetch: u64,
+    content_hash: [u8; 16],
+    /// Sum of the time between consecutive fetches in seconds.
+    total_interval: u64,
+    num_revisits: u32,
+    num_changes: u32,
+    validators: Validators,
+}
+
+impl FetchHistory {
+    fn new(fetched_at: u64, content_hash: [u8; 16], validators: Validators) -> Self {
+        Self {
+            last_fetch: fetched_at,
+            content_hash,
+            total_interval: 0,
+            num_revisits: 0,
+            num_changes: 0,
+            validators,
+        }
+    }
+
+    fn observe(&mut self, fetched_at: u64, content_hash: [u8; 16], validators: Validators) {
+        if fetched_at <= self.last_fetch {
+            return;
+        }
+
+        self.total_interval += fetched_at - self.last_fetch;
+        self.num_revisits += 1;
+
+        if content_hash != self.content_hash {
+            self.num_changes += 1;
+        }
+
+        self.last_fetch = fetched_at;
+        self.content_hash = content_hash;
+        self.validators = validators;
+    }
+
+    /// Combine with the history of the same url from another store. The most
+    /// recent fetch is kept, while the change statistics of both are added up.
+    fn merge(&mut self, other: FetchHistory) {
+        if *self == other {
+            return;
+        }
+
+        let older = if other.last_fetch > self.last_fetch {
+            std::mem::replace(self, other)
+        } else {
+            other
+        };
+
+        self.total_interval += older.total_interval;
+        self.num_revisits += older.num_revisits;
+        self.num_changes += older.num_changes;
+    }
+
+    pub fn validators(&self) -> &Validators {
+        &self.validators
+    }
+
+    /// Estimated number of changes per second.
+    ///
+    /// Changes are only detected once per revisit, so the naive estimate
+    /// (changes / time) is biased low for pages that change more than once
+    /// between fetches. This uses the bias-reduced estimator from Cho and
+    /// Garcia-Molina, "Estimating frequency of change" (2003).
+    pub fn change_rate(&self) -> Option<f64> {
+        if self.num_revisits == 0 || self.total_interval == 0 {
+            return None;
+        }
+
+        let n = self.num_revisits as f64;
+        let x = self.num_changes as f64;
+        let mean_interval = self.total_interval as f64 / n;
+
+        Some(-((n - x + 0.5) / (n + 0.5)).ln() / mean_interval)
+    }
+
+    pub fn recrawl_interval(&self, config: &RecrawlConfig) -> Duration {
+        let min = Duration::from_secs(config.min_interval_hours * 60 * 60);
+        let max = Duration::from_secs(config.max_interval_days * 24 * 60 * 60);
+
+        match self.change_rate() {
+            // the page has only been fetched once, so revisit it soon to
+            // learn how often it changes.
+            None => min,
+            Some(rate) if rate > 0.0 => Duration::from_secs_f64(1.0 / rate).clamp(min, max),
+            Some(_) => max,
+        }
+    }
+
+    pub fn is_due(&self, now: u64, config: &RecrawlConfig) -> bool {
+        now.saturating_sub(self.last_fetch) >= self.recrawl_interval(config).as_secs()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub read: u64,
+    pub written: u64,
+    pub purged: u64,
+}
+
+pub struct RecrawlHistory {
+    store: RocksDbStore<UrlString, FetchHistory>,
+}
+
+impl RecrawlHistory {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+        }
+    }
+
+    pub fn observe(&self, url: &Url, fetched_at: u64, body: &str, validators: Validators) {
+        let content_hash = md5::compute(body.as_bytes()).0;
+        let key = UrlString::from(url);
+
+        let history = match self.store.get(&key) {
+            Some(mut history) => {
+                history.observe(fetched_at, content_hash, validators);
+                history
+            }
+            None => FetchHistory::new(fetched_at, content_hash, validators),
+        };
+
+        self.store.insert(key, history);
+    }
+
+    /// The server answered the revalidation with 304 Not Modified, so the page is
+    /// revisited without a change. Validators missing from the 304 response are kept
+    /// from the previous fetch. Urls without a history are ignored since there
+    /// is no content to compare later fetches against.
+    pub fn observe_not_modified(&self, url: &Url, fetched_at: u64, validators: Validators) {
+        let key = UrlString::from(url);
+
+        if let Some(mut history) = self.store.get(&key) {
+            let validators = Validators {
+                etag: validators.etag.or_else(|| history.validators.etag.clone()),
+                last_modified: validators
+                    .last_modified
+                    .or_else(|| history.validators.last_modified.clone()),
+            };
+
+            history.observe(fetched_at, history.content_hash, validators);
+            self.store.insert(key, history);
+        }
+    }
+
+    /// The planner only knows the host and path of a page, so the url is
+    /// also looked up with the other scheme.
+    pub fn get(&self, url: &Url) -> Option<FetchHistory> {
+        if let Some(history) = self.store.get(&UrlString::from(url)) {
+            return Some(history);
+        }
+
+        let mut other = url.clone();
+        let scheme = if url.scheme() == "http" {
+            "https"
+        } else {
+            "http"
+        };
+        other.set_scheme(scheme).ok()?;
+
+        self.store.get(&UrlString::from(other))
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (UrlString, FetchHistory)> + '_ {
+        self.store.iter()
+    }
+
+    pub fn insert(&self, url: UrlString, history: FetchHistory) {
+        self.store.insert(url, history);
+    }
+
+    /// Merge the histories into a new store at `output`. Urls found in multiple histories
+    /// are merged into a single entry, and urls that haven't been fetched within
+    /// `retention` of `now` (unix seconds) are dropped.
+    pub fn compact<P: AsRef<Path>>(
+        histories: &[RecrawlHistory],
+        output: P,
+        retention: Option<Duration>,
+        now: u64,
+    ) -> Result<(Self, CompactionStats)> {
+        if output.as_ref().exists() {
+            return Err(anyhow!("output path already exists"));
+        }
+
+        let res = Self::open(output);
+        let mut stats = CompactionStats::default();
+        let oldest = retention.map(|retention| now.saturating_sub(retention.as_secs()));
+
+        for history in histories {
+            for (url, fetch) in history.store.iter() {
+                stats.read += 1;
+
+                if oldest.is_some_and(|oldest| fetch.last_fetch < oldest) {
+                    stats.purged += 1;
+                    continue;
+                }
+
+                let merged = match res.store.get(&url) {
+                    Some(mut existing) => {
+                        existing.merge(fetch);
+                        existing
+                    }
+                    None => {
+                        stats.written += 1;
+                        fetch
+                    }
+                };
+
+                res.store.insert(url, merged);
+            }
+        }
+
+        res.flush();
+
+        Ok((res, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn unchanged_page_is_recrawled_rarely() {
+        let config = RecrawlConfig::default();
+        let mut history = FetchHistory::new(0, [0; 16], Validators::default());
+
+        assert_eq!(
+            history.recrawl_interval(&config),
+            Duration::from_secs(config.min_interval_hours * 60 * 60)
+        );
+
+        for i in 1..10 {
+            history.observe(i * 7 * DAY, [0; 16], Validators::default());
+        }
+
+        assert_eq!(
+            history.recrawl_interval(&config),
+            Duration::from_secs(config.max_interval_days * DAY)
+        );
+        assert!(!history.is_due(history.last_fetch + 30 * DAY, &config));
+    }
+
+    #[test]
+    fn frequently_changing_page_is_recrawled_sooner() {
+        let config = RecrawlConfig::default();
+        let mut rarely = FetchHistory::new(0, [0; 16], Validators::default());
+        let mut often = FetchHistory::new(0, [0; 16], Validators::default());
+
+        for i in 1..10u8 {
+            let fetched_at = i as u64 * 7 * DAY;
+
+            rarely.observe(
+                fetched_at,
+                [if i < 5 { 0 } else { 1 }; 16],
+                Validators::default(),
+            );
+            often.observe(fetched_at, [i; 16], Validators::default());
+        }
+
+        assert!(often.change_rate().unwrap() > rarely.change_rate().unwrap());
+        assert!(often.recrawl_interval(&config) < rarely.recrawl_interval(&config));
+        assert!(often.is_due(often.last_fetch + 7 * DAY, &config));
+    }
+
+    #[test]
+    fn out_of_order_fetches_are_ignored() {
+        let mut history = FetchHistory::new(10 * DAY, [0; 16], Validators::default());
+        history.observe(5 * DAY, [1; 16], Validators::default());
+
+        assert_eq!(history.num_revisits, 0);
+        assert_eq!(history.content_hash, [0; 16]);
+    }
+
+    #[test]
+    fn merge_keeps_newest_fetch() {
+        let mut a = FetchHistory::new(0, [0; 16], Validators::default());
+        a.observe(DAY, [1; 16], Validators::default());
+
+        let mut b = FetchHistory::new(2 * DAY, [2; 16], Validators::default());
+        b.observe(4 * DAY, [2; 16], Validators::default());
+
+        let mut merged = a.clone();
+        merged.merge(b.clone());
+
+        assert_eq!(merged.last_fetch, 4 * DAY);
+        assert_eq!(merged.content_hash, [2; 16]);
+        assert_eq!(merged.num_revisits, 2);
+        assert_eq!(merged.num_changes, 1);
+        assert_eq!(merged.total_interval, 3 * DAY);
+
+        let mut same = b.clone();
+        same.merge(b.clone());
+        assert_eq!(same, b);
+    }
+
+    #[test]
+    fn compact_merges_and_purges() {
+        let url = |path: &str| Url::parse(&format!("https://example.com/{path}")).unwrap();
+
+        let first = RecrawlHistory::open(crate::gen_temp_path());
+        first.observe(&url("a"), DAY, "a", Validators::default());
+        first.observe(&url("old"), DAY, "old", Validators::default());
+
+        let second = RecrawlHistory::open(crate::gen_temp_path());
+        second.observe(&url("a"), 50 * DAY, "changed", Validators::default());
+        second.observe(&url("b"), 60 * DAY, "b", Validators::default());
+
+        let (compacted, stats) = RecrawlHistory::compact(
+            &[first, second],
+            crate::gen_temp_path(),
+            Some(Duration::from_secs(30 * DAY)),
+            70 * DAY,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stats,
+            CompactionStats {
+                read: 4,
+                written: 2,
+                purged: 2,
+            }
+        );
+
+        assert_eq!(compacted.get(&url("a")).unwrap().last_fetch, 50 * DAY);
+        assert!(compacted.get(&url("b")).is_some());
+        assert!(compacted.get(&url("old")).is_none());
+    }
+
+    #[test]
+    fn lookup_with_other_scheme() {
+        let history = RecrawlHistory::open(crate::gen_temp_path());
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+
+        history.observe(
+            &Url::parse("https://example.com/page").unwrap(),
+            DAY,
+            "body",
+            validators.clone(),
+        );
+
+        let res = history
+            .get(&Url::parse("http://example.com/page").unwrap())
+            .unwrap();
+        assert_eq!(res.validators(), &validators);
+        assert!(history
+            .get(&Url::parse("http://example.com/other").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn not_modified_is_an_unchanged_revisit() {
+        let history = RecrawlHistory::open(crate::gen_temp_path());
+        let page = Url::parse("https://example.com/page").unwrap();
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: Some("Wed, 22 Jun 2022 19:37:34 GMT".to_string()),
+        };
+
+        history.observe(&page, DAY, "body", validators.clone());
+        history.observe_not_modified(&page, 3 * DAY, Validators::default());
+
+        let res = history.get(&page).unwrap();
+        assert_eq!(res.last_fetch, 3 * DAY);
+        assert_eq!(res.num_revisits, 1);
+        assert_eq!(res.num_changes, 0);
+        assert_eq!(res.validators(), &validators);
+
+        let unknown = Url::parse("https://example.com/unknown").unwrap();
+        history.observe_not_modified(&unknown, 3 * DAY, validators);
+        assert!(history.get(&unknown).is_none());
+    }
+}
@@ -0,0 +1,375 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Rendering of javascript heavy pages in a headless browser. Many sites are
+//! empty until their scripts have run, so pages on the configured domains are
+//! loaded in a browser and the rendered DOM is parsed instead of the raw html.
+//!
+//! The browsers are controlled through the WebDriver protocol, so any WebDriver
+//! server can be used (e.g. chromedriver or a selenium grid). The pages are untrusted,
+//! so the browser keeps its sandbox and the WebDriver server must run in an environment
+//! where that is possible.
+
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::anyhow;
+use futures::{future::BoxFuture, FutureExt};
+use hashbrown::HashSet;
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::config::RenderConfig;
+
+use super::{Domain, Error, Result, MAX_CONTENT_LENGTH};
+
+pub trait Renderer: Send + Sync {
+    /// Load the url and return the html of the rendered page.
+    fn render<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<String>>;
+}
+
+/// Renders pages in browser sessions of a WebDriver server. Sessions are
+/// reused between pages and closed if they fail, if the render is cancelled
+/// or when the driver is dropped.
+pub struct WebDriver {
+    client: reqwest::Client,
+    endpoint: String,
+    capabilities: Value,
+    settle: Duration,
+    idle_sessions: Mutex<Vec<String>>,
+}
+
+impl WebDriver {
+    pub fn new(config: &RenderConfig, user_agent: &str) -> Result<Self> {
+        let timeout = Duration::from_secs(config.timeout_seconds);
+
+        let capabilities = json!({
+            "capabilities": {
+                "alwaysMatch": {
+                    "browserName": "chrome",
+                    "timeouts": {
+                        "pageLoad": timeout.as_millis() as u64,
+                        "script": timeout.as_millis() as u64,
+                    },
+                    "goog:chromeOptions": {
+                        "args": [
+                            "--headless=new",
+                            "--disable-gpu",
+                            format!("--user-agent={user_agent}"),
+                        ],
+                    },
+                },
+            },
+        });
+
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout + Duration::from_millis(config.settle_ms))
+                .build()?,
+            endpoint: config.webdriver_url.trim_end_matches('/').to_string(),
+            capabilities,
+            settle: Duration::from_millis(config.settle_ms),
+            idle_sessions: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn command(&self, method: reqwest::Method, path: &str, body: Value) -> Result<Value> {
+        let res = self
+            .client
+            .request(method, format!("{}{}", self.endpoint, path))
+            .json(&body)
+            .send()
+            .await?;
+
+        let status = res.status();
+        let mut res: Value = res.json().await?;
+        let value = res["value"].take();
+
+        if !status.is_success() {
+            return Err(anyhow!(
+                "webdriver error ({status}): {}",
+                value["message"].as_str().unwrap_or_default()
+            ));
+        }
+
+        Ok(value)
+    }
+
+    async fn new_session(&self) -> Result<String> {
+        let value = self
+            .command(reqwest::Method::POST, "/session", self.capabilities.clone())
+            .await?;
+
+        value["sessionId"]
+            .as_str()
+            .map(|id| id.to_string())
+            .ok_or_else(|| anyhow!("webdriver did not return a session id"))
+    }
+
+    /// Take an idle session or start a new one.
+    async fn session(&self) -> Result<SessionGuard<'_>> {
+        let idle = self
+            .idle_sessions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop();
+
+        let id = match idle {
+            Some(id) => id,
+            None => self.new_session().await?,
+        };
+
+        Ok(SessionGuard {
+            driver: self,
+            id: Some(id),
+        })
+    }
+
+    /// Sessions are closed in the background, as they are also
+    /// closed from `Drop` where we can't wait for the response.
+    fn close_session(&self, session: String) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let request = self
+            .client
+            .delete(format!("{}/session/{}", self.endpoint, session));
+
+        runtime.spawn(async move {
+            if let Err(err) = request.send().await {
+                tracing::debug!("failed to close webdriver session: {err}");
+            }
+        });
+    }
+
+    async fn render_in(&self, session: &str, url: &Url) -> Result<String> {
+        self.command(
+            reqwest::Method::POST,
+            &format!("/session/{session}/url"),
+            json!({ "url": url.as_str() }),
+        )
+        .await?;
+
+        if !self.settle.is_zero() {
+            tokio::time::sleep(self.settle).await;
+        }
+
+        // large pages are dropped in the browser, so they are never sent to us.
+        let html = self
+            .command(
+                reqwest::Method::POST,
+                &format!("/session/{session}/execute/sync"),
+                json!({
+                    "script": "const html = document.documentElement.outerHTML; \
+                               return html.length > arguments[0] ? null : html;",
+                    "args": [MAX_CONTENT_LENGTH],
+                }),
+            )
+            .await?;
+
+        match html {
+            Value::String(html) => Ok(html),
+            Value::Null => Err(Error::ContentTooLarge.into()),
+            _ => Err(anyhow!("webdriver did not return the page html")),
+        }
+    }
+}
+
+impl Drop for WebDriver {
+    fn drop(&mut self) {
+        let sessions =
+            std::mem::take(&mut *self.idle_sessions.lock().unwrap_or_else(|e| e.into_inner()));
+
+        for session in sessions {
+            self.close_session(session);
+        }
+    }
+}
+
+/// A browser session that is in use. The session is closed when the guard is
+/// dropped, unless it was handed back to the idle sessions after a successful render.
+struct SessionGuard<'a> {
+    driver: &'a WebDriver,
+    id: Option<String>,
+}
+
+impl SessionGuard<'_> {
+    fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or_default()
+    }
+
+    fn release(mut self) {
+        if let Some(id) = self.id.take() {
+            self.driver
+                .idle_sessions
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(id);
+        }
+    }
+}
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.driver.close_session(id);
+        }
+    }
+}
+
+impl Renderer for WebDriver {
+    fn render<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<String>> {
+        async move {
+            let session = self.session().await?;
+            let html = self.render_in(session.id(), url).await?;
+            session.release();
+
+            Ok(html)
+        }
+        .boxed()
+    }
+}
+
+/// Decides which pages are rendered and limits the number
+/// of pages that are rendered at the same time.
+pub struct RenderPool {
+    renderer: Box<dyn Renderer>,
+    domains: HashSet<Domain>,
+    sessions: Semaphore,
+}
+
+impl RenderPool {
+    pub fn new(config: &RenderConfig, user_agent: &str) -> Result<Self> {
+        Ok(Self::with_renderer(
+            Box::new(WebDriver::new(config, user_agent)?),
+            &config.domains,
+            config.max_sessions,
+        ))
+    }
+
+    pub fn with_renderer(
+        renderer: Box<dyn Renderer>,
+        domains: &[String],
+        max_sessions: usize,
+    ) -> Self {
+        Self {
+            renderer,
+            domains: domains
+                .iter()
+                .map(|domain| Domain::from(domain.trim().to_ascii_lowercase()))
+                .collect(),
+            sessions: Semaphore::new(max_sessions.max(1)),
+        }
+    }
+
+    pub fn should_render(&self, domain: &Domain) -> bool {
+        self.domains.contains(domain)
+    }
+
+    pub async fn render(&self, url: &Url) -> Result<String> {
+        let _session = self.sessions.acquire().await?;
+        let html = self.renderer.render(url).await?;
+
+        if html.len() > MAX_CONTENT_LENGTH {
+            return Err(Error::ContentTooLarge.into());
+        }
+
+        Ok(html)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingRenderer {
+        active: AtomicUsize,
+        max_active: AtomicUsize,
+    }
+
+    impl Renderer for CountingRenderer {
+        fn render<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<String>> {
+            async move {
+                let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_active.fetch_max(active, Ordering::SeqCst);
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                Ok(format!("<html>{url}</html>"))
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn only_configured_domains_are_rendered() {
+        let pool = RenderPool::with_renderer(
+            Box::<CountingRenderer>::default(),
+            &["Example.com".to_string(), " spa.io ".to_string()],
+            1,
+        );
+
+        assert!(pool.should_render(&Domain::from(
+            &Url::parse("https://www.example.com/a").unwrap()
+        )));
+        assert!(pool.should_render(&Domain::from(&Url::parse("https://app.spa.io/").unwrap())));
+        assert!(!pool.should_render(&Domain::from(&Url::parse("https://example.org/").unwrap())));
+    }
+
+    #[tokio::test]
+    async fn sessions_are_limited() {
+        let renderer = std::sync::Arc::new(CountingRenderer::default());
+
+        struct Shared(std::sync::Arc<CountingRenderer>);
+
+        impl Renderer for Shared {
+            fn render<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<String>> {
+                self.0.render(url)
+            }
+        }
+
+        let pool = RenderPool::with_renderer(Box::new(Shared(renderer.clone())), &[], 2);
+        let url = Url::parse("https://example.com/").unwrap();
+
+        let rendered = futures::future::join_all((0..6).map(|_| pool.render(&url))).await;
+
+        assert!(rendered
+            .into_iter()
+            .all(|html| html.unwrap() == "<html>https://example.com/</html>"));
+        assert_eq!(renderer.max_active.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn large_pages_are_dropped() {
+        struct LargeRenderer;
+
+        impl Renderer for LargeRenderer {
+            fn render<'a>(&'a self, _: &'a Url) -> BoxFuture<'a, Result<String>> {
+                async move { Ok("a".repeat(MAX_CONTENT_LENGTH + 1)) }.boxed()
+            }
+        }
+
+        let pool = RenderPool::with_renderer(Box::new(LargeRenderer), &[], 1);
+        let url = Url::parse("https://example.com/").unwrap();
+
+        assert!(pool.render(&url).await.is_err());
+    }
+}
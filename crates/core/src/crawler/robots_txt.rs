@@ -14,24 +14,56 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::BTreeMap, panic, time::Duration};
-
-use robotstxt_with_cache::matcher::{
-    CachingRobotsMatcher, LongestMatchRobotsMatchStrategy, RobotsMatcher,
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
 };
 
 use url::Url;
 
 use super::{Error, Result, Site};
 
-enum Lookup<T> {
-    Found(T),
+/// Only the first 500 KiB of a robots.txt file are parsed, the same limit that
+/// Google uses.
+const MAX_ROBOTS_TXT_BYTES: usize = 500 * 1024;
+
+/// Hosts whose robots.txt could not be fetched because of a server or network
+/// error are treated as fully disallowed, but only for this long so a temporary
+/// outage doesn't block the host for the whole cache period.
+const UNREACHABLE_EXPIRATION: Duration = Duration::from_secs(10 * 60);
+
+enum Lookup {
+    Found(RobotsTxt),
     NotFound,
+    Unreachable,
+}
+
+struct CachedLookup {
+    lookup: Lookup,
+    fetched: Instant,
+}
+
+impl CachedLookup {
+    fn new(lookup: Lookup) -> Self {
+        Self {
+            lookup,
+            fetched: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self, expiration: &Duration) -> bool {
+        let expiration = match self.lookup {
+            Lookup::Unreachable => (*expiration).min(UNREACHABLE_EXPIRATION),
+            Lookup::Found(_) | Lookup::NotFound => *expiration,
+        };
+
+        self.fetched.elapsed() > expiration
+    }
 }
 
 pub struct RobotsTxtManager {
-    cache: BTreeMap<Site, Lookup<RobotsTxt>>,
-    last_prune: std::time::Instant,
+    cache: BTreeMap<Site, CachedLookup>,
+    last_prune: Instant,
     client: reqwest::Client,
     cache_expiration: Duration,
 }
@@ -41,21 +73,34 @@ impl RobotsTxtManager {
         Self {
             client,
             cache_expiration,
-            last_prune: std::time::Instant::now(),
+            last_prune: Instant::now(),
             cache: BTreeMap::new(),
         }
     }
 
     pub async fn is_allowed(&mut self, url: &Url, user_agent: &str) -> bool {
         match self.get_mut(url).await {
-            Lookup::Found(robots_txt) => robots_txt
-                .matcher
-                .one_agent_allowed_by_robots(user_agent, url.as_str()),
-            _ => true,
+            Lookup::Found(robots_txt) => robots_txt.is_allowed(url, user_agent),
+            Lookup::NotFound => true,
+            Lookup::Unreachable => false,
+        }
+    }
+
+    pub async fn crawl_delay(&mut self, url: &Url, user_agent: &str) -> Option<Duration> {
+        match self.get_mut(url).await {
+            Lookup::Found(robots_txt) => robots_txt.crawl_delay(user_agent),
+            Lookup::NotFound | Lookup::Unreachable => None,
+        }
+    }
+
+    pub async fn sitemap(&mut self, url: &Url) -> Option<Url> {
+        match self.get_mut(url).await {
+            Lookup::Found(robots_txt) => robots_txt.sitemaps().first().cloned(),
+            Lookup::NotFound | Lookup::Unreachable => None,
         }
     }
 
-    async fn fetch_robots_txt(&self, site: &Site) -> Result<RobotsTxt> {
+    async fn fetch_robots_txt(&self, site: &Site) -> Result<Lookup> {
         let mut res = self
             .client
             .get(&format!("http://{}/robots.txt", site.0))
@@ -73,26 +118,33 @@ impl RobotsTxtManager {
         }
 
         let res = res?;
+        let status = res.status();
 
-        if res.status() != reqwest::StatusCode::OK {
-            return Err(Error::FetchFailed(res.status()).into());
+        if status.is_client_error() {
+            return Ok(Lookup::NotFound);
         }
 
+        if !status.is_success() {
+            return Err(Error::FetchFailed(status).into());
+        }
+
+        // some servers answer with an html page for every path, which should not
+        // be parsed as a robots.txt file.
         if !res
             .headers()
             .get(reqwest::header::CONTENT_TYPE)
             .map(|h| h.to_str().unwrap_or_default().starts_with("text/plain"))
-            .unwrap_or(false)
+            .unwrap_or(true)
         {
-            return Err(Error::FetchFailed(reqwest::StatusCode::IM_A_TEAPOT).into());
+            return Ok(Lookup::NotFound);
         }
 
-        let body = res.text().await?;
+        let body = res.bytes().await?;
+        let body = &body[..body.len().min(MAX_ROBOTS_TXT_BYTES)];
 
-        match panic::catch_unwind(|| RobotsTxt::new(body)) {
-            Ok(r) => Ok(r),
-            Err(_) => Err(Error::FetchFailed(reqwest::StatusCode::IM_A_TEAPOT).into()),
-        }
+        Ok(Lookup::Found(RobotsTxt::parse(&String::from_utf8_lossy(
+            body,
+        ))))
     }
 
     fn maybe_prune(&mut self) {
@@ -100,126 +152,382 @@ impl RobotsTxtManager {
             return;
         }
 
-        self.cache.retain(|_, v| match v {
-            Lookup::Found(robots_txt) => !robots_txt.is_expired(&self.cache_expiration),
-            _ => true,
-        });
+        self.cache
+            .retain(|_, v| !v.is_expired(&self.cache_expiration));
 
-        self.last_prune = std::time::Instant::now();
+        self.last_prune = Instant::now();
     }
 
-    async fn get_mut(&mut self, url: &Url) -> &mut Lookup<RobotsTxt> {
+    async fn get_mut(&mut self, url: &Url) -> &mut Lookup {
         self.maybe_prune();
         let site = Site(url.host_str().unwrap_or_default().to_string());
 
-        let cache_should_update = match self.cache.get_mut(&site) {
-            Some(Lookup::Found(robots_txt)) => robots_txt.is_expired(&self.cache_expiration),
-            Some(Lookup::NotFound) => false,
-            _ => true,
+        let cache_should_update = match self.cache.get(&site) {
+            Some(cached) => cached.is_expired(&self.cache_expiration),
+            None => true,
         };
 
         if cache_should_update {
-            match self.fetch_robots_txt(&site).await {
-                Ok(robots_txt) => {
-                    self.cache.insert(site.clone(), Lookup::Found(robots_txt));
+            let lookup = match self.fetch_robots_txt(&site).await {
+                Ok(lookup) => lookup,
+                Err(err) => {
+                    tracing::warn!("failed to fetch robots.txt for {}: {}", site.0, err);
+                    Lookup::Unreachable
                 }
-                Err(err) => match err.downcast_ref() {
-                    Some(Error::FetchFailed(status))
-                        if *status == reqwest::StatusCode::IM_A_TEAPOT =>
-                    {
-                        self.cache.insert(site.clone(), Lookup::NotFound);
-                    }
-                    _ => {
-                        self.cache.insert(site.clone(), Lookup::NotFound);
-                        tracing::warn!("failed to fetch robots.txt for {}: {}", site.0, err);
-                    }
-                },
-            }
+            };
+
+            self.cache.insert(site.clone(), CachedLookup::new(lookup));
         }
 
-        self.cache.get_mut(&site).unwrap()
+        &mut self.cache.get_mut(&site).unwrap().lookup
     }
+}
 
-    pub async fn sitemap(&mut self, url: &Url) -> Option<Url> {
-        match self.get_mut(url).await {
-            Lookup::Found(robotstxt) => robotstxt.sitemap.clone(),
-            Lookup::NotFound => None,
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl Rule {
+    /// Matches the pattern against the path (including query) of a url.
+    /// `*` matches any sequence of characters and a trailing `$` anchors
+    /// the pattern to the end of the path.
+    fn matches(&self, path: &str) -> bool {
+        let (pattern, anchored) = match self.pattern.strip_suffix('$') {
+            Some(pattern) => (pattern, true),
+            None => (self.pattern.as_str(), false),
+        };
+
+        let mut parts = pattern.split('*');
+        let first = parts.next().unwrap_or_default();
+
+        let Some(mut rest) = path.strip_prefix(first) else {
+            return false;
+        };
+
+        let parts: Vec<_> = parts.collect();
+
+        for (i, part) in parts.iter().enumerate() {
+            if anchored && i == parts.len() - 1 {
+                return rest.ends_with(part);
+            }
+
+            match rest.find(part) {
+                Some(idx) => rest = &rest[idx + part.len()..],
+                None => return false,
+            }
         }
+
+        !anchored || rest.is_empty()
+    }
+
+    fn specificity(&self) -> usize {
+        self.pattern.len()
     }
 }
 
-struct RobotsTxt {
-    download_time: std::time::Instant,
-    matcher: CachingRobotsMatcher<LongestMatchRobotsMatchStrategy>,
-    sitemap: Option<Url>,
+#[derive(Debug, Default, Clone)]
+struct Group {
+    user_agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RobotsTxt {
+    groups: Vec<Group>,
+    sitemaps: Vec<Url>,
 }
 
 impl RobotsTxt {
-    fn new(body: String) -> Self {
-        let mut s = Self {
-            matcher: CachingRobotsMatcher::new(RobotsMatcher::default()),
-            sitemap: None,
-            download_time: std::time::Instant::now(),
-        };
+    pub fn parse(body: &str) -> Self {
+        let mut groups = Vec::new();
+        let mut sitemaps = Vec::new();
+
+        let mut current: Option<Group> = None;
+        let mut reading_user_agents = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !reading_user_agents {
+                        groups.extend(current.take());
+                        reading_user_agents = true;
+                    }
+
+                    current
+                        .get_or_insert_with(Group::default)
+                        .user_agents
+                        .push(user_agent_token(value));
+                }
+                "allow" | "disallow" => {
+                    reading_user_agents = false;
+
+                    // an empty disallow means that everything is allowed
+                    if value.is_empty() {
+                        continue;
+                    }
+
+                    if let Some(group) = current.as_mut() {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    reading_user_agents = false;
+
+                    if let Some(group) = current.as_mut() {
+                        group.crawl_delay = value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|delay| delay.is_finite() && *delay >= 0.0)
+                            .map(Duration::from_secs_f64);
+                    }
+                }
+                "sitemap" => {
+                    if let Ok(url) = Url::parse(value) {
+                        sitemaps.push(url);
+                    }
+                }
+                _ => {}
+            }
+        }
 
-        s.update(body);
+        groups.extend(current);
 
-        s
+        Self { groups, sitemaps }
     }
 
-    fn is_expired(&self, expiration: &Duration) -> bool {
-        self.download_time.elapsed() > *expiration
+    /// The groups that apply to the user agent. If no group names the
+    /// user agent, the `*` groups apply.
+    fn groups_for<'a>(&'a self, user_agent: &str) -> Vec<&'a Group> {
+        let user_agent = user_agent_token(user_agent);
+
+        let specific: Vec<_> = self
+            .groups
+            .iter()
+            .filter(|group| group.user_agents.contains(&user_agent))
+            .collect();
+
+        if !specific.is_empty() {
+            return specific;
+        }
+
+        self.groups
+            .iter()
+            .filter(|group| group.user_agents.iter().any(|ua| ua == "*"))
+            .collect()
     }
 
-    fn update(&mut self, body: String) {
-        self.matcher.parse(&body.to_lowercase());
+    /// The most specific (longest) matching rule decides. If an allow and a
+    /// disallow rule are equally specific, the allow rule wins.
+    pub fn is_allowed(&self, url: &Url, user_agent: &str) -> bool {
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        self.groups_for(user_agent)
+            .into_iter()
+            .flat_map(|group| group.rules.iter())
+            .filter(|rule| rule.matches(&path))
+            .max_by_key(|rule| (rule.specificity(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
 
-        self.sitemap = body
-            .to_ascii_lowercase()
-            .lines()
-            .find(|line| line.starts_with("sitemap:"))
-            .map(|line| line.split(':').nth(1).unwrap().trim())
-            .and_then(|s| Url::parse(s).ok());
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.groups_for(user_agent)
+            .into_iter()
+            .filter_map(|group| group.crawl_delay)
+            .max()
+    }
 
-        self.download_time = std::time::Instant::now();
+    pub fn sitemaps(&self) -> &[Url] {
+        &self.sitemaps
     }
 }
 
+/// User agents are matched case-insensitively on their product token,
+/// so `StractBot/1.0` in a robots.txt file applies to `stractbot`.
+fn user_agent_token(user_agent: &str) -> String {
+    user_agent
+        .split(|c: char| c == '/' || c.is_whitespace())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn allowed(robots_txt: &RobotsTxt, url: &str) -> bool {
+        robots_txt.is_allowed(&Url::parse(url).unwrap(), "StractBot")
+    }
+
     #[test]
     fn simple() {
-        let ua_token = "StractBot";
-        let mut robots_txt = RobotsTxt::new(
+        let robots_txt = RobotsTxt::parse(
             r#"User-agent: StractBot
-            Disallow: /test"#
-                .to_string(),
+            Disallow: /test"#,
         );
 
-        assert!(!robots_txt
-            .matcher
-            .one_agent_allowed_by_robots(ua_token, "http://example.com/test"));
-        assert!(robots_txt
-            .matcher
-            .one_agent_allowed_by_robots(ua_token, "http://example.com/example"));
+        assert!(!allowed(&robots_txt, "http://example.com/test"));
+        assert!(allowed(&robots_txt, "http://example.com/example"));
     }
 
     #[test]
     fn lowercase() {
-        let ua_token = "StractBot";
-        let mut robots_txt = RobotsTxt::new(
+        let robots_txt = RobotsTxt::parse(
             r#"User-agent: stractbot
-            Disallow: /test"#
-                .to_string(),
+            Disallow: /test"#,
+        );
+
+        assert!(!allowed(&robots_txt, "http://example.com/test"));
+        assert!(allowed(&robots_txt, "http://example.com/example"));
+    }
+
+    #[test]
+    fn paths_are_case_sensitive() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Disallow: /Private"#,
+        );
+
+        assert!(!allowed(&robots_txt, "http://example.com/Private/a"));
+        assert!(allowed(&robots_txt, "http://example.com/private/a"));
+    }
+
+    #[test]
+    fn specific_group_takes_precedence() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Disallow: /
+
+            User-agent: StractBot/1.0
+            User-agent: OtherBot
+            Disallow: /admin"#,
+        );
+
+        assert!(allowed(&robots_txt, "http://example.com/"));
+        assert!(!allowed(&robots_txt, "http://example.com/admin"));
+        assert!(!robots_txt.is_allowed(&Url::parse("http://example.com/").unwrap(), "ThirdBot"));
+    }
+
+    #[test]
+    fn longest_match_wins() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Disallow: /folder
+            Allow: /folder/page
+            Allow: /page
+            Disallow: /page"#,
+        );
+
+        assert!(!allowed(&robots_txt, "http://example.com/folder/other"));
+        assert!(allowed(&robots_txt, "http://example.com/folder/page.html"));
+        assert!(allowed(&robots_txt, "http://example.com/page"));
+    }
+
+    #[test]
+    fn wildcards() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Disallow: /*.pdf$
+            Disallow: /*?session=
+            Disallow: /a*/b"#,
+        );
+
+        assert!(!allowed(&robots_txt, "http://example.com/files/doc.pdf"));
+        assert!(allowed(
+            &robots_txt,
+            "http://example.com/files/doc.pdf.html"
+        ));
+        assert!(!allowed(
+            &robots_txt,
+            "http://example.com/search?session=123"
+        ));
+        assert!(allowed(&robots_txt, "http://example.com/search?q=123"));
+        assert!(!allowed(&robots_txt, "http://example.com/abc/b"));
+        assert!(allowed(&robots_txt, "http://example.com/abc/c"));
+    }
+
+    #[test]
+    fn empty_disallow_allows_everything() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Disallow:"#,
+        );
+
+        assert!(allowed(&robots_txt, "http://example.com/anything"));
+    }
+
+    #[test]
+    fn robots_txt_is_always_allowed() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Disallow: /"#,
+        );
+
+        assert!(allowed(&robots_txt, "http://example.com/robots.txt"));
+        assert!(!allowed(&robots_txt, "http://example.com/index.html"));
+    }
+
+    #[test]
+    fn crawl_delay() {
+        let robots_txt = RobotsTxt::parse(
+            r#"User-agent: *
+            Crawl-delay: 10
+
+            User-agent: StractBot
+            Crawl-delay: 2.5 # seconds
+            Disallow: /tmp"#,
         );
 
-        assert!(!robots_txt
-            .matcher
-            .one_agent_allowed_by_robots(ua_token, "http://example.com/test"));
-        assert!(robots_txt
-            .matcher
-            .one_agent_allowed_by_robots(ua_token, "http://example.com/example"));
+        assert_eq!(
+            robots_txt.crawl_delay("StractBot"),
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(
+            robots_txt.crawl_delay("OtherBot"),
+            Some(Duration::from_secs(10))
+        );
+        assert!(!allowed(&robots_txt, "http://example.com/tmp/a"));
+    }
+
+    #[test]
+    fn sitemaps() {
+        let robots_txt = RobotsTxt::parse(
+            r#"Sitemap: https://example.com/sitemap.xml
+            User-agent: *
+            Disallow: /private
+            sitemap: https://example.com/News/sitemap.xml"#,
+        );
+
+        assert_eq!(
+            robots_txt.sitemaps(),
+            &[
+                Url::parse("https://example.com/sitemap.xml").unwrap(),
+                Url::parse("https://example.com/News/sitemap.xml").unwrap(),
+            ]
+        );
     }
 }
@@ -5,11 +5,12 @@ use tokio::sync::Mutex;
 
 use crate::{
     distributed::{retry_strategy::ExponentialBackoff, sonic},
-    entrypoint::crawler::coordinator::{CoordinatorService, GetJob},
+    entrypoint::crawler::coordinator::{CompleteJob, CoordinatorService, GetJob},
 };
 
-use super::Job;
+use super::{Domain, Job};
 
+#[derive(Clone)]
 struct RemoteCoordinator {
     addr: SocketAddr,
 }
@@ -35,6 +36,15 @@ impl RemoteCoordinator {
 
         Ok(response)
     }
+
+    async fn complete_job(&self, domain: Domain) -> Result<()> {
+        let conn = self.conn().await?;
+
+        conn.send_with_timeout(&CompleteJob { domain }, Duration::from_secs(90))
+            .await?;
+
+        Ok(())
+    }
 }
 
 struct InnerRouter {
@@ -69,16 +79,36 @@ impl InnerRouter {
 
 pub struct Router {
     inner: Mutex<InnerRouter>,
+    /// All coordinators, including the ones that have run out of jobs,
+    /// since they can still have jobs in flight.
+    all_coordinators: Vec<RemoteCoordinator>,
 }
 
 impl Router {
     pub async fn new(coordinator_addrs: Vec<SocketAddr>) -> Result<Self> {
+        let inner = InnerRouter::new(coordinator_addrs).await?;
+        let all_coordinators = inner.coordinators.clone();
+
         Ok(Self {
-            inner: Mutex::new(InnerRouter::new(coordinator_addrs).await?),
+            inner: Mutex::new(inner),
+            all_coordinators,
         })
     }
 
     pub async fn sample_job(&self) -> Result<Option<Job>> {
         self.inner.lock().await.sample_job().await
     }
+
+    /// Release the lease of a finished job. The router doesn't know which
+    /// coordinator the job came from, but only that coordinator has a lease for it.
+    pub async fn complete_job(&self, domain: Domain) -> Result<()> {
+        let res = futures::future::join_all(
+            self.all_coordinators
+                .iter()
+                .map(|coordinator| coordinator.complete_job(domain.clone())),
+        )
+        .await;
+
+        res.into_iter().collect()
+    }
 }
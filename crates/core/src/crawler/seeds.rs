@@ -0,0 +1,390 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Import seed urls from Common Crawl url indexes (CDX and CDXJ) or plain url lists.
+//! The seeds are grouped by domain and written as crawl jobs in the same queue format
+//! as the crawl planner, so a coordinator can hand them out directly.
+//!
+//! The index files are large, so they are streamed from disk line by line and the
+//! number of domains and seeds per domain kept in memory is capped.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::anyhow;
+use dashmap::{mapref::entry::Entry, DashMap};
+use flate2::read::MultiGzDecoder;
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use url::Url;
+
+use crate::{
+    config::{SeedImportConfig, WarcSource},
+    crawler::{file_queue::FileQueueWriter, Job, UrlFilter, Validators, WeightedUrl},
+    warc::WarcFile,
+};
+
+use super::{budget, Domain, Result};
+
+/// Number of domains whose jobs are built in parallel before they are written to the queues.
+const JOB_CHUNK_SIZE: usize = 10_000;
+
+#[derive(serde::Deserialize)]
+struct CdxjRecord {
+    url: String,
+    #[serde(default)]
+    mime: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+}
+
+fn is_html(mime: &str) -> bool {
+    // common crawl uses `unk` and `-` when the mime type is unknown
+    mime.starts_with("text/html")
+        || mime.starts_with("application/xhtml+xml")
+        || mime == "unk"
+        || mime == "-"
+}
+
+fn is_ok(status: &str) -> bool {
+    status == "200" || status == "-"
+}
+
+fn parse_url(url: &str) -> Option<Url> {
+    let url = Url::parse(url).ok()?;
+
+    if matches!(url.scheme(), "http" | "https") {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Parse a line of a CDXJ index, a classic CDX index or a plain url list.
+/// Index records of non-html pages or pages that were not fetched successfully are skipped.
+pub fn parse_line(line: &str) -> Option<Url> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    if let Some(start) = line.find('{') {
+        let record: CdxjRecord = serde_json::from_str(&line[start..]).ok()?;
+
+        if !record.mime.as_deref().map(is_html).unwrap_or(true)
+            || !record.status.as_deref().map(is_ok).unwrap_or(true)
+        {
+            return None;
+        }
+
+        return parse_url(&record.url);
+    }
+
+    let fields: Vec<_> = line.split_whitespace().collect();
+
+    match fields.as_slice() {
+        [url] => parse_url(url),
+        // urlkey timestamp original mimetype statuscode ...
+        [_, _, url, mime, status, ..] => {
+            if is_html(mime) && is_ok(status) {
+                parse_url(url)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Read the seed urls of a (possibly gzipped) seed file.
+fn read_seeds<R: Read + 'static>(reader: R) -> Result<impl Iterator<Item = Url>> {
+    let mut reader = BufReader::new(reader);
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+
+    let reader: Box<dyn BufRead> = if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+
+    Ok(reader
+        .split(b'\n')
+        .map_while(|line| line.ok())
+        .filter_map(|line| String::from_utf8(line).ok())
+        .filter_map(|line| parse_line(&line)))
+}
+
+/// Open the seed file at `path`. Files that are not on the local disk are downloaded
+/// to `tmp` first, so they don't have to be kept in memory while they are read.
+fn open_seed_file(source: &WarcSource, path: &str, tmp: &Path) -> Result<File> {
+    if let WarcSource::Local(config) = source {
+        return Ok(File::open(Path::new(&config.folder).join(path))?);
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(tmp)?;
+
+    WarcFile::download_into_buf(source, path, &mut file)?;
+    file.rewind()?;
+
+    Ok(file)
+}
+
+pub fn import_seeds<P: AsRef<Path>>(config: SeedImportConfig, output: P) -> Result<()> {
+    if output.as_ref().exists() {
+        return Err(anyhow!("output path already exists"));
+    }
+
+    let queue_path = output.as_ref().join("job_queue");
+    std::fs::create_dir_all(&queue_path)?;
+
+    let paths = if config.paths.is_empty() {
+        config.source.paths()?
+    } else {
+        config.paths.clone()
+    };
+
+    let url_filter = match &config.url_filter_path {
+        Some(path) => UrlFilter::open(path)?,
+        None => UrlFilter::default(),
+    };
+
+    let tmp_path = output.as_ref().join("tmp");
+    std::fs::create_dir_all(&tmp_path)?;
+
+    let max_urls_per_domain = config
+        .budget
+        .max_urls_per_domain
+        .map(|max| max as usize)
+        .unwrap_or(usize::MAX)
+        .min(config.max_seeds_per_domain);
+    let seeds: DashMap<Domain, HashSet<Url>> = DashMap::new();
+    let num_domains = AtomicUsize::new(0);
+    let skipped_domains = AtomicUsize::new(0);
+
+    paths
+        .par_iter()
+        .enumerate()
+        .progress_count(paths.len() as u64)
+        .try_for_each(|(i, path)| {
+            let tmp = tmp_path.join(i.to_string());
+            let file = open_seed_file(&config.source, path, &tmp)?;
+
+            let mut num_seeds = 0;
+            for url in read_seeds(file)?
+                .filter(|url| !budget::is_too_deep(url, config.budget.max_depth))
+                .filter(|url| url_filter.is_allowed(url))
+            {
+                let mut urls = match seeds.entry(Domain::from(&url)) {
+                    Entry::Occupied(urls) => urls.into_ref(),
+                    Entry::Vacant(entry) => {
+                        if num_domains.fetch_add(1, Ordering::Relaxed) >= config.max_domains {
+                            num_domains.fetch_sub(1, Ordering::Relaxed);
+                            skipped_domains.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+
+                        entry.insert(HashSet::new())
+                    }
+                };
+
+                if urls.len() < max_urls_per_domain && urls.insert(url) {
+                    num_seeds += 1;
+                }
+            }
+
+            if tmp.exists() {
+                std::fs::remove_file(&tmp)?;
+            }
+
+            tracing::info!("imported {num_seeds} seeds from {path}");
+
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+    std::fs::remove_dir_all(&tmp_path)?;
+
+    let skipped_domains = skipped_domains.into_inner();
+    if skipped_domains > 0 {
+        tracing::warn!(
+            "skipped {skipped_domains} seeds for new domains after reaching the limit of {} domains",
+            config.max_domains
+        );
+    }
+
+    let mut domains: Vec<_> = seeds
+        .into_iter()
+        .filter(|(domain, _)| !domain.as_str().is_empty())
+        .collect();
+
+    // domains with many seeds are most likely the most important ones,
+    // so they are written first to have them crawled before the rest.
+    domains.sort_by(|(a, a_urls), (b, b_urls)| {
+        b_urls
+            .len()
+            .cmp(&a_urls.len())
+            .then_with(|| a.as_str().cmp(b.as_str()))
+    });
+    tracing::info!("found seeds for {} domains", domains.len());
+
+    let domain_caps = budget::domain_caps(
+        &domains
+            .iter()
+            .map(|(domain, urls)| (domain.clone(), urls.len() as u64, urls.len() as f64))
+            .collect::<Vec<_>>(),
+        &config.budget,
+    );
+
+    let job_queues: Vec<Mutex<FileQueueWriter<Job>>> = (0..config.num_job_queues)
+        .map(|i| FileQueueWriter::new(queue_path.join(format!("{}.queue", i))))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .map(Mutex::new)
+        .collect();
+
+    let mut next_queue = 0;
+    let mut total_seeds = 0;
+
+    for chunk in domains.chunks(JOB_CHUNK_SIZE) {
+        let jobs: Vec<_> = chunk
+            .par_iter()
+            .map(|(domain, urls)| {
+                let cap = domain_caps.get(domain).copied().unwrap_or(u64::MAX);
+
+                let mut urls: Vec<_> = urls.iter().cloned().collect();
+                urls.sort();
+                urls.truncate(cap.min(urls.len() as u64) as usize);
+
+                Job {
+                    domain: domain.clone(),
+                    wandering_urls: config
+                        .wander_budget
+                        .min(cap.saturating_sub(urls.len() as u64)),
+                    urls: urls
+                        .into_iter()
+                        .map(|url| WeightedUrl {
+                            url,
+                            weight: 1.0,
+                            validators: Validators::default(),
+                        })
+                        .collect::<VecDeque<_>>(),
+                    max_depth: config.budget.max_depth,
+                    feeds: Vec::new(),
+                }
+            })
+            .collect();
+
+        for job in jobs {
+            total_seeds += job.urls.len();
+
+            job_queues[next_queue % job_queues.len()]
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(job)?;
+            next_queue += 1;
+        }
+    }
+
+    for queue in job_queues {
+        queue
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .finalize()?;
+    }
+
+    tracing::info!("wrote {total_seeds} seeds in {next_queue} jobs");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn cdxj() {
+        assert_eq!(
+            parse_line(
+                r#"com,example)/a 20240101120000 {"url": "https://example.com/a", "mime": "text/html", "status": "200"}"#
+            ),
+            Some(Url::parse("https://example.com/a").unwrap())
+        );
+        assert_eq!(
+            parse_line(
+                r#"com,example)/a.pdf 20240101120000 {"url": "https://example.com/a.pdf", "mime": "application/pdf", "status": "200"}"#
+            ),
+            None
+        );
+        assert_eq!(
+            parse_line(
+                r#"com,example)/b 20240101120000 {"url": "https://example.com/b", "mime": "text/html", "status": "301"}"#
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn cdx_and_plain_lists() {
+        assert_eq!(
+            parse_line("com,example)/ 20240101120000 http://example.com/ text/html 200 AAAA - - 123 456 file.warc.gz"),
+            Some(Url::parse("http://example.com/").unwrap())
+        );
+        assert_eq!(parse_line(" CDX N b a m s k r M S V g"), None);
+        assert_eq!(
+            parse_line("https://example.com/c\n"),
+            Some(Url::parse("https://example.com/c").unwrap())
+        );
+        assert_eq!(parse_line("ftp://example.com/"), None);
+        assert_eq!(parse_line("# comment"), None);
+    }
+
+    #[test]
+    fn gzipped_seeds() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(b"https://example.com/a\nnot a url\nhttps://example.com/b\n")
+            .unwrap();
+
+        let urls: Vec<_> = read_seeds(std::io::Cursor::new(encoder.finish().unwrap()))
+            .unwrap()
+            .collect();
+
+        assert_eq!(
+            urls,
+            vec![
+                Url::parse("https://example.com/a").unwrap(),
+                Url::parse("https://example.com/b").unwrap(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Statistics for monitoring a running crawl. The coordinator summarises the
+//! jobs that are still pending while the workers count the responses they get.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::metrics::{Counter, Label, PrometheusRegistry};
+
+use super::{FailureReason, Job, Result};
+
+/// Number of domains with the most pending urls to include in the stats.
+pub const NUM_TOP_DOMAINS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchStatus {
+    Ok,
+    Redirect,
+    NotModified,
+    ClientError,
+    Throttled,
+    ServerError,
+    /// The url could not be fetched at all, e.g. because of a timeout
+    /// or an unsupported content type.
+    Failed,
+}
+
+impl FetchStatus {
+    const ALL: [FetchStatus; 7] = [
+        FetchStatus::Ok,
+        FetchStatus::Redirect,
+        FetchStatus::NotModified,
+        FetchStatus::ClientError,
+        FetchStatus::Throttled,
+        FetchStatus::ServerError,
+        FetchStatus::Failed,
+    ];
+
+    pub fn from_status_code(status_code: u16) -> Self {
+        match status_code {
+            304 => FetchStatus::NotModified,
+            429 | 503 => FetchStatus::Throttled,
+            200..=299 => FetchStatus::Ok,
+            300..=399 => FetchStatus::Redirect,
+            400..=499 => FetchStatus::ClientError,
+            500..=599 => FetchStatus::ServerError,
+            _ => FetchStatus::Failed,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FetchStatus::Ok => "ok",
+            FetchStatus::Redirect => "redirect",
+            FetchStatus::NotModified => "not_modified",
+            FetchStatus::ClientError => "client_error",
+            FetchStatus::Throttled => "throttled",
+            FetchStatus::ServerError => "server_error",
+            FetchStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Number of fetched urls for each kind of response. The fetch rate
+/// and error rates can be derived from these in prometheus.
+#[derive(Default, Clone)]
+pub struct FetchMetrics {
+    counters: [Counter; 7],
+    failures: [Counter; FailureReason::KINDS.len()],
+}
+
+impl FetchMetrics {
+    pub fn register(&self, registry: &mut PrometheusRegistry) {
+        let group = registry
+            .new_group(
+                "stract_crawler_fetches".to_string(),
+                Some("Number of urls fetched by the crawler by response status.".to_string()),
+            )
+            .unwrap();
+
+        for (status, counter) in FetchStatus::ALL.iter().zip(self.counters.iter()) {
+            group.register(
+                counter.clone(),
+                vec![Label {
+                    key: "status".to_string(),
+                    val: status.as_str().to_string(),
+                }],
+            );
+        }
+
+        let group = registry
+            .new_group(
+                "stract_crawler_failures".to_string(),
+                Some("Number of urls the crawler failed to crawl by reason.".to_string()),
+            )
+            .unwrap();
+
+        for (kind, counter) in FailureReason::KINDS.iter().zip(self.failures.iter()) {
+            group.register(
+                counter.clone(),
+                vec![Label {
+                    key: "reason".to_string(),
+                    val: kind.to_string(),
+                }],
+            );
+        }
+    }
+
+    pub fn observe(&self, status: FetchStatus) {
+        let idx = FetchStatus::ALL.iter().position(|s| *s == status).unwrap();
+        self.counters[idx].inc();
+    }
+
+    pub fn observe_failure(&self, reason: FailureReason) {
+        let idx = FailureReason::KINDS
+            .iter()
+            .position(|kind| *kind == reason.kind())
+            .unwrap();
+        self.failures[idx].inc();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PendingDomain {
+    pub domain: String,
+    pub scheduled_urls: u64,
+    pub wandering_urls: u64,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct CoordinatorStats {
+    pub pending_jobs: u64,
+    pub pending_scheduled_urls: u64,
+    pub pending_wandering_urls: u64,
+    pub dispatched_jobs: u64,
+    pub dispatched_urls: u64,
+    pub uptime_secs: u64,
+    pub jobs_per_minute: f64,
+    /// Domains with the most pending urls, largest first.
+    pub top_pending_domains: Vec<PendingDomain>,
+}
+
+impl CoordinatorStats {
+    /// Summarise the jobs that have not been handed out yet.
+    pub fn from_pending(jobs: impl Iterator<Item = Result<Job>>) -> Result<Self> {
+        let mut stats = Self::default();
+        let mut top = BinaryHeap::new();
+
+        for job in jobs {
+            let job = job?;
+            let scheduled_urls = job.urls.len() as u64;
+
+            stats.pending_jobs += 1;
+            stats.pending_scheduled_urls += scheduled_urls;
+            stats.pending_wandering_urls += job.wandering_urls;
+
+            top.push(Reverse((
+                scheduled_urls + job.wandering_urls,
+                job.domain.as_str().to_string(),
+                scheduled_urls,
+                job.wandering_urls,
+            )));
+
+            if top.len() > NUM_TOP_DOMAINS {
+                top.pop();
+            }
+        }
+
+        stats.top_pending_domains = top
+            .into_sorted_vec()
+            .into_iter()
+            .map(
+                |Reverse((_, domain, scheduled_urls, wandering_urls))| PendingDomain {
+                    domain,
+                    scheduled_urls,
+                    wandering_urls,
+                },
+            )
+            .collect();
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use url::Url;
+
+    use crate::crawler::{Validators, WeightedUrl};
+
+    use super::*;
+
+    fn job(domain: &str, num_urls: usize, wandering_urls: u64) -> Job {
+        Job {
+            domain: domain.to_string().into(),
+            urls: (0..num_urls)
+                .map(|i| WeightedUrl {
+                    url: Url::parse(&format!("https://{domain}/{i}")).unwrap(),
+                    weight: 1.0,
+                    validators: Validators::default(),
+                })
+                .collect::<VecDeque<_>>(),
+            wandering_urls,
+            max_depth: None,
+            feeds: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(FetchStatus::from_status_code(200), FetchStatus::Ok);
+        assert_eq!(FetchStatus::from_status_code(301), FetchStatus::Redirect);
+        assert_eq!(FetchStatus::from_status_code(304), FetchStatus::NotModified);
+        assert_eq!(FetchStatus::from_status_code(404), FetchStatus::ClientError);
+        assert_eq!(FetchStatus::from_status_code(429), FetchStatus::Throttled);
+        assert_eq!(FetchStatus::from_status_code(503), FetchStatus::Throttled);
+        assert_eq!(FetchStatus::from_status_code(500), FetchStatus::ServerError);
+    }
+
+    #[test]
+    fn pending_stats() {
+        let mut jobs = vec![job("a.com", 1, 10), job("b.com", 5, 0)];
+        for i in 0..NUM_TOP_DOMAINS {
+            jobs.push(job(&format!("small{i}.com"), 1, 0));
+        }
+
+        let stats = CoordinatorStats::from_pending(jobs.into_iter().map(Ok)).unwrap();
+
+        assert_eq!(stats.pending_jobs, 2 + NUM_TOP_DOMAINS as u64);
+        assert_eq!(stats.pending_scheduled_urls, 6 + NUM_TOP_DOMAINS as u64);
+        assert_eq!(stats.pending_wandering_urls, 10);
+
+        assert_eq!(stats.top_pending_domains.len(), NUM_TOP_DOMAINS);
+        assert_eq!(
+            stats.top_pending_domains[0],
+            PendingDomain {
+                domain: "a.com".to_string(),
+                scheduled_urls: 1,
+                wandering_urls: 10,
+            }
+        );
+        assert_eq!(stats.top_pending_domains[1].domain, "b.com");
+    }
+}
@@ -0,0 +1,360 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of crawler traps: sites that generate an endless number of urls
+//! through paths that repeat themselves or calendars that can be browsed forever.
+//!
+//! Pagination is not treated as a trap, and neither are urls that merely contain
+//! digits, since most sites legitimately have many pages that only differ by an id.
+//! Only urls that look like calendar pages are grouped into patterns, where the
+//! date parts of the url are ignored. Once a pattern is detected as a trap,
+//! no more urls with that pattern are added to the frontier of the job.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Datelike, Utc};
+use url::Url;
+
+use crate::metrics::{Counter, Label, PrometheusRegistry};
+
+/// Number of calendar pages with the same pattern the frontier of a job can
+/// contain before the calendar is considered endless.
+const MAX_URLS_PER_PATTERN: usize = 100;
+
+/// Number of times a path segment can be repeated in an url.
+const MAX_REPEATED_SEGMENTS: usize = 3;
+
+/// Calendars that link years into the future are endless.
+const MAX_FUTURE_YEARS: i32 = 2;
+
+const DATE_QUERY_KEYS: [&str; 4] = ["date", "year", "month", "day"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    Calendar,
+    RepeatingPath,
+}
+
+impl TrapKind {
+    const ALL: [TrapKind; 2] = [TrapKind::Calendar, TrapKind::RepeatingPath];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            TrapKind::Calendar => "calendar",
+            TrapKind::RepeatingPath => "repeating_path",
+        }
+    }
+}
+
+/// Number of urls that were dropped from the frontier for each kind of trap.
+#[derive(Default, Clone)]
+pub struct TrapMetrics {
+    counters: [Counter; 2],
+}
+
+impl TrapMetrics {
+    pub fn register(&self, registry: &mut PrometheusRegistry) {
+        let group = registry
+            .new_group(
+                "stract_crawler_trap_urls".to_string(),
+                Some("Number of urls dropped because they matched a crawler trap.".to_string()),
+            )
+            .unwrap();
+
+        for (kind, counter) in TrapKind::ALL.iter().zip(self.counters.iter()) {
+            group.register(
+                counter.clone(),
+                vec![Label {
+                    key: "kind".to_string(),
+                    val: kind.as_str().to_string(),
+                }],
+            );
+        }
+    }
+
+    fn inc(&self, kind: TrapKind) {
+        let idx = TrapKind::ALL.iter().position(|k| *k == kind).unwrap();
+        self.counters[idx].inc();
+    }
+}
+
+#[derive(Default)]
+pub struct TrapDetector {
+    seen_urls: HashSet<Url>,
+    pattern_counts: HashMap<String, usize>,
+    detected: HashMap<String, TrapKind>,
+    metrics: TrapMetrics,
+}
+
+impl TrapDetector {
+    pub fn new(metrics: TrapMetrics) -> Self {
+        Self {
+            metrics,
+            ..Default::default()
+        }
+    }
+
+    /// Check if the url is part of a trap. Urls that are part of
+    /// a trap should not be added to the frontier.
+    pub fn check(&mut self, url: &Url) -> Option<TrapKind> {
+        let kind = if repeating_path(url) {
+            Some(TrapKind::RepeatingPath)
+        } else {
+            self.check_calendar(url)
+        };
+
+        if let Some(kind) = kind {
+            self.metrics.inc(kind);
+        }
+
+        kind
+    }
+
+    fn check_calendar(&mut self, url: &Url) -> Option<TrapKind> {
+        let pattern = calendar_pattern(url)?;
+
+        if self.detected.contains_key(&pattern) {
+            return Some(TrapKind::Calendar);
+        }
+
+        let future = year(url).map_or(false, |year| year > Utc::now().year() + MAX_FUTURE_YEARS);
+
+        if !future {
+            // the same link is usually found on many pages
+            if !self.seen_urls.insert(url.clone()) {
+                return None;
+            }
+
+            let count = self.pattern_counts.entry(pattern.clone()).or_default();
+            *count += 1;
+
+            if *count <= MAX_URLS_PER_PATTERN {
+                return None;
+            }
+        }
+
+        tracing::debug!("detected calendar trap: {}", pattern);
+        self.pattern_counts.remove(&pattern);
+        self.detected.insert(pattern, TrapKind::Calendar);
+
+        Some(TrapKind::Calendar)
+    }
+
+    /// Patterns that were detected as traps.
+    pub fn detected(&self) -> impl Iterator<Item = (&str, TrapKind)> {
+        self.detected
+            .iter()
+            .map(|(pattern, kind)| (pattern.as_str(), *kind))
+    }
+}
+
+/// The host and path of calendar urls where the date segments of the path are
+/// replaced by `*`, followed by the sorted keys of the query. Urls without a date
+/// have no pattern.
+fn calendar_pattern(url: &Url) -> Option<String> {
+    year(url)?;
+
+    let mut pattern = url.host_str().unwrap_or_default().to_string();
+
+    for segment in url.path().split('/').skip(1) {
+        pattern.push('/');
+
+        if is_date_segment(segment) {
+            pattern.push('*');
+        } else {
+            pattern.push_str(segment);
+        }
+    }
+
+    let mut keys: Vec<_> = url.query_pairs().map(|(key, _)| key.to_string()).collect();
+    if !keys.is_empty() {
+        keys.sort();
+        keys.dedup();
+        pattern.push('?');
+        pattern.push_str(&keys.join("&"));
+    }
+
+    Some(pattern)
+}
+
+/// Segments such as `2024`, `05` or `2024-05-01`.
+fn is_date_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment.bytes().all(|b| b.is_ascii_digit() || b == b'-')
+        && segment.bytes().next().map_or(false, |b| b.is_ascii_digit())
+}
+
+/// A path segment that is repeated more than [`MAX_REPEATED_SEGMENTS`] times,
+/// e.g. from relative links that resolve to ever deeper urls.
+fn repeating_path(url: &Url) -> bool {
+    let path = url.path().to_ascii_lowercase();
+    let segments: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    segments
+        .iter()
+        .any(|segment| segments.iter().filter(|s| *s == segment).count() > MAX_REPEATED_SEGMENTS)
+}
+
+/// A year in a date-like part of the url, e.g. `/2024/05/` or `?date=2024-05-01`.
+fn year(url: &Url) -> Option<i32> {
+    let parse_year = |s: &str| -> Option<i32> {
+        let year = s.get(..4)?;
+        let rest = &s[4..];
+
+        if !year.bytes().all(|b| b.is_ascii_digit()) || !(rest.is_empty() || rest.starts_with('-'))
+        {
+            return None;
+        }
+
+        year.parse().ok().filter(|y| (1900..=9999).contains(y))
+    };
+
+    let from_path = url.path_segments().and_then(|segments| {
+        let segments: Vec<_> = segments.collect();
+
+        segments.iter().enumerate().find_map(|(i, segment)| {
+            if segment.len() > 4 {
+                return parse_year(segment);
+            }
+
+            // a bare year only counts when it is followed by a month
+            let month: u32 = segments.get(i + 1)?.parse().ok()?;
+            if (1..=12).contains(&month) {
+                parse_year(segment)
+            } else {
+                None
+            }
+        })
+    });
+
+    from_path.or_else(|| {
+        url.query_pairs().find_map(|(key, value)| {
+            if DATE_QUERY_KEYS.contains(&key.to_ascii_lowercase().as_str()) {
+                parse_year(&value)
+            } else {
+                None
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(
+            calendar_pattern(&url("https://example.com/blog/2024/05/post-12?b=1&a=2&a=3")),
+            Some("example.com/blog/*/*/post-12?a&b".to_string())
+        );
+        assert_eq!(
+            calendar_pattern(&url("https://example.com/events?date=2024-05-01")),
+            Some("example.com/events?date".to_string())
+        );
+        assert_eq!(
+            calendar_pattern(&url("https://example.com/item/12345")),
+            None
+        );
+    }
+
+    #[test]
+    fn pagination_and_ids_are_not_traps() {
+        let mut detector = TrapDetector::default();
+
+        for i in 0..(2 * MAX_URLS_PER_PATTERN) {
+            assert_eq!(
+                detector.check(&url(&format!("https://example.com/list?p={i}"))),
+                None
+            );
+            assert_eq!(
+                detector.check(&url(&format!("https://example.com/item/{i}"))),
+                None
+            );
+        }
+
+        assert_eq!(
+            detector.check(&url("https://example.com/list?page=100000")),
+            None
+        );
+        assert_eq!(detector.detected().count(), 0);
+    }
+
+    #[test]
+    fn repeating_path() {
+        let mut detector = TrapDetector::default();
+
+        assert_eq!(
+            detector.check(&url("https://example.com/a/b/a/b/a/b/a/b")),
+            Some(TrapKind::RepeatingPath)
+        );
+        assert_eq!(detector.check(&url("https://example.com/a/b/a/b")), None);
+    }
+
+    #[test]
+    fn future_calendar() {
+        let mut detector = TrapDetector::default();
+
+        assert_eq!(
+            detector.check(&url("https://example.com/calendar/2999/01")),
+            Some(TrapKind::Calendar)
+        );
+        assert_eq!(
+            detector.check(&url("https://example.com/events?date=2999-01-01")),
+            Some(TrapKind::Calendar)
+        );
+        assert_eq!(
+            detector.check(&url("https://example.com/blog/2010/05")),
+            None
+        );
+    }
+
+    #[test]
+    fn explosion() {
+        let metrics = TrapMetrics::default();
+        let mut detector = TrapDetector::new(metrics.clone());
+
+        for day in 0..MAX_URLS_PER_PATTERN {
+            assert_eq!(
+                detector.check(&url(&format!(
+                    "https://example.com/events?date=2020-01-{day}"
+                ))),
+                None
+            );
+        }
+
+        assert_eq!(
+            detector.check(&url("https://example.com/events?date=2020-02-01")),
+            Some(TrapKind::Calendar)
+        );
+        assert_eq!(
+            detector.check(&url("https://example.com/events?date=2020-02-02")),
+            Some(TrapKind::Calendar)
+        );
+        for _ in 0..(2 * MAX_URLS_PER_PATTERN) {
+            assert_eq!(detector.check(&url("https://example.com/other")), None);
+        }
+
+        assert_eq!(
+            detector.detected().collect::<Vec<_>>(),
+            vec![("example.com/events?date", TrapKind::Calendar)]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Operator-configured block and allow lists for the crawler.
+//!
+//! The lists are read from a toml file:
+//!
+//! ```toml
+//! [block]
+//! domains = ["example.com"]        # exact hosts
+//! suffixes = ["xxx", "example.org"] # hosts equal to or below the suffix
+//! url_patterns = ["^https?://[^/]+/wp-admin/"]
+//!
+//! [allow]
+//! suffixes = ["blog.example.org"]
+//! ```
+//!
+//! Urls that match an allow rule are crawled even if they also match a block rule,
+//! so the allow list can be used to carve out exceptions from broad block rules.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use anyhow::Result;
+use regex::RegexSet;
+use serde::Deserialize;
+use url::Url;
+
+use crate::ranking::models::reloadable::ReloadableModel;
+
+/// How often the filter file is checked for changes.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default, Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    domains: Vec<String>,
+    #[serde(default)]
+    suffixes: Vec<String>,
+    #[serde(default)]
+    url_patterns: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FilterFile {
+    #[serde(default)]
+    block: RulesFile,
+    #[serde(default)]
+    allow: RulesFile,
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim()
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+struct Rules {
+    domains: HashSet<String>,
+    suffixes: HashSet<String>,
+    url_patterns: RegexSet,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            domains: HashSet::new(),
+            suffixes: HashSet::new(),
+            url_patterns: RegexSet::empty(),
+        }
+    }
+}
+
+impl Rules {
+    fn new(file: RulesFile) -> Result<Self> {
+        Ok(Self {
+            domains: file.domains.iter().map(|d| normalize_host(d)).collect(),
+            suffixes: file.suffixes.iter().map(|s| normalize_host(s)).collect(),
+            url_patterns: RegexSet::new(&file.url_patterns)?,
+        })
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        if self.domains.contains(host) {
+            return true;
+        }
+
+        if self.suffixes.is_empty() {
+            return false;
+        }
+
+        let mut rest = host;
+        loop {
+            if self.suffixes.contains(rest) {
+                return true;
+            }
+
+            match rest.split_once('.') {
+                Some((_, tail)) => rest = tail,
+                None => return false,
+            }
+        }
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default().trim_end_matches('.');
+
+        self.matches_host(host) || self.url_patterns.is_match(url.as_str())
+    }
+}
+
+#[derive(Default)]
+pub struct UrlFilter {
+    block: Rules,
+    allow: Rules,
+}
+
+impl UrlFilter {
+    pub fn parse(s: &str) -> Result<Self> {
+        let file: FilterFile = toml::from_str(s)?;
+
+        Ok(Self {
+            block: Rules::new(file.block)?,
+            allow: Rules::new(file.allow)?,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        !self.block.matches(url) || self.allow.matches(url)
+    }
+}
+
+impl ReloadableModel for UrlFilter {
+    fn open_model(path: &Path) -> Result<Self> {
+        Self::open(path)
+    }
+
+    fn dry_run(&self) -> Result<()> {
+        // the file has already been validated when it was parsed.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn block_rules() {
+        let filter = UrlFilter::parse(
+            r#"
+            [block]
+            domains = ["Example.com"]
+            suffixes = ["*.xxx", ".bad.org"]
+            url_patterns = ["^https?://[^/]+/wp-admin/"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(!filter.is_allowed(&url("https://example.com/")));
+        assert!(filter.is_allowed(&url("https://www.example.com/")));
+
+        assert!(!filter.is_allowed(&url("https://site.xxx/")));
+        assert!(!filter.is_allowed(&url("https://a.b.xxx/page")));
+        assert!(!filter.is_allowed(&url("https://bad.org/")));
+        assert!(!filter.is_allowed(&url("https://sub.bad.org./")));
+        assert!(filter.is_allowed(&url("https://notbad.org/")));
+
+        assert!(!filter.is_allowed(&url("https://blog.com/wp-admin/login")));
+        assert!(filter.is_allowed(&url("https://blog.com/wp-content/")));
+    }
+
+    #[test]
+    fn allow_overrides_block() {
+        let filter = UrlFilter::parse(
+            r#"
+            [block]
+            suffixes = ["example.org"]
+
+            [allow]
+            domains = ["blog.example.org"]
+            url_patterns = ["^https://www\\.example\\.org/about$"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(!filter.is_allowed(&url("https://example.org/")));
+        assert!(!filter.is_allowed(&url("https://www.example.org/")));
+        assert!(filter.is_allowed(&url("https://blog.example.org/post")));
+        assert!(filter.is_allowed(&url("https://www.example.org/about")));
+    }
+
+    #[test]
+    fn empty_filter_allows_everything() {
+        let filter = UrlFilter::parse("").unwrap();
+        assert!(filter.is_allowed(&url("https://example.com/")));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(UrlFilter::parse(
+            r#"
+            [block]
+            url_patterns = ["("]
+            "#,
+        )
+        .is_err());
+    }
+}
@@ -14,24 +14,26 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
 use crate::{
-    config::{self, S3Config},
+    config::{self, S3Config, WarcOutputConfig},
     warc,
 };
 
 use super::{CrawlDatum, DatumStream, Result};
 
 /// The WarcWriter is responsible for storing the crawl datums
-/// as WARC files on S3.
+/// as gzipped WARC files on S3 or in a local folder.
 pub struct WarcWriter {
     tx: tokio::sync::mpsc::Sender<WarcWriterMessage>,
 }
 
 impl DatumStream for WarcWriter {
     async fn write(&self, crawl_datum: CrawlDatum) -> Result<()> {
-        self.tx.send(WarcWriterMessage::Crawl(crawl_datum)).await?;
+        self.tx
+            .send(WarcWriterMessage::Crawl(Box::new(crawl_datum)))
+            .await?;
 
         Ok(())
     }
@@ -46,11 +48,17 @@ impl DatumStream for WarcWriter {
 
 #[derive(Debug, Clone)]
 pub enum WarcWriterMessage {
-    Crawl(CrawlDatum),
+    Crawl(Box<CrawlDatum>),
     Finish,
 }
 
-async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
+#[derive(Debug, Clone)]
+enum Destination {
+    S3(S3Config),
+    Local(PathBuf),
+}
+
+async fn commit(writer: warc::DeduplicatedWarcWriter, destination: &Destination) {
     let filename = format!(
         "{}_{}.warc.gz",
         chrono::Utc::now().to_rfc3339(),
@@ -58,6 +66,22 @@ async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
     );
     let data = writer.finish().unwrap();
 
+    match destination {
+        Destination::S3(s3) => upload(s3, &filename, &data).await,
+        Destination::Local(folder) => {
+            let res = match tokio::fs::create_dir_all(folder).await {
+                Ok(()) => tokio::fs::write(folder.join(&filename), &data).await,
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = res {
+                tracing::error!("failed to write warc file to {:?}: {:?}", folder, err);
+            }
+        }
+    }
+}
+
+async fn upload(s3: &config::S3Config, filename: &str, data: &[u8]) {
     match s3::Bucket::new(
         &s3.bucket,
         s3::Region::Custom {
@@ -80,7 +104,7 @@ async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
             if let Err(err) = bucket
                 .put_object_with_content_type(
                     &format!("{}/{}", &s3.folder, filename),
-                    &data,
+                    data,
                     "application/warc",
                 )
                 .await
@@ -92,7 +116,11 @@ async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
     }
 }
 
-async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3: S3Config) {
+async fn writer_task(
+    mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>,
+    destination: Destination,
+    max_file_bytes: usize,
+) {
     let mut writer = warc::DeduplicatedWarcWriter::new();
 
     while let Some(message) = rx.recv().await {
@@ -110,9 +138,15 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
                             response: warc::Response {
                                 body: datum.body,
                                 payload_type: Some(datum.payload_type),
+                                last_modified: datum.last_modified,
+                                etag: datum.etag,
                             },
                             metadata: warc::Metadata {
                                 fetch_time_ms: datum.fetch_time_ms,
+                                fetched_at: Some(datum.fetched_at),
+                                not_modified: datum.status_code == 304,
+                                sitemap_lastmod: datum.sitemap_lastmod,
+                                protocol: datum.protocol,
                             },
                         };
 
@@ -123,14 +157,14 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
 
                 recv.await.unwrap();
 
-                if writer.num_bytes() > 1_000_000_000 {
-                    commit(writer, s3.clone()).await;
+                if writer.num_bytes() > max_file_bytes {
+                    commit(writer, &destination).await;
                     writer = warc::DeduplicatedWarcWriter::new();
                 }
             }
             WarcWriterMessage::Finish => {
                 if writer.num_writes() > 0 {
-                    commit(writer, s3.clone()).await;
+                    commit(writer, &destination).await;
                 }
                 break;
             }
@@ -139,11 +173,85 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
 }
 
 impl WarcWriter {
-    pub fn new(s3: S3Config) -> Self {
+    pub fn new(s3: S3Config, config: WarcOutputConfig) -> Self {
+        let destination = match config.local_path {
+            Some(path) => Destination::Local(path.into()),
+            None => Destination::S3(s3),
+        };
+
+        Self::with_destination(destination, config.max_file_size_mb as usize * 1_000_000)
+    }
+
+    fn with_destination(destination: Destination, max_file_bytes: usize) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(1);
 
-        tokio::spawn(writer_task(rx, s3));
+        tokio::spawn(writer_task(rx, destination, max_file_bytes));
 
         Self { tx }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+
+    fn datum(i: usize) -> CrawlDatum {
+        CrawlDatum {
+            url: Url::parse(&format!("https://example.com/{i}")).unwrap(),
+            status_code: 200,
+            payload_type: warc::PayloadType::Html,
+            body: format!(
+                "<html><body>page {i} {}</body></html>",
+                crate::rand_words(100)
+            ),
+            fetch_time_ms: 42,
+            fetched_at: 1_700_000_000,
+            last_modified: None,
+            etag: Some(format!("\"{i}\"")),
+            sitemap_lastmod: None,
+            protocol: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn local_files_are_rotated_and_readable() {
+        let path = crate::gen_temp_path();
+        let writer = WarcWriter::with_destination(Destination::Local(path.clone()), 1);
+
+        for i in 0..3 {
+            writer.write(datum(i)).await.unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        let mut records = Vec::new();
+        let files: Vec<_> = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 3);
+
+        for file in files {
+            assert!(file.to_str().unwrap().ends_with(".warc.gz"));
+
+            let warc = warc::WarcFile::open(&file).unwrap();
+            records.extend(warc.records().map(|record| record.unwrap()));
+        }
+
+        records.sort_by(|a, b| a.request.url.cmp(&b.request.url));
+
+        assert_eq!(records.len(), 3);
+        for (i, record) in records.into_iter().enumerate() {
+            let expected = datum(i);
+            assert_eq!(record.request.url, expected.url.as_str());
+            assert_eq!(record.response.etag, expected.etag);
+            assert_eq!(record.response.payload_type, Some(warc::PayloadType::Html));
+            assert_eq!(record.metadata.fetch_time_ms, 42);
+            assert!(record
+                .response
+                .body
+                .starts_with(&format!("<html><body>page {i} ")));
+        }
+    }
+}
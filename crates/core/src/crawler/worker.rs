@@ -14,18 +14,16 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 use anyhow::anyhow;
-use encoding_rs::{Encoding, UTF_8};
+use chrono::{DateTime, FixedOffset, Utc};
 use futures::{future::BoxFuture, FutureExt};
 use hashbrown::{HashMap, HashSet};
-use mime::Mime;
 use quick_xml::events::Event;
 use rand::seq::SliceRandom;
-use tokio_stream::StreamExt;
 
 use std::{
     collections::VecDeque,
     net::SocketAddr,
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
@@ -35,18 +33,49 @@ use crate::{
     config::CrawlerConfig,
     crawler::MAX_URL_LEN_BYTES,
     distributed::{retry_strategy::ExponentialBackoff, sonic},
-    entrypoint::crawler::router::{NewJob, RouterService},
+    entrypoint::crawler::router::{JobDone, NewJob, RouterService},
+    feed::{self, Feed, FeedKind},
+    ranking::models::reloadable::Reloadable,
+    simhash,
+    task_queue::TaskQueue,
     warc,
-    webpage::{url_ext::UrlExt, Html},
+    webpage::{
+        parse_date,
+        protocol::{self, ProtocolInfo, TlsStatus},
+        url_ext::UrlExt,
+        Html,
+    },
 };
 
 use super::{
-    reqwest_client, robots_txt::RobotsTxtManager, wander_prirotiser::WanderPrioritiser, CrawlDatum,
-    DatumStream, Domain, Error, Result, RetrieableUrl, Site, UrlResponse, WarcWriter, WeightedUrl,
-    WorkerJob,
+    body, budget,
+    favicons::{self, FaviconTask},
+    limits::FetchLimiter,
+    politeness::{self, DomainState},
+    proxy::{AssignedProxy, ProxyPool},
+    render::RenderPool,
+    reqwest_client,
+    robots_txt::RobotsTxtManager,
+    stats::{FetchMetrics, FetchStatus},
+    traps::{TrapDetector, TrapMetrics},
+    wander_prirotiser::WanderPrioritiser,
+    CrawlDatum, DatumStream, Domain, Error, FailureReason, Resolver, Result, RetrieableUrl, Site,
+    UrlFilter, UrlResponse, Validators, WarcWriter, WeightedUrl, WorkerJob, MAX_CONTENT_LENGTH,
 };
 
-const MAX_CONTENT_LENGTH: usize = 32 * 1024 * 1024; // 32 MB
+/// Number of days since the sitemap `<lastmod>` where the url
+/// gets half the priority of a url modified today.
+const SITEMAP_LASTMOD_DECAY_DAYS: f64 = 30.0;
+
+/// Max number of feeds polled for a domain in each job.
+const MAX_FEEDS_PER_JOB: usize = 8;
+
+/// Wander weight of urls found in a feed. It is far above the weight urls
+/// get from links, so newly published pages are crawled before anything else.
+const FEED_URL_WEIGHT: f64 = 1_000_000.0;
+
+/// Max number of distinct hosts resolved ahead of time when a job is received.
+const MAX_PREFETCHED_HOSTS: usize = 64;
 
 struct ProcessedUrl {
     new_urls: Vec<Url>,
@@ -56,8 +85,16 @@ struct ProcessedUrl {
 pub struct WorkerThread {
     writer: Arc<WarcWriter>,
     client: reqwest::Client,
+    proxy_pool: Option<Arc<ProxyPool>>,
+    resolver: Resolver,
     config: Arc<CrawlerConfig>,
     router_hosts: Vec<SocketAddr>,
+    trap_metrics: TrapMetrics,
+    fetch_metrics: FetchMetrics,
+    url_filter: Arc<Reloadable<UrlFilter>>,
+    limiter: Arc<FetchLimiter>,
+    render_pool: Option<Arc<RenderPool>>,
+    favicon_queue: Option<Arc<TaskQueue>>,
 }
 
 impl WorkerThread {
@@ -65,17 +102,54 @@ impl WorkerThread {
         writer: Arc<WarcWriter>,
         config: CrawlerConfig,
         router_hosts: Vec<SocketAddr>,
+        resolver: Resolver,
+        trap_metrics: TrapMetrics,
+        fetch_metrics: FetchMetrics,
+        url_filter: Arc<Reloadable<UrlFilter>>,
     ) -> Result<Self> {
-        let client = reqwest_client(&config)?;
+        let client = reqwest_client(&config, resolver.clone())?;
+        let limiter = Arc::new(FetchLimiter::new(&config));
 
         Ok(Self {
             writer,
             client,
+            proxy_pool: None,
+            resolver,
             config: Arc::new(config),
             router_hosts,
+            trap_metrics,
+            fetch_metrics,
+            url_filter,
+            limiter,
+            render_pool: None,
+            favicon_queue: None,
         })
     }
 
+    /// Share the fetch limits with the other worker threads of the process.
+    pub fn with_limiter(mut self, limiter: Arc<FetchLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Render the pages of the configured domains with the shared browser pool.
+    pub fn with_render_pool(mut self, render_pool: Option<Arc<RenderPool>>) -> Self {
+        self.render_pool = render_pool;
+        self
+    }
+
+    /// Route the requests through the proxy pool shared by all worker threads.
+    pub fn with_proxy_pool(mut self, proxy_pool: Option<Arc<ProxyPool>>) -> Self {
+        self.proxy_pool = proxy_pool;
+        self
+    }
+
+    /// Queue the favicons of the crawled hosts to be fetched in the background.
+    pub fn with_favicon_queue(mut self, favicon_queue: Option<Arc<TaskQueue>>) -> Self {
+        self.favicon_queue = favicon_queue;
+        self
+    }
+
     async fn router_conn(&self) -> Result<sonic::service::ResilientConnection<RouterService>> {
         let retry = ExponentialBackoff::from_millis(1_000).with_limit(Duration::from_secs(10));
 
@@ -89,6 +163,14 @@ impl WorkerThread {
         .await?)
     }
 
+    async fn job_done(&self, domain: Domain) -> Result<()> {
+        let conn = self.router_conn().await?;
+        conn.send_with_timeout(&JobDone { domain }, Duration::from_secs(90))
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn run(self) {
         loop {
             let conn = self.router_conn().await.unwrap();
@@ -98,13 +180,52 @@ impl WorkerThread {
 
             match res {
                 Ok(Some(job)) => {
-                    let executor = JobExecutor::new(
-                        job.into(),
-                        self.client.clone(),
-                        self.config.clone(),
-                        self.writer.clone(),
-                    );
+                    let domain = job.domain.clone();
+
+                    // proxies resolve the hosts themselves
+                    if self.proxy_pool.is_none() {
+                        let hosts: HashSet<_> = job
+                            .urls
+                            .iter()
+                            .filter_map(|url| url.url.host_str().map(|host| host.to_string()))
+                            .collect();
+
+                        self.resolver
+                            .prefetch(hosts.into_iter().take(MAX_PREFETCHED_HOSTS));
+                    }
+
+                    let executor = match &self.proxy_pool {
+                        Some(pool) => {
+                            let proxy = pool.assign(&job.domain);
+
+                            JobExecutor::new(
+                                job.into(),
+                                proxy.client().clone(),
+                                self.config.clone(),
+                                self.writer.clone(),
+                            )
+                            .with_proxy(Arc::clone(pool), proxy)
+                        }
+                        None => JobExecutor::new(
+                            job.into(),
+                            self.client.clone(),
+                            self.config.clone(),
+                            self.writer.clone(),
+                        ),
+                    }
+                    .with_trap_metrics(self.trap_metrics.clone())
+                    .with_fetch_metrics(self.fetch_metrics.clone())
+                    .with_url_filter(Arc::clone(&self.url_filter))
+                    .with_limiter(Arc::clone(&self.limiter))
+                    .with_render_pool(self.render_pool.clone())
+                    .with_favicon_queue(self.favicon_queue.clone());
+
                     executor.run().await;
+
+                    if let Err(err) = self.job_done(domain).await {
+                        // the job will be handed out again once its lease expires
+                        tracing::error!("failed to report finished job: {err}");
+                    }
                 }
                 Ok(None) => {
                     return;
@@ -118,17 +239,62 @@ impl WorkerThread {
     }
 }
 
+struct JobProxy {
+    pool: Arc<ProxyPool>,
+    current: Mutex<AssignedProxy>,
+}
+
+impl JobProxy {
+    fn client(&self) -> reqwest::Client {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .client()
+            .clone()
+    }
+
+    fn report(&self, res: &reqwest::Result<reqwest::Response>) {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+
+        match res {
+            Ok(_) => self.pool.report_success(&current),
+            Err(err) if err.is_connect() || err.is_timeout() => {
+                if self.pool.report_failure(&current) {
+                    *current = self.pool.rotate(&current);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
 pub struct JobExecutor<S: DatumStream> {
     writer: Arc<S>,
     client: reqwest::Client,
-    politeness_factor: f32,
+    proxy: Option<JobProxy>,
+    domain_state: DomainState,
     robotstxt: RobotsTxtManager,
     crawled_urls: HashSet<Url>,
     crawled_sitemaps: HashSet<Site>,
-    sitemap_urls: HashSet<Url>,
+    sitemap_urls: HashMap<Url, Option<DateTime<FixedOffset>>>,
     config: Arc<CrawlerConfig>,
     wander_prioritiser: WanderPrioritiser,
     job: WorkerJob,
+    not_modified: u64,
+    simhashes: simhash::Table,
+    traps: TrapDetector,
+    fetch_metrics: FetchMetrics,
+    url_filter: Arc<Reloadable<UrlFilter>>,
+    limiter: Arc<FetchLimiter>,
+    render_pool: Option<Arc<RenderPool>>,
+    feeds: HashSet<Feed>,
+    /// Number of near-duplicate pages found for each sibling pattern.
+    near_duplicates: HashMap<String, u64>,
+    /// Number of fetched pages that declared another url as their canonical.
+    canonical_aliases: u64,
+    favicon_queue: Option<Arc<TaskQueue>>,
+    /// Hosts whose favicon has already been queued in this job.
+    favicon_hosts: HashSet<String>,
 }
 
 impl<S: DatumStream> JobExecutor<S> {
@@ -138,31 +304,137 @@ impl<S: DatumStream> JobExecutor<S> {
         config: Arc<CrawlerConfig>,
         writer: Arc<S>,
     ) -> Self {
+        let feeds = job.feeds.iter().cloned().collect();
+        let limiter = Arc::new(FetchLimiter::new(&config));
+
         Self {
             writer,
-            politeness_factor: config.politeness_factor,
+            domain_state: DomainState::new(&config),
             robotstxt: RobotsTxtManager::new(
                 client.clone(),
                 Duration::from_secs(config.robots_txt_cache_sec),
             ),
             client,
+            proxy: None,
             crawled_urls: HashSet::new(),
             crawled_sitemaps: HashSet::new(),
-            sitemap_urls: HashSet::new(),
+            sitemap_urls: HashMap::new(),
             config,
             wander_prioritiser: WanderPrioritiser::new(),
             job,
+            not_modified: 0,
+            simhashes: simhash::Table::default(),
+            traps: TrapDetector::default(),
+            fetch_metrics: FetchMetrics::default(),
+            url_filter: Arc::new(Reloadable::default()),
+            limiter,
+            render_pool: None,
+            feeds,
+            near_duplicates: HashMap::new(),
+            canonical_aliases: 0,
+            favicon_queue: None,
+            favicon_hosts: HashSet::new(),
         }
     }
 
+    /// Count the responses of all fetched urls by their status.
+    fn with_fetch_metrics(mut self, metrics: FetchMetrics) -> Self {
+        self.fetch_metrics = metrics;
+        self
+    }
+
+    /// Never crawl urls that are blocked by the operator's block and allow lists.
+    fn with_url_filter(mut self, url_filter: Arc<Reloadable<UrlFilter>>) -> Self {
+        self.url_filter = url_filter;
+        self
+    }
+
+    /// Share the concurrency and rate limits with the other jobs of the process.
+    pub fn with_limiter(mut self, limiter: Arc<FetchLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Render the pages of the job in a headless browser if the
+    /// domain is configured to be rendered.
+    fn with_render_pool(mut self, render_pool: Option<Arc<RenderPool>>) -> Self {
+        self.render_pool = render_pool.filter(|pool| pool.should_render(&self.job.domain));
+        self
+    }
+
+    /// Queue the favicon of each host the first time one of its pages is crawled.
+    fn with_favicon_queue(mut self, favicon_queue: Option<Arc<TaskQueue>>) -> Self {
+        self.favicon_queue = favicon_queue;
+        self
+    }
+
+    fn is_blocked(&self, url: &Url) -> bool {
+        self.url_filter
+            .get()
+            .map(|filter| !filter.is_allowed(url))
+            .unwrap_or(false)
+    }
+
+    /// Count the urls that are dropped because of crawler traps.
+    fn with_trap_metrics(mut self, metrics: TrapMetrics) -> Self {
+        self.traps = TrapDetector::new(metrics);
+        self
+    }
+
+    /// Route all requests through a proxy from the pool. The proxy is
+    /// replaced if it fails while the job is running.
+    fn with_proxy(mut self, pool: Arc<ProxyPool>, proxy: AssignedProxy) -> Self {
+        self.proxy = Some(JobProxy {
+            pool,
+            current: Mutex::new(proxy),
+        });
+
+        self
+    }
+
     pub async fn run(mut self) {
         tracing::info!("Processing job: {:?}", self.job.domain);
 
         self.scheduled_urls().await;
 
         if self.job.wandering_urls > 0 {
+            self.poll_feeds().await;
             self.wander().await;
         }
+
+        if self.not_modified > 0 {
+            tracing::info!(
+                "{} urls not modified since last crawl of {:?}",
+                self.not_modified,
+                self.job.domain
+            );
+        }
+
+        for (pattern, kind) in self.traps.detected() {
+            tracing::info!(
+                "{:?} trap detected on {:?}: {}",
+                kind,
+                self.job.domain,
+                pattern
+            );
+        }
+
+        let near_duplicates: u64 = self.near_duplicates.values().sum();
+        if near_duplicates > 0 {
+            tracing::info!(
+                "skipped {} near-duplicate pages on {:?}",
+                near_duplicates,
+                self.job.domain
+            );
+        }
+
+        if self.canonical_aliases > 0 {
+            tracing::info!(
+                "{} pages on {:?} were aliases of their canonical url",
+                self.canonical_aliases,
+                self.job.domain
+            );
+        }
     }
 
     async fn scheduled_urls(&mut self) {
@@ -170,17 +442,146 @@ impl<S: DatumStream> JobExecutor<S> {
         self.process_urls(urls, true).await;
     }
 
+    /// Fetch the known feeds of the domain and put their entries at the
+    /// front of the wander frontier.
+    async fn poll_feeds(&mut self) {
+        if self.config.dry_run {
+            return;
+        }
+
+        let mut feeds: Vec<_> = self.feeds.iter().cloned().collect();
+        feeds.sort_by(|a, b| a.url.cmp(&b.url));
+
+        for feed in feeds.into_iter().take(MAX_FEEDS_PER_JOB) {
+            if self.is_blocked(&feed.url)
+                || !self
+                    .robotstxt
+                    .is_allowed(&feed.url, &self.config.user_agent.token)
+                    .await
+            {
+                continue;
+            }
+
+            let Some(body) = self.fetch_feed(&feed).await else {
+                continue;
+            };
+
+            let parsed = match feed::parse(&body, feed.kind) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    tracing::debug!("failed to parse feed ({}): {}", &feed.url, err);
+                    continue;
+                }
+            };
+
+            for url in parsed.links {
+                let url = url.canonicalize();
+
+                if Domain::from(&url) != self.job.domain
+                    || self.crawled_urls.contains(&url)
+                    || url.as_str().len() > MAX_URL_LEN_BYTES
+                    || budget::is_too_deep(&url, self.job.max_depth)
+                    || self.is_blocked(&url)
+                {
+                    continue;
+                }
+
+                self.wander_prioritiser.inc(url, FEED_URL_WEIGHT);
+            }
+        }
+    }
+
+    /// Fetch a feed and store it, so the feed indexer can find it
+    /// for the next crawl plan.
+    async fn fetch_feed(&mut self, feed: &Feed) -> Option<String> {
+        let wait = self.domain_state.wait_before_request();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        let permit = self.limiter.acquire(&self.job.domain).await;
+        let fetched_at = unix_now();
+        let start = Instant::now();
+        let res = self.fetch(feed.url.clone(), &Validators::default()).await;
+        let fetch_time = start.elapsed();
+        drop(permit);
+
+        let delay = self.domain_state.delay_after_request(fetch_time, None);
+        tokio::time::sleep(delay).await;
+
+        let res = res.ok()?;
+
+        if res.status() != reqwest::StatusCode::OK {
+            return None;
+        }
+
+        let protocol = protocol_info(&res, false, false);
+        let body = body::read_body(res, MAX_CONTENT_LENGTH).await.ok()?;
+
+        self.save_datum(CrawlDatum {
+            url: feed.url.clone(),
+            status_code: 200,
+            payload_type: match feed.kind {
+                FeedKind::Rss => warc::PayloadType::Rss,
+                FeedKind::Atom => warc::PayloadType::Atom,
+            },
+            body: body.clone(),
+            fetch_time_ms: fetch_time.as_millis() as u64,
+            fetched_at,
+            last_modified: None,
+            etag: None,
+            sitemap_lastmod: None,
+            protocol,
+        })
+        .await;
+
+        Some(body)
+    }
+
     async fn wander(&mut self) {
         let mut urls: Vec<(Url, f64)> = self
             .wander_prioritiser
             .top_and_clear(self.job.wandering_urls as usize)
             .into_iter()
-            .chain(self.sitemap_urls.drain().map(|url| (url.clone(), 0.0)))
+            .chain(
+                self.sitemap_urls
+                    .iter()
+                    .map(|(url, lastmod)| (url.clone(), sitemap_priority(*lastmod, Utc::now()))),
+            )
             .filter(|(url, _)| !self.crawled_urls.contains(url))
             .filter(|(url, _)| self.job.domain == Domain::from(url))
+            .filter(|(url, _)| !self.is_blocked(url))
             .filter(|(_, score)| score.is_finite())
+            .map(|(url, score)| {
+                // urls that look like pages that turned out to be near-duplicates
+                // (e.g. other facets or pages of the same listing) are less
+                // likely to have unique content.
+                let duplicates = self
+                    .near_duplicates
+                    .get(&sibling_pattern(&url))
+                    .copied()
+                    .unwrap_or_default();
+
+                (url, score / (1 + duplicates) as f64)
+            })
             .collect();
 
+        if !self.config.dry_run {
+            let mut allowed = Vec::with_capacity(urls.len());
+
+            for (url, score) in urls {
+                if self
+                    .robotstxt
+                    .is_allowed(&url, &self.config.user_agent.token)
+                    .await
+                {
+                    allowed.push((url, score));
+                }
+            }
+
+            urls = allowed;
+        }
+
         urls.sort_by(|(a, _), (b, _)| a.cmp(b));
         urls.dedup_by(|(a, _), (b, _)| a == b);
 
@@ -190,7 +591,11 @@ impl<S: DatumStream> JobExecutor<S> {
             .into_iter()
             .take(self.job.wandering_urls as usize)
             .map(|(url, _)| url)
-            .map(|url| WeightedUrl { url, weight: 0.0 })
+            .map(|url| WeightedUrl {
+                url,
+                weight: 0.0,
+                validators: Validators::default(),
+            })
             .map(RetrieableUrl::from)
             .collect();
 
@@ -211,18 +616,42 @@ impl<S: DatumStream> JobExecutor<S> {
                 continue;
             }
 
+            if let Some(not_before) = retryable_url.not_before {
+                let now = Instant::now();
+
+                if not_before > now {
+                    // try the other urls first while the failed url is backing off
+                    if urls
+                        .iter()
+                        .any(|url| url.not_before.map_or(true, |t| t <= now))
+                    {
+                        urls.push_back(retryable_url);
+                        continue;
+                    }
+
+                    tokio::time::sleep(not_before - now).await;
+                }
+            }
+
             if retryable_url.url().host_str().is_none()
                 || !matches!(retryable_url.url().scheme(), "http" | "https")
             {
                 continue;
             }
 
+            // the filter may have been reloaded since the url was scheduled.
+            if self.is_blocked(retryable_url.url()) {
+                continue;
+            }
+
             if !self.config.dry_run
                 && !self
                     .robotstxt
                     .is_allowed(retryable_url.url(), &self.config.user_agent.token)
                     .await
             {
+                self.fetch_metrics
+                    .observe_failure(FailureReason::RobotsDenied);
                 continue;
             }
 
@@ -242,7 +671,21 @@ impl<S: DatumStream> JobExecutor<S> {
                 }
             }
 
-            let res = self.process_url(retryable_url.url().clone()).await;
+            let robots_crawl_delay = if self.config.dry_run {
+                None
+            } else {
+                self.robotstxt
+                    .crawl_delay(retryable_url.url(), &self.config.user_agent.token)
+                    .await
+            };
+
+            let res = self
+                .process_url(
+                    retryable_url.url().clone(),
+                    &retryable_url.weighted_url.validators,
+                    robots_crawl_delay,
+                )
+                .await;
 
             match res.response {
                 UrlResponse::Success { url: _ } => {
@@ -257,87 +700,135 @@ impl<S: DatumStream> JobExecutor<S> {
                             continue;
                         }
 
+                        if budget::is_too_deep(&new_url, self.job.max_depth) {
+                            continue;
+                        }
+
+                        if self.is_blocked(&new_url) {
+                            continue;
+                        }
+
+                        if self.traps.check(&new_url).is_some() {
+                            continue;
+                        }
+
                         self.wander_prioritiser.inc(new_url, weight);
                     }
                 }
                 UrlResponse::Failed {
                     url: _,
-                    status_code,
+                    status_code: _,
+                    reason,
                 } => {
-                    if matches!(status_code, Some(429)) {
+                    self.fetch_metrics.observe_failure(reason);
+
+                    if reason.is_transient() {
+                        let backoff = reason.retry_backoff(
+                            retryable_url.retries,
+                            Duration::from_millis(self.config.limits.url_retry_backoff_ms),
+                        );
+
                         let mut retryable_url = retryable_url;
                         retryable_url.retries += 1;
+                        retryable_url.not_before = Some(Instant::now() + backoff);
                         urls.push_back(retryable_url);
                         continue;
                     }
                 }
                 UrlResponse::Redirected { url: _, new_url: _ } => {}
+                UrlResponse::NotModified { url: _ } => {}
+                UrlResponse::NearDuplicate { url: _ } => {}
             }
+
+            self.crawled_urls.insert(retryable_url.url().clone());
         }
     }
 
-    async fn process_url(&mut self, url: Url) -> ProcessedUrl {
-        let fetch = self.crawl_url(url.clone()).await;
+    async fn process_url(
+        &mut self,
+        url: Url,
+        validators: &Validators,
+        robots_crawl_delay: Option<Duration>,
+    ) -> ProcessedUrl {
+        let fetch = self
+            .crawl_url(url.clone(), validators, robots_crawl_delay)
+            .await;
+
+        self.fetch_metrics.observe(match &fetch {
+            Ok(datum) => FetchStatus::from_status_code(datum.status_code),
+            Err(_) => FetchStatus::Failed,
+        });
 
         match fetch {
-            Ok(datum) => {
+            Ok(datum) if datum.status_code == 304 => {
+                // the page is unchanged since the validators were recorded, so there
+                // are no links to extract. the response is still stored so the indexer
+                // learns that the page was revisited.
+                self.not_modified += 1;
+                self.save_datum(datum).await;
+
+                ProcessedUrl {
+                    new_urls: Vec::new(),
+                    response: UrlResponse::NotModified { url },
+                }
+            }
+            Ok(mut datum) => {
                 if matches!(datum.status_code, 200 | 301 | 302) {
                     if datum.status_code == 200 {
-                        self.save_datum(datum.clone()).await;
-
-                        match Html::parse(&datum.body, datum.url.as_str()) {
-                            Ok(html) => {
-                                let new_urls = html
-                                    .all_links()
-                                    .into_iter()
-                                    .map(|link| link.destination)
-                                    .filter(|url| url.as_str().len() <= MAX_URL_LEN_BYTES)
-                                    .filter(|url| {
-                                        !url.path().ends_with(".pdf")
-                                            && !url.path().ends_with(".jpg")
-                                            && !url.path().ends_with(".zip")
-                                            && !url.path().ends_with(".png")
-                                            && !url.path().ends_with(".css")
-                                            && !url.path().ends_with(".js")
-                                            && !url.path().ends_with(".json")
-                                            && !url.path().ends_with(".jsonp")
-                                            && !url.path().ends_with(".woff2")
-                                            && !url.path().ends_with(".woff")
-                                            && !url.path().ends_with(".ttf")
-                                            && !url.path().ends_with(".svg")
-                                            && !url.path().ends_with(".gif")
-                                            && !url.path().ends_with(".jpeg")
-                                            && !url.path().ends_with(".ico")
-                                            && !url.path().ends_with(".mp4")
-                                            && !url.path().ends_with(".mp3")
-                                            && !url.path().ends_with(".avi")
-                                            && !url.path().ends_with(".mov")
-                                            && !url.path().ends_with(".mpeg")
-                                            && !url.path().ends_with(".webm")
-                                            && !url.path().ends_with(".wav")
-                                            && !url.path().ends_with(".flac")
-                                            && !url.path().ends_with(".aac")
-                                            && !url.path().ends_with(".ogg")
-                                            && !url.path().ends_with(".m4a")
-                                            && !url.path().ends_with(".m4v")
-                                    })
-                                    .collect();
-
-                                let url_res = UrlResponse::Success { url: datum.url };
-
-                                ProcessedUrl {
-                                    new_urls,
-                                    response: url_res,
+                        datum.sitemap_lastmod = self
+                            .sitemap_urls
+                            .get(&url)
+                            .or_else(|| self.sitemap_urls.get(&datum.url))
+                            .copied()
+                            .flatten()
+                            .map(|lastmod| lastmod.to_rfc3339());
+
+                        // the parsed page is not `Send`, so everything we need from it
+                        // must be extracted before the datum is saved.
+                        let processed = {
+                            let html = Html::parse(&datum.body, datum.url.as_str());
+
+                            if let Ok(html) = &html {
+                                if self.is_near_duplicate(html) {
+                                    tracing::debug!("near-duplicate page: {}", &url);
+
+                                    return ProcessedUrl {
+                                        new_urls: Vec::new(),
+                                        response: UrlResponse::NearDuplicate { url },
+                                    };
                                 }
                             }
-                            Err(_) => ProcessedUrl {
-                                new_urls: Vec::new(),
-                                response: UrlResponse::Failed {
-                                    url,
-                                    status_code: None,
+
+                            match html {
+                                Ok(html) => {
+                                    let favicon = html.favicon().map(|favicon| favicon.link);
+                                    self.queue_favicon(&datum.url, favicon);
+
+                                    let new_urls = self.outgoing_urls(&html, &url);
+
+                                    let url_res = UrlResponse::Success {
+                                        url: datum.url.clone(),
+                                    };
+
+                                    ProcessedUrl {
+                                        new_urls,
+                                        response: url_res,
+                                    }
+                                }
+                                Err(_) => ProcessedUrl {
+                                    new_urls: Vec::new(),
+                                    response: UrlResponse::Failed {
+                                        url,
+                                        status_code: None,
+                                        reason: FailureReason::Parse,
+                                    },
                                 },
-                            },
-                        }
+                            }
+                        };
+
+                        self.save_datum(datum).await;
+
+                        processed
                     } else {
                         let url_res = UrlResponse::Redirected {
                             url,
@@ -350,26 +841,13 @@ impl<S: DatumStream> JobExecutor<S> {
                         }
                     }
                 } else {
-                    if datum.status_code == 429 {
-                        self.politeness_factor *= 2.0;
-
-                        if self.politeness_factor > self.config.max_politeness_factor {
-                            self.politeness_factor = self.config.max_politeness_factor;
-                        }
-
-                        tracing::warn!(
-                            "politeness factor increased to {} for {}",
-                            self.politeness_factor,
-                            &url
-                        );
-                    }
-
                     tracing::debug!("failed to fetch url ({}): {}", &url, datum.status_code);
                     ProcessedUrl {
                         new_urls: Vec::new(),
                         response: UrlResponse::Failed {
                             url,
                             status_code: Some(datum.status_code),
+                            reason: FailureReason::Status(datum.status_code),
                         },
                     }
                 }
@@ -382,70 +860,232 @@ impl<S: DatumStream> JobExecutor<S> {
                     response: UrlResponse::Failed {
                         url,
                         status_code: None,
+                        reason: FailureReason::from_error(&err),
                     },
                 }
             }
         }
     }
 
+    /// The urls to follow from a fetched page. Links and feeds are only taken
+    /// from pages that allow it.
+    fn outgoing_urls(&mut self, html: &Html, url: &Url) -> Vec<Url> {
+        if let Some(canonical) = html.canonical_url() {
+            if canonical != *url && Domain::from(&canonical) == self.job.domain {
+                // the page is a copy of its canonical url,
+                // so that doesn't need to be fetched as well.
+                self.crawled_urls.insert(canonical);
+                self.canonical_aliases += 1;
+            }
+        }
+
+        if html.is_no_follow() {
+            return Vec::new();
+        }
+
+        let domain = &self.job.domain;
+        self.feeds.extend(
+            html.feeds()
+                .into_iter()
+                .filter(|feed| Domain::from(&feed.url) == *domain),
+        );
+
+        html.all_links()
+            .into_iter()
+            .map(|link| link.destination.canonicalize())
+            .filter(|url| url.as_str().len() <= MAX_URL_LEN_BYTES)
+            .filter(|url| {
+                !url.path().ends_with(".pdf")
+                    && !url.path().ends_with(".jpg")
+                    && !url.path().ends_with(".zip")
+                    && !url.path().ends_with(".png")
+                    && !url.path().ends_with(".css")
+                    && !url.path().ends_with(".js")
+                    && !url.path().ends_with(".json")
+                    && !url.path().ends_with(".jsonp")
+                    && !url.path().ends_with(".woff2")
+                    && !url.path().ends_with(".woff")
+                    && !url.path().ends_with(".ttf")
+                    && !url.path().ends_with(".svg")
+                    && !url.path().ends_with(".gif")
+                    && !url.path().ends_with(".jpeg")
+                    && !url.path().ends_with(".ico")
+                    && !url.path().ends_with(".mp4")
+                    && !url.path().ends_with(".mp3")
+                    && !url.path().ends_with(".avi")
+                    && !url.path().ends_with(".mov")
+                    && !url.path().ends_with(".mpeg")
+                    && !url.path().ends_with(".webm")
+                    && !url.path().ends_with(".wav")
+                    && !url.path().ends_with(".flac")
+                    && !url.path().ends_with(".aac")
+                    && !url.path().ends_with(".ogg")
+                    && !url.path().ends_with(".m4a")
+                    && !url.path().ends_with(".m4v")
+            })
+            .collect()
+    }
+
+    /// Near-duplicates of pages already crawled in this job are not stored,
+    /// as they would only take up space in the index.
+    fn is_near_duplicate(&mut self, html: &Html) -> bool {
+        let hash = simhash::hash(html.clean_text().map(|s| s.as_str()).unwrap_or_default());
+
+        // pages without text all get the same hash
+        if hash == 0 {
+            return false;
+        }
+
+        if self.simhashes.contains(&hash) {
+            *self
+                .near_duplicates
+                .entry(sibling_pattern(html.url()))
+                .or_default() += 1;
+
+            return true;
+        }
+
+        self.simhashes.insert(hash);
+
+        false
+    }
+
+    /// Queue the favicon of the host of `url` to be fetched, unless it is already
+    /// queued by this job. Hosts that don't link to a favicon on their own site
+    /// are tried at `/favicon.ico`.
+    fn queue_favicon(&mut self, url: &Url, favicon: Option<Url>) {
+        let Some(queue) = self.favicon_queue.clone() else {
+            return;
+        };
+
+        if self.config.dry_run {
+            return;
+        }
+
+        let Some(host) = url.normalized_host().map(|host| host.to_string()) else {
+            return;
+        };
+
+        if !self.favicon_hosts.insert(host.clone()) {
+            return;
+        }
+
+        let Some(url) = favicon
+            .filter(|favicon| matches!(favicon.scheme(), "http" | "https"))
+            .filter(|favicon| favicons::is_same_site(&host, favicon))
+            .or_else(|| url.join("/favicon.ico").ok())
+        else {
+            return;
+        };
+
+        if let Err(err) = queue.push(favicons::QUEUE, &FaviconTask { host, url }) {
+            tracing::error!("failed to queue favicon: {}", err);
+        }
+    }
+
     async fn save_datum(&self, datum: CrawlDatum) {
-        if datum.status_code != 200 {
+        if !matches!(datum.status_code, 200 | 304) {
             return;
         }
 
         self.writer.write(datum).await.ok();
     }
 
-    async fn fetch(&self, url: Url) -> Result<reqwest::Response> {
+    async fn fetch(&self, url: Url, validators: &Validators) -> Result<reqwest::Response> {
         if self.config.dry_run {
             tracing::debug!("dry run: {}", url);
             return Err(Error::FetchFailed(reqwest::StatusCode::IM_A_TEAPOT).into());
         }
 
-        self.client
-            .get(url.to_string())
-            .send()
-            .await
-            .map_err(|e| e.into())
+        // timeouts and connection errors are retried later in the job
+        // (see `FailureReason::is_transient`), without holding the fetch permit.
+        self.send(&url, validators).await.map_err(|e| e.into())
+    }
+
+    async fn send(&self, url: &Url, validators: &Validators) -> reqwest::Result<reqwest::Response> {
+        match &self.proxy {
+            Some(proxy) => {
+                let res = conditional(proxy.client().get(url.to_string()), validators)
+                    .send()
+                    .await;
+                proxy.report(&res);
+
+                res
+            }
+            None => {
+                conditional(self.client.get(url.to_string()), validators)
+                    .send()
+                    .await
+            }
+        }
     }
 
-    async fn crawl_url(&self, url: Url) -> Result<CrawlDatum> {
+    async fn crawl_url(
+        &mut self,
+        url: Url,
+        validators: &Validators,
+        robots_crawl_delay: Option<Duration>,
+    ) -> Result<CrawlDatum> {
+        let wait = self.domain_state.wait_before_request();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        let permit = self.limiter.acquire(&self.job.domain).await;
+        let fetched_at = unix_now();
         let start = Instant::now();
 
+        let mut fell_back_to_http = false;
+        let mut tls_failed = false;
+
         let res = if url.scheme() == "http" {
             let mut https = url.clone();
             https
                 .set_scheme("https")
                 .map_err(|_| anyhow!("set scheme on url failed"))?;
 
-            match self.fetch(https).await {
+            match self.fetch(https, validators).await {
                 Ok(res) => Ok(res),
-                Err(_) => {
+                Err(err) => {
+                    fell_back_to_http = true;
+                    tls_failed = FailureReason::from_error(&err) == FailureReason::Tls;
+
                     tokio::time::sleep(Duration::from_millis(self.config.min_crawl_delay_ms)).await;
-                    self.fetch(url.clone()).await
+                    self.fetch(url.clone(), validators).await
                 }
             }
         } else {
-            self.fetch(url.clone()).await
+            self.fetch(url.clone(), validators).await
         };
 
         let fetch_time = start.elapsed();
-
-        let mut delay = fetch_time;
-
-        if delay < Duration::from_millis(self.config.min_crawl_delay_ms) {
-            delay = Duration::from_millis(self.config.min_crawl_delay_ms);
-        }
-
-        delay = delay.mul_f32(self.politeness_factor);
-
-        if delay > Duration::from_millis(self.config.max_crawl_delay_ms) {
-            delay = Duration::from_millis(self.config.max_crawl_delay_ms);
+        drop(permit);
+
+        if let Ok(res) = &res {
+            match res.status() {
+                reqwest::StatusCode::TOO_MANY_REQUESTS
+                | reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+                    self.domain_state
+                        .on_throttled(politeness::retry_after(res.headers()));
+
+                    tracing::warn!(
+                        "politeness factor increased to {} for {}",
+                        self.domain_state.politeness_factor(),
+                        &url
+                    );
+                }
+                status if status.is_success() => self.domain_state.on_success(),
+                _ => {}
+            }
         }
 
+        let delay = self
+            .domain_state
+            .delay_after_request(fetch_time, robots_crawl_delay);
         tokio::time::sleep(delay).await;
 
         let res = res?;
+        let protocol = protocol_info(&res, fell_back_to_http, tls_failed);
 
         let headers: HashMap<_, _> = res
             .headers()
@@ -467,16 +1107,37 @@ impl<S: DatumStream> JobExecutor<S> {
             }
         }
 
+        let status_code = res.status().as_u16();
+        let last_modified = headers.get("last-modified").cloned();
+        let etag = headers.get("etag").cloned();
+
+        // a not modified response has no body and a throttled response is
+        // retried later, so they are accepted regardless of their content type.
+        if matches!(status_code, 304 | 429 | 503) {
+            return Ok(CrawlDatum {
+                url,
+                status_code,
+                payload_type: warc::PayloadType::Html,
+                body: String::new(),
+                fetch_time_ms: fetch_time.as_millis() as u64,
+                fetched_at,
+                last_modified,
+                etag,
+                sitemap_lastmod: None,
+                protocol,
+            });
+        }
+
         // check if content type is html
         let payload_type = match headers.get("content-type") {
-            Some(ct) if ct.contains("text/html") => warc::PayloadType::Html,
+            Some(ct) if ct.contains("text/html") || ct.contains("application/xhtml") => {
+                warc::PayloadType::Html
+            }
             Some(ct) if ct.contains("application/rss") => warc::PayloadType::Rss,
             Some(ct) if ct.contains("application/atom") => warc::PayloadType::Atom,
             ct => return Err(Error::InvalidContentType(format!("{ct:?}")).into()),
         };
 
-        let status_code = res.status().as_u16();
-
         if status_code == 301 || status_code == 302 {
             let location = res
                 .headers()
@@ -495,64 +1156,88 @@ impl<S: DatumStream> JobExecutor<S> {
                 payload_type,
                 body: String::new(),
                 fetch_time_ms: fetch_time.as_millis() as u64,
+                fetched_at,
+                last_modified,
+                etag,
+                sitemap_lastmod: None,
+                protocol,
             });
         }
 
         let res_url = res.url().clone();
-
-        let content_type = res
-            .headers()
-            .get(reqwest::header::CONTENT_TYPE)
-            .and_then(|value| value.to_str().ok())
-            .and_then(|value| value.parse::<Mime>().ok());
-        let encoding_name = content_type
-            .as_ref()
-            .and_then(|mime| mime.get_param("charset").map(|charset| charset.as_str()))
-            .unwrap_or("utf-8");
-        let encoding = Encoding::for_label(encoding_name.as_bytes()).unwrap_or(UTF_8);
-
-        let mut bytes = Vec::new();
-
-        let mut stream = res.bytes_stream();
-        while let Some(b) = stream.next().await {
-            if b.is_err() {
-                return Err(Error::ContentTooLarge.into());
-            }
-
-            let b = b.unwrap();
-
-            bytes.extend_from_slice(&b);
-
-            if bytes.len() > MAX_CONTENT_LENGTH {
-                return Err(Error::ContentTooLarge.into());
+        let mut body = body::read_body(res, MAX_CONTENT_LENGTH).await?;
+
+        if self.render_pool.is_some()
+            && status_code == 200
+            && payload_type == warc::PayloadType::Html
+        {
+            match self.render(&res_url, robots_crawl_delay).await {
+                Ok(rendered) => body = rendered,
+                Err(err) => tracing::warn!("failed to render {}: {}", res_url, err),
             }
         }
 
-        let (text, _, _) = encoding.decode(&bytes);
-        let body = text.to_string();
-
         Ok(CrawlDatum {
             url: res_url,
             status_code,
             body,
             payload_type,
             fetch_time_ms: fetch_time.as_millis() as u64,
+            fetched_at,
+            last_modified,
+            etag,
+            sitemap_lastmod: None,
+            protocol,
         })
     }
 
+    /// The browser sends its own request for the page, so it is subject to the
+    /// same url filter, robots.txt and fetch limits as the request of the crawler.
+    async fn render(&mut self, url: &Url, robots_crawl_delay: Option<Duration>) -> Result<String> {
+        let render_pool = self
+            .render_pool
+            .clone()
+            .ok_or_else(|| anyhow!("no render pool"))?;
+
+        if self.is_blocked(url) {
+            return Err(anyhow!("url is blocked"));
+        }
+
+        if !self
+            .robotstxt
+            .is_allowed(url, &self.config.user_agent.token)
+            .await
+        {
+            return Err(anyhow!("url is disallowed by robots.txt"));
+        }
+
+        let permit = self.limiter.acquire(&self.job.domain).await;
+        let start = Instant::now();
+        let res = render_pool.render(url).await;
+        let render_time = start.elapsed();
+        drop(permit);
+
+        let delay = self
+            .domain_state
+            .delay_after_request(render_time, robots_crawl_delay);
+        tokio::time::sleep(delay).await;
+
+        res
+    }
+
     fn urls_from_sitemap(
         &self,
         sitemap: Url,
         depth: usize,
         max_depth: usize,
-    ) -> BoxFuture<'_, Vec<Url>> {
+    ) -> BoxFuture<'_, Vec<(Url, Option<DateTime<FixedOffset>>)>> {
         async move {
             if depth == max_depth {
                 return vec![];
             }
 
             // fetch url
-            let res = self.fetch(sitemap).await;
+            let res = self.fetch(sitemap, &Validators::default()).await;
 
             if res.is_err() {
                 return vec![];
@@ -563,7 +1248,7 @@ impl<S: DatumStream> JobExecutor<S> {
                 return vec![];
             }
 
-            let body = res.text().await;
+            let body = body::read_body(res, MAX_CONTENT_LENGTH).await;
 
             if body.is_err() {
                 return vec![];
@@ -578,8 +1263,8 @@ impl<S: DatumStream> JobExecutor<S> {
 
             for entry in entries {
                 match entry {
-                    SitemapEntry::Url(url) => {
-                        urls.push(url);
+                    SitemapEntry::Url { url, lastmod } => {
+                        urls.push((url.canonicalize(), lastmod));
                     }
                     SitemapEntry::Sitemap(url) => {
                         tokio::time::sleep(Duration::from_millis(self.config.min_crawl_delay_ms))
@@ -595,9 +1280,98 @@ impl<S: DatumStream> JobExecutor<S> {
     }
 }
 
+/// Urls with the same host and path, ignoring the query and numeric path
+/// segments, are siblings. Faceted and paginated listings are siblings.
+fn sibling_pattern(url: &Url) -> String {
+    let path = url
+        .path()
+        .split('/')
+        .map(|segment| {
+            if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+                "*"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    format!("{}{}", url.host_str().unwrap_or_default(), path)
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
+/// Adds the validators from the previous fetch of the url to the request,
+/// so the server can answer with 304 Not Modified if the page is unchanged.
+fn conditional(
+    request: reqwest::RequestBuilder,
+    validators: &Validators,
+) -> reqwest::RequestBuilder {
+    let mut request = request;
+
+    if let Some(etag) = &validators.etag {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    request
+}
+
+/// How the response was served. `fell_back_to_http` is set when the https
+/// request for a http url failed so the url was fetched over plain http,
+/// and `tls_failed` when that https request failed during the handshake.
+fn protocol_info(
+    res: &reqwest::Response,
+    fell_back_to_http: bool,
+    tls_failed: bool,
+) -> ProtocolInfo {
+    let https = res.url().scheme() == "https";
+
+    let tls = if https {
+        TlsStatus::Valid
+    } else if tls_failed {
+        TlsStatus::Invalid
+    } else {
+        TlsStatus::None
+    };
+
+    let tls_issuer = res
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .and_then(protocol::certificate_issuer);
+
+    ProtocolInfo {
+        http_version: Some(format!("{:?}", res.version())),
+        tls,
+        tls_issuer,
+        redirected_to_https: https && fell_back_to_http,
+    }
+}
+
+/// Urls from the sitemap that were recently modified are more likely to
+/// have changed since they were last crawled, so they are crawled first.
+fn sitemap_priority(lastmod: Option<DateTime<FixedOffset>>, now: DateTime<Utc>) -> f64 {
+    match lastmod {
+        Some(lastmod) => {
+            let days = (now - lastmod.with_timezone(&Utc)).num_days().max(0) as f64;
+            1.0 / (1.0 + days / SITEMAP_LASTMOD_DECAY_DAYS)
+        }
+        None => 0.0,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum SitemapEntry {
-    Url(Url),
+    Url {
+        url: Url,
+        lastmod: Option<DateTime<FixedOffset>>,
+    },
     Sitemap(Url),
 }
 
@@ -609,6 +1383,10 @@ fn parse_sitemap(s: &str) -> Vec<SitemapEntry> {
     let mut in_sitemap = false;
     let mut in_url = false;
     let mut in_loc = false;
+    let mut in_lastmod = false;
+
+    let mut url = None;
+    let mut lastmod = None;
 
     loop {
         match reader.read_event() {
@@ -619,6 +1397,8 @@ fn parse_sitemap(s: &str) -> Vec<SitemapEntry> {
                     in_url = true;
                 } else if e.name().as_ref() == b"loc" {
                     in_loc = true;
+                } else if e.name().as_ref() == b"lastmod" {
+                    in_lastmod = true;
                 }
             }
             Ok(Event::End(ref e)) => {
@@ -626,8 +1406,20 @@ fn parse_sitemap(s: &str) -> Vec<SitemapEntry> {
                     in_sitemap = false;
                 } else if e.name().as_ref() == b"url" {
                     in_url = false;
+
+                    // <lastmod> can come before or after <loc>
+                    if let Some(url) = url.take() {
+                        res.push(SitemapEntry::Url {
+                            url,
+                            lastmod: lastmod.take(),
+                        });
+                    }
+
+                    lastmod = None;
                 } else if e.name().as_ref() == b"loc" {
                     in_loc = false;
+                } else if e.name().as_ref() == b"lastmod" {
+                    in_lastmod = false;
                 }
             }
             Ok(Event::Text(e)) => {
@@ -636,9 +1428,9 @@ fn parse_sitemap(s: &str) -> Vec<SitemapEntry> {
                         res.push(SitemapEntry::Sitemap(url));
                     }
                 } else if in_url && in_loc {
-                    if let Ok(url) = Url::parse(&e.unescape().unwrap()) {
-                        res.push(SitemapEntry::Url(url));
-                    }
+                    url = Url::parse(&e.unescape().unwrap()).ok();
+                } else if in_url && in_lastmod {
+                    lastmod = parse_date(&e.unescape().unwrap());
                 }
             }
             Ok(Event::Eof) => break,
@@ -655,6 +1447,73 @@ fn parse_sitemap(s: &str) -> Vec<SitemapEntry> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn sitemap_priority_prefers_recent() {
+        let now = Utc::now();
+        let recent = (now - chrono::Duration::days(1)).into();
+        let old = (now - chrono::Duration::days(365)).into();
+
+        assert!(sitemap_priority(Some(recent), now) > sitemap_priority(Some(old), now));
+        assert!(sitemap_priority(Some(old), now) > sitemap_priority(None, now));
+        assert_eq!(sitemap_priority(Some(now.into()), now), 1.0);
+    }
+
+    #[test]
+    fn sibling_patterns() {
+        let pattern = |url: &str| sibling_pattern(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            pattern("https://example.com/shoes?color=red&size=42"),
+            "example.com/shoes"
+        );
+        assert_eq!(
+            pattern("https://example.com/blog/page/2"),
+            pattern("https://example.com/blog/page/3")
+        );
+        assert_ne!(
+            pattern("https://example.com/blog/page/2"),
+            pattern("https://example.com/news/page/2")
+        );
+        assert_eq!(pattern("https://example.com/"), "example.com/");
+    }
+
+    #[test]
+    fn conditional_request_headers() {
+        let client = reqwest::Client::new();
+
+        let request = conditional(client.get("https://example.com"), &Validators::default())
+            .build()
+            .unwrap();
+        assert!(request.headers().is_empty());
+
+        let request = conditional(
+            client.get("https://example.com"),
+            &Validators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: Some("Wed, 22 Jun 2022 19:37:34 GMT".to_string()),
+            },
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            request
+                .headers()
+                .get(reqwest::header::IF_NONE_MATCH)
+                .unwrap(),
+            "\"abc\""
+        );
+        assert_eq!(
+            request
+                .headers()
+                .get(reqwest::header::IF_MODIFIED_SINCE)
+                .unwrap(),
+            "Wed, 22 Jun 2022 19:37:34 GMT"
+        );
+    }
+
     #[test]
     fn parse_sitemap() {
         let dr = r#"<sitemapindex>
@@ -719,29 +1578,35 @@ mod tests {
         </urlset>"#;
 
         let entries = super::parse_sitemap(dr);
+        let lastmod =
+            chrono::DateTime::parse_from_rfc3339("2023-10-18T05:40:04.7435930+00:00").unwrap();
         assert_eq!(
             entries,
             vec![
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/serie/sleepover_6382"
+                super::SitemapEntry::Url {
+                    url: "https://www.dr.dk/drtv/serie/sleepover_6382"
                         .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/saeson/sleepover_9673"
+                        .unwrap(),
+                    lastmod: Some(lastmod),
+                },
+                super::SitemapEntry::Url {
+                    url: "https://www.dr.dk/drtv/saeson/sleepover_9673"
                         .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/episode/sleepover_-zoologisk-museum_52239"
+                        .unwrap(),
+                    lastmod: Some(lastmod),
+                },
+                super::SitemapEntry::Url {
+                    url: "https://www.dr.dk/drtv/episode/sleepover_-zoologisk-museum_52239"
                         .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/episode/sleepover_-koebenhavns-raadhus_52252"
+                        .unwrap(),
+                    lastmod: Some(lastmod),
+                },
+                super::SitemapEntry::Url {
+                    url: "https://www.dr.dk/drtv/episode/sleepover_-koebenhavns-raadhus_52252"
                         .parse()
-                        .unwrap()
-                ),
+                        .unwrap(),
+                    lastmod: Some(lastmod),
+                },
             ]
         );
     }
@@ -0,0 +1,603 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tracks the query load of the search shards and their replicas.
+//!
+//! The QPS and latency of each replica are used to route requests to the least
+//! loaded replica of a shard. Shards whose traffic or latency is far above the
+//! rest of the cluster are reported as hotspots, since uneven document routing
+//! would otherwise slowly degrade the latency of every search.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ShardLoadConfig,
+    metrics::{Counter, PrometheusRegistry},
+    searcher::ShardId,
+};
+
+use super::sonic::{
+    self,
+    replication::{RemoteClient, ReplicaSelector, RequestOutcome},
+};
+
+/// Weight of the newest latency measurement in the moving average.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Failed requests count as at least this slow, so failing replicas are avoided.
+const FAILURE_LATENCY_MS: f64 = 1_000.0;
+
+pub static METRICS: Lazy<LoadMetrics> = Lazy::new(LoadMetrics::default);
+
+#[derive(Default, Clone)]
+pub struct LoadMetrics {
+    pub hot_shard_alerts: Counter,
+    pub hot_shards: Counter,
+}
+
+impl LoadMetrics {
+    pub fn register(&self, registry: &mut PrometheusRegistry) {
+        let group = registry
+            .new_group(
+                "stract_hot_shard_alerts".to_string(),
+                Some("Number of times a search shard was detected as a hotspot.".to_string()),
+            )
+            .unwrap();
+        group.register(self.hot_shard_alerts.clone(), vec![]);
+
+        let group = registry
+            .new_group(
+                "stract_hot_shards".to_string(),
+                Some("Number of search shards that are currently hotspots.".to_string()),
+            )
+            .unwrap();
+        group.register(self.hot_shards.clone(), vec![]);
+    }
+}
+
+/// Number of requests per second within the window.
+struct RateCounter {
+    buckets: VecDeque<(u64, u64)>,
+}
+
+impl RateCounter {
+    fn new() -> Self {
+        Self {
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn prune(&mut self, second: u64, window_sec: u64) {
+        while let Some((bucket, _)) = self.buckets.front() {
+            if bucket + window_sec <= second {
+                self.buckets.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn inc(&mut self, second: u64, window_sec: u64) {
+        self.prune(second, window_sec);
+
+        match self.buckets.back_mut() {
+            Some((bucket, count)) if *bucket == second => *count += 1,
+            _ => self.buckets.push_back((second, 1)),
+        }
+    }
+
+    fn rate(&mut self, second: u64, window_sec: u64) -> f64 {
+        self.prune(second, window_sec);
+
+        self.buckets.iter().map(|(_, count)| *count).sum::<u64>() as f64 / window_sec as f64
+    }
+}
+
+struct ReplicaStats {
+    shard: ShardId,
+    in_flight: usize,
+    latency_ms: Option<f64>,
+    requests: RateCounter,
+    failures: u64,
+    timeouts: u64,
+}
+
+impl ReplicaStats {
+    fn new(shard: ShardId) -> Self {
+        Self {
+            shard,
+            in_flight: 0,
+            latency_ms: None,
+            requests: RateCounter::new(),
+            failures: 0,
+            timeouts: 0,
+        }
+    }
+
+    /// Expected wait for a new request sent to the replica.
+    fn load(&self) -> f64 {
+        (self.in_flight + 1) as f64 * self.latency_ms.unwrap_or(1.0).max(1.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicaLoad {
+    pub addr: SocketAddr,
+    pub qps: f64,
+    pub latency_ms: Option<f64>,
+    pub in_flight: usize,
+    pub failures: u64,
+    pub timeouts: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShardLoad {
+    pub shard: ShardId,
+    pub qps: f64,
+    pub qps_per_replica: f64,
+    pub latency_ms: Option<f64>,
+    pub hot: bool,
+    pub replicas: Vec<ReplicaLoad>,
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+
+    if values.len() % 2 == 0 {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+pub struct LoadTracker {
+    config: ShardLoadConfig,
+    start: Instant,
+    replicas: Mutex<HashMap<SocketAddr, ReplicaStats>>,
+    hot: Mutex<HashSet<ShardId>>,
+}
+
+impl Default for LoadTracker {
+    fn default() -> Self {
+        Self::new(ShardLoadConfig::default())
+    }
+}
+
+impl LoadTracker {
+    pub fn new(config: ShardLoadConfig) -> Self {
+        Self {
+            config,
+            start: Instant::now(),
+            replicas: Mutex::new(HashMap::new()),
+            hot: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    /// Update the replicas of the shards with the current members of the cluster.
+    /// Replicas that have left the cluster are forgotten.
+    pub fn set_replicas(&self, shards: &HashMap<ShardId, Vec<SocketAddr>>) {
+        let mut replicas = self.replicas.lock().unwrap_or_else(|e| e.into_inner());
+
+        let current: HashSet<SocketAddr> = shards.values().flatten().copied().collect();
+        replicas.retain(|addr, _| current.contains(addr));
+
+        for (shard, addrs) in shards {
+            for addr in addrs {
+                replicas
+                    .entry(*addr)
+                    .and_modify(|stats| stats.shard = *shard)
+                    .or_insert_with(|| ReplicaStats::new(*shard));
+            }
+        }
+    }
+
+    fn request_started(&self, addr: SocketAddr) {
+        let mut replicas = self.replicas.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(stats) = replicas.get_mut(&addr) {
+            stats.in_flight += 1;
+        }
+    }
+
+    fn request_finished(&self, addr: SocketAddr, latency: Duration, outcome: RequestOutcome) {
+        self.record(addr, latency, outcome, Instant::now());
+    }
+
+    fn record(&self, addr: SocketAddr, latency: Duration, outcome: RequestOutcome, now: Instant) {
+        let second = self.second(now);
+        let mut replicas = self.replicas.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(stats) = replicas.get_mut(&addr) {
+            stats.in_flight = stats.in_flight.saturating_sub(1);
+            stats.requests.inc(second, self.config.window_sec);
+
+            let mut latency_ms = latency.as_secs_f64() * 1000.0;
+
+            // a timeout only tells that the replica was slower than the deadline
+            // of the caller, so it is not penalized like a failed request.
+            match outcome {
+                RequestOutcome::Success => {}
+                RequestOutcome::Timeout => stats.timeouts += 1,
+                RequestOutcome::Failure => {
+                    stats.failures += 1;
+                    latency_ms = latency_ms.max(FAILURE_LATENCY_MS);
+                }
+            }
+
+            stats.latency_ms = Some(match stats.latency_ms {
+                Some(avg) => avg + LATENCY_SMOOTHING * (latency_ms - avg),
+                None => latency_ms,
+            });
+        }
+    }
+
+    /// Pick the least loaded of two random replicas. Comparing only two
+    /// replicas avoids that all api servers send their requests to the
+    /// same replica at the same time.
+    fn select<'a, S: sonic::service::Service>(
+        &self,
+        clients: &'a [RemoteClient<S>],
+    ) -> Vec<&'a RemoteClient<S>> {
+        let mut rng = rand::thread_rng();
+        let candidates = clients.iter().choose_multiple(&mut rng, 2);
+
+        let replicas = self.replicas.lock().unwrap_or_else(|e| e.into_inner());
+        let load = |client: &RemoteClient<S>| {
+            replicas
+                .get(&client.addr())
+                .map(|stats| stats.load())
+                .unwrap_or(0.0)
+        };
+
+        candidates
+            .into_iter()
+            .min_by(|a, b| load(a).total_cmp(&load(b)))
+            .into_iter()
+            .collect()
+    }
+
+    fn loads_at(&self, now: Instant) -> Vec<ShardLoad> {
+        let second = self.second(now);
+        let window_sec = self.config.window_sec;
+        let mut replicas = self.replicas.lock().unwrap_or_else(|e| e.into_inner());
+        let hot = self.hot.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut shards: HashMap<ShardId, Vec<ReplicaLoad>> = HashMap::new();
+
+        for (addr, stats) in replicas.iter_mut() {
+            shards.entry(stats.shard).or_default().push(ReplicaLoad {
+                addr: *addr,
+                qps: stats.requests.rate(second, window_sec),
+                latency_ms: stats.latency_ms,
+                in_flight: stats.in_flight,
+                failures: stats.failures,
+                timeouts: stats.timeouts,
+            });
+        }
+
+        let mut res: Vec<_> = shards
+            .into_iter()
+            .map(|(shard, mut replicas)| {
+                replicas.sort_by_key(|replica| replica.addr);
+
+                let qps: f64 = replicas.iter().map(|replica| replica.qps).sum();
+                let latencies: Vec<_> = replicas
+                    .iter()
+                    .filter_map(|replica| replica.latency_ms)
+                    .collect();
+
+                ShardLoad {
+                    shard,
+                    qps,
+                    qps_per_replica: qps / replicas.len() as f64,
+                    latency_ms: if latencies.is_empty() {
+                        None
+                    } else {
+                        Some(latencies.iter().sum::<f64>() / latencies.len() as f64)
+                    },
+                    hot: hot.contains(&shard),
+                    replicas,
+                }
+            })
+            .collect();
+
+        res.sort_by_key(|load| load.shard);
+
+        res
+    }
+
+    /// The current load of all the shards.
+    pub fn loads(&self) -> Vec<ShardLoad> {
+        self.loads_at(Instant::now())
+    }
+
+    fn is_hot(&self, load: &ShardLoad, median_qps: f64, median_latency: Option<f64>) -> bool {
+        if load.qps_per_replica < self.config.min_qps {
+            return false;
+        }
+
+        let hot_qps = load.qps_per_replica > self.config.hot_factor * median_qps;
+        let hot_latency = match (load.latency_ms, median_latency) {
+            (Some(latency), Some(median)) => latency > self.config.hot_factor * median,
+            _ => false,
+        };
+
+        hot_qps || hot_latency
+    }
+
+    fn check_hotspots_at(&self, now: Instant) -> Vec<ShardId> {
+        let loads = self.loads_at(now);
+
+        let median_qps = median(loads.iter().map(|load| load.qps_per_replica).collect());
+        let median_latency = median(loads.iter().filter_map(|load| load.latency_ms).collect());
+
+        let mut hot_shards = Vec::new();
+
+        if let Some(median_qps) = median_qps {
+            for load in &loads {
+                if self.is_hot(load, median_qps, median_latency) {
+                    hot_shards.push(load.clone());
+                }
+            }
+        }
+
+        let mut hot = self.hot.lock().unwrap_or_else(|e| e.into_inner());
+
+        for load in &hot_shards {
+            if !hot.contains(&load.shard) {
+                METRICS.hot_shard_alerts.inc();
+                tracing::warn!(
+                    "shard {:?} is a hotspot: {:.1} qps per replica ({:.1} median), {:.1} ms latency ({:.1} median)",
+                    load.shard,
+                    load.qps_per_replica,
+                    median_qps.unwrap_or_default(),
+                    load.latency_ms.unwrap_or_default(),
+                    median_latency.unwrap_or_default(),
+                );
+            }
+        }
+
+        let new_hot: HashSet<_> = hot_shards.iter().map(|load| load.shard).collect();
+
+        for shard in hot.difference(&new_hot) {
+            tracing::info!("shard {shard:?} is no longer a hotspot");
+        }
+
+        *hot = new_hot;
+        METRICS.hot_shards.store(hot.len() as u64);
+
+        hot_shards.into_iter().map(|load| load.shard).collect()
+    }
+
+    /// Detect the shards that are currently hotspots and alert when a shard becomes hot.
+    pub fn check_hotspots(&self) -> Vec<ShardId> {
+        self.check_hotspots_at(Instant::now())
+    }
+
+    pub async fn monitor(self: Arc<Self>) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.check_interval_sec));
+
+        loop {
+            interval.tick().await;
+            self.check_hotspots();
+        }
+    }
+}
+
+/// Sends requests to the least loaded replica of the shard.
+pub struct LoadAwareReplicaSelector<'a>(pub &'a LoadTracker);
+
+impl<S> ReplicaSelector<S> for LoadAwareReplicaSelector<'_>
+where
+    S: sonic::service::Service,
+{
+    fn select<'b>(&self, replicas: &'b [RemoteClient<S>]) -> Vec<&'b RemoteClient<S>> {
+        self.0.select(replicas)
+    }
+
+    fn request_started(&self, replica: SocketAddr) {
+        self.0.request_started(replica);
+    }
+
+    fn request_finished(&self, replica: SocketAddr, latency: Duration, outcome: RequestOutcome) {
+        self.0.request_finished(replica, latency, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn tracker() -> LoadTracker {
+        let tracker = LoadTracker::new(ShardLoadConfig {
+            window_sec: 10,
+            hot_factor: 2.0,
+            min_qps: 1.0,
+            check_interval_sec: 1,
+        });
+
+        let shards = (0..4)
+            .map(|shard| {
+                (
+                    ShardId::new(shard),
+                    vec![addr(shard as u16 * 2), addr(shard as u16 * 2 + 1)],
+                )
+            })
+            .collect();
+        tracker.set_replicas(&shards);
+
+        tracker
+    }
+
+    fn send(tracker: &LoadTracker, replica: SocketAddr, n: usize, latency_ms: u64, now: Instant) {
+        for _ in 0..n {
+            tracker.request_started(replica);
+            tracker.record(
+                replica,
+                Duration::from_millis(latency_ms),
+                RequestOutcome::Success,
+                now,
+            );
+        }
+    }
+
+    #[test]
+    fn qps_within_window() {
+        let tracker = tracker();
+        let now = tracker.start;
+
+        send(&tracker, addr(0), 50, 10, now);
+        send(&tracker, addr(1), 50, 10, now + Duration::from_secs(5));
+
+        let loads = tracker.loads_at(now + Duration::from_secs(5));
+        assert_eq!(loads[0].shard, ShardId::new(0));
+        assert_eq!(loads[0].qps, 10.0);
+        assert_eq!(loads[0].qps_per_replica, 5.0);
+        assert_eq!(loads[0].latency_ms, Some(10.0));
+
+        let loads = tracker.loads_at(now + Duration::from_secs(12));
+        assert_eq!(loads[0].qps, 5.0);
+        assert_eq!(loads[0].replicas[0].in_flight, 0);
+    }
+
+    #[test]
+    fn detect_hot_shard() {
+        let tracker = tracker();
+        let now = tracker.start;
+
+        for shard in 0..4 {
+            for replica in [addr(shard * 2), addr(shard * 2 + 1)] {
+                let n = if shard == 2 { 100 } else { 20 };
+                send(&tracker, replica, n, 10, now);
+            }
+        }
+
+        assert_eq!(tracker.check_hotspots_at(now), vec![ShardId::new(2)]);
+        assert!(tracker.loads_at(now)[2].hot);
+
+        // the load evens out
+        for shard in [0, 1, 3] {
+            for replica in [addr(shard * 2), addr(shard * 2 + 1)] {
+                send(&tracker, replica, 80, 10, now);
+            }
+        }
+
+        assert!(tracker.check_hotspots_at(now).is_empty());
+    }
+
+    #[test]
+    fn detect_slow_shard() {
+        let tracker = tracker();
+        let now = tracker.start;
+
+        for shard in 0..4 {
+            let latency = if shard == 1 { 500 } else { 20 };
+            send(&tracker, addr(shard * 2), 20, latency, now);
+        }
+
+        assert_eq!(tracker.check_hotspots_at(now), vec![ShardId::new(1)]);
+    }
+
+    #[test]
+    fn idle_shards_are_not_hot() {
+        let tracker = tracker();
+        let now = tracker.start;
+
+        send(&tracker, addr(0), 5, 10, now);
+
+        assert!(tracker.check_hotspots_at(now).is_empty());
+    }
+
+    #[test]
+    fn prefer_least_loaded_replica() {
+        let tracker = tracker();
+        let now = tracker.start;
+
+        send(&tracker, addr(0), 10, 200, now);
+        send(&tracker, addr(1), 10, 5, now);
+
+        let clients: Vec<RemoteClient<crate::entrypoint::search_server::SearchService>> =
+            vec![RemoteClient::new(addr(0)), RemoteClient::new(addr(1))];
+
+        for _ in 0..10 {
+            assert_eq!(tracker.select(&clients)[0].addr(), addr(1));
+        }
+    }
+
+    #[test]
+    fn timeouts_are_not_failures() {
+        let tracker = tracker();
+        let now = tracker.start;
+
+        tracker.request_started(addr(0));
+        tracker.record(
+            addr(0),
+            Duration::from_millis(50),
+            RequestOutcome::Timeout,
+            now,
+        );
+        tracker.request_started(addr(1));
+        tracker.record(
+            addr(1),
+            Duration::from_millis(50),
+            RequestOutcome::Failure,
+            now,
+        );
+
+        let loads = tracker.loads_at(now);
+        assert_eq!(loads[0].replicas[0].timeouts, 1);
+        assert_eq!(loads[0].replicas[0].failures, 0);
+        assert_eq!(loads[0].replicas[0].latency_ms, Some(50.0));
+        assert_eq!(loads[0].replicas[1].failures, 1);
+        assert_eq!(loads[0].replicas[1].latency_ms, Some(FAILURE_LATENCY_MS));
+    }
+
+    #[test]
+    fn forget_replicas_that_left() {
+        let tracker = tracker();
+
+        tracker.set_replicas(&[(ShardId::new(0), vec![addr(0)])].into_iter().collect());
+
+        let loads = tracker.loads();
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0].replicas.len(), 1);
+    }
+}
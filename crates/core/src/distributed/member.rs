@@ -25,6 +25,10 @@ pub enum Service {
     Searcher {
         host: SocketAddr,
         shard: ShardId,
+        /// See [`crate::index::Index::generation`]. Searchers from
+        /// before the generation was announced don't have one.
+        #[serde(default)]
+        generation: Option<u64>,
     },
     EntitySearcher {
         host: SocketAddr,
@@ -47,3 +51,23 @@ pub struct Member {
     pub id: String,
     pub service: Service,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn searcher_without_generation() {
+        let service: Service =
+            serde_json::from_str(r#"{"Searcher":{"host":"127.0.0.1:3000","shard":1}}"#).unwrap();
+
+        assert_eq!(
+            service,
+            Service::Searcher {
+                host: "127.0.0.1:3000".parse().unwrap(),
+                shard: ShardId::new(1),
+                generation: None,
+            }
+        );
+    }
+}
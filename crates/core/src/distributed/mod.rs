@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 pub mod cluster;
+pub mod load;
 pub mod member;
 pub mod retry_strategy;
 pub mod sonic;
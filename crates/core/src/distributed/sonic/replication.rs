@@ -19,7 +19,14 @@ use rand::seq::IteratorRandom;
 
 use super::Result;
 use crate::distributed::{retry_strategy::ExponentialBackoff, sonic};
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Timeout of requests to the remote services unless the caller has a deadline.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub struct RemoteClient<S: sonic::service::Service> {
@@ -37,33 +44,69 @@ where
             _phantom: std::marker::PhantomData,
         }
     }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
 }
 
 impl<S> RemoteClient<S>
 where
     S: sonic::service::Service,
 {
-    async fn conn(&self) -> Result<sonic::service::ResilientConnection<S>> {
+    async fn conn(&self, timeout: Duration) -> Result<sonic::service::ResilientConnection<S>> {
         let retry = ExponentialBackoff::from_millis(30)
             .with_limit(Duration::from_millis(200))
             .take(5);
 
         sonic::service::ResilientConnection::create_with_timeout(
             self.addr,
-            Duration::from_secs(30),
+            timeout.min(CONNECT_TIMEOUT),
             retry,
         )
         .await
     }
 
-    async fn send<R: sonic::service::Wrapper<S>>(&self, req: &R) -> Result<R::Response> {
-        let conn = self.conn().await?;
-        conn.send_with_timeout(req, Duration::from_secs(60)).await
+    async fn send<R: sonic::service::Wrapper<S>>(
+        &self,
+        req: &R,
+        timeout: Duration,
+    ) -> Result<R::Response> {
+        let start = Instant::now();
+        let conn = self.conn(timeout).await?;
+
+        conn.send_with_timeout(req, timeout.saturating_sub(start.elapsed()))
+            .await
+    }
+}
+
+/// How a request to a replica ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOutcome {
+    Success,
+    /// The replica did not respond before the deadline of the caller.
+    /// This says more about the deadline than about the health of the replica.
+    Timeout,
+    Failure,
+}
+
+impl RequestOutcome {
+    fn of<T>(res: &Result<T>) -> Self {
+        match res {
+            Ok(_) => Self::Success,
+            Err(sonic::Error::ConnectionTimeout | sonic::Error::RequestTimeout) => Self::Timeout,
+            Err(_) => Self::Failure,
+        }
     }
 }
 
 pub trait ReplicaSelector<S: sonic::service::Service> {
     fn select<'a>(&self, replicas: &'a [RemoteClient<S>]) -> Vec<&'a RemoteClient<S>>;
+
+    fn request_started(&self, _replica: SocketAddr) {}
+
+    fn request_finished(&self, _replica: SocketAddr, _latency: Duration, _outcome: RequestOutcome) {
+    }
 }
 
 pub struct RandomReplicaSelector;
@@ -102,13 +145,34 @@ where
     }
 
     pub async fn send<Req, Rep>(&self, req: &Req, selector: &Rep) -> Result<Vec<Req::Response>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        Rep: ReplicaSelector<S>,
+    {
+        self.send_with_timeout(req, selector, DEFAULT_REQUEST_TIMEOUT)
+            .await
+    }
+
+    pub async fn send_with_timeout<Req, Rep>(
+        &self,
+        req: &Req,
+        selector: &Rep,
+        timeout: Duration,
+    ) -> Result<Vec<Req::Response>>
     where
         Req: sonic::service::Wrapper<S>,
         Rep: ReplicaSelector<S>,
     {
         let mut futures = Vec::new();
         for client in selector.select(&self.clients) {
-            futures.push(client.send(req));
+            futures.push(async move {
+                selector.request_started(client.addr());
+                let start = Instant::now();
+                let res = client.send(req, timeout).await;
+                selector.request_finished(client.addr(), start.elapsed(), RequestOutcome::of(&res));
+
+                res
+            });
         }
 
         let mut results = Vec::new();
@@ -201,6 +265,7 @@ where
         req: &Req,
         shard: &Shard<S, Id>,
         replica_selector: &RSel,
+        timeout: Duration,
     ) -> Result<(Id, Vec<Req::Response>)>
     where
         Req: sonic::service::Wrapper<S>,
@@ -208,7 +273,10 @@ where
     {
         Ok((
             shard.id.clone(),
-            shard.replicas.send(req, replica_selector).await?,
+            shard
+                .replicas
+                .send_with_timeout(req, replica_selector, timeout)
+                .await?,
         ))
     }
 
@@ -218,6 +286,29 @@ where
         shard_selector: &SSel,
         replica_selector: &RSel,
     ) -> Result<Vec<(Id, Vec<Req::Response>)>>
+    where
+        Req: sonic::service::Wrapper<S>,
+        SSel: ShardSelector<S, Id>,
+        RSel: ReplicaSelector<S>,
+    {
+        self.send_with_timeout(
+            req,
+            shard_selector,
+            replica_selector,
+            DEFAULT_REQUEST_TIMEOUT,
+        )
+        .await
+    }
+
+    /// Send the request to the selected shards. Shards that have not responded
+    /// within the timeout are left out of the results.
+    pub async fn send_with_timeout<Req, SSel, RSel>(
+        &self,
+        req: &Req,
+        shard_selector: &SSel,
+        replica_selector: &RSel,
+        timeout: Duration,
+    ) -> Result<Vec<(Id, Vec<Req::Response>)>>
     where
         Req: sonic::service::Wrapper<S>,
         SSel: ShardSelector<S, Id>,
@@ -225,7 +316,7 @@ where
     {
         let mut futures = Vec::new();
         for shard in shard_selector.select(&self.shards) {
-            futures.push(self.send_single(req, shard, replica_selector));
+            futures.push(self.send_single(req, shard, replica_selector, timeout));
         }
 
         let mut results = Vec::new();
@@ -156,6 +156,9 @@ macro_rules! sonic_service {
 
             use $crate::distributed::sonic;
 
+            // the messages of a service can differ a lot in size, and boxing
+            // the large ones would need changes to every service using the macro.
+            #[allow(clippy::large_enum_variant)]
             #[derive(Debug, Clone, ::serde::Deserialize)]
             pub enum Request {
                 $($req($req),)*
@@ -164,6 +167,7 @@ macro_rules! sonic_service {
             pub enum RequestRef<'a> {
                 $($req(&'a $req),)*
             }
+            #[allow(clippy::large_enum_variant)]
             #[derive(::serde::Serialize, ::serde::Deserialize)]
             pub enum Response {
                 $($req(<$req as sonic::service::Message<$service>>::Response),)*
@@ -26,6 +26,10 @@ pub struct Entity {
     pub page_abstract: Span,
     pub info: Vec<(String, Span)>,
     pub image: Option<String>,
+    /// Other names of the entity (e.g. "Barack Hussein Obama II").
+    pub aliases: Vec<String>,
+    /// Number of links to the entity from other entities.
+    pub inbound_links: u64,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -21,7 +21,9 @@ use serde::{Deserialize, Serialize};
 use tantivy::{
     collector::TopDocs,
     query::{BooleanQuery, BoostQuery, MoreLikeThisQuery, Occur, QueryClone, TermQuery},
-    schema::{BytesOptions, IndexRecordOption, Schema, TextFieldIndexing, TextOptions},
+    schema::{
+        BytesOptions, IndexRecordOption, NumericOptions, Schema, TextFieldIndexing, TextOptions,
+    },
     tokenizer::Tokenizer,
     DocAddress, IndexReader, IndexWriter, Searcher, TantivyDocument, Term,
 };
@@ -35,6 +37,18 @@ use crate::{
 use self::entity::{Entity, Link, Span};
 pub(crate) mod entity;
 
+/// Maximum number of query tokens considered when linking
+/// a query to an entity.
+const MAX_LINK_TOKENS: usize = 8;
+
+/// Maximum number of spans searched when linking a query to an entity.
+/// Longer spans are searched first, so the shortest spans of long queries
+/// might not be considered.
+const MAX_LINK_SEARCHES: usize = 16;
+
+/// Number of candidates for each span that are checked for an exact match.
+const MAX_LINK_CANDIDATES: usize = 32;
+
 fn schema() -> Schema {
     let mut builder = tantivy::schema::Schema::builder();
 
@@ -58,6 +72,17 @@ fn schema() -> Schema {
             )
             .set_stored(),
     );
+    builder.add_text_field(
+        "aliases",
+        TextOptions::default()
+            .set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(Normal::as_str())
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            )
+            .set_stored(),
+    );
+    builder.add_u64_field("inbound_links", NumericOptions::default().set_stored());
     builder.add_bytes_field("info", BytesOptions::default().set_stored());
     builder.add_bytes_field("links", BytesOptions::default().set_stored());
     builder.add_text_field(
@@ -83,6 +108,13 @@ fn entity_to_tantivy(entity: Entity, schema: &tantivy::schema::Schema) -> Tantiv
     let mut doc = TantivyDocument::new();
 
     doc.add_text(schema.get_field("title").unwrap(), entity.title);
+    for alias in entity.aliases {
+        doc.add_text(schema.get_field("aliases").unwrap(), alias);
+    }
+    doc.add_u64(
+        schema.get_field("inbound_links").unwrap(),
+        entity.inbound_links,
+    );
     doc.add_text(
         schema.get_field("abstract").unwrap(),
         entity.page_abstract.text,
@@ -308,6 +340,154 @@ impl EntityIndex {
             })
     }
 
+    /// Find the entity mentioned in the query. Unlike [`EntityIndex::search`],
+    /// the query does not need to consist of only the entity title. Spans of the
+    /// query are matched against the entity titles and aliases, and the longest span
+    /// that exactly matches one of them is linked. If several entities match the span,
+    /// the one with the most inbound links wins, with the BM25 score as the tie-break.
+    ///
+    /// E.g. "when was barack obama born" links to the entity "Barack Obama".
+    pub fn link(&self, query: &str) -> Option<EntityMatch> {
+        let tokens: Vec<String> = self
+            .tokenize(query)
+            .into_iter()
+            .take(MAX_LINK_TOKENS)
+            .collect();
+
+        let searcher = self.reader.searcher();
+        let title = self.schema.get_field("title").unwrap();
+        let aliases = self.schema.get_field("aliases").unwrap();
+        let inbound_links = self.schema.get_field("inbound_links").unwrap();
+
+        let mut searched = HashSet::new();
+
+        for len in (1..=tokens.len()).rev() {
+            let mut best: Option<(u64, f32, DocAddress)> = None;
+
+            for span in tokens.windows(len) {
+                let span_terms: Vec<_> = span
+                    .iter()
+                    .filter(|t| !self.stopwords.contains(*t))
+                    .cloned()
+                    .collect();
+
+                if span_terms.is_empty() || searched.contains(&span_terms) {
+                    continue;
+                }
+
+                if searched.len() >= MAX_LINK_SEARCHES {
+                    break;
+                }
+
+                let query = BooleanQuery::from(
+                    [title, aliases]
+                        .into_iter()
+                        .map(|field| {
+                            let terms: Vec<_> = span_terms
+                                .iter()
+                                .map(|t| {
+                                    (
+                                        Occur::Must,
+                                        TermQuery::new(
+                                            Term::from_field_text(field, t),
+                                            IndexRecordOption::WithFreqsAndPositions,
+                                        )
+                                        .box_clone(),
+                                    )
+                                })
+                                .collect();
+
+                            (Occur::Should, BooleanQuery::from(terms).box_clone())
+                        })
+                        .collect::<Vec<_>>(),
+                );
+
+                let candidates = searcher
+                    .search(&query, &TopDocs::with_limit(MAX_LINK_CANDIDATES))
+                    .unwrap_or_default();
+
+                for (score, doc_address) in candidates {
+                    let doc: TantivyDocument = match searcher.doc(doc_address) {
+                        Ok(doc) => doc,
+                        Err(_) => continue,
+                    };
+
+                    let exact = doc
+                        .get_all(title)
+                        .chain(doc.get_all(aliases))
+                        .filter_map(|val| match val {
+                            tantivy::schema::OwnedValue::Str(string) => Some(string),
+                            _ => None,
+                        })
+                        .any(|name| self.title_terms(name) == span_terms);
+
+                    if !exact {
+                        continue;
+                    }
+
+                    let links = doc
+                        .get_first(inbound_links)
+                        .and_then(|val| match val {
+                            tantivy::schema::OwnedValue::U64(links) => Some(*links),
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+
+                    if best
+                        .map(|(best_links, best_score, _)| {
+                            (links, score) > (best_links, best_score)
+                        })
+                        .unwrap_or(true)
+                    {
+                        best = Some((links, score, doc_address));
+                    }
+                }
+
+                searched.insert(span_terms);
+            }
+
+            if let Some((_, score, doc_address)) = best {
+                let entity = self.retrieve_stored_entity(&searcher, doc_address, true, true, true);
+
+                return Some(EntityMatch { entity, score });
+            }
+
+            if searched.len() >= MAX_LINK_SEARCHES {
+                break;
+            }
+        }
+
+        None
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut tokenizer = Normal::default();
+        let mut stream = tokenizer.token_stream(text);
+        let mut tokens = Vec::new();
+
+        while let Some(token) = stream.next() {
+            if token.text.chars().any(|c| c.is_alphanumeric()) {
+                tokens.push(token.text.clone());
+            }
+        }
+
+        tokens
+    }
+
+    /// Non-stopword terms of the title with any trailing
+    /// disambiguation (e.g. "Python (programming language)") removed.
+    fn title_terms(&self, title: &str) -> Vec<String> {
+        let title = match title.find(" (") {
+            Some(idx) if title.ends_with(')') => &title[..idx],
+            _ => title,
+        };
+
+        self.tokenize(title)
+            .into_iter()
+            .filter(|t| !self.stopwords.contains(t))
+            .collect()
+    }
+
     fn retrieve_stored_entity(
         &self,
         searcher: &Searcher,
@@ -436,6 +616,8 @@ mod tests {
             },
             info: Vec::new(),
             image: None,
+            aliases: Vec::new(),
+            inbound_links: 0,
         });
 
         index.commit();
@@ -451,6 +633,119 @@ mod tests {
         );
     }
 
+    fn entity(title: &str) -> Entity {
+        Entity {
+            article_url: String::new(),
+            is_disambiguation: false,
+            title: title.to_string(),
+            page_abstract: Span {
+                text: String::new(),
+                links: Vec::new(),
+            },
+            info: Vec::new(),
+            image: None,
+            aliases: Vec::new(),
+            inbound_links: 0,
+        }
+    }
+
+    #[test]
+    fn link_entity_in_query() {
+        let mut index = EntityIndex::open(crate::gen_temp_path()).unwrap();
+        index.prepare_writer();
+
+        index.insert(entity("Barack Obama"));
+        index.insert(entity("Paris"));
+        index.insert(entity("Paris Hilton"));
+        index.insert(entity("Python (programming language)"));
+
+        index.commit();
+
+        assert_eq!(
+            index
+                .link("when was barack obama born")
+                .unwrap()
+                .entity
+                .title
+                .as_str(),
+            "Barack Obama"
+        );
+        assert_eq!(
+            index.link("hotels in paris").unwrap().entity.title.as_str(),
+            "Paris"
+        );
+        assert_eq!(
+            index
+                .link("paris hilton net worth")
+                .unwrap()
+                .entity
+                .title
+                .as_str(),
+            "Paris Hilton"
+        );
+        assert_eq!(
+            index
+                .link("python list comprehension")
+                .unwrap()
+                .entity
+                .title
+                .as_str(),
+            "Python (programming language)"
+        );
+        assert!(index.link("what is the time").is_none());
+    }
+
+    #[test]
+    fn link_alias() {
+        let mut index = EntityIndex::open(crate::gen_temp_path()).unwrap();
+        index.prepare_writer();
+
+        index.insert(Entity {
+            aliases: vec!["Barack Hussein Obama II".to_string()],
+            ..entity("Barack Obama")
+        });
+
+        index.commit();
+
+        assert_eq!(
+            index
+                .link("barack hussein obama ii speech")
+                .unwrap()
+                .entity
+                .title
+                .as_str(),
+            "Barack Obama"
+        );
+        assert!(index.link("hussein speech").is_none());
+    }
+
+    #[test]
+    fn link_prefers_popular_entities() {
+        let mut index = EntityIndex::open(crate::gen_temp_path()).unwrap();
+        index.prepare_writer();
+
+        index.insert(Entity {
+            inbound_links: 2,
+            ..entity("Paris (Texas)")
+        });
+        index.insert(Entity {
+            inbound_links: 1_000,
+            ..entity("Paris")
+        });
+        index.insert(Entity {
+            aliases: vec!["Paris".to_string()],
+            inbound_links: 10,
+            ..entity("Paris Hilton")
+        });
+
+        index.commit();
+
+        assert_eq!(
+            index.link("hotels in paris").unwrap().entity.title.as_str(),
+            "Paris"
+        );
+    }
+
     #[test]
     fn image() {
         let mut index = EntityIndex::open(crate::gen_temp_path()).unwrap();
@@ -466,6 +761,8 @@ mod tests {
             },
             info: Vec::new(),
             image: Some("test".to_string()),
+            aliases: Vec::new(),
+            inbound_links: 0,
         });
 
         index.commit();
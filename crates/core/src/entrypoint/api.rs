@@ -71,6 +71,10 @@ pub async fn run(config: config::ApiConfig) -> Result<()> {
         .unwrap();
     group.register(daily_active_users.metric(), vec![]);
 
+    crate::safe_browsing::METRICS.register(&mut registry);
+    crate::distributed::load::METRICS.register(&mut registry);
+    crate::task_queue::METRICS.register(&mut registry);
+
     let counters = Counters {
         search_counter_success,
         search_counter_fail,
@@ -0,0 +1,514 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Build everything that is needed to serve search from a set of warc files.
+//!
+//! The steps are run in order and each step checks that the steps it depends on
+//! have been built. The progress is stored in `build_state.json` in the output folder,
+//! so a build that fails halfway can be resumed by running the command again. When a
+//! step is rebuilt, all steps that depend on it are rebuilt as well. Finally a
+//! `manifest.json` with all the artifacts is published in the output folder.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    autosuggest::{Autosuggest, HostAutosuggest},
+    config::{BuildAllConfig, IndexingLocalConfig, WebSpellConfig, WebgraphConstructConfig},
+    index::Index,
+    Result,
+};
+
+use super::{disk_usage::file_sizes, Centrality, Indexer, Webgraph};
+
+const STATE_FILE: &str = "build_state.json";
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Webgraph,
+    HostCentrality,
+    PageCentrality,
+    Index,
+    HostAutosuggest,
+    WebSpell,
+    Autosuggest,
+    Manifest,
+}
+
+/// All steps in the order they are built.
+const ALL_STEPS: [Step; 8] = [
+    Step::Webgraph,
+    Step::HostCentrality,
+    Step::PageCentrality,
+    Step::Index,
+    Step::HostAutosuggest,
+    Step::WebSpell,
+    Step::Autosuggest,
+    Step::Manifest,
+];
+
+impl Step {
+    fn dependencies(&self) -> &'static [Step] {
+        match self {
+            Step::Webgraph | Step::WebSpell | Step::Autosuggest => &[],
+            Step::HostCentrality | Step::PageCentrality => &[Step::Webgraph],
+            Step::Index => &[Step::Webgraph, Step::HostCentrality, Step::PageCentrality],
+            Step::HostAutosuggest => &[Step::Index],
+            Step::Manifest => &[
+                Step::Webgraph,
+                Step::HostCentrality,
+                Step::PageCentrality,
+                Step::Index,
+                Step::HostAutosuggest,
+                Step::WebSpell,
+                Step::Autosuggest,
+            ],
+        }
+    }
+
+    /// Where the artifact of the step is stored relative to the output folder.
+    fn output(&self) -> &'static str {
+        match self {
+            Step::Webgraph => "webgraph",
+            Step::HostCentrality => "centrality/host",
+            Step::PageCentrality => "centrality/page",
+            Step::Index => "index",
+            Step::HostAutosuggest => "host_autosuggest.fst",
+            Step::WebSpell => "web_spell",
+            Step::Autosuggest => "autosuggest",
+            Step::Manifest => MANIFEST_FILE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Built,
+    /// The step is not configured.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct StepRecord {
+    status: Status,
+    finished_at: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BuildState {
+    steps: BTreeMap<Step, StepRecord>,
+}
+
+impl BuildState {
+    fn open(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Forget the step and all steps that depend on it, so they are rebuilt.
+    fn invalidate(&mut self, step: Step) {
+        let mut invalid = HashSet::from([step]);
+
+        for other in ALL_STEPS {
+            if other.dependencies().iter().any(|dep| invalid.contains(dep)) {
+                invalid.insert(other);
+            }
+        }
+
+        self.steps.retain(|step, _| !invalid.contains(step));
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Artifact {
+    step: Step,
+    path: String,
+    size_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest {
+    built_at: i64,
+    steps: BTreeMap<Step, StepRecord>,
+    artifacts: Vec<Artifact>,
+}
+
+fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+struct Builder {
+    config: BuildAllConfig,
+    output: PathBuf,
+    state: BuildState,
+}
+
+impl Builder {
+    fn new(config: BuildAllConfig, restart: bool) -> Result<Self> {
+        let output = PathBuf::from(&config.output_path);
+        fs::create_dir_all(&output)?;
+
+        let state = if restart {
+            BuildState::default()
+        } else {
+            BuildState::open(&output.join(STATE_FILE))?
+        };
+
+        Ok(Self {
+            config,
+            output,
+            state,
+        })
+    }
+
+    fn path(&self, step: Step) -> PathBuf {
+        self.output.join(step.output())
+    }
+
+    /// Fail early instead of after hours of building if an input is missing.
+    fn check_inputs(&self) -> Result<()> {
+        let inputs = [
+            &self.config.topics_path,
+            &self.config.safety_classifier_path,
+            &self.config.queries_csv_path,
+        ];
+
+        for path in inputs.into_iter().flatten() {
+            if !Path::new(path).exists() {
+                bail!("input {path} does not exist");
+            }
+        }
+
+        if self.config.warc_source.paths()?.is_empty() {
+            bail!("no warc files found");
+        }
+
+        Ok(())
+    }
+
+    fn is_configured(&self, step: Step) -> bool {
+        match step {
+            Step::WebSpell => !self.config.spell_languages.is_empty(),
+            Step::Autosuggest => self.config.queries_csv_path.is_some(),
+            _ => true,
+        }
+    }
+
+    fn is_finished(&self, step: Step) -> bool {
+        match self.state.steps.get(&step).map(|record| record.status) {
+            Some(Status::Built) => self.path(step).exists(),
+            // the step must be built if it has been configured since
+            Some(Status::Skipped) => !self.is_configured(step),
+            None => false,
+        }
+    }
+
+    fn run_step(&mut self, step: Step) -> Result<()> {
+        if self.is_finished(step) {
+            info!("{:?} is already built", step);
+            return Ok(());
+        }
+
+        if let Some(dep) = step
+            .dependencies()
+            .iter()
+            .find(|dep| !self.is_finished(**dep))
+        {
+            bail!("{:?} depends on {:?} which has not been built", step, dep);
+        }
+
+        info!("building {:?}", step);
+
+        self.state.invalidate(step);
+        remove_if_exists(&self.path(step))?;
+
+        let status = match step {
+            Step::Webgraph => self.webgraph()?,
+            Step::HostCentrality => self.host_centrality(),
+            Step::PageCentrality => self.page_centrality()?,
+            Step::Index => self.index()?,
+            Step::HostAutosuggest => self.host_autosuggest()?,
+            Step::WebSpell => self.web_spell()?,
+            Step::Autosuggest => self.autosuggest()?,
+            Step::Manifest => self.manifest()?,
+        };
+
+        self.state.steps.insert(
+            step,
+            StepRecord {
+                status,
+                finished_at: Utc::now().timestamp(),
+            },
+        );
+        self.state.save(&self.output.join(STATE_FILE))?;
+
+        info!("finished {:?}", step);
+
+        Ok(())
+    }
+
+    fn webgraph(&self) -> Result<Status> {
+        let tmp = self.output.join("webgraph_tmp");
+        remove_if_exists(&tmp)?;
+
+        let (host, page) = Webgraph::build(&WebgraphConstructConfig {
+            host_graph_base_path: path_string(&tmp.join("host")),
+            page_graph_base_path: path_string(&tmp.join("page")),
+            warc_source: self.config.warc_source.clone(),
+            limit_warc_files: self.config.limit_warc_files,
+            skip_warc_files: self.config.skip_warc_files,
+            batch_size: self.config.batch_size,
+        })?;
+
+        // only move the graphs into place when they are complete
+        let out = self.path(Step::Webgraph);
+        fs::create_dir_all(&out)?;
+        fs::rename(host, out.join("host"))?;
+        fs::rename(page, out.join("page"))?;
+        fs::remove_dir_all(tmp)?;
+
+        Ok(Status::Built)
+    }
+
+    fn host_centrality(&self) -> Status {
+        let webgraph = self.path(Step::Webgraph).join("host");
+        let out = self.path(Step::HostCentrality);
+
+        Centrality::build_harmonic(&webgraph, &out, None);
+        Centrality::build_similarity(&webgraph, &out);
+
+        Status::Built
+    }
+
+    fn page_centrality(&self) -> Result<Status> {
+        Centrality::build_approx_harmonic(
+            self.path(Step::Webgraph).join("page"),
+            self.path(Step::PageCentrality),
+        )?;
+
+        Ok(Status::Built)
+    }
+
+    fn index(&self) -> Result<Status> {
+        let tmp = self.output.join("index_tmp");
+        remove_if_exists(&tmp)?;
+
+        let index = Indexer::run(&IndexingLocalConfig {
+            output_path: path_string(&tmp),
+            limit_warc_files: self.config.limit_warc_files,
+            skip_warc_files: self.config.skip_warc_files,
+            warc_source: self.config.warc_source.clone(),
+            batch_size: self.config.batch_size,
+            page_webgraph_path: Some(path_string(&self.path(Step::Webgraph).join("page"))),
+            host_centrality_threshold: self.config.host_centrality_threshold,
+            topics_path: self.config.topics_path.clone(),
+            host_centrality_store_path: path_string(&self.path(Step::HostCentrality)),
+            page_centrality_store_path: Some(path_string(&self.path(Step::PageCentrality))),
+            safety_classifier_path: self.config.safety_classifier_path.clone(),
+            minimum_clean_words: self.config.minimum_clean_words,
+            embedding_model_path: None,
+            recrawl_history_path: None,
+        })?;
+
+        fs::rename(index.path(), self.path(Step::Index))?;
+        fs::remove_dir_all(tmp)?;
+
+        Ok(Status::Built)
+    }
+
+    fn host_autosuggest(&self) -> Result<Status> {
+        let index = Index::open(self.path(Step::Index))?;
+
+        HostAutosuggest::from_index(&index.inverted_index.tv_searcher())?
+            .save(self.path(Step::HostAutosuggest))?;
+
+        Ok(Status::Built)
+    }
+
+    fn web_spell(&self) -> Result<Status> {
+        if !self.is_configured(Step::WebSpell) {
+            return Ok(Status::Skipped);
+        }
+
+        super::web_spell::run(WebSpellConfig {
+            output_path: path_string(&self.path(Step::WebSpell)),
+            warc_source: self.config.warc_source.clone(),
+            languages: self.config.spell_languages.clone(),
+            limit_warc_files: self.config.limit_warc_files,
+            skip_warc_files: self.config.skip_warc_files,
+        })?;
+
+        Ok(Status::Built)
+    }
+
+    fn autosuggest(&self) -> Result<Status> {
+        let Some(queries) = &self.config.queries_csv_path else {
+            return Ok(Status::Skipped);
+        };
+
+        // make sure the queries can be loaded before they are published
+        Autosuggest::load_csv(queries)?;
+
+        let out = self.path(Step::Autosuggest);
+        fs::create_dir_all(&out)?;
+        fs::copy(queries, out.join("queries.csv"))?;
+
+        Ok(Status::Built)
+    }
+
+    fn manifest(&self) -> Result<Status> {
+        let mut artifacts = Vec::new();
+
+        for (step, record) in &self.state.steps {
+            if record.status != Status::Built {
+                continue;
+            }
+
+            let path = self.path(*step);
+
+            artifacts.push(Artifact {
+                step: *step,
+                size_bytes: file_sizes(&path)?,
+                path: path_string(&path),
+            });
+        }
+
+        let manifest = Manifest {
+            built_at: Utc::now().timestamp(),
+            steps: self.state.steps.clone(),
+            artifacts,
+        };
+
+        write_atomic(
+            &self.path(Step::Manifest),
+            &serde_json::to_string_pretty(&manifest)?,
+        )?;
+
+        Ok(Status::Built)
+    }
+}
+
+/// Run all steps that have not been built yet. If `restart` is set,
+/// the previous build state is ignored and everything is rebuilt.
+pub fn run(config: BuildAllConfig, restart: bool) -> Result<()> {
+    let mut builder = Builder::new(config, restart)?;
+    builder.check_inputs()?;
+
+    for step in ALL_STEPS {
+        builder
+            .run_step(step)
+            .map_err(|err| anyhow!("failed to build {:?}: {err}", step))?;
+    }
+
+    info!(
+        "build finished, manifest written to {}",
+        builder.path(Step::Manifest).display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> StepRecord {
+        StepRecord {
+            status: Status::Built,
+            finished_at: 0,
+        }
+    }
+
+    #[test]
+    fn steps_are_ordered_by_dependencies() {
+        for (i, step) in ALL_STEPS.iter().enumerate() {
+            for dep in step.dependencies() {
+                assert!(ALL_STEPS[..i].contains(dep));
+            }
+        }
+    }
+
+    #[test]
+    fn invalidate_dependents() {
+        let mut state = BuildState::default();
+
+        for step in ALL_STEPS {
+            state.steps.insert(step, record());
+        }
+
+        state.invalidate(Step::PageCentrality);
+
+        assert_eq!(
+            state.steps.keys().copied().collect::<Vec<_>>(),
+            vec![
+                Step::Webgraph,
+                Step::HostCentrality,
+                Step::WebSpell,
+                Step::Autosuggest
+            ]
+        );
+    }
+
+    #[test]
+    fn state_roundtrip() {
+        let path = crate::gen_temp_path();
+        fs::create_dir_all(&path).unwrap();
+        let path = path.join(STATE_FILE);
+
+        let mut state = BuildState::default();
+        state.steps.insert(Step::Webgraph, record());
+        state.save(&path).unwrap();
+
+        let state = BuildState::open(&path).unwrap();
+        assert_eq!(state.steps.get(&Step::Webgraph), Some(&record()));
+    }
+}
@@ -16,15 +16,27 @@
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Reverse, fs::File, path::Path};
+use std::{cmp::Reverse, fs::File, net::SocketAddr, path::Path};
 
 use crate::{
+    config::DistributedHarmonicConfig,
     external_sort::ExternalSorter,
     kv::{rocksdb_store::RocksDbStore, Kv},
+    mapreduce::{Manager, Worker},
     ranking::inbound_similarity::InboundSimilarity,
     webgraph::{
-        centrality::{approx_harmonic::ApproxHarmonic, harmonic::HarmonicCentrality},
-        Node, WebgraphBuilder,
+        centrality::{
+            approx_harmonic::ApproxHarmonic,
+            betweenness::Betweenness,
+            distributed_harmonic::{
+                DistributedHarmonic, HarmonicJob, HarmonicUpdates, HarmonicWorker,
+            },
+            harmonic::{HarmonicCentrality, HarmonicCheckpoint},
+            link_spam::LinkSpam,
+            pagerank::{PageRank, PageRankConfig},
+            trustrank::{self, TrustRank},
+        },
+        Node, NodeID, WebgraphBuilder,
     },
 };
 
@@ -72,13 +84,73 @@ impl Ord for SortableFloat {
 pub struct Centrality;
 
 impl Centrality {
-    pub fn build_harmonic<P: AsRef<Path>>(webgraph_path: P, base_output: P) {
+    pub fn build_harmonic<P: AsRef<Path>>(
+        webgraph_path: P,
+        base_output: P,
+        checkpoint: Option<HarmonicCheckpoint>,
+    ) {
         tracing::info!(
             "Building harmonic centrality for {}",
             webgraph_path.as_ref().to_str().unwrap()
         );
         let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
-        let harmonic_centrality = HarmonicCentrality::calculate(&graph);
+        let harmonic_centrality = match &checkpoint {
+            Some(checkpoint) => HarmonicCentrality::calculate_with_checkpoint(&graph, checkpoint),
+            None => HarmonicCentrality::calculate(&graph),
+        };
+
+        Self::store_harmonic(&harmonic_centrality, |id| graph.id2node(id), base_output);
+    }
+
+    /// Calculate the harmonic centrality with the edges partitioned across the workers.
+    /// The result is stored just like [`Centrality::build_harmonic`].
+    pub async fn build_distributed_harmonic(config: DistributedHarmonicConfig) -> Result<()> {
+        tracing::info!(
+            "Building distributed harmonic centrality for {} shards",
+            config.shards.len()
+        );
+
+        let shards: Vec<_> = config
+            .shards
+            .iter()
+            .map(|path| WebgraphBuilder::new(path).single_threaded().open())
+            .collect();
+
+        let manager = Manager::new(&config.workers);
+        let workers = &manager;
+
+        let harmonic_centrality = DistributedHarmonic::new(config.shards.clone())
+            .calculate(shards.iter().flat_map(|shard| shard.nodes()), move |jobs| {
+                workers.map_all::<HarmonicWorker, HarmonicJob, HarmonicUpdates>(jobs.into_iter())
+            })
+            .await;
+
+        manager
+            .stop::<HarmonicWorker, HarmonicJob, HarmonicUpdates>()
+            .await;
+
+        Self::store_harmonic(
+            &harmonic_centrality,
+            |id| shards.iter().find_map(|shard| shard.id2node(id)),
+            &config.output_path,
+        );
+
+        Ok(())
+    }
+
+    pub async fn run_harmonic_worker(addr: SocketAddr) -> Result<()> {
+        HarmonicWorker::default()
+            .run::<HarmonicJob, HarmonicUpdates>(addr)
+            .await?;
+
+        Ok(())
+    }
+
+    fn store_harmonic<P, F>(harmonic_centrality: &HarmonicCentrality, id2node: F, base_output: P)
+    where
+        P: AsRef<Path>,
+        F: Fn(&NodeID) -> Option<Node>,
+    {
         let store = RocksDbStore::open(base_output.as_ref().join("harmonic"));
 
         for (node_id, centrality) in harmonic_centrality.iter() {
@@ -104,13 +176,142 @@ impl Centrality {
             rank_store.insert(node, rank as f64);
 
             if top_harmonics.len() < 1_000_000 {
-                top_harmonics.push((graph.id2node(&node).unwrap(), centrality));
+                top_harmonics.push((id2node(&node).unwrap(), centrality));
             }
         }
 
         store_csv(top_harmonics, base_output.as_ref().join("harmonic.csv"));
     }
 
+    pub fn build_pagerank<P: AsRef<Path>>(
+        webgraph_path: P,
+        base_output: P,
+        config: PageRankConfig,
+    ) -> Result<()> {
+        tracing::info!(
+            "Building pagerank for {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+        let pagerank = PageRank::calculate(&graph, &config);
+        let store = RocksDbStore::open(base_output.as_ref().join("pagerank"));
+
+        for (node_id, score) in pagerank.iter() {
+            store.insert(*node_id, score);
+        }
+        store.flush();
+
+        let rank_store = RocksDbStore::open(base_output.as_ref().join("pagerank_rank"));
+        let mut top_nodes = Vec::new();
+        for (rank, node, score) in ExternalSorter::new()
+            .with_chunk_size(100_000_000)
+            .sort(
+                pagerank
+                    .iter()
+                    .map(|(node_id, score)| (Reverse(SortableFloat(score)), *node_id)),
+            )?
+            .enumerate()
+            .map(|(rank, (Reverse(SortableFloat(score)), node_id))| (rank, node_id, score))
+        {
+            rank_store.insert(node, rank as f64);
+
+            if top_nodes.len() < 1_000_000 {
+                top_nodes.push((graph.id2node(&node).unwrap(), score));
+            }
+        }
+
+        store_csv(top_nodes, base_output.as_ref().join("pagerank.csv"));
+
+        Ok(())
+    }
+
+    /// The trust is stored next to the harmonic centrality of the host graph,
+    /// where the indexer picks it up.
+    pub fn build_trustrank<P: AsRef<Path>>(
+        webgraph_path: P,
+        seeds_path: P,
+        base_output: P,
+    ) -> Result<()> {
+        tracing::info!(
+            "Building trustrank for {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+        let seeds = trustrank::read_seeds(seeds_path)?;
+        tracing::info!("read {} seed hosts", seeds.len());
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+        let trust = TrustRank::calculate(&graph, &seeds, &PageRankConfig::default());
+        let store = RocksDbStore::open(base_output.as_ref().join("trustrank"));
+
+        let mut top_nodes = Vec::new();
+        for (node_id, trust) in trust.iter() {
+            store.insert(*node_id, trust);
+
+            if trust > 0.0 {
+                top_nodes.push((graph.id2node(node_id).unwrap(), trust));
+            }
+        }
+        store.flush();
+
+        store_csv(top_nodes, base_output.as_ref().join("trustrank.csv"));
+
+        Ok(())
+    }
+
+    /// The spam likelihood is stored next to the harmonic centrality of the host graph,
+    /// where the indexer picks it up.
+    pub fn build_link_spam<P: AsRef<Path>>(webgraph_path: P, base_output: P) {
+        tracing::info!(
+            "Detecting link spam in {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+        let spam = LinkSpam::calculate(&graph);
+        let store = RocksDbStore::open(base_output.as_ref().join("link_spam"));
+
+        let mut likely_spam = Vec::new();
+        for (node_id, score) in spam.iter() {
+            store.insert(*node_id, score);
+
+            if score > 0.5 {
+                likely_spam.push((graph.id2node(node_id).unwrap(), score));
+            }
+        }
+        store.flush();
+
+        store_csv(likely_spam, base_output.as_ref().join("link_spam.csv"));
+    }
+
+    /// Estimate the betweenness centrality from the shortest paths of randomly sampled
+    /// pivot nodes. This finds hosts that bridge otherwise separate parts of the graph.
+    pub fn build_betweenness<P: AsRef<Path>>(webgraph_path: P, base_output: P, num_pivots: usize) {
+        tracing::info!(
+            "Building betweenness centrality for {} with {} pivots",
+            webgraph_path.as_ref().to_str().unwrap(),
+            num_pivots
+        );
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+        let betweenness = Betweenness::calculate_sampled(&graph, num_pivots, rand::random());
+        let store = RocksDbStore::open(base_output.as_ref().join("betweenness"));
+
+        let mut top_nodes = Vec::new();
+        for (node, centrality) in betweenness.centrality {
+            store.insert(node.id(), centrality);
+
+            if centrality > 0.0 {
+                top_nodes.push((node, centrality));
+            }
+        }
+        store.flush();
+
+        top_nodes.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        top_nodes.truncate(1_000_000);
+
+        store_csv(top_nodes, base_output.as_ref().join("betweenness.csv"));
+    }
+
     pub fn build_similarity<P: AsRef<Path>>(webgraph_path: P, base_output: P) {
         tracing::info!(
             "Building inbound similarity for {}",
@@ -0,0 +1,322 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Export the aggregated clicks as learning-to-rank training data.
+//!
+//! Every query is searched in a local index to get the ranking signals of its results.
+//! The relevance labels are derived from the click-through rate of each result.
+//! A `.features` file with the name of each feature id is written next to the output,
+//! and the LightGBM format additionally gets a `.query` file with the size of each query group.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use tracing::info;
+
+use crate::{
+    clicks::{ClickStore, QueryClicks},
+    index::Index,
+    ranking::{Signal, SignalScore, ALL_SIGNALS},
+    searcher::{LocalSearcher, SearchQuery},
+    Result,
+};
+
+/// Number of results to compute ranking signals for per query.
+const NUM_RESULTS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LtrFormat {
+    /// `<label> qid:<qid> <feature>:<value> ... # <url>`
+    SvmRank,
+    /// `<label> <feature>:<value> ...` with the query groups in a separate file.
+    LightGbm,
+}
+
+impl Display for LtrFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LtrFormat::SvmRank => write!(f, "svmrank"),
+            LtrFormat::LightGbm => write!(f, "lightgbm"),
+        }
+    }
+}
+
+impl FromStr for LtrFormat {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "svmrank" => Ok(LtrFormat::SvmRank),
+            "lightgbm" => Ok(LtrFormat::LightGbm),
+            _ => Err(crate::Error::UnknownCLIOption),
+        }
+    }
+}
+
+/// A result of a query with its relevance label and ranking signals.
+pub(crate) struct Example {
+    pub relevance: u8,
+    pub url: String,
+    pub signals: HashMap<Signal, SignalScore>,
+}
+
+fn features(signals: &HashMap<Signal, SignalScore>) -> String {
+    ALL_SIGNALS
+        .iter()
+        .filter_map(|signal| {
+            signals
+                .get(signal)
+                .map(|score| format!("{}:{}", usize::from(*signal) + 1, score.value))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn write_group<W: Write>(
+    wrt: &mut W,
+    format: LtrFormat,
+    qid: usize,
+    examples: &[Example],
+) -> Result<()> {
+    for example in examples {
+        match format {
+            LtrFormat::SvmRank => writeln!(
+                wrt,
+                "{} qid:{} {} # {}",
+                example.relevance,
+                qid,
+                features(&example.signals),
+                example.url
+            )?,
+            LtrFormat::LightGbm => {
+                writeln!(wrt, "{} {}", example.relevance, features(&example.signals))?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+
+    path.into()
+}
+
+fn search(
+    searcher: &LocalSearcher<Index>,
+    query: &QueryClicks,
+    num_results: usize,
+) -> Result<Vec<(String, HashMap<Signal, SignalScore>)>> {
+    let result = searcher.search(&SearchQuery {
+        query: query.query.clone(),
+        num_results,
+        return_ranking_signals: true,
+        ..Default::default()
+    })?;
+
+    Ok(result
+        .webpages
+        .into_iter()
+        .filter_map(|webpage| {
+            webpage
+                .ranking_signals
+                .map(|signals| (webpage.url, signals))
+        })
+        .collect())
+}
+
+/// Search the query in the index and label the results that have been shown to users
+/// by their click-through rate. Queries where none of the results have been clicked are
+/// left out, as they don't say anything about the relative relevance of the results.
+pub(crate) fn labeled_results(
+    searcher: &LocalSearcher<Index>,
+    query: &QueryClicks,
+    num_results: usize,
+) -> Option<Vec<Example>> {
+    let mut signals: HashMap<_, _> = match search(searcher, query, num_results) {
+        Ok(results) => results.into_iter().collect(),
+        Err(err) => {
+            tracing::warn!("failed to search for {:?}: {err}", query.query);
+            return None;
+        }
+    };
+
+    let examples: Vec<_> = query
+        .results
+        .iter()
+        .filter_map(|result| {
+            signals.remove(&result.url).map(|signals| Example {
+                relevance: result.relevance(),
+                url: result.url.clone(),
+                signals,
+            })
+        })
+        .collect();
+
+    if examples.iter().all(|example| example.relevance == 0) {
+        return None;
+    }
+
+    Some(examples)
+}
+
+pub fn run<P: AsRef<Path>>(
+    click_log_path: P,
+    index_path: P,
+    output_path: P,
+    format: LtrFormat,
+    min_query_impressions: u64,
+) -> Result<()> {
+    let output_path = output_path.as_ref();
+    let clicks = ClickStore::open(click_log_path);
+    let searcher = LocalSearcher::from(Index::open(index_path)?);
+
+    let mut wrt = BufWriter::new(File::create(output_path)?);
+    let mut groups = Vec::new();
+
+    for query in clicks.queries(min_query_impressions) {
+        let Some(examples) = labeled_results(&searcher, &query, NUM_RESULTS) else {
+            continue;
+        };
+
+        write_group(&mut wrt, format, groups.len() + 1, &examples)?;
+        groups.push(examples.len());
+    }
+
+    wrt.flush()?;
+
+    if format == LtrFormat::LightGbm {
+        let mut wrt = BufWriter::new(File::create(with_extension(output_path, "query"))?);
+
+        for size in &groups {
+            writeln!(wrt, "{size}")?;
+        }
+
+        wrt.flush()?;
+    }
+
+    let mut wrt = BufWriter::new(File::create(with_extension(output_path, "features"))?);
+
+    for signal in ALL_SIGNALS {
+        writeln!(wrt, "{} {}", usize::from(signal) + 1, signal.name())?;
+    }
+
+    wrt.flush()?;
+
+    info!(
+        "exported {} queries in {format} format to {}",
+        groups.len(),
+        output_path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals() -> HashMap<Signal, SignalScore> {
+        [
+            (
+                Signal::Bm25Title,
+                SignalScore {
+                    coefficient: 1.0,
+                    value: 0.5,
+                },
+            ),
+            (
+                Signal::HostCentrality,
+                SignalScore {
+                    coefficient: 1.0,
+                    value: 0.25,
+                },
+            ),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn svm_rank_format() {
+        let signals = signals();
+        let examples = vec![
+            Example {
+                relevance: 3,
+                url: "https://a.com/".to_string(),
+                signals: signals.clone(),
+            },
+            Example {
+                relevance: 0,
+                url: "https://b.com/".to_string(),
+                signals,
+            },
+        ];
+
+        let mut out = Vec::new();
+        write_group(&mut out, LtrFormat::SvmRank, 7, &examples).unwrap();
+
+        let bm25_title = usize::from(Signal::Bm25Title) + 1;
+        let host_centrality = usize::from(Signal::HostCentrality) + 1;
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "3 qid:7 {bm25_title}:0.5 {host_centrality}:0.25 # https://a.com/\n\
+                 0 qid:7 {bm25_title}:0.5 {host_centrality}:0.25 # https://b.com/\n"
+            )
+        );
+    }
+
+    #[test]
+    fn lightgbm_format() {
+        let examples = vec![Example {
+            relevance: 1,
+            url: "https://a.com/".to_string(),
+            signals: signals(),
+        }];
+
+        let mut out = Vec::new();
+        write_group(&mut out, LtrFormat::LightGbm, 1, &examples).unwrap();
+
+        let bm25_title = usize::from(Signal::Bm25Title) + 1;
+        let host_centrality = usize::from(Signal::HostCentrality) + 1;
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("1 {bm25_title}:0.5 {host_centrality}:0.25\n")
+        );
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!("svmrank".parse::<LtrFormat>().unwrap(), LtrFormat::SvmRank);
+        assert_eq!(
+            "LightGBM".parse::<LtrFormat>().unwrap(),
+            LtrFormat::LightGbm
+        );
+        assert!("csv".parse::<LtrFormat>().is_err());
+    }
+}
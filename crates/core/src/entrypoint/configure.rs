@@ -138,7 +138,7 @@ fn calculate_centrality() {
     let out_path = Path::new(DATA_PATH).join("centrality");
 
     if !out_path.exists() {
-        Centrality::build_harmonic(&webgraph_path, &out_path);
+        Centrality::build_harmonic(&webgraph_path, &out_path, None);
         Centrality::build_similarity(&webgraph_path, &out_path);
     }
 
@@ -14,13 +14,17 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc, time::Duration};
 
+use axum::{extract::State, http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config,
-    crawler::{self, planner::make_crawl_plan, CrawlCoordinator, Crawler},
+    crawler::{
+        self, frontier, planner::make_crawl_plan, recrawl::RecrawlHistory, seeds::import_seeds,
+        stats::CoordinatorStats, CrawlCoordinator, Crawler,
+    },
     distributed::sonic::{self, service::Message},
     kv::rocksdb_store::RocksDbStore,
     sonic_service,
@@ -37,7 +41,21 @@ pub async fn worker(config: config::CrawlerConfig) -> Result<()> {
 }
 
 pub async fn coordinator(config: config::CrawlCoordinatorConfig) -> Result<()> {
-    let coordinator = Arc::new(CrawlCoordinator::new(config.job_queue)?);
+    let coordinator = Arc::new(CrawlCoordinator::new(
+        config.job_queue,
+        Duration::from_secs(config.job_lease_timeout_sec),
+    )?);
+
+    if let Some(addr) = config.stats_host {
+        let router = axum::Router::new()
+            .route("/stats", axum::routing::get(stats))
+            .with_state(Arc::clone(&coordinator));
+
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        tracing::info!("crawl stats listening on {}", addr);
+
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+    }
 
     let addr: SocketAddr = config.host;
     let server = coordinator::CoordinatorService { coordinator }
@@ -52,6 +70,20 @@ pub async fn coordinator(config: config::CrawlCoordinatorConfig) -> Result<()> {
     }
 }
 
+async fn stats(
+    State(coordinator): State<Arc<CrawlCoordinator>>,
+) -> std::result::Result<Json<CoordinatorStats>, StatusCode> {
+    // reading the pending jobs can take a while for large queues
+    match tokio::task::spawn_blocking(move || coordinator.stats()).await {
+        Ok(Ok(stats)) => Ok(Json(stats)),
+        Ok(Err(err)) => {
+            tracing::error!("failed to compute crawl stats: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
 pub async fn router(config: config::CrawlRouterConfig) -> Result<()> {
     let router = crawler::Router::new(config.coordinator_addrs.clone()).await?;
 
@@ -73,6 +105,10 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
         .single_threaded()
         .open();
     let host_graph = WebgraphBuilder::new(&config.host_graph_path).open();
+    let recrawl_history = config
+        .recrawl_history_path
+        .as_ref()
+        .map(RecrawlHistory::open);
     let output_path = config.output_path.clone();
 
     make_crawl_plan(
@@ -80,6 +116,7 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
         page_centrality,
         host_graph,
         page_graph,
+        recrawl_history,
         config,
         output_path,
     )?;
@@ -87,15 +124,83 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
     Ok(())
 }
 
+pub fn seeds(config: config::SeedImportConfig) -> Result<()> {
+    let output_path = config.output_path.clone();
+
+    import_seeds(config, output_path)
+}
+
+pub fn export_frontier(
+    job_queue_path: String,
+    output_path: String,
+    recrawl_history_path: Option<String>,
+) -> Result<()> {
+    let history = recrawl_history_path.map(RecrawlHistory::open);
+    let stats = frontier::export(job_queue_path, history.as_ref(), output_path)?;
+
+    tracing::info!(
+        "exported {} jobs with {} urls and {} fetch histories",
+        stats.jobs,
+        stats.urls,
+        stats.histories
+    );
+
+    Ok(())
+}
+
+pub fn import_frontier(
+    input_path: String,
+    job_queue_path: String,
+    recrawl_history_path: Option<String>,
+) -> Result<()> {
+    let history = recrawl_history_path.map(RecrawlHistory::open);
+    let stats = frontier::import(input_path, job_queue_path, history.as_ref())?;
+
+    tracing::info!(
+        "imported {} jobs with {} urls and {} fetch histories",
+        stats.jobs,
+        stats.urls,
+        stats.histories
+    );
+
+    Ok(())
+}
+
+pub fn compact_recrawl_history(config: config::RecrawlCompactionConfig) -> Result<()> {
+    let histories: Vec<_> = config
+        .history_paths
+        .iter()
+        .map(RecrawlHistory::open)
+        .collect();
+
+    let (_, stats) = RecrawlHistory::compact(
+        &histories,
+        &config.output_path,
+        config
+            .retention_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        chrono::Utc::now().timestamp().max(0) as u64,
+    )?;
+
+    tracing::info!(
+        "compacted {} fetch histories into {} urls ({} purged)",
+        stats.read,
+        stats.written,
+        stats.purged
+    );
+
+    Ok(())
+}
+
 pub mod router {
-    use crate::crawler::Job;
+    use crate::crawler::{Domain, Job};
 
     use super::*;
     pub struct RouterService {
         pub router: crawler::Router,
     }
 
-    sonic_service!(RouterService, [NewJob]);
+    sonic_service!(RouterService, [NewJob, JobDone]);
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct NewJob {}
@@ -107,10 +212,23 @@ pub mod router {
             Ok(server.router.sample_job().await?)
         }
     }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct JobDone {
+        pub domain: Domain,
+    }
+
+    impl Message<RouterService> for JobDone {
+        type Response = ();
+
+        async fn handle(self, server: &RouterService) -> sonic::Result<Self::Response> {
+            Ok(server.router.complete_job(self.domain).await?)
+        }
+    }
 }
 
 pub mod coordinator {
-    use crate::crawler::Job;
+    use crate::crawler::{Domain, Job};
 
     use super::*;
 
@@ -118,7 +236,7 @@ pub mod coordinator {
         pub coordinator: Arc<CrawlCoordinator>,
     }
 
-    sonic_service!(CoordinatorService, [GetJob]);
+    sonic_service!(CoordinatorService, [GetJob, CompleteJob, GetStats]);
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct GetJob {}
@@ -131,4 +249,28 @@ pub mod coordinator {
             Ok(job)
         }
     }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct CompleteJob {
+        pub domain: Domain,
+    }
+
+    impl Message<CoordinatorService> for CompleteJob {
+        type Response = ();
+
+        async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
+            Ok(server.coordinator.complete_job(self.domain.as_str())?)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct GetStats {}
+
+    impl Message<CoordinatorService> for GetStats {
+        type Response = CoordinatorStats;
+
+        async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
+            Ok(server.coordinator.stats()?)
+        }
+    }
 }
@@ -0,0 +1,538 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Report what is using disk space in an index or webgraph. The report is broken
+//! down by subsystem (postings, positions, fast fields etc. for the index and
+//! adjacency stores and RocksDB column families for the webgraph) and ends with
+//! a few suggestions based on the breakdown.
+
+use std::{fs, path::Path};
+
+use anyhow::anyhow;
+
+use crate::Result;
+
+const INVERTED_INDEX_SUBFOLDER_NAME: &str = "inverted_index";
+const WEBGRAPH_SEGMENTS_FOLDER_NAME: &str = "segments";
+const WEBGRAPH_METADATA_FILE_NAME: &str = "metadata.json";
+
+/// Segments above this count should be merged.
+const MAX_SEGMENTS_BEFORE_MERGE: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub name: String,
+    pub bytes: u64,
+    pub children: Vec<Usage>,
+}
+
+impl Usage {
+    fn leaf(name: impl Into<String>, bytes: u64) -> Self {
+        Self {
+            name: name.into(),
+            bytes,
+            children: Vec::new(),
+        }
+    }
+
+    /// Create a node where the size is the sum of the children.
+    /// Children are sorted by size, largest first.
+    fn node(name: impl Into<String>, mut children: Vec<Usage>) -> Self {
+        children.retain(|c| c.bytes > 0);
+        children.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+
+        Self {
+            name: name.into(),
+            bytes: children.iter().map(|c| c.bytes).sum(),
+            children,
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Usage> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn fmt_tree(&self, total: u64, depth: usize, out: &mut String) {
+        let share = if total == 0 {
+            0.0
+        } else {
+            self.bytes as f64 / total as f64 * 100.0
+        };
+
+        out.push_str(&format!(
+            "{:indent$}{:<width$} {:>10} {:>6.1}%\n",
+            "",
+            self.name,
+            human_bytes(self.bytes),
+            share,
+            indent = depth * 2,
+            width = 40usize.saturating_sub(depth * 2),
+        ));
+
+        for child in &self.children {
+            child.fmt_tree(total, depth + 1, out);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub usage: Usage,
+    pub suggestions: Vec<String>,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.usage.fmt_tree(self.usage.bytes, 0, &mut out);
+
+        if !self.suggestions.is_empty() {
+            out.push_str("\nsuggestions:\n");
+
+            for suggestion in &self.suggestions {
+                out.push_str(&format!("  - {suggestion}\n"));
+            }
+        }
+
+        write!(f, "{out}")
+    }
+}
+
+pub fn run<P: AsRef<Path>>(path: P) -> Result<()> {
+    let report = analyze(path)?;
+    println!("{report}");
+
+    Ok(())
+}
+
+/// Detect whether the path is a search index, a webgraph or a plain tantivy
+/// index and analyze it accordingly.
+pub fn analyze<P: AsRef<Path>>(path: P) -> Result<Report> {
+    let path = path.as_ref();
+
+    if !path.exists() {
+        return Err(anyhow!("{} does not exist", path.display()));
+    }
+
+    if path.join(INVERTED_INDEX_SUBFOLDER_NAME).exists() {
+        search_index(path)
+    } else if path.join(WEBGRAPH_METADATA_FILE_NAME).exists()
+        && path.join(WEBGRAPH_SEGMENTS_FOLDER_NAME).exists()
+    {
+        webgraph(path)
+    } else if path.join("meta.json").exists() {
+        let (usage, suggestions) = tantivy_index(path, "inverted index")?;
+
+        Ok(Report { usage, suggestions })
+    } else {
+        Ok(Report {
+            usage: dir_usage(path)?,
+            suggestions: Vec::new(),
+        })
+    }
+}
+
+fn search_index(path: &Path) -> Result<Report> {
+    let (inverted_index, suggestions) =
+        tantivy_index(&path.join(INVERTED_INDEX_SUBFOLDER_NAME), "inverted index")?;
+
+    let mut children = vec![inverted_index];
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+
+        if entry.file_name() == INVERTED_INDEX_SUBFOLDER_NAME {
+            continue;
+        }
+
+        children.push(dir_usage(&entry.path())?);
+    }
+
+    Ok(Report {
+        usage: Usage::node(path.display().to_string(), children),
+        suggestions,
+    })
+}
+
+fn tantivy_index(path: &Path, name: &str) -> Result<(Usage, Vec<String>)> {
+    let index = tantivy::Index::open_in_dir(path)?;
+    let schema = index.schema();
+    let searcher = index.reader()?.searcher();
+    let space_usage = searcher.space_usage()?;
+
+    let mut termdict = Vec::new();
+    let mut postings = Vec::new();
+    let mut positions = Vec::new();
+    let mut fast_fields = Vec::new();
+    let mut fieldnorms = Vec::new();
+    let mut store_data = 0;
+    let mut store_offsets = 0;
+    let mut deletes = 0;
+
+    for segment in space_usage.segments() {
+        for (usages, per_field) in [
+            (&mut termdict, segment.termdict()),
+            (&mut postings, segment.postings()),
+            (&mut positions, segment.positions()),
+            (&mut fast_fields, segment.fast_fields()),
+            (&mut fieldnorms, segment.fieldnorms()),
+        ] {
+            for (field, usage) in per_field.fields() {
+                usages.push(Usage::leaf(
+                    schema.get_field_name(*field),
+                    usage.total().get_bytes(),
+                ));
+            }
+        }
+
+        store_data += segment.store().data_usage().get_bytes();
+        store_offsets += segment.store().offsets_usage().get_bytes();
+        deletes += segment.deletes().get_bytes();
+    }
+
+    let usage = Usage::node(
+        name,
+        vec![
+            Usage::node("term dictionary", merge_fields(termdict)),
+            Usage::node("postings", merge_fields(postings)),
+            Usage::node("positions", merge_fields(positions)),
+            Usage::node("fast fields", merge_fields(fast_fields)),
+            Usage::node("fieldnorms", merge_fields(fieldnorms)),
+            Usage::node(
+                "stored fields",
+                vec![
+                    Usage::leaf("data", store_data),
+                    Usage::leaf("offsets", store_offsets),
+                ],
+            ),
+            Usage::leaf("deletes", deletes),
+        ],
+    );
+
+    let suggestions = index_suggestions(&usage, space_usage.segments().len(), deletes);
+
+    Ok((usage, suggestions))
+}
+
+/// Sum the usage of fields with the same name across segments.
+fn merge_fields(usages: Vec<Usage>) -> Vec<Usage> {
+    let mut merged: Vec<Usage> = Vec::new();
+
+    for usage in usages {
+        match merged.iter_mut().find(|u| u.name == usage.name) {
+            Some(existing) => existing.bytes += usage.bytes,
+            None => merged.push(usage),
+        }
+    }
+
+    merged
+}
+
+fn index_suggestions(usage: &Usage, num_segments: usize, deletes: u64) -> Vec<String> {
+    let mut suggestions = Vec::new();
+    let share = |bytes: u64| {
+        if usage.bytes == 0 {
+            0.0
+        } else {
+            bytes as f64 / usage.bytes as f64
+        }
+    };
+
+    if let Some(store) = usage.child("stored fields") {
+        if share(store.bytes) > 0.5 {
+            suggestions.push(format!(
+                "stored fields use {:.0}% of the index. Consider a stronger docstore compressor or storing fewer fields.",
+                share(store.bytes) * 100.0
+            ));
+        }
+    }
+
+    if let Some(positions) = usage.child("positions") {
+        for field in &positions.children {
+            if share(field.bytes) > 0.1 {
+                suggestions.push(format!(
+                    "positions for '{}' use {}. If the field is not used for phrase queries, index it without positions.",
+                    field.name,
+                    human_bytes(field.bytes)
+                ));
+            }
+        }
+    }
+
+    if let Some(fast_fields) = usage.child("fast fields") {
+        if share(fast_fields.bytes) > 0.3 {
+            if let Some(largest) = fast_fields.children.first() {
+                suggestions.push(format!(
+                    "fast fields use {:.0}% of the index and '{}' is the largest. Check whether all fast fields are needed for ranking.",
+                    share(fast_fields.bytes) * 100.0,
+                    largest.name
+                ));
+            }
+        }
+    }
+
+    if share(deletes) > 0.05 || num_segments > MAX_SEGMENTS_BEFORE_MERGE {
+        suggestions.push(format!(
+            "the index has {num_segments} segments. Merge the segments to reclaim space from deleted documents and duplicated term dictionaries."
+        ));
+    }
+
+    suggestions
+}
+
+fn webgraph(path: &Path) -> Result<Report> {
+    let mut segments = Vec::new();
+
+    for entry in fs::read_dir(path.join(WEBGRAPH_SEGMENTS_FOLDER_NAME))? {
+        let entry = entry?;
+
+        if entry.path().is_dir() {
+            segments.push(dir_usage(&entry.path())?);
+        }
+    }
+
+    let num_segments = segments.len();
+    let mut children = vec![Usage::node("segments", segments)];
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+
+        if entry.file_name() == WEBGRAPH_SEGMENTS_FOLDER_NAME {
+            continue;
+        }
+
+        children.push(dir_usage(&entry.path())?);
+    }
+
+    let usage = Usage::node(path.display().to_string(), children);
+    let suggestions = webgraph_suggestions(&usage, num_segments);
+
+    Ok(Report { usage, suggestions })
+}
+
+fn webgraph_suggestions(usage: &Usage, num_segments: usize) -> Vec<String> {
+    let mut suggestions = Vec::new();
+
+    if num_segments > 1 {
+        suggestions.push(format!(
+            "the webgraph has {num_segments} segments. Merging them removes duplicated node ranges and prefixes."
+        ));
+    }
+
+    let mut labels = 0;
+    let mut nodes = 0;
+    let mut rocksdb = 0;
+    sum_adjacency(usage, &mut labels, &mut nodes, &mut rocksdb);
+
+    if labels > 2 * nodes && labels > 0 {
+        suggestions.push(format!(
+            "edge labels use {} compared to {} for edge nodes. Make sure the webgraph is built with lz4 compression.",
+            human_bytes(labels),
+            human_bytes(nodes)
+        ));
+    }
+
+    if rocksdb > labels + nodes && rocksdb > 0 {
+        suggestions.push(format!(
+            "rocksdb uses {} which is more than the adjacency data. Compacting the databases might reclaim space.",
+            human_bytes(rocksdb)
+        ));
+    }
+
+    suggestions
+}
+
+fn sum_adjacency(usage: &Usage, labels: &mut u64, nodes: &mut u64, rocksdb: &mut u64) {
+    for child in &usage.children {
+        match child.name.as_str() {
+            "labels" if child.children.is_empty() => *labels += child.bytes,
+            "nodes" if child.children.is_empty() => *nodes += child.bytes,
+            name if name.ends_with("(rocksdb)") => *rocksdb += child.bytes,
+            _ => sum_adjacency(child, labels, nodes, rocksdb),
+        }
+    }
+}
+
+fn is_rocksdb(path: &Path) -> bool {
+    path.join("CURRENT").exists() && path.join("IDENTITY").exists()
+}
+
+/// Usage of a file or directory. RocksDB databases are broken
+/// down by column family.
+fn dir_usage(path: &Path) -> Result<Usage> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string());
+
+    if path.is_file() {
+        return Ok(Usage::leaf(name, path.metadata()?.len()));
+    }
+
+    if is_rocksdb(path) {
+        return rocksdb_usage(path, name);
+    }
+
+    let mut children = Vec::new();
+    for entry in fs::read_dir(path)? {
+        children.push(dir_usage(&entry?.path())?);
+    }
+
+    Ok(Usage::node(name, children))
+}
+
+fn rocksdb_usage(path: &Path, name: String) -> Result<Usage> {
+    let total = file_sizes(path)?;
+    let opts = rocksdb::Options::default();
+
+    let column_families = match rocksdb::DB::list_cf(&opts, path) {
+        Ok(cfs) => cfs,
+        Err(_) => return Ok(Usage::leaf(format!("{name} (rocksdb)"), total)),
+    };
+
+    let db = match rocksdb::DB::open_cf_for_read_only(&opts, path, &column_families, false) {
+        Ok(db) => db,
+        Err(err) => {
+            tracing::debug!("could not open {} read-only: {err}", path.display());
+            return Ok(Usage::leaf(format!("{name} (rocksdb)"), total));
+        }
+    };
+
+    let mut children = Vec::new();
+    for cf_name in &column_families {
+        let sst = db
+            .cf_handle(cf_name)
+            .and_then(|cf| {
+                db.property_int_value_cf(&cf, "rocksdb.total-sst-files-size")
+                    .ok()
+                    .flatten()
+            })
+            .unwrap_or(0);
+
+        children.push(Usage::leaf(format!("column family '{cf_name}'"), sst));
+    }
+
+    let sst_total: u64 = children.iter().map(|c| c.bytes).sum();
+    children.push(Usage::leaf(
+        "wal, logs and manifest",
+        total.saturating_sub(sst_total),
+    ));
+
+    Ok(Usage::node(format!("{name} (rocksdb)"), children))
+}
+
+pub(super) fn file_sizes(path: &Path) -> Result<u64> {
+    if path.is_file() {
+        return Ok(path.metadata()?.len());
+    }
+
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += file_sizes(&entry?.path())?;
+    }
+
+    Ok(total)
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{index::Index, webpage::Webpage};
+
+    use super::*;
+
+    #[test]
+    fn human_readable() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+        assert_eq!(human_bytes(1024), "1.0 KB");
+        assert_eq!(human_bytes(1536 * 1024), "1.5 MB");
+        assert_eq!(human_bytes(3 * 1024 * 1024 * 1024 * 1024), "3.0 TB");
+    }
+
+    #[test]
+    fn node_sums_and_sorts_children() {
+        let usage = Usage::node(
+            "root",
+            vec![
+                Usage::leaf("a", 1),
+                Usage::leaf("empty", 0),
+                Usage::leaf("b", 10),
+            ],
+        );
+
+        assert_eq!(usage.bytes, 11);
+        assert_eq!(
+            usage
+                .children
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["b", "a"]
+        );
+    }
+
+    #[test]
+    fn search_index_breakdown() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        index
+            .insert(
+                Webpage::new(
+                    r#"
+                    <html>
+                        <head>
+                            <title>Test website</title>
+                        </head>
+                        <body>
+                            example example example
+                        </body>
+                    </html>
+                "#,
+                    "https://www.example.com",
+                )
+                .unwrap(),
+            )
+            .expect("failed to insert webpage");
+        index.commit().expect("failed to commit index");
+
+        let report = analyze(&index.path).unwrap();
+
+        let inverted_index = report.usage.child("inverted index").unwrap();
+        assert!(inverted_index.bytes > 0);
+        assert!(inverted_index.child("postings").is_some());
+        assert!(inverted_index.child("stored fields").is_some());
+
+        assert!(report.to_string().contains("inverted index"));
+    }
+}
@@ -14,6 +14,9 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
 use kuchiki::{traits::TendrilSink, NodeRef};
 use zimba::{Article, ArticleIterator, ZimFile};
 
@@ -26,6 +29,9 @@ use crate::{
     Result,
 };
 
+/// Infobox rows that list other names of the entity.
+const ALIAS_INFO_KEYS: [&str; 4] = ["other names", "also known as", "nickname", "nickname(s)"];
+
 struct EntityIterator<'a> {
     articles: ArticleIterator<'a>,
 }
@@ -98,13 +104,37 @@ fn article_to_entity(article: Article) -> Entity {
         })
         .unwrap_or_default();
 
-    let page_abstract = root
+    let abstract_node = root
         .select("p")
         .unwrap()
-        .find(|p| p.text_contents().trim().len() > 10)
+        .find(|p| p.text_contents().trim().len() > 10);
+
+    let page_abstract = abstract_node
+        .as_ref()
         .map(|n| node_into_span(n.as_node()))
         .unwrap_or_default();
 
+    // the first paragraph names the subject of the article in bold, including its other names
+    let bold_names: Vec<_> = abstract_node
+        .map(|n| {
+            n.as_node()
+                .select("b")
+                .unwrap()
+                .map(|b| b.text_contents())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let aliases = info
+        .iter()
+        .filter(|(key, _)| ALIAS_INFO_KEYS.contains(&key.to_lowercase().as_str()))
+        .flat_map(|(_, value)| value.text().split(',').map(str::to_string))
+        .chain(bold_names)
+        .map(|alias| alias.trim().to_string())
+        .filter(|alias| !alias.is_empty() && *alias != title)
+        .unique()
+        .collect();
+
     let is_disambiguation = root
         .select("meta")
         .map(|mut metas| {
@@ -125,6 +155,8 @@ fn article_to_entity(article: Article) -> Entity {
         page_abstract,
         image,
         info,
+        aliases,
+        inbound_links: 0,
     }
 }
 
@@ -142,6 +174,15 @@ impl<'a> Iterator for EntityIterator<'a> {
     }
 }
 
+/// Articles link to each other by their url, optionally with a fragment.
+fn link_key(target: &str) -> &str {
+    target
+        .split('#')
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches("./")
+}
+
 fn node_into_span(node: &NodeRef) -> Span {
     let mut span = Span::default();
 
@@ -196,12 +237,36 @@ impl EntityIndexer {
         let mut index = EntityIndex::open(output_path)?;
         index.prepare_writer();
 
+        // the dump is read twice, since the number of links to an
+        // entity is needed when it is inserted.
+        let mut inbound_links: HashMap<String, u64> = HashMap::new();
+
+        for entity in EntityIterator::new(&zim)? {
+            let targets: HashSet<_> = entity
+                .page_abstract
+                .links
+                .iter()
+                .chain(entity.info.iter().flat_map(|(_, span)| span.links.iter()))
+                .map(|link| link_key(&link.target))
+                .filter(|target| *target != link_key(&entity.article_url))
+                .collect();
+
+            for target in targets {
+                *inbound_links.entry(target.to_string()).or_default() += 1;
+            }
+        }
+
         let mut inserts = 0;
 
-        for entity in EntityIterator::new(&zim)?
+        for mut entity in EntityIterator::new(&zim)?
             .filter(|e| !e.is_disambiguation)
             .filter(|e| !e.article_url.starts_with("Portal:"))
         {
+            entity.inbound_links = inbound_links
+                .get(link_key(&entity.article_url))
+                .copied()
+                .unwrap_or_default();
+
             index.insert(entity);
             inserts += 1;
 
@@ -66,7 +66,10 @@ pub struct Search {
 impl sonic::service::Message<SearchService> for Search {
     type Response = Option<crate::entity_index::EntityMatch>;
     async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
-        Ok(server.index.search(&self.query))
+        Ok(server
+            .index
+            .search(&self.query)
+            .or_else(|| server.index.link(&self.query)))
     }
 }
 
@@ -17,6 +17,7 @@ use anyhow::anyhow;
 use chrono::Utc;
 use rayon::prelude::*;
 use std::path::Path;
+use std::sync::Arc;
 use std::thread;
 
 use itertools::Itertools;
@@ -25,15 +26,17 @@ use tokio::pin;
 use tracing::{debug, info, trace, warn};
 
 use crate::config::{self, WarcSource};
+use crate::crawler::{recrawl::RecrawlHistory, Validators};
 use crate::entrypoint::download_all_warc_files;
 use crate::index::Index;
 use crate::kv::rocksdb_store::RocksDbStore;
 use crate::kv::Kv;
 use crate::mapreduce::{Map, Reduce, Worker};
+use crate::ranking::models::embedding::{self, Embedder, EmbeddingModel};
 use crate::ranking::SignalAggregator;
-use crate::warc::PayloadType;
+use crate::warc::{PayloadType, WarcRecord};
 use crate::webgraph::{Node, NodeID, Webgraph, WebgraphBuilder};
-use crate::webpage::{safety_classifier, Html, Webpage};
+use crate::webpage::{protocol::ProtocolInfo, safety_classifier, Html, Webpage};
 use crate::{human_website_annotations, Result};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -53,11 +56,15 @@ pub struct JobSettings {
 pub struct IndexingWorker {
     host_centrality_store: RocksDbStore<NodeID, f64>,
     host_centrality_rank_store: RocksDbStore<NodeID, f64>,
+    host_trust_store: Option<RocksDbStore<NodeID, f64>>,
+    host_link_spam_store: Option<RocksDbStore<NodeID, f64>>,
     page_centrality_store: Option<RocksDbStore<NodeID, f64>>,
     page_centrality_rank_store: Option<RocksDbStore<NodeID, f64>>,
     page_webgraph: Option<Webgraph>,
     topics: Option<human_website_annotations::Mapper>,
     safety_classifier: Option<safety_classifier::Model>,
+    embedder: Option<Arc<dyn Embedder>>,
+    recrawl_history: Option<RecrawlHistory>,
     job_settings: Option<JobSettings>,
 }
 
@@ -69,7 +76,17 @@ impl IndexingWorker {
         topics_path: Option<String>,
         safety_classifier_path: Option<String>,
     ) -> Self {
+        // the trustrank is only computed if a set of trusted seed hosts has been curated
+        let host_trust_path = Path::new(&host_centrality_store_path).join("trustrank");
+        let host_link_spam_path = Path::new(&host_centrality_store_path).join("link_spam");
+
         Self {
+            host_trust_store: host_trust_path
+                .exists()
+                .then(|| RocksDbStore::open(host_trust_path)),
+            host_link_spam_store: host_link_spam_path
+                .exists()
+                .then(|| RocksDbStore::open(host_link_spam_path)),
             host_centrality_store: RocksDbStore::open(
                 Path::new(&host_centrality_store_path).join("harmonic"),
             ),
@@ -87,6 +104,8 @@ impl IndexingWorker {
             topics: topics_path.map(|path| human_website_annotations::Mapper::open(path).unwrap()),
             safety_classifier: safety_classifier_path
                 .map(|path| safety_classifier::Model::open(path).unwrap()),
+            embedder: None,
+            recrawl_history: None,
             job_settings: None,
         }
     }
@@ -95,7 +114,46 @@ impl IndexingWorker {
         self.job_settings = Some(job_settings);
     }
 
-    pub fn prepare_webpage(&self, body: &str, url: &str, fetch_time_ms: u64) -> Result<Webpage> {
+    pub fn set_embedder(&mut self, embedder: Arc<dyn Embedder>) {
+        self.embedder = Some(embedder);
+    }
+
+    pub fn set_recrawl_history(&mut self, recrawl_history: RecrawlHistory) {
+        self.recrawl_history = Some(recrawl_history);
+    }
+
+    /// Records written before the fetch time was stored in the warc fall back
+    /// to the indexing time, which is shortly after the page was crawled.
+    fn record_fetch(&self, record: &WarcRecord) {
+        if let Some(recrawl_history) = &self.recrawl_history {
+            if let Ok(url) = url::Url::parse(&record.request.url) {
+                let fetched_at = record
+                    .metadata
+                    .fetched_at
+                    .unwrap_or_else(|| Utc::now().timestamp().max(0) as u64);
+                let validators = Validators {
+                    etag: record.response.etag.clone(),
+                    last_modified: record.response.last_modified.clone(),
+                };
+
+                if record.metadata.not_modified {
+                    recrawl_history.observe_not_modified(&url, fetched_at, validators);
+                } else {
+                    recrawl_history.observe(&url, fetched_at, &record.response.body, validators);
+                }
+            }
+        }
+    }
+
+    pub fn prepare_webpage(
+        &self,
+        body: &str,
+        url: &str,
+        fetch_time_ms: u64,
+        last_modified: Option<&str>,
+        sitemap_lastmod: Option<&str>,
+        protocol: ProtocolInfo,
+    ) -> Result<Webpage> {
         let mut html = match Html::parse_without_text(body, url) {
             Ok(html) => html,
             Err(err) => {
@@ -104,6 +162,14 @@ impl IndexingWorker {
             }
         };
 
+        if let Some(last_modified) = last_modified {
+            html.set_http_last_modified(last_modified);
+        }
+
+        if let Some(sitemap_lastmod) = sitemap_lastmod {
+            html.set_sitemap_lastmod(sitemap_lastmod);
+        }
+
         if html.is_no_index() {
             return Err(anyhow!("noindex"));
         }
@@ -155,37 +221,21 @@ impl IndexingWorker {
         let backlink_labels: Vec<String> = self
             .page_webgraph
             .as_ref()
-            .map(|webgraph| {
-                webgraph
-                    .raw_ingoing_edges_with_labels(&Node::from(html.url()).id())
-                    .into_iter()
-                    .map(|edge| edge.label)
-                    .filter(|label| !label.is_empty())
-                    .filter(|label| {
-                        let label = label.to_lowercase();
-                        let stopwords = [
-                            "click",
-                            "click here",
-                            "here",
-                            "link",
-                            "website",
-                            "webpage",
-                            "page",
-                            "site",
-                            "url",
-                            "web",
-                            "visit",
-                            "more",
-                            "info",
-                            "information",
-                            "read",
-                            "read more",
-                        ];
-
-                        !stopwords.contains(&label.as_str())
-                    })
-                    .collect()
-            })
+            .map(|webgraph| webgraph.anchor_texts(&Node::from(html.url()).id()))
+            .unwrap_or_default();
+
+        let host_trust = self
+            .host_trust_store
+            .as_ref()
+            .and_then(|store| store.get(&host_node_id))
+            .filter(|trust| trust.is_finite())
+            .unwrap_or_default();
+
+        let host_link_spam = self
+            .host_link_spam_store
+            .as_ref()
+            .and_then(|store| store.get(&host_node_id))
+            .filter(|spam| spam.is_finite())
             .unwrap_or_default();
 
         let mut page_centrality = 0.0;
@@ -235,18 +285,29 @@ impl IndexingWorker {
             page_centrality_rank,
             host_centrality,
             host_centrality_rank,
+            host_trust,
+            host_link_spam,
             fetch_time_ms,
             pre_computed_score: 0.0,
             node_id: Some(host_node_id),
             dmoz_description,
             safety_classification: None,
             inserted_at: Utc::now(),
+            embedding: None,
+            protocol,
         };
 
         if let Some(model) = self.safety_classifier.as_ref() {
             webpage.safety_classification = Some(model.predict(&webpage).label);
         }
 
+        if let Some(embedder) = self.embedder.as_ref() {
+            match embedder.embed(&[embedding::document_text(&webpage)]) {
+                Ok(mut embeddings) => webpage.embedding = embeddings.pop(),
+                Err(err) => debug!("failed to embed {}: {}", url, err),
+            }
+        }
+
         let mut signal_aggregator = SignalAggregator::new(None);
         signal_aggregator.set_current_timestamp(Utc::now().timestamp().max(0) as usize);
 
@@ -280,10 +341,20 @@ pub fn process_job(job: &Job, worker: &IndexingWorker) -> Index {
                     None => true,
                 })
         {
+            worker.record_fetch(&record);
+
+            // the page is unchanged and already in the index.
+            if record.metadata.not_modified {
+                continue;
+            }
+
             if let Ok(webpage) = worker.prepare_webpage(
                 &record.response.body,
                 &record.request.url,
                 record.metadata.fetch_time_ms,
+                record.response.last_modified.as_deref(),
+                record.metadata.sitemap_lastmod.as_deref(),
+                record.metadata.protocol.clone(),
             ) {
                 if webpage.host_centrality > 0.0 {
                     has_host_centrality = true;
@@ -308,6 +379,10 @@ pub fn process_job(job: &Job, worker: &IndexingWorker) -> Index {
         }
 
         index.commit().unwrap();
+
+        if let Some(recrawl_history) = &worker.recrawl_history {
+            recrawl_history.flush();
+        }
     }
 
     if !has_host_centrality {
@@ -338,6 +413,12 @@ impl From<String> for IndexPointer {
     }
 }
 
+impl IndexPointer {
+    pub fn path(&self) -> &str {
+        &self.0
+    }
+}
+
 impl Worker for IndexingWorker {}
 
 impl Map<IndexingWorker, IndexPointer> for Job {
@@ -362,12 +443,12 @@ impl Reduce<Index> for Index {
 
 pub struct Indexer {}
 impl Indexer {
-    pub fn run(config: &config::IndexingLocalConfig) -> Result<()> {
+    pub fn run(config: &config::IndexingLocalConfig) -> Result<IndexPointer> {
         let warc_paths = config.warc_source.paths()?;
 
         let job_config: WarcSource = config.warc_source.clone();
 
-        let worker = IndexingWorker::new(
+        let mut worker = IndexingWorker::new(
             config.host_centrality_store_path.clone(),
             config.page_centrality_store_path.clone(),
             config.page_webgraph_path.clone(),
@@ -375,6 +456,14 @@ impl Indexer {
             config.safety_classifier_path.clone(),
         );
 
+        if let Some(path) = &config.embedding_model_path {
+            worker.set_embedder(Arc::new(EmbeddingModel::open(path)?));
+        }
+
+        if let Some(path) = &config.recrawl_history_path {
+            worker.set_recrawl_history(RecrawlHistory::open(path));
+        }
+
         let indexes = warc_paths
             .into_iter()
             .skip(config.skip_warc_files.unwrap_or(0))
@@ -399,11 +488,11 @@ impl Indexer {
             })
             .collect();
 
-        Self::merge(indexes)?;
-        Ok(())
+        Self::merge(indexes)
     }
 
-    pub fn merge(indexes: Vec<IndexPointer>) -> Result<()> {
+    /// Merge the indexes into a single index and return a pointer to it.
+    pub fn merge(indexes: Vec<IndexPointer>) -> Result<IndexPointer> {
         let num_indexes = indexes.len();
         let mut it = indexes.into_iter();
         let num_cores = num_cpus::get();
@@ -455,6 +544,6 @@ impl Indexer {
 
         index.inverted_index.merge_into_max_segments(1).unwrap();
 
-        Ok(())
+        Ok(IndexPointer(index.path.clone()))
     }
 }
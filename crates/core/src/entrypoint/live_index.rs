@@ -17,6 +17,7 @@
 use std::{path::Path, sync::Arc};
 
 use crate::{
+    cached_page::CachedPage,
     config::{LiveIndexConfig, LiveIndexSchedulerConfig},
     distributed::{
         cluster::Cluster,
@@ -26,21 +27,29 @@ use crate::{
     feed::{self, index::FeedIndex},
     inverted_index,
     kv::rocksdb_store::RocksDbStore,
-    live_index::{Index, IndexManager},
+    live_index::{Index, IndexManager, RECRAWL_QUEUE},
     ranking::inbound_similarity::InboundSimilarity,
     searcher::{InitialWebsiteResult, LocalSearcher},
     sonic_service,
+    task_queue::TaskQueue,
     webgraph::WebgraphBuilder,
 };
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use tracing::info;
+use url::Url;
 
-use super::search_server::{RetrieveWebsites, Search};
+use super::search_server::{DeleteUrls, GetCachedPage, RetrieveWebsites, Search};
 
-sonic_service!(SearchService, [RetrieveWebsites, Search]);
+sonic_service!(
+    SearchService,
+    [RetrieveWebsites, Search, DeleteUrls, GetCachedPage, Recrawl]
+);
 
 pub struct SearchService {
     local_searcher: LocalSearcher<Arc<Index>>,
+    index: Arc<Index>,
+    task_queue: Arc<TaskQueue>,
     // dropping the handle leaves the cluster
     #[allow(unused)]
     cluster_handle: Cluster,
@@ -53,7 +62,9 @@ impl SearchService {
         )?;
 
         let manager = IndexManager::new(config.clone())?;
-        let mut local_searcher = LocalSearcher::new(manager.index());
+        let index = manager.index();
+        let task_queue = manager.task_queue();
+        let mut local_searcher = LocalSearcher::new(index.clone());
 
         local_searcher.set_inbound_similarity(inbound_similarity);
 
@@ -74,6 +85,8 @@ impl SearchService {
 
         Ok(Self {
             local_searcher,
+            index,
+            task_queue,
             cluster_handle,
         })
     }
@@ -102,6 +115,49 @@ impl sonic::service::Message<SearchService> for Search {
     }
 }
 
+impl sonic::service::Message<SearchService> for DeleteUrls {
+    type Response = Option<usize>;
+    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+        match server.index.delete_urls(&self.urls) {
+            Ok(num_deleted) => Ok(Some(num_deleted)),
+            Err(err) => {
+                tracing::error!("failed to delete urls: {err}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl sonic::service::Message<SearchService> for GetCachedPage {
+    type Response = Option<CachedPage>;
+    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+        Ok(server.local_searcher.get_cached_page(&self.url))
+    }
+}
+
+/// Crawl the pages again and replace them in the live index.
+/// Responds with the number of urls that were queued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recrawl {
+    pub urls: Vec<Url>,
+}
+impl sonic::service::Message<SearchService> for Recrawl {
+    type Response = usize;
+    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+        Ok(self
+            .urls
+            .into_iter()
+            .filter(|url| match server.task_queue.push(RECRAWL_QUEUE, url) {
+                Ok(_) => true,
+                Err(err) => {
+                    tracing::error!("failed to queue {url} for recrawl: {err}");
+                    false
+                }
+            })
+            .count())
+    }
+}
+
 pub async fn serve(config: LiveIndexConfig) -> Result<()> {
     let addr = config.host;
 
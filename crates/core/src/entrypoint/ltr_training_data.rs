@@ -0,0 +1,258 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Build learning-to-rank training data for LightGBM.
+//!
+//! The queries from the query log are joined with the aggregated clicks and the
+//! ranking signals of the results in the index. The output folder contains a
+//! `train.tsv` and `valid.tsv` with a header of signal names that can be parsed with
+//! [`Signal::from_str`](crate::ranking::Signal), a `.query` file with the size of each
+//! query group next to each of them and a `lightgbm.conf` so the model can be
+//! trained with `lightgbm config=lightgbm.conf`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use tracing::info;
+
+use crate::{
+    clicks::{normalize_query, ClickStore},
+    config::LtrTrainingDataConfig,
+    index::Index,
+    prehashed,
+    ranking::{inbound_similarity::InboundSimilarity, Signal, SignalScore, ALL_SIGNALS},
+    searcher::LocalSearcher,
+    Result,
+};
+
+use super::click_export::{labeled_results, with_extension, Example};
+
+const TRAIN_FILE: &str = "train.tsv";
+const VALID_FILE: &str = "valid.tsv";
+const LIGHTGBM_CONFIG_FILE: &str = "lightgbm.conf";
+const LABEL_COLUMN: &str = "label";
+
+/// Whether the query is used for validation. The split only depends on the query,
+/// so the same query always ends up in the same set across runs.
+fn is_validation(query: &str, validation_fraction: f64) -> bool {
+    let hash = prehashed::hash(query).0 as u64;
+
+    (hash as f64 / u64::MAX as f64) < validation_fraction
+}
+
+fn header() -> String {
+    std::iter::once(LABEL_COLUMN.to_string())
+        .chain(ALL_SIGNALS.iter().map(|signal| signal.name()))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+fn row(label: u8, signals: &HashMap<Signal, SignalScore>) -> String {
+    std::iter::once(label.to_string())
+        .chain(ALL_SIGNALS.iter().map(|signal| {
+            signals
+                .get(signal)
+                .map(|score| score.value.to_string())
+                .unwrap_or_else(|| "nan".to_string())
+        }))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// A tsv file with its query groups.
+struct DatasetWriter {
+    wrt: BufWriter<File>,
+    groups: BufWriter<File>,
+    num_groups: usize,
+}
+
+impl DatasetWriter {
+    fn create(path: &Path) -> Result<Self> {
+        let mut wrt = BufWriter::new(File::create(path)?);
+        writeln!(wrt, "{}", header())?;
+
+        Ok(Self {
+            wrt,
+            groups: BufWriter::new(File::create(with_extension(path, "query"))?),
+            num_groups: 0,
+        })
+    }
+
+    fn write_group(&mut self, examples: &[Example]) -> Result<()> {
+        for example in examples {
+            writeln!(self.wrt, "{}", row(example.relevance, &example.signals))?;
+        }
+
+        writeln!(self.groups, "{}", examples.len())?;
+        self.num_groups += 1;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<usize> {
+        self.wrt.flush()?;
+        self.groups.flush()?;
+
+        Ok(self.num_groups)
+    }
+}
+
+fn lightgbm_config() -> String {
+    [
+        "task=train".to_string(),
+        "objective=lambdarank".to_string(),
+        format!("data={TRAIN_FILE}"),
+        format!("valid={VALID_FILE}"),
+        "header=true".to_string(),
+        format!("label_column=name:{LABEL_COLUMN}"),
+        "metric=ndcg".to_string(),
+        "eval_at=1,5,10".to_string(),
+        "output_model=model.txt".to_string(),
+    ]
+    .join("\n")
+}
+
+fn query_log(path: &str) -> Result<HashSet<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(normalize_query)
+        .filter(|query| !query.is_empty())
+        .collect())
+}
+
+fn searcher(config: &LtrTrainingDataConfig) -> Result<LocalSearcher<Index>> {
+    let mut searcher = LocalSearcher::new(Index::open(&config.index_path)?);
+
+    if let Some(path) = &config.host_centrality_store_path {
+        searcher.set_inbound_similarity(InboundSimilarity::open(
+            Path::new(path).join("inbound_similarity"),
+        )?);
+    }
+
+    Ok(searcher)
+}
+
+pub fn run(config: LtrTrainingDataConfig) -> Result<()> {
+    let output_path = Path::new(&config.output_path);
+    fs::create_dir_all(output_path)?;
+
+    let query_log = match &config.query_log_path {
+        Some(path) => Some(query_log(path)?),
+        None => None,
+    };
+
+    let clicks = ClickStore::open(&config.click_log_path);
+    let searcher = searcher(&config)?;
+
+    let mut train = DatasetWriter::create(&output_path.join(TRAIN_FILE))?;
+    let mut valid = DatasetWriter::create(&output_path.join(VALID_FILE))?;
+
+    for query in clicks.queries(config.min_query_impressions) {
+        if let Some(query_log) = &query_log {
+            if !query_log.contains(&query.query) {
+                continue;
+            }
+        }
+
+        let Some(examples) = labeled_results(&searcher, &query, config.num_results) else {
+            continue;
+        };
+
+        if is_validation(&query.query, config.validation_fraction) {
+            valid.write_group(&examples)?;
+        } else {
+            train.write_group(&examples)?;
+        }
+    }
+
+    let num_train = train.finish()?;
+    let num_valid = valid.finish()?;
+
+    fs::write(output_path.join(LIGHTGBM_CONFIG_FILE), lightgbm_config())?;
+
+    info!(
+        "wrote {num_train} training and {num_valid} validation queries to {}",
+        output_path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn header_matches_signals() {
+        let header = header();
+        let mut columns = header.split('\t');
+
+        assert_eq!(columns.next(), Some(LABEL_COLUMN));
+
+        let signals: Vec<_> = columns
+            .map(|name| Signal::from_str(name).unwrap())
+            .collect();
+        assert_eq!(signals, ALL_SIGNALS.to_vec());
+    }
+
+    #[test]
+    fn missing_signals_are_nan() {
+        let signals: HashMap<_, _> = [(
+            Signal::Bm25Title,
+            SignalScore {
+                coefficient: 1.0,
+                value: 0.5,
+            },
+        )]
+        .into_iter()
+        .collect();
+
+        let row = row(3, &signals);
+        let columns: Vec<_> = row.split('\t').collect();
+
+        assert_eq!(columns.len(), ALL_SIGNALS.len() + 1);
+        assert_eq!(columns[0], "3");
+
+        for (signal, value) in ALL_SIGNALS.iter().zip(&columns[1..]) {
+            if *signal == Signal::Bm25Title {
+                assert_eq!(*value, "0.5");
+            } else {
+                assert_eq!(*value, "nan");
+            }
+        }
+    }
+
+    #[test]
+    fn validation_split() {
+        let queries: Vec<_> = (0..1000).map(|i| format!("query {i}")).collect();
+
+        assert!(queries.iter().all(|q| !is_validation(q, 0.0)));
+        assert!(queries.iter().all(|q| is_validation(q, 1.0)));
+
+        let num_valid = queries.iter().filter(|q| is_validation(q, 0.1)).count();
+        assert!(num_valid > 50 && num_valid < 150);
+
+        for query in &queries {
+            assert_eq!(is_validation(query, 0.1), is_validation(query, 0.1));
+        }
+    }
+}
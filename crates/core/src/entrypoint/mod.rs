@@ -17,19 +17,25 @@
 //! The entrypoint module contains all entrypoints that runs the executables.
 pub mod api;
 pub mod autosuggest_scrape;
+pub mod build_all;
 mod centrality;
+pub mod click_export;
 #[cfg(feature = "dev")]
 pub mod configure;
 pub mod crawler;
+pub mod disk_usage;
 pub mod dmoz_parser;
 mod entity;
 pub mod entity_search_server;
 pub mod feed_indexer;
 pub mod indexer;
+pub mod ltr_training_data;
+pub mod result_diff;
 pub mod safety_classifier;
 pub mod search_server;
 pub mod web_spell;
 mod webgraph;
+pub mod webgraph_export;
 pub mod webgraph_server;
 
 pub use centrality::Centrality;
@@ -0,0 +1,410 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare the ranked results of two searchers on the same set of queries.
+//!
+//! This is used to evaluate an upgrade (new index generation, ranking model or config)
+//! before it is deployed. The report contains the diff of every query, sorted with
+//! the most changed queries first, and summary metrics over all the queries.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use optics::Optic;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    config::{DiffSearcherConfig, ResultDiffConfig},
+    index::Index,
+    ranking::{
+        inbound_similarity::InboundSimilarity,
+        models::{linear::LinearRegression, reloadable::Reloadable},
+    },
+    searcher::{LocalSearcher, SearchQuery},
+    Result,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankShift {
+    pub url: String,
+    /// 1-based rank of the url in the baseline results.
+    pub baseline_rank: usize,
+    /// 1-based rank of the url in the candidate results.
+    pub candidate_rank: usize,
+}
+
+impl RankShift {
+    fn distance(&self) -> usize {
+        self.baseline_rank.abs_diff(self.candidate_rank)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryDiff {
+    pub query: String,
+    pub baseline: Vec<String>,
+    pub candidate: Vec<String>,
+    /// Fraction of the top results that are returned by both searchers.
+    pub overlap: f64,
+    /// Results only returned by the candidate.
+    pub added: Vec<String>,
+    /// Results only returned by the baseline.
+    pub removed: Vec<String>,
+    /// Results returned by both searchers at different ranks.
+    pub shifts: Vec<RankShift>,
+}
+
+impl QueryDiff {
+    pub fn new(query: String, baseline: Vec<String>, candidate: Vec<String>) -> Self {
+        let baseline_ranks: HashMap<&str, usize> = baseline
+            .iter()
+            .enumerate()
+            .map(|(rank, url)| (url.as_str(), rank + 1))
+            .collect();
+        let candidate_ranks: HashMap<&str, usize> = candidate
+            .iter()
+            .enumerate()
+            .map(|(rank, url)| (url.as_str(), rank + 1))
+            .collect();
+
+        let added: Vec<String> = candidate
+            .iter()
+            .filter(|url| !baseline_ranks.contains_key(url.as_str()))
+            .cloned()
+            .collect();
+        let removed: Vec<String> = baseline
+            .iter()
+            .filter(|url| !candidate_ranks.contains_key(url.as_str()))
+            .cloned()
+            .collect();
+
+        let mut num_shared = 0;
+        let mut shifts = Vec::new();
+
+        for (url, baseline_rank) in &baseline_ranks {
+            if let Some(candidate_rank) = candidate_ranks.get(url) {
+                num_shared += 1;
+
+                if baseline_rank != candidate_rank {
+                    shifts.push(RankShift {
+                        url: url.to_string(),
+                        baseline_rank: *baseline_rank,
+                        candidate_rank: *candidate_rank,
+                    });
+                }
+            }
+        }
+
+        shifts.sort_by_key(|shift| shift.baseline_rank);
+
+        let max_len = baseline_ranks.len().max(candidate_ranks.len());
+        let overlap = if max_len == 0 {
+            1.0
+        } else {
+            num_shared as f64 / max_len as f64
+        };
+
+        Self {
+            query,
+            baseline,
+            candidate,
+            overlap,
+            added,
+            removed,
+            shifts,
+        }
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.baseline == self.candidate
+    }
+
+    pub fn top_result_changed(&self) -> bool {
+        self.baseline.first() != self.candidate.first()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffSummary {
+    pub num_queries: usize,
+    /// Queries that failed on either searcher and are not part of the report.
+    pub failed_queries: usize,
+    pub top_k: usize,
+    /// Mean overlap@k over all the queries.
+    pub mean_overlap: f64,
+    /// Queries where both searchers returned exactly the same ranking.
+    pub identical_queries: usize,
+    pub top_result_changed: usize,
+    /// Mean number of positions a result moved among the results returned by both searchers.
+    pub mean_abs_rank_shift: f64,
+    pub max_abs_rank_shift: usize,
+}
+
+impl DiffSummary {
+    pub fn new(diffs: &[QueryDiff], top_k: usize, failed_queries: usize) -> Self {
+        let mut summary = Self {
+            num_queries: diffs.len(),
+            failed_queries,
+            top_k,
+            ..Default::default()
+        };
+
+        if diffs.is_empty() {
+            return summary;
+        }
+
+        let mut num_shared = 0;
+        let mut total_shift = 0;
+
+        for diff in diffs {
+            summary.mean_overlap += diff.overlap;
+
+            if diff.is_identical() {
+                summary.identical_queries += 1;
+            }
+
+            if diff.top_result_changed() {
+                summary.top_result_changed += 1;
+            }
+
+            // results at the same rank are not part of the shifts but still count towards the mean
+            num_shared += diff.baseline.len() - diff.removed.len();
+
+            for shift in &diff.shifts {
+                total_shift += shift.distance();
+                summary.max_abs_rank_shift = summary.max_abs_rank_shift.max(shift.distance());
+            }
+        }
+
+        summary.mean_overlap /= diffs.len() as f64;
+
+        if num_shared > 0 {
+            summary.mean_abs_rank_shift = total_shift as f64 / num_shared as f64;
+        }
+
+        summary
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffReport {
+    pub summary: DiffSummary,
+    pub queries: Vec<QueryDiff>,
+}
+
+struct DiffSearcher {
+    searcher: LocalSearcher<Index>,
+    optic: Option<Optic>,
+}
+
+impl DiffSearcher {
+    fn open(config: DiffSearcherConfig) -> Result<Self> {
+        let mut searcher = LocalSearcher::new(Index::open(&config.index_path)?);
+
+        if let Some(path) = config.host_centrality_store_path {
+            searcher.set_inbound_similarity(InboundSimilarity::open(
+                Path::new(&path).join("inbound_similarity"),
+            )?);
+        }
+
+        if let Some(path) = config.linear_model_path {
+            searcher.set_linear_model(LinearRegression::open(path)?);
+        }
+
+        if let Some(path) = config.lambda_model_path {
+            searcher.set_lambda_model(Reloadable::open(path)?);
+        }
+
+        searcher.set_collector_config(config.collector);
+        searcher.set_freshness_config(config.freshness);
+        searcher.set_ranking_pipeline_config(config.ranking_pipeline);
+
+        let optic = match config.optic_path {
+            Some(path) => Some(Optic::parse(&fs::read_to_string(path)?)?),
+            None => None,
+        };
+
+        Ok(Self { searcher, optic })
+    }
+
+    fn search(&self, query: &str, top_k: usize) -> Result<Vec<String>> {
+        let result = self.searcher.search(&SearchQuery {
+            query: query.to_string(),
+            num_results: top_k,
+            optic: self.optic.clone(),
+            ..Default::default()
+        })?;
+
+        Ok(result
+            .webpages
+            .into_iter()
+            .take(top_k)
+            .map(|webpage| webpage.url)
+            .collect())
+    }
+}
+
+pub fn run(config: ResultDiffConfig) -> Result<()> {
+    let baseline = DiffSearcher::open(config.baseline)?;
+    let candidate = DiffSearcher::open(config.candidate)?;
+
+    let queries = fs::read_to_string(&config.queries_path)?;
+
+    let mut diffs = Vec::new();
+    let mut failed_queries = 0;
+
+    for query in queries.lines().map(str::trim).filter(|q| !q.is_empty()) {
+        match (
+            baseline.search(query, config.top_k),
+            candidate.search(query, config.top_k),
+        ) {
+            (Ok(baseline), Ok(candidate)) => {
+                diffs.push(QueryDiff::new(query.to_string(), baseline, candidate))
+            }
+            (Err(err), _) | (_, Err(err)) => {
+                tracing::warn!("failed to search for {query:?}: {err}");
+                failed_queries += 1;
+            }
+        }
+    }
+
+    let summary = DiffSummary::new(&diffs, config.top_k, failed_queries);
+
+    info!(
+        "compared {} queries: mean overlap@{} {:.3}, {} identical, top result changed for {}, mean rank shift {:.2}",
+        summary.num_queries,
+        summary.top_k,
+        summary.mean_overlap,
+        summary.identical_queries,
+        summary.top_result_changed,
+        summary.mean_abs_rank_shift
+    );
+
+    // the most changed queries are the most interesting to look at
+    diffs.sort_by(|a, b| a.overlap.total_cmp(&b.overlap));
+
+    let wrt = BufWriter::new(File::create(&config.output_path)?);
+    serde_json::to_writer_pretty(
+        wrt,
+        &DiffReport {
+            summary,
+            queries: diffs,
+        },
+    )?;
+
+    info!("wrote report to {}", config.output_path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn identical_results() {
+        let diff = QueryDiff::new(
+            "test".to_string(),
+            urls(&["a", "b", "c"]),
+            urls(&["a", "b", "c"]),
+        );
+
+        assert!(diff.is_identical());
+        assert!(!diff.top_result_changed());
+        assert_eq!(diff.overlap, 1.0);
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert!(diff.shifts.is_empty());
+    }
+
+    #[test]
+    fn changed_results() {
+        let diff = QueryDiff::new(
+            "test".to_string(),
+            urls(&["a", "b", "c", "d"]),
+            urls(&["b", "a", "e", "c"]),
+        );
+
+        assert!(!diff.is_identical());
+        assert!(diff.top_result_changed());
+        assert_eq!(diff.overlap, 0.75);
+        assert_eq!(diff.added, urls(&["e"]));
+        assert_eq!(diff.removed, urls(&["d"]));
+        assert_eq!(
+            diff.shifts,
+            vec![
+                RankShift {
+                    url: "a".to_string(),
+                    baseline_rank: 1,
+                    candidate_rank: 2,
+                },
+                RankShift {
+                    url: "b".to_string(),
+                    baseline_rank: 2,
+                    candidate_rank: 1,
+                },
+                RankShift {
+                    url: "c".to_string(),
+                    baseline_rank: 3,
+                    candidate_rank: 4,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_results() {
+        let diff = QueryDiff::new("test".to_string(), vec![], vec![]);
+        assert_eq!(diff.overlap, 1.0);
+
+        let diff = QueryDiff::new("test".to_string(), urls(&["a"]), vec![]);
+        assert_eq!(diff.overlap, 0.0);
+        assert_eq!(diff.removed, urls(&["a"]));
+    }
+
+    #[test]
+    fn summary() {
+        let diffs = vec![
+            QueryDiff::new("same".to_string(), urls(&["a", "b"]), urls(&["a", "b"])),
+            QueryDiff::new("swapped".to_string(), urls(&["a", "b"]), urls(&["b", "a"])),
+            QueryDiff::new("new".to_string(), urls(&["a", "b"]), urls(&["c", "d"])),
+        ];
+
+        let summary = DiffSummary::new(&diffs, 2, 1);
+
+        assert_eq!(summary.num_queries, 3);
+        assert_eq!(summary.failed_queries, 1);
+        assert_eq!(summary.identical_queries, 1);
+        assert_eq!(summary.top_result_changed, 2);
+        assert!((summary.mean_overlap - 2.0 / 3.0).abs() < 1e-9);
+        // 4 shared results where 2 of them moved a single position
+        assert_eq!(summary.mean_abs_rank_shift, 0.5);
+        assert_eq!(summary.max_abs_rank_shift, 1);
+    }
+}
@@ -14,13 +14,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use url::Url;
 
 use crate::{
+    cached_page::CachedPage,
     config,
     distributed::{
         cluster::Cluster,
@@ -31,9 +37,9 @@ use crate::{
     inverted_index::{self, RetrievedWebpage},
     ranking::{
         inbound_similarity::InboundSimilarity,
-        models::{lambdamart::LambdaMART, linear::LinearRegression},
+        models::{embedding::EmbeddingModel, linear::LinearRegression, reloadable::Reloadable},
     },
-    searcher::{InitialWebsiteResult, LocalSearcher, SearchQuery},
+    searcher::{sample::IndexSample, InitialWebsiteResult, LocalSearcher, SearchQuery},
     sonic_service, Result,
 };
 
@@ -44,11 +50,15 @@ sonic_service!(
         Search,
         GetWebpage,
         GetHomepageDescriptions,
+        SampleDocuments,
+        DeleteUrls,
+        GetCachedPage,
     ]
 );
 
 pub struct SearchService {
-    local_searcher: LocalSearcher<Index>,
+    local_searcher: LocalSearcher<Arc<RwLock<Index>>>,
+    index: Arc<RwLock<Index>>,
     // dropping the handle leaves the cluster
     #[allow(unused)]
     cluster_handle: Cluster,
@@ -60,8 +70,10 @@ impl SearchService {
             .host_centrality_store_path
             .map(|p| InboundSimilarity::open(Path::new(&p).join("inbound_similarity")).unwrap());
         let search_index = Index::open(config.index_path)?;
+        let generation = search_index.generation();
+        let index = Arc::new(RwLock::new(search_index));
 
-        let mut local_searcher = LocalSearcher::new(search_index);
+        let mut local_searcher = LocalSearcher::new(index.clone());
 
         if let Some(centrality_store) = centrality_store {
             local_searcher.set_inbound_similarity(centrality_store);
@@ -72,11 +84,27 @@ impl SearchService {
         }
 
         if let Some(model_path) = config.lambda_model_path {
-            local_searcher.set_lambda_model(LambdaMART::open(model_path)?);
+            local_searcher.set_lambda_model(Reloadable::open(model_path)?);
+        }
+
+        if let Some(interval) = config.model_reload_interval_sec {
+            tokio::spawn(
+                local_searcher
+                    .lambda_model()
+                    .clone()
+                    .watch(Duration::from_secs(interval)),
+            );
         }
 
         local_searcher.set_collector_config(config.collector);
         local_searcher.set_snippet_config(config.snippet);
+        local_searcher.set_freshness_config(config.freshness);
+        local_searcher.set_ranking_pipeline_config(config.ranking_pipeline);
+        local_searcher.set_dense_retrieval_config(config.dense_retrieval);
+
+        if let Some(model_path) = config.embedding_model_path {
+            local_searcher.set_embedder(Arc::new(EmbeddingModel::open(model_path)?));
+        }
 
         let cluster_handle = Cluster::join(
             Member {
@@ -84,6 +112,7 @@ impl SearchService {
                 service: Service::Searcher {
                     host: config.host,
                     shard: config.shard_id,
+                    generation: Some(generation),
                 },
             },
             config.gossip_addr,
@@ -93,9 +122,45 @@ impl SearchService {
 
         Ok(SearchService {
             local_searcher,
+            index,
             cluster_handle,
         })
     }
+
+    async fn delete_urls(&self, urls: Vec<Url>) -> Result<usize> {
+        let index = Arc::clone(&self.index);
+        let num_deleted = tokio::task::spawn_blocking(move || {
+            index
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .delete_urls(&urls)
+        })
+        .await??;
+
+        if num_deleted > 0 {
+            let index = Arc::clone(&self.index);
+            tokio::task::spawn_blocking(move || {
+                if let Err(err) = merge_deleted(&index) {
+                    tracing::error!("failed to merge segments with deleted documents: {err}");
+                }
+            });
+        }
+
+        Ok(num_deleted)
+    }
+}
+
+/// Reclaim the space of the deleted documents. The lock is only held while the
+/// merges are started and while the reader is reloaded afterwards.
+fn merge_deleted(index: &RwLock<Index>) -> Result<()> {
+    let merges = index
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .merge_deleted()?;
+
+    merges.wait()?;
+
+    index.write().unwrap_or_else(|e| e.into_inner()).reload()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -162,6 +227,59 @@ impl sonic::service::Message<SearchService> for GetHomepageDescriptions {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleDocuments {
+    pub num_docs: usize,
+    pub fields: Vec<String>,
+    pub seed: Option<u64>,
+}
+impl sonic::service::Message<SearchService> for SampleDocuments {
+    type Response = Option<IndexSample>;
+    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+        match server
+            .local_searcher
+            .sample(self.num_docs, &self.fields, self.seed)
+        {
+            Ok(sample) => Ok(Some(sample)),
+            Err(err) => {
+                tracing::error!("failed to sample documents: {err}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Remove the pages from the shard, e.g. for takedown requests.
+/// The affected segments are merged in the background so the pages are also
+/// removed from disk. Responds with the number of deleted documents.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteUrls {
+    pub urls: Vec<Url>,
+}
+impl sonic::service::Message<SearchService> for DeleteUrls {
+    type Response = Option<usize>;
+    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+        match server.delete_urls(self.urls).await {
+            Ok(num_deleted) => Ok(Some(num_deleted)),
+            Err(err) => {
+                tracing::error!("failed to delete urls: {err}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetCachedPage {
+    pub url: String,
+}
+impl sonic::service::Message<SearchService> for GetCachedPage {
+    type Response = Option<CachedPage>;
+    async fn handle(self, server: &SearchService) -> sonic::Result<Self::Response> {
+        Ok(server.local_searcher.get_cached_page(&self.url))
+    }
+}
+
 pub async fn run(config: config::SearchServerConfig) -> Result<()> {
     let addr = config.host;
     let server = SearchService::new(config).await?.bind(addr).await.unwrap();
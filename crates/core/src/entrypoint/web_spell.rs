@@ -69,6 +69,10 @@ impl SpellWorker {
 
         for file in warc_files.by_ref() {
             for record in file.records().flatten() {
+                if record.metadata.not_modified {
+                    continue;
+                }
+
                 let webpage = match Html::parse(&record.response.body, &record.request.url) {
                     Ok(webpage) => webpage,
                     Err(err) => {
@@ -18,7 +18,7 @@ use crate::{
     config::{self, WebgraphConstructConfig},
     entrypoint::download_all_warc_files,
     mapreduce::Worker,
-    webgraph::{self, Node, WebgraphWriter},
+    webgraph::{self, metadata::NodeMetadata, EdgeWeight, Node, WebgraphWriter},
     webpage::{url_ext::UrlExt, Html},
     Result,
 };
@@ -100,6 +100,10 @@ impl WebgraphWorker {
 
         for file in warc_files.by_ref() {
             for record in file.records().flatten() {
+                if record.metadata.not_modified {
+                    continue;
+                }
+
                 let webpage =
                     match Html::parse_without_text(&record.response.body, &record.request.url) {
                         Ok(webpage) => webpage,
@@ -109,11 +113,25 @@ impl WebgraphWorker {
                         }
                     };
 
-                for mut link in webpage
-                    .anchor_links()
+                let metadata = NodeMetadata::page(
+                    webpage.url(),
+                    webpage.declared_lang(),
+                    record.metadata.fetch_time_ms / 1000,
+                );
+                let page = Node::from(webpage.url());
+
+                self.page_graph
+                    .insert_metadata(page.clone(), metadata.clone());
+                self.host_graph.insert_metadata(page.into_host(), metadata);
+
+                for anchor in webpage
+                    .anchor_links_with_context()
                     .into_iter()
-                    .filter(|link| matches!(link.destination.scheme(), "http" | "https"))
+                    .filter(|anchor| matches!(anchor.link.destination.scheme(), "http" | "https"))
                 {
+                    let weight = EdgeWeight::link(anchor.prominent, anchor.nofollow);
+                    let mut link = anchor.link;
+
                     let source = link.source.clone();
                     let destination = link.destination.clone();
                     link.text = link.text.chars().take(128).collect();
@@ -123,8 +141,12 @@ impl WebgraphWorker {
 
                     let mut destination = Node::from(destination);
 
-                    self.page_graph
-                        .insert(source.clone(), destination.clone(), link.text.clone());
+                    self.page_graph.insert_with_weight(
+                        source.clone(),
+                        destination.clone(),
+                        link.text.clone(),
+                        weight,
+                    );
 
                     source = source.into_host();
                     destination = destination.into_host();
@@ -135,7 +157,8 @@ impl WebgraphWorker {
                         && source_domain.is_some()
                         && dest_domain != source_domain
                     {
-                        self.host_graph.insert(source, destination, link.text);
+                        self.host_graph
+                            .insert_with_weight(source, destination, link.text, weight);
                     }
                 }
             }
@@ -154,6 +177,12 @@ pub struct Webgraph {}
 
 impl Webgraph {
     pub fn run(config: &WebgraphConstructConfig) -> Result<()> {
+        Self::build(config)?;
+        Ok(())
+    }
+
+    /// Build the host and page graphs and return the paths of the merged graphs.
+    pub fn build(config: &WebgraphConstructConfig) -> Result<(String, String)> {
         let warc_paths = config.warc_source.paths()?;
 
         let job_config = JobConfig::from(config.warc_source.clone());
@@ -229,6 +258,6 @@ impl Webgraph {
             fs::remove_dir_all(other_page_path)?;
         }
 
-        Ok(())
+        Ok((host_graph.path.clone(), page_graph.path.clone()))
     }
 }
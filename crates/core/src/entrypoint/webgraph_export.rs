@@ -0,0 +1,238 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Export a webgraph to formats that can be loaded by graph libraries
+//! such as igraph and NetworkX without reading the underlying stores.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use tracing::info;
+
+use crate::{
+    webgraph::{Webgraph, WebgraphBuilder, NODE_CHUNK_SIZE},
+    Result,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// `from,to,label` rows with the node names as the endpoints.
+    Csv,
+    /// GraphML with the node names and anchor texts as data attributes.
+    GraphMl,
+}
+
+impl Display for GraphFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphFormat::Csv => write!(f, "csv"),
+            GraphFormat::GraphMl => write!(f, "graphml"),
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(GraphFormat::Csv),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            _ => Err(crate::Error::UnknownCLIOption),
+        }
+    }
+}
+
+fn write_csv<W: Write>(graph: &Webgraph, wrt: W) -> Result<u64> {
+    let mut wtr = csv::Writer::from_writer(wrt);
+    let mut num_edges = 0;
+
+    wtr.write_record(["from", "to", "label"])?;
+
+    let chunks = graph.par_map_node_chunks(NODE_CHUNK_SIZE, |id| {
+        graph.raw_outgoing_edges_with_labels(&id)
+    });
+
+    for (id, edges) in chunks.flatten() {
+        if edges.is_empty() {
+            continue;
+        }
+
+        let Some(from) = graph.id2node(&id) else {
+            continue;
+        };
+
+        for edge in edges {
+            if let Some(to) = graph.id2node(&edge.to) {
+                wtr.write_record([&from.name, &to.name, &edge.label])?;
+                num_edges += 1;
+            }
+        }
+    }
+
+    wtr.flush()?;
+
+    Ok(num_edges)
+}
+
+fn write_graphml<W: Write>(graph: &Webgraph, mut wrt: W) -> Result<u64> {
+    let mut num_edges = 0;
+
+    writeln!(wrt, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        wrt,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        wrt,
+        r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#
+    )?;
+    writeln!(
+        wrt,
+        r#"  <key id="label" for="edge" attr.name="label" attr.type="string"/>"#
+    )?;
+    writeln!(wrt, r#"  <graph id="webgraph" edgedefault="directed">"#)?;
+
+    for (node, id) in graph.node_ids() {
+        writeln!(
+            wrt,
+            r#"    <node id="n{}"><data key="name">{}</data></node>"#,
+            id.as_u64(),
+            quick_xml::escape::escape(&node.name)
+        )?;
+    }
+
+    let chunks = graph.par_map_node_chunks(NODE_CHUNK_SIZE, |id| {
+        graph.raw_outgoing_edges_with_labels(&id)
+    });
+
+    for (_, edges) in chunks.flatten() {
+        for edge in edges {
+            writeln!(
+                wrt,
+                r#"    <edge source="n{}" target="n{}"><data key="label">{}</data></edge>"#,
+                edge.from.as_u64(),
+                edge.to.as_u64(),
+                quick_xml::escape::escape(&edge.label)
+            )?;
+            num_edges += 1;
+        }
+    }
+
+    writeln!(wrt, "  </graph>")?;
+    writeln!(wrt, "</graphml>")?;
+    wrt.flush()?;
+
+    Ok(num_edges)
+}
+
+pub fn export(graph: &Webgraph, output_path: &Path, format: GraphFormat) -> Result<u64> {
+    let wrt = BufWriter::new(File::create(output_path)?);
+
+    match format {
+        GraphFormat::Csv => write_csv(graph, wrt),
+        GraphFormat::GraphMl => write_graphml(graph, wrt),
+    }
+}
+
+pub fn run<P: AsRef<Path>>(webgraph_path: P, output_path: P, format: GraphFormat) -> Result<()> {
+    let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+    let num_edges = export(&graph, output_path.as_ref(), format)?;
+
+    info!(
+        "exported {} edges as {} to {}",
+        num_edges,
+        format,
+        output_path.as_ref().display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        executor::Executor,
+        webgraph::{Compression, Node, WebgraphWriter},
+    };
+
+    use super::*;
+
+    fn graph() -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        writer.insert(
+            Node::from("a.com"),
+            Node::from("b.com"),
+            "b & co".to_string(),
+        );
+        writer.insert(
+            Node::from("b.com"),
+            Node::from("a.com"),
+            "a, the".to_string(),
+        );
+
+        writer.finalize()
+    }
+
+    #[test]
+    fn csv() {
+        let path = crate::gen_temp_path();
+        assert_eq!(export(&graph(), &path, GraphFormat::Csv).unwrap(), 2);
+
+        let mut rows: Vec<Vec<String>> = csv::Reader::from_path(&path)
+            .unwrap()
+            .records()
+            .map(|row| row.unwrap().iter().map(|s| s.to_string()).collect())
+            .collect();
+        rows.sort();
+
+        assert_eq!(
+            rows,
+            vec![
+                vec!["a.com", "b.com", "b & co"],
+                vec!["b.com", "a.com", "a, the"],
+            ]
+        );
+    }
+
+    #[test]
+    fn graphml() {
+        let path = crate::gen_temp_path();
+        assert_eq!(export(&graph(), &path, GraphFormat::GraphMl).unwrap(), 2);
+
+        let res = std::fs::read_to_string(&path).unwrap();
+        let a = Node::from("a.com").id().as_u64();
+        let b = Node::from("b.com").id().as_u64();
+
+        assert!(res.contains(&format!(
+            r#"<node id="n{a}"><data key="name">a.com</data></node>"#
+        )));
+        assert!(res.contains(&format!(
+            r#"<edge source="n{a}" target="n{b}"><data key="label">b &amp; co</data></edge>"#
+        )));
+        assert!(res.trim_end().ends_with("</graphml>"));
+    }
+}
@@ -31,6 +31,8 @@ use crate::distributed::member::Member;
 use crate::distributed::member::Service;
 use crate::distributed::sonic;
 use crate::distributed::sonic::service::Message;
+use crate::kv::rocksdb_store::RocksDbStore;
+use crate::kv::Kv;
 use crate::ranking::inbound_similarity::InboundSimilarity;
 use crate::searcher::DistributedSearcher;
 use crate::searcher::SearchClient;
@@ -39,6 +41,8 @@ use crate::sonic_service;
 use crate::webgraph::Compression;
 use crate::webgraph::FullEdge;
 use crate::webgraph::Node;
+use crate::webgraph::NodeID;
+use crate::webgraph::ShortestPaths;
 use crate::webgraph::Webgraph;
 use crate::webgraph::WebgraphBuilder;
 use crate::Result;
@@ -52,17 +56,41 @@ pub struct ScoredHost {
 }
 
 const MAX_HOSTS: usize = 20;
+const MAX_LINKS: usize = 1_000;
+const MAX_PATH_DEPTH: u8 = 5;
 
 pub struct WebGraphService {
     granularity: WebgraphGranularity,
     searcher: DistributedSearcher,
     similar_hosts_finder: Option<SimilarHostsFinder>,
+    centrality: Option<Arc<RocksDbStore<NodeID, f64>>>,
     graph: Arc<Webgraph>,
 }
 
+fn centrality(store: Option<&RocksDbStore<NodeID, f64>>, node: &NodeID) -> f64 {
+    store
+        .and_then(|centrality| centrality.get(node))
+        .unwrap_or_default()
+}
+
+impl WebGraphService {
+    fn node(&self, node: Node) -> Node {
+        match self.granularity {
+            WebgraphGranularity::Host => node.into_host(),
+            WebgraphGranularity::Page => node,
+        }
+    }
+}
+
 sonic_service!(
     WebGraphService,
-    [SimilarHosts, Knows, IngoingLinks, OutgoingLinks]
+    [
+        SimilarHosts,
+        Knows,
+        IngoingLinks,
+        OutgoingLinks,
+        ShortestPath
+    ]
 );
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -147,41 +175,87 @@ impl Message<WebGraphService> for Knows {
     }
 }
 
+/// Links pointing to the node, with the most central linking nodes first.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IngoingLinks {
     pub node: Node,
+    pub offset: usize,
+    pub limit: usize,
 }
 
 impl Message<WebGraphService> for IngoingLinks {
     type Response = Vec<FullEdge>;
 
     async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        match server.granularity {
-            WebgraphGranularity::Host => {
-                let node = self.node.into_host();
-                Ok(server.graph.ingoing_edges(node))
-            }
-            WebgraphGranularity::Page => Ok(server.graph.ingoing_edges(self.node)),
-        }
+        let node = server.node(self.node);
+        let graph = Arc::clone(&server.graph);
+        let store = server.centrality.clone();
+        let limit = self.limit.min(MAX_LINKS);
+
+        // ranking the links reads the centrality of every linking node from disk
+        tokio::task::spawn_blocking(move || {
+            graph.ingoing_links(
+                &node,
+                |id| centrality(store.as_deref(), id),
+                self.offset,
+                limit,
+            )
+        })
+        .await
+        .map_err(|e| sonic::Error::Other(e.into()))
     }
 }
 
+/// Links from the node, with the most central linked nodes first.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutgoingLinks {
     pub node: Node,
+    pub offset: usize,
+    pub limit: usize,
 }
 
 impl Message<WebGraphService> for OutgoingLinks {
     type Response = Vec<FullEdge>;
 
     async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        match server.granularity {
-            WebgraphGranularity::Host => {
-                let node = self.node.into_host();
-                Ok(server.graph.outgoing_edges(node))
-            }
-            WebgraphGranularity::Page => Ok(server.graph.outgoing_edges(self.node)),
-        }
+        let node = server.node(self.node);
+        let graph = Arc::clone(&server.graph);
+        let store = server.centrality.clone();
+        let limit = self.limit.min(MAX_LINKS);
+
+        tokio::task::spawn_blocking(move || {
+            graph.outgoing_links(
+                &node,
+                |id| centrality(store.as_deref(), id),
+                self.offset,
+                limit,
+            )
+        })
+        .await
+        .map_err(|e| sonic::Error::Other(e.into()))
+    }
+}
+
+/// Shortest path of links from one node to another that is at most `max_depth` links long.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShortestPath {
+    pub from: Node,
+    pub to: Node,
+    pub max_depth: u8,
+}
+
+impl Message<WebGraphService> for ShortestPath {
+    type Response = Option<Vec<Node>>;
+
+    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+        let graph = Arc::clone(&server.graph);
+        let from = server.node(self.from);
+        let to = server.node(self.to);
+        let max_depth = self.max_depth.min(MAX_PATH_DEPTH);
+
+        tokio::task::spawn_blocking(move || graph.shortest_path(from, to, max_depth))
+            .await
+            .map_err(|e| sonic::Error::Other(e.into()))
     }
 }
 
@@ -220,10 +294,15 @@ pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
         )
     });
 
+    let centrality = config
+        .centrality_path
+        .map(|path| Arc::new(RocksDbStore::open(path)));
+
     let server = WebGraphService {
         graph,
         searcher,
         similar_hosts_finder,
+        centrality,
         granularity: config.granularity,
     }
     .bind(addr)
@@ -0,0 +1,388 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Relevance feedback from users that flag a result for a query.
+//!
+//! Only aggregated counts per (query, url, kind) are stored. Just like for the
+//! improvement events, nothing that can link the feedback back to the user is stored.
+//! The client address is only used (salted and hashed) in memory to rate limit reports.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use chrono::Utc;
+use ring::{digest, rand, rand::SecureRandom};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    config::FeedbackConfig,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+};
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+const MAX_TRACKED_CLIENTS: usize = 1_000_000;
+const MAX_QUERY_LEN: usize = 256;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("too many reports from client")]
+    RateLimited,
+
+    #[error("empty query")]
+    EmptyQuery,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FeedbackKind {
+    Spam,
+    Irrelevant,
+    Offensive,
+}
+
+impl FeedbackKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeedbackKind::Spam => "spam",
+            FeedbackKind::Irrelevant => "irrelevant",
+            FeedbackKind::Offensive => "offensive",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feedback {
+    pub query: String,
+    pub url: Url,
+    pub kind: FeedbackKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct FeedbackKey {
+    query: String,
+    url: String,
+    kind: FeedbackKind,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FeedbackCount {
+    count: u64,
+    /// Unix timestamp truncated to the hour.
+    last_reported: i64,
+}
+
+/// All reports of a specific kind for a query and url.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregatedFeedback {
+    pub query: String,
+    pub url: String,
+    pub kind: FeedbackKind,
+    pub count: u64,
+    pub last_reported: i64,
+}
+
+/// Flagged results are irrelevant for the query, so they can be
+/// used as negative examples when training the ranking models.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrainingLabel {
+    pub query: String,
+    pub url: String,
+    pub relevance: u8,
+}
+
+/// Counts the reports of each client in fixed windows. All counts are dropped
+/// at once when the window ends, so checking a client is O(1).
+struct RateLimiter {
+    window: Duration,
+    window_start: Instant,
+    counts: HashMap<u64, usize>,
+}
+
+impl RateLimiter {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            window_start: Instant::now(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Count a report from the client, unless it has already made `max_reports`
+    /// in the current window. New clients are rejected while the max number of
+    /// clients are tracked, so the memory use stays bounded.
+    fn check(&mut self, client: u64, max_reports: usize) -> Result<(), Error> {
+        if self.window_start.elapsed() >= self.window {
+            self.counts.clear();
+            self.window_start = Instant::now();
+        }
+
+        if self.counts.len() >= MAX_TRACKED_CLIENTS && !self.counts.contains_key(&client) {
+            return Err(Error::RateLimited);
+        }
+
+        let count = self.counts.entry(client).or_default();
+
+        if *count >= max_reports {
+            return Err(Error::RateLimited);
+        }
+
+        *count += 1;
+
+        Ok(())
+    }
+}
+
+fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .chars()
+        .take(MAX_QUERY_LEN)
+        .collect()
+}
+
+pub struct FeedbackStore {
+    store: RocksDbStore<FeedbackKey, FeedbackCount>,
+    // guards the read-modify-write of the counts
+    write_lock: Mutex<()>,
+    rate_limiter: Mutex<RateLimiter>,
+    max_reports_per_hour: usize,
+    salt: [u8; 32],
+}
+
+impl FeedbackStore {
+    pub fn open<P: AsRef<Path>>(path: P, max_reports_per_hour: usize) -> anyhow::Result<Self> {
+        let mut salt = [0; 32];
+        rand::SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow!("failed to generate salt"))?;
+
+        Ok(Self {
+            store: RocksDbStore::open(path),
+            write_lock: Mutex::new(()),
+            rate_limiter: Mutex::new(RateLimiter::new(RATE_LIMIT_WINDOW)),
+            max_reports_per_hour,
+            salt,
+        })
+    }
+
+    pub fn from_config(config: &FeedbackConfig) -> anyhow::Result<Self> {
+        Self::open(&config.path, config.max_reports_per_hour)
+    }
+
+    fn client_id(&self, client: IpAddr) -> u64 {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(&self.salt);
+        ctx.update(client.to_string().as_bytes());
+
+        let mut id = [0; 8];
+        id.copy_from_slice(&ctx.finish().as_ref()[..8]);
+        u64::from_le_bytes(id)
+    }
+
+    fn check_rate_limit(&self, client: IpAddr) -> Result<(), Error> {
+        let client = self.client_id(client);
+
+        self.rate_limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .check(client, self.max_reports_per_hour)
+    }
+
+    pub fn report(&self, client: IpAddr, feedback: Feedback) -> Result<(), Error> {
+        let query = normalize_query(&feedback.query);
+
+        if query.is_empty() {
+            return Err(Error::EmptyQuery);
+        }
+
+        self.check_rate_limit(client)?;
+
+        let key = FeedbackKey {
+            query,
+            url: feedback.url.to_string(),
+            kind: feedback.kind,
+        };
+
+        let now = Utc::now().timestamp();
+
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut count = self.store.get(&key).unwrap_or_default();
+
+        count.count += 1;
+        // it is important that we strip minutes and seconds here for privacy
+        count.last_reported = now - now.rem_euclid(60 * 60);
+
+        self.store.insert(key, count);
+
+        Ok(())
+    }
+
+    /// Feedback with at least `min_reports` reports, most reported first.
+    pub fn review_queue(&self, min_reports: u64) -> Vec<AggregatedFeedback> {
+        let mut res: Vec<_> = self
+            .store
+            .iter()
+            .filter(|(_, count)| count.count >= min_reports)
+            .map(|(key, count)| AggregatedFeedback {
+                query: key.query,
+                url: key.url,
+                kind: key.kind,
+                count: count.count,
+                last_reported: count.last_reported,
+            })
+            .collect();
+
+        res.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.last_reported.cmp(&a.last_reported))
+        });
+
+        res
+    }
+
+    /// Negative training labels for results that have been
+    /// flagged at least `min_reports` times for the query.
+    pub fn training_labels(&self, min_reports: u64) -> Vec<TrainingLabel> {
+        let mut res: Vec<_> = self
+            .review_queue(min_reports)
+            .into_iter()
+            .map(|feedback| TrainingLabel {
+                query: feedback.query,
+                url: feedback.url,
+                relevance: 0,
+            })
+            .collect();
+
+        // a result might be flagged with multiple kinds
+        res.sort_by(|a, b| a.query.cmp(&b.query).then(a.url.cmp(&b.url)));
+        res.dedup();
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn feedback(query: &str, url: &str, kind: FeedbackKind) -> Feedback {
+        Feedback {
+            query: query.to_string(),
+            url: Url::parse(url).unwrap(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn aggregates_reports() {
+        let store = FeedbackStore::open(crate::gen_temp_path(), 100).unwrap();
+        let a = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        let b = IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8));
+
+        store
+            .report(
+                a,
+                feedback("Best  Pizza", "https://spam.com", FeedbackKind::Spam),
+            )
+            .unwrap();
+        store
+            .report(
+                b,
+                feedback("best pizza", "https://spam.com", FeedbackKind::Spam),
+            )
+            .unwrap();
+        store
+            .report(
+                b,
+                feedback("best pizza", "https://spam.com", FeedbackKind::Irrelevant),
+            )
+            .unwrap();
+
+        let queue = store.review_queue(1);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue[0].query, "best pizza");
+        assert_eq!(queue[0].kind, FeedbackKind::Spam);
+        assert_eq!(queue[0].count, 2);
+        assert_eq!(queue[0].last_reported % 3600, 0);
+
+        assert_eq!(store.review_queue(2).len(), 1);
+
+        assert_eq!(
+            store.training_labels(1),
+            vec![TrainingLabel {
+                query: "best pizza".to_string(),
+                url: "https://spam.com/".to_string(),
+                relevance: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn rate_limit() {
+        let store = FeedbackStore::open(crate::gen_temp_path(), 2).unwrap();
+        let client = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        let report = || feedback("query", "https://example.com", FeedbackKind::Offensive);
+
+        assert!(store.report(client, report()).is_ok());
+        assert!(store.report(client, report()).is_ok());
+        assert_eq!(store.report(client, report()), Err(Error::RateLimited));
+
+        assert!(store
+            .report(IpAddr::V4(Ipv4Addr::new(4, 3, 2, 1)), report())
+            .is_ok());
+
+        assert_eq!(store.review_queue(0)[0].count, 3);
+    }
+
+    #[test]
+    fn rate_limit_window() {
+        let mut limiter = RateLimiter::new(Duration::from_millis(50));
+
+        assert!(limiter.check(1, 1).is_ok());
+        assert_eq!(limiter.check(1, 1), Err(Error::RateLimited));
+        assert!(limiter.check(2, 1).is_ok());
+
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert!(limiter.check(1, 1).is_ok());
+    }
+
+    #[test]
+    fn empty_query() {
+        let store = FeedbackStore::open(crate::gen_temp_path(), 2).unwrap();
+        let client = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+
+        assert_eq!(
+            store.report(client, feedback("  ", "https://a.com", FeedbackKind::Spam)),
+            Err(Error::EmptyQuery)
+        );
+    }
+}
@@ -0,0 +1,344 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Approximate nearest neighbour search with a hierarchical navigable small world graph.
+//!
+//! Every vector is a node in a number of layers, where each layer is a proximity graph
+//! and the higher layers contain exponentially fewer nodes. A search starts in the top
+//! layer and greedily walks towards the query, using the closest node of each layer as
+//! the entry point of the layer below. See Malkov & Yashunin (2016).
+//!
+//! The vectors are expected to be normalized, so the dot product is the cosine similarity.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+pub type NodeId = u32;
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    distance: f32,
+    node: NodeId,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>()
+}
+
+/// Deterministic pseudo random number in `(0, 1]` for the node.
+fn uniform(node: NodeId) -> f64 {
+    // splitmix64
+    let mut z = (node as u64).wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+
+    ((z >> 11) as f64 + 1.0) / (1u64 << 53) as f64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hnsw {
+    /// Maximum number of neighbours of a node in the layers above the bottom layer.
+    /// The bottom layer allows twice as many.
+    m: usize,
+    ef_construction: usize,
+    vectors: Vec<Vec<f32>>,
+    /// `neighbours[node][layer]`
+    neighbours: Vec<Vec<Vec<NodeId>>>,
+    entry_point: Option<NodeId>,
+}
+
+impl Default for Hnsw {
+    fn default() -> Self {
+        Self::new(16, 100)
+    }
+}
+
+impl Hnsw {
+    pub fn new(m: usize, ef_construction: usize) -> Self {
+        Self {
+            m: m.max(2),
+            ef_construction: ef_construction.max(1),
+            vectors: Vec::new(),
+            neighbours: Vec::new(),
+            entry_point: None,
+        }
+    }
+
+    pub fn vector(&self, node: NodeId) -> &[f32] {
+        &self.vectors[node as usize]
+    }
+
+    fn max_neighbours(&self, layer: usize) -> usize {
+        if layer == 0 {
+            2 * self.m
+        } else {
+            self.m
+        }
+    }
+
+    fn random_layer(&self, node: NodeId) -> usize {
+        let ml = 1.0 / (self.m as f64).ln();
+        (-uniform(node).ln() * ml).floor() as usize
+    }
+
+    fn top_layer(&self) -> usize {
+        self.entry_point
+            .map(|node| self.neighbours[node as usize].len() - 1)
+            .unwrap_or(0)
+    }
+
+    /// The `ef` closest nodes to the query in the layer, sorted by distance.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[Candidate],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<NodeId> = entry_points.iter().map(|c| c.node).collect();
+        let mut candidates: BinaryHeap<_> =
+            entry_points.iter().map(|c| std::cmp::Reverse(*c)).collect();
+        let mut best: BinaryHeap<Candidate> = entry_points.iter().copied().collect();
+
+        while let Some(std::cmp::Reverse(candidate)) = candidates.pop() {
+            if let Some(worst) = best.peek() {
+                if best.len() >= ef && candidate.distance > worst.distance {
+                    break;
+                }
+            }
+
+            for neighbour in &self.neighbours[candidate.node as usize][layer] {
+                if !visited.insert(*neighbour) {
+                    continue;
+                }
+
+                let neighbour = Candidate {
+                    distance: distance(query, self.vector(*neighbour)),
+                    node: *neighbour,
+                };
+
+                if best.len() < ef || neighbour.distance < best.peek().unwrap().distance {
+                    candidates.push(std::cmp::Reverse(neighbour));
+                    best.push(neighbour);
+
+                    if best.len() > ef {
+                        best.pop();
+                    }
+                }
+            }
+        }
+
+        best.into_sorted_vec()
+    }
+
+    /// Only keep the closest neighbours of the node in the layer.
+    fn prune(&mut self, node: NodeId, layer: usize) {
+        let max = self.max_neighbours(layer);
+        if self.neighbours[node as usize][layer].len() <= max {
+            return;
+        }
+
+        let vector = self.vector(node);
+        let mut neighbours: Vec<_> = self.neighbours[node as usize][layer]
+            .iter()
+            .map(|n| Candidate {
+                distance: distance(vector, self.vector(*n)),
+                node: *n,
+            })
+            .collect();
+        neighbours.sort();
+        neighbours.truncate(max);
+
+        self.neighbours[node as usize][layer] = neighbours.into_iter().map(|c| c.node).collect();
+    }
+
+    pub fn insert(&mut self, vector: Vec<f32>) -> NodeId {
+        let node = self.vectors.len() as NodeId;
+        let layer = self.random_layer(node);
+
+        self.vectors.push(vector);
+        self.neighbours.push(vec![Vec::new(); layer + 1]);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(node);
+            return node;
+        };
+
+        let top_layer = self.top_layer();
+        let query = self.vectors[node as usize].clone();
+
+        let mut entry_points = vec![Candidate {
+            distance: distance(&query, self.vector(entry_point)),
+            node: entry_point,
+        }];
+
+        for l in (layer + 1..=top_layer).rev() {
+            entry_points = self.search_layer(&query, &entry_points, 1, l);
+        }
+
+        for l in (0..=layer.min(top_layer)).rev() {
+            let candidates = self.search_layer(&query, &entry_points, self.ef_construction, l);
+
+            let neighbours: Vec<_> = candidates
+                .iter()
+                .take(self.max_neighbours(l))
+                .map(|c| c.node)
+                .collect();
+
+            for neighbour in &neighbours {
+                self.neighbours[*neighbour as usize][l].push(node);
+                self.prune(*neighbour, l);
+            }
+
+            self.neighbours[node as usize][l] = neighbours;
+            entry_points = candidates;
+        }
+
+        if layer > top_layer {
+            self.entry_point = Some(node);
+        }
+
+        node
+    }
+
+    /// The approximately `k` most similar nodes to the query together with their
+    /// cosine similarity. Higher `ef` gives better recall at the cost of speed.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(NodeId, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+
+        let mut entry_points = vec![Candidate {
+            distance: distance(query, self.vector(entry_point)),
+            node: entry_point,
+        }];
+
+        for l in (1..=self.top_layer()).rev() {
+            entry_points = self.search_layer(query, &entry_points, 1, l);
+        }
+
+        self.search_layer(query, &entry_points, ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|c| (c.node, 1.0 - c.distance))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(mut v: Vec<f32>) -> Vec<f32> {
+        let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        v.iter_mut().for_each(|x| *x /= norm);
+        v
+    }
+
+    fn vectors(n: usize, dim: usize) -> Vec<Vec<f32>> {
+        (0..n)
+            .map(|i| {
+                normalize(
+                    (0..dim)
+                        .map(|j| uniform((i * dim + j) as NodeId) as f32 - 0.5)
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        let hnsw = Hnsw::default();
+        assert!(hnsw.search(&[1.0, 0.0], 10, 10).is_empty());
+    }
+
+    #[test]
+    fn finds_exact_match() {
+        let mut hnsw = Hnsw::default();
+        let vectors = vectors(500, 8);
+
+        for v in &vectors {
+            hnsw.insert(v.clone());
+        }
+
+        for (i, v) in vectors.iter().enumerate().step_by(50) {
+            let res = hnsw.search(v, 1, 50);
+            assert_eq!(res[0].0, i as NodeId);
+            assert!((res[0].1 - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn recall() {
+        let mut hnsw = Hnsw::new(8, 64);
+        let vectors = vectors(1000, 16);
+
+        for v in &vectors {
+            hnsw.insert(v.clone());
+        }
+
+        let queries = vectors.iter().step_by(100);
+        let k = 10;
+        let mut found = 0;
+        let mut total = 0;
+
+        for query in queries {
+            let mut exact: Vec<_> = vectors
+                .iter()
+                .enumerate()
+                .map(|(i, v)| Candidate {
+                    distance: distance(query, v),
+                    node: i as NodeId,
+                })
+                .collect();
+            exact.sort();
+
+            let exact: HashSet<_> = exact.iter().take(k).map(|c| c.node).collect();
+            let res = hnsw.search(query, k, 64);
+
+            found += res.iter().filter(|(node, _)| exact.contains(node)).count();
+            total += k;
+        }
+
+        assert!(found as f64 / total as f64 > 0.9);
+    }
+}
@@ -122,6 +122,10 @@ impl BaseImageStore {
         self.store.get(key)
     }
 
+    fn contains(&self, key: &String) -> bool {
+        self.store.contains_key(key)
+    }
+
     fn merge(&mut self, other: BaseImageStore) {
         for (key, image) in other.store.iter() {
             self.insert(key, image);
@@ -169,6 +173,81 @@ impl EntityImageStore {
     }
 }
 
+/// Side length in pixels of the stored favicons.
+pub const FAVICON_SIZE: u32 = 32;
+
+/// Scale and crop the image to a square, so favicons of all
+/// hosts are displayed with the same size.
+struct SquareFilter {
+    size: u32,
+}
+
+impl ImageFilter for SquareFilter {
+    fn transform(&self, image: Image) -> Image {
+        if image.0.width() == self.size && image.0.height() == self.size {
+            return image;
+        }
+
+        Image(
+            image
+                .0
+                .resize_to_fill(self.size, self.size, FilterType::Lanczos3),
+        )
+    }
+}
+
+/// Favicons of the crawled hosts. The images are keyed by the
+/// normalized host, so `www.example.com` and `example.com` share a favicon.
+pub struct FaviconStore {
+    store: BaseImageStore,
+}
+
+impl FaviconStore {
+    pub fn prepare_writer(&mut self) {
+        self.store.prepare_writer();
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let store = BaseImageStore::open_with_filters(
+            path,
+            vec![Box::new(SquareFilter { size: FAVICON_SIZE })],
+        );
+
+        Self { store }
+    }
+
+    pub fn contains(&self, host: &str) -> bool {
+        self.store.contains(&Self::key(host))
+    }
+
+    fn key(host: &str) -> String {
+        let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+
+        match host.strip_prefix("www.") {
+            Some(host) => host.to_string(),
+            None => host,
+        }
+    }
+}
+
+impl ImageStore<String> for FaviconStore {
+    fn insert(&mut self, host: String, image: Image) {
+        self.store.insert(Self::key(&host), image);
+    }
+
+    fn get(&self, host: &String) -> Option<Image> {
+        self.store.get(&Self::key(host))
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.store.merge(other.store)
+    }
+
+    fn flush(&self) {
+        self.store.flush()
+    }
+}
+
 impl ImageStore<String> for EntityImageStore {
     fn insert(&mut self, name: String, image: Image) {
         self.store.insert(name, image);
@@ -208,6 +287,48 @@ impl Image {
         }
     }
 
+    /// Decode an image from an untrusted source. Images larger than `max_width`×`max_height`
+    /// are rejected before their pixels are allocated.
+    pub(crate) fn from_untrusted_bytes(
+        bytes: &[u8],
+        max_width: u32,
+        max_height: u32,
+    ) -> Result<Image> {
+        let mut limits = image::io::Limits::default();
+        limits.max_image_width = Some(max_width);
+        limits.max_image_height = Some(max_height);
+
+        let decode = |format: Option<image::ImageFormat>| -> Result<DynamicImage> {
+            let mut reader = image::io::Reader::new(Cursor::new(bytes));
+
+            match format {
+                Some(format) => reader.set_format(format),
+                None => reader = reader.with_guessed_format()?,
+            }
+
+            reader.limits(limits.clone());
+
+            Ok(reader.decode()?)
+        };
+
+        let mut res = decode(None);
+
+        for format in [
+            image::ImageFormat::Jpeg,
+            image::ImageFormat::WebP,
+            image::ImageFormat::Gif,
+            image::ImageFormat::Png,
+        ] {
+            if res.is_ok() {
+                break;
+            }
+
+            res = decode(Some(format));
+        }
+
+        res.map(Self)
+    }
+
     pub(crate) fn as_raw_bytes(&self) -> Vec<u8> {
         let mut cursor = Cursor::new(Vec::new());
         self.0
@@ -225,6 +346,11 @@ impl Image {
         MaxSizeFilter { width, height }.transform(self)
     }
 
+    /// Scale and crop the image to a `size`×`size` square.
+    pub fn square(self, size: u32) -> Self {
+        SquareFilter { size }.transform(self)
+    }
+
     pub fn empty(width: u32, height: u32) -> Self {
         Self(image::DynamicImage::new_rgb8(width, height))
     }
@@ -280,4 +406,34 @@ mod tests {
         assert_eq!(transformed_image.0.width(), 16);
         assert_eq!(transformed_image.0.height(), 16);
     }
+
+    #[test]
+    fn favicons_are_square() {
+        let image = Image(
+            ImageBuffer::from_pixel(64, 16, image::Rgb::<u16>([u16::MAX, u16::MAX, u16::MAX]))
+                .into(),
+        );
+
+        let mut store = FaviconStore::open(crate::gen_temp_path());
+        store.prepare_writer();
+
+        assert!(!store.contains("example.com"));
+        store.insert("www.Example.com".to_string(), image);
+        assert!(store.contains("example.com"));
+
+        let favicon = store.get(&"example.com".to_string()).unwrap();
+        assert_eq!(favicon.0.width(), FAVICON_SIZE);
+        assert_eq!(favicon.0.height(), FAVICON_SIZE);
+    }
+
+    #[test]
+    fn untrusted_images_are_limited() {
+        let image = Image(
+            ImageBuffer::from_pixel(64, 16, image::Rgb::<u8>([u8::MAX, u8::MAX, u8::MAX])).into(),
+        );
+        let bytes = image.as_raw_bytes();
+
+        assert!(Image::from_untrusted_bytes(&bytes, 64, 64).is_ok());
+        assert!(Image::from_untrusted_bytes(&bytes, 32, 32).is_err());
+    }
 }
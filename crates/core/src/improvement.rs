@@ -23,7 +23,7 @@ use tokio::{sync::Mutex, time};
 use url::Url;
 use uuid::Uuid;
 
-use crate::leaky_queue::LeakyQueue;
+use crate::{leaky_queue::LeakyQueue, ranking::experiment::ExperimentArm};
 
 #[derive(Debug, Error)]
 enum Error {
@@ -42,6 +42,7 @@ pub struct StoredQuery {
     query: String,
     result_urls: Vec<Url>,
     timestamp: Option<DateTime<Utc>>, // it is extremely important that we strip minutes, seconds and nanoseconds here for privacy
+    experiment: Option<ExperimentArm>,
 }
 
 #[derive(Clone)]
@@ -51,7 +52,7 @@ pub enum ImprovementEvent {
 }
 
 impl StoredQuery {
-    pub fn new(query: String, urls: Vec<Url>) -> Self {
+    pub fn new(query: String, urls: Vec<Url>, experiment: Option<ExperimentArm>) -> Self {
         let timestamp = Utc::now()
             .with_minute(0)
             .and_then(|t| t.with_second(0))
@@ -64,12 +65,21 @@ impl StoredQuery {
             query,
             result_urls: urls,
             timestamp,
+            experiment,
         }
     }
 
     pub fn qid(&self) -> &Uuid {
         &self.qid
     }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn urls(&self) -> &[Url] {
+        &self.result_urls
+    }
 }
 
 async fn dump_queue(queue: &Mutex<LeakyQueue<ImprovementEvent>>) -> Vec<ImprovementEvent> {
@@ -120,11 +130,20 @@ impl ScyllaConn {
         session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await?;
         session
         .query(
-            "CREATE TABLE IF NOT EXISTS ks.queries (qid uuid, query text, urls text, timestamp timestamp, primary key (qid, timestamp)) WITH default_time_to_live = 7776000", // ttl 90 days
+            "CREATE TABLE IF NOT EXISTS ks.queries (qid uuid, query text, urls text, timestamp timestamp, experiment text, arm text, primary key (qid, timestamp)) WITH default_time_to_live = 7776000", // ttl 90 days
             &[],
         )
         .await?;
 
+        // tables created before the ranking experiments don't have the experiment columns
+        for column in ["experiment", "arm"] {
+            let query = format!("ALTER TABLE ks.queries ADD {column} text");
+
+            if let Err(err) = session.query(query, &[]).await {
+                tracing::debug!("did not add column {column} to ks.queries: {err}");
+            }
+        }
+
         session
             .query(
                 "CREATE TABLE IF NOT EXISTS ks.clicks (qid uuid, click tinyint, primary key (qid)) WITH default_time_to_live = 7776000", // ttl 90 days
@@ -133,7 +152,7 @@ impl ScyllaConn {
             .await?;
 
         let prepared_insert: PreparedStatement = session
-            .prepare("INSERT INTO ks.queries (qid, query, urls, timestamp) VALUES(?, ?, ?, ?)")
+            .prepare("INSERT INTO ks.queries (qid, query, urls, timestamp, experiment, arm) VALUES(?, ?, ?, ?, ?, ?)")
             .await?;
 
         let prepared_click: PreparedStatement = session
@@ -154,6 +173,10 @@ impl ScyllaConn {
             .map(|timestamp| chrono::Duration::seconds(timestamp.timestamp()))
             .unwrap_or_else(|| chrono::Duration::seconds(0));
         let qid = query.qid;
+        let (experiment, arm) = query
+            .experiment
+            .map(|experiment| (Some(experiment.experiment), Some(experiment.arm)))
+            .unwrap_or_default();
 
         let res = self
             .session
@@ -164,6 +187,8 @@ impl ScyllaConn {
                     query.query,
                     urls,
                     scylla::frame::value::Timestamp(timestamp),
+                    experiment,
+                    arm,
                 ),
             )
             .await;
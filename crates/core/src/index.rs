@@ -17,26 +17,37 @@
 use std::collections::HashSet;
 use std::fs;
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Mutex, RwLock};
 use std::time::SystemTime;
 
 use tantivy::tokenizer::TokenizerManager;
+use url::Url;
 
+use crate::cached_page::{CachedPage, CachedPageStore};
 use crate::collector::MainCollector;
-use crate::inverted_index::{self, InvertedIndex};
+use crate::inverted_index::{self, InvertedIndex, PendingMerges};
 use crate::query::Query;
+use crate::ranking::dense_retrieval::EmbeddingStore;
+use crate::ranking::host_stats::HostStats;
 use crate::search_ctx::Ctx;
 use crate::webgraph::NodeID;
-use crate::webpage::region::{Region, RegionCount};
+use crate::webpage::region::{RegionCount, RegionSet};
 use crate::webpage::Webpage;
 use crate::Result;
 
 const INVERTED_INDEX_SUBFOLDER_NAME: &str = "inverted_index";
 const REGION_COUNT_FILE_NAME: &str = "region_count.json";
+const HOST_STATS_FILE_NAME: &str = "host_stats.bin";
+const EMBEDDINGS_FILE_NAME: &str = "embeddings.bin";
+const CACHED_PAGES_SUBFOLDER_NAME: &str = "cached_pages";
 
 pub struct Index {
     pub inverted_index: InvertedIndex,
     pub region_count: Mutex<RegionCount>,
+    pub host_stats: Mutex<HostStats>,
+    /// Searched concurrently by every query, so only writers take an exclusive lock.
+    pub embeddings: RwLock<EmbeddingStore>,
+    pub cached_pages: CachedPageStore,
     pub path: String,
 }
 
@@ -50,10 +61,16 @@ impl Index {
             InvertedIndex::open(path.as_ref().join(INVERTED_INDEX_SUBFOLDER_NAME))?;
 
         let region_count = RegionCount::open(path.as_ref().join(REGION_COUNT_FILE_NAME));
+        let host_stats = HostStats::open(path.as_ref().join(HOST_STATS_FILE_NAME))?;
+        let embeddings = EmbeddingStore::open(path.as_ref().join(EMBEDDINGS_FILE_NAME))?;
+        let cached_pages = CachedPageStore::open(path.as_ref().join(CACHED_PAGES_SUBFOLDER_NAME));
 
         Ok(Self {
             inverted_index,
             region_count: Mutex::new(region_count),
+            host_stats: Mutex::new(host_stats),
+            embeddings: RwLock::new(embeddings),
+            cached_pages,
             path: path.as_ref().to_str().unwrap().to_string(),
         })
     }
@@ -77,17 +94,83 @@ impl Index {
     }
 
     pub fn insert(&self, webpage: Webpage) -> Result<()> {
-        if let Ok(region) = Region::guess_from(&webpage) {
+        let regions = RegionSet::guess_from(&webpage);
+        if !regions.is_empty() {
             let mut reg = self.region_count.lock().unwrap_or_else(|e| e.into_inner());
-            reg.increment(&region);
+
+            for region in regions.iter() {
+                reg.increment(&region);
+            }
+        }
+
+        self.host_stats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(&webpage);
+
+        if let Some(embedding) = &webpage.embedding {
+            self.embeddings
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(webpage.html.url().to_string(), embedding.clone());
+        }
+
+        if let Some(page) = CachedPage::from_webpage(&webpage) {
+            self.cached_pages.insert(&page);
         }
 
         self.inverted_index.insert(webpage)
     }
 
-    pub fn delete_all_before(&self, timestamp: SystemTime) -> Result<()> {
+    /// Replace the document for the webpage's url, if any, with the new version.
+    pub fn update(&self, webpage: Webpage) -> Result<()> {
+        self.inverted_index.delete_url(webpage.html.url())?;
+        self.insert(webpage)
+    }
+
+    /// Remove the documents with the urls from the index.
+    /// Returns the number of documents that were deleted.
+    pub fn delete_urls(&mut self, urls: &[Url]) -> Result<usize> {
+        self.prepare_writer()?;
+
+        let mut num_deleted = 0;
+        for url in urls {
+            num_deleted += self.inverted_index.delete_url(url)?;
+            self.cached_pages.delete(url.as_str());
+        }
+
+        if num_deleted > 0 {
+            self.commit()?;
+        }
+
+        Ok(num_deleted)
+    }
+
+    pub fn merge_deleted(&mut self) -> Result<PendingMerges> {
+        self.inverted_index.merge_deleted()
+    }
+
+    pub fn reload(&mut self) -> Result<()> {
+        self.inverted_index.reload()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.inverted_index.generation()
+    }
+
+    /// Delete the documents that were inserted before `timestamp` together with their cached pages.
+    pub fn delete_all_before(&mut self, timestamp: SystemTime) -> Result<()> {
         self.inverted_index
-            .delete_all_before(tantivy::DateTime::from_utc(timestamp.into()))
+            .delete_all_before(tantivy::DateTime::from_utc(timestamp.into()))?;
+        self.commit()?;
+
+        let inverted_index = &self.inverted_index;
+        self.cached_pages.retain(|url| match Url::parse(url) {
+            Ok(url) => inverted_index.contains_url(&url).unwrap_or(true),
+            Err(_) => false,
+        });
+
+        Ok(())
     }
 
     pub fn commit(&mut self) -> Result<()> {
@@ -96,6 +179,18 @@ impl Index {
         let mut reg = self.region_count.lock().unwrap_or_else(|e| e.into_inner());
         reg.commit();
 
+        self.host_stats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .commit()?;
+
+        self.embeddings
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .commit()?;
+
+        self.cached_pages.flush();
+
         Ok(())
     }
 
@@ -141,6 +236,34 @@ impl Index {
 
         self_region_count.merge(other_region_count);
 
+        let mut self_host_stats = self
+            .host_stats
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        let other_host_stats = other
+            .host_stats
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+
+        self_host_stats
+            .merge(other_host_stats)
+            .expect("failed to merge host stats");
+
+        let mut self_embeddings = self
+            .embeddings
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        let other_embeddings = other
+            .embeddings
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+
+        self_embeddings
+            .merge(other_embeddings)
+            .expect("failed to merge embeddings");
+
+        self.cached_pages.merge(other.cached_pages);
+
         let mut res = Self::open(&self.path).expect("failed to open index");
         res.prepare_writer().expect("failed to prepare writer");
         res
@@ -249,4 +372,76 @@ mod tests {
                 .unwrap())
             .all(|&v| v.value > 0.0));
     }
+
+    #[test]
+    fn cached_pages() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        index
+            .insert(
+                Webpage::new(
+                    &format!(
+                        r#"
+            <html>
+                <head>
+                    <title>Test website</title>
+                </head>
+                <body>
+                    {CONTENT}
+                </body>
+            </html>
+            "#
+                    ),
+                    "https://www.first.com",
+                )
+                .unwrap(),
+            )
+            .expect("failed to insert webpage");
+        index.commit().unwrap();
+
+        let page = index.cached_pages.get("https://www.first.com/").unwrap();
+        assert_eq!(page.title.as_deref(), Some("Test website"));
+        assert!(page.text.contains("best example website"));
+
+        let url = Url::parse("https://www.first.com/").unwrap();
+        assert_eq!(index.delete_urls(&[url]).unwrap(), 1);
+        assert!(index.cached_pages.get("https://www.first.com/").is_none());
+    }
+
+    #[test]
+    fn prune_cached_pages() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        index
+            .insert(
+                Webpage::new(
+                    &format!(
+                        r#"
+            <html>
+                <head>
+                    <title>Test website</title>
+                </head>
+                <body>
+                    {CONTENT}
+                </body>
+            </html>
+            "#
+                    ),
+                    "https://www.first.com",
+                )
+                .unwrap(),
+            )
+            .expect("failed to insert webpage");
+        index.commit().unwrap();
+
+        index
+            .delete_all_before(SystemTime::now() - std::time::Duration::from_secs(3600))
+            .unwrap();
+        assert!(index.cached_pages.get("https://www.first.com/").is_some());
+
+        index
+            .delete_all_before(SystemTime::now() + std::time::Duration::from_secs(3600))
+            .unwrap();
+        assert!(index.cached_pages.get("https://www.first.com/").is_none());
+    }
 }
@@ -36,9 +36,10 @@ use tantivy::tokenizer::TokenizerManager;
 use tantivy::{IndexReader, IndexWriter, SegmentMeta, TantivyDocument};
 use url::Url;
 
-use crate::collector::{Hashes, MainCollector};
+use crate::collector::{self, Hashes, MainCollector};
 use crate::config::SnippetConfig;
 use crate::fastfield_reader::FastFieldReader;
+use crate::prehashed;
 use crate::query::shortcircuit::ShortCircuitQuery;
 use crate::query::Query;
 use crate::ranking::initial::Score;
@@ -52,8 +53,11 @@ use crate::tokenizer::{
     BigramTokenizer, Identity, JsonField, SiteOperatorUrlTokenizer, TrigramTokenizer,
 };
 use crate::webgraph::NodeID;
-use crate::webpage::region::Region;
+use crate::webpage::hreflang::LanguageAlternate;
+use crate::webpage::open_graph::OpenGraph;
+use crate::webpage::protocol::TlsStatus;
 use crate::webpage::url_ext::UrlExt;
+use crate::webpage::{language, region::RegionSet};
 use crate::webpage::{schema_org, Webpage};
 use crate::Result;
 use crate::{schema::create_schema, tokenizer::Tokenizer};
@@ -156,6 +160,20 @@ struct SegmentMergeCandidate {
     segments: Vec<SegmentMeta>,
 }
 
+/// Merges that have been started on the merge threads of the index writer.
+pub struct PendingMerges(Vec<tantivy::FutureResult<Option<SegmentMeta>>>);
+
+impl PendingMerges {
+    /// Block until all the merges are done.
+    pub fn wait(self) -> Result<()> {
+        for merge in self.0 {
+            merge.wait()?;
+        }
+
+        Ok(())
+    }
+}
+
 pub struct InvertedIndex {
     pub path: String,
     tantivy_index: tantivy::Index,
@@ -298,12 +316,38 @@ impl InvertedIndex {
             .as_mut()
             .expect("writer has not been prepared")
             .commit()?;
+        self.reload()
+    }
+
+    /// Search the segments that are currently committed, e.g. after a merge has finished.
+    pub fn reload(&mut self) -> Result<()> {
         self.reader.reload()?;
         self.fastfield_reader = FastFieldReader::new(&self.reader.searcher());
 
         Ok(())
     }
 
+    /// Identifies the searchable state of the index. It changes whenever
+    /// segments are added, merged or have documents deleted.
+    pub fn generation(&self) -> u64 {
+        let segments = self
+            .reader
+            .searcher()
+            .segment_readers()
+            .iter()
+            .map(|segment| {
+                format!(
+                    "{}:{}",
+                    segment.segment_id().uuid_string(),
+                    segment.num_deleted_docs()
+                )
+            })
+            .sorted()
+            .join(",");
+
+        prehashed::hash(segments).0 as u64
+    }
+
     fn delete(&self, query: Box<dyn tantivy::query::Query>) -> Result<()> {
         self.writer
             .as_ref()
@@ -325,6 +369,60 @@ impl InvertedIndex {
         self.delete(Box::new(query))
     }
 
+    /// Whether a committed document has the url.
+    pub fn contains_url(&self, url: &Url) -> Result<bool> {
+        let query = tantivy::query::TermQuery::new(
+            self.url_term(url),
+            tantivy::schema::IndexRecordOption::Basic,
+        );
+
+        Ok(self.reader.searcher().search(&query, &Count)? > 0)
+    }
+
+    fn url_term(&self, url: &Url) -> tantivy::Term {
+        let field = self
+            .schema
+            .get_field(Field::Text(TextField::UrlNoTokenizer).name())
+            .unwrap();
+
+        tantivy::Term::from_field_text(field, url.as_str())
+    }
+
+    /// Mark all documents with the url as deleted. The documents are removed from
+    /// search results after the next commit and from disk when the segments
+    /// are merged. Returns the number of matching documents.
+    pub fn delete_url(&self, url: &Url) -> Result<usize> {
+        let term = self.url_term(url);
+        let query =
+            tantivy::query::TermQuery::new(term.clone(), tantivy::schema::IndexRecordOption::Basic);
+        let num_docs = self.reader.searcher().search(&query, &Count)?;
+
+        self.writer
+            .as_ref()
+            .expect("writer has not been prepared")
+            .delete_term(term);
+
+        Ok(num_docs)
+    }
+
+    /// Start rewriting the committed segments that contain deleted documents so the
+    /// deleted documents no longer take up space on disk. The merges run on the merge
+    /// threads of the writer, so the index can still be searched while they run.
+    /// The merged segments are searched after the next [`InvertedIndex::reload`].
+    pub fn merge_deleted(&mut self) -> Result<PendingMerges> {
+        let writer = self.writer.as_mut().expect("writer has not been prepared");
+
+        let merges = self
+            .tantivy_index
+            .searchable_segment_metas()?
+            .into_iter()
+            .filter(|segment| segment.has_deletes())
+            .map(|segment| writer.merge(&[segment.id()]))
+            .collect();
+
+        Ok(PendingMerges(merges))
+    }
+
     pub fn search_initial(
         &self,
         query: &Query,
@@ -489,14 +587,14 @@ impl InvertedIndex {
                     page.snippet = snippet::generate(
                         query,
                         page.description.as_deref().unwrap_or_default(),
-                        &page.region,
+                        page.lang,
                         self.snippet_config.clone(),
                     );
                 } else {
                     page.snippet = snippet::generate(
                         query,
                         &page.body,
-                        &page.region,
+                        page.lang,
                         self.snippet_config.clone(),
                     );
                 }
@@ -646,6 +744,50 @@ impl InvertedIndex {
             .map(|(_, doc)| self.retrieve_doc(doc.into(), &tv_searcher).unwrap())
     }
 
+    /// Pointers to the documents with the given urls. Urls that are
+    /// not in the index are skipped.
+    pub fn website_pointers(
+        &self,
+        ctx: &Ctx,
+        urls: &[(String, f64)],
+    ) -> Result<Vec<WebsitePointer>> {
+        let field = ctx
+            .tv_searcher
+            .schema()
+            .get_field(Field::Text(TextField::UrlNoTokenizer).name())
+            .unwrap();
+
+        let mut pointers = Vec::with_capacity(urls.len());
+
+        for (url, score) in urls {
+            let term = tantivy::Term::from_field_text(field, url);
+            let query =
+                tantivy::query::TermQuery::new(term, tantivy::schema::IndexRecordOption::Basic);
+
+            let Some((_, address)) = ctx
+                .tv_searcher
+                .search(&query, &tantivy::collector::TopDocs::with_limit(1))?
+                .pop()
+            else {
+                continue;
+            };
+
+            let segment_id = ctx
+                .tv_searcher
+                .segment_reader(address.segment_ord)
+                .segment_id();
+            let fastfield_segment_reader = ctx.fastfield_reader.get_segment(&segment_id);
+
+            pointers.push(WebsitePointer {
+                score: Score { total: *score },
+                hashes: collector::hashes(&fastfield_segment_reader, address.doc_id),
+                address: address.into(),
+            });
+        }
+
+        Ok(pointers)
+    }
+
     pub(crate) fn get_homepage(&self, url: &Url) -> Option<RetrievedWebpage> {
         let tv_searcher = self.reader.searcher();
         let field = tv_searcher
@@ -685,10 +827,19 @@ pub struct RetrievedWebpage {
     pub dmoz_description: Option<String>,
     pub updated_time: Option<NaiveDateTime>,
     pub schema_org: Vec<schema_org::Item>,
-    pub region: Region,
+    pub regions: RegionSet,
+    /// Language of the page if it could be detected.
+    pub lang: Option<whatlang::Lang>,
     pub likely_has_ads: bool,
     pub likely_has_paywall: bool,
+    /// Whether the page was served over valid https when it was crawled.
+    pub tls: TlsStatus,
     pub recipe_first_ingredient_tag_id: Option<String>,
+    pub code_language: Option<String>,
+    pub code_snippet: Option<String>,
+    /// Language variants of the page from its hreflang links.
+    pub language_alternates: Vec<LanguageAlternate>,
+    pub open_graph: Option<OpenGraph>,
 }
 impl RetrievedWebpage {
     pub fn description(&self) -> Option<&String> {
@@ -754,10 +905,16 @@ impl From<TantivyDocument> for RetrievedWebpage {
                         .expect("All body field should be text")
                         .to_string();
                 }
-                Some(Field::Fast(FastField::Region)) => {
-                    webpage.region = {
+                Some(Field::Fast(FastField::Regions)) => {
+                    webpage.regions = {
+                        let bits = value.value().as_value().as_u64().unwrap();
+                        RegionSet::from_bits(bits)
+                    }
+                }
+                Some(Field::Fast(FastField::Language)) => {
+                    webpage.lang = {
                         let id = value.value().as_value().as_u64().unwrap();
-                        Region::from_id(id)
+                        language::from_id(id)
                     }
                 }
                 Some(Field::Text(TextField::DmozDescription)) => {
@@ -788,6 +945,10 @@ impl From<TantivyDocument> for RetrievedWebpage {
                     webpage.likely_has_paywall =
                         value.value().as_value().as_u64().unwrap_or_default() != 0;
                 }
+                Some(Field::Fast(FastField::TlsStatus)) => {
+                    webpage.tls =
+                        TlsStatus::from_u64(value.value().as_value().as_u64().unwrap_or_default());
+                }
                 Some(Field::Text(TextField::RecipeFirstIngredientTagId)) => {
                     let tag_id = value
                         .value()
@@ -800,6 +961,53 @@ impl From<TantivyDocument> for RetrievedWebpage {
                         webpage.recipe_first_ingredient_tag_id = Some(tag_id);
                     }
                 }
+                Some(Field::Text(TextField::CodeLanguage)) => {
+                    let language = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Code language field should be stored as text")
+                        .to_string();
+
+                    if !language.is_empty() {
+                        webpage.code_language = Some(language);
+                    }
+                }
+                Some(Field::Text(TextField::CodeSnippet)) => {
+                    let snippet = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Code snippet field should be stored as text")
+                        .to_string();
+
+                    if !snippet.is_empty() {
+                        webpage.code_snippet = Some(snippet);
+                    }
+                }
+                Some(Field::Text(TextField::LanguageAlternates)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Language alternates field should be stored as text");
+
+                    if !json.is_empty() {
+                        webpage.language_alternates =
+                            serde_json::from_str(json).unwrap_or_default();
+                    }
+                }
+                Some(Field::Text(TextField::OpenGraph)) => {
+                    let json = value
+                        .value()
+                        .as_value()
+                        .as_str()
+                        .expect("Open graph field should be stored as text");
+
+                    if !json.is_empty() {
+                        webpage.open_graph = serde_json::from_str(json).ok();
+                    }
+                }
                 _ => {}
             }
         }
@@ -1120,6 +1328,64 @@ mod tests {
         assert_eq!(result.documents[0].url, "https://www.a.com/");
     }
 
+    #[test]
+    fn inanchor_searches_backlinks() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, backlink) in [
+            ("https://www.a.com", "independent search engine"),
+            ("https://www.b.com", "recipes for dinner"),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Website</title>
+                </head>
+                <body>
+                    {CONTENT}
+                </body>
+            </html>
+            "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    backlink_labels: vec![backlink.to_string()],
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let ctx = index.local_search_ctx();
+        let query = Query::parse(
+            &ctx,
+            &SearchQuery {
+                query: "inanchor:search".to_string(),
+                ..Default::default()
+            },
+            &index,
+        )
+        .expect("Failed to parse query");
+        let ranker = Ranker::new(
+            SignalAggregator::new(Some(&query)),
+            ctx.fastfield_reader.clone(),
+            Default::default(),
+        );
+
+        let result =
+            search(&index, &query, &ctx, ranker.collector(ctx.clone())).expect("Search failed");
+
+        assert_eq!(result.documents.len(), 1);
+        assert_eq!(result.documents[0].url, "https://www.a.com/");
+    }
+
     #[test]
     fn limited_top_docs() {
         let mut index = InvertedIndex::temporary().expect("Unable to open index");
@@ -1650,4 +1916,317 @@ mod tests {
 
         assert_eq!(result.documents.len(), 1);
     }
+
+    #[test]
+    fn language_operator() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, text) in [
+            (
+                "https://www.a.com",
+                "Das ist eine Webseite über das Kochen. Hier finden Sie viele Rezepte für das Abendessen, die schnell und einfach zuzubereiten sind. Wir wünschen Ihnen viel Spaß beim Kochen und einen guten Appetit.",
+            ),
+            (
+                "https://www.b.com",
+                "This is a website about cooking. Here you will find many recipes for dinner that are quick and easy to prepare. We hope you enjoy cooking and have a good appetite.",
+            ),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Webseite website</title>
+                </head>
+                <body>
+                    <p>{text}</p>
+                </body>
+            </html>
+            "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        for (query, expected) in [
+            ("webseite lang:de", "https://www.a.com/"),
+            ("webseite lang:eng", "https://www.b.com/"),
+        ] {
+            let ctx = index.local_search_ctx();
+            let query = Query::parse(
+                &ctx,
+                &SearchQuery {
+                    query: query.to_string(),
+                    ..Default::default()
+                },
+                &index,
+            )
+            .expect("Failed to parse query");
+            let ranker = Ranker::new(
+                SignalAggregator::new(Some(&query)),
+                ctx.fastfield_reader.clone(),
+                Default::default(),
+            );
+
+            let result =
+                search(&index, &query, &ctx, ranker.collector(ctx.clone())).expect("Search failed");
+
+            assert_eq!(result.documents.len(), 1);
+            assert_eq!(result.documents[0].url, expected);
+        }
+    }
+
+    #[test]
+    fn author_operator() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, author) in [
+            ("https://www.a.com", "Jane Doe"),
+            ("https://www.b.com", "John Smith"),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Website</title>
+                    <meta name="author" content="{author}" />
+                </head>
+                <body>
+                    <p>{CONTENT}</p>
+                </body>
+            </html>
+            "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        for (query, expected) in [
+            ("website author:jane", "https://www.a.com/"),
+            ("website author:smith", "https://www.b.com/"),
+        ] {
+            let ctx = index.local_search_ctx();
+            let query = Query::parse(
+                &ctx,
+                &SearchQuery {
+                    query: query.to_string(),
+                    ..Default::default()
+                },
+                &index,
+            )
+            .expect("Failed to parse query");
+            let ranker = Ranker::new(
+                SignalAggregator::new(Some(&query)),
+                ctx.fastfield_reader.clone(),
+                Default::default(),
+            );
+
+            let result =
+                search(&index, &query, &ctx, ranker.collector(ctx.clone())).expect("Search failed");
+
+            assert_eq!(result.documents.len(), 1);
+            assert_eq!(result.documents[0].url, expected);
+        }
+    }
+
+    #[test]
+    fn image_text_is_searchable() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, image) in [
+            (
+                "https://www.a.com",
+                r#"<figure><img src="/bird.jpg" alt="A kingfisher diving"><figcaption>Caught a fish</figcaption></figure>"#,
+            ),
+            ("https://www.b.com", r#"<img src="/bird.jpg">"#),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Website</title>
+                </head>
+                <body>
+                    <p>{CONTENT}</p>
+                    {image}
+                </body>
+            </html>
+            "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        for query in ["kingfisher", "caught fish"] {
+            let ctx = index.local_search_ctx();
+            let query = Query::parse(
+                &ctx,
+                &SearchQuery {
+                    query: query.to_string(),
+                    ..Default::default()
+                },
+                &index,
+            )
+            .expect("Failed to parse query");
+            let ranker = Ranker::new(
+                SignalAggregator::new(Some(&query)),
+                ctx.fastfield_reader.clone(),
+                Default::default(),
+            );
+
+            let result =
+                search(&index, &query, &ctx, ranker.collector(ctx.clone())).expect("Search failed");
+
+            assert_eq!(result.documents.len(), 1);
+            assert_eq!(result.documents[0].url, "https://www.a.com/");
+        }
+    }
+
+    #[test]
+    fn date_operators() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for (url, byline) in [
+            ("https://www.a.com", "Published March 3, 2019"),
+            ("https://www.b.com", "Published 2nd Jan 2022"),
+            ("https://www.c.com", ""),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Website</title>
+                </head>
+                <body>
+                    <div class="byline">{byline}</div>
+                    <p>{CONTENT}</p>
+                </body>
+            </html>
+            "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        for (query, expected) in [
+            ("website before:2020", "https://www.a.com/"),
+            ("website after:2021-06", "https://www.b.com/"),
+        ] {
+            let ctx = index.local_search_ctx();
+            let query = Query::parse(
+                &ctx,
+                &SearchQuery {
+                    query: query.to_string(),
+                    ..Default::default()
+                },
+                &index,
+            )
+            .expect("Failed to parse query");
+            let ranker = Ranker::new(
+                SignalAggregator::new(Some(&query)),
+                ctx.fastfield_reader.clone(),
+                Default::default(),
+            );
+
+            let result =
+                search(&index, &query, &ctx, ranker.collector(ctx.clone())).expect("Search failed");
+
+            assert_eq!(result.documents.len(), 1);
+            assert_eq!(result.documents[0].url, expected);
+        }
+    }
+
+    #[test]
+    fn delete_url() {
+        let mut index = InvertedIndex::temporary().expect("Unable to open index");
+
+        for url in ["https://www.a.com", "https://www.b.com"] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>Website</title>
+                </head>
+                <body>
+                    {CONTENT}
+                </body>
+            </html>
+            "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let url = Url::parse("https://www.a.com").unwrap();
+        assert_eq!(index.delete_url(&url).unwrap(), 1);
+        index.commit().expect("failed to commit index");
+
+        assert!(index.get_webpage("https://www.a.com").is_none());
+        assert!(index.get_webpage("https://www.b.com").is_some());
+        assert_eq!(index.delete_url(&url).unwrap(), 0);
+
+        index
+            .merge_deleted()
+            .expect("failed to start merges")
+            .wait()
+            .expect("failed to merge segments");
+        index.reload().expect("failed to reload index");
+
+        let searcher = index.reader.searcher();
+        assert_eq!(searcher.num_docs(), 1);
+        assert!(searcher
+            .segment_readers()
+            .iter()
+            .all(|segment| segment.num_deleted_docs() == 0));
+        assert!(index.get_webpage("https://www.b.com").is_some());
+    }
 }
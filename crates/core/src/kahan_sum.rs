@@ -16,7 +16,9 @@
 
 use std::ops::{Add, AddAssign};
 
-#[derive(Default, Clone, Copy, Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct KahanSum {
     sum: f64,
     err: f64,
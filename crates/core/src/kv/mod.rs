@@ -26,6 +26,7 @@ where
 {
     fn get_raw(&self, key: &[u8]) -> Option<Vec<u8>>;
     fn insert_raw(&self, key: Vec<u8>, value: Vec<u8>);
+    fn delete_raw(&self, key: &[u8]);
     fn flush(&self);
     fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (K, V)> + 'a>;
 
@@ -42,4 +43,17 @@ where
 
         self.insert_raw(key_bytes, val_bytes);
     }
+
+    /// Whether the key is in the store, without deserializing its value.
+    fn contains_key(&self, key: &K) -> bool {
+        let key_bytes = bincode::serialize(key).expect("failed to serialize key");
+
+        self.get_raw(&key_bytes).is_some()
+    }
+
+    fn delete(&self, key: &K) {
+        let key_bytes = bincode::serialize(key).expect("failed to serialize key");
+
+        self.delete_raw(&key_bytes);
+    }
 }
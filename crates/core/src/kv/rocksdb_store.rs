@@ -129,6 +129,15 @@ where
             .expect("failed to insert value");
     }
 
+    fn delete_raw(&self, key: &[u8]) {
+        let mut opt = rocksdb::WriteOptions::default();
+        opt.disable_wal(true);
+
+        self.db
+            .delete_opt(key, &opt)
+            .expect("failed to delete value");
+    }
+
     fn flush(&self) {
         if let Err(err) = self.db.flush() {
             match err.kind() {
@@ -32,9 +32,12 @@ pub mod inverted_index;
 pub mod mapreduce;
 
 mod api;
+mod audit;
 pub mod autosuggest;
 pub mod bangs;
-mod bloom;
+pub mod bloom;
+pub mod cached_page;
+mod clicks;
 mod collector;
 pub mod config;
 pub mod crawler;
@@ -45,6 +48,8 @@ mod executor;
 mod external_sort;
 mod fastfield_reader;
 pub mod feed;
+mod feedback;
+mod hnsw;
 mod human_website_annotations;
 pub mod hyperloglog;
 pub mod image_store;
@@ -58,10 +63,12 @@ mod live_index;
 mod llm_utils;
 mod metrics;
 mod models;
+mod moderation;
 pub mod naive_bayes;
 pub mod prehashed;
 mod query;
 pub mod ranking;
+mod safe_browsing;
 mod schema;
 mod search_ctx;
 mod search_prettifier;
@@ -70,6 +77,7 @@ mod simhash;
 pub mod similar_hosts;
 mod snippet;
 pub mod summarizer;
+pub mod task_queue;
 mod tokenizer;
 #[allow(unused)]
 mod ttl_cache;
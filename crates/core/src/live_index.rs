@@ -22,25 +22,32 @@ use std::{
 };
 
 use chrono::{DateTime, Utc};
+use itertools::Itertools;
 use url::Url;
 
 use crate::{
-    config::{CrawlerConfig, LiveIndexConfig},
+    config::{defaults, CrawlerConfig, LiveIndexConfig},
     crawler::{
-        reqwest_client, CrawlDatum, DatumStream, JobExecutor, RetrieableUrl, WeightedUrl, WorkerJob,
+        reqwest_client, CrawlDatum, DatumStream, FamilyMetrics, FetchLimiter, JobExecutor,
+        Resolver, RetrieableUrl, Validators, WeightedUrl, WorkerJob,
     },
     entrypoint::indexer::IndexingWorker,
     feed::{
         self,
         scheduler::{Domain, DomainFeeds, Split},
     },
+    task_queue::{Task, TaskQueue},
 };
 
+/// Name of the task queue with the urls to recrawl.
+pub const RECRAWL_QUEUE: &str = "recrawl";
+
 const TTL: Duration = Duration::from_secs(60 * 60 * 24 * 60); // 60 days
 const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60); // 1 hour
 const FEED_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 10); // 10 minutes
 const AUTO_COMMIT_INTERVAL: Duration = Duration::from_secs(60 * 5); // 5 minutes
 const EVENT_LOOP_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RECRAWLS_PER_ITERATION: usize = 1_000;
 
 #[derive(Debug, Clone)]
 struct Feeds {
@@ -121,6 +128,9 @@ struct Crawler {
     downloaded_db: DownloadedDb,
     config: Arc<CrawlerConfig>,
     client: reqwest::Client,
+    /// Shared by all jobs, so the per domain limits also hold
+    /// when a domain is crawled by several jobs at once.
+    limiter: Arc<FetchLimiter>,
 }
 
 impl Crawler {
@@ -130,7 +140,8 @@ impl Crawler {
         downloaded_db: DownloadedDb,
         config: Arc<CrawlerConfig>,
     ) -> Result<Self> {
-        let client = reqwest_client(&config)?;
+        let client = reqwest_client(&config, Resolver::new(&config, FamilyMetrics::default()))?;
+        let limiter = Arc::new(FetchLimiter::new(&config));
 
         Ok(Self {
             feeds: split.into(),
@@ -138,6 +149,7 @@ impl Crawler {
             downloaded_db,
             config,
             client,
+            limiter,
         })
     }
 
@@ -152,9 +164,17 @@ impl Crawler {
             urls: urls
                 .clone()
                 .into_iter()
-                .map(|url| RetrieableUrl::from(WeightedUrl { url, weight: 1.0 }))
+                .map(|url| {
+                    RetrieableUrl::from(WeightedUrl {
+                        url,
+                        weight: 1.0,
+                        validators: Validators::default(),
+                    })
+                })
                 .collect(),
             wandering_urls: 0,
+            max_depth: None,
+            feeds: Vec::new(),
         };
 
         let executor = JobExecutor::new(
@@ -162,7 +182,8 @@ impl Crawler {
             self.client.clone(),
             self.config.clone(),
             self.indexer.clone(),
-        );
+        )
+        .with_limiter(Arc::clone(&self.limiter));
         executor.run().await;
 
         for url in &urls {
@@ -198,6 +219,40 @@ impl Crawler {
         self.process_urls(urls).await
     }
 
+    /// Fetch the urls of the tasks again, regardless of whether they have been downloaded before.
+    /// The tasks are completed if their domain was crawled and failed otherwise, so they are retried.
+    async fn recrawl(&self, tasks: Vec<Task<Url>>, queue: &TaskQueue) -> CrawlResults {
+        let futures = tasks
+            .into_iter()
+            .into_group_map_by(|task| Domain::from(&task.payload))
+            .into_values()
+            .map(|tasks| async move {
+                let urls = tasks.iter().map(|task| task.payload.clone()).collect();
+                let res = self.process_urls(urls).await;
+
+                for task in &tasks {
+                    let update = match &res {
+                        Ok(_) => queue.complete(&task.id),
+                        Err(err) => queue.fail(&task.id, &err.to_string()),
+                    };
+
+                    if let Err(err) = update {
+                        tracing::error!("failed to update recrawl task {}: {err}", task.id);
+                    }
+                }
+
+                res
+            });
+
+        let res = futures::future::join_all(futures).await;
+
+        if res.iter().filter_map(|r| r.as_ref().ok()).any(|r| *r) {
+            CrawlResults::HasInserts
+        } else {
+            CrawlResults::None
+        }
+    }
+
     async fn check_feeds(&mut self) -> CrawlResults {
         let mut feeds = self.feeds.clone();
 
@@ -252,6 +307,13 @@ impl Index {
             .ok();
     }
 
+    pub fn delete_urls(&self, urls: &[Url]) -> Result<usize> {
+        self.search_index
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .delete_urls(urls)
+    }
+
     fn clone_inner_index(&self) -> Arc<RwLock<crate::index::Index>> {
         self.search_index.clone()
     }
@@ -267,16 +329,25 @@ impl Index {
 
 impl DatumStream for Indexer {
     async fn write(&self, crawl_datum: CrawlDatum) -> Result<()> {
+        // the page is unchanged and already in the index.
+        if crawl_datum.status_code == 304 {
+            return Ok(());
+        }
+
         let webpage = self.worker.prepare_webpage(
             &crawl_datum.body,
             crawl_datum.url.as_str(),
             crawl_datum.fetch_time_ms,
+            crawl_datum.last_modified.as_deref(),
+            crawl_datum.sitemap_lastmod.as_deref(),
+            crawl_datum.protocol,
         )?;
 
+        // recrawled pages replace the version that is already in the index
         self.search_index
             .read()
             .unwrap_or_else(|e| e.into_inner())
-            .insert(webpage)?;
+            .update(webpage)?;
 
         Ok(())
     }
@@ -301,9 +372,14 @@ impl From<&LiveIndexConfig> for CrawlerConfig {
             min_crawl_delay_ms: live.min_crawl_delay_ms,
             max_crawl_delay_ms: live.max_crawl_delay_ms,
             max_politeness_factor: live.max_politeness_factor,
+            max_domain_requests_per_sec: defaults::Crawler::max_domain_requests_per_sec(),
+            domain_request_burst: defaults::Crawler::domain_request_burst(),
             max_url_slowdown_retry: live.max_url_slowdown_retry,
             max_redirects: live.max_redirects,
             dry_run: false,
+            ip_family: live.ip_family,
+            dns_cache_ttl_sec: defaults::Crawler::dns_cache_ttl_sec(),
+            dns_negative_cache_ttl_sec: defaults::Crawler::dns_negative_cache_ttl_sec(),
             timeout_seconds: live.timeout_seconds,
             // no impact
             s3: crate::config::S3Config {
@@ -314,6 +390,14 @@ impl From<&LiveIndexConfig> for CrawlerConfig {
                 endpoint: String::new(),
             },
             router_hosts: Vec::new(),
+            prometheus_host: None,
+            proxy: Default::default(),
+            url_filter_path: None,
+            warc: Default::default(),
+            limits: Default::default(),
+            render: None,
+            favicon_store_path: None,
+            task_queue: None,
         }
     }
 }
@@ -321,6 +405,7 @@ impl From<&LiveIndexConfig> for CrawlerConfig {
 pub struct IndexManager {
     index: Arc<Index>,
     crawler: Crawler,
+    task_queue: Arc<TaskQueue>,
 }
 
 impl IndexManager {
@@ -346,9 +431,12 @@ impl IndexManager {
             Arc::new(crawler_config),
         )?;
 
+        let task_queue = Arc::new(TaskQueue::from_config(&config.task_queue)?);
+
         Ok(Self {
             index: Arc::new(index),
             crawler,
+            task_queue,
         })
     }
 
@@ -362,6 +450,23 @@ impl IndexManager {
                 has_inserts = true;
             }
 
+            let mut recrawl = Vec::new();
+            while recrawl.len() < MAX_RECRAWLS_PER_ITERATION {
+                match self.task_queue.lease::<Url>(RECRAWL_QUEUE) {
+                    Ok(Some(task)) => recrawl.push(task),
+                    Ok(None) => break,
+                    Err(err) => {
+                        tracing::error!("failed to lease recrawl task: {err}");
+                        break;
+                    }
+                }
+            }
+
+            if let CrawlResults::HasInserts = self.crawler.recrawl(recrawl, &self.task_queue).await
+            {
+                has_inserts = true;
+            }
+
             if last_prune + PRUNE_INTERVAL < Utc::now() {
                 self.index.prune();
 
@@ -382,4 +487,9 @@ impl IndexManager {
     pub fn index(&self) -> Arc<Index> {
         self.index.clone()
     }
+
+    /// Urls pushed to the [`RECRAWL_QUEUE`] are crawled and indexed again by the event loop.
+    pub fn task_queue(&self) -> Arc<TaskQueue> {
+        Arc::clone(&self.task_queue)
+    }
 }
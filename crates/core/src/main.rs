@@ -17,9 +17,12 @@ use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use serde::de::DeserializeOwned;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::Path;
 use stract::config;
 use stract::entrypoint::autosuggest_scrape::{self, Gl};
+use stract::entrypoint::click_export::LtrFormat;
+use stract::entrypoint::webgraph_export::GraphFormat;
 
 #[cfg(feature = "dev")]
 use stract::entrypoint::configure;
@@ -27,6 +30,8 @@ use stract::entrypoint::configure;
 use stract::entrypoint::{
     self, api, entity_search_server, safety_classifier, search_server, webgraph_server,
 };
+use stract::webgraph::centrality::harmonic::HarmonicCheckpoint;
+use stract::webgraph::centrality::pagerank::PageRankConfig;
 use stract::webgraph::WebgraphBuilder;
 use tracing_subscriber::prelude::*;
 
@@ -125,6 +130,50 @@ enum Commands {
     WebSpell {
         config_path: String,
     },
+
+    /// Build the webgraphs, centrality, index, spell checker and autosuggest in one run
+    /// and publish a manifest of the artifacts. A failed build is resumed when run again.
+    BuildAll {
+        config_path: String,
+
+        /// Ignore the previous build state and rebuild everything.
+        #[clap(long)]
+        restart: bool,
+    },
+
+    /// Report the disk usage of an index or webgraph broken down by subsystem,
+    /// along with suggestions on how to reduce it.
+    DiskUsage {
+        path: String,
+    },
+
+    /// Export the aggregated clicks from the api as learning-to-rank training data.
+    /// The ranking signals of the results are computed by searching the index.
+    ExportClicks {
+        click_log_path: String,
+        index_path: String,
+        output_path: String,
+
+        /// Either svmrank or lightgbm.
+        #[clap(long, default_value = "svmrank")]
+        format: LtrFormat,
+
+        /// Only export queries that have been searched at least this many times.
+        #[clap(long, default_value_t = 10)]
+        min_query_impressions: u64,
+    },
+
+    /// Join the query log, the clicks and the ranking signals from the index into
+    /// training and validation data for a LightGBM ranking model.
+    LtrTrainingData {
+        config_path: String,
+    },
+
+    /// Run the same queries against two searchers (e.g. different index generations,
+    /// models or configs) and report how the ranked results differ.
+    ResultDiff {
+        config_path: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -152,6 +201,32 @@ enum Crawler {
 
     /// Create a crawl plan.
     Plan { config_path: String },
+
+    /// Create crawl jobs from seed urls in Common Crawl url indexes or plain url lists.
+    ImportSeeds { config_path: String },
+
+    /// Merge the fetch histories from the indexers into one for the planner
+    /// and drop urls that haven't been fetched within the retention period.
+    CompactHistory { config_path: String },
+
+    /// Export the jobs that have not been handed out yet and the fetch history
+    /// to a single file, e.g. to move a crawl to another machine.
+    ExportFrontier {
+        job_queue_path: String,
+        output_path: String,
+
+        #[clap(long)]
+        recrawl_history_path: Option<String>,
+    },
+
+    /// Recreate the job queues and fetch history from an exported frontier.
+    ImportFrontier {
+        input_path: String,
+        job_queue_path: String,
+
+        #[clap(long)]
+        recrawl_history_path: Option<String>,
+    },
 }
 
 /// Commands to train or run inference on the classifier that predicts if a webpage is NSFW or SFW.
@@ -173,12 +248,61 @@ enum CentralityMode {
     Host {
         webgraph_path: String,
         output_path: String,
+        /// Save the state of the harmonic centrality calculation to this file and
+        /// resume from it if it exists.
+        #[clap(long)]
+        checkpoint_path: Option<String>,
+        /// Number of iterations between each checkpoint.
+        #[clap(long, default_value_t = 1)]
+        checkpoint_interval: usize,
     },
     /// Calculate metrics for the page webgraph.
     Page {
         webgraph_path: String,
         output_path: String,
     },
+    /// Propagate trust from a curated list of seed hosts through the host webgraph.
+    TrustRank {
+        webgraph_path: String,
+        /// File with one trusted host per line.
+        seeds_path: String,
+        output_path: String,
+    },
+    /// Score how likely each host is part of a link farm from its reciprocal links
+    /// and its out-degree/in-degree ratio in the host webgraph.
+    LinkSpam {
+        webgraph_path: String,
+        output_path: String,
+    },
+    /// Coordinate a harmonic centrality calculation where the edges are partitioned
+    /// across the harmonic workers.
+    DistributedHarmonic { config_path: String },
+    /// Serve as a worker for the distributed harmonic centrality calculation.
+    HarmonicWorker { host: SocketAddr },
+    /// Estimate the betweenness centrality of the nodes in a webgraph from
+    /// the shortest paths of randomly sampled pivot nodes.
+    Betweenness {
+        webgraph_path: String,
+        output_path: String,
+        /// Number of pivot nodes to search from. More pivots give a better estimate.
+        #[clap(long, default_value_t = 1000)]
+        pivots: usize,
+    },
+    /// Calculate the pagerank of the nodes in a webgraph.
+    PageRank {
+        webgraph_path: String,
+        output_path: String,
+        #[clap(long, default_value_t = 0.85)]
+        damping: f64,
+        #[clap(long, default_value_t = 1e-6)]
+        tolerance: f64,
+        #[clap(long, default_value_t = 100)]
+        max_iterations: usize,
+        /// Split the score of a node by the number and prominence of its links
+        /// instead of evenly between the linked nodes.
+        #[clap(long)]
+        weighted: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -192,9 +316,22 @@ enum WebgraphOptions {
         paths: Vec<String>,
     },
 
+    /// Rewrite the segments of a webgraph into a single segment and remove nodes without any edges.
+    Compact { path: String },
+
     /// Deploy the webgraph server. The webgraph server is responsible for serving the webgraph to the search servers.
     /// This is e.g. used to find similar sites etc.
     Server { config_path: String },
+
+    /// Export the edges of the webgraph with their anchor texts so the graph can be loaded into other tools.
+    Export {
+        webgraph_path: String,
+        output_path: String,
+
+        /// Either csv or graphml.
+        #[clap(long, default_value = "csv")]
+        format: GraphFormat,
+    },
 }
 
 #[derive(Subcommand)]
@@ -266,14 +403,72 @@ fn main() -> Result<()> {
                 CentralityMode::Host {
                     webgraph_path,
                     output_path,
+                    checkpoint_path,
+                    checkpoint_interval,
                 } => {
-                    entrypoint::Centrality::build_harmonic(&webgraph_path, &output_path);
+                    let checkpoint = checkpoint_path.map(|path| HarmonicCheckpoint {
+                        path: path.into(),
+                        interval: checkpoint_interval,
+                    });
+
+                    entrypoint::Centrality::build_harmonic(
+                        &webgraph_path,
+                        &output_path,
+                        checkpoint,
+                    );
                     entrypoint::Centrality::build_similarity(&webgraph_path, &output_path);
                 }
                 CentralityMode::Page {
                     webgraph_path,
                     output_path,
                 } => entrypoint::Centrality::build_approx_harmonic(webgraph_path, output_path)?,
+                CentralityMode::TrustRank {
+                    webgraph_path,
+                    seeds_path,
+                    output_path,
+                } => {
+                    entrypoint::Centrality::build_trustrank(webgraph_path, seeds_path, output_path)?
+                }
+                CentralityMode::LinkSpam {
+                    webgraph_path,
+                    output_path,
+                } => entrypoint::Centrality::build_link_spam(webgraph_path, output_path),
+                CentralityMode::DistributedHarmonic { config_path } => {
+                    let config: config::DistributedHarmonicConfig = load_toml_config(config_path);
+
+                    tokio::runtime::Builder::new_multi_thread()
+                        .enable_all()
+                        .build()?
+                        .block_on(entrypoint::Centrality::build_distributed_harmonic(config))?
+                }
+                CentralityMode::HarmonicWorker { host } => {
+                    tokio::runtime::Builder::new_multi_thread()
+                        .enable_all()
+                        .build()?
+                        .block_on(entrypoint::Centrality::run_harmonic_worker(host))?
+                }
+                CentralityMode::Betweenness {
+                    webgraph_path,
+                    output_path,
+                    pivots,
+                } => entrypoint::Centrality::build_betweenness(webgraph_path, output_path, pivots),
+                CentralityMode::PageRank {
+                    webgraph_path,
+                    output_path,
+                    damping,
+                    tolerance,
+                    max_iterations,
+                    weighted,
+                } => entrypoint::Centrality::build_pagerank(
+                    webgraph_path,
+                    output_path,
+                    PageRankConfig {
+                        damping,
+                        tolerance,
+                        max_iterations,
+                        weighted,
+                    },
+                )?,
             }
             tracing::info!("Done");
         }
@@ -293,6 +488,10 @@ fn main() -> Result<()> {
                     std::fs::remove_dir_all(other_path).unwrap();
                 }
             }
+            WebgraphOptions::Compact { path } => {
+                let mut webgraph = WebgraphBuilder::new(path).single_threaded().open();
+                webgraph.compact();
+            }
             WebgraphOptions::Server { config_path } => {
                 let config: config::WebgraphServerConfig = load_toml_config(config_path);
 
@@ -301,6 +500,11 @@ fn main() -> Result<()> {
                     .build()?
                     .block_on(webgraph_server::run(config))?
             }
+            WebgraphOptions::Export {
+                webgraph_path,
+                output_path,
+                format,
+            } => entrypoint::webgraph_export::run(webgraph_path, output_path, format)?,
         },
         Commands::Api { config_path } => {
             let config: config::ApiConfig = load_toml_config(config_path);
@@ -372,6 +576,34 @@ fn main() -> Result<()> {
 
                 entrypoint::crawler::planner(config)?;
             }
+            Crawler::ImportSeeds { config_path } => {
+                let config: config::SeedImportConfig = load_toml_config(config_path);
+
+                entrypoint::crawler::seeds(config)?;
+            }
+            Crawler::CompactHistory { config_path } => {
+                let config: config::RecrawlCompactionConfig = load_toml_config(config_path);
+
+                entrypoint::crawler::compact_recrawl_history(config)?;
+            }
+            Crawler::ExportFrontier {
+                job_queue_path,
+                output_path,
+                recrawl_history_path,
+            } => entrypoint::crawler::export_frontier(
+                job_queue_path,
+                output_path,
+                recrawl_history_path,
+            )?,
+            Crawler::ImportFrontier {
+                input_path,
+                job_queue_path,
+                recrawl_history_path,
+            } => entrypoint::crawler::import_frontier(
+                input_path,
+                job_queue_path,
+                recrawl_history_path,
+            )?,
         },
         Commands::SafetyClassifier { options } => match options {
             SafetyClassifierOptions::Train {
@@ -400,6 +632,35 @@ fn main() -> Result<()> {
             let config: config::WebSpellConfig = load_toml_config(config_path);
             entrypoint::web_spell::run(config)?;
         }
+        Commands::DiskUsage { path } => entrypoint::disk_usage::run(path)?,
+        Commands::ExportClicks {
+            click_log_path,
+            index_path,
+            output_path,
+            format,
+            min_query_impressions,
+        } => entrypoint::click_export::run(
+            click_log_path,
+            index_path,
+            output_path,
+            format,
+            min_query_impressions,
+        )?,
+        Commands::BuildAll {
+            config_path,
+            restart,
+        } => {
+            let config: config::BuildAllConfig = load_toml_config(config_path);
+            entrypoint::build_all::run(config, restart)?;
+        }
+        Commands::LtrTrainingData { config_path } => {
+            let config: config::LtrTrainingDataConfig = load_toml_config(config_path);
+            entrypoint::ltr_training_data::run(config)?;
+        }
+        Commands::ResultDiff { config_path } => {
+            let config: config::ResultDiffConfig = load_toml_config(config_path);
+            entrypoint::result_diff::run(config)?;
+        }
     }
 
     Ok(())
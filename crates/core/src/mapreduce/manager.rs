@@ -235,6 +235,31 @@ impl Manager {
         }
     }
 
+    /// Execute all the jobs on the remote machines. Unlike [`Manager::run`], the workers
+    /// are not stopped afterwards, so they can be given more jobs that depend on the
+    /// results. The results are returned in no particular order.
+    pub async fn map_all<W, I, O>(&self, jobs: impl Iterator<Item = I> + Send) -> Vec<O>
+    where
+        W: Worker,
+        I: Map<W, O> + Send + Clone,
+        O: Serialize + DeserializeOwned + Send,
+    {
+        futures::stream::iter(jobs.map(|job| self.map::<W, I, O>(job)))
+            .buffer_unordered(self.pool.size())
+            .collect()
+            .await
+    }
+
+    /// Stop the workers after they have been given their jobs with [`Manager::map_all`].
+    pub async fn stop<W, I, O>(self)
+    where
+        W: Worker,
+        I: Map<W, O> + Send,
+        O: Serialize + DeserializeOwned + Send,
+    {
+        self.pool.stop_workers::<W, I, O>().await;
+    }
+
     fn reduce<O1, O2>(acc: Option<O2>, elem: O1) -> O2
     where
         O1: Serialize + DeserializeOwned + Send,
@@ -0,0 +1,713 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Moderation of hosts and pages by the operators of the search engine.
+//!
+//! Hosts and pages are flagged either by users (see [`crate::feedback`]) or by
+//! classifiers such as the malware and phishing annotations. Operators review the
+//! flagged targets and decide to penalize, remove or whitelist them. Every decision
+//! is appended to an audit log.
+//!
+//! Decisions are applied at query time. Penalized and removed hosts are added to
+//! the disliked and blocked hosts of the query, so they are handled by the shards
+//! just like the host rankings of the user. Penalized pages are moved to the
+//! bottom of the results and removed pages are filtered out.
+//!
+//! Flags from the classifiers are recorded in the background by a [`FlagRecorder`],
+//! so searches never wait for the store.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use optics::HostRankings;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::{
+    feedback::FeedbackStore,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    search_prettifier::{Annotation, DisplayedWebpage},
+    ttl_cache::TTLCache,
+};
+
+const AUDIT_LOG_FILE: &str = "audit.jsonl";
+
+/// Flags waiting to be recorded. New flags are dropped while the queue is full.
+const MAX_PENDING_FLAGS: usize = 10_000;
+
+/// Maximum number of flags recorded with a single write to the store.
+const MAX_FLAG_BATCH: usize = 1_000;
+
+/// A query only flags the same result once within this window,
+/// so repeated searches don't inflate the counts.
+const FLAG_DEDUP_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+const MAX_DEDUP_ENTRIES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Target {
+    Host(String),
+    Page(String),
+}
+
+impl Target {
+    /// Normalize the target so the same host or page always has the same key.
+    fn normalize(self) -> Option<Self> {
+        match self {
+            Target::Host(host) => {
+                let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+
+                if host.is_empty() {
+                    None
+                } else {
+                    Some(Target::Host(host))
+                }
+            }
+            Target::Page(url) => Url::parse(url.trim())
+                .ok()
+                .map(|url| Target::Page(url.to_string())),
+        }
+    }
+
+    /// Whether the page at `url` is covered by the (normalized) target.
+    pub fn matches(&self, url: &str) -> bool {
+        match self {
+            Target::Host(host) => Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(|h| h == host))
+                .unwrap_or(false),
+            Target::Page(_) => Target::Page(url.to_string()).normalize().as_ref() == Some(self),
+        }
+    }
+
+    fn host(&self) -> Option<String> {
+        match self {
+            Target::Host(host) => Some(host.clone()),
+            Target::Page(url) => Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    Penalize,
+    Remove,
+    Whitelist,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Decision {
+    pub action: Action,
+    pub operator: String,
+    pub reason: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetDecision {
+    pub target: Target,
+    #[serde(flatten)]
+    pub decision: Decision,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub timestamp: i64,
+    pub operator: String,
+    pub target: Target,
+    pub action: Action,
+    pub previous: Option<Action>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct FlagKey {
+    target: Target,
+    source: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FlagCount {
+    count: u64,
+    last_flagged: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagSource {
+    /// Where the flag came from, e.g. `feedback:spam` or `malware:blocklist`.
+    pub source: String,
+    pub count: u64,
+    pub last_flagged: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewItem {
+    pub target: Target,
+    pub flags: Vec<FlagSource>,
+}
+
+impl ReviewItem {
+    fn total_count(&self) -> u64 {
+        self.flags.iter().map(|flag| flag.count).sum()
+    }
+}
+
+fn now() -> i64 {
+    let now = Utc::now().timestamp();
+    // no need for more precision than hours
+    now - now.rem_euclid(60 * 60)
+}
+
+pub struct ModerationStore {
+    decisions: RocksDbStore<Target, Decision>,
+    flags: RocksDbStore<FlagKey, FlagCount>,
+    // decisions are needed on every search, so we keep them in memory
+    active: RwLock<HashMap<Target, Action>>,
+    audit_log_path: PathBuf,
+    // guards writes to the stores and the audit log
+    write_lock: Mutex<()>,
+}
+
+impl ModerationStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+
+        let decisions: RocksDbStore<Target, Decision> = RocksDbStore::open(path.join("decisions"));
+        let active = decisions
+            .iter()
+            .map(|(target, decision)| (target, decision.action))
+            .collect();
+
+        Ok(Self {
+            decisions,
+            flags: RocksDbStore::open(path.join("flags")),
+            active: RwLock::new(active),
+            audit_log_path: path.join(AUDIT_LOG_FILE),
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn action(&self, target: &Target) -> Option<Action> {
+        self.active
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target)
+            .copied()
+    }
+
+    /// Record that a classifier or other automatic source flagged the target.
+    /// Whitelisted targets are not flagged again.
+    pub fn flag(&self, target: Target, source: &str) {
+        self.flag_all(vec![(target, source.to_string())]);
+    }
+
+    /// Record a batch of flags as `(target, source)` pairs.
+    pub fn flag_all(&self, flags: Vec<(Target, String)>) {
+        let whitelisted = |target: &Target| self.action(target) == Some(Action::Whitelist);
+
+        let keys: Vec<_> = flags
+            .into_iter()
+            .filter_map(|(target, source)| Some((target.normalize()?, source)))
+            .filter(|(target, _)| {
+                !whitelisted(target)
+                    && !target
+                        .host()
+                        .map(|host| whitelisted(&Target::Host(host)))
+                        .unwrap_or(false)
+            })
+            .map(|(target, source)| FlagKey { target, source })
+            .collect();
+
+        if keys.is_empty() {
+            return;
+        }
+
+        let now = now();
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        for key in keys {
+            let mut count = self.flags.get(&key).unwrap_or_default();
+
+            count.count += 1;
+            count.last_flagged = now;
+
+            self.flags.insert(key, count);
+        }
+    }
+
+    pub fn decide(
+        &self,
+        target: Target,
+        action: Action,
+        operator: String,
+        reason: Option<String>,
+    ) -> Result<AuditEntry> {
+        let target = target
+            .normalize()
+            .ok_or_else(|| anyhow::anyhow!("invalid moderation target"))?;
+
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let previous = self.action(&target);
+        let timestamp = Utc::now().timestamp();
+
+        let entry = AuditEntry {
+            timestamp,
+            operator: operator.clone(),
+            target: target.clone(),
+            action,
+            previous,
+            reason: reason.clone(),
+        };
+
+        // the decision is only applied if it has been logged
+        self.append_audit_entry(&entry)?;
+
+        self.decisions.insert(
+            target.clone(),
+            Decision {
+                action,
+                operator,
+                reason,
+                timestamp,
+            },
+        );
+        self.decisions.flush();
+
+        self.active
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(target, action);
+
+        Ok(entry)
+    }
+
+    fn append_audit_entry(&self, entry: &AuditEntry) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_log_path)?;
+
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+
+    pub fn audit_log(&self) -> Result<Vec<AuditEntry>> {
+        if !self.audit_log_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        BufReader::new(File::open(&self.audit_log_path)?)
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    /// All decisions, newest first.
+    pub fn decisions(&self) -> Vec<TargetDecision> {
+        let mut res: Vec<_> = self
+            .decisions
+            .iter()
+            .map(|(target, decision)| TargetDecision { target, decision })
+            .collect();
+
+        res.sort_by(|a, b| b.decision.timestamp.cmp(&a.decision.timestamp));
+
+        res
+    }
+
+    /// Targets flagged at least `min_reports` times that have not been decided on yet,
+    /// most flagged first. The reports of the users are taken from the feedback store.
+    pub fn review_queue(
+        &self,
+        feedback: Option<&FeedbackStore>,
+        min_reports: u64,
+    ) -> Vec<ReviewItem> {
+        let mut items: BTreeMap<Target, Vec<FlagSource>> = BTreeMap::new();
+
+        for (key, count) in self.flags.iter() {
+            items.entry(key.target).or_default().push(FlagSource {
+                source: key.source,
+                count: count.count,
+                last_flagged: count.last_flagged,
+            });
+        }
+
+        if let Some(feedback) = feedback {
+            for report in feedback.review_queue(0) {
+                let flags = items.entry(Target::Page(report.url)).or_default();
+                let source = format!("feedback:{}", report.kind.as_str());
+
+                match flags.iter_mut().find(|flag| flag.source == source) {
+                    Some(flag) => {
+                        flag.count += report.count;
+                        flag.last_flagged = flag.last_flagged.max(report.last_reported);
+                    }
+                    None => flags.push(FlagSource {
+                        source,
+                        count: report.count,
+                        last_flagged: report.last_reported,
+                    }),
+                }
+            }
+        }
+
+        let mut res: Vec<_> = items
+            .into_iter()
+            .filter(|(target, _)| !self.is_decided(target))
+            .map(|(target, flags)| ReviewItem { target, flags })
+            .filter(|item| item.total_count() >= min_reports)
+            .collect();
+
+        res.sort_by(|a, b| b.total_count().cmp(&a.total_count()));
+
+        res
+    }
+
+    fn is_decided(&self, target: &Target) -> bool {
+        if self.action(target).is_some() {
+            return true;
+        }
+
+        match target {
+            Target::Host(_) => false,
+            Target::Page(_) => target
+                .host()
+                .map(|host| self.action(&Target::Host(host)).is_some())
+                .unwrap_or(false),
+        }
+    }
+
+    /// Add the penalized and removed hosts to the host rankings of the query.
+    pub fn apply_host_rankings(&self, host_rankings: &mut Option<HostRankings>) {
+        let active = self.active.read().unwrap_or_else(|e| e.into_inner());
+
+        for (target, action) in active.iter() {
+            if let Target::Host(host) = target {
+                let rankings = host_rankings.get_or_insert_with(HostRankings::default);
+
+                match action {
+                    Action::Penalize => rankings.disliked.push(host.clone()),
+                    Action::Remove => rankings.blocked.push(host.clone()),
+                    Action::Whitelist => {}
+                }
+            }
+        }
+    }
+
+    /// The action for the page at `url`, either for the page itself or its host.
+    pub fn action_for_url(&self, url: &str) -> Option<Action> {
+        let page = Target::Page(url.to_string()).normalize()?;
+
+        self.action(&page)
+            .or_else(|| self.action(&Target::Host(page.host()?)))
+    }
+
+    /// Remove results that have been removed by an operator and
+    /// move the penalized results to the bottom.
+    pub fn apply(&self, webpages: &mut Vec<DisplayedWebpage>) {
+        let action = |webpage: &DisplayedWebpage| self.action_for_url(&webpage.url);
+
+        webpages.retain(|webpage| action(webpage) != Some(Action::Remove));
+
+        // stable sort keeps the order of the results within each group
+        webpages.sort_by_key(|webpage| action(webpage) == Some(Action::Penalize));
+    }
+
+    /// Pages that should be deleted from the index.
+    pub fn removed_pages(&self) -> Vec<String> {
+        self.active
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter_map(|(target, action)| match (target, action) {
+                (Target::Page(url), Action::Remove) => Some(url.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Records the flags of dangerous results in the background.
+pub struct FlagRecorder {
+    sender: mpsc::Sender<(Target, String)>,
+    recent: Mutex<TTLCache<(String, Target, String), ()>>,
+}
+
+impl FlagRecorder {
+    /// Start recording flags to the store. Must be called from within a tokio runtime.
+    pub fn spawn(store: Arc<ModerationStore>) -> Self {
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_FLAGS);
+        tokio::spawn(record_flags(store, receiver));
+
+        Self {
+            sender,
+            recent: Mutex::new(TTLCache::with_ttl_and_max_size(
+                FLAG_DEDUP_WINDOW,
+                Some(MAX_DEDUP_ENTRIES),
+            )),
+        }
+    }
+
+    /// Flag the results that the annotation providers found to be dangerous.
+    pub fn flag_dangerous(&self, query: &str, webpages: &[DisplayedWebpage]) {
+        for webpage in webpages {
+            for annotation in &webpage.annotations {
+                let source = match annotation {
+                    Annotation::Malware { source } => format!("malware:{source}"),
+                    Annotation::Phishing { source } => format!("phishing:{source}"),
+                    _ => continue,
+                };
+
+                let target = Target::Page(webpage.url.clone());
+                let key = (query.to_string(), target.clone(), source.clone());
+
+                {
+                    let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+
+                    if recent.get(&key).is_some() {
+                        continue;
+                    }
+
+                    recent.insert(key, ());
+                }
+
+                if self.sender.try_send((target, source)).is_err() {
+                    tracing::debug!("dropping moderation flag as the queue is full");
+                }
+            }
+        }
+    }
+}
+
+async fn record_flags(store: Arc<ModerationStore>, mut receiver: mpsc::Receiver<(Target, String)>) {
+    while let Some(flag) = receiver.recv().await {
+        let mut batch = vec![flag];
+
+        while batch.len() < MAX_FLAG_BATCH {
+            match receiver.try_recv() {
+                Ok(flag) => batch.push(flag),
+                Err(_) => break,
+            }
+        }
+
+        let store = Arc::clone(&store);
+
+        if let Err(err) = tokio::task::spawn_blocking(move || store.flag_all(batch)).await {
+            tracing::error!("failed to record moderation flags: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::{
+        feedback::{Feedback, FeedbackKind},
+        inverted_index::RetrievedWebpage,
+    };
+
+    use super::*;
+
+    fn webpage(url: &str) -> DisplayedWebpage {
+        DisplayedWebpage::from(RetrievedWebpage {
+            title: "title".to_string(),
+            url: url.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn review_queue() {
+        let store = ModerationStore::open(crate::gen_temp_path()).unwrap();
+        let feedback = FeedbackStore::open(crate::gen_temp_path(), 100).unwrap();
+
+        store.flag(Target::Page("https://a.com/evil".to_string()), "blocklist");
+        store.flag(Target::Host("B.com".to_string()), "blocklist");
+        feedback
+            .report(
+                IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+                Feedback {
+                    query: "test".to_string(),
+                    url: Url::parse("https://a.com/evil").unwrap(),
+                    kind: FeedbackKind::Spam,
+                },
+            )
+            .unwrap();
+
+        let queue = store.review_queue(Some(&feedback), 1);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(
+            queue[0].target,
+            Target::Page("https://a.com/evil".to_string())
+        );
+        assert_eq!(queue[0].flags.len(), 2);
+        assert_eq!(queue[1].target, Target::Host("b.com".to_string()));
+
+        store
+            .decide(
+                Target::Host("a.com".to_string()),
+                Action::Whitelist,
+                "operator".to_string(),
+                None,
+            )
+            .unwrap();
+
+        let queue = store.review_queue(Some(&feedback), 1);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].target, Target::Host("b.com".to_string()));
+    }
+
+    #[test]
+    fn decisions_are_audited() {
+        let path = crate::gen_temp_path();
+        let store = ModerationStore::open(&path).unwrap();
+        let target = Target::Host("spam.com".to_string());
+
+        store
+            .decide(target.clone(), Action::Penalize, "alice".to_string(), None)
+            .unwrap();
+        store
+            .decide(
+                target.clone(),
+                Action::Remove,
+                "bob".to_string(),
+                Some("still spam".to_string()),
+            )
+            .unwrap();
+
+        let log = store.audit_log().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].previous, None);
+        assert_eq!(log[1].previous, Some(Action::Penalize));
+        assert_eq!(log[1].operator, "bob");
+
+        drop(store);
+        let store = ModerationStore::open(&path).unwrap();
+        assert_eq!(store.action(&target), Some(Action::Remove));
+        assert_eq!(store.audit_log().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn apply_decisions() {
+        let store = ModerationStore::open(crate::gen_temp_path()).unwrap();
+
+        let decide = |target, action| {
+            store
+                .decide(target, action, "operator".to_string(), None)
+                .unwrap()
+        };
+
+        decide(
+            Target::Page("https://a.com/removed".to_string()),
+            Action::Remove,
+        );
+        decide(Target::Host("b.com".to_string()), Action::Penalize);
+        decide(Target::Host("c.com".to_string()), Action::Remove);
+
+        let mut webpages = vec![
+            webpage("https://b.com/"),
+            webpage("https://a.com/removed"),
+            webpage("https://a.com/"),
+            webpage("https://c.com/"),
+        ];
+        store.apply(&mut webpages);
+
+        let urls: Vec<_> = webpages.iter().map(|w| w.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://a.com/", "https://b.com/"]);
+
+        let mut host_rankings = None;
+        store.apply_host_rankings(&mut host_rankings);
+        let host_rankings = host_rankings.unwrap();
+        assert_eq!(host_rankings.disliked, vec!["b.com".to_string()]);
+        assert_eq!(host_rankings.blocked, vec!["c.com".to_string()]);
+
+        assert_eq!(
+            store.removed_pages(),
+            vec!["https://a.com/removed".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_searches_flag_once() {
+        let store = Arc::new(ModerationStore::open(crate::gen_temp_path()).unwrap());
+        let recorder = FlagRecorder::spawn(Arc::clone(&store));
+
+        let mut evil = webpage("https://a.com/evil");
+        evil.annotations.push(Annotation::Malware {
+            source: "blocklist".to_string(),
+        });
+        let webpages = vec![evil, webpage("https://a.com/")];
+
+        recorder.flag_dangerous("a", &webpages);
+        recorder.flag_dangerous("a", &webpages);
+        recorder.flag_dangerous("b", &webpages);
+
+        let mut queue = Vec::new();
+        for _ in 0..100 {
+            queue = store.review_queue(None, 1);
+
+            if queue.first().map(|item| item.total_count()) == Some(2) {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(queue.len(), 1);
+        assert_eq!(
+            queue[0].target,
+            Target::Page("https://a.com/evil".to_string())
+        );
+        assert_eq!(queue[0].flags[0].source, "malware:blocklist");
+        assert_eq!(queue[0].total_count(), 2);
+    }
+
+    #[test]
+    fn target_matches() {
+        let host = Target::Host("A.com".to_string()).normalize().unwrap();
+        let page = Target::Page("https://a.com/page".to_string())
+            .normalize()
+            .unwrap();
+
+        assert!(host.matches("https://a.com/other"));
+        assert!(!host.matches("https://b.a.com/"));
+        assert!(page.matches("https://a.com/page"));
+        assert!(!page.matches("https://a.com/other"));
+    }
+}
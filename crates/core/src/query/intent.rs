@@ -0,0 +1,107 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Lightweight classification of what the user is looking for.
+//!
+//! The classifier only looks at the terms of the query, so it is cheap enough to
+//! run for every search. It is used to adjust the ranking signals at query time.
+
+use chrono::{Datelike, Utc};
+
+/// Terms that indicate that the user is looking for recent events.
+const NEWS_TERMS: &[&str] = &[
+    "news",
+    "latest",
+    "breaking",
+    "today",
+    "todays",
+    "yesterday",
+    "tonight",
+    "headlines",
+    "live",
+    "update",
+    "updates",
+    "announced",
+    "announces",
+    "election",
+    "results",
+    "score",
+    "scores",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intent {
+    /// The user is looking for recent events.
+    News,
+    Other,
+}
+
+impl Intent {
+    pub fn classify(terms: &[String]) -> Self {
+        let current_year = Utc::now().year();
+
+        let is_news = terms.iter().any(|term| {
+            let term = term
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .replace('\'', "")
+                .to_lowercase();
+
+            NEWS_TERMS.contains(&term.as_str())
+                || term
+                    .parse::<i32>()
+                    .map(|year| year == current_year)
+                    .unwrap_or(false)
+        });
+
+        if is_news {
+            Intent::News
+        } else {
+            Intent::Other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(query: &str) -> Intent {
+        Intent::classify(
+            &query
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn news_queries() {
+        assert_eq!(classify("election results"), Intent::News);
+        assert_eq!(classify("Latest rust release"), Intent::News);
+        assert_eq!(classify("today's headlines"), Intent::News);
+        assert_eq!(
+            classify(&format!("world cup {}", Utc::now().year())),
+            Intent::News
+        );
+    }
+
+    #[test]
+    fn other_queries() {
+        assert_eq!(classify("rust borrow checker"), Intent::Other);
+        assert_eq!(classify("world cup 1998"), Intent::Other);
+        assert_eq!(classify(""), Intent::Other);
+    }
+}
@@ -18,17 +18,18 @@ use crate::{
     inverted_index::InvertedIndex,
     query::parser::TermCompound,
     ranking::SignalCoefficient,
-    schema::{Field, TextField},
+    schema::{FastField, Field, TextField, FLOAT_SCALING},
     search_ctx::Ctx,
     searcher::SearchQuery,
-    webpage::{region::Region, safety_classifier},
+    webpage::{region::Region, safety_classifier, ACCESSIBLE_THRESHOLD},
     Result,
 };
 use optics::{HostRankings, Optic};
 use std::collections::HashMap;
-use tantivy::query::{BooleanQuery, Occur, QueryClone, TermQuery};
+use tantivy::query::{BooleanQuery, Occur, QueryClone, RangeQuery, TermQuery};
 
 mod const_query;
+pub mod intent;
 pub mod intersection;
 pub mod optic;
 pub mod parser;
@@ -127,6 +128,18 @@ impl Query {
             ));
         }
 
+        if query.accessible_only {
+            let threshold = (ACCESSIBLE_THRESHOLD * FLOAT_SCALING as f64) as u64;
+
+            queries.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_u64(
+                    Field::Fast(FastField::Accessibility).name().to_string(),
+                    threshold..u64::MAX,
+                )),
+            ));
+        }
+
         let mut tantivy_query = Box::new(BooleanQuery::new(queries));
 
         let simple_terms_text: Vec<String> = terms
@@ -146,6 +159,10 @@ impl Query {
             optics.push(site_rankigns_optic);
         }
 
+        // the user supplied optic must be last, as later
+        // optics take precedence for the signal coefficients
+        optics.extend(query.default_optics.iter().cloned());
+
         if let Some(optic) = &query.optic {
             optics.push(optic.clone());
         }
@@ -213,15 +230,7 @@ impl Query {
             return None;
         }
 
-        Some(
-            self.optics
-                .iter()
-                .fold(SignalCoefficient::default(), |mut acc, optic| {
-                    let coeffs = SignalCoefficient::from_optic(optic);
-                    acc.merge_into(coeffs);
-                    acc
-                }),
-        )
+        Some(SignalCoefficient::from_optics(&self.optics))
     }
 }
 
@@ -925,6 +934,65 @@ mod tests {
         assert_eq!(result.webpages[0].url, "https://www.sfw.com/");
     }
 
+    #[test]
+    fn accessible_only() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, body) in [
+            (
+                "https://www.accessible.com",
+                r#"<h1>Test website</h1><img src="a.png" alt="A chart">"#,
+            ),
+            (
+                "https://www.inaccessible.com",
+                r#"<h4>Test website</h4><img src="a.png"><p style="color: #eee; background: #fff">hi</p>"#,
+            ),
+        ] {
+            let mut webpage = Webpage::new(
+                &format!(
+                    r#"
+                <html>
+                    <head>
+                        <title>Test website</title>
+                    </head>
+                    <body>
+                        {body}
+                        This is a test website {}
+                    </body>
+                </html>
+            "#,
+                    rand_words(1000)
+                ),
+                url,
+            )
+            .unwrap();
+
+            webpage.html.set_clean_text("test".to_string());
+            index.insert(webpage).expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let query = SearchQuery {
+            query: "test".to_string(),
+            ..Default::default()
+        };
+
+        let result = searcher.search(&query).expect("Search failed");
+        assert_eq!(result.webpages.len(), 2);
+
+        let query = SearchQuery {
+            query: "test".to_string(),
+            accessible_only: true,
+            ..Default::default()
+        };
+
+        let result = searcher.search(&query).expect("Search failed");
+        assert_eq!(result.webpages.len(), 1);
+        assert_eq!(result.webpages[0].url, "https://www.accessible.com/");
+    }
+
     #[test]
     fn suffix_domain_prefix_path_site_operator() {
         let mut index = Index::temporary().expect("Unable to open index");
@@ -14,16 +14,18 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use chrono::{NaiveDate, TimeZone, Utc};
 use std::fmt::Display;
 use tantivy::{
-    query::{BooleanQuery, Occur, PhraseQuery, TermQuery},
+    query::{BooleanQuery, Occur, PhraseQuery, RangeQuery, TermQuery},
     tokenizer::Tokenizer,
 };
 
 use crate::{
     bangs::BANG_PREFIXES,
     floor_char_boundary,
-    schema::{Field, TextField},
+    schema::{FastField, Field, TextField},
+    webpage::language,
 };
 
 #[derive(Debug, Clone)]
@@ -80,6 +82,14 @@ pub enum Term {
     Title(String),
     Body(String),
     Url(String),
+    Anchor(String),
+    /// ISO 639-3 code of the language the pages must be written in.
+    Language(String),
+    Author(String),
+    /// Pages published before the date.
+    Before(NaiveDate),
+    /// Pages published on or after the date.
+    After(NaiveDate),
     PossibleBang(String),
 }
 
@@ -93,6 +103,11 @@ impl Display for Term {
             Term::Title(title) => write!(f, "intitle:{}", title),
             Term::Body(body) => write!(f, "inbody:{}", body),
             Term::Url(url) => write!(f, "inurl:{}", url),
+            Term::Anchor(anchor) => write!(f, "inanchor:{}", anchor),
+            Term::Language(lang) => write!(f, "lang:{}", lang),
+            Term::Author(author) => write!(f, "author:{}", author),
+            Term::Before(date) => write!(f, "before:{}", date.format("%Y-%m-%d")),
+            Term::After(date) => write!(f, "after:{}", date.format("%Y-%m-%d")),
             Term::PossibleBang(bang) => write!(f, "{}{}", BANG_PREFIXES[0], bang),
         }
     }
@@ -231,6 +246,63 @@ impl Term {
 
                 (Occur::Must, Term::tantivy_text_query(field, url))
             }
+            Term::Anchor(anchor) => {
+                let field = fields
+                    .iter()
+                    .find(|field| {
+                        matches!(
+                            Field::get(field.field_id() as usize),
+                            Some(Field::Text(TextField::BacklinkText))
+                        )
+                    })
+                    .unwrap();
+
+                (Occur::Must, Term::tantivy_text_query(field, anchor))
+            }
+            Term::Language(lang) => {
+                let field = fields
+                    .iter()
+                    .find(|field| {
+                        matches!(
+                            Field::get(field.field_id() as usize),
+                            Some(Field::Text(TextField::Language))
+                        )
+                    })
+                    .unwrap();
+
+                (Occur::Must, Term::tantivy_text_query(field, lang))
+            }
+            Term::Author(author) => {
+                let field = fields
+                    .iter()
+                    .find(|field| {
+                        matches!(
+                            Field::get(field.field_id() as usize),
+                            Some(Field::Text(TextField::Author))
+                        )
+                    })
+                    .unwrap();
+
+                (Occur::Must, Term::tantivy_text_query(field, author))
+            }
+            Term::Before(date) => {
+                // pages without a known publish date have a timestamp of 0
+                // and should not match
+                (
+                    Occur::Must,
+                    Box::new(RangeQuery::new_u64(
+                        Field::Fast(FastField::PublishedTime).name().to_string(),
+                        1..date_timestamp(date),
+                    )),
+                )
+            }
+            Term::After(date) => (
+                Occur::Must,
+                Box::new(RangeQuery::new_u64(
+                    Field::Fast(FastField::PublishedTime).name().to_string(),
+                    date_timestamp(date)..u64::MAX,
+                )),
+            ),
             Term::PossibleBang(text) => {
                 let mut term = String::new();
 
@@ -379,6 +451,33 @@ fn parse_term(term: &str) -> Box<Term> {
         } else {
             Box::new(Term::Simple(term.to_string().into()))
         }
+    } else if let Some(anchor) = term.strip_prefix("inanchor:") {
+        if !anchor.is_empty() {
+            Box::new(Term::Anchor(anchor.to_string()))
+        } else {
+            Box::new(Term::Simple(term.to_string().into()))
+        }
+    } else if let Some(lang) = term.strip_prefix("lang:") {
+        match language::from_code(lang) {
+            Some(lang) => Box::new(Term::Language(lang.code().to_string())),
+            None => Box::new(Term::Simple(term.to_string().into())),
+        }
+    } else if let Some(author) = term.strip_prefix("author:") {
+        if !author.is_empty() {
+            Box::new(Term::Author(author.to_string()))
+        } else {
+            Box::new(Term::Simple(term.to_string().into()))
+        }
+    } else if let Some(date) = term.strip_prefix("before:") {
+        match parse_operator_date(date) {
+            Some(date) => Box::new(Term::Before(date)),
+            None => Box::new(Term::Simple(term.to_string().into())),
+        }
+    } else if let Some(date) = term.strip_prefix("after:") {
+        match parse_operator_date(date) {
+            Some(date) => Box::new(Term::After(date)),
+            None => Box::new(Term::Simple(term.to_string().into())),
+        }
     } else {
         for bang_prefix in BANG_PREFIXES {
             if let Some(bang) = term.strip_prefix(bang_prefix) {
@@ -390,6 +489,22 @@ fn parse_term(term: &str) -> Box<Term> {
     }
 }
 
+/// Parse the date of a `before:` or `after:` operator. Both
+/// full dates (2021-03-03), months (2021-03) and years (2021) are supported
+/// and are interpreted as the first day of the period.
+fn parse_operator_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(&format!("{date}-01"), "%Y-%m-%d").ok())
+        .or_else(|| NaiveDate::parse_from_str(&format!("{date}-01-01"), "%Y-%m-%d").ok())
+}
+
+fn date_timestamp(date: &NaiveDate) -> u64 {
+    date.and_hms_opt(0, 0, 0).map_or(0, |time| {
+        Utc.from_utc_datetime(&time).timestamp().max(0) as u64
+    })
+}
+
 #[allow(clippy::vec_box)]
 pub fn parse(query: &str) -> Vec<Box<Term>> {
     let query = query.to_lowercase().replace(['“', '”'], "\"");
@@ -515,6 +630,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn anchor() {
+        assert_eq!(
+            parse("this inanchor:test"),
+            vec![
+                Box::new(Term::Simple("this".to_string().into())),
+                Box::new(Term::Anchor("test".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn language() {
+        assert_eq!(
+            parse("rezepte lang:de"),
+            vec![
+                Box::new(Term::Simple("rezepte".to_string().into())),
+                Box::new(Term::Language("deu".to_string()))
+            ]
+        );
+        assert_eq!(
+            parse("lang:xx"),
+            vec![Box::new(Term::Simple("lang:xx".to_string().into()))]
+        );
+    }
+
+    #[test]
+    fn author() {
+        assert_eq!(
+            parse("russia author:sanger"),
+            vec![
+                Box::new(Term::Simple("russia".to_string().into())),
+                Box::new(Term::Author("sanger".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            parse("news before:2021-03-03 after:2020"),
+            vec![
+                Box::new(Term::Simple("news".to_string().into())),
+                Box::new(Term::Before(NaiveDate::from_ymd_opt(2021, 3, 3).unwrap())),
+                Box::new(Term::After(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap())),
+            ]
+        );
+        assert_eq!(
+            parse("after:2020-02"),
+            vec![Box::new(Term::After(
+                NaiveDate::from_ymd_opt(2020, 2, 1).unwrap()
+            ))]
+        );
+        assert_eq!(
+            parse("before:yesterday"),
+            vec![Box::new(Term::Simple(
+                "before:yesterday".to_string().into()
+            ))]
+        );
+    }
+
     #[test]
     fn empty() {
         assert_eq!(parse(""), vec![]);
@@ -0,0 +1,293 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! BM25F scoring of the query over multiple text fields at once.
+//!
+//! Instead of saturating the term frequency of every field on its own, the term
+//! frequencies are first normalized by the length of their field, weighted and summed
+//! into a single pseudo term frequency per query term. The saturation is then applied
+//! once, so a term that occurs in both the title and the body does not get the full
+//! score twice. See Robertson et al., "Simple BM25 extension to multiple weighted fields".
+
+use tantivy::fieldnorm::FieldNormReader;
+use tantivy::postings::SegmentPostings;
+use tantivy::tokenizer::Tokenizer;
+use tantivy::{DocId, DocSet, Postings, Score, Searcher, Term};
+
+use crate::{schema::TextField, Result};
+
+use super::bm25::idf;
+
+const K1: Score = 1.2;
+
+pub struct Bm25FField {
+    pub field: TextField,
+    /// How much an occurrence in this field counts relative to an occurrence in the body.
+    pub weight: Score,
+    /// Length normalization of the field. 0 disables the normalization and 1 fully
+    /// scales the term frequency by the length of the field relative to the average.
+    pub b: Score,
+}
+
+pub const BM25F_FIELDS: [Bm25FField; 4] = [
+    Bm25FField {
+        field: TextField::Title,
+        weight: 3.0,
+        b: 0.75,
+    },
+    Bm25FField {
+        field: TextField::CleanBody,
+        weight: 1.0,
+        b: 0.75,
+    },
+    Bm25FField {
+        field: TextField::Url,
+        weight: 1.5,
+        b: 0.5,
+    },
+    // backlinks often repeat the same anchor text, so long fields should not be penalized as much
+    Bm25FField {
+        field: TextField::BacklinkText,
+        weight: 2.0,
+        b: 0.25,
+    },
+];
+
+#[derive(Clone)]
+struct FieldNorm {
+    /// `weight / (1 - b + b * dl / avgdl)` for every fieldnorm id.
+    cache: [Score; 256],
+}
+
+impl FieldNorm {
+    fn new(field: &Bm25FField, average_fieldnorm: Score) -> Self {
+        let mut cache = [0.0; 256];
+
+        for (fieldnorm_id, cache_mut) in cache.iter_mut().enumerate() {
+            let fieldnorm = FieldNormReader::id_to_fieldnorm(fieldnorm_id as u8) as Score;
+            let norm = 1.0 - field.b + field.b * fieldnorm / average_fieldnorm;
+
+            *cache_mut = field.weight / norm;
+        }
+
+        Self { cache }
+    }
+}
+
+#[derive(Clone)]
+pub struct Bm25FWeight {
+    fields: Vec<FieldNorm>,
+}
+
+impl Bm25FWeight {
+    pub fn new(fields: &[Bm25FField], average_fieldnorms: &[Score]) -> Self {
+        Self {
+            fields: fields
+                .iter()
+                .zip(average_fieldnorms)
+                .map(|(field, average_fieldnorm)| FieldNorm::new(field, *average_fieldnorm))
+                .collect(),
+        }
+    }
+
+    /// The weighted and length normalized term frequency of a term in one of the fields.
+    #[inline]
+    pub fn pseudo_tf(&self, field: usize, fieldnorm_id: u8, term_freq: u32) -> Score {
+        term_freq as Score * self.fields[field].cache[fieldnorm_id as usize]
+    }
+
+    /// Score of a term given the sum of its pseudo term frequencies over all the fields.
+    #[inline]
+    pub fn score(&self, idf: Score, pseudo_tf: Score) -> Score {
+        idf * (K1 + 1.0) * pseudo_tf / (K1 + pseudo_tf)
+    }
+}
+
+#[derive(Clone)]
+struct Bm25FTerm {
+    idf: Score,
+    /// Postings of the tokens of the term in each of the fields.
+    postings: Vec<(usize, SegmentPostings)>,
+}
+
+#[derive(Clone)]
+pub struct Bm25FScorer {
+    weight: Bm25FWeight,
+    fieldnorm_readers: Vec<FieldNormReader>,
+    terms: Vec<Bm25FTerm>,
+}
+
+impl Bm25FScorer {
+    /// Prepare the scorer for a segment. Returns `None` if none of the terms occur in the segment.
+    pub fn for_segment(
+        searcher: &Searcher,
+        segment_reader: &tantivy::SegmentReader,
+        terms: &[String],
+    ) -> Result<Option<Self>> {
+        let schema = searcher.schema();
+        let total_num_docs: u64 = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment| u64::from(segment.max_doc()))
+            .sum();
+
+        if total_num_docs == 0 {
+            return Ok(None);
+        }
+
+        let mut average_fieldnorms = Vec::with_capacity(BM25F_FIELDS.len());
+        let mut fieldnorm_readers = Vec::with_capacity(BM25F_FIELDS.len());
+        let mut inverted_indices = Vec::with_capacity(BM25F_FIELDS.len());
+
+        for field in &BM25F_FIELDS {
+            let tv_field = schema.get_field(field.field.name()).unwrap();
+
+            let mut total_num_tokens = 0u64;
+            for segment in searcher.segment_readers() {
+                total_num_tokens += segment.inverted_index(tv_field)?.total_num_tokens();
+            }
+
+            average_fieldnorms.push(if total_num_tokens == 0 {
+                1.0
+            } else {
+                total_num_tokens as Score / total_num_docs as Score
+            });
+            fieldnorm_readers.push(segment_reader.get_fieldnorms_reader(tv_field)?);
+            inverted_indices.push(segment_reader.inverted_index(tv_field)?);
+        }
+
+        let mut bm25f_terms = Vec::with_capacity(terms.len());
+
+        for term in terms {
+            let mut postings = Vec::new();
+            let mut doc_freq = 0;
+
+            for (i, field) in BM25F_FIELDS.iter().enumerate() {
+                let tv_field = schema.get_field(field.field.name()).unwrap();
+                let mut tokenizer = field.field.indexing_tokenizer();
+                let mut stream = tokenizer.token_stream(term);
+
+                while let Some(token) = stream.next() {
+                    let tv_term = Term::from_field_text(tv_field, &token.text);
+
+                    // the number of documents that contain the term in any field is
+                    // at least the number of documents that contain it in a single field
+                    doc_freq = doc_freq.max(searcher.doc_freq(&tv_term)?);
+
+                    if let Some(p) =
+                        inverted_indices[i].read_postings(&tv_term, field.field.index_option())?
+                    {
+                        postings.push((i, p));
+                    }
+                }
+            }
+
+            if !postings.is_empty() {
+                bm25f_terms.push(Bm25FTerm {
+                    idf: idf(doc_freq, total_num_docs),
+                    postings,
+                });
+            }
+        }
+
+        if bm25f_terms.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            weight: Bm25FWeight::new(&BM25F_FIELDS, &average_fieldnorms),
+            fieldnorm_readers,
+            terms: bm25f_terms,
+        }))
+    }
+
+    /// Score the document. The documents must be scored in ascending order of their id.
+    pub fn score(&mut self, doc: DocId) -> f64 {
+        let mut score = 0.0;
+
+        for term in &mut self.terms {
+            let mut pseudo_tf = 0.0;
+
+            for (field, posting) in &mut term.postings {
+                if posting.doc() == doc || (posting.doc() < doc && posting.seek(doc) == doc) {
+                    let fieldnorm_id = self.fieldnorm_readers[*field].fieldnorm_id(doc);
+                    pseudo_tf += self
+                        .weight
+                        .pseudo_tf(*field, fieldnorm_id, posting.term_freq());
+                }
+            }
+
+            if pseudo_tf > 0.0 {
+                score += self.weight.score(term.idf, pseudo_tf);
+            }
+        }
+
+        score as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> [Bm25FField; 2] {
+        [
+            Bm25FField {
+                field: TextField::Title,
+                weight: 2.0,
+                b: 0.0,
+            },
+            Bm25FField {
+                field: TextField::CleanBody,
+                weight: 1.0,
+                b: 1.0,
+            },
+        ]
+    }
+
+    fn fieldnorm_id(fieldnorm: u32) -> u8 {
+        FieldNormReader::fieldnorm_to_id(fieldnorm)
+    }
+
+    #[test]
+    fn field_weights() {
+        let weight = Bm25FWeight::new(&fields(), &[10.0, 10.0]);
+
+        // without length normalization only the weight matters
+        assert_eq!(weight.pseudo_tf(0, fieldnorm_id(10), 1), 2.0);
+        assert_eq!(weight.pseudo_tf(0, fieldnorm_id(40), 1), 2.0);
+
+        // an average length field is not normalized
+        assert_eq!(weight.pseudo_tf(1, fieldnorm_id(10), 3), 3.0);
+        // but a field of twice the average length counts half as much
+        assert_eq!(weight.pseudo_tf(1, fieldnorm_id(20), 2), 1.0);
+    }
+
+    #[test]
+    fn saturation_is_applied_across_fields() {
+        let weight = Bm25FWeight::new(&fields(), &[10.0, 10.0]);
+
+        let title = weight.pseudo_tf(0, fieldnorm_id(10), 1);
+        let body = weight.pseudo_tf(1, fieldnorm_id(10), 1);
+
+        let combined = weight.score(1.0, title + body);
+        let separate = weight.score(1.0, title) + weight.score(1.0, body);
+
+        assert!(combined > weight.score(1.0, title));
+        assert!(combined < separate);
+        assert!(combined < 1.0 + K1);
+    }
+}
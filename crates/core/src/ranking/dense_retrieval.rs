@@ -0,0 +1,160 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Semantic retrieval of pages with dense embeddings.
+//!
+//! The embeddings of the pages are computed when the index is built and stored in an
+//! approximate nearest neighbour index next to the inverted index of the shard. Queries
+//! with few lexical matches are embedded with the same model, and the most similar pages
+//! are added to the candidates of the recall stage.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{hnsw::Hnsw, Result};
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoredEmbeddings {
+    hnsw: Hnsw,
+    /// Url of each node in the graph.
+    urls: Vec<String>,
+}
+
+pub struct EmbeddingStore {
+    path: PathBuf,
+    embeddings: StoredEmbeddings,
+    dirty: bool,
+}
+
+impl EmbeddingStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let embeddings = if path.exists() {
+            bincode::deserialize_from(BufReader::new(File::open(&path)?))?
+        } else {
+            StoredEmbeddings::default()
+        };
+
+        Ok(Self {
+            path,
+            embeddings,
+            dirty: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.embeddings.urls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.embeddings.urls.is_empty()
+    }
+
+    pub fn insert(&mut self, url: String, embedding: Vec<f32>) {
+        self.embeddings.hnsw.insert(embedding);
+        self.embeddings.urls.push(url);
+        self.dirty = true;
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut wrt = BufWriter::new(File::create(&self.path)?);
+        bincode::serialize_into(&mut wrt, &self.embeddings)?;
+        wrt.flush()?;
+
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: Self) -> Result<()> {
+        for (node, url) in other.embeddings.urls.into_iter().enumerate() {
+            let embedding = other.embeddings.hnsw.vector(node as u32).to_vec();
+            self.insert(url, embedding);
+        }
+
+        if other.path.exists() {
+            std::fs::remove_file(other.path)?;
+        }
+
+        self.commit()
+    }
+
+    /// Urls of the `k` pages that are most similar to the query embedding
+    /// together with their cosine similarity.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(String, f32)> {
+        self.embeddings
+            .hnsw
+            .search(query, k, ef)
+            .into_iter()
+            .map(|(node, similarity)| (self.embeddings.urls[node as usize].clone(), similarity))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_is_persisted() {
+        let path = crate::gen_temp_path().join("embeddings.bin");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut store = EmbeddingStore::open(&path).unwrap();
+        store.insert("https://a.com/".to_string(), vec![1.0, 0.0]);
+        store.insert("https://b.com/".to_string(), vec![0.0, 1.0]);
+        store.commit().unwrap();
+
+        let store = EmbeddingStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+
+        let res = store.search(&[0.8, 0.6], 2, 10);
+        assert_eq!(res[0].0, "https://a.com/");
+        assert_eq!(res[1].0, "https://b.com/");
+    }
+
+    #[test]
+    fn merge() {
+        let dir = crate::gen_temp_path();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut a = EmbeddingStore::open(dir.join("a.bin")).unwrap();
+        a.insert("https://a.com/".to_string(), vec![1.0, 0.0]);
+        a.commit().unwrap();
+
+        let mut b = EmbeddingStore::open(dir.join("b.bin")).unwrap();
+        b.insert("https://b.com/".to_string(), vec![0.0, 1.0]);
+        b.commit().unwrap();
+
+        a.merge(b).unwrap();
+
+        assert!(!dir.join("b.bin").exists());
+
+        let a = EmbeddingStore::open(dir.join("a.bin")).unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.search(&[0.0, 1.0], 1, 10)[0].0, "https://b.com/");
+    }
+}
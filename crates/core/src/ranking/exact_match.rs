@@ -0,0 +1,277 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Exact matches between the query and the title or domain name of a page.
+//!
+//! BM25 rewards pages where the query terms occur, but does not distinguish a page titled
+//! "GitHub" from a page titled "How to use GitHub". Navigational queries like "github"
+//! should strongly prefer the host whose name is exactly the query, so these scorers
+//! only give a score when the query and the field are identical.
+
+use std::collections::BTreeMap;
+
+use tantivy::fieldnorm::FieldNormReader;
+use tantivy::postings::SegmentPostings;
+use tantivy::schema::IndexRecordOption;
+use tantivy::tokenizer::Tokenizer;
+use tantivy::{DocId, DocSet, Postings, Term};
+
+use crate::{schema::TextField, Result};
+
+/// Advance the postings to the document and return whether it contains the term.
+fn seek(postings: &mut SegmentPostings, doc: DocId) -> bool {
+    postings.doc() == doc || (postings.doc() < doc && postings.seek(doc) == doc)
+}
+
+/// Scores 1 if the title consists of exactly the query terms, ignoring case and punctuation.
+pub struct ExactTitleScorer {
+    /// Postings of each distinct query token together with the number of
+    /// times the token occurs in the query.
+    postings: Vec<(SegmentPostings, u32)>,
+    num_tokens: u32,
+    fieldnorm_reader: FieldNormReader,
+}
+
+impl ExactTitleScorer {
+    /// Prepare the scorer for a segment. Returns `None` if no document
+    /// in the segment can have the query as its title.
+    pub fn for_segment(
+        searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+        terms: &[String],
+    ) -> Result<Option<Self>> {
+        let field = TextField::Title;
+        let tv_field = searcher.schema().get_field(field.name()).unwrap();
+        let inverted_index = segment_reader.inverted_index(tv_field)?;
+        let mut tokenizer = field.indexing_tokenizer();
+
+        let mut tokens: BTreeMap<String, u32> = BTreeMap::new();
+        for term in terms {
+            let mut stream = tokenizer.token_stream(term);
+
+            while let Some(token) = stream.next() {
+                *tokens.entry(token.text.clone()).or_default() += 1;
+            }
+        }
+
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+
+        let mut postings = Vec::with_capacity(tokens.len());
+        for (token, count) in &tokens {
+            match inverted_index.read_postings(
+                &Term::from_field_text(tv_field, token),
+                IndexRecordOption::WithFreqs,
+            )? {
+                Some(p) => postings.push((p, *count)),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(Self {
+            postings,
+            num_tokens: tokens.values().sum(),
+            fieldnorm_reader: segment_reader.get_fieldnorms_reader(tv_field)?,
+        }))
+    }
+
+    /// Score the document. The documents must be scored in ascending order of their id.
+    pub fn score(&mut self, doc: DocId) -> f64 {
+        // fieldnorms are exact for short fields, which are the only ones that can match
+        if self.fieldnorm_reader.fieldnorm(doc) != self.num_tokens {
+            return 0.0;
+        }
+
+        for (postings, count) in &mut self.postings {
+            if !seek(postings, doc) || postings.term_freq() != *count {
+                return 0.0;
+            }
+        }
+
+        1.0
+    }
+}
+
+/// The domain name a navigational query refers to, e.g. "github" for both
+/// "GitHub" and "git hub". Returns `None` if the query can not be a domain name.
+pub fn query_domain_name(terms: &[String]) -> Option<String> {
+    let name: String = terms
+        .iter()
+        .flat_map(|term| term.chars())
+        .filter(|c| c.is_alphanumeric() || *c == '-')
+        .flat_map(|c| c.to_lowercase())
+        .collect();
+
+    if name.is_empty() || name.starts_with('-') || name.ends_with('-') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Scores 1 if the domain name of the page (without its suffix) is exactly the query.
+pub struct ExactDomainNameScorer {
+    postings: SegmentPostings,
+}
+
+impl ExactDomainNameScorer {
+    /// Prepare the scorer for a segment. Returns `None` if no document
+    /// in the segment has the query as its domain name.
+    pub fn for_segment(
+        searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+        terms: &[String],
+    ) -> Result<Option<Self>> {
+        let Some(name) = query_domain_name(terms) else {
+            return Ok(None);
+        };
+
+        let field = TextField::DomainNameNoTokenizer;
+        let tv_field = searcher.schema().get_field(field.name()).unwrap();
+        let inverted_index = segment_reader.inverted_index(tv_field)?;
+
+        Ok(inverted_index
+            .read_postings(
+                &Term::from_field_text(tv_field, &name),
+                IndexRecordOption::Basic,
+            )?
+            .map(|postings| Self { postings }))
+    }
+
+    /// Score the document. The documents must be scored in ascending order of their id.
+    pub fn score(&mut self, doc: DocId) -> f64 {
+        if seek(&mut self.postings, doc) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        index::Index,
+        ranking::Signal,
+        searcher::{LocalSearcher, SearchQuery},
+        webpage::Webpage,
+    };
+
+    use super::*;
+
+    fn terms(query: &str) -> Vec<String> {
+        query.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn domain_name_from_query() {
+        assert_eq!(
+            query_domain_name(&terms("GitHub")),
+            Some("github".to_string())
+        );
+        assert_eq!(
+            query_domain_name(&terms("git hub")),
+            Some("github".to_string())
+        );
+        assert_eq!(
+            query_domain_name(&terms("stack-overflow")),
+            Some("stack-overflow".to_string())
+        );
+        assert_eq!(query_domain_name(&terms("-")), None);
+        assert_eq!(query_domain_name(&[]), None);
+    }
+
+    #[test]
+    fn exact_matches_are_scored() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (title, url) in [
+            ("GitHub", "https://github.com/"),
+            ("Why I moved away from github", "https://www.example.com/"),
+            ("github", "https://www.mirror.com/"),
+        ] {
+            index
+                .insert(
+                    Webpage::new(
+                        &format!(
+                            r#"
+                        <html>
+                            <head>
+                                <title>{title}</title>
+                            </head>
+                            <body>
+                                {}
+                            </body>
+                        </html>
+                    "#,
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                )
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().unwrap();
+
+        let searcher = LocalSearcher::from(index);
+        let res = searcher
+            .search(&SearchQuery {
+                query: "github".to_string(),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(res.webpages.len(), 3);
+
+        let signal = |url: &str, signal: Signal| {
+            res.webpages
+                .iter()
+                .find(|w| w.url == url)
+                .unwrap()
+                .ranking_signals
+                .as_ref()
+                .unwrap()
+                .get(&signal)
+                .map(|s| s.value)
+                .unwrap_or(0.0)
+        };
+
+        assert_eq!(signal("https://github.com/", Signal::TitleExactMatch), 1.0);
+        assert_eq!(
+            signal("https://www.mirror.com/", Signal::TitleExactMatch),
+            1.0
+        );
+        assert_eq!(
+            signal("https://www.example.com/", Signal::TitleExactMatch),
+            0.0
+        );
+
+        assert_eq!(
+            signal("https://github.com/", Signal::DomainNameExactMatch),
+            1.0
+        );
+        assert_eq!(
+            signal("https://www.mirror.com/", Signal::DomainNameExactMatch),
+            0.0
+        );
+
+        assert_eq!(res.webpages[0].url, "https://github.com/");
+    }
+}
@@ -0,0 +1,305 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A/B experiments between ranking configurations.
+//!
+//! Each search is assigned to an arm of the experiment by hashing the id of the
+//! client, so a client stays in the same arm between searches. The assigned arm is
+//! returned with the results and stored in the query logs for offline analysis.
+
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use optics::{
+    ast::{RankingCoeff, RankingTarget},
+    Optic,
+};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    config::{ExperimentArmConfig, ExperimentConfig},
+    searcher::SearchQuery,
+};
+
+use super::{
+    models::{lambdamart::LambdaMART, reloadable::Reloadable},
+    Signal,
+};
+
+/// The arm a search was assigned to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExperimentArm {
+    pub experiment: String,
+    pub arm: String,
+}
+
+pub struct Arm {
+    name: String,
+    weight: u64,
+    lambda_model: Option<Arc<Reloadable<LambdaMART>>>,
+    signal_coefficients: Vec<(String, f64)>,
+    cross_encoder: bool,
+}
+
+impl Arm {
+    pub fn from_config(config: &ExperimentArmConfig) -> Result<Self> {
+        let mut signal_coefficients = Vec::with_capacity(config.signal_coefficients.len());
+
+        for (signal, coefficient) in &config.signal_coefficients {
+            Signal::from_str(signal)
+                .map_err(|_| anyhow!("unknown signal in arm {}: {signal}", config.name))?;
+            signal_coefficients.push((signal.clone(), *coefficient));
+        }
+
+        signal_coefficients.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let lambda_model = match &config.lambda_model_path {
+            Some(path) => Some(Arc::new(Reloadable::open(path)?)),
+            None => None,
+        };
+
+        Ok(Self {
+            name: config.name.clone(),
+            weight: config.weight,
+            lambda_model,
+            signal_coefficients,
+            cross_encoder: config.cross_encoder,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The model used by the arm if it differs from the default model.
+    pub fn lambda_model(&self) -> Option<&Arc<Reloadable<LambdaMART>>> {
+        self.lambda_model.as_ref()
+    }
+
+    pub fn use_cross_encoder(&self) -> bool {
+        self.cross_encoder
+    }
+
+    /// Add the signal coefficients of the arm to the optic of the query.
+    /// Signals that already have a coefficient in the optic are left untouched.
+    pub fn apply(&self, query: &mut SearchQuery) {
+        if self.signal_coefficients.is_empty() {
+            return;
+        }
+
+        let optic = query.optic.get_or_insert_with(Optic::default);
+
+        for (signal, coefficient) in &self.signal_coefficients {
+            let has_coefficient = optic.rankings.iter().any(|coeff| match &coeff.target {
+                RankingTarget::Signal(name) => name == signal,
+            });
+
+            if !has_coefficient {
+                optic.rankings.push(RankingCoeff {
+                    target: RankingTarget::Signal(signal.clone()),
+                    value: *coefficient,
+                });
+            }
+        }
+    }
+}
+
+pub struct Experiment {
+    name: String,
+    arms: Vec<Arm>,
+    total_weight: u64,
+}
+
+impl Experiment {
+    pub fn new(name: String, arms: Vec<Arm>) -> Result<Self> {
+        let total_weight = arms.iter().map(|arm| arm.weight).sum();
+
+        if total_weight == 0 {
+            return Err(anyhow!(
+                "experiment {name} has no arms with a positive weight"
+            ));
+        }
+
+        Ok(Self {
+            name,
+            arms,
+            total_weight,
+        })
+    }
+
+    pub fn from_config(config: &ExperimentConfig) -> Result<Self> {
+        let arms = config
+            .arms
+            .iter()
+            .map(Arm::from_config)
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::new(config.name.clone(), arms)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arms(&self) -> &[Arm] {
+        &self.arms
+    }
+
+    fn bucket(&self, id: &str) -> u64 {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        // include the name so clients are shuffled differently between experiments
+        ctx.update(self.name.as_bytes());
+        ctx.update(&[0]);
+        ctx.update(id.as_bytes());
+
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&ctx.finish().as_ref()[..8]);
+
+        u64::from_le_bytes(hash) % self.total_weight
+    }
+
+    /// The arm that the client with the id is assigned to.
+    pub fn assign(&self, id: &str) -> &Arm {
+        let mut bucket = self.bucket(id);
+
+        for arm in &self.arms {
+            if bucket < arm.weight {
+                return arm;
+            }
+
+            bucket -= arm.weight;
+        }
+
+        unreachable!("bucket is always smaller than the total weight")
+    }
+
+    pub fn record(&self, arm: &Arm) -> ExperimentArm {
+        ExperimentArm {
+            experiment: self.name.clone(),
+            arm: arm.name.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn arm(name: &str, weight: u64) -> Arm {
+        Arm {
+            name: name.to_string(),
+            weight,
+            lambda_model: None,
+            signal_coefficients: Vec::new(),
+            cross_encoder: true,
+        }
+    }
+
+    #[test]
+    fn assignment_is_deterministic() {
+        let experiment = Experiment::new(
+            "test".to_string(),
+            vec![arm("control", 1), arm("treatment", 1)],
+        )
+        .unwrap();
+
+        for i in 0..100 {
+            let client_id = format!("client-{i}");
+            assert_eq!(
+                experiment.assign(&client_id).name(),
+                experiment.assign(&client_id).name()
+            );
+        }
+    }
+
+    #[test]
+    fn traffic_follows_weights() {
+        let experiment = Experiment::new(
+            "test".to_string(),
+            vec![arm("control", 3), arm("treatment", 1), arm("disabled", 0)],
+        )
+        .unwrap();
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+
+        for i in 0..10_000 {
+            let arm = experiment.assign(&format!("client-{i}"));
+            *counts.entry(arm.name().to_string()).or_default() += 1;
+        }
+
+        assert!(!counts.contains_key("disabled"));
+        assert!((7_000..8_000).contains(&counts["control"]));
+        assert!((2_000..3_000).contains(&counts["treatment"]));
+    }
+
+    #[test]
+    fn no_weights() {
+        assert!(Experiment::new("test".to_string(), vec![arm("control", 0)]).is_err());
+        assert!(Experiment::new("test".to_string(), vec![]).is_err());
+    }
+
+    #[test]
+    fn query_optic_takes_precedence() {
+        let mut arm = arm("treatment", 1);
+        arm.signal_coefficients = vec![
+            ("bm25_title".to_string(), 2.0),
+            ("host_centrality".to_string(), 3.0),
+        ];
+
+        let mut query = SearchQuery {
+            optic: Some(Optic::parse("Ranking(Signal(\"bm25_title\"), 10);").unwrap()),
+            ..Default::default()
+        };
+
+        arm.apply(&mut query);
+
+        let coefficients: Vec<_> = query
+            .optic
+            .unwrap()
+            .rankings
+            .into_iter()
+            .map(|coeff| match coeff.target {
+                RankingTarget::Signal(name) => (name, coeff.value),
+            })
+            .collect();
+
+        assert_eq!(
+            coefficients,
+            vec![
+                ("bm25_title".to_string(), 10.0),
+                ("host_centrality".to_string(), 3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_signal() {
+        let config = ExperimentArmConfig {
+            name: "treatment".to_string(),
+            weight: 1,
+            lambda_model_path: None,
+            signal_coefficients: [("not_a_signal".to_string(), 1.0)].into_iter().collect(),
+            cross_encoder: true,
+        };
+
+        assert!(Arm::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Aggregates of the indexed pages of each host.
+//!
+//! The aggregates are collected while the pages are inserted into the index and stored
+//! next to it. At ranking time they give a page signals about its host, so a new page
+//! on a reputable host is not ranked like a page on an unknown host.
+//!
+//! The store starts with a magic number and the version of the layout of the aggregates.
+//! Stores written before the layout was versioned have no header and are read as
+//! version 0.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    webgraph::NodeID,
+    webpage::{protocol::TlsStatus, Webpage},
+    Result,
+};
+
+/// Read as a little-endian length, this is far more hosts than a store from
+/// before the header was added could contain, so the two can't be confused.
+const MAGIC: [u8; 8] = *b"HOSTSTAT";
+const VERSION: u32 = 1;
+
+/// Layout of the aggregates before the protocol of the pages was recorded.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct HostAggregateV0 {
+    num_pages: u64,
+    page_centrality_sum: f64,
+    first_seen: i64,
+}
+
+impl From<HostAggregateV0> for HostAggregate {
+    fn from(aggregate: HostAggregateV0) -> Self {
+        Self {
+            num_pages: aggregate.num_pages,
+            page_centrality_sum: aggregate.page_centrality_sum,
+            first_seen: aggregate.first_seen,
+            secure_pages: 0,
+            insecure_pages: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HostAggregate {
+    pub num_pages: u64,
+    pub page_centrality_sum: f64,
+    /// Unix timestamp of the oldest page of the host.
+    pub first_seen: i64,
+    /// Pages served over https with a valid certificate.
+    pub secure_pages: u64,
+    /// Pages served over plain http or with an invalid certificate.
+    pub insecure_pages: u64,
+}
+
+impl Default for HostAggregate {
+    fn default() -> Self {
+        Self {
+            num_pages: 0,
+            page_centrality_sum: 0.0,
+            first_seen: i64::MAX,
+            secure_pages: 0,
+            insecure_pages: 0,
+        }
+    }
+}
+
+impl HostAggregate {
+    pub fn mean_page_centrality(&self) -> f64 {
+        if self.num_pages == 0 {
+            0.0
+        } else {
+            self.page_centrality_sum / self.num_pages as f64
+        }
+    }
+
+    /// Fraction of the pages of the host that were served securely. `None` if the
+    /// protocol of none of the pages is known, e.g. if they were crawled before
+    /// it was recorded.
+    pub fn secure_fraction(&self) -> Option<f64> {
+        let known = self.secure_pages + self.insecure_pages;
+
+        if known == 0 {
+            None
+        } else {
+            Some(self.secure_pages as f64 / known as f64)
+        }
+    }
+
+    fn add(&mut self, page_centrality: f64, timestamp: i64, tls: TlsStatus) {
+        self.num_pages += 1;
+        self.page_centrality_sum += page_centrality;
+        self.first_seen = self.first_seen.min(timestamp);
+
+        if tls.is_secure() {
+            self.secure_pages += 1;
+        } else if tls.is_insecure() {
+            self.insecure_pages += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.num_pages += other.num_pages;
+        self.page_centrality_sum += other.page_centrality_sum;
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.secure_pages += other.secure_pages;
+        self.insecure_pages += other.insecure_pages;
+    }
+}
+
+/// The committed aggregates that are used for ranking.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostAggregates(HashMap<NodeID, HostAggregate>);
+
+impl HostAggregates {
+    pub fn get(&self, host: &NodeID) -> Option<&HostAggregate> {
+        self.0.get(host)
+    }
+
+    fn merge(&mut self, other: &HashMap<NodeID, HostAggregate>) {
+        for (host, aggregate) in other {
+            self.0.entry(*host).or_default().merge(aggregate);
+        }
+    }
+
+    fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0; MAGIC.len()];
+        reader.read_exact(&mut header)?;
+
+        if header != MAGIC {
+            let legacy: HashMap<NodeID, HostAggregateV0> =
+                bincode::deserialize_from((&header[..]).chain(reader))?;
+
+            return Ok(Self(
+                legacy
+                    .into_iter()
+                    .map(|(host, aggregate)| (host, aggregate.into()))
+                    .collect(),
+            ));
+        }
+
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+
+        match u32::from_le_bytes(version) {
+            VERSION => Ok(bincode::deserialize_from(reader)?),
+            version => Err(anyhow!("unsupported host stats version {version}")),
+        }
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+pub struct HostStats {
+    path: PathBuf,
+    committed: Arc<HostAggregates>,
+    pending: HashMap<NodeID, HostAggregate>,
+}
+
+impl HostStats {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let committed = if path.exists() {
+            HostAggregates::read(BufReader::new(File::open(&path)?))?
+        } else {
+            HostAggregates::default()
+        };
+
+        Ok(Self {
+            path,
+            committed: Arc::new(committed),
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn insert(&mut self, webpage: &Webpage) {
+        let Some(host) = webpage.node_id else {
+            return;
+        };
+
+        let timestamps = webpage.html.timestamps();
+        let timestamp = timestamps
+            .published
+            .or(timestamps.modified)
+            .map(|date| date.timestamp())
+            .unwrap_or_else(|| webpage.inserted_at.timestamp());
+
+        self.pending.entry(host).or_default().add(
+            webpage.page_centrality,
+            timestamp,
+            webpage.protocol.tls,
+        );
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            Arc::make_mut(&mut self.committed).merge(&pending);
+        }
+
+        self.committed
+            .write(BufWriter::new(File::create(&self.path)?))
+    }
+
+    pub fn merge(&mut self, other: Self) -> Result<()> {
+        let committed = Arc::make_mut(&mut self.committed);
+        committed.merge(&other.committed.0);
+        committed.merge(&other.pending);
+
+        if other.path.exists() {
+            std::fs::remove_file(other.path)?;
+        }
+
+        self.commit()
+    }
+
+    /// The committed aggregates. Pages that have been inserted since
+    /// the last commit are not included.
+    pub fn aggregates(&self) -> Arc<HostAggregates> {
+        Arc::clone(&self.committed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webpage(url: &str, host: u64, page_centrality: f64) -> Webpage {
+        let mut webpage = Webpage::new("<html><title>test</title></html>", url).unwrap();
+        webpage.node_id = Some(NodeID::from(host));
+        webpage.page_centrality = page_centrality;
+
+        webpage
+    }
+
+    #[test]
+    fn aggregates_are_committed() {
+        let path = crate::gen_temp_path().join("host_stats.bin");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut stats = HostStats::open(&path).unwrap();
+
+        stats.insert(&webpage("https://a.com/1", 1, 0.2));
+        stats.insert(&webpage("https://a.com/2", 1, 0.4));
+        stats.insert(&webpage("https://b.com/", 2, 0.1));
+
+        assert!(stats.aggregates().get(&NodeID::from(1u64)).is_none());
+
+        stats.commit().unwrap();
+
+        let a = *stats.aggregates().get(&NodeID::from(1u64)).unwrap();
+        assert_eq!(a.num_pages, 2);
+        assert!((a.mean_page_centrality() - 0.3).abs() < 1e-9);
+
+        let stats = HostStats::open(&path).unwrap();
+        assert_eq!(stats.aggregates().get(&NodeID::from(1u64)), Some(&a));
+        assert_eq!(
+            stats
+                .aggregates()
+                .get(&NodeID::from(2u64))
+                .unwrap()
+                .num_pages,
+            1
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let dir = crate::gen_temp_path();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut a = HostStats::open(dir.join("a.bin")).unwrap();
+        a.insert(&webpage("https://a.com/1", 1, 0.2));
+        a.commit().unwrap();
+
+        let mut b = HostStats::open(dir.join("b.bin")).unwrap();
+        b.insert(&webpage("https://a.com/2", 1, 0.4));
+        b.commit().unwrap();
+
+        a.merge(b).unwrap();
+
+        assert!(!dir.join("b.bin").exists());
+        assert_eq!(
+            a.aggregates().get(&NodeID::from(1u64)).unwrap().num_pages,
+            2
+        );
+    }
+
+    #[test]
+    fn secure_fraction() {
+        let path = crate::gen_temp_path().join("host_stats.bin");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut stats = HostStats::open(&path).unwrap();
+
+        for (i, tls) in [
+            TlsStatus::Valid,
+            TlsStatus::Valid,
+            TlsStatus::Valid,
+            TlsStatus::Invalid,
+            TlsStatus::Unknown,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut page = webpage(&format!("https://a.com/{i}"), 1, 0.1);
+            page.protocol.tls = tls;
+            stats.insert(&page);
+        }
+
+        stats.insert(&webpage("http://b.com/", 2, 0.1));
+        stats.commit().unwrap();
+
+        let a = *stats.aggregates().get(&NodeID::from(1u64)).unwrap();
+        assert_eq!(a.num_pages, 5);
+        assert_eq!(a.secure_fraction(), Some(0.75));
+
+        let b = *stats.aggregates().get(&NodeID::from(2u64)).unwrap();
+        assert_eq!(b.secure_fraction(), None);
+    }
+
+    #[test]
+    fn reads_stores_without_version() {
+        let path = crate::gen_temp_path().join("host_stats.bin");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let legacy: HashMap<NodeID, HostAggregateV0> = [(
+            NodeID::from(1u64),
+            HostAggregateV0 {
+                num_pages: 3,
+                page_centrality_sum: 0.6,
+                first_seen: 42,
+            },
+        )]
+        .into_iter()
+        .collect();
+        std::fs::write(&path, bincode::serialize(&legacy).unwrap()).unwrap();
+
+        let mut stats = HostStats::open(&path).unwrap();
+        let a = *stats.aggregates().get(&NodeID::from(1u64)).unwrap();
+        assert_eq!(a.num_pages, 3);
+        assert_eq!(a.first_seen, 42);
+        assert_eq!(a.secure_fraction(), None);
+
+        // the store is written in the current version on the next commit
+        stats.commit().unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(&MAGIC));
+        assert_eq!(
+            HostStats::open(&path)
+                .unwrap()
+                .aggregates()
+                .get(&NodeID::from(1u64)),
+            Some(&a)
+        );
+    }
+}
@@ -18,7 +18,8 @@ use std::{
     fs::File,
     io::{BufReader, BufWriter, Read},
     path::Path,
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use dashmap::DashMap;
@@ -29,6 +30,7 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    ttl_cache::TTLCache,
     webgraph::{NodeID, Webgraph},
     Result,
 };
@@ -38,10 +40,70 @@ use super::bitvec_similarity;
 const PRECALCULATE_TOP_N: usize = 1_000;
 const TOP_CANDIDATES_PER_PRECALCULATION: usize = 1_000;
 
+const SEED_CACHE_TTL: Duration = Duration::from_secs(30 * 60);
+const SEED_CACHE_SIZE: usize = 1_000;
+
+/// The inbound vectors of the liked hosts combined into a single vector.
+///
+/// Each liked host contributes its normalized inbound vector, so a host is scored by how
+/// similar its inbound links are to those of the liked hosts as a whole. Hosts that are
+/// linked from the same pages as several of the liked hosts get the highest scores.
+struct PersonalizedSeed {
+    weights: HashMap<u64, f64>,
+    norm: f64,
+}
+
+impl PersonalizedSeed {
+    fn new<'a>(liked: impl Iterator<Item = &'a bitvec_similarity::BitVec>) -> Self {
+        let mut weights: HashMap<u64, f64> = HashMap::default();
+
+        for inbound in liked.filter(|inbound| !inbound.is_empty()) {
+            let weight = 1.0 / (inbound.len() as f64).sqrt();
+
+            for rank in inbound.ranks() {
+                *weights.entry(*rank).or_default() += weight;
+            }
+        }
+
+        let norm = weights.values().map(|w| w * w).sum::<f64>().sqrt();
+
+        Self { weights, norm }
+    }
+
+    /// Cosine similarity between the seed and the inbound vector.
+    fn sim(&self, inbound: &bitvec_similarity::BitVec) -> f64 {
+        if self.norm == 0.0 || inbound.is_empty() {
+            return 0.0;
+        }
+
+        let dot: f64 = inbound
+            .ranks()
+            .iter()
+            .filter_map(|rank| self.weights.get(rank))
+            .sum();
+
+        dot / (self.norm * (inbound.len() as f64).sqrt())
+    }
+}
+
+/// Seeds of recently seen sets of liked hosts. Users usually send many
+/// queries with the same liked hosts, so the seed is rarely rebuilt.
+struct SeedCache(Mutex<TTLCache<Vec<NodeID>, Arc<PersonalizedSeed>>>);
+
+impl Default for SeedCache {
+    fn default() -> Self {
+        Self(Mutex::new(TTLCache::with_ttl_and_max_size(
+            SEED_CACHE_TTL,
+            Some(SEED_CACHE_SIZE),
+        )))
+    }
+}
+
 #[derive(Clone)]
 pub struct Scorer {
     liked: Vec<NodeScorer>,
     disliked: Vec<NodeScorer>,
+    seed: Option<Arc<PersonalizedSeed>>,
     vectors: Arc<VecMap>,
     cache: HashMap<NodeID, f64>,
     normalized: bool,
@@ -103,12 +165,22 @@ impl Scorer {
     fn calculate_score(&mut self, node: &NodeID) -> f64 {
         let s = match self.vectors.get(node) {
             Some(vec) => {
+                let liked = match &self.seed {
+                    Some(seed) => {
+                        let sim = self
+                            .liked
+                            .iter()
+                            .find(|liked| liked.node == *node)
+                            .map(|liked| liked.self_score)
+                            .unwrap_or_else(|| seed.sim(vec));
+
+                        sim * self.liked.len() as f64
+                    }
+                    None => 0.0,
+                };
+
                 (self.disliked.len() as f64)
-                    + (self
-                        .liked
-                        .iter()
-                        .map(|liked| liked.sim(node, vec))
-                        .sum::<f64>()
+                    + (liked
                         - self
                             .disliked
                             .iter()
@@ -217,6 +289,8 @@ impl VecMap {
 pub struct InboundSimilarity {
     vectors: Arc<VecMap>,
     precalculated: Arc<PreCalculatedSimilarities>,
+    #[serde(skip)]
+    seeds: SeedCache,
 }
 
 impl InboundSimilarity {
@@ -273,9 +347,29 @@ impl InboundSimilarity {
         Self {
             vectors: Arc::new(vectors),
             precalculated: Arc::new(precalculated),
+            seeds: SeedCache::default(),
         }
     }
 
+    fn seed(&self, liked: &[NodeScorer]) -> Arc<PersonalizedSeed> {
+        let mut key: Vec<_> = liked.iter().map(|scorer| scorer.node).collect();
+        key.sort();
+        key.dedup();
+
+        let mut seeds = self.seeds.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(seed) = seeds.get(&key) {
+            return Arc::clone(seed);
+        }
+
+        let seed = Arc::new(PersonalizedSeed::new(
+            liked.iter().map(|scorer| &scorer.inbound),
+        ));
+        seeds.insert(key, Arc::clone(&seed));
+
+        seed
+    }
+
     pub fn scorer(
         &self,
         liked_hosts: &[NodeID],
@@ -294,9 +388,16 @@ impl InboundSimilarity {
             .map(|(node, inbound)| NodeScorer::new(*node, inbound, self.precalculated.clone()))
             .collect();
 
+        let seed = if liked.is_empty() {
+            None
+        } else {
+            Some(self.seed(&liked))
+        };
+
         Scorer {
             liked,
             disliked,
+            seed,
             vectors: self.vectors.clone(),
             cache: HashMap::default(),
             normalized,
@@ -380,6 +481,50 @@ mod tests {
         assert!(scorer.score(&e) > scorer.score(&d));
     }
 
+    #[test]
+    fn seed_combines_liked_hosts() {
+        let b = bitvec_similarity::BitVec::new(vec![1, 2]);
+        let c = bitvec_similarity::BitVec::new(vec![3, 4]);
+
+        let x = bitvec_similarity::BitVec::new(vec![1, 2, 3, 4]);
+        let y = bitvec_similarity::BitVec::new(vec![1, 2, 5]);
+
+        let single = PersonalizedSeed::new([&b].into_iter());
+        assert!((single.sim(&y) - b.sim(&y)).abs() < 1e-9);
+
+        let seed = PersonalizedSeed::new([&b, &c].into_iter());
+        assert!((seed.sim(&x) - 1.0).abs() < 1e-9);
+        assert!(seed.sim(&x) > seed.sim(&y));
+        assert_eq!(seed.sim(&bitvec_similarity::BitVec::new(vec![6])), 0.0);
+    }
+
+    #[test]
+    fn seed_is_cached() {
+        let mut wrt = WebgraphWriter::new(
+            gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        wrt.insert(Node::from("a.com"), Node::from("b.com"), String::new());
+        wrt.insert(Node::from("a.com"), Node::from("c.com"), String::new());
+
+        let graph = wrt.finalize();
+        let inbound = InboundSimilarity::build(&graph);
+
+        let b = Node::from("b.com").id();
+        let c = Node::from("c.com").id();
+
+        let first = inbound.scorer(&[b, c], &[], false);
+        let second = inbound.scorer(&[c, b], &[], false);
+
+        assert!(Arc::ptr_eq(
+            first.seed.as_ref().unwrap(),
+            second.seed.as_ref().unwrap()
+        ));
+        assert!(inbound.scorer(&[], &[b], false).seed.is_none());
+    }
+
     #[test]
     fn it_ranks_search_results() {
         let mut wrt = WebgraphWriter::new(
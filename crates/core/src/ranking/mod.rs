@@ -16,11 +16,17 @@
 
 pub mod bitvec_similarity;
 pub mod bm25;
+pub mod bm25f;
+pub mod dense_retrieval;
+pub mod exact_match;
+pub mod experiment;
+pub mod host_stats;
 pub mod inbound_similarity;
 pub mod initial;
 pub mod models;
 pub mod optics;
 pub mod pipeline;
+pub mod proximity;
 pub mod query_centrality;
 pub mod signal;
 
@@ -128,7 +134,9 @@ mod tests {
 
     use optics::Optic;
 
+    use super::Signal;
     use crate::{
+        config::FreshnessConfig,
         index::Index,
         searcher::{LocalSearcher, SearchQuery},
         webpage::{Html, Webpage},
@@ -338,6 +346,346 @@ mod tests {
         assert_eq!(result.webpages[0].url, "https://www.new.com/");
     }
 
+    #[test]
+    fn freshness_signal() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, updated) in [
+            ("https://www.old.com", "2003-06-22T19:37:34+00:00"),
+            ("https://www.new.com", "2023-06-22T19:37:34+00:00"),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>Title</title>
+                            <meta property="article:modified_time" content="{updated}" />
+                        </head>
+                        <body>
+                            {CONTENT} {}
+                        </body>
+                    </html>
+                "#,
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    host_centrality: 1.0,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+        let result = searcher
+            .search(&SearchQuery {
+                query: "title".to_string(),
+                optic: Some(Optic::parse("Ranking(Signal(\"freshness\"), 1000);").unwrap()),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert_eq!(result.webpages[0].url, "https://www.new.com/");
+
+        let freshness = |i: usize| {
+            result.webpages[i].ranking_signals.as_ref().unwrap()[&Signal::Freshness].value
+        };
+
+        assert!(freshness(0) > freshness(1));
+        assert!(freshness(0) < 1.0);
+        assert!(freshness(1) > 0.0);
+    }
+
+    #[test]
+    fn freshness_decay() {
+        let config = FreshnessConfig {
+            half_life_days: 10.0,
+            steepness: 2.0,
+            news_half_life_days: 2.0,
+        };
+
+        assert_eq!(config.score(0.0), 1.0);
+        assert_eq!(config.score(10.0), 0.5);
+        assert!(config.score(5.0) > 0.75);
+        assert!(config.score(100.0) < 0.01);
+
+        assert_eq!(config.news_score(0.0), 1.0);
+        assert_eq!(config.news_score(2.0), 0.5);
+        assert_eq!(config.news_score(4.0), 0.25);
+    }
+
+    #[test]
+    fn news_recency_only_for_news_queries() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        let now = chrono::Utc::now();
+        for (url, updated) in [
+            ("https://www.old.com", now - chrono::Duration::days(30)),
+            ("https://www.new.com", now - chrono::Duration::hours(6)),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>Election title</title>
+                            <meta property="article:modified_time" content="{}" />
+                        </head>
+                        <body>
+                            {CONTENT} {}
+                        </body>
+                    </html>
+                "#,
+                            updated.to_rfc3339(),
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    host_centrality: 1.0,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "title".to_string(),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert!(result.webpages.iter().all(|webpage| !webpage
+            .ranking_signals
+            .as_ref()
+            .unwrap()
+            .contains_key(&Signal::NewsRecency)));
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "election".to_string(),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert_eq!(result.webpages[0].url, "https://www.new.com/");
+
+        let recency = |i: usize| {
+            result.webpages[i].ranking_signals.as_ref().unwrap()[&Signal::NewsRecency].value
+        };
+
+        assert!(recency(0) > 0.5);
+        assert!(recency(1) < 0.01);
+    }
+
+    #[test]
+    fn bm25f_combines_fields() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, title, body) in [
+            (
+                "https://www.both.com",
+                "Lighthouse",
+                format!("{CONTENT} lighthouse"),
+            ),
+            ("https://www.title.com", "Lighthouse", CONTENT.to_string()),
+            (
+                "https://www.body.com",
+                "Example",
+                format!("{CONTENT} lighthouse"),
+            ),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>{title}</title>
+                        </head>
+                        <body>
+                            {body}
+                        </body>
+                    </html>
+                "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+        let result = searcher
+            .search(&SearchQuery {
+                query: "lighthouse".to_string(),
+                optic: Some(Optic::parse("Ranking(Signal(\"bm25f\"), 1000);").unwrap()),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert_eq!(result.webpages.len(), 3);
+
+        let bm25f = |url: &str| {
+            result
+                .webpages
+                .iter()
+                .find(|webpage| webpage.url == url)
+                .unwrap()
+                .ranking_signals
+                .as_ref()
+                .unwrap()[&Signal::Bm25F]
+                .value
+        };
+
+        // a match in the title counts more than a match in the body,
+        // and matches in multiple fields add up
+        assert_eq!(result.webpages[0].url, "https://www.both.com/");
+        assert!(bm25f("https://www.both.com/") > bm25f("https://www.title.com/"));
+        assert!(bm25f("https://www.title.com/") > bm25f("https://www.body.com/"));
+        assert!(bm25f("https://www.body.com/") > 0.0);
+    }
+
+    #[test]
+    fn term_proximity() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, body) in [
+            (
+                "https://www.close.com",
+                format!("{CONTENT} lighthouse keeper"),
+            ),
+            (
+                "https://www.far.com",
+                format!("lighthouse {CONTENT} keeper"),
+            ),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>Example</title>
+                        </head>
+                        <body>
+                            {body}
+                        </body>
+                    </html>
+                "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+        let result = searcher
+            .search(&SearchQuery {
+                query: "lighthouse keeper".to_string(),
+                optic: Some(Optic::parse("Ranking(Signal(\"term_proximity\"), 1000);").unwrap()),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        let proximity = |url: &str| {
+            result
+                .webpages
+                .iter()
+                .find(|webpage| webpage.url == url)
+                .and_then(|webpage| webpage.ranking_signals.as_ref())
+                .and_then(|signals| signals.get(&Signal::TermProximity))
+                .map(|score| score.value)
+        };
+
+        assert_eq!(result.webpages[0].url, "https://www.close.com/");
+        assert_eq!(proximity("https://www.close.com/"), Some(1.0));
+        assert!(proximity("https://www.far.com/").unwrap() > 0.0);
+        assert!(proximity("https://www.far.com/").unwrap() < 0.1);
+    }
+
+    #[test]
+    fn derank_low_quality_pages() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        let stuffed = "cheap title watches ".repeat(50);
+        let links: String = (0..50)
+            .map(|i| format!(r#"<a href="https://site{i}.com" rel="sponsored">title deals</a> "#))
+            .collect();
+
+        for (url, body) in [
+            ("https://www.spam.com", format!("{stuffed} {links}")),
+            ("https://www.good.com", format!("{CONTENT} title")),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>Title</title>
+                        </head>
+                        <body>
+                            {body}
+                        </body>
+                    </html>
+                "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    host_centrality: 1.0,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+        let result = searcher
+            .search(&SearchQuery {
+                query: "title".to_string(),
+                optic: Some(Optic::parse("Ranking(Signal(\"page_quality\"), 1000);").unwrap()),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert_eq!(result.webpages.len(), 2);
+        assert_eq!(result.webpages[0].url, "https://www.good.com/");
+
+        let quality = |i: usize| {
+            result.webpages[i].ranking_signals.as_ref().unwrap()[&Signal::PageQuality].value
+        };
+
+        assert!(quality(0) > quality(1));
+    }
+
     #[test]
     fn derank_trackers() {
         let mut index = Index::temporary().expect("Unable to open index");
@@ -468,6 +816,58 @@ mod tests {
         assert_eq!(result.webpages[0].url, "https://www.first.com/");
     }
 
+    #[test]
+    fn heading_matches() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, compost) in [
+            ("https://www.paragraph.com", "<p>Making compost</p>"),
+            ("https://www.heading.com", "<h2>Making compost</h2>"),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                    <html>
+                        <head>
+                            <title>Gardening guide</title>
+                        </head>
+                        <body>
+                            {compost}
+                            <p>{CONTENT}</p>
+                        </body>
+                    </html>
+                "#
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+        let result = searcher
+            .search(&SearchQuery {
+                query: "compost".to_string(),
+                return_ranking_signals: true,
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert_eq!(result.webpages.len(), 2);
+        assert_eq!(result.webpages[0].url, "https://www.heading.com/");
+        assert!(result.webpages[0]
+            .ranking_signals
+            .as_ref()
+            .unwrap()
+            .contains_key(&Signal::Bm25H2));
+    }
+
     #[test]
     fn custom_signal_aggregation() {
         let mut index = Index::temporary().expect("Unable to open index");
@@ -0,0 +1,199 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Calibration of ranking scores to a relevance in `[0, 1]`.
+//!
+//! The raw scores of the ranking model are only meaningful relative to the other
+//! results of the same query, so they cannot be compared across queries or thresholded.
+//! The calibration maps a score to the probability that the result is relevant. The
+//! parameters are fitted offline on judged results and stored next to the model.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("isotonic calibration needs at least one point")]
+    NoPoints,
+
+    #[error("isotonic calibration has {scores} scores but {probabilities} probabilities")]
+    LengthMismatch { scores: usize, probabilities: usize },
+
+    #[error("isotonic calibration scores must be sorted in ascending order")]
+    UnsortedScores,
+
+    #[error("isotonic calibration probabilities must be non-decreasing and within [0, 1]")]
+    InvalidProbabilities,
+
+    #[error("calibration parameters must be finite")]
+    NotFinite,
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Calibration {
+    /// Platt scaling: `1 / (1 + exp(-(a * score + b)))`.
+    Platt { a: f64, b: f64 },
+    /// Isotonic regression. The relevance is interpolated linearly between the points,
+    /// and scores outside the points get the relevance of the nearest point.
+    Isotonic {
+        scores: Vec<f64>,
+        probabilities: Vec<f64>,
+    },
+}
+
+impl Calibration {
+    pub fn parse(s: &str) -> Result<Self> {
+        let calibration: Self = serde_json::from_str(s)?;
+        calibration.validate()?;
+
+        Ok(calibration)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            Calibration::Platt { a, b } => {
+                if !a.is_finite() || !b.is_finite() {
+                    return Err(Error::NotFinite);
+                }
+            }
+            Calibration::Isotonic {
+                scores,
+                probabilities,
+            } => {
+                if scores.is_empty() {
+                    return Err(Error::NoPoints);
+                }
+
+                if scores.len() != probabilities.len() {
+                    return Err(Error::LengthMismatch {
+                        scores: scores.len(),
+                        probabilities: probabilities.len(),
+                    });
+                }
+
+                if scores.iter().any(|s| !s.is_finite()) {
+                    return Err(Error::NotFinite);
+                }
+
+                if scores.windows(2).any(|w| w[0] > w[1]) {
+                    return Err(Error::UnsortedScores);
+                }
+
+                if probabilities.iter().any(|p| !(0.0..=1.0).contains(p))
+                    || probabilities.windows(2).any(|w| w[0] > w[1])
+                {
+                    return Err(Error::InvalidProbabilities);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The calibrated relevance of the score in `[0, 1]`.
+    pub fn calibrate(&self, score: f64) -> f64 {
+        match self {
+            Calibration::Platt { a, b } => 1.0 / (1.0 + (-(a * score + b)).exp()),
+            Calibration::Isotonic {
+                scores,
+                probabilities,
+            } => {
+                let idx = scores.partition_point(|s| *s < score);
+
+                if idx == 0 {
+                    return probabilities[0];
+                }
+
+                if idx == scores.len() {
+                    return probabilities[scores.len() - 1];
+                }
+
+                let (lo, hi) = (scores[idx - 1], scores[idx]);
+                if hi == lo {
+                    return probabilities[idx];
+                }
+
+                let t = (score - lo) / (hi - lo);
+                probabilities[idx - 1] + t * (probabilities[idx] - probabilities[idx - 1])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platt() {
+        let calibration = Calibration::parse(r#"{"type": "platt", "a": 2.0, "b": -1.0}"#).unwrap();
+
+        assert_eq!(calibration.calibrate(0.5), 0.5);
+        assert!(calibration.calibrate(10.0) > 0.99);
+        assert!(calibration.calibrate(-10.0) < 0.01);
+        assert!(calibration.calibrate(1.0) > calibration.calibrate(0.9));
+    }
+
+    #[test]
+    fn isotonic() {
+        let calibration = Calibration::parse(
+            r#"{"type": "isotonic", "scores": [0.0, 1.0, 3.0], "probabilities": [0.1, 0.5, 0.9]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(calibration.calibrate(-5.0), 0.1);
+        assert_eq!(calibration.calibrate(0.0), 0.1);
+        assert!((calibration.calibrate(0.5) - 0.3).abs() < 1e-9);
+        assert_eq!(calibration.calibrate(1.0), 0.5);
+        assert!((calibration.calibrate(2.0) - 0.7).abs() < 1e-9);
+        assert_eq!(calibration.calibrate(100.0), 0.9);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(
+            Calibration::parse(r#"{"type": "isotonic", "scores": [], "probabilities": []}"#)
+                .is_err()
+        );
+        assert!(Calibration::parse(
+            r#"{"type": "isotonic", "scores": [1.0, 0.0], "probabilities": [0.1, 0.5]}"#
+        )
+        .is_err());
+        assert!(Calibration::parse(
+            r#"{"type": "isotonic", "scores": [0.0, 1.0], "probabilities": [0.5, 0.1]}"#
+        )
+        .is_err());
+        assert!(Calibration::parse(
+            r#"{"type": "isotonic", "scores": [0.0, 1.0], "probabilities": [0.5]}"#
+        )
+        .is_err());
+        assert!(Calibration::parse(r#"{"type": "unknown"}"#).is_err());
+    }
+}
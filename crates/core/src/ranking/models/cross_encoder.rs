@@ -27,6 +27,8 @@ use tokenizers::TruncationParams;
 use crate::models::bert;
 use crate::models::bert::BertModel;
 
+use super::reloadable::ReloadableModel;
+
 const TRUNCATE_INPUT: usize = 128;
 
 pub struct CrossEncoderModel {
@@ -88,6 +90,30 @@ impl CrossEncoderModel {
     }
 }
 
+impl ReloadableModel for CrossEncoderModel {
+    fn open_model(path: &Path) -> Result<Self> {
+        Self::open(path)
+    }
+
+    fn dry_run(&self) -> Result<()> {
+        let bodies = [
+            "the quick brown fox jumps over the lazy dog".to_string(),
+            String::new(),
+        ];
+
+        let scores = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.run("quick fox", &bodies)
+        }))
+        .map_err(|_| anyhow!("model panicked on fixture"))?;
+
+        if scores.len() != bodies.len() || scores.iter().any(|score| !score.is_finite()) {
+            return Err(anyhow!("model predicted {scores:?} on fixture"));
+        }
+
+        Ok(())
+    }
+}
+
 impl CrossEncoder for CrossEncoderModel {
     fn run(&self, query: &str, bodies: &[String]) -> Vec<f64> {
         if bodies.is_empty() {
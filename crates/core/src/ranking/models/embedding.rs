@@ -0,0 +1,178 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Dense embeddings of queries and documents for semantic retrieval.
+
+use anyhow::anyhow;
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use std::path::Path;
+use tokenizers::PaddingParams;
+use tokenizers::TruncationParams;
+
+use crate::models::bert;
+use crate::models::bert::BertModel;
+use crate::webpage::Webpage;
+
+const TRUNCATE_INPUT: usize = 128;
+
+pub trait Embedder: Send + Sync {
+    /// Normalized embedding of each of the texts.
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+}
+
+/// The text of the page that is embedded.
+pub fn document_text(webpage: &Webpage) -> String {
+    let title = webpage.html.title().unwrap_or_default();
+    let body = webpage
+        .html
+        .description()
+        .or_else(|| webpage.html.clean_text().cloned())
+        .unwrap_or_default();
+
+    let body: Vec<_> = body.split_whitespace().take(TRUNCATE_INPUT).collect();
+
+    format!("{title}\n{}", body.join(" "))
+}
+
+pub fn normalize(mut embedding: Vec<f32>) -> Vec<f32> {
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+
+    embedding
+}
+
+/// Bi-encoder that embeds a text as the mean of its token embeddings.
+pub struct EmbeddingModel {
+    tokenizer: tokenizers::Tokenizer,
+    encoder: BertModel,
+    device: Device,
+}
+
+impl EmbeddingModel {
+    pub fn open<P: AsRef<Path>>(folder: P) -> Result<Self> {
+        let device = Device::Cpu;
+
+        let truncation = TruncationParams {
+            max_length: TRUNCATE_INPUT,
+            ..Default::default()
+        };
+
+        let padding = PaddingParams {
+            ..Default::default()
+        };
+
+        let mut tokenizer =
+            tokenizers::Tokenizer::from_file(folder.as_ref().join("tokenizer.json"))
+                .map_err(|_| anyhow!("couldn't open tokenizer"))?;
+
+        tokenizer
+            .with_truncation(Some(truncation))
+            .map_err(|_| anyhow!("tokenizer truncation settings"))?;
+        tokenizer.with_padding(Some(padding));
+
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(
+                &[folder.as_ref().join("model.safetensors")],
+                candle_core::DType::F32,
+                &device,
+            )?
+        };
+        let config = std::fs::read_to_string(folder.as_ref().join("config.json"))?;
+        let config: bert::Config = serde_json::from_str(&config)?;
+
+        let mut encoder = BertModel::load(vb, &config)?;
+        // the embeddings are pooled from the token embeddings
+        encoder.set_pooler(None);
+
+        Ok(Self {
+            tokenizer,
+            encoder,
+            device,
+        })
+    }
+}
+
+impl Embedder for EmbeddingModel {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encoded = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| anyhow!(e))?;
+
+        let ids = encoded
+            .iter()
+            .map(|enc| Tensor::new(enc.get_ids(), &self.device).map_err(|e| anyhow!(e)))
+            .collect::<Result<Vec<_>>>()?;
+        let input_ids = Tensor::stack(&ids, 0)?;
+        let token_type_ids = input_ids.zeros_like()?;
+
+        let attention_mask = encoded
+            .iter()
+            .map(|enc| Tensor::new(enc.get_attention_mask(), &self.device).map_err(|e| anyhow!(e)))
+            .collect::<Result<Vec<_>>>()?;
+        let attention_mask =
+            Tensor::stack(&attention_mask, 0)?.to_dtype(candle_core::DType::F32)?;
+
+        // (batch, seq, hidden)
+        let tokens = self
+            .encoder
+            .forward(&input_ids, &token_type_ids, &attention_mask)?;
+
+        // mean of the token embeddings that are not padding
+        let mask = attention_mask.unsqueeze(2)?;
+        let sum = tokens.broadcast_mul(&mask)?.sum(1)?;
+        let count = mask.sum(1)?.clamp(1.0, f64::MAX)?;
+        let mean = sum.broadcast_div(&count)?;
+
+        Ok(mean.to_vec2::<f32>()?.into_iter().map(normalize).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanity_check() {
+        if !Path::new("../../data/embedding_model").exists() {
+            return;
+        }
+
+        let model = EmbeddingModel::open("../../data/embedding_model")
+            .expect("Failed to find embedding model");
+
+        let embeddings = model
+            .embed(&[
+                "how to train a dog".to_string(),
+                "puppy training tips".to_string(),
+                "stock market crash".to_string(),
+            ])
+            .unwrap();
+
+        let sim = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+
+        assert!(sim(&embeddings[0], &embeddings[1]) > sim(&embeddings[0], &embeddings[2]));
+    }
+}
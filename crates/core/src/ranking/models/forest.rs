@@ -0,0 +1,216 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Flattened tree ensemble used for inference.
+//!
+//! The nodes of all trees are stored in a single contiguous array with absolute child
+//! indices, and the leaves are nodes without a feature. Batch prediction first writes
+//! the features of all documents into a dense matrix (missing signals are NaN) and then
+//! walks one tree for every document before moving on to the next tree, so the nodes
+//! of the tree being walked stay in cache.
+
+use crate::{
+    enum_map::EnumMap,
+    ranking::{Signal, ALL_SIGNALS},
+};
+
+use super::lambdamart::{AsValue, MissingType, ZERO_THRESHOLD};
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct FlatNode {
+    /// `None` for leaves.
+    feature: Option<Signal>,
+    /// Threshold of a split or the value of a leaf.
+    value: f64,
+    left: u32,
+    right: u32,
+    default_left: bool,
+    missing_type: MissingType,
+    /// Whether values equal to the threshold go left.
+    inclusive: bool,
+}
+
+impl FlatNode {
+    pub(super) fn leaf(value: f64) -> Self {
+        Self {
+            feature: None,
+            value,
+            left: 0,
+            right: 0,
+            default_left: false,
+            missing_type: MissingType::None,
+            inclusive: false,
+        }
+    }
+
+    /// A LightGBM numerical split: `value <= threshold` goes left.
+    pub(super) fn lightgbm(
+        feature: Signal,
+        threshold: f64,
+        default_left: bool,
+        missing_type: MissingType,
+        left: usize,
+        right: usize,
+    ) -> Self {
+        Self {
+            feature: Some(feature),
+            value: threshold,
+            left: left as u32,
+            right: right as u32,
+            default_left,
+            missing_type,
+            inclusive: true,
+        }
+    }
+
+    /// An XGBoost split: `value < threshold` goes left and missing values
+    /// go in the default direction.
+    pub(super) fn xgboost(
+        feature: Signal,
+        threshold: f64,
+        default_left: bool,
+        left: usize,
+        right: usize,
+    ) -> Self {
+        Self {
+            feature: Some(feature),
+            value: threshold,
+            left: left as u32,
+            right: right as u32,
+            default_left,
+            missing_type: MissingType::NaN,
+            inclusive: false,
+        }
+    }
+
+    #[inline]
+    fn go_left(&self, mut value: f64) -> bool {
+        if value.is_nan() && self.missing_type != MissingType::NaN {
+            value = 0.0;
+        }
+
+        let is_missing = match self.missing_type {
+            MissingType::None => false,
+            MissingType::Zero => value.abs() <= ZERO_THRESHOLD,
+            MissingType::NaN => value.is_nan(),
+        };
+
+        if is_missing {
+            self.default_left
+        } else if self.inclusive {
+            value <= self.value
+        } else {
+            value < self.value
+        }
+    }
+}
+
+/// Sum of the trees divided by `divisor` plus `bias`.
+pub(super) struct FlatForest {
+    nodes: Vec<FlatNode>,
+    roots: Vec<u32>,
+    /// Number of columns in the feature matrix.
+    num_features: usize,
+    divisor: f64,
+    bias: f64,
+}
+
+impl FlatForest {
+    pub(super) fn new(divisor: f64, bias: f64) -> Self {
+        Self {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            num_features: 0,
+            divisor,
+            bias,
+        }
+    }
+
+    /// Add a tree whose root is its first node. The child indices are relative to the root.
+    pub(super) fn push_tree(&mut self, nodes: impl IntoIterator<Item = FlatNode>) {
+        let offset = self.nodes.len() as u32;
+        self.roots.push(offset);
+
+        for mut node in nodes {
+            if let Some(feature) = node.feature {
+                node.left += offset;
+                node.right += offset;
+                self.num_features = self.num_features.max(usize::from(feature) + 1);
+            }
+
+            self.nodes.push(node);
+        }
+    }
+
+    #[inline]
+    fn walk(&self, root: u32, value: impl Fn(Signal) -> f64) -> f64 {
+        let mut node = &self.nodes[root as usize];
+
+        while let Some(feature) = node.feature {
+            let next = if node.go_left(value(feature)) {
+                node.left
+            } else {
+                node.right
+            };
+
+            node = &self.nodes[next as usize];
+        }
+
+        node.value
+    }
+
+    fn finish(&self, sum: f64) -> f64 {
+        sum / self.divisor + self.bias
+    }
+
+    pub(super) fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64 {
+        let value = |signal| {
+            features
+                .get(signal)
+                .map(|v| v.as_value())
+                .unwrap_or(f64::NAN)
+        };
+
+        self.finish(self.roots.iter().map(|root| self.walk(*root, value)).sum())
+    }
+
+    pub(super) fn predict_many<V: AsValue>(&self, features: &[&EnumMap<Signal, V>]) -> Vec<f64> {
+        let num_features = self.num_features.max(1);
+        let mut matrix = vec![f64::NAN; features.len() * num_features];
+
+        for (row, features) in matrix.chunks_mut(num_features).zip(features) {
+            for signal in ALL_SIGNALS {
+                let idx = usize::from(signal);
+
+                if idx < num_features {
+                    if let Some(value) = features.get(signal) {
+                        row[idx] = value.as_value();
+                    }
+                }
+            }
+        }
+
+        let mut sums = vec![0.0; features.len()];
+
+        for root in &self.roots {
+            for (sum, row) in sums.iter_mut().zip(matrix.chunks(num_features)) {
+                *sum += self.walk(*root, |signal| row[usize::from(signal)]);
+            }
+        }
+
+        sums.into_iter().map(|sum| self.finish(sum)).collect()
+    }
+}
@@ -14,15 +14,38 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Serialize;
 
 use crate::{
     enum_map::EnumMap,
-    ranking::{signal, Signal},
+    ranking::{
+        signal::{self, ALL_SIGNALS},
+        Signal,
+    },
+};
+
+use super::{
+    calibration::{self, Calibration},
+    forest::{FlatForest, FlatNode},
+    reloadable::ReloadableModel,
+    xgboost::XGBoost,
 };
 
 type Result<T> = std::result::Result<T, Error>;
 
+// bits of the `decision_type` of a LightGBM split
+const CATEGORICAL_MASK: u8 = 1;
+const DEFAULT_LEFT_MASK: u8 = 2;
+
+/// Values this close to zero are considered zero by LightGBM.
+pub(super) const ZERO_THRESHOLD: f64 = 1e-35;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("leaf not found")]
@@ -33,6 +56,15 @@ pub enum Error {
     #[error("couldn't find end of trees")]
     NoEndOfTrees,
 
+    #[error("invalid tree")]
+    InvalidTree,
+
+    #[error("categorical splits are not supported")]
+    CategoricalSplit,
+
+    #[error("unsupported booster: {0}")]
+    UnsupportedBooster(String),
+
     #[error("Signal error: {0}")]
     Signal(#[from] signal::Error),
 
@@ -44,6 +76,12 @@ pub enum Error {
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Calibration error: {0}")]
+    Calibration(#[from] calibration::Error),
 }
 
 #[derive(Clone, Debug)]
@@ -62,20 +100,120 @@ impl AsValue for f64 {
     }
 }
 
+/// How much the model relies on a signal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureImportance {
+    pub signal: Signal,
+    /// Number of splits on the signal across all trees.
+    pub splits: u64,
+    /// Total gain of the splits on the signal.
+    pub gain: f64,
+}
+
+/// Aggregate the `(signal, gain)` of all splits in the model
+/// into a list sorted by total gain.
+pub(super) fn feature_importance(
+    splits: impl Iterator<Item = (Signal, f64)>,
+) -> Vec<FeatureImportance> {
+    let mut importance: HashMap<Signal, FeatureImportance> = HashMap::new();
+
+    for (signal, gain) in splits {
+        let entry = importance.entry(signal).or_insert(FeatureImportance {
+            signal,
+            splits: 0,
+            gain: 0.0,
+        });
+
+        entry.splits += 1;
+        entry.gain += gain;
+    }
+
+    let mut res: Vec<_> = importance.into_values().collect();
+    res.sort_by(|a, b| {
+        b.gain
+            .total_cmp(&a.gain)
+            .then(b.splits.cmp(&a.splits))
+            .then((a.signal as usize).cmp(&(b.signal as usize)))
+    });
+
+    res
+}
+
+/// An ensemble of gradient boosted decision trees.
+pub trait Gbdt {
+    fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64;
+
+    /// Predict the scores of all the documents at once.
+    fn predict_many<V: AsValue>(&self, features: &[&EnumMap<Signal, V>]) -> Vec<f64> {
+        features
+            .iter()
+            .map(|features| self.predict(features))
+            .collect()
+    }
+
+    /// Split counts and gain per signal, most important first.
+    fn feature_importance(&self) -> Vec<FeatureImportance>;
+}
+
+/// Which values LightGBM treats as missing for a split.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) enum MissingType {
+    #[default]
+    None,
+    Zero,
+    NaN,
+}
+
+impl MissingType {
+    fn from_decision_type(decision_type: u8) -> Self {
+        match (decision_type >> 2) & 3 {
+            1 => MissingType::Zero,
+            2 => MissingType::NaN,
+            _ => MissingType::None,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Node {
     threshold: f64,
     feature: Option<Signal>,
+    gain: f64,
+    default_left: bool,
+    missing_type: MissingType,
     leaf_value: f64,
     left: Option<NodeOrLeaf>,
     right: Option<NodeOrLeaf>,
 }
 
 impl Node {
+    /// Follows LightGBM's numerical decision. Signals that are not
+    /// in `features` are treated as NaN.
     fn next<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> Option<&NodeOrLeaf> {
         self.feature.and_then(|feature| {
-            let value = features.get(feature).map(|v| v.as_value()).unwrap_or(0.0);
-            if value <= self.threshold {
+            let mut value = features
+                .get(feature)
+                .map(|v| v.as_value())
+                .unwrap_or(f64::NAN);
+
+            if value.is_nan() && self.missing_type != MissingType::NaN {
+                value = 0.0;
+            }
+
+            let is_missing = match self.missing_type {
+                MissingType::None => false,
+                MissingType::Zero => value.abs() <= ZERO_THRESHOLD,
+                MissingType::NaN => value.is_nan(),
+            };
+
+            let go_left = if is_missing {
+                self.default_left
+            } else {
+                value <= self.threshold
+            };
+
+            if go_left {
                 self.left.as_ref()
             } else {
                 self.right.as_ref()
@@ -92,6 +230,8 @@ impl Tree {
     fn parse(s: &str, header: &Header) -> Result<Self> {
         let mut split_features = Vec::new();
         let mut thresholds = Vec::new();
+        let mut split_gains = Vec::new();
+        let mut decision_types = Vec::new();
         let mut leaf_values = Vec::new();
         let mut lefts = Vec::new();
         let mut rights = Vec::new();
@@ -111,6 +251,23 @@ impl Tree {
                             thresholds.push(thresh);
                         }
                     }
+                    "decision_type" => {
+                        for decision_type in value.split(' ') {
+                            let decision_type: u8 = decision_type.parse()?;
+
+                            if decision_type & CATEGORICAL_MASK != 0 {
+                                return Err(Error::CategoricalSplit);
+                            }
+
+                            decision_types.push(decision_type);
+                        }
+                    }
+                    "split_gain" => {
+                        for gain in value.split(' ') {
+                            let gain: f64 = gain.parse()?;
+                            split_gains.push(gain);
+                        }
+                    }
                     "leaf_value" => {
                         for value in value.split(' ') {
                             let value: f64 = value.parse()?;
@@ -167,6 +324,9 @@ impl Tree {
             nodes.push(Node {
                 threshold: 0.0,
                 feature: None,
+                gain: 0.0,
+                default_left: false,
+                missing_type: MissingType::None,
                 leaf_value: leaf_value + offest,
                 left: None,
                 right: None,
@@ -181,6 +341,15 @@ impl Tree {
             nodes[idx].threshold = *threshold;
         }
 
+        for (idx, gain) in split_gains.iter().enumerate() {
+            nodes[idx].gain = *gain;
+        }
+
+        for (idx, decision_type) in decision_types.iter().enumerate() {
+            nodes[idx].default_left = decision_type & DEFAULT_LEFT_MASK != 0;
+            nodes[idx].missing_type = MissingType::from_decision_type(*decision_type);
+        }
+
         for (idx, left) in lefts.iter().enumerate() {
             nodes[idx].left = Some(left.clone());
         }
@@ -203,6 +372,48 @@ impl Tree {
 
         Err(Error::LeafNotFound)
     }
+
+    /// The splits are laid out first, followed by the leaves.
+    fn flatten(&self) -> Result<Vec<FlatNode>> {
+        let num_splits = self
+            .nodes
+            .iter()
+            .take_while(|node| node.feature.is_some())
+            .count();
+
+        if num_splits == 0 {
+            return Ok(vec![FlatNode::leaf(
+                self.nodes.first().ok_or(Error::InvalidTree)?.leaf_value,
+            )]);
+        }
+
+        let child = |child: &Option<NodeOrLeaf>| match child {
+            Some(NodeOrLeaf::Node(idx)) if *idx < num_splits => Ok(*idx),
+            Some(NodeOrLeaf::Leaf(idx)) if *idx < self.nodes.len() => Ok(num_splits + idx),
+            _ => Err(Error::InvalidTree),
+        };
+
+        let mut nodes = Vec::with_capacity(num_splits + self.nodes.len());
+
+        for node in &self.nodes[..num_splits] {
+            nodes.push(FlatNode::lightgbm(
+                node.feature.ok_or(Error::InvalidTree)?,
+                node.threshold,
+                node.default_left,
+                node.missing_type,
+                child(&node.left)?,
+                child(&node.right)?,
+            ));
+        }
+
+        nodes.extend(
+            self.nodes
+                .iter()
+                .map(|node| FlatNode::leaf(node.leaf_value)),
+        );
+
+        Ok(nodes)
+    }
 }
 
 struct Header {
@@ -231,11 +442,12 @@ impl Header {
     }
 }
 
-pub struct LambdaMART {
+/// Model dumped by LightGBM in its text format.
+pub struct LightGbm {
     trees: Vec<Tree>,
 }
 
-impl LambdaMART {
+impl LightGbm {
     pub fn parse(s: &str) -> Result<Self> {
         let lines: Vec<_> = s.lines().map(|s| s.to_string()).collect();
         let end_header = lines
@@ -284,19 +496,153 @@ impl LambdaMART {
         Ok(Self { trees })
     }
 
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let s = std::fs::read_to_string(path)?;
+    fn flatten(&self) -> Result<FlatForest> {
+        let mut forest = FlatForest::new(self.trees.len() as f64, 0.0);
+
+        for tree in &self.trees {
+            forest.push_tree(tree.flatten()?);
+        }
 
-        Self::parse(&s)
+        Ok(forest)
     }
+}
 
-    pub fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64 {
+impl Gbdt for LightGbm {
+    fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64 {
         self.trees
             .iter()
             .map(|t| t.predict(features).unwrap())
             .sum::<f64>()
             / (self.trees.len() as f64)
     }
+
+    fn feature_importance(&self) -> Vec<FeatureImportance> {
+        feature_importance(self.trees.iter().flat_map(|tree| {
+            tree.nodes
+                .iter()
+                .filter_map(|node| node.feature.map(|feature| (feature, node.gain)))
+        }))
+    }
+}
+
+enum Booster {
+    LightGbm(LightGbm),
+    XGBoost(XGBoost),
+}
+
+pub struct LambdaMART {
+    booster: Booster,
+    /// Used for inference, while the booster is kept for introspection.
+    forest: FlatForest,
+    calibration: Option<Calibration>,
+}
+
+/// The calibration of a model is stored next to it, e.g. `model.calibration.json`
+/// for the model `model.txt`. It is reloaded when the model is reloaded.
+pub fn calibration_path(model_path: &Path) -> PathBuf {
+    model_path.with_extension("calibration.json")
+}
+
+impl LambdaMART {
+    /// Parse a model in the LightGBM text format.
+    pub fn parse(s: &str) -> Result<Self> {
+        let model = LightGbm::parse(s)?;
+
+        Ok(Self {
+            forest: model.flatten()?,
+            booster: Booster::LightGbm(model),
+            calibration: None,
+        })
+    }
+
+    /// Parse a model in the XGBoost JSON format.
+    pub fn parse_xgboost(s: &str) -> Result<Self> {
+        let model = XGBoost::parse(s)?;
+
+        Ok(Self {
+            forest: model.flatten(),
+            booster: Booster::XGBoost(model),
+            calibration: None,
+        })
+    }
+
+    /// Open a model in either the LightGBM text or XGBoost JSON format,
+    /// together with its calibration if there is one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)?;
+
+        let model = if s.trim_start().starts_with('{') {
+            Self::parse_xgboost(&s)?
+        } else {
+            Self::parse(&s)?
+        };
+
+        let calibration_path = calibration_path(path);
+        if calibration_path.exists() {
+            return Ok(model.with_calibration(Calibration::open(calibration_path)?));
+        }
+
+        Ok(model)
+    }
+
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Relevance of the score in `[0, 1]` that is comparable across queries.
+    /// Only available if the model has been calibrated.
+    pub fn calibrate(&self, score: f64) -> Option<f64> {
+        self.calibration
+            .as_ref()
+            .map(|calibration| calibration.calibrate(score))
+    }
+}
+
+impl ReloadableModel for LambdaMART {
+    fn open_model(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self::open(path)?)
+    }
+
+    fn dry_run(&self) -> anyhow::Result<()> {
+        let mut zeros = EnumMap::new();
+        let mut ones = EnumMap::new();
+
+        for signal in ALL_SIGNALS {
+            zeros.insert(signal, 0.0);
+            ones.insert(signal, 1.0);
+        }
+
+        let fixtures: [EnumMap<Signal, f64>; 3] = [EnumMap::new(), zeros, ones];
+
+        for features in &fixtures {
+            let score = self.predict(features);
+
+            if !score.is_finite() {
+                anyhow::bail!("model predicted {score} on fixture");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Gbdt for LambdaMART {
+    fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64 {
+        self.forest.predict(features)
+    }
+
+    fn predict_many<V: AsValue>(&self, features: &[&EnumMap<Signal, V>]) -> Vec<f64> {
+        self.forest.predict_many(features)
+    }
+
+    fn feature_importance(&self) -> Vec<FeatureImportance> {
+        match &self.booster {
+            Booster::LightGbm(model) => model.feature_importance(),
+            Booster::XGBoost(model) => model.feature_importance(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -306,7 +652,7 @@ mod tests {
     #[test]
     fn simple() {
         let model = include_str!("../../../testcases/lambdamart.txt");
-        let model = LambdaMART::parse(model).unwrap();
+        let model = LightGbm::parse(model).unwrap();
         assert!(!model.trees.is_empty());
 
         let mut features = EnumMap::new();
@@ -342,4 +688,137 @@ mod tests {
 
         assert_eq!((model.predict(&features) * 1000.0) as u64, 1050);
     }
+
+    fn split(default_left: bool, missing_type: MissingType) -> Node {
+        Node {
+            threshold: 0.5,
+            feature: Some(Signal::Bm25Title),
+            gain: 0.0,
+            default_left,
+            missing_type,
+            leaf_value: 0.0,
+            left: Some(NodeOrLeaf::Leaf(0)),
+            right: Some(NodeOrLeaf::Leaf(1)),
+        }
+    }
+
+    fn goes_left(node: &Node, value: Option<f64>) -> bool {
+        let mut features = EnumMap::new();
+        if let Some(value) = value {
+            features.insert(Signal::Bm25Title, value);
+        }
+
+        matches!(node.next(&features), Some(NodeOrLeaf::Leaf(0)))
+    }
+
+    #[test]
+    fn missing_values() {
+        // missing and NaN values are treated as zero
+        let node = split(false, MissingType::None);
+        assert!(goes_left(&node, None));
+        assert!(goes_left(&node, Some(f64::NAN)));
+        assert!(!goes_left(&node, Some(1.0)));
+
+        // zero is missing and follows the default direction
+        let node = split(false, MissingType::Zero);
+        assert!(!goes_left(&node, None));
+        assert!(!goes_left(&node, Some(0.0)));
+        assert!(goes_left(&node, Some(0.1)));
+
+        // NaN is missing and follows the default direction
+        let node = split(false, MissingType::NaN);
+        assert!(!goes_left(&node, None));
+        assert!(goes_left(&node, Some(0.0)));
+
+        let node = split(true, MissingType::NaN);
+        assert!(goes_left(&node, Some(f64::NAN)));
+        assert!(!goes_left(&node, Some(1.0)));
+    }
+
+    #[test]
+    fn decision_type() {
+        assert_eq!(MissingType::from_decision_type(2), MissingType::None);
+        assert_eq!(MissingType::from_decision_type(6), MissingType::Zero);
+        assert_eq!(MissingType::from_decision_type(10), MissingType::NaN);
+    }
+
+    #[test]
+    fn lightgbm_feature_importance() {
+        let model = include_str!("../../../testcases/lambdamart.txt");
+        let model = LightGbm::parse(model).unwrap();
+
+        let importance = model.feature_importance();
+        assert!(!importance.is_empty());
+
+        let num_splits: usize = model
+            .trees
+            .iter()
+            .map(|tree| tree.nodes.iter().filter(|n| n.feature.is_some()).count())
+            .sum();
+        assert_eq!(
+            importance.iter().map(|i| i.splits).sum::<u64>(),
+            num_splits as u64
+        );
+
+        assert!(importance
+            .windows(2)
+            .all(|window| window[0].gain >= window[1].gain));
+    }
+
+    #[test]
+    fn flattened_forest_matches_trees() {
+        let model = include_str!("../../../testcases/lambdamart.txt");
+        let trees = LightGbm::parse(model).unwrap();
+        let model = LambdaMART::parse(model).unwrap();
+
+        let features: Vec<EnumMap<Signal, f64>> = (0..50)
+            .map(|i| {
+                ALL_SIGNALS
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(j, _)| (i + j) % 5 != 0)
+                    .map(|(j, signal)| (signal, ((i * 31 + j * 17) % 100) as f64 / 10.0))
+                    .collect()
+            })
+            .chain(std::iter::once(EnumMap::new()))
+            .collect();
+
+        let batch = model.predict_many(&features.iter().collect::<Vec<_>>());
+        assert_eq!(batch.len(), features.len());
+
+        for (features, batch) in features.iter().zip(batch) {
+            let expected = trees.predict(features);
+
+            assert!((model.predict(features) - expected).abs() < 1e-9);
+            assert!((batch - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn calibration_is_loaded_with_model() {
+        let dir = crate::gen_temp_path();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let model_path = dir.join("model.txt");
+        std::fs::write(
+            &model_path,
+            include_str!("../../../testcases/lambdamart.txt"),
+        )
+        .unwrap();
+
+        let model = LambdaMART::open(&model_path).unwrap();
+        assert_eq!(model.calibrate(1.0), None);
+
+        std::fs::write(
+            calibration_path(&model_path),
+            r#"{"type": "platt", "a": 1.0, "b": 0.0}"#,
+        )
+        .unwrap();
+
+        let model = LambdaMART::open(&model_path).unwrap();
+        assert_eq!(model.calibrate(0.0), Some(0.5));
+
+        std::fs::write(calibration_path(&model_path), r#"{"type": "platt"}"#).unwrap();
+        assert!(LambdaMART::open(&model_path).is_err());
+    }
 }
@@ -1,3 +1,8 @@
+pub mod calibration;
 pub mod cross_encoder;
+pub mod embedding;
+mod forest;
 pub mod lambdamart;
 pub mod linear;
+pub mod reloadable;
+pub mod xgboost;
@@ -0,0 +1,238 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ranking models that can be swapped at runtime without restarting the server.
+//!
+//! A new model is only swapped in after it has been opened and passed a dry-run
+//! on a small fixture. Searches that are in flight keep using the model they
+//! started with, since they hold their own reference to it.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+
+pub trait ReloadableModel: Sized + Send + Sync + 'static {
+    fn open_model(path: &Path) -> Result<Self>;
+
+    /// Run the model on a fixture and check that the output looks sane.
+    fn dry_run(&self) -> Result<()>;
+}
+
+struct Source {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+pub struct Reloadable<T> {
+    model: RwLock<Option<Arc<T>>>,
+    source: Mutex<Option<Source>>,
+}
+
+impl<T> Default for Reloadable<T> {
+    fn default() -> Self {
+        Self {
+            model: RwLock::new(None),
+            source: Mutex::new(None),
+        }
+    }
+}
+
+/// The latest modification time of the file, or of any file
+/// in the folder for models that consist of multiple files.
+fn last_modified(path: &Path) -> Option<SystemTime> {
+    let metadata = std::fs::metadata(path).ok()?;
+
+    if !metadata.is_dir() {
+        return metadata.modified().ok();
+    }
+
+    std::fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .chain(metadata.modified().ok())
+        .max()
+}
+
+impl<T: ReloadableModel> Reloadable<T> {
+    pub fn new(model: T) -> Self {
+        Self {
+            model: RwLock::new(Some(Arc::new(model))),
+            source: Mutex::new(None),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let res = Self::default();
+        res.reload(path)?;
+        Ok(res)
+    }
+
+    pub fn open_optional<P: AsRef<Path>>(path: Option<P>) -> Result<Self> {
+        match path {
+            Some(path) => Self::open(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The currently loaded model.
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.model.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Path the current model was loaded from.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.source
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|source| source.path.clone())
+    }
+
+    /// Validate the model and swap it with the current one.
+    pub fn swap(&self, model: T) -> Result<()> {
+        model.dry_run()?;
+
+        *self.model.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(model));
+
+        Ok(())
+    }
+
+    /// Open the model at `path` and swap it with the current one if it is valid.
+    /// The path will be watched for changes by [`Reloadable::reload_if_modified`].
+    pub fn reload<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let modified = last_modified(path);
+
+        let model = T::open_model(path)
+            .map_err(|err| anyhow!("failed to open model {}: {err}", path.display()))?;
+        self.swap(model)?;
+
+        *self.source.lock().unwrap_or_else(|e| e.into_inner()) = Some(Source {
+            path: path.to_path_buf(),
+            modified,
+        });
+
+        Ok(())
+    }
+
+    /// Reload the model if the file has changed since it was loaded.
+    /// Returns whether the model was swapped.
+    pub fn reload_if_modified(&self) -> Result<bool> {
+        let path = {
+            let source = self.source.lock().unwrap_or_else(|e| e.into_inner());
+
+            match source.as_ref() {
+                Some(source) if last_modified(&source.path) != source.modified => {
+                    source.path.clone()
+                }
+                _ => return Ok(false),
+            }
+        };
+
+        match self.reload(&path) {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                // don't retry the same broken model until it changes again
+                if let Some(source) = self
+                    .source
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .as_mut()
+                {
+                    source.modified = last_modified(&path);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Periodically check the model file for changes and reload it.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let model = self.clone();
+            match tokio::task::spawn_blocking(move || model.reload_if_modified()).await {
+                Ok(Ok(true)) => tracing::info!("reloaded {:?}", self.path()),
+                Ok(Ok(false)) => {}
+                Ok(Err(err)) => tracing::error!("failed to reload {:?}: {err}", self.path()),
+                Err(err) => tracing::error!("model reload task failed: {err}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Constant(f64);
+
+    impl ReloadableModel for Constant {
+        fn open_model(path: &Path) -> Result<Self> {
+            Ok(Self(std::fs::read_to_string(path)?.trim().parse()?))
+        }
+
+        fn dry_run(&self) -> Result<()> {
+            if self.0.is_finite() {
+                Ok(())
+            } else {
+                Err(anyhow!("non-finite output"))
+            }
+        }
+    }
+
+    #[test]
+    fn swap_is_validated() {
+        let model = Reloadable::new(Constant(1.0));
+
+        assert!(model.swap(Constant(f64::NAN)).is_err());
+        assert_eq!(model.get().unwrap().0, 1.0);
+
+        let in_flight = model.get().unwrap();
+        model.swap(Constant(2.0)).unwrap();
+
+        assert_eq!(in_flight.0, 1.0);
+        assert_eq!(model.get().unwrap().0, 2.0);
+    }
+
+    #[test]
+    fn reload_modified_file() {
+        let path = crate::gen_temp_path().join("model.txt");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "1.0").unwrap();
+
+        let model: Reloadable<Constant> = Reloadable::open(&path).unwrap();
+        assert!(!model.reload_if_modified().unwrap());
+
+        std::fs::write(&path, "not a number").unwrap();
+        model.source.lock().unwrap().as_mut().unwrap().modified = None;
+        assert!(model.reload_if_modified().is_err());
+        assert_eq!(model.get().unwrap().0, 1.0);
+
+        std::fs::write(&path, "3.0").unwrap();
+        model.source.lock().unwrap().as_mut().unwrap().modified = None;
+        assert!(model.reload_if_modified().unwrap());
+        assert_eq!(model.get().unwrap().0, 3.0);
+    }
+}
@@ -0,0 +1,384 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Loader for models saved with XGBoost's `save_model("model.json")`.
+//! The feature names of the model must be the names of the ranking signals.
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::{enum_map::EnumMap, ranking::Signal};
+
+use super::{
+    forest::{FlatForest, FlatNode},
+    lambdamart::{feature_importance, AsValue, Error, FeatureImportance, Gbdt},
+};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Deserialize)]
+struct Model {
+    learner: Learner,
+}
+
+#[derive(Deserialize)]
+struct Learner {
+    #[serde(default)]
+    feature_names: Vec<String>,
+    gradient_booster: GradientBooster,
+    learner_model_param: LearnerModelParam,
+}
+
+#[derive(Deserialize)]
+struct LearnerModelParam {
+    base_score: String,
+}
+
+#[derive(Deserialize)]
+struct GradientBooster {
+    name: String,
+    model: Option<GbTreeModel>,
+}
+
+#[derive(Deserialize)]
+struct GbTreeModel {
+    trees: Vec<JsonTree>,
+}
+
+/// XGBoost has written `default_left` both as integers and as booleans.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum Flag {
+    Bool(bool),
+    Int(u8),
+}
+
+impl From<Flag> for bool {
+    fn from(flag: Flag) -> Self {
+        match flag {
+            Flag::Bool(b) => b,
+            Flag::Int(i) => i != 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonTree {
+    left_children: Vec<i64>,
+    right_children: Vec<i64>,
+    split_indices: Vec<usize>,
+    split_conditions: Vec<f64>,
+    default_left: Vec<Flag>,
+    #[serde(default)]
+    loss_changes: Vec<f64>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Split {
+        feature: Signal,
+        threshold: f64,
+        default_left: bool,
+        gain: f64,
+        left: usize,
+        right: usize,
+    },
+    Leaf(f64),
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn from_json(tree: JsonTree, features: &[Signal]) -> Result<Self> {
+        let num_nodes = tree.left_children.len();
+
+        if num_nodes == 0
+            || tree.right_children.len() != num_nodes
+            || tree.split_indices.len() != num_nodes
+            || tree.split_conditions.len() != num_nodes
+            || tree.default_left.len() != num_nodes
+        {
+            return Err(Error::InvalidTree);
+        }
+
+        let mut nodes = Vec::with_capacity(num_nodes);
+
+        for i in 0..num_nodes {
+            let left = tree.left_children[i];
+            let right = tree.right_children[i];
+
+            // leaves have no children and store their value in the split condition
+            if left < 0 {
+                nodes.push(Node::Leaf(tree.split_conditions[i]));
+                continue;
+            }
+
+            if right < 0 || left as usize >= num_nodes || right as usize >= num_nodes {
+                return Err(Error::InvalidTree);
+            }
+
+            let feature = *features
+                .get(tree.split_indices[i])
+                .ok_or(Error::InvalidTree)?;
+
+            nodes.push(Node::Split {
+                feature,
+                threshold: tree.split_conditions[i],
+                default_left: tree.default_left[i].into(),
+                gain: tree.loss_changes.get(i).copied().unwrap_or_default(),
+                left: left as usize,
+                right: right as usize,
+            });
+        }
+
+        Ok(Self { nodes })
+    }
+
+    fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64 {
+        let mut node = &self.nodes[0];
+
+        loop {
+            match node {
+                Node::Leaf(value) => return *value,
+                Node::Split {
+                    feature,
+                    threshold,
+                    default_left,
+                    left,
+                    right,
+                    ..
+                } => {
+                    let go_left = match features.get(*feature) {
+                        Some(value) => value.as_value() < *threshold,
+                        None => *default_left,
+                    };
+
+                    node = if go_left {
+                        &self.nodes[*left]
+                    } else {
+                        &self.nodes[*right]
+                    };
+                }
+            }
+        }
+    }
+}
+
+pub struct XGBoost {
+    base_score: f64,
+    trees: Vec<Tree>,
+}
+
+impl XGBoost {
+    pub fn parse(s: &str) -> Result<Self> {
+        let model: Model = serde_json::from_str(s)?;
+        let learner = model.learner;
+
+        if learner.gradient_booster.name != "gbtree" {
+            return Err(Error::UnsupportedBooster(learner.gradient_booster.name));
+        }
+
+        if learner.feature_names.is_empty() {
+            return Err(Error::NoFeatures);
+        }
+
+        let features = learner
+            .feature_names
+            .iter()
+            .map(|name| Signal::from_str(name))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // newer versions of xgboost store the base score as a vector, e.g. `[5E-1]`
+        let base_score: f64 = learner
+            .learner_model_param
+            .base_score
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse()?;
+
+        let trees = learner
+            .gradient_booster
+            .model
+            .map(|model| model.trees)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tree| Tree::from_json(tree, &features))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { base_score, trees })
+    }
+
+    pub(super) fn flatten(&self) -> FlatForest {
+        let mut forest = FlatForest::new(1.0, self.base_score);
+
+        for tree in &self.trees {
+            forest.push_tree(tree.nodes.iter().map(|node| match node {
+                Node::Split {
+                    feature,
+                    threshold,
+                    default_left,
+                    left,
+                    right,
+                    ..
+                } => FlatNode::xgboost(*feature, *threshold, *default_left, *left, *right),
+                Node::Leaf(value) => FlatNode::leaf(*value),
+            }));
+        }
+
+        forest
+    }
+}
+
+impl Gbdt for XGBoost {
+    fn predict<V: AsValue>(&self, features: &EnumMap<Signal, V>) -> f64 {
+        self.base_score + self.trees.iter().map(|t| t.predict(features)).sum::<f64>()
+    }
+
+    fn feature_importance(&self) -> Vec<FeatureImportance> {
+        feature_importance(self.trees.iter().flat_map(|tree| {
+            tree.nodes.iter().filter_map(|node| match node {
+                Node::Split { feature, gain, .. } => Some((*feature, *gain)),
+                Node::Leaf(_) => None,
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = r#"{
+        "learner": {
+            "attributes": {},
+            "feature_names": ["bm25_title", "host_centrality"],
+            "feature_types": ["float", "float"],
+            "gradient_booster": {
+                "model": {
+                    "gbtree_model_param": {"num_parallel_tree": "1", "num_trees": "2"},
+                    "tree_info": [0, 0],
+                    "trees": [
+                        {
+                            "id": 0,
+                            "left_children": [1, -1, -1],
+                            "right_children": [2, -1, -1],
+                            "split_indices": [0, 0, 0],
+                            "split_conditions": [10.0, -0.5, 0.5],
+                            "default_left": [1, 0, 0],
+                            "loss_changes": [3.0, 0.0, 0.0]
+                        },
+                        {
+                            "id": 1,
+                            "left_children": [1, -1, -1],
+                            "right_children": [2, -1, -1],
+                            "split_indices": [1, 0, 0],
+                            "split_conditions": [0.1, 0.0, 0.25],
+                            "default_left": [false, false, false],
+                            "loss_changes": [1.5, 0.0, 0.0]
+                        }
+                    ]
+                },
+                "name": "gbtree"
+            },
+            "learner_model_param": {"base_score": "[5E-1]", "num_class": "0", "num_feature": "2"},
+            "objective": {"name": "rank:ndcg"}
+        },
+        "version": [2, 0, 0]
+    }"#;
+
+    #[test]
+    fn parse_and_predict() {
+        let model = XGBoost::parse(MODEL).unwrap();
+        assert_eq!(model.trees.len(), 2);
+
+        let mut features = EnumMap::new();
+        features.insert(Signal::Bm25Title, 20.0);
+        features.insert(Signal::HostCentrality, 0.2);
+        assert_eq!(model.predict(&features), 0.5 + 0.5 + 0.25);
+
+        let mut features = EnumMap::new();
+        features.insert(Signal::Bm25Title, 5.0);
+        features.insert(Signal::HostCentrality, 0.05);
+        assert_eq!(model.predict(&features), 0.5 - 0.5);
+    }
+
+    #[test]
+    fn missing_features_follow_default_direction() {
+        let model = XGBoost::parse(MODEL).unwrap();
+
+        let features: EnumMap<Signal, f64> = EnumMap::new();
+        assert_eq!(model.predict(&features), 0.5 - 0.5 + 0.25);
+    }
+
+    #[test]
+    fn flattened() {
+        let model = XGBoost::parse(MODEL).unwrap();
+        let forest = model.flatten();
+
+        let mut high = EnumMap::new();
+        high.insert(Signal::Bm25Title, 20.0);
+        high.insert(Signal::HostCentrality, 0.2);
+
+        let mut low = EnumMap::new();
+        low.insert(Signal::Bm25Title, 5.0);
+        low.insert(Signal::HostCentrality, 0.05);
+
+        let missing = EnumMap::new();
+
+        let features = [&high, &low, &missing];
+
+        for features in features {
+            assert_eq!(forest.predict(features), model.predict(features));
+        }
+
+        assert_eq!(
+            forest.predict_many(&features),
+            features
+                .iter()
+                .map(|features| model.predict(*features))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn importance() {
+        let model = XGBoost::parse(MODEL).unwrap();
+
+        assert_eq!(
+            model.feature_importance(),
+            vec![
+                FeatureImportance {
+                    signal: Signal::Bm25Title,
+                    splits: 1,
+                    gain: 3.0,
+                },
+                FeatureImportance {
+                    signal: Signal::HostCentrality,
+                    splits: 1,
+                    gain: 1.5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_feature() {
+        let model = MODEL.replace("host_centrality", "not_a_signal");
+        assert!(XGBoost::parse(&model).is_err());
+    }
+}
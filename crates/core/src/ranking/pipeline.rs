@@ -20,7 +20,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     collector::{self, BucketCollector},
-    config::CollectorConfig,
+    config::{CollectorConfig, RankingStageKind},
     enum_map::EnumMap,
     inverted_index::{RetrievedWebpage, WebsitePointer},
     searcher::SearchQuery,
@@ -28,11 +28,11 @@ use crate::{
 };
 
 use super::{
-    models::lambdamart::{self, LambdaMART},
+    models::lambdamart::{self, Gbdt, LambdaMART},
     Signal, SignalAggregator, SignalCoefficient, SignalScore,
 };
 
-use super::models::cross_encoder::CrossEncoder;
+use super::models::cross_encoder::{CrossEncoder, CrossEncoderModel};
 
 pub trait AsRankingWebsite: Clone {
     fn as_ranking(&self) -> &RankingWebsite;
@@ -108,6 +108,9 @@ pub struct RankingWebsite {
     pub snippet: Option<String>,
     pub optic_boost: Option<f64>,
     pub score: f64,
+    /// Output of the LambdaMART model before it is weighted by its coefficient.
+    /// This is the score the calibration of the model has been fitted on.
+    pub model_score: Option<f64>,
 }
 
 impl RankingWebsite {
@@ -118,6 +121,7 @@ impl RankingWebsite {
             score: pointer.score.total,
             optic_boost: None,
             snippet: None,
+            model_score: None,
             pointer: pointer.clone(),
         };
 
@@ -134,23 +138,115 @@ impl RankingWebsite {
     }
 }
 
-trait Scorer<T>: Send + Sync {
-    fn score(&self, websites: &mut [T]);
+/// A step of the ranking pipeline. The stages are applied in the order they are
+/// configured in [`RankingPipelineConfig`], and each stage gets the websites
+/// in the order produced by the previous stage.
+pub trait RankingStage<T>: Send + Sync {
+    fn rank(&self, websites: Vec<T>) -> Vec<T>;
     fn set_query_info(&mut self, _query: &SearchQuery) {}
 }
 
+fn linear_score(signals: &EnumMap<Signal, SignalScore>) -> f64 {
+    signals
+        .values()
+        .map(|score| score.coefficient * score.value)
+        .sum()
+}
+
+fn set_score<T: AsRankingWebsite>(website: &mut T, score: f64) {
+    let website = website.as_mut_ranking();
+
+    website.score = match website.optic_boost {
+        Some(boost) if boost != 0.0 => score * boost,
+        _ => score,
+    };
+}
+
+fn sort_by_score<T: AsRankingWebsite>(websites: &mut [T]) {
+    websites.sort_by(|a, b| b.as_ranking().score.total_cmp(&a.as_ranking().score));
+}
+
+/// Score the websites by the weighted sum of their signals.
+#[derive(Default)]
+struct Recall;
+
+impl<T: AsRankingWebsite> RankingStage<T> for Recall {
+    fn rank(&self, mut websites: Vec<T>) -> Vec<T> {
+        for website in websites.iter_mut() {
+            let score = linear_score(&website.as_ranking().signals);
+            set_score(website, score);
+        }
+
+        sort_by_score(&mut websites);
+
+        websites
+    }
+}
+
+/// Score the websites using the LambdaMART model. The previous score is kept
+/// if the query sets the coefficient of the model to 0.
+struct LambdaMartStage {
+    model: Arc<LambdaMART>,
+    signal_coefficients: Option<SignalCoefficient>,
+}
+
+impl LambdaMartStage {
+    fn new(model: Arc<LambdaMART>) -> Self {
+        Self {
+            model,
+            signal_coefficients: None,
+        }
+    }
+
+    fn coefficient(&self) -> f64 {
+        self.signal_coefficients
+            .as_ref()
+            .and_then(|coeffs| coeffs.get(&Signal::LambdaMART))
+            .unwrap_or(Signal::LambdaMART.default_coefficient())
+    }
+}
+
+impl<T: AsRankingWebsite> RankingStage<T> for LambdaMartStage {
+    fn rank(&self, mut websites: Vec<T>) -> Vec<T> {
+        let coefficient = self.coefficient();
+
+        if coefficient == 0.0 {
+            return websites;
+        }
+
+        let features: Vec<_> = websites
+            .iter()
+            .map(|website| &website.as_ranking().signals)
+            .collect();
+        let scores = self.model.predict_many(&features);
+
+        for (website, score) in websites.iter_mut().zip(scores) {
+            website.as_mut_ranking().model_score = Some(score);
+            set_score(website, coefficient * score);
+        }
+
+        sort_by_score(&mut websites);
+
+        websites
+    }
+
+    fn set_query_info(&mut self, query: &SearchQuery) {
+        self.signal_coefficients = query.signal_coefficients();
+    }
+}
+
+/// Add the cross encoder signals of the title and snippet and rescore the websites
+/// by the weighted sum of their signals.
 struct ReRanker<M: CrossEncoder> {
     crossencoder: Arc<M>,
-    lambda_mart: Option<Arc<LambdaMART>>,
     query: Option<SearchQuery>,
     signal_coefficients: Option<SignalCoefficient>,
 }
 
 impl<M: CrossEncoder> ReRanker<M> {
-    fn new(crossencoder: Arc<M>, lambda: Option<Arc<LambdaMART>>) -> Self {
+    fn new(crossencoder: Arc<M>) -> Self {
         Self {
             crossencoder,
-            lambda_mart: lambda,
             query: None,
             signal_coefficients: None,
         }
@@ -205,218 +301,141 @@ impl<M: CrossEncoder> ReRanker<M> {
     }
 }
 
-impl<T: AsRankingWebsite, M: CrossEncoder> Scorer<T> for ReRanker<M> {
-    fn score(&self, websites: &mut [T]) {
-        self.crossencoder_score_websites(websites);
+impl<T: AsRankingWebsite, M: CrossEncoder> RankingStage<T> for ReRanker<M> {
+    fn rank(&self, mut websites: Vec<T>) -> Vec<T> {
+        self.crossencoder_score_websites(&mut websites);
 
         for website in websites.iter_mut() {
-            let website = website.as_mut_ranking();
-            website.score = calculate_score(
-                &self.lambda_mart,
-                &self.signal_coefficients,
-                &website.signals,
-            );
+            let score = linear_score(&website.as_ranking().signals);
+            set_score(website, score);
         }
-    }
 
-    fn set_query_info(&mut self, query: &SearchQuery) {
-        self.query = Some(query.clone());
+        sort_by_score(&mut websites);
 
-        self.signal_coefficients = query.optic.as_ref().map(SignalCoefficient::from_optic);
-    }
-}
-
-struct IdentityScorer;
-
-impl<T: AsRankingWebsite> Scorer<T> for IdentityScorer {
-    fn score(&self, _websites: &mut [T]) {}
-}
-
-fn calculate_score(
-    model: &Option<Arc<LambdaMART>>,
-    signal_coefficients: &Option<SignalCoefficient>,
-    signals: &EnumMap<Signal, SignalScore>,
-) -> f64 {
-    let lambda_score = match model {
-        Some(model) => match signal_coefficients {
-            Some(coefficients) => match coefficients.get(&Signal::LambdaMART) {
-                Some(coeff) => {
-                    if coeff == 0.0 {
-                        signals
-                            .values()
-                            .map(|score| score.coefficient * score.value)
-                            .sum()
-                    } else {
-                        coeff * model.predict(signals)
-                    }
-                }
-                None => Signal::LambdaMART.default_coefficient() * model.predict(signals),
-            },
-            None => Signal::LambdaMART.default_coefficient() * model.predict(signals),
-        },
-        None => signals
-            .values()
-            .map(|score| score.coefficient * score.value)
-            .sum(),
-    };
-
-    lambda_score
-}
-
-#[derive(Default)]
-struct Initial {
-    model: Option<Arc<LambdaMART>>,
-    signal_coefficients: Option<SignalCoefficient>,
-}
-
-impl<T: AsRankingWebsite> Scorer<T> for Initial {
-    fn score(&self, websites: &mut [T]) {
-        for website in websites {
-            let website = website.as_mut_ranking();
-            website.score =
-                calculate_score(&self.model, &self.signal_coefficients, &website.signals);
-        }
+        websites
     }
 
     fn set_query_info(&mut self, query: &SearchQuery) {
-        self.signal_coefficients = query.optic.as_ref().map(SignalCoefficient::from_optic);
+        self.query = Some(query.clone());
+
+        self.signal_coefficients = query.signal_coefficients();
     }
 }
 
-struct RankingStage<T> {
-    scorer: Box<dyn Scorer<T>>,
-    stage_top_n: usize,
-    derank_similar: bool,
+/// Derank websites that are similar to higher ranked websites,
+/// e.g. from the same site or with near duplicate content.
+struct Diversity {
+    collector_config: CollectorConfig,
 }
 
-impl<T: AsRankingWebsite> RankingStage<T> {
-    fn apply(
-        &self,
-        websites: Vec<T>,
-        top_n: usize,
-        offset: usize,
-        collector_config: CollectorConfig,
-    ) -> Vec<T> {
-        let mut websites = websites
-            .into_iter()
-            .skip(offset)
-            .take(self.stage_top_n.max(top_n))
-            .collect::<Vec<_>>();
-
-        self.scorer.score(&mut websites);
-        for website in websites.iter_mut() {
-            let boost = website.as_ranking().optic_boost;
-            if let Some(boost) = boost {
-                if boost != 0.0 {
-                    website.as_mut_ranking().score *= boost;
-                }
-            }
+impl<T: AsRankingWebsite> RankingStage<T> for Diversity {
+    fn rank(&self, websites: Vec<T>) -> Vec<T> {
+        if websites.is_empty() {
+            return websites;
         }
 
-        let mut collector =
-            BucketCollector::new(self.stage_top_n.max(top_n) + offset, collector_config);
+        let mut collector = BucketCollector::new(websites.len(), self.collector_config.clone());
 
         for website in websites {
             collector.insert(website);
         }
 
-        collector
-            .into_sorted_vec(self.derank_similar)
-            .into_iter()
-            .take(top_n)
-            .collect()
+        collector.into_sorted_vec(true)
     }
+}
 
-    fn set_query_info(&mut self, query: &SearchQuery) {
-        self.scorer.set_query_info(query);
-    }
+fn create_stages<T: AsRankingWebsite, M: CrossEncoder + 'static>(
+    kinds: &[RankingStageKind],
+    crossencoder: Option<Arc<M>>,
+    lambda: Option<Arc<LambdaMART>>,
+    collector_config: &CollectorConfig,
+) -> Vec<Box<dyn RankingStage<T>>> {
+    kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            RankingStageKind::Recall => Some(Box::new(Recall) as Box<dyn RankingStage<T>>),
+            RankingStageKind::LambdaMart => lambda
+                .clone()
+                .map(|model| Box::new(LambdaMartStage::new(model)) as Box<dyn RankingStage<T>>),
+            RankingStageKind::CrossEncoder => crossencoder
+                .clone()
+                .map(|model| Box::new(ReRanker::new(model)) as Box<dyn RankingStage<T>>),
+            RankingStageKind::Diversity => Some(Box::new(Diversity {
+                collector_config: collector_config.clone(),
+            }) as Box<dyn RankingStage<T>>),
+        })
+        .collect()
 }
 
 pub struct RankingPipeline<T> {
-    stage: RankingStage<T>,
+    stages: Vec<Box<dyn RankingStage<T>>>,
+    stage_top_n: usize,
     page: usize,
     pub top_n: usize,
-    collector_config: CollectorConfig,
 }
 
 impl<T: AsRankingWebsite> RankingPipeline<T> {
-    fn create_reranking_stage<M: CrossEncoder + 'static>(
+    /// Create a pipeline of the given stages. Stages that need a model
+    /// which is not loaded are left out.
+    fn new<M: CrossEncoder + 'static>(
+        query: &mut SearchQuery,
+        stages: &[RankingStageKind],
         crossencoder: Option<Arc<M>>,
         lambda: Option<Arc<LambdaMART>>,
         collector_config: CollectorConfig,
         top_n_considered: usize,
-    ) -> Result<Self> {
-        let scorer = match crossencoder {
-            Some(cross_encoder) => {
-                Box::new(ReRanker::new(cross_encoder, lambda)) as Box<dyn Scorer<T>>
-            }
-            None => Box::new(IdentityScorer) as Box<dyn Scorer<T>>,
-        };
-
-        let stage = RankingStage {
-            scorer,
+    ) -> Self {
+        let mut pipeline = Self {
+            stages: create_stages(stages, crossencoder, lambda, &collector_config),
             stage_top_n: top_n_considered,
-            derank_similar: true,
-        };
-
-        Ok(Self {
-            stage,
             page: 0,
             top_n: 0,
-            collector_config,
-        })
+        };
+        pipeline.set_query_info(query);
+
+        pipeline
     }
 
     pub fn reranker<M: CrossEncoder + 'static>(
         query: &mut SearchQuery,
+        stages: &[RankingStageKind],
         crossencoder: Option<Arc<M>>,
         lambda: Option<Arc<LambdaMART>>,
         collector_config: CollectorConfig,
         top_n_considered: usize,
     ) -> Result<Self> {
-        let mut pipeline =
-            Self::create_reranking_stage(crossencoder, lambda, collector_config, top_n_considered)?;
-        pipeline.set_query_info(query);
-
-        Ok(pipeline)
-    }
-
-    fn create_recall_stage(
-        model: Option<Arc<LambdaMART>>,
-        collector_config: CollectorConfig,
-        stage_top_n: usize,
-    ) -> Self {
-        let last_stage = RankingStage {
-            scorer: Box::new(Initial {
-                model,
-                signal_coefficients: None,
-            }),
-            stage_top_n,
-            derank_similar: true,
-        };
-
-        Self {
-            stage: last_stage,
-            page: 0,
-            top_n: 0,
+        Ok(Self::new(
+            query,
+            stages,
+            crossencoder,
+            lambda,
             collector_config,
-        }
+            top_n_considered,
+        ))
     }
 
     pub fn recall_stage(
         query: &mut SearchQuery,
+        stages: &[RankingStageKind],
         model: Option<Arc<LambdaMART>>,
         collector_config: CollectorConfig,
         top_n_considered: usize,
     ) -> Self {
-        let mut pipeline = Self::create_recall_stage(model, collector_config, top_n_considered);
-        pipeline.set_query_info(query);
-
-        pipeline
+        Self::new::<CrossEncoderModel>(
+            query,
+            stages,
+            None,
+            model,
+            collector_config,
+            top_n_considered,
+        )
     }
 
     fn set_query_info(&mut self, query: &mut SearchQuery) {
-        self.stage.set_query_info(query);
+        for stage in self.stages.iter_mut() {
+            stage.set_query_info(query);
+        }
+
         self.page = query.page;
         self.top_n = query.num_results;
 
@@ -433,12 +452,19 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
             return websites;
         }
 
-        self.stage.apply(
-            websites,
-            self.top_n,
-            self.offset(),
-            self.collector_config.clone(),
-        )
+        let mut websites = websites
+            .into_iter()
+            .skip(self.offset())
+            .take(self.initial_top_n())
+            .collect::<Vec<_>>();
+
+        for stage in &self.stages {
+            websites = stage.rank(websites);
+        }
+
+        websites.truncate(self.top_n);
+
+        websites
     }
 
     pub fn collector_top_n(&self) -> usize {
@@ -446,7 +472,7 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
     }
 
     pub fn initial_top_n(&self) -> usize {
-        self.stage.stage_top_n.max(self.top_n)
+        self.stage_top_n.max(self.top_n)
     }
 }
 
@@ -456,8 +482,8 @@ mod tests {
 
     use crate::ranking::models::cross_encoder::DummyCrossEncoder;
     use crate::{
-        collector::Hashes, inverted_index::DocAddress, prehashed::Prehashed,
-        ranking::initial::Score,
+        collector::Hashes, config::RankingPipelineConfig, inverted_index::DocAddress,
+        prehashed::Prehashed, ranking::initial::Score,
     };
 
     use super::*;
@@ -493,6 +519,7 @@ mod tests {
                     title: None,
                     snippet: None,
                     score: 1.0 / i as f64,
+                    model_score: None,
                 }
             })
             .collect()
@@ -504,6 +531,7 @@ mod tests {
             &mut SearchQuery {
                 ..Default::default()
             },
+            &RankingPipelineConfig::default().rerank,
             Some(Arc::new(DummyCrossEncoder {})),
             None,
             CollectorConfig::default(),
@@ -536,6 +564,7 @@ mod tests {
                 num_results,
                 ..Default::default()
             },
+            &RankingPipelineConfig::default().rerank,
             Some(Arc::new(DummyCrossEncoder {})),
             None,
             CollectorConfig::default(),
@@ -571,6 +600,7 @@ mod tests {
                 num_results,
                 ..Default::default()
             },
+            &RankingPipelineConfig::default().rerank,
             Some(Arc::new(DummyCrossEncoder {})),
             None,
             CollectorConfig::default(),
@@ -586,6 +616,7 @@ mod tests {
                     page: p,
                     ..Default::default()
                 },
+                &RankingPipelineConfig::default().rerank,
                 Some(Arc::new(DummyCrossEncoder {})),
                 None,
                 CollectorConfig::default(),
@@ -610,4 +641,54 @@ mod tests {
             prev = res;
         }
     }
+
+    #[test]
+    fn configured_stages() {
+        let num_results = 10;
+        let pipeline = |stages: &[RankingStageKind]| {
+            RankingPipeline::<RankingWebsite>::reranker::<DummyCrossEncoder>(
+                &mut SearchQuery {
+                    num_results,
+                    ..Default::default()
+                },
+                stages,
+                None,
+                None,
+                CollectorConfig::default(),
+                num_results,
+            )
+            .unwrap()
+        };
+
+        let mut sample = sample_websites(num_results);
+        for website in sample.iter_mut() {
+            website.score = 0.0;
+        }
+        sample.reverse();
+
+        let addresses = |websites: Vec<RankingWebsite>| {
+            websites
+                .into_iter()
+                .map(|w| w.pointer.address)
+                .collect_vec()
+        };
+
+        // without any stages the websites are returned in the order they are given
+        assert_eq!(
+            addresses(pipeline(&[]).apply(sample.clone())),
+            addresses(sample.clone())
+        );
+
+        let res = pipeline(&[RankingStageKind::Recall]).apply(sample.clone());
+        assert_eq!(addresses(res), addresses(sample_websites(num_results)));
+
+        // stages that need a model which is not loaded are skipped
+        assert_eq!(
+            addresses(
+                pipeline(&[RankingStageKind::LambdaMart, RankingStageKind::CrossEncoder])
+                    .apply(sample.clone())
+            ),
+            addresses(sample)
+        );
+    }
 }
@@ -0,0 +1,167 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Term proximity of the query in the body of a page.
+//!
+//! The score is based on the smallest window of the body that contains all the
+//! query terms. Pages where the terms occur close together are usually more relevant
+//! than pages where the same terms are scattered across unrelated parts of the text.
+
+use std::collections::BinaryHeap;
+
+use tantivy::postings::SegmentPostings;
+use tantivy::tokenizer::Tokenizer;
+use tantivy::{DocId, DocSet, Postings, Term};
+
+use crate::{schema::TextField, Result};
+
+const FIELD: TextField = TextField::CleanBody;
+
+/// Length of the smallest window that contains at least one position from each of the lists.
+/// The position lists must be sorted. Returns `None` if any of the lists are empty.
+pub fn min_window(positions: &[Vec<u32>]) -> Option<u32> {
+    if positions.is_empty() || positions.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+
+    // min-heap of the current position of each list
+    let mut heap: BinaryHeap<_> = positions
+        .iter()
+        .enumerate()
+        .map(|(list, p)| std::cmp::Reverse((p[0], list, 0)))
+        .collect();
+    let mut max = positions.iter().map(|p| p[0]).max().unwrap();
+    let mut best = u32::MAX;
+
+    while let Some(std::cmp::Reverse((min, list, idx))) = heap.pop() {
+        best = best.min(max - min + 1);
+
+        // the window can only get smaller by moving the start forward
+        let Some(next) = positions[list].get(idx + 1) else {
+            break;
+        };
+
+        max = max.max(*next);
+        heap.push(std::cmp::Reverse((*next, list, idx + 1)));
+    }
+
+    Some(best)
+}
+
+/// Score in `(0, 1]` where 1 means that the terms occur right next to each other.
+pub fn score(num_terms: usize, window: u32) -> f64 {
+    (num_terms as f64 / window as f64).min(1.0)
+}
+
+#[derive(Clone)]
+pub struct ProximityScorer {
+    postings: Vec<SegmentPostings>,
+    positions: Vec<Vec<u32>>,
+}
+
+impl ProximityScorer {
+    /// Prepare the scorer for a segment. Returns `None` if the query has less than
+    /// two distinct terms or if not all the terms occur in the segment.
+    pub fn for_segment(
+        searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+        terms: &[String],
+    ) -> Result<Option<Self>> {
+        let tv_field = searcher.schema().get_field(FIELD.name()).unwrap();
+        let inverted_index = segment_reader.inverted_index(tv_field)?;
+        let mut tokenizer = FIELD.indexing_tokenizer();
+
+        let mut tokens = Vec::new();
+        for term in terms {
+            let mut stream = tokenizer.token_stream(term);
+
+            while let Some(token) = stream.next() {
+                tokens.push(token.text.clone());
+            }
+        }
+
+        tokens.sort();
+        tokens.dedup();
+
+        if tokens.len() < 2 {
+            return Ok(None);
+        }
+
+        let mut postings = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match inverted_index.read_postings(
+                &Term::from_field_text(tv_field, token),
+                FIELD.index_option(),
+            )? {
+                Some(p) => postings.push(p),
+                // no document in the segment can contain all the terms
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(Self {
+            positions: vec![Vec::new(); postings.len()],
+            postings,
+        }))
+    }
+
+    /// Score the document. The documents must be scored in ascending order of their id.
+    pub fn score(&mut self, doc: DocId) -> f64 {
+        for (posting, positions) in self.postings.iter_mut().zip(&mut self.positions) {
+            if posting.doc() > doc || (posting.doc() < doc && posting.seek(doc) != doc) {
+                return 0.0;
+            }
+
+            posting.positions(positions);
+        }
+
+        match min_window(&self.positions) {
+            Some(window) => score(self.positions.len(), window),
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjacent_terms() {
+        assert_eq!(min_window(&[vec![3], vec![4]]), Some(2));
+        assert_eq!(score(2, 2), 1.0);
+    }
+
+    #[test]
+    fn smallest_window_is_found() {
+        let positions = vec![vec![0, 10, 50], vec![20, 48], vec![5, 52]];
+
+        // 48, 50, 52
+        assert_eq!(min_window(&positions), Some(5));
+        assert_eq!(score(3, 5), 0.6);
+    }
+
+    #[test]
+    fn terms_in_any_order() {
+        assert_eq!(min_window(&[vec![9], vec![1, 7]]), Some(3));
+    }
+
+    #[test]
+    fn missing_term() {
+        assert_eq!(min_window(&[vec![1, 2], vec![]]), None);
+        assert_eq!(min_window(&[]), None);
+    }
+}
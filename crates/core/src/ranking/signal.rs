@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::query::intent::Intent;
 use crate::query::optic::AsSearchableRule;
 use crate::query::Query;
 use crate::Result;
@@ -41,12 +42,20 @@ use tantivy::DocSet;
 use tantivy::{DocId, Postings};
 
 use crate::{
+    config::FreshnessConfig,
     schema::FLOAT_SCALING,
-    webpage::region::{Region, RegionCount},
+    webpage::{
+        language::{self, UNKNOWN_LANG_ID},
+        region::{RegionCount, RegionSet},
+    },
 };
 
 use super::bm25::Bm25Weight;
+use super::bm25f::Bm25FScorer;
+use super::exact_match::{ExactDomainNameScorer, ExactTitleScorer};
+use super::host_stats::{HostAggregate, HostAggregates};
 use super::models::linear::LinearRegression;
+use super::proximity::ProximityScorer;
 use super::{inbound_similarity, query_centrality};
 
 #[derive(Debug, Error)]
@@ -105,6 +114,10 @@ pub enum Signal {
     HostCentrality,
     #[serde(rename = "host_centrality_rank")]
     HostCentralityRank,
+    #[serde(rename = "host_trust")]
+    HostTrust,
+    #[serde(rename = "host_link_quality")]
+    HostLinkQuality,
     #[serde(rename = "page_centrality")]
     PageCentrality,
     #[serde(rename = "page_centrality_rank")]
@@ -131,6 +144,38 @@ pub enum Signal {
     UrlSlashes,
     #[serde(rename = "link_density")]
     LinkDensity,
+    #[serde(rename = "accessibility")]
+    Accessibility,
+    #[serde(rename = "freshness")]
+    Freshness,
+    #[serde(rename = "page_quality")]
+    PageQuality,
+    #[serde(rename = "bm25f")]
+    Bm25F,
+    #[serde(rename = "term_proximity")]
+    TermProximity,
+    #[serde(rename = "language_match")]
+    LanguageMatch,
+    #[serde(rename = "host_mean_page_centrality")]
+    HostMeanPageCentrality,
+    #[serde(rename = "host_age")]
+    HostAge,
+    #[serde(rename = "host_num_pages")]
+    HostNumPages,
+    #[serde(rename = "host_security")]
+    HostSecurity,
+    #[serde(rename = "title_exact_match")]
+    TitleExactMatch,
+    #[serde(rename = "domain_name_exact_match")]
+    DomainNameExactMatch,
+    #[serde(rename = "news_recency")]
+    NewsRecency,
+    #[serde(rename = "bm25_h1")]
+    Bm25H1,
+    #[serde(rename = "bm25_h2")]
+    Bm25H2,
+    #[serde(rename = "bm25_h3")]
+    Bm25H3,
 }
 
 impl From<Signal> for usize {
@@ -139,7 +184,7 @@ impl From<Signal> for usize {
     }
 }
 
-pub const ALL_SIGNALS: [Signal; 37] = [
+pub const ALL_SIGNALS: [Signal; 55] = [
     Signal::Bm25Title,
     Signal::Bm25TitleBigrams,
     Signal::Bm25TitleTrigrams,
@@ -164,6 +209,8 @@ pub const ALL_SIGNALS: [Signal; 37] = [
     Signal::CrossEncoderTitle,
     Signal::HostCentrality,
     Signal::HostCentralityRank,
+    Signal::HostTrust,
+    Signal::HostLinkQuality,
     Signal::PageCentrality,
     Signal::PageCentralityRank,
     Signal::IsHomepage,
@@ -177,6 +224,22 @@ pub const ALL_SIGNALS: [Signal; 37] = [
     Signal::UrlDigits,
     Signal::UrlSlashes,
     Signal::LinkDensity,
+    Signal::Accessibility,
+    Signal::Freshness,
+    Signal::PageQuality,
+    Signal::Bm25F,
+    Signal::TermProximity,
+    Signal::LanguageMatch,
+    Signal::HostMeanPageCentrality,
+    Signal::HostAge,
+    Signal::HostNumPages,
+    Signal::HostSecurity,
+    Signal::TitleExactMatch,
+    Signal::DomainNameExactMatch,
+    Signal::NewsRecency,
+    Signal::Bm25H1,
+    Signal::Bm25H2,
+    Signal::Bm25H3,
 ];
 
 fn score_timestamp(timestamp: usize, signal_aggregator: &SignalAggregator) -> f64 {
@@ -194,6 +257,29 @@ fn score_timestamp(timestamp: usize, signal_aggregator: &SignalAggregator) -> f6
     }
 }
 
+fn score_freshness(timestamp: usize, signal_aggregator: &SignalAggregator) -> f64 {
+    // pages without a timestamp have the timestamp 0
+    if timestamp == 0 {
+        return 0.0;
+    }
+
+    let current_timestamp = signal_aggregator.current_timestamp.unwrap_or(0);
+    let days_since_update = current_timestamp.saturating_sub(timestamp) as f64 / (24.0 * 3600.0);
+
+    signal_aggregator.freshness.score(days_since_update)
+}
+
+fn score_news_recency(timestamp: usize, signal_aggregator: &SignalAggregator) -> f64 {
+    if timestamp == 0 {
+        return 0.0;
+    }
+
+    let current_timestamp = signal_aggregator.current_timestamp.unwrap_or(0);
+    let days_since_update = current_timestamp.saturating_sub(timestamp) as f64 / (24.0 * 3600.0);
+
+    signal_aggregator.freshness.news_score(days_since_update)
+}
+
 #[inline]
 fn score_rank(rank: f64) -> f64 {
     1.0 / (rank + 1.0)
@@ -223,22 +309,43 @@ fn score_link_density(link_density: f64) -> f64 {
     }
 }
 
-fn score_region(webpage_region: Region, aggregator: &SignalAggregator) -> f64 {
+/// Pages in the language of the query get 1 and pages in other languages get 0.
+/// Pages where the language could not be detected are placed in between.
+fn score_language_match(query_lang: u64, webpage_lang: u64) -> f64 {
+    if webpage_lang == UNKNOWN_LANG_ID {
+        0.5
+    } else if webpage_lang == query_lang {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Age of the host in years, squashed into `[0, 1)`.
+fn score_host_age(first_seen: i64, signal_aggregator: &SignalAggregator) -> f64 {
+    let now = signal_aggregator.current_timestamp.unwrap_or(0) as i64;
+    let years = (now - first_seen).max(0) as f64 / (365.0 * 24.0 * 60.0 * 60.0);
+
+    years / (years + 1.0)
+}
+
+fn score_host_num_pages(num_pages: u64) -> f64 {
+    let log = (num_pages as f64).ln_1p();
+    log / (log + 1.0)
+}
+
+fn score_region(webpage_regions: RegionSet, aggregator: &SignalAggregator) -> f64 {
     match aggregator.region_count.as_ref() {
         Some(region_count) => {
-            let boost = aggregator
-                .query_data
-                .as_ref()
-                .and_then(|q| q.selected_region)
-                .map_or(0.0, |region| {
-                    if region != Region::All && region == webpage_region {
-                        50.0
-                    } else {
-                        0.0
-                    }
-                });
+            let boost = aggregator.query_data.as_ref().map_or(0.0, |q| {
+                if q.selected_regions.intersects(&webpage_regions) {
+                    50.0
+                } else {
+                    0.0
+                }
+            });
 
-            boost + region_count.score(&webpage_region)
+            boost + region_count.score_set(&webpage_regions)
         }
         None => 0.0,
     }
@@ -265,6 +372,15 @@ fn bm25(field: &mut TextFieldData, doc: DocId) -> f64 {
 }
 
 impl Signal {
+    /// Name of the signal as used in optics and ranking models.
+    /// It can be parsed back with [`Signal::from_str`].
+    pub fn name(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|name| name.as_str().map(|name| name.to_string()))
+            .unwrap_or_else(|| format!("{self:?}"))
+    }
+
     fn is_computable_before_search(&self) -> bool {
         self.as_fastfield().is_some()
     }
@@ -295,6 +411,8 @@ impl Signal {
             Signal::CrossEncoderTitle => 0.17,
             Signal::HostCentrality => 0.5,
             Signal::HostCentralityRank => 0.0,
+            Signal::HostTrust => 0.1,
+            Signal::HostLinkQuality => 0.1,
             Signal::PageCentrality => 0.25,
             Signal::PageCentralityRank => 0.0,
             Signal::QueryCentrality => 0.0,
@@ -308,6 +426,22 @@ impl Signal {
             Signal::UrlSlashes => 0.01,
             Signal::UrlDigits => 0.01,
             Signal::LinkDensity => 0.00,
+            Signal::Accessibility => 0.0,
+            Signal::Freshness => 0.0,
+            Signal::PageQuality => 0.0,
+            Signal::Bm25F => 0.0,
+            Signal::TermProximity => 0.0,
+            Signal::LanguageMatch => 0.1,
+            Signal::HostMeanPageCentrality => 0.1,
+            Signal::HostAge => 0.01,
+            Signal::HostNumPages => 0.01,
+            Signal::HostSecurity => 0.05,
+            Signal::TitleExactMatch => 0.05,
+            Signal::DomainNameExactMatch => 0.2,
+            Signal::NewsRecency => 0.3,
+            Signal::Bm25H1 => 0.004,
+            Signal::Bm25H2 => 0.002,
+            Signal::Bm25H3 => 0.001,
         }
     }
 
@@ -332,7 +466,7 @@ impl Signal {
         };
 
         let value: Option<f64> = match self {
-            Signal::HostCentrality | Signal::PageCentrality => {
+            Signal::HostCentrality | Signal::HostTrust | Signal::PageCentrality => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(val as f64 / FLOAT_SCALING as f64)
             }
@@ -340,6 +474,10 @@ impl Signal {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(score_rank(val as f64))
             }
+            Signal::HostLinkQuality => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+                Some(1.0 - val as f64 / FLOAT_SCALING as f64)
+            }
             Signal::IsHomepage => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(val as f64)
@@ -348,6 +486,10 @@ impl Signal {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(score_link_density(val as f64 / FLOAT_SCALING as f64))
             }
+            Signal::Accessibility | Signal::PageQuality => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+                Some(val as f64 / FLOAT_SCALING as f64)
+            }
             Signal::FetchTimeMs => {
                 let fetch_time_ms = fastfield_reader.get(&self.as_fastfield().unwrap()) as usize;
 
@@ -359,8 +501,28 @@ impl Signal {
             }
             Signal::UpdateTimestamp => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap()) as usize;
+                let confidence = fastfield_reader.get(&FastField::TimestampConfidence) as f64
+                    / FLOAT_SCALING as f64;
+
+                Some(score_timestamp(val, signal_aggregator) * confidence)
+            }
+            Signal::Freshness => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap()) as usize;
+                let confidence = fastfield_reader.get(&FastField::TimestampConfidence) as f64
+                    / FLOAT_SCALING as f64;
+
+                Some(score_freshness(val, signal_aggregator) * confidence)
+            }
+            Signal::NewsRecency => {
+                if !signal_aggregator.is_news_query() {
+                    return None;
+                }
 
-                Some(score_timestamp(val, signal_aggregator))
+                let val = fastfield_reader.get(&FastField::LastUpdated) as usize;
+                let confidence = fastfield_reader.get(&FastField::TimestampConfidence) as f64
+                    / FLOAT_SCALING as f64;
+
+                Some(score_news_recency(val, signal_aggregator) * confidence)
             }
             Signal::TrackerScore => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
@@ -376,15 +538,34 @@ impl Signal {
             }
             Signal::Region => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
-                let region = Region::from_id(val);
-                Some(score_region(region, signal_aggregator))
+                let regions = RegionSet::from_bits(val);
+                Some(score_region(regions, signal_aggregator))
             }
+            Signal::LanguageMatch => signal_aggregator
+                .query_data
+                .as_ref()
+                .and_then(|q| q.lang)
+                .map(|query_lang| {
+                    score_language_match(query_lang, fastfield_reader.get(&FastField::Language))
+                }),
             Signal::QueryCentrality => {
                 host_id.and_then(|host_id| signal_aggregator.query_centrality(host_id))
             }
             Signal::InboundSimilarity => {
                 host_id.map(|host_id| signal_aggregator.inbound_similarity(host_id))
             }
+            Signal::HostMeanPageCentrality => host_id
+                .and_then(|host_id| signal_aggregator.host_aggregate(host_id))
+                .map(|host| host.mean_page_centrality()),
+            Signal::HostAge => host_id
+                .and_then(|host_id| signal_aggregator.host_aggregate(host_id))
+                .map(|host| score_host_age(host.first_seen, signal_aggregator)),
+            Signal::HostNumPages => host_id
+                .and_then(|host_id| signal_aggregator.host_aggregate(host_id))
+                .map(|host| score_host_num_pages(host.num_pages)),
+            Signal::HostSecurity => host_id
+                .and_then(|host_id| signal_aggregator.host_aggregate(host_id))
+                .and_then(|host| host.secure_fraction()),
             Signal::Bm25Title
             | Signal::Bm25TitleBigrams
             | Signal::Bm25TitleTrigrams
@@ -404,10 +585,26 @@ impl Signal {
             | Signal::Bm25DomainNameIfHomepageNoTokenizer
             | Signal::Bm25DomainIfHomepageNoTokenizer
             | Signal::Bm25TitleIfHomepage
-            | Signal::Bm25BacklinkText => seg_reader
+            | Signal::Bm25BacklinkText
+            | Signal::Bm25H1
+            | Signal::Bm25H2
+            | Signal::Bm25H3 => seg_reader
                 .text_fields
                 .get_mut(self.as_textfield().unwrap())
                 .map(|field| bm25(field, doc)),
+            Signal::Bm25F => seg_reader.bm25f.as_mut().map(|scorer| scorer.score(doc)),
+            Signal::TermProximity => seg_reader
+                .proximity
+                .as_mut()
+                .map(|scorer| scorer.score(doc)),
+            Signal::TitleExactMatch => seg_reader
+                .exact_title
+                .as_mut()
+                .map(|scorer| scorer.score(doc)),
+            Signal::DomainNameExactMatch => seg_reader
+                .exact_domain_name
+                .as_mut()
+                .map(|scorer| scorer.score(doc)),
 
             Signal::CrossEncoderSnippet => None, // this is calculated in a later step
             Signal::CrossEncoderTitle => None,   // this is calculated in a later step
@@ -432,6 +629,8 @@ impl Signal {
         let value = match self {
             Signal::HostCentrality => Some(webpage.host_centrality),
             Signal::HostCentralityRank => Some(webpage.host_centrality_rank),
+            Signal::HostTrust => Some(webpage.host_trust),
+            Signal::HostLinkQuality => Some(1.0 - webpage.host_link_spam),
             Signal::PageCentrality => Some(webpage.page_centrality),
             Signal::PageCentralityRank => Some(webpage.page_centrality_rank),
             Signal::IsHomepage => Some(webpage.html.is_homepage().into()),
@@ -444,21 +643,21 @@ impl Signal {
                 }
             }
             Signal::UpdateTimestamp => {
-                let update_timestamp = webpage
-                    .html
-                    .updated_time()
+                let timestamps = webpage.html.timestamps();
+                let update_timestamp = timestamps
+                    .freshest()
                     .map(|date| date.timestamp().max(0))
                     .unwrap_or(0) as usize;
 
-                Some(score_timestamp(update_timestamp, signal_aggregator))
+                Some(score_timestamp(update_timestamp, signal_aggregator) * timestamps.confidence)
             }
             Signal::TrackerScore => {
                 let num_trackers = webpage.html.trackers().len() as f64;
                 Some(score_trackers(num_trackers))
             }
             Signal::Region => {
-                let region = Region::guess_from(webpage).unwrap_or(Region::All);
-                Some(score_region(region, signal_aggregator))
+                let regions = RegionSet::guess_from(webpage);
+                Some(score_region(regions, signal_aggregator))
             }
             Signal::UrlDigits => {
                 let num_digits = (webpage
@@ -492,6 +691,17 @@ impl Signal {
                 let link_density = webpage.html.link_density();
                 Some(score_link_density(link_density))
             }
+            Signal::Accessibility => Some(webpage.html.accessibility().score()),
+            Signal::PageQuality => Some(webpage.html.quality().score()),
+            Signal::Freshness => {
+                let timestamps = webpage.html.timestamps();
+                let update_timestamp = timestamps
+                    .freshest()
+                    .map(|date| date.timestamp().max(0))
+                    .unwrap_or(0) as usize;
+
+                Some(score_freshness(update_timestamp, signal_aggregator) * timestamps.confidence)
+            }
             Signal::Bm25Title
             | Signal::Bm25TitleBigrams
             | Signal::Bm25TitleTrigrams
@@ -512,11 +722,24 @@ impl Signal {
             | Signal::Bm25DomainIfHomepageNoTokenizer
             | Signal::Bm25TitleIfHomepage
             | Signal::Bm25BacklinkText
+            | Signal::Bm25H1
+            | Signal::Bm25H2
+            | Signal::Bm25H3
+            | Signal::Bm25F
+            | Signal::TermProximity
+            | Signal::LanguageMatch
             | Signal::CrossEncoderSnippet
             | Signal::CrossEncoderTitle
             | Signal::InboundSimilarity
             | Signal::LambdaMART
-            | Signal::QueryCentrality => {
+            | Signal::QueryCentrality
+            | Signal::HostMeanPageCentrality
+            | Signal::HostAge
+            | Signal::HostNumPages
+            | Signal::HostSecurity
+            | Signal::TitleExactMatch
+            | Signal::DomainNameExactMatch
+            | Signal::NewsRecency => {
                 tracing::error!("signal {self:?} cannot be precomputed");
                 None
             }
@@ -535,16 +758,21 @@ impl Signal {
         match self {
             Signal::HostCentrality => Some(FastField::HostCentrality),
             Signal::HostCentralityRank => Some(FastField::HostCentralityRank),
+            Signal::HostTrust => Some(FastField::HostTrust),
+            Signal::HostLinkQuality => Some(FastField::HostLinkSpam),
             Signal::PageCentrality => Some(FastField::PageCentrality),
             Signal::PageCentralityRank => Some(FastField::PageCentralityRank),
             Signal::IsHomepage => Some(FastField::IsHomepage),
             Signal::FetchTimeMs => Some(FastField::FetchTimeMs),
             Signal::UpdateTimestamp => Some(FastField::LastUpdated),
             Signal::TrackerScore => Some(FastField::TrackerScore),
-            Signal::Region => Some(FastField::Region),
+            Signal::Region => Some(FastField::Regions),
             Signal::UrlSlashes => Some(FastField::NumPathAndQuerySlashes),
             Signal::UrlDigits => Some(FastField::NumPathAndQueryDigits),
             Signal::LinkDensity => Some(FastField::LinkDensity),
+            Signal::Accessibility => Some(FastField::Accessibility),
+            Signal::PageQuality => Some(FastField::PageQuality),
+            Signal::Freshness => Some(FastField::LastUpdated),
             _ => None,
         }
     }
@@ -573,6 +801,9 @@ impl Signal {
             Signal::Bm25TitleIfHomepage => Some(TextField::TitleIfHomepage),
             Signal::Bm25BacklinkText => Some(TextField::BacklinkText),
             Signal::Bm25DomainIfHomepageNoTokenizer => Some(TextField::DomainIfHomepageNoTokenizer),
+            Signal::Bm25H1 => Some(TextField::H1),
+            Signal::Bm25H2 => Some(TextField::H2),
+            Signal::Bm25H3 => Some(TextField::H3),
             _ => None,
         }
     }
@@ -618,6 +849,24 @@ impl SignalCoefficient {
         }))
     }
 
+    /// Coefficients from multiple optics where later optics
+    /// take precedence if they set the same signal.
+    pub fn from_optics<'a>(optics: impl IntoIterator<Item = &'a Optic>) -> Self {
+        let mut res = Self::default();
+
+        for optic in optics {
+            let coeffs = Self::from_optic(optic);
+
+            for signal in ALL_SIGNALS {
+                if let Some(coeff) = coeffs.get(&signal) {
+                    res.map.insert(signal, coeff);
+                }
+            }
+        }
+
+        res
+    }
+
     pub fn merge_into(&mut self, coeffs: SignalCoefficient) {
         for signal in ALL_SIGNALS {
             if let Some(coeff) = coeffs.get(&signal) {
@@ -650,6 +899,10 @@ struct OpticBoosts {
 
 struct SegmentReader {
     text_fields: EnumMap<TextField, TextFieldData>,
+    bm25f: Option<Bm25FScorer>,
+    proximity: Option<ProximityScorer>,
+    exact_title: Option<ExactTitleScorer>,
+    exact_domain_name: Option<ExactDomainNameScorer>,
     optic_boosts: OpticBoosts,
     fastfield_reader: Arc<fastfield_reader::SegmentReader>,
 }
@@ -658,7 +911,11 @@ struct SegmentReader {
 struct QueryData {
     simple_terms: Vec<String>,
     optic_rules: Vec<optics::Rule>,
-    selected_region: Option<Region>,
+    /// Regions selected by the user. Pages in any of them are boosted.
+    selected_regions: RegionSet,
+    /// Id of the query language if it could be reliably detected.
+    lang: Option<u64>,
+    intent: Intent,
 }
 
 pub struct SignalAggregator {
@@ -668,8 +925,10 @@ pub struct SignalAggregator {
     inbound_similarity: Option<RefCell<inbound_similarity::Scorer>>,
     fetch_time_ms_cache: Vec<f64>,
     update_time_cache: Vec<f64>,
+    freshness: FreshnessConfig,
     query_centrality: Option<RefCell<query_centrality::Scorer>>,
     region_count: Option<Arc<RegionCount>>,
+    host_aggregates: Option<Arc<HostAggregates>>,
     current_timestamp: Option<usize>,
     linear_regression: Option<Arc<LinearRegression>>,
     order: SignalOrder,
@@ -694,8 +953,10 @@ impl Clone for SignalAggregator {
             inbound_similarity,
             fetch_time_ms_cache: self.fetch_time_ms_cache.clone(),
             update_time_cache: self.update_time_cache.clone(),
+            freshness: self.freshness,
             query_centrality,
             region_count: self.region_count.clone(),
+            host_aggregates: self.host_aggregates.clone(),
             current_timestamp: self.current_timestamp,
             linear_regression: self.linear_regression.clone(),
             order: self.order.clone(),
@@ -742,7 +1003,10 @@ impl SignalAggregator {
                 })
                 .cloned()
                 .collect(),
-            selected_region: q.region().cloned(),
+            selected_regions: q.region().copied().into_iter().collect(),
+            lang: language::detect_query(&q.simple_terms().join(" "))
+                .map(|lang| language::id(Some(&lang))),
+            intent: Intent::classify(q.simple_terms()),
         });
 
         let mut s = Self {
@@ -751,8 +1015,10 @@ impl SignalAggregator {
             query_signal_coefficients,
             fetch_time_ms_cache,
             update_time_cache,
+            freshness: FreshnessConfig::default(),
             query_centrality: None,
             region_count: None,
+            host_aggregates: None,
             current_timestamp: None,
             linear_regression: None,
             query_data: query,
@@ -827,6 +1093,76 @@ impl SignalAggregator {
         Ok(text_fields)
     }
 
+    fn prepare_bm25f(
+        &self,
+        tv_searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+    ) -> Result<Option<Bm25FScorer>> {
+        // the scorer reads the postings of every field, so only prepare it when it is used
+        if self.coefficient(&Signal::Bm25F) == 0.0 {
+            return Ok(None);
+        }
+
+        match &self.query_data {
+            Some(query) if !query.simple_terms.is_empty() => {
+                Bm25FScorer::for_segment(tv_searcher, segment_reader, &query.simple_terms)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn prepare_proximity(
+        &self,
+        tv_searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+    ) -> Result<Option<ProximityScorer>> {
+        // reading the positions of every matching document is expensive
+        if self.coefficient(&Signal::TermProximity) == 0.0 {
+            return Ok(None);
+        }
+
+        match &self.query_data {
+            Some(query) => {
+                ProximityScorer::for_segment(tv_searcher, segment_reader, &query.simple_terms)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn prepare_exact_title(
+        &self,
+        tv_searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+    ) -> Result<Option<ExactTitleScorer>> {
+        if self.coefficient(&Signal::TitleExactMatch) == 0.0 {
+            return Ok(None);
+        }
+
+        match &self.query_data {
+            Some(query) => {
+                ExactTitleScorer::for_segment(tv_searcher, segment_reader, &query.simple_terms)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn prepare_exact_domain_name(
+        &self,
+        tv_searcher: &tantivy::Searcher,
+        segment_reader: &tantivy::SegmentReader,
+    ) -> Result<Option<ExactDomainNameScorer>> {
+        if self.coefficient(&Signal::DomainNameExactMatch) == 0.0 {
+            return Ok(None);
+        }
+
+        match &self.query_data {
+            Some(query) => {
+                ExactDomainNameScorer::for_segment(tv_searcher, segment_reader, &query.simple_terms)
+            }
+            None => Ok(None),
+        }
+    }
+
     fn prepare_optic(
         &self,
         tv_searcher: &tantivy::Searcher,
@@ -866,10 +1202,18 @@ impl SignalAggregator {
     ) -> Result<()> {
         let fastfield_segment_reader = fastfield_reader.get_segment(&segment_reader.segment_id());
         let text_fields = self.prepare_textfields(tv_searcher, segment_reader)?;
+        let bm25f = self.prepare_bm25f(tv_searcher, segment_reader)?;
+        let proximity = self.prepare_proximity(tv_searcher, segment_reader)?;
+        let exact_title = self.prepare_exact_title(tv_searcher, segment_reader)?;
+        let exact_domain_name = self.prepare_exact_domain_name(tv_searcher, segment_reader)?;
         let optic_rule_boosts = self.prepare_optic(tv_searcher, segment_reader, fastfield_reader);
 
         self.segment_reader = Some(RefCell::new(SegmentReader {
             text_fields,
+            bm25f,
+            proximity,
+            exact_title,
+            exact_domain_name,
             fastfield_reader: fastfield_segment_reader,
             optic_boosts: OpticBoosts {
                 rules: optic_rule_boosts,
@@ -894,10 +1238,18 @@ impl SignalAggregator {
         self.region_count = Some(Arc::new(region_count));
     }
 
+    pub fn set_host_aggregates(&mut self, host_aggregates: Arc<HostAggregates>) {
+        self.host_aggregates = Some(host_aggregates);
+    }
+
     pub fn set_current_timestamp(&mut self, current_timestamp: usize) {
         self.current_timestamp = Some(current_timestamp);
     }
 
+    pub fn set_freshness(&mut self, freshness: FreshnessConfig) {
+        self.freshness = freshness;
+    }
+
     pub fn set_linear_model(&mut self, linear_model: Arc<LinearRegression>) {
         self.linear_regression = Some(linear_model);
     }
@@ -908,6 +1260,12 @@ impl SignalAggregator {
             .map(|scorer| scorer.borrow_mut().score(host_id))
     }
 
+    pub fn host_aggregate(&self, host_id: NodeID) -> Option<&HostAggregate> {
+        self.host_aggregates
+            .as_ref()
+            .and_then(|aggregates| aggregates.get(&host_id))
+    }
+
     pub fn inbound_similarity(&self, host_id: NodeID) -> f64 {
         self.inbound_similarity
             .as_ref()
@@ -962,6 +1320,13 @@ impl SignalAggregator {
             .sum()
     }
 
+    pub fn is_news_query(&self) -> bool {
+        self.query_data
+            .as_ref()
+            .map(|q| q.intent == Intent::News)
+            .unwrap_or(false)
+    }
+
     pub fn coefficient(&self, signal: &Signal) -> f64 {
         self.query_signal_coefficients
             .as_ref()
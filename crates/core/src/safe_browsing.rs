@@ -0,0 +1,410 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Safe-Browsing-style blocklists of malware and phishing urls.
+//!
+//! The lists are synced to a local folder with a `malware.txt` and a `phishing.txt` file.
+//! Each line of a file is the hex encoded sha256 hash of a url expression
+//! (e.g. `evil.com/` or `a.evil.com/path/page.html`). A url is looked up by
+//! hashing all its host-suffix/path-prefix expressions and checking them against
+//! the 4 byte hash prefixes before confirming the match on the full hash.
+
+use std::{collections::HashSet, net::IpAddr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    metrics::{Counter, Label, PrometheusRegistry},
+    ranking::models::reloadable::{Reloadable, ReloadableModel},
+};
+
+const MAX_HOST_SUFFIXES: usize = 5;
+const MAX_PATH_PREFIXES: usize = 6;
+
+/// Name of the recurring task that picks up newly synced lists.
+pub const SYNC_QUEUE: &str = "blocklist_sync";
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub static METRICS: Lazy<BlocklistMetrics> = Lazy::new(BlocklistMetrics::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreatType {
+    Malware,
+    Phishing,
+}
+
+impl ThreatType {
+    fn file_name(&self) -> &'static str {
+        match self {
+            ThreatType::Malware => "malware.txt",
+            ThreatType::Phishing => "phishing.txt",
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct BlocklistMetrics {
+    pub lookups: Counter,
+    pub malware_hits: Counter,
+    pub phishing_hits: Counter,
+}
+
+impl BlocklistMetrics {
+    pub fn register(&self, registry: &mut PrometheusRegistry) {
+        let group = registry
+            .new_group(
+                "stract_blocklist_lookups".to_string(),
+                Some(
+                    "Number of urls looked up in the malware and phishing blocklists.".to_string(),
+                ),
+            )
+            .unwrap();
+        group.register(self.lookups.clone(), vec![]);
+
+        let group = registry
+            .new_group(
+                "stract_blocklist_hits".to_string(),
+                Some("Number of urls found in the blocklists.".to_string()),
+            )
+            .unwrap();
+
+        group.register(
+            self.malware_hits.clone(),
+            vec![Label {
+                key: "threat".to_string(),
+                val: "malware".to_string(),
+            }],
+        );
+        group.register(
+            self.phishing_hits.clone(),
+            vec![Label {
+                key: "threat".to_string(),
+                val: "phishing".to_string(),
+            }],
+        );
+    }
+
+    fn record_hit(&self, threat: ThreatType) {
+        match threat {
+            ThreatType::Malware => self.malware_hits.inc(),
+            ThreatType::Phishing => self.phishing_hits.inc(),
+        }
+    }
+}
+
+type FullHash = [u8; 32];
+type HashPrefix = [u8; 4];
+
+fn sha256(expression: &str) -> FullHash {
+    let digest = ring::digest::digest(&ring::digest::SHA256, expression.as_bytes());
+
+    let mut res = [0; 32];
+    res.copy_from_slice(digest.as_ref());
+    res
+}
+
+fn prefix(hash: &FullHash) -> HashPrefix {
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn parse_hex(s: &str) -> Option<FullHash> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+
+    let mut res = [0; 32];
+
+    for (i, byte) in res.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(res)
+}
+
+/// All host-suffix/path-prefix combinations of the url
+/// as described in the Safe Browsing url hashing scheme.
+pub fn url_expressions(url: &Url) -> Vec<String> {
+    let host = match url.host_str() {
+        Some(host) => host.trim_end_matches('.').to_ascii_lowercase(),
+        None => return Vec::new(),
+    };
+
+    let mut hosts = vec![host.clone()];
+
+    if host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+        .is_err()
+    {
+        let components: Vec<_> = host.split('.').collect();
+        let start = components.len().saturating_sub(MAX_HOST_SUFFIXES);
+
+        for i in start.max(1)..components.len().saturating_sub(1) {
+            hosts.push(components[i..].join("."));
+        }
+    }
+
+    let path = url.path();
+    let mut paths = Vec::new();
+
+    if let Some(query) = url.query() {
+        paths.push(format!("{path}?{query}"));
+    }
+    paths.push(path.to_string());
+
+    paths.push("/".to_string());
+
+    for (i, _) in path.match_indices('/').skip(1).take(MAX_PATH_PREFIXES - 2) {
+        paths.push(path[..=i].to_string());
+    }
+
+    let mut res = Vec::new();
+    let mut seen = HashSet::new();
+
+    for host in &hosts {
+        for path in &paths {
+            let expression = format!("{host}{path}");
+
+            if seen.insert(expression.clone()) {
+                res.push(expression);
+            }
+        }
+    }
+
+    res
+}
+
+struct HashPrefixList {
+    prefixes: Vec<HashPrefix>,
+    full_hashes: HashSet<FullHash>,
+}
+
+impl HashPrefixList {
+    fn new(hashes: impl IntoIterator<Item = FullHash>) -> Self {
+        let full_hashes: HashSet<_> = hashes.into_iter().collect();
+
+        let mut prefixes: Vec<_> = full_hashes.iter().map(prefix).collect();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+
+        Self {
+            prefixes,
+            full_hashes,
+        }
+    }
+
+    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())
+            .map_err(|err| anyhow!("failed to read {}: {err}", path.as_ref().display()))?;
+
+        let mut hashes = Vec::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match parse_hex(line) {
+                Some(hash) => hashes.push(hash),
+                None => tracing::warn!("invalid hash in blocklist: {line}"),
+            }
+        }
+
+        Ok(Self::new(hashes))
+    }
+
+    fn contains(&self, hash: &FullHash) -> bool {
+        self.prefixes.binary_search(&prefix(hash)).is_ok() && self.full_hashes.contains(hash)
+    }
+
+    fn len(&self) -> usize {
+        self.full_hashes.len()
+    }
+}
+
+#[derive(Default)]
+pub struct Blocklist {
+    lists: Vec<(ThreatType, HashPrefixList)>,
+}
+
+impl Blocklist {
+    /// Open the synced lists in the folder. Lists that have not been
+    /// synced yet are treated as empty.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut lists = Vec::new();
+
+        for threat in [ThreatType::Malware, ThreatType::Phishing] {
+            let file = path.as_ref().join(threat.file_name());
+
+            if !file.exists() {
+                tracing::warn!("blocklist {} does not exist", file.display());
+                continue;
+            }
+
+            let list = HashPrefixList::open(file)?;
+            tracing::info!("loaded {} {:?} hashes", list.len(), threat);
+            lists.push((threat, list));
+        }
+
+        Ok(Self { lists })
+    }
+
+    /// Build a blocklist directly from url expressions (e.g. `evil.com/`).
+    #[cfg(test)]
+    pub fn from_expressions<'a>(
+        lists: impl IntoIterator<Item = (ThreatType, Vec<&'a str>)>,
+    ) -> Self {
+        Self {
+            lists: lists
+                .into_iter()
+                .map(|(threat, expressions)| {
+                    (
+                        threat,
+                        HashPrefixList::new(expressions.into_iter().map(sha256)),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.iter().all(|(_, list)| list.len() == 0)
+    }
+
+    pub fn lookup(&self, url: &Url) -> Option<ThreatType> {
+        if self.is_empty() {
+            return None;
+        }
+
+        METRICS.lookups.inc();
+
+        let threat = self.find(url)?;
+
+        METRICS.record_hit(threat);
+
+        Some(threat)
+    }
+
+    fn find(&self, url: &Url) -> Option<ThreatType> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let hashes: Vec<_> = url_expressions(url).iter().map(|e| sha256(e)).collect();
+
+        self.lists
+            .iter()
+            .find(|(_, list)| hashes.iter().any(|hash| list.contains(hash)))
+            .map(|(threat, _)| *threat)
+    }
+
+    /// Lookup all pages on the host. Unlike [`Blocklist::lookup`], this is not
+    /// recorded in the metrics as it is used offline (e.g. when planning crawls).
+    pub fn lookup_host(&self, host: &str) -> Option<ThreatType> {
+        Url::parse(&format!("http://{host}/"))
+            .ok()
+            .and_then(|url| self.find(&url))
+    }
+}
+
+/// Reload the blocklists whose lists have been synced since they were loaded.
+pub async fn sync(blocklists: Vec<Arc<Reloadable<Blocklist>>>) -> Result<()> {
+    for blocklist in blocklists {
+        let path = blocklist.path();
+
+        if tokio::task::spawn_blocking(move || blocklist.reload_if_modified()).await?? {
+            tracing::info!("reloaded blocklist {:?}", path);
+        }
+    }
+
+    Ok(())
+}
+
+impl ReloadableModel for Blocklist {
+    fn open_model(path: &Path) -> Result<Self> {
+        Self::open(path)
+    }
+
+    fn dry_run(&self) -> Result<()> {
+        // the hashes have already been validated when the lists were parsed.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expressions() {
+        let url = Url::parse("http://a.b.c/1/2.html?param=1").unwrap();
+
+        assert_eq!(
+            url_expressions(&url),
+            vec![
+                "a.b.c/1/2.html?param=1",
+                "a.b.c/1/2.html",
+                "a.b.c/",
+                "a.b.c/1/",
+                "b.c/1/2.html?param=1",
+                "b.c/1/2.html",
+                "b.c/",
+                "b.c/1/",
+            ]
+        );
+
+        let url = Url::parse("http://1.2.3.4/").unwrap();
+        assert_eq!(url_expressions(&url), vec!["1.2.3.4/"]);
+    }
+
+    #[test]
+    fn hex() {
+        let hash = sha256("evil.com/");
+        let hex: String = hash.iter().map(|b| format!("{b:02x}")).collect();
+
+        assert_eq!(parse_hex(&hex), Some(hash));
+        assert_eq!(parse_hex("abc"), None);
+    }
+
+    #[test]
+    fn lookup() {
+        let blocklist = Blocklist::from_expressions([
+            (ThreatType::Malware, vec!["evil.com/"]),
+            (ThreatType::Phishing, vec!["login.example.com/account/"]),
+        ]);
+
+        assert_eq!(
+            blocklist.lookup(&Url::parse("https://www.evil.com/download.exe").unwrap()),
+            Some(ThreatType::Malware)
+        );
+        assert_eq!(
+            blocklist.lookup(&Url::parse("https://login.example.com/account/reset").unwrap()),
+            Some(ThreatType::Phishing)
+        );
+        assert_eq!(
+            blocklist.lookup(&Url::parse("https://login.example.com/").unwrap()),
+            None
+        );
+        assert_eq!(blocklist.lookup_host("evil.com"), Some(ThreatType::Malware));
+        assert_eq!(blocklist.lookup_host("example.com"), None);
+    }
+}
@@ -61,6 +61,22 @@ pub enum TextField {
     SafetyClassification,
     InsertionTimestamp,
     RecipeFirstIngredientTagId,
+    /// dominant programming language of the code blocks on code-heavy pages
+    CodeLanguage,
+    CodeSnippet,
+    LanguageAlternates,
+    /// ISO 639-3 code of the detected language of the page
+    Language,
+    /// names of the authors from meta tags and schema.org
+    Author,
+    /// json of the open graph metadata of the page
+    OpenGraph,
+    /// text of the headings outside of navigation and other boilerplate
+    H1,
+    H2,
+    H3,
+    /// alt texts and figure captions of the images in the content
+    ImageText,
 }
 
 impl From<TextField> for usize {
@@ -103,6 +119,16 @@ impl TextField {
             TextField::SafetyClassification => 1,
             TextField::InsertionTimestamp => 1,
             TextField::RecipeFirstIngredientTagId => 1,
+            TextField::CodeLanguage => 1,
+            TextField::CodeSnippet => 1,
+            TextField::LanguageAlternates => 1,
+            TextField::Language => 1,
+            TextField::Author => 1,
+            TextField::OpenGraph => 1,
+            TextField::H1 => 1,
+            TextField::H2 => 1,
+            TextField::H3 => 1,
+            TextField::ImageText => 1,
         }
     }
 
@@ -141,6 +167,16 @@ impl TextField {
             TextField::SafetyClassification => TextField::SafetyClassification,
             TextField::InsertionTimestamp => TextField::InsertionTimestamp,
             TextField::RecipeFirstIngredientTagId => TextField::RecipeFirstIngredientTagId,
+            TextField::CodeLanguage => TextField::CodeLanguage,
+            TextField::CodeSnippet => TextField::CodeSnippet,
+            TextField::LanguageAlternates => TextField::LanguageAlternates,
+            TextField::Language => TextField::Language,
+            TextField::Author => TextField::Author,
+            TextField::OpenGraph => TextField::OpenGraph,
+            TextField::H1 => TextField::H1,
+            TextField::H2 => TextField::H2,
+            TextField::H3 => TextField::H3,
+            TextField::ImageText => TextField::ImageText,
         }
     }
 
@@ -187,6 +223,16 @@ impl TextField {
             TextField::SafetyClassification => Tokenizer::Identity(Identity {}),
             TextField::InsertionTimestamp => Tokenizer::Identity(Identity {}),
             TextField::RecipeFirstIngredientTagId => Tokenizer::Identity(Identity {}),
+            TextField::CodeLanguage => Tokenizer::Identity(Identity {}),
+            TextField::CodeSnippet => Tokenizer::Identity(Identity {}),
+            TextField::LanguageAlternates => Tokenizer::Identity(Identity {}),
+            TextField::Language => Tokenizer::Identity(Identity {}),
+            TextField::Author => Tokenizer::default(),
+            TextField::OpenGraph => Tokenizer::Identity(Identity {}),
+            TextField::H1 => Tokenizer::default(),
+            TextField::H2 => Tokenizer::default(),
+            TextField::H3 => Tokenizer::default(),
+            TextField::ImageText => Tokenizer::default(),
         }
     }
 
@@ -231,6 +277,16 @@ impl TextField {
             TextField::SafetyClassification => false,
             TextField::InsertionTimestamp => false,
             TextField::RecipeFirstIngredientTagId => false,
+            TextField::CodeLanguage => false,
+            TextField::CodeSnippet => false,
+            TextField::LanguageAlternates => false,
+            TextField::Language => false,
+            TextField::Author => true,
+            TextField::OpenGraph => false,
+            TextField::H1 => true,
+            TextField::H2 => true,
+            TextField::H3 => true,
+            TextField::ImageText => true,
         }
     }
 
@@ -267,6 +323,16 @@ impl TextField {
             TextField::SafetyClassification => "safety_classification",
             TextField::InsertionTimestamp => "insertion_timestamp",
             TextField::RecipeFirstIngredientTagId => "recipe_first_ingredient_tag_id",
+            TextField::CodeLanguage => "code_language",
+            TextField::CodeSnippet => "code_snippet",
+            TextField::LanguageAlternates => "language_alternates",
+            TextField::Language => "language_code",
+            TextField::Author => "author",
+            TextField::OpenGraph => "open_graph",
+            TextField::H1 => "h1",
+            TextField::H2 => "h2",
+            TextField::H3 => "h3",
+            TextField::ImageText => "image_text",
         }
     }
 }
@@ -276,12 +342,17 @@ pub enum FastField {
     IsHomepage,
     HostCentrality,
     HostCentralityRank,
+    HostTrust,
+    HostLinkSpam,
     PageCentrality,
     PageCentralityRank,
     FetchTimeMs,
+    TlsStatus,
     LastUpdated,
+    PublishedTime,
+    TimestampConfidence,
     TrackerScore,
-    Region,
+    Regions,
     NumUrlTokens,
     NumTitleTokens,
     NumCleanBodyTokens,
@@ -310,6 +381,9 @@ pub enum FastField {
     LikelyHasAds,
     LikelyHasPaywall,
     LinkDensity,
+    Accessibility,
+    PageQuality,
+    Language,
 }
 
 impl FastField {
@@ -317,13 +391,18 @@ impl FastField {
         match self {
             FastField::HostCentrality => "host_centrality",
             FastField::HostCentralityRank => "host_centrality_rank",
+            FastField::HostTrust => "host_trust",
+            FastField::HostLinkSpam => "host_link_spam",
             FastField::PageCentrality => "page_centrality",
             FastField::PageCentralityRank => "page_centrality_rank",
             FastField::IsHomepage => "is_homepage",
             FastField::FetchTimeMs => "fetch_time_ms",
+            FastField::TlsStatus => "tls_status",
             FastField::LastUpdated => "last_updated",
+            FastField::PublishedTime => "published_time",
+            FastField::TimestampConfidence => "timestamp_confidence",
             FastField::TrackerScore => "tracker_score",
-            FastField::Region => "region",
+            FastField::Regions => "regions",
             FastField::NumUrlTokens => "num_url_tokens",
             FastField::NumTitleTokens => "num_title_tokens",
             FastField::NumCleanBodyTokens => "num_clean_body_tokens",
@@ -352,6 +431,9 @@ impl FastField {
             FastField::LikelyHasAds => "likely_has_ads",
             FastField::LikelyHasPaywall => "likely_has_paywall",
             FastField::LinkDensity => "link_density",
+            FastField::Accessibility => "accessibility",
+            FastField::PageQuality => "page_quality",
+            FastField::Language => "language",
         }
     }
 }
@@ -368,7 +450,7 @@ pub enum Field {
     Text(TextField),
 }
 
-static ALL_FIELDS: [Field; 66] = [
+static ALL_FIELDS: [Field; 84] = [
     Field::Text(TextField::Title),
     Field::Text(TextField::CleanBody),
     Field::Text(TextField::StemmedTitle),
@@ -399,16 +481,31 @@ static ALL_FIELDS: [Field; 66] = [
     Field::Text(TextField::MicroformatTags),
     Field::Text(TextField::SafetyClassification),
     Field::Text(TextField::InsertionTimestamp),
+    Field::Text(TextField::CodeLanguage),
+    Field::Text(TextField::CodeSnippet),
+    Field::Text(TextField::LanguageAlternates),
+    Field::Text(TextField::Language),
+    Field::Text(TextField::Author),
+    Field::Text(TextField::OpenGraph),
+    Field::Text(TextField::H1),
+    Field::Text(TextField::H2),
+    Field::Text(TextField::H3),
+    Field::Text(TextField::ImageText),
     // FAST FIELDS
     Field::Fast(FastField::IsHomepage),
     Field::Fast(FastField::HostCentrality),
     Field::Fast(FastField::HostCentralityRank),
+    Field::Fast(FastField::HostTrust),
+    Field::Fast(FastField::HostLinkSpam),
     Field::Fast(FastField::PageCentrality),
     Field::Fast(FastField::PageCentralityRank),
     Field::Fast(FastField::FetchTimeMs),
+    Field::Fast(FastField::TlsStatus),
     Field::Fast(FastField::LastUpdated),
+    Field::Fast(FastField::PublishedTime),
+    Field::Fast(FastField::TimestampConfidence),
     Field::Fast(FastField::TrackerScore),
-    Field::Fast(FastField::Region),
+    Field::Fast(FastField::Regions),
     Field::Fast(FastField::NumUrlTokens),
     Field::Fast(FastField::NumTitleTokens),
     Field::Fast(FastField::NumCleanBodyTokens),
@@ -436,6 +533,9 @@ static ALL_FIELDS: [Field; 66] = [
     Field::Fast(FastField::NumPathAndQueryDigits),
     Field::Fast(FastField::LikelyHasAds),
     Field::Fast(FastField::LikelyHasPaywall),
+    Field::Fast(FastField::Accessibility),
+    Field::Fast(FastField::PageQuality),
+    Field::Fast(FastField::Language),
 ];
 
 impl Field {
@@ -552,6 +652,24 @@ impl Field {
             Field::Text(TextField::RecipeFirstIngredientTagId) => {
                 IndexingOption::Text(self.default_text_options().set_stored())
             }
+            Field::Text(TextField::CodeLanguage) => {
+                IndexingOption::Text(self.default_text_options().set_stored())
+            }
+            Field::Text(TextField::CodeSnippet) => {
+                IndexingOption::Text(TextOptions::default().set_stored())
+            }
+            Field::Text(TextField::LanguageAlternates) => {
+                IndexingOption::Text(TextOptions::default().set_stored())
+            }
+            Field::Text(TextField::Language) => IndexingOption::Text(self.default_text_options()),
+            Field::Text(TextField::Author) => IndexingOption::Text(self.default_text_options()),
+            Field::Text(TextField::OpenGraph) => {
+                IndexingOption::Text(TextOptions::default().set_stored())
+            }
+            Field::Text(TextField::H1) => IndexingOption::Text(self.default_text_options()),
+            Field::Text(TextField::H2) => IndexingOption::Text(self.default_text_options()),
+            Field::Text(TextField::H3) => IndexingOption::Text(self.default_text_options()),
+            Field::Text(TextField::ImageText) => IndexingOption::Text(self.default_text_options()),
             Field::Text(TextField::InsertionTimestamp) => {
                 IndexingOption::DateTime(tantivy::schema::DateOptions::default().set_indexed())
             }
@@ -564,6 +682,12 @@ impl Field {
             Field::Fast(FastField::HostCentralityRank) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
+            Field::Fast(FastField::HostTrust) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::HostLinkSpam) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
             Field::Fast(FastField::PageCentrality) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
@@ -573,6 +697,12 @@ impl Field {
             Field::Fast(FastField::FetchTimeMs) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
+            Field::Fast(FastField::TlsStatus) => IndexingOption::Integer(
+                NumericOptions::default()
+                    .set_fast()
+                    .set_indexed()
+                    .set_stored(),
+            ),
             Field::Fast(FastField::TrackerScore) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
@@ -582,7 +712,16 @@ impl Field {
                     .set_stored()
                     .set_indexed(),
             ),
-            Field::Fast(FastField::Region) => IndexingOption::Integer(
+            Field::Fast(FastField::PublishedTime) => IndexingOption::Integer(
+                NumericOptions::default()
+                    .set_fast()
+                    .set_stored()
+                    .set_indexed(),
+            ),
+            Field::Fast(FastField::TimestampConfidence) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::Regions) => IndexingOption::Integer(
                 NumericOptions::default()
                     .set_fast()
                     .set_stored()
@@ -693,6 +832,18 @@ impl Field {
             Field::Fast(FastField::LinkDensity) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
             }
+            Field::Fast(FastField::Accessibility) => IndexingOption::Integer(
+                NumericOptions::default()
+                    .set_fast()
+                    .set_indexed()
+                    .set_stored(),
+            ),
+            Field::Fast(FastField::PageQuality) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
+            Field::Fast(FastField::Language) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_stored())
+            }
         }
     }
 
@@ -728,6 +879,15 @@ impl Field {
                 | Field::Text(TextField::Domain) // will match url
                 | Field::Text(TextField::InsertionTimestamp)
                 | Field::Text(TextField::RecipeFirstIngredientTagId)
+                | Field::Text(TextField::CodeLanguage)
+                | Field::Text(TextField::CodeSnippet)
+                | Field::Text(TextField::LanguageAlternates)
+                | Field::Text(TextField::Language)
+                | Field::Text(TextField::Author)
+                | Field::Text(TextField::OpenGraph)
+                | Field::Text(TextField::H1)
+                | Field::Text(TextField::H2)
+                | Field::Text(TextField::H3)
         ) && !self.is_fast()
     }
 
@@ -780,12 +940,17 @@ impl FastField {
             FastField::IsHomepage => DataType::U64,
             FastField::HostCentrality => DataType::U64,
             FastField::HostCentralityRank => DataType::U64,
+            FastField::HostTrust => DataType::U64,
+            FastField::HostLinkSpam => DataType::U64,
             FastField::PageCentrality => DataType::U64,
             FastField::PageCentralityRank => DataType::U64,
             FastField::FetchTimeMs => DataType::U64,
+            FastField::TlsStatus => DataType::U64,
             FastField::LastUpdated => DataType::U64,
+            FastField::PublishedTime => DataType::U64,
+            FastField::TimestampConfidence => DataType::U64,
             FastField::TrackerScore => DataType::U64,
-            FastField::Region => DataType::U64,
+            FastField::Regions => DataType::U64,
             FastField::NumUrlTokens => DataType::U64,
             FastField::NumTitleTokens => DataType::U64,
             FastField::NumMicroformatTagsTokens => DataType::U64,
@@ -814,6 +979,9 @@ impl FastField {
             FastField::LikelyHasAds => DataType::U64,
             FastField::LikelyHasPaywall => DataType::U64,
             FastField::LinkDensity => DataType::U64,
+            FastField::Accessibility => DataType::U64,
+            FastField::PageQuality => DataType::U64,
+            FastField::Language => DataType::U64,
         }
     }
 }
@@ -0,0 +1,501 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Search-time annotations of results from external data sources (blocklists,
+//! fact-check databases etc.). Providers are queried concurrently for all the results
+//! on a page and their answers are cached per url. A slow or failing provider
+//! never fails the search, its annotations are simply left out.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{AnnotationProviderConfig, AnnotationsConfig},
+    ranking::models::reloadable::Reloadable,
+    safe_browsing::{Blocklist, ThreatType},
+    ttl_cache::TTLCache,
+    webpage::url_ext::UrlExt,
+};
+
+use super::DisplayedWebpage;
+
+const MAX_CACHED_URLS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum Annotation {
+    Malware {
+        source: String,
+    },
+    Phishing {
+        source: String,
+    },
+    FactCheck {
+        publisher: String,
+        rating: String,
+        url: String,
+    },
+    Notice {
+        text: String,
+        source: String,
+    },
+}
+
+impl Annotation {
+    /// Whether the page is considered harmful to visit.
+    pub fn is_dangerous(&self) -> bool {
+        matches!(
+            self,
+            Annotation::Malware { .. } | Annotation::Phishing { .. }
+        )
+    }
+}
+
+/// A source of annotations. Implement this to decorate
+/// search results with data from an external service.
+pub trait AnnotationProvider: Send + Sync {
+    /// Annotations for each of the urls. The result must have the same length as `urls`.
+    fn annotate<'a>(&'a self, urls: &'a [Url]) -> BoxFuture<'a, Result<Vec<Vec<Annotation>>>>;
+}
+
+struct CachedProvider {
+    provider: Box<dyn AnnotationProvider>,
+    cache: Mutex<TTLCache<String, Vec<Annotation>>>,
+}
+
+impl CachedProvider {
+    async fn annotate(&self, urls: &[Url], timeout: Duration) -> Vec<Vec<Annotation>> {
+        let mut res = vec![Vec::new(); urls.len()];
+        let mut missing = Vec::new();
+
+        {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+
+            for (i, url) in urls.iter().enumerate() {
+                match cache.get(&url.to_string()) {
+                    Some(annotations) => res[i] = annotations.clone(),
+                    None => missing.push(i),
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            return res;
+        }
+
+        let missing_urls: Vec<_> = missing.iter().map(|i| urls[*i].clone()).collect();
+
+        match tokio::time::timeout(timeout, self.provider.annotate(&missing_urls)).await {
+            Ok(Ok(annotations)) if annotations.len() == missing_urls.len() => {
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+
+                for (i, annotations) in missing.into_iter().zip(annotations) {
+                    cache.insert(urls[i].to_string(), annotations.clone());
+                    res[i] = annotations;
+                }
+            }
+            Ok(Ok(annotations)) => tracing::warn!(
+                "annotation provider returned {} results for {} urls",
+                annotations.len(),
+                missing_urls.len()
+            ),
+            Ok(Err(err)) => tracing::warn!("annotation provider failed: {err}"),
+            Err(_) => tracing::warn!("annotation provider timed out"),
+        }
+
+        res
+    }
+}
+
+pub struct Annotator {
+    providers: Vec<CachedProvider>,
+    blocklists: Vec<Arc<Reloadable<Blocklist>>>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    filter_dangerous: bool,
+}
+
+impl Default for Annotator {
+    fn default() -> Self {
+        Self::new(&AnnotationsConfig::default()).unwrap()
+    }
+}
+
+impl Annotator {
+    pub fn new(config: &AnnotationsConfig) -> Result<Self> {
+        let mut annotator = Self {
+            providers: Vec::new(),
+            blocklists: Vec::new(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            cache_ttl: Duration::from_secs(config.cache_ttl_sec),
+            filter_dangerous: config.filter_dangerous,
+        };
+
+        for provider in &config.providers {
+            let provider: Box<dyn AnnotationProvider> = match provider {
+                AnnotationProviderConfig::Http { url } => Box::new(HttpProvider::new(url.clone())),
+                AnnotationProviderConfig::File { path } => Box::new(FileProvider::open(path)?),
+                AnnotationProviderConfig::Blocklist { path } => {
+                    let blocklist = Arc::new(Reloadable::open(path)?);
+                    annotator.blocklists.push(Arc::clone(&blocklist));
+
+                    Box::new(BlocklistProvider::new(blocklist))
+                }
+            };
+
+            annotator = annotator.with_provider(provider);
+        }
+
+        Ok(annotator)
+    }
+
+    /// The blocklists of the configured providers. They are reloaded when the synced lists change.
+    pub fn blocklists(&self) -> &[Arc<Reloadable<Blocklist>>] {
+        &self.blocklists
+    }
+
+    pub fn with_provider(mut self, provider: Box<dyn AnnotationProvider>) -> Self {
+        self.providers.push(CachedProvider {
+            provider,
+            cache: Mutex::new(TTLCache::with_ttl_and_max_size(
+                self.cache_ttl,
+                Some(MAX_CACHED_URLS),
+            )),
+        });
+
+        self
+    }
+
+    /// Attach the annotations from all providers to the webpages.
+    pub async fn annotate(&self, webpages: &mut [DisplayedWebpage]) {
+        if self.providers.is_empty() || webpages.is_empty() {
+            return;
+        }
+
+        let (idx, urls): (Vec<_>, Vec<_>) = webpages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, webpage)| Url::parse(&webpage.url).ok().map(|url| (i, url)))
+            .unzip();
+
+        let results = futures::future::join_all(
+            self.providers
+                .iter()
+                .map(|provider| provider.annotate(&urls, self.timeout)),
+        )
+        .await;
+
+        for annotations in results {
+            for (i, annotations) in idx.iter().zip(annotations) {
+                for annotation in annotations {
+                    if !webpages[*i].annotations.contains(&annotation) {
+                        webpages[*i].annotations.push(annotation);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the url is on one of the blocklists and dangerous results are filtered.
+    pub fn is_filtered(&self, url: &Url) -> bool {
+        self.filter_dangerous
+            && self
+                .blocklists
+                .iter()
+                .filter_map(|blocklist| blocklist.get())
+                .any(|blocklist| blocklist.lookup(url).is_some())
+    }
+
+    /// Remove dangerous results if the annotator is configured to filter them.
+    pub fn filter(&self, webpages: &mut Vec<DisplayedWebpage>) {
+        if self.filter_dangerous {
+            webpages.retain(|webpage| !webpage.annotations.iter().any(Annotation::is_dangerous));
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HttpRequest<'a> {
+    urls: &'a [Url],
+}
+
+#[derive(Deserialize)]
+struct HttpResponse {
+    annotations: Vec<Vec<Annotation>>,
+}
+
+/// Provider backed by a http service. The urls are posted as `{"urls": [...]}`
+/// and the service responds with `{"annotations": [[...], ...]}` in the same order.
+pub struct HttpProvider {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpProvider {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap(),
+            url,
+        }
+    }
+
+    async fn fetch(&self, urls: &[Url]) -> Result<Vec<Vec<Annotation>>> {
+        let res: HttpResponse = self
+            .client
+            .post(&self.url)
+            .json(&HttpRequest { urls })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(res.annotations)
+    }
+}
+
+impl AnnotationProvider for HttpProvider {
+    fn annotate<'a>(&'a self, urls: &'a [Url]) -> BoxFuture<'a, Result<Vec<Vec<Annotation>>>> {
+        self.fetch(urls).boxed()
+    }
+}
+
+/// Provider backed by a locally synced json file with annotations
+/// keyed by exact url or by host. Host annotations also apply to all subdomains
+/// of the host.
+#[derive(Debug, Default, Deserialize)]
+pub struct FileProvider {
+    #[serde(default)]
+    urls: HashMap<String, Vec<Annotation>>,
+    #[serde(default)]
+    hosts: HashMap<String, Vec<Annotation>>,
+}
+
+impl FileProvider {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path.as_ref()).map_err(|err| {
+            anyhow!(
+                "failed to open annotations file {}: {err}",
+                path.as_ref().display()
+            )
+        })?;
+
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    fn lookup(&self, url: &Url) -> Vec<Annotation> {
+        let mut res = self.urls.get(url.as_str()).cloned().unwrap_or_default();
+
+        let mut host = url.normalized_host().unwrap_or_default();
+
+        loop {
+            if let Some(annotations) = self.hosts.get(host) {
+                res.extend(annotations.iter().cloned());
+            }
+
+            match host.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => host = parent,
+                _ => break,
+            }
+        }
+
+        res
+    }
+}
+
+impl AnnotationProvider for FileProvider {
+    fn annotate<'a>(&'a self, urls: &'a [Url]) -> BoxFuture<'a, Result<Vec<Vec<Annotation>>>> {
+        let res = urls.iter().map(|url| self.lookup(url)).collect();
+        async move { Ok(res) }.boxed()
+    }
+}
+
+/// Provider backed by the locally synced malware and phishing hash lists.
+pub struct BlocklistProvider {
+    blocklist: Arc<Reloadable<Blocklist>>,
+}
+
+impl BlocklistProvider {
+    pub fn new(blocklist: Arc<Reloadable<Blocklist>>) -> Self {
+        Self { blocklist }
+    }
+}
+
+impl AnnotationProvider for BlocklistProvider {
+    fn annotate<'a>(&'a self, urls: &'a [Url]) -> BoxFuture<'a, Result<Vec<Vec<Annotation>>>> {
+        let blocklist = self.blocklist.get();
+        let res = urls
+            .iter()
+            .map(|url| {
+                let source = "blocklist".to_string();
+
+                match blocklist
+                    .as_ref()
+                    .and_then(|blocklist| blocklist.lookup(url))
+                {
+                    Some(ThreatType::Malware) => vec![Annotation::Malware { source }],
+                    Some(ThreatType::Phishing) => vec![Annotation::Phishing { source }],
+                    None => Vec::new(),
+                }
+            })
+            .collect();
+
+        async move { Ok(res) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{inverted_index::RetrievedWebpage, search_prettifier::Prettifier};
+
+    use super::*;
+
+    fn webpage(url: &str) -> DisplayedWebpage {
+        Prettifier::default().prettify(RetrievedWebpage {
+            title: "title".to_string(),
+            url: url.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn malware() -> Annotation {
+        Annotation::Malware {
+            source: "test".to_string(),
+        }
+    }
+
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>,
+        inner: FileProvider,
+    }
+
+    impl AnnotationProvider for CountingProvider {
+        fn annotate<'a>(&'a self, urls: &'a [Url]) -> BoxFuture<'a, Result<Vec<Vec<Annotation>>>> {
+            self.calls.fetch_add(urls.len(), Ordering::SeqCst);
+            self.inner.annotate(urls)
+        }
+    }
+
+    struct SlowProvider;
+
+    impl AnnotationProvider for SlowProvider {
+        fn annotate<'a>(&'a self, urls: &'a [Url]) -> BoxFuture<'a, Result<Vec<Vec<Annotation>>>> {
+            async move {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(vec![vec![malware()]; urls.len()])
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn file_provider_matches_subdomains() {
+        let provider = FileProvider {
+            hosts: [("bad.com".to_string(), vec![malware()])].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            provider.lookup(&Url::parse("https://a.b.bad.com/page").unwrap()),
+            vec![malware()]
+        );
+        assert!(provider
+            .lookup(&Url::parse("https://notbad.com").unwrap())
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn annotations_are_attached_and_cached() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let annotator = Annotator::default().with_provider(Box::new(CountingProvider {
+            calls: Arc::clone(&calls),
+            inner: FileProvider {
+                hosts: [("bad.com".to_string(), vec![malware()])].into(),
+                ..Default::default()
+            },
+        }));
+
+        let mut webpages = vec![webpage("https://bad.com/a"), webpage("https://good.com")];
+        annotator.annotate(&mut webpages).await;
+
+        assert_eq!(webpages[0].annotations, vec![malware()]);
+        assert!(webpages[1].annotations.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let mut webpages = vec![webpage("https://bad.com/a"), webpage("https://bad.com/b")];
+        annotator.annotate(&mut webpages).await;
+
+        assert_eq!(webpages[1].annotations, vec![malware()]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn slow_provider_is_skipped() {
+        let annotator = Annotator::new(&AnnotationsConfig {
+            timeout_ms: 10,
+            ..Default::default()
+        })
+        .unwrap()
+        .with_provider(Box::new(SlowProvider));
+
+        let mut webpages = vec![webpage("https://example.com")];
+        annotator.annotate(&mut webpages).await;
+
+        assert!(webpages[0].annotations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn blocklisted_results_are_filtered() {
+        let blocklist = Blocklist::from_expressions([(ThreatType::Phishing, vec!["bad.com/"])]);
+
+        let annotator = Annotator::new(&AnnotationsConfig {
+            filter_dangerous: true,
+            ..Default::default()
+        })
+        .unwrap()
+        .with_provider(Box::new(BlocklistProvider::new(Arc::new(Reloadable::new(
+            blocklist,
+        )))));
+
+        let mut webpages = vec![
+            webpage("https://www.bad.com/login"),
+            webpage("https://good.com"),
+        ];
+        annotator.annotate(&mut webpages).await;
+        annotator.filter(&mut webpages);
+
+        assert_eq!(webpages.len(), 1);
+        assert_eq!(webpages[0].url, "https://good.com");
+    }
+}
@@ -0,0 +1,177 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A short trail like `docs.rs › tokio › net` that tells the user where
+//! in the site a result is. The trail is only shown for sites that provide
+//! a `BreadcrumbList` in the structured data of the page, as a trail guessed
+//! from the url would hide the url itself.
+
+use url::Url;
+
+use crate::webpage::schema_org::Item;
+
+use super::rich_result::{item_prop, string_prop};
+
+/// Crumbs after these are left out, so the trail fits on a single line.
+const MAX_CRUMBS: usize = 4;
+
+/// The breadcrumb for the page, if it has one in its structured data.
+/// The first crumb is always the site.
+pub fn breadcrumb(site: &str, title: &str, schema_org: &[Item]) -> Option<Vec<String>> {
+    let crumbs = schema_org
+        .iter()
+        .find_map(|item| from_schema_org(item, title))?;
+
+    Some(
+        std::iter::once(site.to_string())
+            .chain(crumbs.into_iter().take(MAX_CRUMBS))
+            .collect(),
+    )
+}
+
+fn from_schema_org(item: &Item, title: &str) -> Option<Vec<String>> {
+    if item.types_contains("BreadcrumbList") {
+        let crumbs = list_names(item, title);
+        return (!crumbs.is_empty()).then_some(crumbs);
+    }
+
+    // the list is often nested in the WebPage or in a JSON-LD graph
+    ["breadcrumb", "@graph"]
+        .iter()
+        .filter_map(|key| item.properties.get(*key).cloned())
+        .flat_map(|prop| prop.many())
+        .filter_map(|prop| prop.try_into_item())
+        .find_map(|item| from_schema_org(&item, title))
+}
+
+fn list_names(list: &Item, title: &str) -> Vec<String> {
+    let mut elements: Vec<_> = list
+        .properties
+        .get("itemListElement")
+        .cloned()
+        .map(|elements| elements.many())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|element| element.try_into_item())
+        .enumerate()
+        .map(|(i, element)| {
+            let position = string_prop(&element, "position")
+                .and_then(|position| position.parse::<usize>().ok())
+                .unwrap_or(i);
+
+            (position, element)
+        })
+        .collect();
+
+    elements.sort_by_key(|(position, _)| *position);
+
+    elements
+        .into_iter()
+        .filter(|(_, element)| !links_to_root(element))
+        .filter_map(|(_, element)| {
+            string_prop(&element, "name")
+                .or_else(|| item_prop(&element, "item").and_then(|i| string_prop(&i, "name")))
+        })
+        .map(|name| name.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|name| !name.eq_ignore_ascii_case("home") && name != title)
+        .collect()
+}
+
+/// The first crumb usually links to the front page, which is already shown as the site.
+fn links_to_root(element: &Item) -> bool {
+    let url = string_prop(element, "item").or_else(|| {
+        item_prop(element, "item")
+            .and_then(|item| string_prop(&item, "@id").or_else(|| string_prop(&item, "url")))
+    });
+
+    url.and_then(|url| Url::parse(&url).ok())
+        .map(|url| url.path() == "/" && url.query().is_none())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::webpage::schema_org::{OneOrMany, Property};
+
+    use super::*;
+
+    fn list_item(position: &str, name: &str, url: &str) -> Property {
+        Property::Item(Item {
+            itemtype: Some(OneOrMany::One("ListItem".to_string())),
+            properties: hashmap! {
+                "position".to_string() => OneOrMany::One(Property::String(position.to_string())),
+                "name".to_string() => OneOrMany::One(Property::String(name.to_string())),
+                "item".to_string() => OneOrMany::One(Property::String(url.to_string())),
+            },
+        })
+    }
+
+    #[test]
+    fn schema_org_breadcrumb() {
+        let list = Item {
+            itemtype: Some(OneOrMany::One("BreadcrumbList".to_string())),
+            properties: hashmap! {
+                "itemListElement".to_string() => OneOrMany::Many(vec![
+                    list_item("3", "Europe", "https://www.nytimes.com/section/world/europe"),
+                    list_item("1", "Home", "https://www.nytimes.com/"),
+                    list_item("2", "World", "https://www.nytimes.com/section/world"),
+                ]),
+            },
+        };
+        assert_eq!(
+            breadcrumb("nytimes.com", "Title", &[list.clone()]),
+            Some(vec![
+                "nytimes.com".to_string(),
+                "World".to_string(),
+                "Europe".to_string()
+            ])
+        );
+
+        let page = Item {
+            itemtype: Some(OneOrMany::One("WebPage".to_string())),
+            properties: hashmap! {
+                "breadcrumb".to_string() => OneOrMany::One(Property::Item(list)),
+            },
+        };
+
+        assert_eq!(
+            breadcrumb("nytimes.com", "Europe", &[page]),
+            Some(vec!["nytimes.com".to_string(), "World".to_string()])
+        );
+
+        assert_eq!(breadcrumb("nytimes.com", "Title", &[]), None);
+    }
+
+    #[test]
+    fn deep_trail_is_capped() {
+        let list = Item {
+            itemtype: Some(OneOrMany::One("BreadcrumbList".to_string())),
+            properties: hashmap! {
+                "itemListElement".to_string() => OneOrMany::Many(
+                    (1..=10)
+                        .map(|i| list_item(&i.to_string(), &format!("Level {i}"), &format!("https://example.com/{i}")))
+                        .collect(),
+                ),
+            },
+        };
+
+        let crumbs = breadcrumb("example.com", "Title", &[list]).unwrap();
+        assert_eq!(crumbs.len(), MAX_CRUMBS + 1);
+        assert_eq!(crumbs[1], "Level 1");
+    }
+}
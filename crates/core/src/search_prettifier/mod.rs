@@ -14,7 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod annotations;
+mod breadcrumb;
 mod entity;
+mod pipeline;
+mod related_questions;
+mod rich_result;
 mod stack_overflow;
 
 use std::collections::HashMap;
@@ -29,11 +34,14 @@ use crate::{
     ranking::{Signal, SignalScore},
     snippet::TextSnippet,
     web_spell::{self, CorrectionTerm},
-    webpage::url_ext::UrlExt,
 };
 
 pub use self::stack_overflow::{create_stackoverflow_sidebar, CodeOrText};
+pub use annotations::{Annotation, Annotator};
 pub use entity::DisplayedEntity;
+pub use pipeline::{Prettifier, PrettifierOptions};
+pub use related_questions::{related_questions, RelatedQuestion};
+pub use rich_result::{Price, Rating, RichResult};
 
 pub use self::stack_overflow::{stackoverflow_snippet, StackOverflowAnswer, StackOverflowQuestion};
 
@@ -143,29 +151,7 @@ fn prettify_date(date: NaiveDateTime) -> String {
     format!("{}", date.format("%d. %b. %Y"))
 }
 
-fn generate_snippet(webpage: &RetrievedWebpage) -> Snippet {
-    let last_updated = webpage.updated_time.map(prettify_date);
-
-    let url = Url::parse(&webpage.url).unwrap();
-
-    if url.root_domain().unwrap_or_default() == "stackoverflow.com"
-        && webpage
-            .schema_org
-            .iter()
-            .any(|item| item.types_contains("QAPage"))
-    {
-        if let Ok(snippet) = stackoverflow_snippet(webpage) {
-            return snippet;
-        }
-    }
-
-    Snippet::Normal {
-        date: last_updated,
-        text: webpage.snippet.clone(),
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DisplayedWebpage {
     pub title: String,
@@ -173,11 +159,34 @@ pub struct DisplayedWebpage {
     pub site: String,
     pub domain: String,
     pub pretty_url: String,
+    /// Where in the site the page is, starting with the site itself.
+    /// Shown instead of the pretty url when set.
+    pub breadcrumb: Option<Vec<String>>,
     pub snippet: Snippet,
     pub ranking_signals: Option<HashMap<Signal, SignalScore>>,
     pub score: Option<f64>,
+    /// The score calibrated to a relevance in `[0, 1]` that can be compared across queries.
+    /// Only set with the ranking signals if the ranking model has been calibrated.
+    pub relevance: Option<f64>,
     pub likely_has_ads: bool,
     pub likely_has_paywall: bool,
+    /// The page was served over plain http or with an invalid certificate.
+    pub insecure: bool,
+    pub code: Option<DisplayedCode>,
+    /// Url of an image representing the page.
+    pub thumbnail: Option<String>,
+    /// Decoration from the schema.org markup of recipes, products and events.
+    pub rich_result: Option<RichResult>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Decoration for results from code-heavy pages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplayedCode {
+    pub language: String,
+    pub snippet: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -192,24 +201,7 @@ pub struct DisplayedAnswer {
 
 impl From<RetrievedWebpage> for DisplayedWebpage {
     fn from(webpage: RetrievedWebpage) -> Self {
-        let snippet = generate_snippet(&webpage);
-
-        let url = Url::parse(&webpage.url).unwrap();
-        let domain = url.root_domain().unwrap_or_default().to_string();
-        let pretty_url = prettify_url(&url);
-
-        Self {
-            title: webpage.title,
-            site: url.normalized_host().unwrap_or_default().to_string(),
-            url: webpage.url,
-            pretty_url,
-            domain,
-            snippet,
-            ranking_signals: None,
-            score: None,
-            likely_has_ads: webpage.likely_has_ads,
-            likely_has_paywall: webpage.likely_has_paywall,
-        }
+        Prettifier::default().prettify(webpage)
     }
 }
 
@@ -0,0 +1,531 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The prettifier turns a retrieved webpage into the webpage displayed to the user.
+//! Each part of the presentation is a separate step that can be
+//! turned off per request through [`PrettifierOptions`].
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::{
+    config::defaults,
+    inverted_index::RetrievedWebpage,
+    moderation::{Action, ModerationStore},
+    snippet::TextSnippet,
+    webpage::{hreflang, region::Region, schema_org, url_ext::UrlExt},
+};
+
+use super::{
+    breadcrumb, prettify_date, prettify_url, stackoverflow_snippet, DisplayedCode,
+    DisplayedWebpage, RichResult, Snippet,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrettifierOptions {
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub title_cleanup: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub snippet: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub pretty_url: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub breadcrumb: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub badges: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub rich_data: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub code: bool,
+    #[serde(default = "defaults::Prettifier::enabled")]
+    pub thumbnail: bool,
+}
+
+impl Default for PrettifierOptions {
+    fn default() -> Self {
+        Self {
+            title_cleanup: defaults::Prettifier::enabled(),
+            snippet: defaults::Prettifier::enabled(),
+            pretty_url: defaults::Prettifier::enabled(),
+            breadcrumb: defaults::Prettifier::enabled(),
+            badges: defaults::Prettifier::enabled(),
+            rich_data: defaults::Prettifier::enabled(),
+            code: defaults::Prettifier::enabled(),
+            thumbnail: defaults::Prettifier::enabled(),
+        }
+    }
+}
+
+/// A single step in the prettifier pipeline. Steps are applied in order
+/// and each step can overwrite what previous steps have set.
+pub trait PrettifierStep: Send + Sync {
+    fn apply(&self, webpage: &RetrievedWebpage, url: &Url, displayed: &mut DisplayedWebpage);
+}
+
+/// Collapse whitespace in the title and fall back to the site
+/// if the page has no title.
+pub struct TitleCleanup;
+
+impl PrettifierStep for TitleCleanup {
+    fn apply(&self, _: &RetrievedWebpage, _: &Url, displayed: &mut DisplayedWebpage) {
+        let title = displayed
+            .title
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        displayed.title = if title.is_empty() {
+            displayed.site.clone()
+        } else {
+            title
+        };
+    }
+}
+
+pub struct SnippetStep;
+
+impl PrettifierStep for SnippetStep {
+    fn apply(&self, webpage: &RetrievedWebpage, _: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.snippet = Snippet::Normal {
+            date: webpage.updated_time.map(prettify_date),
+            text: webpage.snippet.clone(),
+        };
+    }
+}
+
+pub struct PrettyUrl;
+
+impl PrettifierStep for PrettyUrl {
+    fn apply(&self, _: &RetrievedWebpage, url: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.pretty_url = prettify_url(url);
+    }
+}
+
+/// Show where in the site the page is for sites that provide a trail in their
+/// structured data. Other pages keep showing their url.
+pub struct Breadcrumb;
+
+impl PrettifierStep for Breadcrumb {
+    fn apply(&self, webpage: &RetrievedWebpage, _: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.breadcrumb =
+            breadcrumb::breadcrumb(&displayed.site, &displayed.title, &webpage.schema_org);
+    }
+}
+
+/// Mark pages that likely have ads or a paywall and pages that are not served securely.
+pub struct Badges;
+
+impl PrettifierStep for Badges {
+    fn apply(&self, webpage: &RetrievedWebpage, _: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.likely_has_ads = webpage.likely_has_ads;
+        displayed.likely_has_paywall = webpage.likely_has_paywall;
+        displayed.insecure = webpage.tls.is_insecure();
+    }
+}
+
+/// Replace the snippet with a richer snippet based on the structured data of the page
+/// and decorate recipes, products and events with their key properties.
+pub struct RichData;
+
+impl PrettifierStep for RichData {
+    fn apply(&self, webpage: &RetrievedWebpage, url: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.rich_result = RichResult::from_schema_org(&webpage.schema_org);
+
+        if url.root_domain().unwrap_or_default() == "stackoverflow.com"
+            && webpage
+                .schema_org
+                .iter()
+                .any(|item| item.types_contains("QAPage"))
+        {
+            if let Ok(snippet) = stackoverflow_snippet(webpage) {
+                displayed.snippet = snippet;
+            }
+        }
+    }
+}
+
+/// Show the programming language of code-heavy pages along with
+/// a snippet of their code, so the code is not mangled into the text snippet.
+pub struct Code;
+
+impl PrettifierStep for Code {
+    fn apply(&self, webpage: &RetrievedWebpage, _: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.code = webpage
+            .code_language
+            .as_ref()
+            .map(|language| DisplayedCode {
+                language: language.clone(),
+                snippet: webpage.code_snippet.clone(),
+            });
+    }
+}
+
+/// Show the image the page has chosen to represent itself. The og:image
+/// is preferred and the image from the structured data is used as fallback.
+pub struct Thumbnail;
+
+impl Thumbnail {
+    fn schema_org_image(item: &schema_org::Item) -> Option<String> {
+        item.properties
+            .get("image")
+            .cloned()
+            .and_then(|image| image.one())
+            .and_then(|image| match image {
+                schema_org::Property::String(url) => Some(url),
+                schema_org::Property::Item(image) => ["contentUrl", "url"]
+                    .iter()
+                    .filter_map(|key| image.properties.get(*key).cloned())
+                    .find_map(|url| url.one().and_then(|url| url.try_into_string())),
+            })
+    }
+}
+
+impl PrettifierStep for Thumbnail {
+    fn apply(&self, webpage: &RetrievedWebpage, url: &Url, displayed: &mut DisplayedWebpage) {
+        displayed.thumbnail = webpage
+            .open_graph
+            .as_ref()
+            .and_then(|og| og.image.clone())
+            .or_else(|| webpage.schema_org.iter().find_map(Self::schema_org_image))
+            .and_then(|image| Url::parse(&image).or_else(|_| url.join(&image)).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(|image| image.to_string());
+    }
+}
+
+/// Link to the variant of the page for the language of the selected region
+/// when the page has hreflang alternates. Only variants on the same site as the
+/// page are used, and never variants that have been moderated.
+pub struct LanguageVariant {
+    pub region: Region,
+    pub moderation: Option<Arc<ModerationStore>>,
+}
+
+impl PrettifierStep for LanguageVariant {
+    fn apply(&self, webpage: &RetrievedWebpage, url: &Url, displayed: &mut DisplayedWebpage) {
+        let Some(alternate) = hreflang::best_alternate(&webpage.language_alternates, self.region)
+        else {
+            return;
+        };
+
+        let Ok(alternate) = Url::parse(&alternate.url) else {
+            return;
+        };
+
+        // pages indexed before the alternates were restricted to the same site
+        // may still have alternates on other sites.
+        if url.root_domain().is_none() || alternate.root_domain() != url.root_domain() {
+            return;
+        }
+
+        if let Some(moderation) = &self.moderation {
+            if !matches!(
+                moderation.action_for_url(alternate.as_str()),
+                None | Some(Action::Whitelist)
+            ) {
+                return;
+            }
+        }
+
+        let url = alternate;
+
+        if displayed.pretty_url != displayed.url {
+            displayed.pretty_url = prettify_url(&url);
+        } else {
+            displayed.pretty_url = url.to_string();
+        }
+
+        // the trail from the structured data describes the original page
+        displayed.breadcrumb = None;
+
+        displayed.url = url.to_string();
+    }
+}
+
+pub struct Prettifier {
+    steps: Vec<Box<dyn PrettifierStep>>,
+}
+
+impl Default for Prettifier {
+    fn default() -> Self {
+        Self::new(&PrettifierOptions::default())
+    }
+}
+
+impl Prettifier {
+    pub fn new(options: &PrettifierOptions) -> Self {
+        let mut steps: Vec<Box<dyn PrettifierStep>> = Vec::new();
+
+        if options.title_cleanup {
+            steps.push(Box::new(TitleCleanup));
+        }
+
+        if options.snippet {
+            steps.push(Box::new(SnippetStep));
+        }
+
+        if options.pretty_url {
+            steps.push(Box::new(PrettyUrl));
+        }
+
+        if options.breadcrumb {
+            steps.push(Box::new(Breadcrumb));
+        }
+
+        if options.badges {
+            steps.push(Box::new(Badges));
+        }
+
+        if options.rich_data {
+            steps.push(Box::new(RichData));
+        }
+
+        if options.code {
+            steps.push(Box::new(Code));
+        }
+
+        if options.thumbnail {
+            steps.push(Box::new(Thumbnail));
+        }
+
+        Self { steps }
+    }
+
+    pub fn with_step(mut self, step: Box<dyn PrettifierStep>) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn with_region(
+        self,
+        region: Option<Region>,
+        moderation: Option<Arc<ModerationStore>>,
+    ) -> Self {
+        match region {
+            Some(region) if region != Region::All => {
+                self.with_step(Box::new(LanguageVariant { region, moderation }))
+            }
+            _ => self,
+        }
+    }
+
+    pub fn prettify(&self, webpage: RetrievedWebpage) -> DisplayedWebpage {
+        let url = Url::parse(&webpage.url).unwrap();
+
+        let mut displayed = DisplayedWebpage {
+            title: webpage.title.clone(),
+            url: webpage.url.clone(),
+            site: url.normalized_host().unwrap_or_default().to_string(),
+            domain: url.root_domain().unwrap_or_default().to_string(),
+            pretty_url: webpage.url.clone(),
+            breadcrumb: None,
+            snippet: Snippet::Normal {
+                date: None,
+                text: TextSnippet::default(),
+            },
+            ranking_signals: None,
+            score: None,
+            relevance: None,
+            likely_has_ads: false,
+            likely_has_paywall: false,
+            insecure: false,
+            code: None,
+            thumbnail: None,
+            rich_result: None,
+            annotations: Vec::new(),
+        };
+
+        for step in &self.steps {
+            step.apply(&webpage, &url, &mut displayed);
+        }
+
+        displayed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::open_graph::OpenGraph;
+
+    use super::*;
+
+    fn webpage() -> RetrievedWebpage {
+        RetrievedWebpage {
+            title: "  Example \n  title ".to_string(),
+            url: "https://www.example.com/a/b?q=1".to_string(),
+            likely_has_ads: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn all_steps() {
+        let displayed = Prettifier::default().prettify(webpage());
+
+        assert_eq!(displayed.title, "Example title");
+        assert_eq!(displayed.pretty_url, "https://www.example.com › a › b");
+        assert_eq!(displayed.breadcrumb, None);
+        assert_eq!(displayed.site, "example.com");
+        assert!(displayed.likely_has_ads);
+    }
+
+    #[test]
+    fn disabled_steps() {
+        let options = PrettifierOptions {
+            title_cleanup: false,
+            pretty_url: false,
+            breadcrumb: false,
+            badges: false,
+            ..Default::default()
+        };
+
+        let displayed = Prettifier::new(&options).prettify(webpage());
+
+        assert_eq!(displayed.title, "  Example \n  title ");
+        assert_eq!(displayed.pretty_url, "https://www.example.com/a/b?q=1");
+        assert_eq!(displayed.breadcrumb, None);
+        assert!(!displayed.likely_has_ads);
+    }
+
+    #[test]
+    fn code_decoration() {
+        let mut page = webpage();
+        page.code_language = Some("Rust".to_string());
+        page.code_snippet = Some("fn main() {}".to_string());
+
+        let displayed = Prettifier::default().prettify(page.clone());
+
+        assert_eq!(
+            displayed.code,
+            Some(DisplayedCode {
+                language: "Rust".to_string(),
+                snippet: Some("fn main() {}".to_string()),
+            })
+        );
+
+        let options = PrettifierOptions {
+            code: false,
+            ..Default::default()
+        };
+        assert_eq!(Prettifier::new(&options).prettify(page).code, None);
+    }
+
+    #[test]
+    fn thumbnail() {
+        let mut page = webpage();
+        page.schema_org = vec![schema_org::Item {
+            itemtype: Some(schema_org::OneOrMany::One("Recipe".to_string())),
+            properties: maplit::hashmap! {
+                "image".to_string() => schema_org::OneOrMany::One(
+                    schema_org::Property::String("/images/cake.jpg".to_string())
+                ),
+            },
+        }];
+
+        assert_eq!(
+            Prettifier::default().prettify(page.clone()).thumbnail,
+            Some("https://www.example.com/images/cake.jpg".to_string())
+        );
+
+        page.open_graph = Some(OpenGraph {
+            image: Some("https://cdn.example.com/og.jpg".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Prettifier::default().prettify(page.clone()).thumbnail,
+            Some("https://cdn.example.com/og.jpg".to_string())
+        );
+
+        let options = PrettifierOptions {
+            thumbnail: false,
+            ..Default::default()
+        };
+        assert_eq!(Prettifier::new(&options).prettify(page).thumbnail, None);
+    }
+
+    #[test]
+    fn language_variant() {
+        let mut page = webpage();
+        page.language_alternates = vec![
+            hreflang::LanguageAlternate {
+                lang: "en".to_string(),
+                url: "https://www.example.com/a/b?q=1".to_string(),
+            },
+            hreflang::LanguageAlternate {
+                lang: "de".to_string(),
+                url: "https://www.example.com/de/a/b".to_string(),
+            },
+        ];
+
+        let displayed = Prettifier::default()
+            .with_region(Some(Region::Germany), None)
+            .prettify(page.clone());
+
+        assert_eq!(displayed.url, "https://www.example.com/de/a/b");
+        assert_eq!(displayed.pretty_url, "https://www.example.com › de › a › b");
+        assert_eq!(displayed.breadcrumb, None);
+
+        let displayed = Prettifier::default()
+            .with_region(Some(Region::Denmark), None)
+            .prettify(page.clone());
+        assert_eq!(displayed.url, "https://www.example.com/a/b?q=1");
+
+        let displayed = Prettifier::default()
+            .with_region(None, None)
+            .prettify(page.clone());
+        assert_eq!(displayed.url, "https://www.example.com/a/b?q=1");
+
+        let moderation = Arc::new(ModerationStore::open(crate::gen_temp_path()).unwrap());
+        moderation
+            .decide(
+                crate::moderation::Target::Page("https://www.example.com/de/a/b".to_string()),
+                Action::Remove,
+                "operator".to_string(),
+                None,
+            )
+            .unwrap();
+
+        let displayed = Prettifier::default()
+            .with_region(Some(Region::Germany), Some(moderation))
+            .prettify(page.clone());
+        assert_eq!(displayed.url, "https://www.example.com/a/b?q=1");
+
+        page.language_alternates = vec![hreflang::LanguageAlternate {
+            lang: "de".to_string(),
+            url: "https://other.com/de/a/b".to_string(),
+        }];
+
+        let displayed = Prettifier::default()
+            .with_region(Some(Region::Germany), None)
+            .prettify(page);
+        assert_eq!(displayed.url, "https://www.example.com/a/b?q=1");
+    }
+
+    #[test]
+    fn empty_title_uses_site() {
+        let mut page = webpage();
+        page.title = "   ".to_string();
+
+        let displayed = Prettifier::default().prettify(page);
+
+        assert_eq!(displayed.title, "example.com");
+    }
+}
@@ -0,0 +1,189 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! "People also ask". Question-shaped titles among the search results
+//! that are related to the query are shown as an expandable list where
+//! the snippet of the page answers the question.
+
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::snippet::TextSnippet;
+
+use super::{DisplayedWebpage, Snippet};
+
+const MAX_RELATED_QUESTIONS: usize = 4;
+
+const QUESTION_WORDS: [&str; 14] = [
+    "who", "what", "when", "where", "why", "how", "which", "is", "are", "can", "does", "do",
+    "should", "will",
+];
+
+static STOPWORDS: Lazy<HashSet<String>> = Lazy::new(|| {
+    include_str!("../../stopwords/English.txt")
+        .lines()
+        .take(50)
+        .map(str::to_ascii_lowercase)
+        .collect()
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedQuestion {
+    pub question: String,
+    pub answer: TextSnippet,
+    pub title: String,
+    pub url: String,
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+}
+
+fn terms(text: &str) -> HashSet<String> {
+    words(text).filter(|w| !STOPWORDS.contains(w)).collect()
+}
+
+/// Strip site names like "... - Stack Overflow" or "... | Reddit" from the title
+/// and return the question if what is left looks like one.
+fn as_question(title: &str) -> Option<String> {
+    let question = title
+        .split(" - ")
+        .next()?
+        .split(" | ")
+        .next()?
+        .split(" — ")
+        .next()?
+        .trim();
+
+    let first_word = words(question).next()?;
+
+    if question.ends_with('?') {
+        Some(question.to_string())
+    } else if QUESTION_WORDS.contains(&first_word.as_str()) && question.contains(' ') {
+        Some(format!("{question}?"))
+    } else {
+        None
+    }
+}
+
+/// Find questions among the webpages that share at least one
+/// term with the query. Questions identical to the query are skipped
+/// as the results already answer them.
+pub fn related_questions(query: &str, webpages: &[DisplayedWebpage]) -> Vec<RelatedQuestion> {
+    let query_terms = terms(query);
+
+    if query_terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut seen = HashSet::new();
+    seen.insert(words(query).collect::<Vec<_>>());
+
+    let mut res = Vec::new();
+
+    for webpage in webpages {
+        let answer = match &webpage.snippet {
+            Snippet::Normal { text, .. } if !text.fragments.is_empty() => text,
+            _ => continue,
+        };
+
+        let Some(question) = as_question(&webpage.title) else {
+            continue;
+        };
+
+        if terms(&question).is_disjoint(&query_terms) {
+            continue;
+        }
+
+        if !seen.insert(words(&question).collect::<Vec<_>>()) {
+            continue;
+        }
+
+        res.push(RelatedQuestion {
+            question,
+            answer: answer.clone(),
+            title: webpage.title.clone(),
+            url: webpage.url.clone(),
+        });
+
+        if res.len() >= MAX_RELATED_QUESTIONS {
+            break;
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{inverted_index::RetrievedWebpage, search_prettifier::Prettifier};
+
+    use super::*;
+
+    fn webpage(title: &str) -> DisplayedWebpage {
+        let mut snippet = TextSnippet::default();
+        snippet
+            .fragments
+            .push(crate::snippet::TextSnippetFragment::new_unhighlighted(
+                "some answer".to_string(),
+            ));
+
+        Prettifier::default().prettify(RetrievedWebpage {
+            title: title.to_string(),
+            url: "https://example.com".to_string(),
+            snippet,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn question_shaped_titles() {
+        assert_eq!(
+            as_question("How do I exit vim? - Stack Overflow"),
+            Some("How do I exit vim?".to_string())
+        );
+        assert_eq!(
+            as_question("what is rust | Reddit"),
+            Some("what is rust?".to_string())
+        );
+        assert_eq!(as_question("The Rust Programming Language"), None);
+        assert_eq!(as_question("How"), None);
+    }
+
+    #[test]
+    fn related_to_query() {
+        let webpages = vec![
+            webpage("The Rust Programming Language"),
+            webpage("Why is Rust so fast? - Stack Overflow"),
+            webpage("How do I bake bread?"),
+            webpage("Why is rust so fast"),
+            webpage("what is rust"),
+        ];
+
+        let questions: Vec<_> = related_questions("what is rust", &webpages)
+            .into_iter()
+            .map(|q| q.question)
+            .collect();
+
+        assert_eq!(questions, vec!["Why is Rust so fast?".to_string()]);
+    }
+}
@@ -0,0 +1,399 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Typed decorations for results with schema.org markup for recipes,
+//! products and events, so the result can show e.g. the rating and cook time
+//! of a recipe without the user having to open the page.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::webpage::{parse_date, schema_org::Item};
+
+/// Ratings without an explicit `bestRating` are out of 5.
+const DEFAULT_BEST_RATING: f64 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Rating {
+    pub value: f64,
+    pub best: f64,
+    pub count: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Price {
+    pub amount: String,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RichResult {
+    #[serde(rename_all = "camelCase")]
+    Recipe {
+        rating: Option<Rating>,
+        cook_time: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Product {
+        price: Option<Price>,
+        rating: Option<Rating>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Event {
+        start_date: Option<String>,
+        location: Option<String>,
+    },
+}
+
+impl RichResult {
+    /// The decoration for the first recipe, product or event in the
+    /// structured data of the page that has any of the displayed properties.
+    pub fn from_schema_org(items: &[Item]) -> Option<Self> {
+        items.iter().find_map(|item| {
+            if item.types_contains("Recipe") {
+                recipe(item)
+            } else if item.types_contains("Product") {
+                product(item)
+            } else if is_event(item) {
+                event(item)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+fn is_event(item: &Item) -> bool {
+    // there are many subtypes like MusicEvent and SportsEvent
+    item.itemtype
+        .clone()
+        .map(|types| types.many().iter().any(|t| t.ends_with("Event")))
+        .unwrap_or(false)
+}
+
+pub(super) fn string_prop(item: &Item, key: &str) -> Option<String> {
+    item.properties
+        .get(key)
+        .cloned()
+        .and_then(|prop| prop.one())
+        .and_then(|prop| prop.try_into_string())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+pub(super) fn item_prop(item: &Item, key: &str) -> Option<Item> {
+    item.properties
+        .get(key)
+        .cloned()
+        .and_then(|prop| prop.one())
+        .and_then(|prop| prop.try_into_item())
+}
+
+fn rating(item: &Item) -> Option<Rating> {
+    let rating = item_prop(item, "aggregateRating")?;
+
+    let value: f64 = string_prop(&rating, "ratingValue")?.parse().ok()?;
+    let best = string_prop(&rating, "bestRating")
+        .and_then(|best| best.parse().ok())
+        .unwrap_or(DEFAULT_BEST_RATING);
+
+    if !(best.is_finite() && best > 0.0 && value > 0.0 && value <= best) {
+        return None;
+    }
+
+    let count = string_prop(&rating, "ratingCount")
+        .or_else(|| string_prop(&rating, "reviewCount"))
+        .and_then(|count| count.parse().ok());
+
+    Some(Rating { value, best, count })
+}
+
+/// Turn an ISO 8601 duration like `PT1H30M` into `1 h 30 min`.
+fn prettify_duration(duration: &str) -> Option<String> {
+    let rest = duration.trim().strip_prefix('P')?;
+    let (date, time) = rest.split_once('T').unwrap_or((rest, ""));
+
+    let mut minutes: u64 = 0;
+
+    for (part, is_time) in [(date, false), (time, true)] {
+        let mut num = String::new();
+
+        for c in part.chars() {
+            if c.is_ascii_digit() {
+                num.push(c);
+                continue;
+            }
+
+            let n: u64 = num.parse().ok()?;
+            num.clear();
+
+            // 'M' is months in the date part and minutes in the time part.
+            // years, months, weeks and seconds are ignored.
+            let unit = match (is_time, c) {
+                (false, 'D') => 24 * 60,
+                (true, 'H') => 60,
+                (true, 'M') => 1,
+                (_, '.' | ',') => return None,
+                _ => 0,
+            };
+
+            minutes = minutes.checked_add(n.checked_mul(unit)?)?;
+        }
+    }
+
+    match (minutes / 60, minutes % 60) {
+        (0, 0) => None,
+        (0, m) => Some(format!("{m} min")),
+        (h, 0) => Some(format!("{h} h")),
+        (h, m) => Some(format!("{h} h {m} min")),
+    }
+}
+
+fn recipe(item: &Item) -> Option<RichResult> {
+    let rating = rating(item);
+    let cook_time = string_prop(item, "cookTime")
+        .or_else(|| string_prop(item, "totalTime"))
+        .and_then(|time| prettify_duration(&time));
+
+    if rating.is_none() && cook_time.is_none() {
+        return None;
+    }
+
+    Some(RichResult::Recipe { rating, cook_time })
+}
+
+fn product(item: &Item) -> Option<RichResult> {
+    let rating = rating(item);
+    let price = item_prop(item, "offers").and_then(|offer| {
+        let amount = string_prop(&offer, "price").or_else(|| string_prop(&offer, "lowPrice"))?;
+
+        Some(Price {
+            amount,
+            currency: string_prop(&offer, "priceCurrency"),
+        })
+    });
+
+    if rating.is_none() && price.is_none() {
+        return None;
+    }
+
+    Some(RichResult::Product { price, rating })
+}
+
+fn location(item: &Item) -> Option<String> {
+    if let Some(location) = string_prop(item, "location") {
+        return Some(location);
+    }
+
+    let location = item_prop(item, "location")?;
+
+    if location.types_contains("VirtualLocation") {
+        return Some("Online".to_string());
+    }
+
+    let name = string_prop(&location, "name");
+    let locality = string_prop(&location, "address").or_else(|| {
+        item_prop(&location, "address").and_then(|address| string_prop(&address, "addressLocality"))
+    });
+
+    match (name, locality) {
+        (Some(name), Some(locality)) if name != locality => Some(format!("{name}, {locality}")),
+        (name, locality) => name.or(locality),
+    }
+}
+
+fn event(item: &Item) -> Option<RichResult> {
+    let start_date = string_prop(item, "startDate")
+        .and_then(|date| parse_date(&date))
+        .map(|date| date.format("%d. %b. %Y").to_string());
+    let location = location(item);
+
+    if start_date.is_none() && location.is_none() {
+        return None;
+    }
+
+    Some(RichResult::Event {
+        start_date,
+        location,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::Html;
+
+    use super::*;
+
+    fn rich_result(json: &str) -> Option<RichResult> {
+        let html = Html::parse(
+            &format!(
+                r#"
+    <html>
+        <head>
+            <script type="application/ld+json">{json}</script>
+        </head>
+        <body>
+        </body>
+    </html>
+            "#
+            ),
+            "https://example.com",
+        )
+        .unwrap();
+
+        RichResult::from_schema_org(&html.schema_org())
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(prettify_duration("PT1H30M"), Some("1 h 30 min".to_string()));
+        assert_eq!(prettify_duration("PT45M"), Some("45 min".to_string()));
+        assert_eq!(prettify_duration("P1DT2H"), Some("26 h".to_string()));
+        assert_eq!(prettify_duration("PT0M"), None);
+        assert_eq!(prettify_duration("45 minutes"), None);
+        assert_eq!(prettify_duration("PT99999999999999999H"), None);
+    }
+
+    #[test]
+    fn recipe_decoration() {
+        assert_eq!(
+            rich_result(
+                r#"{
+                    "@context": "https://schema.org",
+                    "@type": "Recipe",
+                    "name": "Pancakes",
+                    "cookTime": "PT20M",
+                    "aggregateRating": {
+                        "@type": "AggregateRating",
+                        "ratingValue": 4.5,
+                        "ratingCount": 120
+                    }
+                }"#
+            ),
+            Some(RichResult::Recipe {
+                rating: Some(Rating {
+                    value: 4.5,
+                    best: 5.0,
+                    count: Some(120),
+                }),
+                cook_time: Some("20 min".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn product_decoration() {
+        assert_eq!(
+            rich_result(
+                r#"{
+                    "@context": "https://schema.org",
+                    "@type": "Product",
+                    "name": "Keyboard",
+                    "offers": {
+                        "@type": "AggregateOffer",
+                        "lowPrice": "49.99",
+                        "priceCurrency": "EUR"
+                    },
+                    "aggregateRating": {
+                        "@type": "AggregateRating",
+                        "ratingValue": "12",
+                        "bestRating": "10"
+                    }
+                }"#
+            ),
+            Some(RichResult::Product {
+                price: Some(Price {
+                    amount: "49.99".to_string(),
+                    currency: Some("EUR".to_string()),
+                }),
+                rating: None,
+            })
+        );
+
+        assert_eq!(
+            rich_result(
+                r#"{
+                    "@context": "https://schema.org",
+                    "@type": "Product",
+                    "name": "Keyboard",
+                    "aggregateRating": {
+                        "@type": "AggregateRating",
+                        "ratingValue": "4",
+                        "bestRating": "Infinity"
+                    }
+                }"#
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn event_decoration() {
+        assert_eq!(
+            rich_result(
+                r#"{
+                    "@context": "https://schema.org",
+                    "@type": "MusicEvent",
+                    "name": "Concert",
+                    "startDate": "2024-07-21T19:00:00+02:00",
+                    "location": {
+                        "@type": "Place",
+                        "name": "Royal Arena",
+                        "address": {
+                            "@type": "PostalAddress",
+                            "addressLocality": "Copenhagen"
+                        }
+                    }
+                }"#
+            ),
+            Some(RichResult::Event {
+                start_date: Some("21. Jul. 2024".to_string()),
+                location: Some("Royal Arena, Copenhagen".to_string()),
+            })
+        );
+
+        assert_eq!(
+            rich_result(
+                r#"{
+                    "@context": "https://schema.org",
+                    "@type": "Event",
+                    "location": {"@type": "VirtualLocation", "url": "https://example.com/live"}
+                }"#
+            ),
+            Some(RichResult::Event {
+                start_date: None,
+                location: Some("Online".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn no_rich_result() {
+        assert_eq!(
+            rich_result(
+                r#"{"@context": "https://schema.org", "@type": "Recipe", "name": "Pancakes"}"#
+            ),
+            None
+        );
+        assert_eq!(
+            rich_result(r#"{"@context": "https://schema.org", "@type": "NewsArticle"}"#),
+            None
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Optics configured by the operator that are applied to every search.
+//!
+//! The default optics are composed with the optic and host rankings from the user:
+//!  * The rules of all the optics are applied, so a result is discarded if any
+//!    of the optics discards it and the boosts of the matching rules are combined.
+//!  * The host rankings of the user take precedence. A host that the user has liked,
+//!    disliked or blocked is removed from the host rankings of the default optics.
+//!  * Signal coefficients from the user supplied optic take precedence. Otherwise
+//!    the coefficient from the last default optic that sets the signal is used.
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{anyhow, Result};
+use optics::{HostRankings, Optic};
+
+use crate::searcher::SearchQuery;
+
+#[derive(Default)]
+pub struct DefaultOptics {
+    optics: Vec<Optic>,
+}
+
+impl DefaultOptics {
+    pub fn new(optics: Vec<Optic>) -> Self {
+        Self { optics }
+    }
+
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let mut optics = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            let path = path.as_ref();
+            let optic = std::fs::read_to_string(path)?;
+            let optic = Optic::parse(&optic)
+                .map_err(|err| anyhow!("failed to parse optic {}: {err}", path.display()))?;
+
+            optics.push(optic);
+        }
+
+        Ok(Self::new(optics))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.optics.is_empty()
+    }
+
+    pub fn apply(&self, query: &mut SearchQuery) {
+        if self.is_empty() {
+            return;
+        }
+
+        let mut user_hosts: HashSet<String> = HashSet::new();
+
+        for host_rankings in query
+            .host_rankings
+            .iter()
+            .chain(query.optic.as_ref().map(|optic| &optic.host_rankings))
+        {
+            user_hosts.extend(hosts(host_rankings).map(|host| normalize_host(host)));
+        }
+
+        query.default_optics = self
+            .optics
+            .iter()
+            .cloned()
+            .map(|mut optic| {
+                optic.host_rankings = without_hosts(optic.host_rankings, &user_hosts);
+                optic
+            })
+            .collect();
+    }
+}
+
+fn hosts(host_rankings: &HostRankings) -> impl Iterator<Item = &String> {
+    host_rankings
+        .liked
+        .iter()
+        .chain(host_rankings.disliked.iter())
+        .chain(host_rankings.blocked.iter())
+}
+
+fn normalize_host(host: &str) -> String {
+    let host = host.to_lowercase();
+
+    host.strip_prefix("www.")
+        .map(|host| host.to_string())
+        .unwrap_or(host)
+}
+
+fn without_hosts(host_rankings: HostRankings, hosts: &HashSet<String>) -> HostRankings {
+    let retain = |list: Vec<String>| {
+        list.into_iter()
+            .filter(|host| !hosts.contains(&normalize_host(host)))
+            .collect()
+    };
+
+    HostRankings {
+        liked: retain(host_rankings.liked),
+        disliked: retain(host_rankings.disliked),
+        blocked: retain(host_rankings.blocked),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ranking::Signal;
+
+    use super::*;
+
+    fn default_optics() -> DefaultOptics {
+        DefaultOptics::new(vec![Optic::parse(
+            r#"
+                Ranking(Signal("bm25_title"), 2);
+                Ranking(Signal("host_centrality"), 3);
+                Like(Site("friendly.com"));
+                Dislike(Site("seo-spam.com"));
+            "#,
+        )
+        .unwrap()])
+    }
+
+    #[test]
+    fn user_host_rankings_take_precedence() {
+        let mut query = SearchQuery {
+            host_rankings: Some(HostRankings {
+                liked: vec!["www.seo-spam.com".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        default_optics().apply(&mut query);
+
+        assert_eq!(query.default_optics.len(), 1);
+        assert_eq!(
+            query.default_optics[0].host_rankings,
+            HostRankings {
+                liked: vec!["friendly.com".to_string()],
+                disliked: vec![],
+                blocked: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn user_coefficients_take_precedence() {
+        let mut query = SearchQuery {
+            optic: Some(Optic::parse(r#"Ranking(Signal("bm25_title"), 10);"#).unwrap()),
+            ..Default::default()
+        };
+
+        default_optics().apply(&mut query);
+        let coefficients = query.signal_coefficients().unwrap();
+
+        assert_eq!(coefficients.get(&Signal::Bm25Title), Some(10.0));
+        assert_eq!(coefficients.get(&Signal::HostCentrality), Some(3.0));
+        assert_eq!(coefficients.get(&Signal::Bm25CleanBody), None);
+    }
+
+    #[test]
+    fn no_default_optics() {
+        let mut query = SearchQuery::default();
+
+        DefaultOptics::default().apply(&mut query);
+
+        assert!(query.default_optics.is_empty());
+        assert!(query.signal_coefficients().is_none());
+    }
+}
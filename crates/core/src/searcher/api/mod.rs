@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod default_optics;
+mod result_cache;
 mod sidebar;
 mod widget;
 
@@ -25,26 +27,42 @@ use itertools::{intersperse, Itertools};
 use url::Url;
 
 use crate::bangs::{Bang, BangHit};
+use crate::cached_page::CachedPage;
 use crate::collector::Doc;
-use crate::config::{ApiConfig, CollectorConfig};
+use crate::config::{ApiConfig, CollectorConfig, RankingPipelineConfig};
 use crate::image_store::Image;
 use crate::inverted_index::RetrievedWebpage;
 use crate::ranking::models::cross_encoder::CrossEncoderModel;
 use crate::ranking::pipeline::{AsRankingWebsite, RankingWebsite, RetrievedWebpageRanking};
 use crate::ranking::ALL_SIGNALS;
-use crate::search_prettifier::{DisplayedSidebar, DisplayedWebpage, HighlightedSpellCorrection};
+use crate::search_prettifier::{
+    related_questions, Annotator, DisplayedSidebar, DisplayedWebpage, HighlightedSpellCorrection,
+    Prettifier,
+};
 use crate::web_spell::SpellChecker;
 use crate::widgets::{Widget, Widgets};
 use crate::{
     bangs::Bangs,
     collector::BucketCollector,
-    ranking::{models::lambdamart::LambdaMART, pipeline::RankingPipeline},
+    moderation::{AuditEntry, FlagRecorder, ModerationStore, Target},
+    ranking::{
+        experiment::{Arm, Experiment},
+        models::{
+            lambdamart::{FeatureImportance, Gbdt, LambdaMART},
+            reloadable::Reloadable,
+        },
+        pipeline::RankingPipeline,
+    },
 };
 use crate::{query, Result};
 
+use self::default_optics::DefaultOptics;
+use self::result_cache::{RankedResult, ResultCache};
 use self::sidebar::SidebarManager;
 use self::widget::WidgetManager;
 
+use super::budget::Deadline;
+use super::sample::IndexSample;
 use super::{distributed, live, SearchQuery, SearchResult, WebsitesResult};
 
 #[derive(Clone)]
@@ -140,12 +158,19 @@ pub struct ApiSearcher<S, L> {
     distributed_searcher: Arc<S>,
     sidebar_manager: SidebarManager<S>,
     live_searcher: Option<L>,
-    cross_encoder: Option<Arc<CrossEncoderModel>>,
-    lambda_model: Option<Arc<LambdaMART>>,
+    cross_encoder: Arc<Reloadable<CrossEncoderModel>>,
+    lambda_model: Arc<Reloadable<LambdaMART>>,
     bangs: Bangs,
     collector_config: CollectorConfig,
+    ranking_pipeline: RankingPipelineConfig,
     widget_manager: WidgetManager,
     spell_checker: Option<SpellChecker>,
+    annotator: Annotator,
+    default_optics: DefaultOptics,
+    moderation: Option<Arc<ModerationStore>>,
+    flags: Option<FlagRecorder>,
+    experiment: Option<Experiment>,
+    result_cache: Option<ResultCache>,
 }
 
 impl<S, L> ApiSearcher<S, L>
@@ -156,8 +181,8 @@ where
     pub fn new(
         dist_searcher: S,
         live_searcher: Option<L>,
-        cross_encoder: Option<CrossEncoderModel>,
-        lambda_model: Option<LambdaMART>,
+        cross_encoder: Reloadable<CrossEncoderModel>,
+        lambda_model: Reloadable<LambdaMART>,
         bangs: Bangs,
         config: ApiConfig,
     ) -> Self {
@@ -165,25 +190,72 @@ where
         let sidebar_manager =
             SidebarManager::new(Arc::clone(&dist_searcher), config.thresholds.clone());
 
-        let lambda_model = lambda_model.map(Arc::new);
-
         let widget_manager = WidgetManager::new(Widgets::new(config.widgets).unwrap());
+        let annotator = Annotator::new(&config.annotations).unwrap();
+        let default_optics = DefaultOptics::open(&config.default_optics).unwrap();
+        let result_cache = config.result_cache.as_ref().map(ResultCache::new);
 
         Self {
             distributed_searcher: dist_searcher,
             sidebar_manager,
             live_searcher,
-            cross_encoder: cross_encoder.map(Arc::new),
-            lambda_model,
+            cross_encoder: Arc::new(cross_encoder),
+            lambda_model: Arc::new(lambda_model),
             bangs,
             collector_config: config.collector,
+            ranking_pipeline: config.ranking_pipeline,
             widget_manager,
             spell_checker: config
                 .spell_checker_path
                 .map(|c| SpellChecker::open(c, config.correction_config).unwrap()),
+            annotator,
+            default_optics,
+            moderation: None,
+            flags: None,
+            experiment: None,
+            result_cache,
+        }
+    }
+
+    pub fn set_moderation(&mut self, moderation: Arc<ModerationStore>) {
+        self.flags = Some(FlagRecorder::spawn(Arc::clone(&moderation)));
+        self.moderation = Some(moderation);
+    }
+
+    pub fn set_experiment(&mut self, experiment: Experiment) {
+        self.experiment = Some(experiment);
+    }
+
+    pub fn experiment(&self) -> Option<&Experiment> {
+        self.experiment.as_ref()
+    }
+
+    pub fn annotator(&self) -> &Annotator {
+        &self.annotator
+    }
+
+    /// Invalidate the cached results affected by the moderation decision.
+    pub fn invalidate(&self, decision: &AuditEntry) {
+        if let Some(cache) = &self.result_cache {
+            cache.invalidate(&decision.target, decision.previous);
         }
     }
 
+    /// Split counts and gain per signal of the loaded LambdaMART model.
+    pub fn feature_importance(&self) -> Option<Vec<FeatureImportance>> {
+        self.lambda_model
+            .get()
+            .map(|model| model.feature_importance())
+    }
+
+    pub fn lambda_model(&self) -> &Arc<Reloadable<LambdaMART>> {
+        &self.lambda_model
+    }
+
+    pub fn cross_encoder(&self) -> &Arc<Reloadable<CrossEncoderModel>> {
+        &self.cross_encoder
+    }
+
     async fn check_bangs(&self, query: &SearchQuery) -> Result<Option<BangHit>> {
         let parsed_terms = query::parser::parse(&query.query);
 
@@ -287,11 +359,13 @@ where
         Some(HighlightedSpellCorrection::from(correction))
     }
 
+    /// Retrieve the webpages of the top websites together with their index in `top_websites`.
     async fn retrieve_webpages(
         &self,
         query: &str,
         top_websites: &[ScoredWebsitePointer],
-    ) -> Vec<RetrievedWebpageRanking> {
+        deadline: Option<Deadline>,
+    ) -> Vec<(usize, RetrievedWebpageRanking)> {
         let normal: Vec<_> = top_websites
             .iter()
             .enumerate()
@@ -317,8 +391,9 @@ where
             .collect();
 
         let (retrieved_normal, retrieved_live) = tokio::join!(
-            self.distributed_searcher.retrieve_webpages(&normal, query),
-            self.retrieve_webpages_from_live(&live, query),
+            self.distributed_searcher
+                .retrieve_webpages(&normal, query, deadline),
+            self.retrieve_webpages_from_live(&live, query, deadline),
         );
 
         let mut retrieved_webpages: Vec<_> =
@@ -326,17 +401,15 @@ where
         retrieved_webpages.sort_by(|(a, _), (b, _)| a.cmp(b));
 
         retrieved_webpages
-            .into_iter()
-            .map(|(_, webpage)| webpage)
-            .collect::<Vec<_>>()
     }
 
     async fn search_initial_from_live(
         &self,
         query: &SearchQuery,
+        deadline: Option<Deadline>,
     ) -> Option<Vec<live::InitialSearchResultSplit>> {
         match &self.live_searcher {
-            Some(searcher) => Some(searcher.search_initial(query).await),
+            Some(searcher) => Some(searcher.search_initial(query, deadline).await),
             None => None,
         }
     }
@@ -345,9 +418,10 @@ where
         &self,
         pointers: &[(usize, live::ScoredWebsitePointer)],
         query: &str,
+        deadline: Option<Deadline>,
     ) -> Vec<(usize, RetrievedWebpageRanking)> {
         match &self.live_searcher {
-            Some(searcher) => searcher.retrieve_webpages(pointers, query).await,
+            Some(searcher) => searcher.retrieve_webpages(pointers, query, deadline).await,
             None => vec![],
         }
     }
@@ -359,6 +433,102 @@ where
             return Err(distributed::Error::EmptyQuery.into());
         }
 
+        let arm = self.experiment.as_ref().map(|experiment| {
+            experiment.assign(query.client_id.as_deref().unwrap_or(&query.query))
+        });
+
+        // searches in an experiment are not cached, so the arms are compared on equal terms.
+        // without a known generation the entries could not be told apart from stale ones.
+        let cache = match (&self.result_cache, arm) {
+            (Some(cache), None) => self
+                .distributed_searcher
+                .index_generation()
+                .await
+                .map(|generation| (cache, generation)),
+            _ => None,
+        };
+
+        let cached = cache.and_then(|(cache, generation)| cache.get(generation, query));
+
+        let RankedResult {
+            mut webpages,
+            num_hits,
+            has_more_results,
+        } = match cached {
+            Some(result) => result,
+            None => {
+                let (result, complete) = self.rank_websites(query, arm).await?;
+
+                // results where some shards missed the deadline are not served to later searches
+                if let Some((cache, generation)) = cache.filter(|_| complete) {
+                    cache.insert(generation, query, result.clone());
+                }
+
+                result
+            }
+        };
+
+        if let Some(moderation) = &self.moderation {
+            moderation.apply(&mut webpages);
+        }
+
+        self.annotator.filter(&mut webpages);
+
+        let related_questions = if query.page == 0 {
+            related_questions(&query.query, &webpages)
+        } else {
+            Vec::new()
+        };
+
+        let search_duration_ms = start.elapsed().as_millis();
+
+        Ok(WebsitesResult {
+            num_hits,
+            webpages,
+            related_questions,
+            search_duration_ms,
+            has_more_results,
+            experiment: self
+                .experiment
+                .as_ref()
+                .zip(arm)
+                .map(|(experiment, arm)| experiment.record(arm)),
+        })
+    }
+
+    /// Search the shards and rank the results. Also returns whether all shards
+    /// responded within the budget.
+    async fn rank_websites(
+        &self,
+        query: &SearchQuery,
+        arm: Option<&Arm>,
+    ) -> Result<(RankedResult, bool)> {
+        let mut query = query.clone();
+
+        self.default_optics.apply(&mut query);
+
+        if let Some(moderation) = &self.moderation {
+            moderation.apply_host_rankings(&mut query.host_rankings);
+        }
+
+        if let Some(arm) = arm {
+            arm.apply(&mut query);
+        }
+
+        let lambda_model = arm
+            .and_then(|arm| arm.lambda_model())
+            .unwrap_or(&self.lambda_model)
+            .get();
+
+        let cross_encoder = if arm.map(|arm| arm.use_cross_encoder()).unwrap_or(true) {
+            self.cross_encoder.get()
+        } else {
+            None
+        };
+
+        let query = &query;
+        let budget = query.budget;
+
         let mut search_query = query.clone();
         let top_n = search_query.num_results;
 
@@ -366,16 +536,22 @@ where
         // so the query knows how many results to fetch from the indices
         let recall_pipeline: RankingPipeline<ScoredWebsitePointer> = RankingPipeline::recall_stage(
             &mut search_query,
-            self.lambda_model.clone(),
+            &self.ranking_pipeline.recall,
+            lambda_model.clone(),
             self.collector_config.clone(),
             top_n,
         );
 
         let (initial_results, live_results) = tokio::join!(
-            self.distributed_searcher.search_initial(&search_query),
-            self.search_initial_from_live(&search_query),
+            self.distributed_searcher
+                .search_initial(&search_query, budget.map(|b| b.initial_search())),
+            self.search_initial_from_live(&search_query, budget.map(|b| b.initial_search())),
         );
 
+        let initial_expired = budget
+            .map(|b| b.initial_search().is_expired())
+            .unwrap_or(false);
+
         let num_docs = initial_results
             .iter()
             .map(|result| result.local_result.num_websites)
@@ -389,9 +565,42 @@ where
         );
 
         let retrieved_webpages = self
-            .retrieve_webpages(&search_query.query, &top_websites)
+            .retrieve_webpages(
+                &search_query.query,
+                &top_websites,
+                budget.map(|b| b.retrieve()),
+            )
             .await;
 
+        // with a budget, the websites whose shards missed the deadline are dropped
+        // instead of failing the entire search
+        let top_websites = if budget.is_some() {
+            let mut retrieved = retrieved_webpages.iter().map(|(i, _)| *i).peekable();
+
+            top_websites
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| retrieved.next_if_eq(i).is_some())
+                .map(|(_, website)| website)
+                .collect()
+        } else {
+            top_websites
+        };
+
+        let retrieved_webpages: Vec<_> = retrieved_webpages
+            .into_iter()
+            .map(|(_, webpage)| webpage)
+            .collect();
+
+        let retrieve_expired = budget.map(|b| b.retrieve().is_expired()).unwrap_or(false);
+
+        // skip the expensive cross encoder if there is no time left for it
+        let cross_encoder = if retrieve_expired {
+            None
+        } else {
+            cross_encoder
+        };
+
         let mut search_query = SearchQuery {
             page: 0,
             ..query.clone()
@@ -400,18 +609,20 @@ where
         let reranking_pipeline: RankingPipeline<RetrievedWebpageRanking> =
             RankingPipeline::reranker(
                 &mut search_query,
-                self.cross_encoder.clone(),
-                self.lambda_model.clone(),
+                &self.ranking_pipeline.rerank,
+                cross_encoder,
+                lambda_model.clone(),
                 self.collector_config.clone(),
                 query.num_results,
             )?;
 
         let retrieved_webpages = reranking_pipeline.apply(retrieved_webpages);
 
+        let prettifier = Prettifier::new(&query.prettifier)
+            .with_region(query.selected_region, self.moderation.clone());
         let mut retrieved_webpages: Vec<_> = retrieved_webpages
             .into_iter()
-            .map(|webpage| webpage.into_retrieved_webpage())
-            .map(DisplayedWebpage::from)
+            .map(|webpage| prettifier.prettify(webpage.into_retrieved_webpage()))
             .collect();
 
         if retrieved_webpages.len() != top_websites.len() {
@@ -424,16 +635,29 @@ where
 
         for (website, pointer) in retrieved_webpages.iter_mut().zip(top_websites.iter()) {
             website.score = Some(pointer.score());
+
+            if query.return_ranking_signals {
+                website.relevance = lambda_model
+                    .as_ref()
+                    .zip(pointer.as_ranking().model_score)
+                    .and_then(|(model, score)| model.calibrate(score));
+            }
         }
 
-        let search_duration_ms = start.elapsed().as_millis();
+        self.annotator.annotate(&mut retrieved_webpages).await;
 
-        Ok(WebsitesResult {
-            num_hits: num_docs,
-            webpages: retrieved_webpages,
-            search_duration_ms,
-            has_more_results,
-        })
+        if let Some(flags) = &self.flags {
+            flags.flag_dangerous(&query.query, &retrieved_webpages);
+        }
+
+        Ok((
+            RankedResult {
+                webpages: retrieved_webpages,
+                num_hits: num_docs,
+                has_more_results,
+            },
+            !initial_expired && !retrieve_expired,
+        ))
     }
 
     pub async fn search(&self, query: &SearchQuery) -> Result<SearchResult> {
@@ -448,6 +672,65 @@ where
         self.distributed_searcher.get_webpage(url).await
     }
 
+    /// The most recently fetched cached page for the url in the index or the live index.
+    pub async fn get_cached_page(&self, url: &str) -> Option<CachedPage> {
+        let live = async {
+            match &self.live_searcher {
+                Some(live_searcher) => live_searcher.get_cached_page(url).await,
+                None => None,
+            }
+        };
+
+        let (page, live_page) =
+            futures::join!(self.distributed_searcher.get_cached_page(url), live);
+
+        page.into_iter()
+            .chain(live_page)
+            .max_by_key(|page| page.fetch_time_ms)
+    }
+
+    pub async fn sample_index(
+        &self,
+        num_docs: usize,
+        fields: &[String],
+        seed: Option<u64>,
+    ) -> IndexSample {
+        self.distributed_searcher
+            .sample(num_docs, fields, seed)
+            .await
+    }
+
+    /// Delete the pages from the shards and the live index.
+    /// Returns the number of deleted documents.
+    pub async fn delete_urls(&self, urls: &[Url]) -> Result<usize> {
+        let mut num_deleted = self.distributed_searcher.delete_urls(urls).await?;
+
+        if let Some(live_searcher) = &self.live_searcher {
+            num_deleted += live_searcher.delete_urls(urls).await?;
+        }
+
+        if let Some(cache) = &self.result_cache {
+            for url in urls {
+                cache.invalidate(&Target::Page(url.to_string()), None);
+            }
+        }
+
+        Ok(num_deleted)
+    }
+
+    /// Replace the pages with freshly crawled versions. The stale documents are deleted
+    /// everywhere and the pages are crawled again by the live index.
+    /// Returns the number of urls that were queued for crawling.
+    pub async fn reindex(&self, urls: &[Url]) -> Result<usize> {
+        let Some(live_searcher) = &self.live_searcher else {
+            return Err(distributed::Error::NoLiveIndex.into());
+        };
+
+        self.delete_urls(urls).await?;
+
+        live_searcher.recrawl(urls).await
+    }
+
     pub async fn get_entity_image(
         &self,
         image_id: &str,
@@ -0,0 +1,210 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cache of the ranked results (including their snippets) per query.
+//!
+//! Entries are keyed by the generation of the index in addition to the query.
+//! Every query is sent to all shards, so when any shard is swapped for a new index
+//! all entries of the old generation are stale. They are simply never hit again
+//! and age out of the cache.
+//!
+//! The results are cached before moderation is applied, so decisions are applied
+//! on every hit. A decision about a page or host still invalidates the entries that
+//! contain it, as the shards would rank their results differently. Lifting a
+//! decision about a host invalidates all entries, since the host was left out of
+//! every search while the decision was active.
+
+use std::{sync::Mutex, time::Duration};
+
+use crate::{
+    config::ResultCacheConfig,
+    moderation::{Action, Target},
+    prehashed::{self, Prehashed},
+    search_prettifier::DisplayedWebpage,
+    searcher::SearchQuery,
+    ttl_cache::TTLCache,
+};
+
+/// The ranked results of a search before moderation and filtering.
+#[derive(Debug, Clone)]
+pub struct RankedResult {
+    pub webpages: Vec<DisplayedWebpage>,
+    pub num_hits: Option<usize>,
+    pub has_more_results: bool,
+}
+
+/// Version of the key. Bump it when the way the key is derived changes,
+/// so entries with the old key are never mistaken for ones with the new.
+const KEY_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    version: u8,
+    generation: u64,
+    query: Prehashed,
+}
+
+impl Key {
+    fn new(generation: u64, query: &SearchQuery) -> Self {
+        // the client id only assigns the experiment arm, and searches
+        // in an experiment are never cached
+        let query = SearchQuery {
+            client_id: None,
+            ..query.clone()
+        };
+
+        Self {
+            version: KEY_VERSION,
+            generation,
+            query: prehashed::hash(serde_json::to_vec(&query).unwrap_or_default()),
+        }
+    }
+}
+
+pub struct ResultCache {
+    entries: Mutex<TTLCache<Key, RankedResult>>,
+}
+
+impl ResultCache {
+    pub fn new(config: &ResultCacheConfig) -> Self {
+        Self {
+            entries: Mutex::new(TTLCache::with_ttl_and_max_size(
+                Duration::from_secs(config.ttl_sec),
+                Some(config.max_entries),
+            )),
+        }
+    }
+
+    pub fn get(&self, generation: u64, query: &SearchQuery) -> Option<RankedResult> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&Key::new(generation, query))
+            .cloned()
+    }
+
+    pub fn insert(&self, generation: u64, query: &SearchQuery, result: RankedResult) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(Key::new(generation, query), result);
+    }
+
+    /// Invalidate the entries affected by a moderation decision about the target.
+    /// `previous` is the action that was active for the target before the decision.
+    pub fn invalidate(&self, target: &Target, previous: Option<Action>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        match (target, previous) {
+            (Target::Host(_), Some(Action::Penalize | Action::Remove)) => entries.clear(),
+            _ => entries.retain(|_, result| {
+                !result
+                    .webpages
+                    .iter()
+                    .any(|webpage| target.matches(&webpage.url))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inverted_index::RetrievedWebpage;
+
+    use super::*;
+
+    fn result(urls: &[&str]) -> RankedResult {
+        RankedResult {
+            webpages: urls
+                .iter()
+                .map(|url| {
+                    DisplayedWebpage::from(RetrievedWebpage {
+                        title: "title".to_string(),
+                        url: url.to_string(),
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+            num_hits: Some(urls.len()),
+            has_more_results: false,
+        }
+    }
+
+    fn query(query: &str) -> SearchQuery {
+        SearchQuery {
+            query: query.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn urls(result: Option<RankedResult>) -> Option<Vec<String>> {
+        result.map(|result| result.webpages.into_iter().map(|w| w.url).collect())
+    }
+
+    #[test]
+    fn generations() {
+        let cache = ResultCache::new(&ResultCacheConfig::default());
+
+        cache.insert(1, &query("a"), result(&["https://a.com/"]));
+
+        assert_eq!(
+            urls(cache.get(1, &query("a"))),
+            Some(vec!["https://a.com/".to_string()])
+        );
+        assert!(cache.get(1, &query("b")).is_none());
+        assert!(cache.get(2, &query("a")).is_none());
+
+        let with_client_id = SearchQuery {
+            client_id: Some("123".to_string()),
+            ..query("a")
+        };
+        assert!(cache.get(1, &with_client_id).is_some());
+    }
+
+    #[test]
+    fn invalidate_page() {
+        let cache = ResultCache::new(&ResultCacheConfig::default());
+
+        cache.insert(
+            1,
+            &query("a"),
+            result(&["https://a.com/", "https://b.com/x"]),
+        );
+        cache.insert(1, &query("b"), result(&["https://b.com/y"]));
+
+        cache.invalidate(&Target::Page("https://b.com/x".to_string()), None);
+
+        assert!(cache.get(1, &query("a")).is_none());
+        assert!(cache.get(1, &query("b")).is_some());
+    }
+
+    #[test]
+    fn invalidate_host() {
+        let cache = ResultCache::new(&ResultCacheConfig::default());
+
+        cache.insert(1, &query("a"), result(&["https://a.com/"]));
+        cache.insert(1, &query("b"), result(&["https://b.com/y"]));
+
+        cache.invalidate(&Target::Host("b.com".to_string()), None);
+
+        assert!(cache.get(1, &query("a")).is_some());
+        assert!(cache.get(1, &query("b")).is_none());
+
+        // the host could now be part of any result
+        cache.invalidate(&Target::Host("c.com".to_string()), Some(Action::Remove));
+        assert!(cache.get(1, &query("a")).is_none());
+    }
+}
@@ -51,7 +51,7 @@ where
 
         let mut results: Vec<_> = self
             .distributed_searcher
-            .search_initial(&query)
+            .search_initial(&query, None)
             .await
             .into_iter()
             .filter_map(|result| {
@@ -73,7 +73,7 @@ where
                     vec![(0, distributed::ScoredWebsitePointer { website, shard })];
                 let mut retrieved = self
                     .distributed_searcher
-                    .retrieve_webpages(&scored_websites, &query.query)
+                    .retrieve_webpages(&scored_websites, &query.query, None)
                     .await;
 
                 if let Some((_, res)) = retrieved.pop() {
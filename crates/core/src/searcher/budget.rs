@@ -0,0 +1,122 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Latency budget of a search given by the caller.
+//!
+//! The budget is split into deadlines for each stage of the search. The deadlines
+//! are used as timeouts for the requests to the shards, so shards that are too slow
+//! are left out of the results instead of delaying the entire search.
+
+use std::time::{Duration, Instant};
+
+/// Header with the number of milliseconds the caller is willing to wait for the search.
+pub const BUDGET_HEADER: &str = "x-search-budget-ms";
+
+/// Part of the budget that can be spent before the initial search on the shards must finish.
+const INITIAL_SEARCH_SHARE: f64 = 0.5;
+
+/// Part of the budget that can be spent before the webpages must have been retrieved.
+/// The rest is reserved for reranking and preparing the results.
+const RETRIEVE_SHARE: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(duration: Duration) -> Self {
+        Self(Instant::now() + duration)
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchBudget {
+    start: Instant,
+    total: Duration,
+}
+
+impl SearchBudget {
+    pub fn new(total: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            total,
+        }
+    }
+
+    /// Parse the budget from the value of the budget header.
+    pub fn from_header(value: &str) -> Option<Self> {
+        value
+            .trim()
+            .parse()
+            .ok()
+            .map(|ms| Self::new(Duration::from_millis(ms)))
+    }
+
+    fn deadline(&self, share: f64) -> Deadline {
+        Deadline(self.start + self.total.mul_f64(share))
+    }
+
+    pub fn initial_search(&self) -> Deadline {
+        self.deadline(INITIAL_SEARCH_SHARE)
+    }
+
+    pub fn retrieve(&self) -> Deadline {
+        self.deadline(RETRIEVE_SHARE)
+    }
+
+    pub fn total(&self) -> Deadline {
+        self.deadline(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_deadlines() {
+        let budget = SearchBudget::from_header(" 1000 ").unwrap();
+
+        assert!(budget.initial_search().remaining() <= Duration::from_millis(500));
+        assert!(budget.initial_search().remaining() > Duration::from_millis(400));
+        assert!(budget.retrieve().remaining() <= Duration::from_millis(800));
+        assert!(budget.retrieve().remaining() > budget.initial_search().remaining());
+        assert!(budget.total().remaining() <= Duration::from_millis(1000));
+        assert!(!budget.total().is_expired());
+    }
+
+    #[test]
+    fn expired() {
+        let budget = SearchBudget::from_header("0").unwrap();
+
+        assert!(budget.initial_search().is_expired());
+        assert!(budget.total().is_expired());
+        assert_eq!(budget.retrieve().remaining(), Duration::ZERO);
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(SearchBudget::from_header("fast").is_none());
+        assert!(SearchBudget::from_header("-10").is_none());
+    }
+}
@@ -15,12 +15,15 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::{
+    cached_page::CachedPage,
     distributed::{
         cluster::Cluster,
+        load::{LoadAwareReplicaSelector, LoadTracker},
         member::Service,
         sonic::replication::{
-            AllShardsSelector, RandomReplicaSelector, RemoteClient, ReplicatedClient, Shard,
-            ShardIdentifier, ShardedClient, SpecificShardSelector,
+            AllReplicaSelector, AllShardsSelector, RandomReplicaSelector, RemoteClient,
+            ReplicatedClient, Shard, ShardIdentifier, ShardedClient, SpecificShardSelector,
+            DEFAULT_REQUEST_TIMEOUT,
         },
     },
     entity_index::EntityMatch,
@@ -30,21 +33,27 @@ use crate::{
     },
     image_store::Image,
     inverted_index::{RetrievedWebpage, WebsitePointer},
+    prehashed,
     ranking::pipeline::{RankingWebsite, RetrievedWebpageRanking},
     Result,
 };
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use fnv::FnvHashMap;
 use futures::future::join_all;
 use itertools::Itertools;
+use rand::{rngs::StdRng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::future::Future;
 use thiserror::Error;
 use url::Url;
 
-use super::{InitialWebsiteResult, SearchQuery};
+use super::{
+    budget::Deadline,
+    sample::{self, IndexSample},
+    InitialWebsiteResult, SearchQuery,
+};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -56,6 +65,12 @@ pub enum Error {
 
     #[error("Webpage not found")]
     WebpageNotFound,
+
+    #[error("Failed to delete the urls from all replicas")]
+    DeleteFailed,
+
+    #[error("Reindexing requires a live index")]
+    NoLiveIndex,
 }
 
 #[derive(Clone, Debug)]
@@ -64,7 +79,7 @@ pub struct ScoredWebsitePointer {
     pub shard: ShardId,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
 pub struct ShardId(u64);
 
 impl ShardId {
@@ -75,6 +90,13 @@ impl ShardId {
 
 impl ShardIdentifier for ShardId {}
 
+/// Timeout for a request to the shards that must respond before the deadline.
+pub fn timeout(deadline: Option<Deadline>) -> Duration {
+    deadline
+        .map(|deadline| deadline.remaining())
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+}
+
 #[derive(Debug)]
 pub struct InitialSearchResultShard {
     pub local_result: InitialWebsiteResult,
@@ -83,21 +105,40 @@ pub struct InitialSearchResultShard {
 
 pub struct DistributedSearcher {
     cluster: Arc<Cluster>,
+    load: Arc<LoadTracker>,
 }
 
 impl DistributedSearcher {
     pub fn new(cluster: Arc<Cluster>) -> Self {
-        Self { cluster }
+        Self {
+            cluster,
+            load: Arc::new(LoadTracker::default()),
+        }
+    }
+
+    pub fn with_load_tracker(mut self, load: Arc<LoadTracker>) -> Self {
+        self.load = load;
+        self
+    }
+
+    pub fn load(&self) -> &Arc<LoadTracker> {
+        &self.load
+    }
+
+    fn replica_selector(&self) -> LoadAwareReplicaSelector<'_> {
+        LoadAwareReplicaSelector(&self.load)
     }
 
     async fn client(&self) -> ShardedClient<SearchService, ShardId> {
         let mut shards = HashMap::new();
         for member in self.cluster.members().await {
-            if let Service::Searcher { host, shard } = member.service {
+            if let Service::Searcher { host, shard, .. } = member.service {
                 shards.entry(shard).or_insert_with(Vec::new).push(host);
             }
         }
 
+        self.load.set_replicas(&shards);
+
         let mut shard_clients = Vec::new();
 
         for (id, replicas) in shards {
@@ -127,17 +168,19 @@ impl DistributedSearcher {
         client: &ShardedClient<SearchService, ShardId>,
         query: &str,
         pointers: Vec<(usize, WebsitePointer)>,
+        deadline: Option<Deadline>,
     ) -> Vec<(usize, RetrievedWebpage)> {
         let (idxs, pointers): (Vec<usize>, Vec<WebsitePointer>) = pointers.into_iter().unzip();
 
         match client
-            .send(
+            .send_with_timeout(
                 &search_server::RetrieveWebsites {
                     websites: pointers,
                     query: query.to_string(),
                 },
                 &SpecificShardSelector(shard),
-                &RandomReplicaSelector,
+                &self.replica_selector(),
+                timeout(deadline),
             )
             .await
         {
@@ -155,17 +198,44 @@ impl DistributedSearcher {
 }
 
 impl SearchClient for DistributedSearcher {
-    async fn search_initial(&self, query: &SearchQuery) -> Vec<InitialSearchResultShard> {
+    async fn index_generation(&self) -> Option<u64> {
+        let mut generations = Vec::new();
+
+        for member in self.cluster.members().await {
+            if let Service::Searcher {
+                shard, generation, ..
+            } = member.service
+            {
+                generations.push((shard, generation?));
+            }
+        }
+
+        let generations = generations
+            .into_iter()
+            .sorted()
+            .dedup()
+            .map(|(shard, generation)| format!("{}:{generation}", shard.0))
+            .join(",");
+
+        Some(prehashed::hash(generations).0 as u64)
+    }
+
+    async fn search_initial(
+        &self,
+        query: &SearchQuery,
+        deadline: Option<Deadline>,
+    ) -> Vec<InitialSearchResultShard> {
         let client = self.client().await;
         let mut results = Vec::new();
 
         if let Ok(res) = client
-            .send(
+            .send_with_timeout(
                 &search_server::Search {
                     query: query.clone(),
                 },
                 &AllShardsSelector,
-                &RandomReplicaSelector,
+                &self.replica_selector(),
+                timeout(deadline),
             )
             .await
         {
@@ -186,6 +256,7 @@ impl SearchClient for DistributedSearcher {
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        deadline: Option<Deadline>,
     ) -> Vec<(usize, RetrievedWebpageRanking)> {
         let mut rankings = FnvHashMap::default();
         let mut pointers: HashMap<_, Vec<_>> = HashMap::new();
@@ -202,7 +273,8 @@ impl SearchClient for DistributedSearcher {
         let client = self.client().await;
         let mut futures = Vec::new();
         for (shard, pointers) in pointers {
-            futures.push(self.retrieve_webpages_from_shard(shard, &client, query, pointers));
+            futures
+                .push(self.retrieve_webpages_from_shard(shard, &client, query, pointers, deadline));
         }
 
         let mut retrieved_webpages = Vec::new();
@@ -213,7 +285,8 @@ impl SearchClient for DistributedSearcher {
             }
         }
 
-        debug_assert_eq!(retrieved_webpages.len(), top_websites.len());
+        // shards that miss the deadline are left out
+        debug_assert!(deadline.is_some() || retrieved_webpages.len() == top_websites.len());
 
         retrieved_webpages.sort_by(|(a, _), (b, _)| a.cmp(b));
 
@@ -229,7 +302,7 @@ impl SearchClient for DistributedSearcher {
                     url: url.to_string(),
                 },
                 &AllShardsSelector,
-                &RandomReplicaSelector,
+                &self.replica_selector(),
             )
             .await
             .map_err(|_| Error::SearchFailed)?;
@@ -241,6 +314,79 @@ impl SearchClient for DistributedSearcher {
         }
     }
 
+    async fn get_cached_page(&self, url: &str) -> Option<CachedPage> {
+        let client = self.client().await;
+
+        // shards that fail to respond are left out
+        let res = client
+            .send(
+                &search_server::GetCachedPage {
+                    url: url.to_string(),
+                },
+                &AllShardsSelector,
+                &self.replica_selector(),
+            )
+            .await
+            .unwrap_or_default();
+
+        res.into_iter()
+            .flat_map(|(_, v)| v)
+            .flatten()
+            .max_by_key(|page| page.fetch_time_ms)
+    }
+
+    async fn sample(&self, num_docs: usize, fields: &[String], seed: Option<u64>) -> IndexSample {
+        let client = self.client().await;
+
+        let samples = match client
+            .send(
+                &search_server::SampleDocuments {
+                    num_docs,
+                    fields: fields.to_vec(),
+                    seed,
+                },
+                &AllShardsSelector,
+                &self.replica_selector(),
+            )
+            .await
+        {
+            Ok(res) => res.into_iter().flat_map(|(_, v)| v).flatten().collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut rng = seed
+            .map(StdRng::seed_from_u64)
+            .unwrap_or_else(StdRng::from_entropy);
+
+        sample::combine(samples, num_docs, &mut rng)
+    }
+
+    async fn delete_urls(&self, urls: &[Url]) -> Result<usize> {
+        let client = self.client().await;
+
+        let res = client
+            .send(
+                &search_server::DeleteUrls {
+                    urls: urls.to_vec(),
+                },
+                &AllShardsSelector,
+                &AllReplicaSelector,
+            )
+            .await
+            .map_err(|_| Error::DeleteFailed)?;
+
+        let mut num_deleted = 0;
+        for (_, replicas) in res {
+            let replicas: Option<Vec<usize>> = replicas.into_iter().collect();
+            let replicas = replicas.ok_or(Error::DeleteFailed)?;
+
+            // all replicas of a shard hold the same documents
+            num_deleted += replicas.into_iter().max().unwrap_or_default();
+        }
+
+        Ok(num_deleted)
+    }
+
     async fn get_homepage_descriptions(&self, urls: &[Url]) -> HashMap<Url, String> {
         let client = self.client().await;
 
@@ -250,7 +396,7 @@ impl SearchClient for DistributedSearcher {
                     urls: urls.to_vec(),
                 },
                 &AllShardsSelector,
-                &RandomReplicaSelector,
+                &self.replica_selector(),
             )
             .await;
 
@@ -301,15 +447,23 @@ impl SearchClient for DistributedSearcher {
 }
 
 pub trait SearchClient {
+    /// Identifies the generations of all the shard replicas in the cluster.
+    /// It changes whenever one of the shards is swapped for a new index.
+    /// `None` if the generation of a replica is unknown, e.g. during a rollout
+    /// from a version of the searchers that did not announce it.
+    fn index_generation(&self) -> impl Future<Output = Option<u64>> + Send;
+
     fn search_initial(
         &self,
         query: &SearchQuery,
+        deadline: Option<Deadline>,
     ) -> impl Future<Output = Vec<InitialSearchResultShard>> + Send;
 
     fn retrieve_webpages(
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        deadline: Option<Deadline>,
     ) -> impl Future<Output = Vec<(usize, RetrievedWebpageRanking)>> + Send;
 
     fn search_entity(&self, query: &str) -> impl Future<Output = Option<EntityMatch>> + Send;
@@ -324,6 +478,20 @@ pub trait SearchClient {
         urls: &[Url],
     ) -> impl Future<Output = HashMap<Url, String>> + Send;
 
+    /// The most recently fetched copy of the page across the shards.
+    fn get_cached_page(&self, url: &str) -> impl Future<Output = Option<CachedPage>> + Send;
+
+    /// Delete the documents with the urls from all replicas of all shards.
+    /// Returns the number of deleted documents.
+    fn delete_urls(&self, urls: &[Url]) -> impl Future<Output = Result<usize>> + Send;
+
+    fn sample(
+        &self,
+        num_docs: usize,
+        fields: &[String],
+        seed: Option<u64>,
+    ) -> impl Future<Output = IndexSample> + Send;
+
     fn get_entity_image(
         &self,
         image_id: &str,
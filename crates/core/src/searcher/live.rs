@@ -15,18 +15,27 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::{
+    cached_page::CachedPage,
     distributed::{
         cluster::Cluster,
         member::Service,
-        sonic::replication::{
-            AllShardsSelector, RandomReplicaSelector, RemoteClient, ReplicatedClient, Shard,
-            ShardIdentifier, ShardedClient, SpecificShardSelector,
+        sonic::{
+            self,
+            replication::{
+                AllReplicaSelector, AllShardsSelector, RandomReplicaSelector, RandomShardSelector,
+                RemoteClient, ReplicatedClient, Shard, ShardIdentifier, ShardedClient,
+                SpecificShardSelector,
+            },
         },
     },
-    entrypoint::search_server::{self, SearchService},
+    entrypoint::{
+        live_index,
+        search_server::{self, SearchService},
+    },
     feed::scheduler::SplitId,
     inverted_index::{RetrievedWebpage, WebsitePointer},
     ranking::pipeline::{RankingWebsite, RetrievedWebpageRanking},
+    Result,
 };
 
 use std::future::Future;
@@ -35,8 +44,13 @@ use std::{collections::HashMap, sync::Arc};
 use fnv::FnvHashMap;
 use futures::future::join_all;
 use itertools::Itertools;
+use url::Url;
 
-use super::{InitialWebsiteResult, SearchQuery};
+use super::{
+    budget::Deadline,
+    distributed::{timeout, Error},
+    InitialWebsiteResult, SearchQuery,
+};
 
 #[derive(Clone, Debug)]
 pub struct ScoredWebsitePointer {
@@ -62,6 +76,15 @@ impl LiveSearcher {
     }
 
     async fn client(&self) -> ShardedClient<SearchService, SplitId> {
+        self.sharded_client().await
+    }
+
+    /// Client for the requests that are only handled by the live index.
+    async fn live_client(&self) -> ShardedClient<live_index::SearchService, SplitId> {
+        self.sharded_client().await
+    }
+
+    async fn sharded_client<S: sonic::service::Service>(&self) -> ShardedClient<S, SplitId> {
         let mut shards = HashMap::new();
         for member in self.cluster.members().await {
             if let Service::LiveIndex { host, split_id } = member.service {
@@ -87,17 +110,19 @@ impl LiveSearcher {
         client: &ShardedClient<SearchService, SplitId>,
         query: &str,
         pointers: Vec<(usize, WebsitePointer)>,
+        deadline: Option<Deadline>,
     ) -> Vec<(usize, RetrievedWebpage)> {
         let (idxs, pointers): (Vec<usize>, Vec<WebsitePointer>) = pointers.into_iter().unzip();
 
         match client
-            .send(
+            .send_with_timeout(
                 &search_server::RetrieveWebsites {
                     websites: pointers,
                     query: query.to_string(),
                 },
                 &SpecificShardSelector(split),
                 &RandomReplicaSelector,
+                timeout(deadline),
             )
             .await
         {
@@ -115,17 +140,22 @@ impl LiveSearcher {
 }
 
 impl SearchClient for LiveSearcher {
-    async fn search_initial(&self, query: &SearchQuery) -> Vec<InitialSearchResultSplit> {
+    async fn search_initial(
+        &self,
+        query: &SearchQuery,
+        deadline: Option<Deadline>,
+    ) -> Vec<InitialSearchResultSplit> {
         let client = self.client().await;
         let mut results = Vec::new();
 
         if let Ok(res) = client
-            .send(
+            .send_with_timeout(
                 &search_server::Search {
                     query: query.clone(),
                 },
                 &AllShardsSelector,
                 &RandomReplicaSelector,
+                timeout(deadline),
             )
             .await
         {
@@ -146,6 +176,7 @@ impl SearchClient for LiveSearcher {
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        deadline: Option<Deadline>,
     ) -> Vec<(usize, RetrievedWebpageRanking)> {
         let mut rankings = FnvHashMap::default();
         let mut pointers: HashMap<_, Vec<_>> = HashMap::new();
@@ -162,7 +193,8 @@ impl SearchClient for LiveSearcher {
         let client = self.client().await;
         let mut futures = Vec::new();
         for (shard, pointers) in pointers {
-            futures.push(self.retrieve_webpages_from_shard(shard, &client, query, pointers));
+            futures
+                .push(self.retrieve_webpages_from_shard(shard, &client, query, pointers, deadline));
         }
 
         let mut retrieved_webpages = Vec::new();
@@ -173,22 +205,106 @@ impl SearchClient for LiveSearcher {
             }
         }
 
-        debug_assert_eq!(retrieved_webpages.len(), top_websites.len());
+        // splits that miss the deadline are left out
+        debug_assert!(deadline.is_some() || retrieved_webpages.len() == top_websites.len());
 
         retrieved_webpages.sort_by(|(a, _), (b, _)| a.cmp(b));
 
         retrieved_webpages
     }
+
+    async fn delete_urls(&self, urls: &[Url]) -> Result<usize> {
+        let client = self.live_client().await;
+
+        let res = client
+            .send(
+                &search_server::DeleteUrls {
+                    urls: urls.to_vec(),
+                },
+                &AllShardsSelector,
+                &AllReplicaSelector,
+            )
+            .await
+            .map_err(|_| Error::DeleteFailed)?;
+
+        let mut num_deleted = 0;
+        for (_, replicas) in res {
+            let replicas: Option<Vec<usize>> = replicas.into_iter().collect();
+            num_deleted += replicas
+                .ok_or(Error::DeleteFailed)?
+                .into_iter()
+                .max()
+                .unwrap_or_default();
+        }
+
+        Ok(num_deleted)
+    }
+
+    async fn get_cached_page(&self, url: &str) -> Option<CachedPage> {
+        let client = self.live_client().await;
+
+        let res = match client
+            .send(
+                &search_server::GetCachedPage {
+                    url: url.to_string(),
+                },
+                &AllShardsSelector,
+                &RandomReplicaSelector,
+            )
+            .await
+        {
+            Ok(res) => res,
+            Err(_) => return None,
+        };
+
+        res.into_iter()
+            .flat_map(|(_, v)| v)
+            .flatten()
+            .max_by_key(|page| page.fetch_time_ms)
+    }
+
+    async fn recrawl(&self, urls: &[Url]) -> Result<usize> {
+        let client = self.live_client().await;
+
+        // every replica of the split crawls its own copy of the pages
+        let res = client
+            .send(
+                &live_index::Recrawl {
+                    urls: urls.to_vec(),
+                },
+                &RandomShardSelector,
+                &AllReplicaSelector,
+            )
+            .await?;
+
+        Ok(res
+            .into_iter()
+            .flat_map(|(_, replicas)| replicas.into_iter().max())
+            .sum())
+    }
 }
 
 pub trait SearchClient {
     fn search_initial(
         &self,
         query: &SearchQuery,
+        deadline: Option<Deadline>,
     ) -> impl Future<Output = Vec<InitialSearchResultSplit>> + Send;
     fn retrieve_webpages(
         &self,
         top_websites: &[(usize, ScoredWebsitePointer)],
         query: &str,
+        deadline: Option<Deadline>,
     ) -> impl Future<Output = Vec<(usize, RetrievedWebpageRanking)>> + Send;
+
+    /// Delete the documents with the urls from all replicas of all splits.
+    /// Returns the number of deleted documents.
+    fn delete_urls(&self, urls: &[Url]) -> impl Future<Output = Result<usize>> + Send;
+
+    /// The most recently fetched cached page for the url in any of the splits.
+    fn get_cached_page(&self, url: &str) -> impl Future<Output = Option<CachedPage>> + Send;
+
+    /// Crawl the urls again and add them to the live index.
+    /// Returns the number of urls that were queued.
+    fn recrawl(&self, urls: &[Url]) -> impl Future<Output = Result<usize>> + Send;
 }
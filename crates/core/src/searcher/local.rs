@@ -15,24 +15,32 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::collections::HashMap;
-use std::sync::{Arc, RwLockReadGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
+use rand::{rngs::StdRng, SeedableRng};
 use url::Url;
 
-use crate::config::{CollectorConfig, SnippetConfig};
+use crate::cached_page::CachedPage;
+use crate::config::{
+    CollectorConfig, DenseRetrievalConfig, FreshnessConfig, RankingPipelineConfig, SnippetConfig,
+};
 use crate::index::Index;
-use crate::inverted_index::{InvertedIndex, RetrievedWebpage};
+use crate::inverted_index::{InvertedIndex, RetrievedWebpage, WebsitePointer};
+use crate::query::parser::Term;
 use crate::query::Query;
 use crate::ranking::inbound_similarity::InboundSimilarity;
+use crate::ranking::models::embedding::Embedder;
 use crate::ranking::models::lambdamart::LambdaMART;
 use crate::ranking::models::linear::LinearRegression;
+use crate::ranking::models::reloadable::Reloadable;
 use crate::ranking::pipeline::{RankingPipeline, RankingWebsite};
 use crate::ranking::{query_centrality, Ranker, Signal, SignalAggregator, ALL_SIGNALS};
 use crate::search_ctx::Ctx;
-use crate::search_prettifier::DisplayedWebpage;
+use crate::search_prettifier::{related_questions, Prettifier};
 use crate::webgraph::Node;
 use crate::{inverted_index, live_index, Error, Result};
 
+use super::sample::{self, IndexSample};
 use super::WebsitesResult;
 use super::{InitialWebsiteResult, SearchQuery};
 
@@ -88,6 +96,23 @@ impl SearchableIndex for Arc<live_index::Index> {
     }
 }
 
+impl SearchableIndex for Arc<RwLock<Index>> {
+    type SearchGuard<'a> = LiveIndexSearchGuard<'a>;
+
+    fn guard(&self) -> Self::SearchGuard<'_> {
+        LiveIndexSearchGuard {
+            lock_guard: self.read().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    fn set_snippet_config(&mut self, config: SnippetConfig) {
+        self.write()
+            .unwrap_or_else(|e| e.into_inner())
+            .inverted_index
+            .set_snippet_config(config);
+    }
+}
+
 pub struct LiveIndexSearchGuard<'a> {
     lock_guard: RwLockReadGuard<'a, crate::index::Index>,
 }
@@ -102,8 +127,12 @@ pub struct LocalSearcher<I: SearchableIndex> {
     index: I,
     inbound_similarity: Option<InboundSimilarity>,
     linear_regression: Option<Arc<LinearRegression>>,
-    lambda_model: Option<Arc<LambdaMART>>,
+    lambda_model: Arc<Reloadable<LambdaMART>>,
     collector_config: CollectorConfig,
+    freshness: FreshnessConfig,
+    ranking_pipeline: RankingPipelineConfig,
+    embedder: Option<Arc<dyn Embedder>>,
+    dense_retrieval: DenseRetrievalConfig,
 }
 
 impl<I> From<I> for LocalSearcher<I>
@@ -130,8 +159,12 @@ where
             index,
             inbound_similarity: None,
             linear_regression: None,
-            lambda_model: None,
+            lambda_model: Arc::new(Reloadable::default()),
             collector_config: CollectorConfig::default(),
+            freshness: FreshnessConfig::default(),
+            ranking_pipeline: RankingPipelineConfig::default(),
+            embedder: None,
+            dense_retrieval: DenseRetrievalConfig::default(),
         }
     }
 
@@ -143,8 +176,12 @@ where
         self.linear_regression = Some(Arc::new(model));
     }
 
-    pub fn set_lambda_model(&mut self, model: LambdaMART) {
-        self.lambda_model = Some(Arc::new(model));
+    pub fn set_lambda_model(&mut self, model: Reloadable<LambdaMART>) {
+        self.lambda_model = Arc::new(model);
+    }
+
+    pub fn lambda_model(&self) -> &Arc<Reloadable<LambdaMART>> {
+        &self.lambda_model
     }
 
     pub fn set_collector_config(&mut self, config: CollectorConfig) {
@@ -155,6 +192,22 @@ where
         self.index.set_snippet_config(config);
     }
 
+    pub fn set_freshness_config(&mut self, config: FreshnessConfig) {
+        self.freshness = config;
+    }
+
+    pub fn set_ranking_pipeline_config(&mut self, config: RankingPipelineConfig) {
+        self.ranking_pipeline = config;
+    }
+
+    pub fn set_embedder(&mut self, embedder: Arc<dyn Embedder>) {
+        self.embedder = Some(embedder);
+    }
+
+    pub fn set_dense_retrieval_config(&mut self, config: DenseRetrievalConfig) {
+        self.dense_retrieval = config;
+    }
+
     fn parse_query<'a, G: SearchGuard<'a>>(
         &'a self,
         ctx: &Ctx,
@@ -228,13 +281,15 @@ where
         let mut query = query.clone();
         let pipeline: RankingPipeline<RankingWebsite> = RankingPipeline::recall_stage(
             &mut query,
-            self.lambda_model.clone(),
+            &self.ranking_pipeline.recall,
+            self.lambda_model.get(),
             self.collector_config.clone(),
             100,
         );
         let parsed_query = self.parse_query(ctx, guard, &query)?;
 
         let mut aggregator = SignalAggregator::new(Some(&parsed_query));
+        aggregator.set_freshness(self.freshness);
 
         if let Some(inbound_sim) = &self.inbound_similarity {
             let liked_hosts: Vec<_> = parsed_query
@@ -266,6 +321,15 @@ where
                 .clone(),
         );
 
+        aggregator.set_host_aggregates(
+            guard
+                .search_index()
+                .host_stats
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .aggregates(),
+        );
+
         if let Some(model) = self.linear_regression.as_ref() {
             aggregator.set_linear_model(model.clone());
         }
@@ -278,11 +342,16 @@ where
             ranker.collector(ctx.clone()),
         )?;
 
+        let mut top_websites = res.top_websites;
+        let semantic =
+            self.semantic_candidates(ctx, guard, &query, &parsed_query, &top_websites)?;
+        top_websites.extend(semantic);
+
         let fastfield_reader = guard.inverted_index().fastfield_reader();
 
         let ranking_websites = guard.inverted_index().retrieve_ranking_websites(
             ctx,
-            res.top_websites,
+            top_websites,
             ranker.aggregator(),
             &fastfield_reader,
         )?;
@@ -299,6 +368,70 @@ where
         })
     }
 
+    /// Pages that are semantically similar to the query but not among the lexical matches.
+    /// Only used for plain queries with few lexical matches, since the semantic
+    /// candidates are not filtered by the optics and operators of the query.
+    fn semantic_candidates<'a, G: SearchGuard<'a>>(
+        &'a self,
+        ctx: &Ctx,
+        guard: &G,
+        query: &SearchQuery,
+        parsed_query: &Query,
+        lexical: &[WebsitePointer],
+    ) -> Result<Vec<WebsitePointer>> {
+        let Some(embedder) = self.embedder.as_ref() else {
+            return Ok(Vec::new());
+        };
+
+        let is_plain = query.optic.is_none()
+            && !query.safe_search
+            && parsed_query.optics().is_empty()
+            && parsed_query
+                .terms()
+                .iter()
+                .all(|term| matches!(term.as_ref(), Term::Simple(_)));
+
+        if !is_plain
+            || parsed_query.simple_terms().is_empty()
+            || lexical.len() >= self.dense_retrieval.min_lexical_hits
+        {
+            return Ok(Vec::new());
+        }
+
+        let Some(embedding) = embedder
+            .embed(&[parsed_query.simple_terms().join(" ")])?
+            .pop()
+        else {
+            return Ok(Vec::new());
+        };
+
+        let urls: Vec<_> = guard
+            .search_index()
+            .embeddings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .search(
+                &embedding,
+                self.dense_retrieval.num_candidates,
+                self.dense_retrieval.ef_search,
+            )
+            .into_iter()
+            .filter(|(_, similarity)| *similarity >= self.dense_retrieval.min_similarity)
+            .map(|(url, similarity)| (url, similarity as f64))
+            .collect();
+
+        Ok(guard
+            .inverted_index()
+            .website_pointers(ctx, &urls)?
+            .into_iter()
+            .filter(|pointer| {
+                !lexical
+                    .iter()
+                    .any(|website| website.address == pointer.address)
+            })
+            .collect())
+    }
+
     pub fn search_initial(
         &self,
         query: &SearchQuery,
@@ -348,6 +481,7 @@ where
             match CrossEncoderModel::open("data/cross_encoder") {
                 Ok(model) => RankingPipeline::reranker::<CrossEncoderModel>(
                     &mut search_query,
+                    &self.ranking_pipeline.rerank,
                     Some(Arc::new(model)),
                     None,
                     self.collector_config.clone(),
@@ -355,6 +489,7 @@ where
                 )?,
                 Err(_) => RankingPipeline::reranker::<CrossEncoderModel>(
                     &mut search_query,
+                    &self.ranking_pipeline.rerank,
                     None,
                     None,
                     self.collector_config.clone(),
@@ -378,11 +513,15 @@ where
 
         let retrieved_sites = self.retrieve_websites(&pointers, &search_query.query)?;
 
+        let prettifier =
+            Prettifier::new(&query.prettifier).with_region(query.selected_region, None);
         let mut webpages: Vec<_> = retrieved_sites
             .into_iter()
-            .map(DisplayedWebpage::from)
+            .map(|webpage| prettifier.prettify(webpage))
             .collect();
 
+        let lambda_model = self.lambda_model.get();
+
         for (webpage, ranking) in webpages.iter_mut().zip(top_websites) {
             let mut ranking_signals = HashMap::new();
 
@@ -393,13 +532,21 @@ where
             }
 
             webpage.ranking_signals = Some(ranking_signals);
+            webpage.relevance = lambda_model
+                .as_ref()
+                .zip(ranking.model_score)
+                .and_then(|(model, score)| model.calibrate(score));
         }
 
+        let related_questions = related_questions(&query.query, &webpages);
+
         Ok(WebsitesResult {
             num_hits: search_result.num_websites,
             webpages,
+            related_questions,
             search_duration_ms: start.elapsed().as_millis(),
             has_more_results,
+            experiment: None,
         })
     }
 
@@ -410,6 +557,29 @@ where
     pub fn get_homepage(&self, url: &Url) -> Option<RetrievedWebpage> {
         self.index.guard().inverted_index().get_homepage(url)
     }
+
+    pub fn get_cached_page(&self, url: &str) -> Option<CachedPage> {
+        self.index.guard().search_index().cached_pages.get(url)
+    }
+
+    /// Uniform random sample of the documents in the index with the selected stored fields.
+    pub fn sample(
+        &self,
+        num_docs: usize,
+        fields: &[String],
+        seed: Option<u64>,
+    ) -> Result<IndexSample> {
+        let mut rng = seed
+            .map(StdRng::seed_from_u64)
+            .unwrap_or_else(StdRng::from_entropy);
+
+        sample::sample(
+            &self.index.guard().inverted_index().tv_searcher(),
+            num_docs,
+            fields,
+            &mut rng,
+        )
+    }
 }
 
 #[cfg(test)]
@@ -479,4 +649,80 @@ mod tests {
             }
         }
     }
+
+    /// Embeds texts about dogs close to each other.
+    struct DogEmbedder;
+
+    impl Embedder for DogEmbedder {
+        fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    let text = text.to_lowercase();
+                    if text.contains("dog") || text.contains("puppy") {
+                        vec![1.0, 0.0]
+                    } else {
+                        vec![0.0, 1.0]
+                    }
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn semantic_candidates_for_few_lexical_matches() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, title, embedding) in [
+            ("https://www.puppy.com", "Puppy training", vec![1.0, 0.0]),
+            ("https://www.cars.com", "Fast cars", vec![0.0, 1.0]),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+            <html>
+                <head>
+                    <title>{title}</title>
+                </head>
+                <body>
+                    {}
+                </body>
+            </html>
+            "#,
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    embedding: Some(embedding),
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().unwrap();
+
+        let mut searcher = LocalSearcher::new(index);
+
+        let query = SearchQuery {
+            query: "dog".to_string(),
+            ..Default::default()
+        };
+
+        assert!(searcher.search(&query).unwrap().webpages.is_empty());
+
+        searcher.set_embedder(Arc::new(DogEmbedder));
+
+        let urls: Vec<_> = searcher
+            .search(&query)
+            .unwrap()
+            .webpages
+            .into_iter()
+            .map(|page| page.url)
+            .collect();
+
+        assert_eq!(urls, vec!["https://www.puppy.com/".to_string()]);
+    }
 }
@@ -15,9 +15,11 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 pub mod api;
+pub mod budget;
 pub mod distributed;
 pub mod live;
 pub mod local;
+pub mod sample;
 
 pub use distributed::*;
 pub use local::*;
@@ -25,9 +27,13 @@ use optics::{HostRankings, Optic};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use self::budget::SearchBudget;
 use crate::{
-    bangs::BangHit, config::defaults, ranking::pipeline::RankingWebsite,
-    search_prettifier::DisplayedWebpage, webpage::region::Region,
+    bangs::BangHit,
+    config::defaults,
+    ranking::{experiment::ExperimentArm, pipeline::RankingWebsite, SignalCoefficient},
+    search_prettifier::{DisplayedWebpage, PrettifierOptions, RelatedQuestion},
+    webpage::region::Region,
 };
 
 pub const NUM_RESULTS_PER_PAGE: usize = 20;
@@ -42,9 +48,12 @@ pub enum SearchResult {
 #[serde(rename_all = "camelCase")]
 pub struct WebsitesResult {
     pub webpages: Vec<DisplayedWebpage>,
+    pub related_questions: Vec<RelatedQuestion>,
     pub num_hits: Option<usize>,
     pub search_duration_ms: u128,
     pub has_more_results: bool,
+    /// The arm of the ranking experiment that was used for the search.
+    pub experiment: Option<ExperimentArm>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -54,10 +63,22 @@ pub struct SearchQuery {
     pub num_results: usize,
     pub selected_region: Option<Region>,
     pub optic: Option<Optic>,
+    /// Optics configured by the operator. They are applied in addition to `optic`,
+    /// which takes precedence for signal coefficients.
+    pub default_optics: Vec<Optic>,
     pub host_rankings: Option<HostRankings>,
     pub return_ranking_signals: bool,
     pub safe_search: bool,
+    pub accessible_only: bool,
     pub count_results: bool,
+    pub prettifier: PrettifierOptions,
+    /// Used to assign the search to an arm of the ranking experiment.
+    pub client_id: Option<String>,
+    /// Latency budget of the search, from the caller or the api config.
+    /// The shards get the deadlines derived from it as request timeouts,
+    /// so the budget itself is never sent to them.
+    #[serde(skip)]
+    pub budget: Option<SearchBudget>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,10 +99,15 @@ impl Default for SearchQuery {
             num_results: NUM_RESULTS_PER_PAGE,
             selected_region: Default::default(),
             optic: Default::default(),
+            default_optics: Default::default(),
             host_rankings: Default::default(),
             return_ranking_signals: defaults::SearchQuery::return_ranking_signals(),
             safe_search: defaults::SearchQuery::safe_search(),
+            accessible_only: Default::default(),
             count_results: defaults::SearchQuery::count_results(),
+            prettifier: Default::default(),
+            client_id: Default::default(),
+            budget: Default::default(),
         }
     }
 }
@@ -90,4 +116,14 @@ impl SearchQuery {
     pub fn is_empty(&self) -> bool {
         self.query.is_empty()
     }
+
+    pub fn signal_coefficients(&self) -> Option<SignalCoefficient> {
+        if self.optic.is_none() && self.default_optics.is_empty() {
+            return None;
+        }
+
+        Some(SignalCoefficient::from_optics(
+            self.default_optics.iter().chain(self.optic.as_ref()),
+        ))
+    }
 }
@@ -0,0 +1,343 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Uniform random samples of the indexed documents, used to audit the corpus
+//! (language mix, spam rate, length distribution etc.) without exporting the entire index.
+//!
+//! Every shard samples its own documents. The shard samples are then combined by
+//! repeatedly picking a shard with probability proportional to the number of documents
+//! it has left, which gives a uniform sample of the entire index.
+
+use std::collections::BTreeMap;
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use tantivy::{schema::Value, DocAddress, DocId, TantivyDocument};
+
+use crate::Result;
+
+/// Fields that are returned if none are requested.
+pub const DEFAULT_SAMPLE_FIELDS: [&str; 2] = ["url", "title"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StoredValue {
+    Text(String),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+}
+
+impl From<StoredValue> for serde_json::Value {
+    fn from(value: StoredValue) -> Self {
+        match value {
+            StoredValue::Text(text) => serde_json::Value::String(text),
+            StoredValue::U64(n) => n.into(),
+            StoredValue::I64(n) => n.into(),
+            StoredValue::F64(n) => n.into(),
+            StoredValue::Bool(b) => b.into(),
+        }
+    }
+}
+
+/// The selected stored fields of a document by their name in the schema.
+pub type SampledDocument = BTreeMap<String, StoredValue>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexSample {
+    /// Number of documents the sample was drawn from.
+    pub num_docs: u64,
+    pub documents: Vec<SampledDocument>,
+}
+
+fn select_fields(
+    doc: &TantivyDocument,
+    fields: &[(tantivy::schema::Field, String)],
+) -> SampledDocument {
+    let mut res = SampledDocument::new();
+
+    for field_value in doc.field_values() {
+        let Some((_, name)) = fields.iter().find(|(field, _)| *field == field_value.field) else {
+            continue;
+        };
+
+        let value = field_value.value().as_value();
+        let value = if let Some(text) = value.as_str() {
+            StoredValue::Text(text.to_string())
+        } else if let Some(n) = value.as_u64() {
+            StoredValue::U64(n)
+        } else if let Some(n) = value.as_i64() {
+            StoredValue::I64(n)
+        } else if let Some(n) = value.as_f64() {
+            StoredValue::F64(n)
+        } else if let Some(b) = value.as_bool() {
+            StoredValue::Bool(b)
+        } else {
+            continue;
+        };
+
+        // only keep the first value of multi-valued fields
+        res.entry(name.clone()).or_insert(value);
+    }
+
+    res
+}
+
+/// Sample `num_docs` documents without replacement from the searcher. The documents are
+/// returned in random order, so any prefix of the sample is also a uniform sample.
+pub fn sample<R: Rng>(
+    searcher: &tantivy::Searcher,
+    num_docs: usize,
+    fields: &[String],
+    rng: &mut R,
+) -> Result<IndexSample> {
+    let schema = searcher.schema();
+    let fields = fields
+        .iter()
+        .map(|name| Ok((schema.get_field(name)?, name.clone())))
+        .collect::<Result<Vec<_>>>()?;
+
+    let total: u64 = searcher
+        .segment_readers()
+        .iter()
+        .map(|segment| u64::from(segment.num_docs()))
+        .sum();
+
+    let amount = (num_docs as u64).min(total) as usize;
+    let mut idxs = rand::seq::index::sample(rng, total as usize, amount).into_vec();
+    idxs.sort_unstable();
+
+    let mut idxs = idxs.into_iter().peekable();
+    let mut documents = Vec::with_capacity(amount);
+    let mut offset = 0;
+
+    for (segment_ord, segment) in searcher.segment_readers().iter().enumerate() {
+        let num_alive = segment.num_docs() as usize;
+        let alive: Option<Vec<DocId>> = if segment.has_deletes() {
+            Some(segment.doc_ids_alive().collect())
+        } else {
+            None
+        };
+
+        while let Some(idx) = idxs.next_if(|idx| *idx < offset + num_alive) {
+            let local = idx - offset;
+            let doc_id = match &alive {
+                Some(alive) => alive[local],
+                None => local as DocId,
+            };
+
+            let doc: TantivyDocument = searcher.doc(DocAddress::new(segment_ord as u32, doc_id))?;
+            documents.push(select_fields(&doc, &fields));
+        }
+
+        offset += num_alive;
+    }
+
+    documents.shuffle(rng);
+
+    Ok(IndexSample {
+        num_docs: total,
+        documents,
+    })
+}
+
+/// Combine the samples of the shards into a uniform sample of `num_docs` documents from all the shards.
+pub fn combine<R: Rng>(samples: Vec<IndexSample>, num_docs: usize, rng: &mut R) -> IndexSample {
+    let total: u64 = samples.iter().map(|sample| sample.num_docs).sum();
+    let mut remaining: Vec<u64> = samples.iter().map(|sample| sample.num_docs).collect();
+    let mut remaining_total = total;
+
+    let mut shards: Vec<_> = samples
+        .into_iter()
+        .map(|sample| sample.documents.into_iter())
+        .collect();
+
+    let mut documents = Vec::with_capacity(num_docs);
+
+    while documents.len() < num_docs && remaining_total > 0 {
+        let mut pick = rng.gen_range(0..remaining_total);
+        let shard = remaining
+            .iter()
+            .position(|num_docs| {
+                if pick < *num_docs {
+                    true
+                } else {
+                    pick -= num_docs;
+                    false
+                }
+            })
+            .unwrap();
+
+        match shards[shard].next() {
+            Some(doc) => {
+                remaining[shard] -= 1;
+                remaining_total -= 1;
+                documents.push(doc);
+            }
+            None => {
+                // the shard sampled fewer documents than requested, so it can not be picked again
+                remaining_total -= remaining[shard];
+                remaining[shard] = 0;
+            }
+        }
+    }
+
+    IndexSample {
+        num_docs: total,
+        documents,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        index::Index,
+        webpage::{Html, Webpage},
+    };
+
+    use super::*;
+
+    fn index(num_docs: usize) -> Index {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for i in 0..num_docs {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            "<html><head><title>Page {i}</title></head><body>body</body></html>"
+                        ),
+                        &format!("https://www.{i}.com"),
+                    )
+                    .unwrap(),
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        index
+    }
+
+    fn fields() -> Vec<String> {
+        DEFAULT_SAMPLE_FIELDS
+            .iter()
+            .map(|f| f.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sample_documents() {
+        let index = index(20);
+        let searcher = index.inverted_index.tv_searcher();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let sample = sample(&searcher, 5, &fields(), &mut rng).unwrap();
+
+        assert_eq!(sample.num_docs, 20);
+        assert_eq!(sample.documents.len(), 5);
+
+        for doc in &sample.documents {
+            assert_eq!(doc.len(), 2);
+            assert!(
+                matches!(&doc["url"], StoredValue::Text(url) if url.starts_with("https://www."))
+            );
+            assert!(matches!(&doc["title"], StoredValue::Text(title) if title.starts_with("Page")));
+        }
+
+        let urls: std::collections::HashSet<_> = sample
+            .documents
+            .iter()
+            .map(|doc| format!("{:?}", doc["url"]))
+            .collect();
+        assert_eq!(urls.len(), 5);
+    }
+
+    #[test]
+    fn sample_larger_than_index() {
+        let index = index(3);
+        let searcher = index.inverted_index.tv_searcher();
+
+        let sample = sample(&searcher, 10, &fields(), &mut StdRng::seed_from_u64(1)).unwrap();
+        assert_eq!(sample.documents.len(), 3);
+    }
+
+    #[test]
+    fn unknown_field() {
+        let index = index(3);
+        let searcher = index.inverted_index.tv_searcher();
+
+        assert!(sample(
+            &searcher,
+            1,
+            &["not_a_field".to_string()],
+            &mut StdRng::seed_from_u64(1)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn combine_is_proportional_to_shard_size() {
+        let shard = |name: &str, num_docs: u64| IndexSample {
+            num_docs,
+            documents: (0..100)
+                .map(|_| {
+                    [("shard".to_string(), StoredValue::Text(name.to_string()))]
+                        .into_iter()
+                        .collect()
+                })
+                .collect(),
+        };
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut from_large = 0;
+
+        for _ in 0..100 {
+            let combined = combine(
+                vec![shard("small", 1_000), shard("large", 9_000)],
+                10,
+                &mut rng,
+            );
+
+            assert_eq!(combined.num_docs, 10_000);
+            assert_eq!(combined.documents.len(), 10);
+
+            from_large += combined
+                .documents
+                .iter()
+                .filter(|doc| doc["shard"] == StoredValue::Text("large".to_string()))
+                .count();
+        }
+
+        // 90% of the documents are expected to come from the large shard
+        assert!((850..=950).contains(&from_large));
+    }
+
+    #[test]
+    fn combine_small_shards() {
+        let shard = |num_docs: u64| IndexSample {
+            num_docs,
+            documents: (0..num_docs).map(|_| SampledDocument::new()).collect(),
+        };
+
+        let combined = combine(vec![shard(2), shard(3)], 10, &mut StdRng::seed_from_u64(1));
+
+        assert_eq!(combined.documents.len(), 5);
+    }
+}
@@ -20,7 +20,6 @@ use crate::config::SnippetConfig;
 use crate::query::Query;
 use crate::tokenizer::{BigramTokenizer, Normal, Stemmed, Tokenizer, TrigramTokenizer};
 use crate::web_spell::sentence_ranges;
-use crate::webpage::region::Region;
 use hashbrown::{HashMap, HashSet};
 use utoipa::ToSchema;
 
@@ -310,8 +309,13 @@ fn snippet_string(
     snippet_string_builder(text, terms, lang, config, tokenizer).build()
 }
 
-pub fn generate(query: &Query, text: &str, region: &Region, config: SnippetConfig) -> TextSnippet {
-    let lang = match region.lang() {
+pub fn generate(
+    query: &Query,
+    text: &str,
+    lang: Option<Lang>,
+    config: SnippetConfig,
+) -> TextSnippet {
+    let lang = match lang {
         Some(lang) => lang,
         None => match config.num_words_for_lang_detection {
             Some(num_words) => whatlang::detect_lang(
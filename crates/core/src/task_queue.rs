@@ -0,0 +1,889 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Persistent queue for background jobs such as recrawl scheduling and fetching of favicons.
+//!
+//! Tasks are stored in RocksDB, so they survive restarts. A worker leases a task from a named
+//! queue and either completes or fails it. Failed tasks are retried with exponential backoff
+//! until they have been attempted `max_attempts` times, after which they are moved to the
+//! dead letters of the queue for manual inspection. If a worker dies while holding a task,
+//! the lease expires and the task is handed out again, so handlers must be idempotent.
+
+use std::{
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    config::TaskQueueConfig,
+    metrics::{Counter, Label, PrometheusRegistry},
+    Result,
+};
+
+const TASK_PREFIX: u8 = b't';
+const READY_PREFIX: u8 = b'r';
+const DEAD_PREFIX: u8 = b'd';
+const RECURRING_PREFIX: u8 = b'c';
+
+pub static METRICS: Lazy<TaskQueueMetrics> = Lazy::new(TaskQueueMetrics::default);
+
+#[derive(Default, Clone)]
+pub struct TaskQueueMetrics {
+    pub enqueued: Counter,
+    pub completed: Counter,
+    pub retried: Counter,
+    pub dead_lettered: Counter,
+}
+
+impl TaskQueueMetrics {
+    pub fn register(&self, registry: &mut PrometheusRegistry) {
+        let group = registry
+            .new_group(
+                "stract_background_tasks".to_string(),
+                Some("Number of background tasks by what happened to them.".to_string()),
+            )
+            .unwrap();
+
+        for (metric, status) in [
+            (&self.enqueued, "enqueued"),
+            (&self.completed, "completed"),
+            (&self.retried, "retried"),
+            (&self.dead_lettered, "dead_lettered"),
+        ] {
+            group.register(
+                metric.clone(),
+                vec![Label {
+                    key: "status".to_string(),
+                    val: status.to_string(),
+                }],
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TaskId(Uuid);
+
+impl TaskId {
+    fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TaskRecord {
+    queue: String,
+    payload: Vec<u8>,
+    attempts: u32,
+    /// When the task can be leased. For leased tasks this is when the lease expires.
+    ready_at_ms: u64,
+    last_error: Option<String>,
+}
+
+/// A task that has been leased by a worker.
+#[derive(Debug, Clone)]
+pub struct Task<T> {
+    pub id: TaskId,
+    pub queue: String,
+    /// Number of times the task has been leased, including this time.
+    pub attempts: u32,
+    pub payload: T,
+}
+
+/// A task that failed too many times.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: TaskId,
+    pub queue: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub failed_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DeadRecord {
+    task: TaskRecord,
+    failed_at_ms: u64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn queue_prefix(kind: u8, queue: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(3 + queue.len());
+    key.push(kind);
+    key.extend_from_slice(&(queue.len() as u16).to_be_bytes());
+    key.extend_from_slice(queue.as_bytes());
+
+    key
+}
+
+fn task_key(id: &TaskId) -> Vec<u8> {
+    let mut key = vec![TASK_PREFIX];
+    key.extend_from_slice(id.0.as_bytes());
+
+    key
+}
+
+/// Ready keys are sorted by the time the task can be leased, so the first
+/// key of a queue is always the next task to run.
+fn ready_key(queue: &str, ready_at_ms: u64, id: &TaskId) -> Vec<u8> {
+    let mut key = queue_prefix(READY_PREFIX, queue);
+    key.extend_from_slice(&ready_at_ms.to_be_bytes());
+    key.extend_from_slice(id.0.as_bytes());
+
+    key
+}
+
+/// Holds the interval of recurring tasks. It is kept apart from the
+/// task record so the records stored before it was added can still be read.
+fn recurring_key(id: &TaskId) -> Vec<u8> {
+    let mut key = vec![RECURRING_PREFIX];
+    key.extend_from_slice(id.0.as_bytes());
+
+    key
+}
+
+fn dead_key(queue: &str, id: &TaskId) -> Vec<u8> {
+    let mut key = queue_prefix(DEAD_PREFIX, queue);
+    key.extend_from_slice(id.0.as_bytes());
+
+    key
+}
+
+fn parse_ready_key(key: &[u8], prefix_len: usize) -> Option<(u64, TaskId)> {
+    let ready_at_ms = u64::from_be_bytes(key.get(prefix_len..prefix_len + 8)?.try_into().ok()?);
+    let id = Uuid::from_slice(key.get(prefix_len + 8..)?).ok()?;
+
+    Some((ready_at_ms, TaskId(id)))
+}
+
+fn new_record<T: Serialize>(queue: &str, payload: &T, delay: Duration) -> Result<TaskRecord> {
+    Ok(TaskRecord {
+        queue: queue.to_string(),
+        payload: bincode::serialize(payload)?,
+        attempts: 0,
+        ready_at_ms: now_ms() + delay.as_millis() as u64,
+        last_error: None,
+    })
+}
+
+pub struct TaskQueue {
+    db: rocksdb::DB,
+    config: TaskQueueConfig,
+    // guards the read-modify-write of the tasks
+    lock: Mutex<()>,
+}
+
+impl TaskQueue {
+    pub fn open<P: AsRef<Path>>(path: P, config: TaskQueueConfig) -> Result<Self> {
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+
+        let db = rocksdb::DB::open(&options, path)?;
+
+        Ok(Self {
+            db,
+            config,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn from_config(config: &TaskQueueConfig) -> Result<Self> {
+        Self::open(&config.path, config.clone())
+    }
+
+    fn get(&self, id: &TaskId) -> Result<Option<TaskRecord>> {
+        match self.db.get(task_key(id))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Interval of the task if it is recurring.
+    fn interval(&self, id: &TaskId) -> Result<Option<Duration>> {
+        match self.db.get(recurring_key(id))? {
+            Some(bytes) => Ok(bytes
+                .as_slice()
+                .try_into()
+                .ok()
+                .map(|bytes| Duration::from_millis(u64::from_be_bytes(bytes)))),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, batch: &mut rocksdb::WriteBatch, id: &TaskId, record: &TaskRecord) -> Result<()> {
+        batch.put(task_key(id), bincode::serialize(record)?);
+        batch.put(ready_key(&record.queue, record.ready_at_ms, id), b"");
+
+        Ok(())
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+
+        Duration::from_millis(
+            self.config
+                .retry_backoff_ms
+                .saturating_mul(factor)
+                .min(self.config.max_retry_backoff_ms),
+        )
+    }
+
+    pub fn push<T: Serialize>(&self, queue: &str, payload: &T) -> Result<TaskId> {
+        self.push_delayed(queue, payload, Duration::ZERO)
+    }
+
+    /// Add a task that can not be leased before the delay has passed.
+    pub fn push_delayed<T: Serialize>(
+        &self,
+        queue: &str,
+        payload: &T,
+        delay: Duration,
+    ) -> Result<TaskId> {
+        let id = TaskId::new();
+        let mut batch = rocksdb::WriteBatch::default();
+        self.put(&mut batch, &id, &new_record(queue, payload, delay)?)?;
+        self.db.write(batch)?;
+
+        METRICS.enqueued.inc();
+
+        Ok(id)
+    }
+
+    /// Make the tasks of the queue recurring with the interval, or add a
+    /// recurring task if the queue is empty. Recurring tasks are re-inserted
+    /// with their next due time when they are completed or run out of attempts.
+    pub fn schedule_recurring<T: Serialize>(
+        &self,
+        queue: &str,
+        payload: &T,
+        interval: Duration,
+    ) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let prefix = queue_prefix(READY_PREFIX, queue);
+        let interval_ms = (interval.as_millis() as u64).to_be_bytes();
+
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut is_empty = true;
+
+        // the tasks from before a restart are still queued
+        for item in self.db.iterator(rocksdb::IteratorMode::From(
+            &prefix,
+            rocksdb::Direction::Forward,
+        )) {
+            let (key, _) = item?;
+
+            if !key.starts_with(&prefix) {
+                break;
+            }
+
+            if let Some((_, id)) = parse_ready_key(&key, prefix.len()) {
+                batch.put(recurring_key(&id), interval_ms);
+                is_empty = false;
+            }
+        }
+
+        if is_empty {
+            let id = TaskId::new();
+            self.put(
+                &mut batch,
+                &id,
+                &new_record(queue, payload, Duration::ZERO)?,
+            )?;
+            batch.put(recurring_key(&id), interval_ms);
+
+            METRICS.enqueued.inc();
+        }
+
+        self.db.write(batch)?;
+
+        Ok(())
+    }
+
+    fn reschedule(
+        &self,
+        batch: &mut rocksdb::WriteBatch,
+        id: &TaskId,
+        mut record: TaskRecord,
+        interval: Duration,
+    ) -> Result<()> {
+        batch.delete(ready_key(&record.queue, record.ready_at_ms, id));
+        record.attempts = 0;
+        record.ready_at_ms = now_ms() + interval.as_millis() as u64;
+        self.put(batch, id, &record)
+    }
+
+    /// Move the task to the dead letters, or wait for the next run if it is recurring.
+    fn give_up(
+        &self,
+        batch: &mut rocksdb::WriteBatch,
+        id: &TaskId,
+        record: TaskRecord,
+    ) -> Result<()> {
+        match self.interval(id)? {
+            Some(interval) => {
+                tracing::warn!(
+                    "recurring task {id} in queue {} failed {} times: {:?}",
+                    record.queue,
+                    record.attempts,
+                    record.last_error
+                );
+
+                self.reschedule(batch, id, record, interval)
+            }
+            None => self.kill(batch, id, record),
+        }
+    }
+
+    fn kill(&self, batch: &mut rocksdb::WriteBatch, id: &TaskId, record: TaskRecord) -> Result<()> {
+        tracing::warn!(
+            "task {id} in queue {} failed {} times: {:?}",
+            record.queue,
+            record.attempts,
+            record.last_error
+        );
+
+        batch.delete(task_key(id));
+        batch.delete(ready_key(&record.queue, record.ready_at_ms, id));
+        batch.delete(recurring_key(id));
+        batch.put(
+            dead_key(&record.queue, id),
+            bincode::serialize(&DeadRecord {
+                task: record,
+                failed_at_ms: now_ms(),
+            })?,
+        );
+
+        METRICS.dead_lettered.inc();
+
+        Ok(())
+    }
+
+    /// Lease the next task of the queue that is ready to run.
+    /// The task is handed out again if it is neither completed nor failed before the lease expires.
+    pub fn lease<T: DeserializeOwned>(&self, queue: &str) -> Result<Option<Task<T>>> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let prefix = queue_prefix(READY_PREFIX, queue);
+        let now = now_ms();
+
+        loop {
+            let next = self
+                .db
+                .iterator(rocksdb::IteratorMode::From(
+                    &prefix,
+                    rocksdb::Direction::Forward,
+                ))
+                .next()
+                .transpose()?
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .and_then(|(key, _)| parse_ready_key(&key, prefix.len()));
+
+            let (ready_at_ms, id) = match next {
+                Some((ready_at_ms, id)) if ready_at_ms <= now => (ready_at_ms, id),
+                _ => return Ok(None),
+            };
+
+            let mut batch = rocksdb::WriteBatch::default();
+
+            let mut record = match self.get(&id)? {
+                Some(record) => record,
+                None => {
+                    // dangling index entry
+                    batch.delete(ready_key(queue, ready_at_ms, &id));
+                    self.db.write(batch)?;
+                    continue;
+                }
+            };
+
+            if record.attempts >= self.config.max_attempts {
+                // the lease of the last attempt expired without the task being completed
+                record.last_error = record
+                    .last_error
+                    .or_else(|| Some("lease expired".to_string()));
+                self.give_up(&mut batch, &id, record)?;
+                self.db.write(batch)?;
+                continue;
+            }
+
+            let payload = match bincode::deserialize(&record.payload) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    record.last_error = Some(format!("invalid payload: {err}"));
+                    self.kill(&mut batch, &id, record)?;
+                    self.db.write(batch)?;
+                    continue;
+                }
+            };
+
+            batch.delete(ready_key(queue, record.ready_at_ms, &id));
+            record.attempts += 1;
+            record.ready_at_ms = now + self.config.lease_timeout_sec * 1000;
+            self.put(&mut batch, &id, &record)?;
+            self.db.write(batch)?;
+
+            return Ok(Some(Task {
+                id,
+                queue: record.queue,
+                attempts: record.attempts,
+                payload,
+            }));
+        }
+    }
+
+    pub fn complete(&self, id: &TaskId) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(record) = self.get(id)? {
+            let mut batch = rocksdb::WriteBatch::default();
+
+            match self.interval(id)? {
+                Some(interval) => self.reschedule(&mut batch, id, record, interval)?,
+                None => {
+                    batch.delete(task_key(id));
+                    batch.delete(ready_key(&record.queue, record.ready_at_ms, id));
+                }
+            }
+
+            self.db.write(batch)?;
+
+            METRICS.completed.inc();
+        }
+
+        Ok(())
+    }
+
+    /// Retry the task after a backoff, or move it to the dead letters
+    /// if it has been attempted too many times.
+    pub fn fail(&self, id: &TaskId, error: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut record = match self.get(id)? {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+
+        let mut batch = rocksdb::WriteBatch::default();
+        record.last_error = Some(error.to_string());
+
+        if record.attempts >= self.config.max_attempts {
+            self.give_up(&mut batch, id, record)?;
+        } else {
+            batch.delete(ready_key(&record.queue, record.ready_at_ms, id));
+            record.ready_at_ms = now_ms() + self.backoff(record.attempts).as_millis() as u64;
+            self.put(&mut batch, id, &record)?;
+
+            METRICS.retried.inc();
+        }
+
+        self.db.write(batch)?;
+
+        Ok(())
+    }
+
+    /// Number of tasks in the queue, including the ones that are currently leased.
+    pub fn len(&self, queue: &str) -> Result<usize> {
+        let prefix = queue_prefix(READY_PREFIX, queue);
+        let mut len = 0;
+
+        for item in self.db.iterator(rocksdb::IteratorMode::From(
+            &prefix,
+            rocksdb::Direction::Forward,
+        )) {
+            let (key, _) = item?;
+
+            if !key.starts_with(&prefix) {
+                break;
+            }
+
+            len += 1;
+        }
+
+        Ok(len)
+    }
+
+    pub fn is_empty(&self, queue: &str) -> Result<bool> {
+        Ok(self.len(queue)? == 0)
+    }
+
+    pub fn dead_letters(&self, queue: &str) -> Result<Vec<DeadLetter>> {
+        let prefix = queue_prefix(DEAD_PREFIX, queue);
+        let mut res = Vec::new();
+
+        for item in self.db.iterator(rocksdb::IteratorMode::From(
+            &prefix,
+            rocksdb::Direction::Forward,
+        )) {
+            let (key, value) = item?;
+
+            if !key.starts_with(&prefix) {
+                break;
+            }
+
+            let id = TaskId(Uuid::from_slice(&key[prefix.len()..])?);
+            let dead: DeadRecord = bincode::deserialize(&value)?;
+
+            res.push(DeadLetter {
+                id,
+                queue: dead.task.queue,
+                attempts: dead.task.attempts,
+                error: dead.task.last_error,
+                failed_at_ms: dead.failed_at_ms,
+            });
+        }
+
+        Ok(res)
+    }
+
+    /// Move a dead letter back into its queue with a fresh number of attempts.
+    pub fn retry_dead_letter(&self, queue: &str, id: &TaskId) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let key = dead_key(queue, id);
+
+        let dead: DeadRecord = match self.db.get(&key)? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => return Err(anyhow!("no dead letter {id} in queue {queue}")),
+        };
+
+        let record = TaskRecord {
+            attempts: 0,
+            ready_at_ms: now_ms(),
+            ..dead.task
+        };
+
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.delete(key);
+        self.put(&mut batch, id, &record)?;
+        self.db.write(batch)?;
+
+        Ok(())
+    }
+
+    /// Run the handler on the tasks of the queue until the process stops.
+    /// The queue is polled when it has no tasks that are ready to run.
+    pub async fn process<T, F, Fut>(self: Arc<Self>, queue: &str, handler: F)
+    where
+        T: DeserializeOwned,
+        F: Fn(Task<T>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.config.poll_interval_ms));
+
+        loop {
+            let task = match self.lease::<T>(queue) {
+                Ok(Some(task)) => task,
+                Ok(None) => {
+                    interval.tick().await;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::error!("failed to lease task from queue {queue}: {err}");
+                    interval.tick().await;
+                    continue;
+                }
+            };
+
+            let id = task.id;
+
+            let res = match handler(task).await {
+                Ok(()) => self.complete(&id),
+                Err(err) => self.fail(&id, &err.to_string()),
+            };
+
+            if let Err(err) = res {
+                tracing::error!("failed to update task {id} in queue {queue}: {err}");
+            }
+        }
+    }
+
+    /// Run the job every `interval` until the process stops. The queue holds a single
+    /// recurring task for the job, which is due again when it succeeds or runs out of retries.
+    pub async fn recurring<F, Fut>(self: Arc<Self>, queue: &'static str, interval: Duration, job: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        if let Err(err) = self.schedule_recurring(queue, &(), interval) {
+            tracing::error!("failed to schedule recurring job {queue}: {err}");
+            return;
+        }
+
+        self.process(queue, move |_: Task<()>| job()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn config(path: &Path) -> TaskQueueConfig {
+        TaskQueueConfig {
+            path: path.to_str().unwrap().to_string(),
+            max_attempts: 3,
+            retry_backoff_ms: 0,
+            max_retry_backoff_ms: 0,
+            lease_timeout_sec: 60,
+            poll_interval_ms: 10,
+        }
+    }
+
+    fn open() -> TaskQueue {
+        let path = crate::gen_temp_path();
+        TaskQueue::open(&path, config(&path)).unwrap()
+    }
+
+    #[test]
+    fn lease_and_complete() {
+        let queue = open();
+
+        queue.push("favicons", &"a.com".to_string()).unwrap();
+        queue.push("favicons", &"b.com".to_string()).unwrap();
+        queue.push("other", &"c.com".to_string()).unwrap();
+
+        assert_eq!(queue.len("favicons").unwrap(), 2);
+
+        let task = queue.lease::<String>("favicons").unwrap().unwrap();
+        assert_eq!(task.payload, "a.com");
+        assert_eq!(task.attempts, 1);
+        queue.complete(&task.id).unwrap();
+
+        let task = queue.lease::<String>("favicons").unwrap().unwrap();
+        assert_eq!(task.payload, "b.com");
+
+        // the leased task is not handed out twice
+        assert!(queue.lease::<String>("favicons").unwrap().is_none());
+
+        queue.complete(&task.id).unwrap();
+        assert!(queue.is_empty("favicons").unwrap());
+        assert_eq!(queue.len("other").unwrap(), 1);
+    }
+
+    #[test]
+    fn delayed_tasks() {
+        let queue = open();
+
+        queue
+            .push_delayed("recrawl", &1u64, Duration::from_secs(3600))
+            .unwrap();
+
+        assert_eq!(queue.len("recrawl").unwrap(), 1);
+        assert!(queue.lease::<u64>("recrawl").unwrap().is_none());
+    }
+
+    #[test]
+    fn failed_tasks_are_retried_and_dead_lettered() {
+        let queue = open();
+        let id = queue.push("sync", &42u64).unwrap();
+
+        for attempt in 1..=3 {
+            let task = queue.lease::<u64>("sync").unwrap().unwrap();
+            assert_eq!(task.attempts, attempt);
+            queue.fail(&task.id, "connection refused").unwrap();
+        }
+
+        assert!(queue.lease::<u64>("sync").unwrap().is_none());
+        assert!(queue.is_empty("sync").unwrap());
+
+        let dead = queue.dead_letters("sync").unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, id);
+        assert_eq!(dead[0].attempts, 3);
+        assert_eq!(dead[0].error.as_deref(), Some("connection refused"));
+
+        queue.retry_dead_letter("sync", &id).unwrap();
+        assert!(queue.dead_letters("sync").unwrap().is_empty());
+
+        let task = queue.lease::<u64>("sync").unwrap().unwrap();
+        assert_eq!(task.payload, 42);
+        assert_eq!(task.attempts, 1);
+    }
+
+    #[test]
+    fn expired_leases_are_handed_out_again() {
+        let path = crate::gen_temp_path();
+        let queue = TaskQueue::open(
+            &path,
+            TaskQueueConfig {
+                lease_timeout_sec: 0,
+                ..config(&path)
+            },
+        )
+        .unwrap();
+
+        queue.push("screenshots", &"a.com".to_string()).unwrap();
+
+        let first = queue.lease::<String>("screenshots").unwrap().unwrap();
+        let second = queue.lease::<String>("screenshots").unwrap().unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_eq!(second.attempts, 2);
+    }
+
+    #[test]
+    fn tasks_are_persisted() {
+        let path = crate::gen_temp_path();
+
+        {
+            let queue = TaskQueue::open(&path, config(&path)).unwrap();
+            queue.push("recrawl", &"a.com".to_string()).unwrap();
+        }
+
+        let queue = TaskQueue::open(&path, config(&path)).unwrap();
+        let task = queue.lease::<String>("recrawl").unwrap().unwrap();
+        assert_eq!(task.payload, "a.com");
+    }
+
+    #[tokio::test]
+    async fn process_tasks() {
+        let queue = Arc::new(open());
+        let processed = Arc::new(AtomicUsize::new(0));
+
+        queue.push("jobs", &1u64).unwrap();
+        queue.push("jobs", &2u64).unwrap();
+
+        let handle = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            let processed = Arc::clone(&processed);
+
+            async move {
+                queue
+                    .process("jobs", |task: Task<u64>| {
+                        let processed = Arc::clone(&processed);
+
+                        async move {
+                            if task.payload == 2 && task.attempts == 1 {
+                                return Err(anyhow!("transient error"));
+                            }
+
+                            processed.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        }
+                    })
+                    .await
+            }
+        });
+
+        for _ in 0..100 {
+            if processed.load(Ordering::SeqCst) == 2 {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        handle.abort();
+
+        assert_eq!(processed.load(Ordering::SeqCst), 2);
+        assert!(queue.is_empty("jobs").unwrap());
+    }
+
+    #[test]
+    fn recurring_tasks_are_due_again() {
+        let path = crate::gen_temp_path();
+        let queue = TaskQueue::open(
+            &path,
+            TaskQueueConfig {
+                lease_timeout_sec: 0,
+                ..config(&path)
+            },
+        )
+        .unwrap();
+        let interval = Duration::from_secs(3600);
+
+        queue.schedule_recurring("sync", &(), interval).unwrap();
+        queue.schedule_recurring("sync", &(), interval).unwrap();
+        assert_eq!(queue.len("sync").unwrap(), 1);
+
+        let task = queue.lease::<()>("sync").unwrap().unwrap();
+        queue.complete(&task.id).unwrap();
+
+        assert_eq!(queue.len("sync").unwrap(), 1);
+        assert!(queue.lease::<()>("sync").unwrap().is_none());
+
+        queue.push("other", &()).unwrap();
+        queue.schedule_recurring("other", &(), interval).unwrap();
+
+        // the lease of the last attempt expires without the job finishing
+        for _ in 0..3 {
+            queue.lease::<()>("other").unwrap().unwrap();
+        }
+
+        assert!(queue.lease::<()>("other").unwrap().is_none());
+        assert!(queue.dead_letters("other").unwrap().is_empty());
+        assert_eq!(queue.len("other").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn recurring_jobs_are_rescheduled() {
+        let queue = Arc::new(open());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let handle = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            let runs = Arc::clone(&runs);
+
+            async move {
+                queue
+                    .recurring("sync", Duration::from_secs(3600), || {
+                        let runs = Arc::clone(&runs);
+
+                        async move {
+                            runs.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        }
+                    })
+                    .await
+            }
+        });
+
+        for _ in 0..100 {
+            if runs.load(Ordering::SeqCst) == 1 {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // wait for the next run to be scheduled
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.abort();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(queue.len("sync").unwrap(), 1);
+        assert!(queue.lease::<()>("sync").unwrap().is_none());
+    }
+}
@@ -41,8 +41,10 @@ impl From<Lang> for MyStemmer {
             Lang::Fin => MyStemmer(Stemmer::new(Language::Finnish)),
             Lang::Fra => MyStemmer(Stemmer::new(Language::French)),
             Lang::Deu => MyStemmer(Stemmer::new(Language::German)),
+            Lang::Ell => MyStemmer(Stemmer::new(Language::Greek)),
             Lang::Hun => MyStemmer(Stemmer::new(Language::Hungarian)),
             Lang::Ita => MyStemmer(Stemmer::new(Language::Italian)),
+            Lang::Nob => MyStemmer(Stemmer::new(Language::Norwegian)),
             Lang::Por => MyStemmer(Stemmer::new(Language::Portuguese)),
             Lang::Ron => MyStemmer(Stemmer::new(Language::Romanian)),
             Lang::Rus => MyStemmer(Stemmer::new(Language::Russian)),
@@ -72,6 +72,32 @@ impl<K: Hash + Eq + Clone, V> TTLCache<K, V> {
         })
     }
 
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let current_time = SystemTime::now();
+        let insertion_time = self.insertion_times.get(key)?;
+
+        if current_time.duration_since(*insertion_time).unwrap() < self.ttl {
+            self.data.get_mut(key)
+        } else {
+            None
+        }
+    }
+
+    /// Remove all entries for which `f` returns false.
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+        self.data.retain(|key, val| f(key, val));
+
+        let data = &self.data;
+        self.insertion_order.retain(|key| data.contains_key(key));
+        self.insertion_times.retain(|key, _| data.contains_key(key));
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.insertion_order.clear();
+        self.insertion_times.clear();
+    }
+
     fn prune_old_entries(&mut self) {
         let current_time = SystemTime::now();
 
@@ -141,4 +167,26 @@ mod tests {
         assert_eq!(cache.get(&0), None);
         assert_eq!(cache.get(&1), Some(&1));
     }
+
+    #[test]
+    fn retain() {
+        let mut cache = TTLCache::with_ttl(Duration::from_secs(60));
+
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+
+        cache.retain(|_, val| val % 2 == 0);
+
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+        assert_eq!(cache.data.len(), 5);
+        assert_eq!(cache.insertion_order.len(), 5);
+        assert_eq!(cache.insertion_times.len(), 5);
+
+        cache.clear();
+
+        assert_eq!(cache.get(&2), None);
+        assert!(cache.insertion_order.is_empty());
+    }
 }
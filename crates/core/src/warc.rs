@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::distributed::retry_strategy::ExponentialBackoff;
+use crate::webpage::protocol::{ProtocolInfo, TlsStatus};
 use crate::{config::S3Config, config::WarcSource, Error, Result};
 use std::collections::BTreeMap;
 use std::fmt::Display;
@@ -280,18 +281,44 @@ impl Display for PayloadType {
 pub struct Response {
     pub body: String,
     pub payload_type: Option<PayloadType>,
+    // Last-Modified http header
+    #[cfg_attr(
+        test,
+        proptest(strategy = "proptest::option::of(\"[A-Za-z0-9:,]+( [A-Za-z0-9:,]+)*\")")
+    )]
+    pub last_modified: Option<String>,
+    // ETag http header
+    #[cfg_attr(
+        test,
+        proptest(strategy = "proptest::option::of(\"\\\"[A-Za-z0-9-]+\\\"\")")
+    )]
+    pub etag: Option<String>,
 }
 
 impl Response {
     fn from_raw(record: RawWarcRecord) -> Result<Self> {
         let content = decode(&record.content[..]);
 
-        let (_header, content) = content
+        let (header, content) = content
             .split_once("\r\n\r\n")
             .ok_or(Error::WarcParse("Invalid http body".to_string()))?;
 
+        let header_value = |name: &str| {
+            header.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+
+                if key.trim().eq_ignore_ascii_case(name) {
+                    Some(value.trim().to_string())
+                } else {
+                    None
+                }
+            })
+        };
+
         Ok(Self {
             body: content.to_string(),
+            last_modified: header_value("last-modified"),
+            etag: header_value("etag"),
             payload_type: record
                 .header
                 .get("WARC-IDENTIFIED-PAYLOAD-TYPE")
@@ -305,12 +332,32 @@ impl Response {
 pub struct Metadata {
     // fetchTimeMs
     pub fetch_time_ms: u64,
+    // fetchedAt: unix timestamp (seconds) of the fetch. Missing in records
+    // written before it was added.
+    pub fetched_at: Option<u64>,
+    // notModified: the server answered a conditional request with 304 Not Modified,
+    // so the record has no body.
+    pub not_modified: bool,
+    // sitemapLastmod
+    #[cfg_attr(
+        test,
+        proptest(strategy = "proptest::option::of(\"[A-Za-z0-9:+.-]+\")")
+    )]
+    pub sitemap_lastmod: Option<String>,
+    // httpVersion, tls, tlsIssuer and redirectedToHttps
+    pub protocol: ProtocolInfo,
 }
 
 impl Metadata {
     fn from_raw(record: RawWarcRecord) -> Result<Self> {
         let r = BufReader::new(&record.content[..]);
 
+        let mut fetch_time_ms = None;
+        let mut fetched_at = None;
+        let mut not_modified = false;
+        let mut sitemap_lastmod = None;
+        let mut protocol = ProtocolInfo::default();
+
         for line in r.lines() {
             let mut line = line?;
             if let Some(semi) = line.find(':') {
@@ -318,13 +365,35 @@ impl Metadata {
                 line.pop(); // remove colon
                 let key = line;
                 if key == "fetchTimeMs" {
-                    let fetch_time_ms = value.parse::<u64>()?;
-                    return Ok(Self { fetch_time_ms });
+                    fetch_time_ms = Some(value.parse::<u64>()?);
+                } else if key == "fetchedAt" {
+                    fetched_at = value.parse::<u64>().ok();
+                } else if key == "notModified" {
+                    not_modified = value == "true";
+                } else if key == "sitemapLastmod" {
+                    sitemap_lastmod = Some(value);
+                } else if key == "httpVersion" {
+                    protocol.http_version = Some(value);
+                } else if key == "tls" {
+                    protocol.tls = value.parse().unwrap_or_default();
+                } else if key == "tlsIssuer" {
+                    protocol.tls_issuer = Some(value);
+                } else if key == "redirectedToHttps" {
+                    protocol.redirected_to_https = value == "true";
                 }
             }
         }
 
-        Err(Error::WarcParse("Failed to parse metadata".to_string()).into())
+        match fetch_time_ms {
+            Some(fetch_time_ms) => Ok(Self {
+                fetch_time_ms,
+                fetched_at,
+                not_modified,
+                sitemap_lastmod,
+                protocol,
+            }),
+            None => Err(Error::WarcParse("Failed to parse metadata".to_string()).into()),
+        }
     }
 }
 
@@ -622,13 +691,25 @@ impl WarcWriter {
             )?;
         }
 
+        let mut http_header = Vec::new();
+
+        if let Some(last_modified) = &record.response.last_modified {
+            http_header.push(format!("Last-Modified: {last_modified}"));
+        }
+
+        if let Some(etag) = &record.response.etag {
+            http_header.push(format!("ETag: {etag}"));
+        }
+
+        let http_header = http_header.join("\r\n");
+
         let body = record.response.body.as_bytes();
-        let content_len = body.len() + 4; // +4 is for the \r\n\r\n between http header and body
+        let content_len = http_header.len() + body.len() + 4; // +4 is for the \r\n\r\n between http header and body
         self.writer
             .write_all(format!("Content-Length: {content_len}\r\n").as_bytes())?;
 
         self.writer.write_all("\r\n".as_bytes())?;
-        // write the http-header here if we want to in the future
+        self.writer.write_all(http_header.as_bytes())?;
         self.writer.write_all("\r\n\r\n".as_bytes())?;
 
         self.writer.write_all(body)?;
@@ -638,7 +719,29 @@ impl WarcWriter {
         self.writer
             .write_all("WARC-Type: metadata\r\n".as_bytes())?;
 
-        let body = format!("fetchTimeMs: {}", record.metadata.fetch_time_ms);
+        let mut body = format!("fetchTimeMs: {}", record.metadata.fetch_time_ms);
+        if let Some(fetched_at) = record.metadata.fetched_at {
+            body.push_str(&format!("\r\nfetchedAt: {fetched_at}"));
+        }
+        if record.metadata.not_modified {
+            body.push_str("\r\nnotModified: true");
+        }
+        if let Some(sitemap_lastmod) = &record.metadata.sitemap_lastmod {
+            body.push_str(&format!("\r\nsitemapLastmod: {sitemap_lastmod}"));
+        }
+        let protocol = &record.metadata.protocol;
+        if let Some(http_version) = &protocol.http_version {
+            body.push_str(&format!("\r\nhttpVersion: {http_version}"));
+        }
+        if protocol.tls != TlsStatus::Unknown {
+            body.push_str(&format!("\r\ntls: {}", protocol.tls.as_str()));
+        }
+        if let Some(tls_issuer) = &protocol.tls_issuer {
+            body.push_str(&format!("\r\ntlsIssuer: {tls_issuer}"));
+        }
+        if protocol.redirected_to_https {
+            body.push_str("\r\nredirectedToHttps: true");
+        }
         let content_len = body.len();
 
         self.writer
@@ -758,9 +861,20 @@ mod tests {
             response: Response {
                 body: "body of a".to_string(),
                 payload_type: Some(PayloadType::Html),
+                last_modified: Some("Wed, 22 Jun 2022 19:37:34 GMT".to_string()),
+                etag: Some("\"33a64df5\"".to_string()),
             },
             metadata: Metadata {
                 fetch_time_ms: 1337,
+                fetched_at: Some(1_700_000_000),
+                not_modified: false,
+                sitemap_lastmod: Some("2023-10-18T05:40:04+00:00".to_string()),
+                protocol: ProtocolInfo {
+                    http_version: Some("HTTP/2.0".to_string()),
+                    tls: TlsStatus::Valid,
+                    tls_issuer: Some("Let's Encrypt".to_string()),
+                    redirected_to_https: true,
+                },
             },
         };
         writer.write(&record1).unwrap();
@@ -772,9 +886,15 @@ mod tests {
             response: Response {
                 body: "body of b".to_string(),
                 payload_type: None,
+                last_modified: None,
+                etag: None,
             },
             metadata: Metadata {
                 fetch_time_ms: 4242,
+                fetched_at: None,
+                not_modified: true,
+                sitemap_lastmod: None,
+                protocol: ProtocolInfo::default(),
             },
         };
         writer.write(&record2).unwrap();
@@ -789,11 +909,28 @@ mod tests {
         assert_eq!(records.len(), 2);
         assert_eq!(&records[0].request.url, "https://a.com");
         assert_eq!(&records[0].response.body, "body of a");
+        assert_eq!(
+            records[0].response.last_modified.as_deref(),
+            Some("Wed, 22 Jun 2022 19:37:34 GMT")
+        );
+        assert_eq!(records[0].response.etag.as_deref(), Some("\"33a64df5\""));
         assert_eq!(records[0].metadata.fetch_time_ms, 1337);
+        assert_eq!(
+            records[0].metadata.sitemap_lastmod.as_deref(),
+            Some("2023-10-18T05:40:04+00:00")
+        );
+        assert_eq!(records[0].metadata.protocol, record1.metadata.protocol);
 
         assert_eq!(&records[1].request.url, "https://b.com");
         assert_eq!(&records[1].response.body, "body of b");
+        assert_eq!(records[1].response.last_modified, None);
+        assert_eq!(records[1].response.etag, None);
         assert_eq!(records[1].metadata.fetch_time_ms, 4242);
+
+        assert_eq!(records[0].metadata.fetched_at, Some(1_700_000_000));
+        assert!(!records[0].metadata.not_modified);
+        assert_eq!(records[1].metadata.fetched_at, None);
+        assert!(records[1].metadata.not_modified);
     }
 
     #[test]
@@ -808,8 +945,16 @@ mod tests {
             response: Response {
                 body: utf8.to_string(),
                 payload_type: Some(PayloadType::Html),
+                last_modified: None,
+                etag: None,
+            },
+            metadata: Metadata {
+                fetch_time_ms: 0,
+                fetched_at: None,
+                not_modified: false,
+                sitemap_lastmod: None,
+                protocol: ProtocolInfo::default(),
             },
-            metadata: Metadata { fetch_time_ms: 0 },
         };
         writer.write(&record).unwrap();
 
@@ -840,8 +985,16 @@ mod tests {
             response: Response {
                 body: body.to_string(),
                 payload_type: Some(PayloadType::Html),
+                last_modified: None,
+                etag: None,
+            },
+            metadata: Metadata {
+                fetch_time_ms: 0,
+                fetched_at: None,
+                not_modified: false,
+                sitemap_lastmod: None,
+                protocol: ProtocolInfo::default(),
             },
-            metadata: Metadata { fetch_time_ms: 0 },
         };
         writer.write(&record).unwrap();
 
@@ -0,0 +1,139 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Anchor texts of the links pointing to each node. The anchor texts are collected
+//! while the graph is constructed and indexed as the backlink text of the pages.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::node_store::{Mergeable, NodeStore};
+
+pub type AnchorTextStore = NodeStore<AnchorTexts>;
+
+/// Maximum number of distinct anchor texts kept for a node.
+/// The most frequent texts are kept.
+pub const MAX_ANCHOR_TEXTS: usize = 128;
+
+/// Anchor texts that don't say anything about the page they link to.
+const STOPWORDS: [&str; 16] = [
+    "click",
+    "click here",
+    "here",
+    "link",
+    "website",
+    "webpage",
+    "page",
+    "site",
+    "url",
+    "web",
+    "visit",
+    "more",
+    "info",
+    "information",
+    "read",
+    "read more",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnchorTexts {
+    /// Number of links with each anchor text.
+    texts: BTreeMap<String, u64>,
+}
+
+impl AnchorTexts {
+    /// Anchor text of a single link, unless the text is empty or uninformative.
+    pub fn link(text: &str) -> Option<Self> {
+        let text = text.trim();
+
+        if text.is_empty() || STOPWORDS.contains(&text.to_lowercase().as_str()) {
+            return None;
+        }
+
+        let mut texts = BTreeMap::new();
+        texts.insert(text.to_string(), 1);
+
+        Some(Self { texts })
+    }
+
+    /// The anchor texts, most frequent first.
+    pub fn texts(&self) -> Vec<&str> {
+        let mut texts: Vec<_> = self.texts.iter().collect();
+        texts.sort_by(|(a_text, a), (b_text, b)| b.cmp(a).then(a_text.cmp(b_text)));
+
+        texts.into_iter().map(|(text, _)| text.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+}
+
+impl Mergeable for AnchorTexts {
+    fn merge(&mut self, other: Self) {
+        for (text, count) in other.texts {
+            *self.texts.entry(text).or_default() += count;
+        }
+
+        if self.texts.len() > MAX_ANCHOR_TEXTS {
+            let keep: Vec<String> = self
+                .texts()
+                .into_iter()
+                .take(MAX_ANCHOR_TEXTS)
+                .map(String::from)
+                .collect();
+
+            self.texts.retain(|text, _| keep.contains(text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uninformative_texts() {
+        assert_eq!(AnchorTexts::link(""), None);
+        assert_eq!(AnchorTexts::link("  "), None);
+        assert_eq!(AnchorTexts::link("Click Here"), None);
+        assert!(AnchorTexts::link("stract search engine").is_some());
+    }
+
+    #[test]
+    fn most_frequent_first() {
+        let mut texts = AnchorTexts::link("b").unwrap();
+        texts.merge(AnchorTexts::link("a").unwrap());
+        texts.merge(AnchorTexts::link("c").unwrap());
+        texts.merge(AnchorTexts::link("c").unwrap());
+
+        assert_eq!(texts.texts(), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn keeps_most_frequent() {
+        let mut texts = AnchorTexts::link("popular").unwrap();
+        texts.merge(AnchorTexts::link("popular").unwrap());
+
+        for i in 0..MAX_ANCHOR_TEXTS {
+            texts.merge(AnchorTexts::link(&format!("text {i}")).unwrap());
+        }
+
+        assert_eq!(texts.texts().len(), MAX_ANCHOR_TEXTS);
+        assert_eq!(texts.texts()[0], "popular");
+    }
+}
@@ -15,11 +15,18 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! this is an implementation of the algorithm
-//! described in "A Faster Algorithm for Betweenness Centrality"
+//! described in "A Faster Algorithm for Betweenness Centrality".
+//!
+//! Calculating the exact centrality requires a breadth-first search from every node,
+//! which is infeasible for large graphs. [`Betweenness::calculate_sampled`] instead
+//! only searches from a uniform sample of pivot nodes and extrapolates the
+//! dependencies to the entire graph, as described in "Centrality Estimation in
+//! Large Networks" by Brandes and Pich.
 
 use std::collections::{HashMap, VecDeque};
 
 use indicatif::{ProgressBar, ProgressStyle};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -27,105 +34,112 @@ use crate::{
     webgraph::{Node, NodeID, Webgraph},
 };
 
-fn calculate(graph: &Webgraph, with_progress: bool) -> (HashMap<Node, f64>, i32) {
-    let mut centrality: HashMap<NodeID, f64> = HashMap::new();
-    let mut n = 0;
-    let mut max_dist = 0;
-
-    let nodes: Vec<_> = graph.nodes().take(100_000).collect();
+/// Add the dependencies of all nodes on `s` to `centrality` and return
+/// the largest distance from `s` to any reachable node.
+fn accumulate(graph: &Webgraph, s: NodeID, centrality: &mut HashMap<NodeID, f64>) -> i32 {
+    centrality.entry(s).or_default();
 
-    let pb =
-        if with_progress {
-            let pb = ProgressBar::new(nodes.len() as u64);
-            pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar}] {pos:>7}/{len:7} ({eta})")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-            Some(pb)
-        } else {
-            None
-        };
+    let mut stack = Vec::new();
+    let mut predecessors: IntMap<NodeID, Vec<NodeID>> = IntMap::new();
 
-    for s in nodes.into_iter() {
-        if let Some(pb) = &pb {
-            pb.inc(1);
-        }
+    let mut sigma = IntMap::new();
 
-        n += 1;
-        centrality.entry(s).or_default();
+    sigma.insert(s, 1);
 
-        let mut stack = Vec::new();
-        let mut predecessors: IntMap<NodeID, Vec<NodeID>> = IntMap::new();
+    let mut distances = IntMap::new();
+    distances.insert(s, 0);
 
-        let mut sigma = IntMap::new();
+    let mut q = VecDeque::new();
+    q.push_back(s);
 
-        sigma.insert(s, 1);
+    while let Some(v) = q.pop_front() {
+        stack.push(v);
+        for edge in graph.raw_outgoing_edges(&v) {
+            let w = edge.to;
 
-        let mut distances = IntMap::new();
-        distances.insert(s, 0);
+            if !distances.contains_key(&w) {
+                let dist_v = distances.get(&v).unwrap();
+                q.push_back(w);
+                distances.insert(w, dist_v + 1);
+            }
 
-        let mut q = VecDeque::new();
-        q.push_back(s);
+            if *distances.get(&w).unwrap() == distances.get(&v).unwrap() + 1 {
+                let sigma_v = *sigma.get(&v).unwrap_or(&0);
 
-        while let Some(v) = q.pop_front() {
-            stack.push(v);
-            for edge in graph.raw_outgoing_edges(&v) {
-                let w = edge.to;
+                if !sigma.contains_key(&w) {
+                    sigma.insert(w, 0);
+                }
+                *sigma.get_mut(&w).unwrap() += sigma_v;
 
-                if !distances.contains_key(&w) {
-                    let dist_v = distances.get(&v).unwrap();
-                    q.push_back(w);
-                    distances.insert(w, dist_v + 1);
+                if !predecessors.contains_key(&w) {
+                    predecessors.insert(w, Vec::new());
                 }
 
-                if *distances.get(&w).unwrap() == distances.get(&v).unwrap() + 1 {
-                    let sigma_v = *sigma.get(&v).unwrap_or(&0);
+                predecessors.get_mut(&w).unwrap().push(v);
+            }
+        }
+    }
 
-                    if !sigma.contains_key(&w) {
-                        sigma.insert(w, 0);
-                    }
-                    *sigma.get_mut(&w).unwrap() += sigma_v;
+    let max_dist = *distances.iter().map(|(_, dist)| dist).max().unwrap_or(&0);
 
-                    if !predecessors.contains_key(&w) {
-                        predecessors.insert(w, Vec::new());
-                    }
+    let mut delta = IntMap::new();
+    while let Some(w) = stack.pop() {
+        if let Some(pred) = predecessors.get(&w) {
+            for v in pred {
+                let dv = delta.get(v).copied().unwrap_or(0.0);
 
-                    predecessors.get_mut(&w).unwrap().push(v);
-                }
+                delta.insert(
+                    *v,
+                    dv + (*sigma.get(v).unwrap() as f64 / *sigma.get(&w).unwrap() as f64)
+                        * (1.0 + delta.get(&w).unwrap_or(&0.0)),
+                );
             }
         }
 
-        max_dist = max_dist.max(*distances.iter().map(|(_, dist)| dist).max().unwrap_or(&0));
+        if w != s {
+            *centrality.entry(w).or_insert(0.0) += *delta.get(&w).unwrap_or(&0.0);
+        }
+    }
 
-        let mut delta = IntMap::new();
-        while let Some(w) = stack.pop() {
-            if let Some(pred) = predecessors.get(&w) {
-                for v in pred {
-                    let dv = delta.get(v).copied().unwrap_or(0.0);
+    max_dist
+}
 
-                    delta.insert(
-                        *v,
-                        dv + (*sigma.get(v).unwrap() as f64 / *sigma.get(&w).unwrap() as f64)
-                            * (1.0 + delta.get(&w).unwrap_or(&0.0)),
-                    );
-                }
-            }
+/// Accumulate the dependencies on all `sources` and divide the centralities by `norm`.
+fn calculate(
+    graph: &Webgraph,
+    sources: Vec<NodeID>,
+    norm: f64,
+    with_progress: bool,
+) -> (HashMap<Node, f64>, i32) {
+    let mut centrality: HashMap<NodeID, f64> = HashMap::new();
+    let mut max_dist = 0;
 
-            if w != s {
-                *centrality.entry(w).or_insert(0.0) += *delta.get(&w).unwrap_or(&0.0);
-            }
+    let pb =
+        if with_progress {
+            let pb = ProgressBar::new(sources.len() as u64);
+            pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar}] {pos:>7}/{len:7} ({eta})")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+            Some(pb)
+        } else {
+            None
+        };
+
+    for s in sources {
+        if let Some(pb) = &pb {
+            pb.inc(1);
         }
+
+        max_dist = max_dist.max(accumulate(graph, s, &mut centrality));
     }
 
     if let Some(pb) = &pb {
         pb.finish_and_clear();
     }
 
-    let n = n as f64;
-    let norm = n * (n - 1.0);
-
     (
         centrality
             .into_iter()
@@ -135,6 +149,37 @@ fn calculate(graph: &Webgraph, with_progress: bool) -> (HashMap<Node, f64>, i32)
     )
 }
 
+/// Sample `num` nodes uniformly at random from the graph with reservoir sampling,
+/// so the nodes don't have to be collected in memory first.
+fn sample_pivots(graph: &Webgraph, num: usize, seed: u64) -> (Vec<NodeID>, usize) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut pivots = Vec::with_capacity(num);
+    let mut num_nodes = 0;
+
+    for node in graph.nodes() {
+        if pivots.len() < num {
+            pivots.push(node);
+        } else {
+            let i = rng.gen_range(0..=num_nodes);
+
+            if i < num {
+                pivots[i] = node;
+            }
+        }
+
+        num_nodes += 1;
+    }
+
+    (pivots, num_nodes)
+}
+
+fn first_nodes(graph: &Webgraph) -> (Vec<NodeID>, f64) {
+    let nodes: Vec<_> = graph.nodes().take(100_000).collect();
+    let n = nodes.len() as f64;
+
+    (nodes, n * (n - 1.0))
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Betweenness {
     pub centrality: HashMap<Node, f64>,
@@ -144,7 +189,8 @@ pub struct Betweenness {
 impl Betweenness {
     #[allow(unused)]
     pub fn calculate(graph: &Webgraph) -> Self {
-        let (host, max_dist) = calculate(graph, false);
+        let (sources, norm) = first_nodes(graph);
+        let (host, max_dist) = calculate(graph, sources, norm, false);
         Self {
             centrality: host,
             max_dist: max_dist.max(0) as usize,
@@ -152,7 +198,24 @@ impl Betweenness {
     }
 
     pub fn calculate_with_progress(graph: &Webgraph) -> Self {
-        let (host, max_dist) = calculate(graph, true);
+        let (sources, norm) = first_nodes(graph);
+        let (host, max_dist) = calculate(graph, sources, norm, true);
+        Self {
+            centrality: host,
+            max_dist: max_dist.max(0) as usize,
+        }
+    }
+
+    /// Estimate the centrality from the shortest paths of `num_pivots` randomly sampled
+    /// nodes. The estimate is unbiased, and the exact centrality is calculated if there
+    /// are no more nodes than pivots. `max_dist` is the largest distance seen from a pivot.
+    pub fn calculate_sampled(graph: &Webgraph, num_pivots: usize, seed: u64) -> Self {
+        let (pivots, num_nodes) = sample_pivots(graph, num_pivots, seed);
+
+        // every pivot stands in for num_nodes / num_pivots sources
+        let norm = pivots.len() as f64 * (num_nodes as f64 - 1.0);
+
+        let (host, max_dist) = calculate(graph, pivots, norm, true);
         Self {
             centrality: host,
             max_dist: max_dist.max(0) as usize,
@@ -202,4 +265,53 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn sampled_with_all_nodes_is_exact() {
+        let p = create_path_graph(5);
+
+        let exact = Betweenness::calculate(&p);
+        let sampled = Betweenness::calculate_sampled(&p, 10, 0);
+
+        assert_eq!(sampled.centrality, exact.centrality);
+        assert_eq!(sampled.max_dist, 4);
+    }
+
+    #[test]
+    fn sampled_finds_bridge() {
+        // two stars connected through a single bridge node
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for i in 0..20 {
+            let a = Node::from(format!("a{i}"));
+            let b = Node::from(format!("b{i}"));
+
+            writer.insert(a.clone(), Node::from("a"), String::new());
+            writer.insert(Node::from("a"), a, String::new());
+            writer.insert(b.clone(), Node::from("b"), String::new());
+            writer.insert(Node::from("b"), b, String::new());
+        }
+
+        writer.insert(Node::from("a"), Node::from("bridge"), String::new());
+        writer.insert(Node::from("bridge"), Node::from("a"), String::new());
+        writer.insert(Node::from("b"), Node::from("bridge"), String::new());
+        writer.insert(Node::from("bridge"), Node::from("b"), String::new());
+
+        let graph = writer.finalize();
+        let sampled = Betweenness::calculate_sampled(&graph, 10, 1);
+
+        let bridge = sampled.centrality[&Node::from("bridge")];
+        let leaf = sampled
+            .centrality
+            .get(&Node::from("a0"))
+            .copied()
+            .unwrap_or_default();
+
+        assert!(bridge > 0.0);
+        assert!(bridge > leaf);
+    }
 }
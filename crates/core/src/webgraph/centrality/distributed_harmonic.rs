@@ -0,0 +1,244 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Harmonic centrality for graphs where the edges don't fit on a single machine.
+//!
+//! The edges are partitioned into shards, which are webgraphs that together contain
+//! all the edges of the graph (e.g. the graphs built by the individual webgraph workers
+//! before they are merged). The coordinator keeps a HyperLogLog counter for each node.
+//! In every iteration it sends the counters of the nodes that changed in the previous
+//! iteration to the workers, and each worker returns the merged counters of the nodes
+//! these link to in its shard. A node's counter grows with the nodes that can reach it
+//! within `t` hops, exactly like in [`super::harmonic`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    hyperloglog::HyperLogLog,
+    kahan_sum::KahanSum,
+    mapreduce::{Map, Worker},
+    webgraph::{NodeID, Webgraph, WebgraphBuilder},
+};
+
+use super::harmonic::{self, HarmonicCentrality, HYPERLOGLOG_COUNTERS};
+
+type Counter = HyperLogLog<HYPERLOGLOG_COUNTERS>;
+
+/// Maximum number of counters sent to a worker in a single job.
+const MAX_COUNTERS_PER_JOB: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HarmonicJob {
+    /// Path of the webgraph with the edges of the shard.
+    pub shard: String,
+    /// Counters of the nodes that changed in the last iteration.
+    pub changed: Vec<(NodeID, Counter)>,
+}
+
+/// Merged counters of the sources linking to each node.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HarmonicUpdates(pub Vec<(NodeID, Counter)>);
+
+/// Worker that propagates counters along the edges of the shards. The shards are
+/// opened the first time the worker gets a job for them.
+#[derive(Default)]
+pub struct HarmonicWorker {
+    shards: Mutex<HashMap<String, Arc<Webgraph>>>,
+}
+
+impl HarmonicWorker {
+    fn shard(&self, path: &str) -> Arc<Webgraph> {
+        let mut shards = self.shards.lock().unwrap_or_else(|e| e.into_inner());
+
+        Arc::clone(shards.entry(path.to_string()).or_insert_with(|| {
+            info!("opening shard {}", path);
+            Arc::new(WebgraphBuilder::new(path).single_threaded().open())
+        }))
+    }
+}
+
+impl Worker for HarmonicWorker {}
+
+impl Map<HarmonicWorker, HarmonicUpdates> for HarmonicJob {
+    fn map(&self, worker: &HarmonicWorker) -> HarmonicUpdates {
+        let graph = worker.shard(&self.shard);
+        let mut updates: BTreeMap<NodeID, Counter> = BTreeMap::new();
+
+        for (node, counter) in &self.changed {
+            for edge in graph.raw_outgoing_edges(node) {
+                updates.entry(edge.to).or_default().merge(counter);
+            }
+        }
+
+        HarmonicUpdates(updates.into_iter().collect())
+    }
+}
+
+pub struct DistributedHarmonic {
+    shards: Vec<String>,
+}
+
+impl DistributedHarmonic {
+    pub fn new(shards: Vec<String>) -> Self {
+        Self { shards }
+    }
+
+    /// Calculate the centrality of `nodes`. Edges to nodes that are not in `nodes` are ignored.
+    /// `execute` runs a batch of jobs, e.g. on the remote workers, and returns their results.
+    pub async fn calculate<F, Fut>(
+        &self,
+        nodes: impl Iterator<Item = NodeID>,
+        mut execute: F,
+    ) -> HarmonicCentrality
+    where
+        F: FnMut(Vec<HarmonicJob>) -> Fut,
+        Fut: Future<Output = Vec<HarmonicUpdates>>,
+    {
+        let mut counters: BTreeMap<NodeID, Counter> = BTreeMap::new();
+        let mut centralities: BTreeMap<NodeID, KahanSum> = BTreeMap::new();
+
+        for node in nodes {
+            let mut counter = Counter::default();
+            counter.add(node.as_u64());
+
+            counters.insert(node, counter);
+            centralities.insert(node, KahanSum::default());
+        }
+
+        let num_nodes = counters.len();
+        info!("Found {} nodes in the graph", num_nodes);
+
+        let mut changed: Vec<NodeID> = counters.keys().copied().collect();
+        let mut t = 0;
+
+        while !changed.is_empty() {
+            let mut incoming: BTreeMap<NodeID, Counter> = BTreeMap::new();
+
+            for chunk in changed.chunks(MAX_COUNTERS_PER_JOB) {
+                let changed_counters: Vec<_> = chunk
+                    .iter()
+                    .map(|node| (*node, counters[node].clone()))
+                    .collect();
+
+                let jobs = self
+                    .shards
+                    .iter()
+                    .map(|shard| HarmonicJob {
+                        shard: shard.clone(),
+                        changed: changed_counters.clone(),
+                    })
+                    .collect();
+
+                for updates in execute(jobs).await {
+                    for (node, counter) in updates.0 {
+                        if counters.contains_key(&node) {
+                            incoming.entry(node).or_default().merge(&counter);
+                        }
+                    }
+                }
+            }
+
+            changed = Vec::new();
+
+            for (node, update) in incoming {
+                let counter = counters.get_mut(&node).unwrap();
+
+                if counter
+                    .registers()
+                    .iter()
+                    .zip(update.registers().iter())
+                    .any(|(current, new)| *new > *current)
+                {
+                    let old_size = counter.size();
+                    counter.merge(&update);
+
+                    if let Some(score) = centralities.get_mut(&node) {
+                        *score += counter.size().saturating_sub(old_size) as f64 / (t + 1) as f64;
+                    }
+
+                    changed.push(node);
+                }
+            }
+
+            t += 1;
+            info!("Iteration {} changed {} nodes", t, changed.len());
+        }
+
+        info!("Harmonic centrality calculated");
+
+        HarmonicCentrality(harmonic::normalize(centralities, num_nodes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{Compression, Node, WebgraphWriter};
+
+    fn shard(edges: &[(&str, &str)]) -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            Compression::default(),
+        );
+
+        for (from, to) in edges {
+            writer.insert(Node::from(*from), Node::from(*to), String::new());
+        }
+
+        writer.finalize()
+    }
+
+    #[tokio::test]
+    async fn same_as_single_machine() {
+        let edges = [
+            ("A", "B"),
+            ("B", "C"),
+            ("A", "C"),
+            ("C", "A"),
+            ("D", "C"),
+            ("C", "E"),
+            ("E", "F"),
+        ];
+
+        let full = shard(&edges);
+        let expected = HarmonicCentrality::calculate(&full);
+
+        // the shards are closed before the worker opens them
+        let shards: Vec<_> = [shard(&edges[..3]), shard(&edges[3..])]
+            .iter()
+            .map(|shard| shard.path.clone())
+            .collect();
+        let worker = HarmonicWorker::default();
+
+        let nodes: Vec<_> = full.nodes().collect();
+        let res = DistributedHarmonic::new(shards)
+            .calculate(nodes.into_iter(), |jobs| {
+                let res = jobs.iter().map(|job| job.map(&worker)).collect();
+                async move { res }
+            })
+            .await;
+
+        assert_eq!(res.0, expected.0);
+    }
+}
@@ -16,40 +16,137 @@
 
 use std::{
     collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
     sync::atomic::AtomicBool,
 };
 
 use std::sync::atomic::Ordering;
 
 use crate::bloom::BloomFilter;
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
 use crate::{
     hyperloglog::HyperLogLog,
     kahan_sum::KahanSum,
     webgraph::{NodeID, Webgraph},
+    Result,
 };
 
-const HYPERLOGLOG_COUNTERS: usize = 64;
+pub(super) const HYPERLOGLOG_COUNTERS: usize = 64;
 
-fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
-    let mut num_nodes = 0;
+/// False positive rate of the filter tracking which nodes changed in the last iteration.
+const CHANGED_NODES_FP_RATE: f64 = 0.01;
 
-    let mut counters: BTreeMap<NodeID, HyperLogLog<HYPERLOGLOG_COUNTERS>> = BTreeMap::new();
+/// Where and how often the state of the calculation is saved, so an interrupted
+/// calculation can continue from the last saved iteration instead of starting over.
+#[derive(Debug, Clone)]
+pub struct HarmonicCheckpoint {
+    pub path: PathBuf,
+    /// Number of iterations between each save.
+    pub interval: usize,
+}
 
-    let mut centralities: BTreeMap<NodeID, KahanSum> = BTreeMap::new();
+impl HarmonicCheckpoint {
+    fn load(&self) -> Option<State> {
+        if !self.path.exists() {
+            return None;
+        }
 
-    for node in graph.nodes() {
-        let mut counter = HyperLogLog::default();
-        counter.add(node.as_u64());
+        let res = File::open(&self.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(bincode::deserialize_from(BufReader::new(file))?));
+
+        match res {
+            Ok(state) => Some(state),
+            Err(err) => {
+                warn!(
+                    "ignoring unreadable checkpoint {}: {}",
+                    self.path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// The state is written to a temporary file first, so a crash while saving
+    /// doesn't corrupt the previous checkpoint.
+    fn save(&self, state: &State) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, state)?;
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(tmp_path, &self.path)?;
+
+        Ok(())
+    }
+
+    fn remove(&self) {
+        if self.path.exists() {
+            if let Err(err) = fs::remove_file(&self.path) {
+                warn!("failed to remove checkpoint: {}", err);
+            }
+        }
+    }
+}
 
-        counters.insert(node, counter);
-        centralities.insert(node, KahanSum::default());
+/// The state of the calculation after an iteration. The filter of changed nodes
+/// is not part of the state; all nodes are considered changed after resuming,
+/// which only costs extra work in the first iteration.
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    t: usize,
+    exact_counting: bool,
+    exact_changed_nodes: BTreeSet<NodeID>,
+    counters: BTreeMap<NodeID, HyperLogLog<HYPERLOGLOG_COUNTERS>>,
+    centralities: BTreeMap<NodeID, KahanSum>,
+}
+
+impl State {
+    fn new(graph: &Webgraph) -> Self {
+        let mut state = Self::default();
+
+        for node in graph.nodes() {
+            let mut counter = HyperLogLog::default();
+            counter.add(node.as_u64());
+
+            state.counters.insert(node, counter);
+            state.centralities.insert(node, KahanSum::default());
+        }
+
+        state
+    }
+}
 
-        num_nodes += 1;
+fn load_or_create_state(graph: &Webgraph, checkpoint: Option<&HarmonicCheckpoint>) -> State {
+    if let Some(state) = checkpoint.and_then(|checkpoint| checkpoint.load()) {
+        if state.counters.len() == graph.nodes().count() {
+            info!("Resuming harmonic centrality from iteration {}", state.t);
+            return state;
+        }
+
+        warn!("checkpoint doesn't match the graph, starting over");
     }
 
-    let mut changed_nodes = BloomFilter::new(num_nodes as u64, 0.05);
+    State::new(graph)
+}
+
+/// Run at most `max_iterations` iterations, or until the counters no longer change.
+fn iterate(
+    graph: &Webgraph,
+    checkpoint: Option<&HarmonicCheckpoint>,
+    max_iterations: usize,
+) -> State {
+    let mut state = load_or_create_state(graph, checkpoint);
+    let num_nodes = state.counters.len();
+
+    let mut changed_nodes = BloomFilter::new(num_nodes as u64, CHANGED_NODES_FP_RATE);
 
     for node in graph.nodes() {
         changed_nodes.insert(node.as_u64());
@@ -57,30 +154,26 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
 
     info!("Found {} nodes in the graph", num_nodes);
     let exact_counting_threshold = (num_nodes as f64).sqrt().max(0.0).round() as u64;
-    let norm_factor = (num_nodes - 1) as f64;
 
-    let mut exact_counting = false;
     let has_changes = AtomicBool::new(true);
-    let mut t = 0;
 
-    let mut exact_changed_nodes: BTreeSet<NodeID> = BTreeSet::default();
-
-    loop {
+    for _ in 0..max_iterations {
         if !has_changes.load(Ordering::Relaxed) {
             break;
         }
 
+        let counters = &state.counters;
         let mut new_counters = counters.clone();
 
         has_changes.store(false, Ordering::Relaxed);
-        let mut new_changed_nodes = BloomFilter::new(num_nodes as u64, 0.05);
+        let mut new_changed_nodes = BloomFilter::new(num_nodes as u64, CHANGED_NODES_FP_RATE);
 
-        if !exact_changed_nodes.is_empty()
-            && exact_changed_nodes.len() as u64 <= exact_counting_threshold
+        if !state.exact_changed_nodes.is_empty()
+            && state.exact_changed_nodes.len() as u64 <= exact_counting_threshold
         {
             let mut new_exact_changed_nodes = BTreeSet::default();
 
-            exact_changed_nodes.iter().for_each(|changed_node| {
+            state.exact_changed_nodes.iter().for_each(|changed_node| {
                 for edge in graph.raw_outgoing_edges(changed_node) {
                     if let (Some(counter_to), Some(counter_from)) =
                         (new_counters.get_mut(&edge.to), counters.get(&edge.from))
@@ -102,9 +195,9 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
                 }
             });
 
-            exact_changed_nodes = new_exact_changed_nodes;
+            state.exact_changed_nodes = new_exact_changed_nodes;
         } else {
-            exact_changed_nodes = BTreeSet::default();
+            let mut exact_changed_nodes = BTreeSet::default();
             graph.edges().for_each(|edge| {
                 if changed_nodes.contains(&edge.from.as_u64()) {
                     if let (Some(counter_to), Some(counter_from)) =
@@ -119,7 +212,7 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
                             counter_to.merge(counter_from);
                             new_changed_nodes.insert(edge.to.as_u64());
 
-                            if exact_counting {
+                            if state.exact_counting {
                                 exact_changed_nodes.insert(edge.to);
                             }
 
@@ -127,10 +220,13 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
                         }
                     }
                 }
-            })
+            });
+
+            state.exact_changed_nodes = exact_changed_nodes;
         }
 
-        centralities.iter_mut().for_each(|(node, score)| {
+        let t = state.t;
+        state.centralities.iter_mut().for_each(|(node, score)| {
             *score += new_counters
                 .get(node)
                 .map(|counter| counter.size())
@@ -145,16 +241,54 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
                 / (t + 1) as f64;
         });
 
-        counters = new_counters;
+        state.counters = new_counters;
         changed_nodes = new_changed_nodes;
-        t += 1;
+        state.t += 1;
 
         if changed_nodes.estimate_card() <= exact_counting_threshold {
-            exact_counting = true;
+            state.exact_counting = true;
+        }
+
+        if let Some(checkpoint) = checkpoint {
+            if has_changes.load(Ordering::Relaxed) && state.t % checkpoint.interval.max(1) == 0 {
+                match checkpoint.save(&state) {
+                    Ok(()) => info!("Saved checkpoint after iteration {}", state.t),
+                    Err(err) => warn!("failed to save checkpoint: {}", err),
+                }
+            }
         }
     }
 
-    let res = centralities
+    state
+}
+
+fn calculate_centrality(
+    graph: &Webgraph,
+    checkpoint: Option<&HarmonicCheckpoint>,
+) -> BTreeMap<NodeID, f64> {
+    let state = iterate(graph, checkpoint, usize::MAX);
+    let num_nodes = state.counters.len();
+
+    let res = normalize(state.centralities, num_nodes);
+
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.remove();
+    }
+
+    info!("Harmonic centrality calculated");
+
+    res
+}
+
+/// Normalize the summed centralities by the number of other nodes in the graph.
+/// Nodes without any centrality are left out.
+pub(super) fn normalize(
+    centralities: BTreeMap<NodeID, KahanSum>,
+    num_nodes: usize,
+) -> BTreeMap<NodeID, f64> {
+    let norm_factor = (num_nodes - 1) as f64;
+
+    centralities
         .into_iter()
         .map(|(node_id, sum)| (node_id, f64::from(sum)))
         .filter(|(_, centrality)| *centrality > 0.0)
@@ -166,18 +300,20 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
                 (node_id, centrality)
             }
         })
-        .collect();
-
-    info!("Harmonic centrality calculated");
-
-    res
+        .collect()
 }
 
-pub struct HarmonicCentrality(BTreeMap<NodeID, f64>);
+pub struct HarmonicCentrality(pub(super) BTreeMap<NodeID, f64>);
 
 impl HarmonicCentrality {
     pub fn calculate(graph: &Webgraph) -> Self {
-        Self(calculate_centrality(graph))
+        Self(calculate_centrality(graph, None))
+    }
+
+    /// Calculate the centrality while periodically saving the state to the checkpoint.
+    /// If the checkpoint already exists, the calculation continues from it.
+    pub fn calculate_with_checkpoint(graph: &Webgraph, checkpoint: &HarmonicCheckpoint) -> Self {
+        Self(calculate_centrality(graph, Some(checkpoint)))
     }
 
     pub fn get(&self, node: &NodeID) -> Option<f64> {
@@ -369,4 +505,25 @@ mod tests {
 
         assert_eq!(centrality.0, centrality_extra.0);
     }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let graph = test_graph();
+        let expected = HarmonicCentrality::calculate(&graph);
+
+        let checkpoint = HarmonicCheckpoint {
+            path: crate::gen_temp_path().join("checkpoint"),
+            interval: 1,
+        };
+        fs::create_dir_all(checkpoint.path.parent().unwrap()).unwrap();
+
+        let partial = iterate(&graph, Some(&checkpoint), 1);
+        assert_eq!(partial.t, 1);
+        assert_eq!(checkpoint.load().unwrap().t, 1);
+
+        let resumed = HarmonicCentrality::calculate_with_checkpoint(&graph, &checkpoint);
+
+        assert_eq!(expected.0, resumed.0);
+        assert!(!checkpoint.path.exists());
+    }
 }
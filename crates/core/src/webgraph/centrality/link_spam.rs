@@ -0,0 +1,203 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of link farms in the host graph.
+//!
+//! Link farms are groups of hosts that link to each other to inflate their centrality.
+//! A host looks like part of a farm when most of its links are reciprocated and the
+//! hosts it exchanges links with also exchange links among themselves. Hosts that
+//! link to far more hosts than link to them, like link directories, are also suspicious.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rayon::prelude::*;
+use tracing::info;
+
+use crate::webgraph::{NodeID, Webgraph};
+
+/// Hosts with fewer reciprocal links than this are not considered part of a cluster.
+const MIN_CLUSTER_SIZE: usize = 3;
+
+/// Number of reciprocal neighbours used to estimate how densely they link to each other.
+const MAX_CLUSTER_NEIGHBOURS: usize = 64;
+
+/// Out-degree/in-degree ratio up to which a host is considered normal.
+const NORMAL_DEGREE_RATIO: f64 = 10.0;
+
+fn outgoing(graph: &Webgraph, node: &NodeID) -> BTreeSet<NodeID> {
+    graph
+        .raw_outgoing_edges(node)
+        .into_iter()
+        .map(|edge| edge.to)
+        .collect()
+}
+
+fn ingoing(graph: &Webgraph, node: &NodeID) -> BTreeSet<NodeID> {
+    graph
+        .raw_ingoing_edges(node)
+        .into_iter()
+        .map(|edge| edge.from)
+        .collect()
+}
+
+/// Fraction of the outgoing links that are reciprocated, multiplied by the fraction
+/// of pairs of reciprocal neighbours that also link reciprocally to each other.
+fn reciprocal_cluster_score(
+    graph: &Webgraph,
+    outgoing: &BTreeSet<NodeID>,
+    ingoing: &BTreeSet<NodeID>,
+) -> f64 {
+    let reciprocal: Vec<NodeID> = outgoing.intersection(ingoing).copied().collect();
+
+    if reciprocal.len() < MIN_CLUSTER_SIZE {
+        return 0.0;
+    }
+
+    let reciprocity = reciprocal.len() as f64 / outgoing.len() as f64;
+
+    let neighbours: Vec<_> = reciprocal
+        .into_iter()
+        .take(MAX_CLUSTER_NEIGHBOURS)
+        .map(|node| (node, self::outgoing(graph, &node)))
+        .collect();
+
+    let mut num_pairs = 0;
+    let mut num_linked = 0;
+
+    for (i, (a, a_outgoing)) in neighbours.iter().enumerate() {
+        for (b, b_outgoing) in &neighbours[i + 1..] {
+            num_pairs += 1;
+
+            if a_outgoing.contains(b) && b_outgoing.contains(a) {
+                num_linked += 1;
+            }
+        }
+    }
+
+    reciprocity * num_linked as f64 / num_pairs as f64
+}
+
+/// 0 up to the normal ratio, growing to 1 for hosts with 100 times the normal ratio.
+fn degree_ratio_score(out_degree: usize, in_degree: usize) -> f64 {
+    let ratio = (out_degree as f64 + 1.0) / (in_degree as f64 + 1.0);
+
+    ((ratio / NORMAL_DEGREE_RATIO).log10() / 2.0).clamp(0.0, 1.0)
+}
+
+fn spam_likelihood(graph: &Webgraph, node: &NodeID) -> f64 {
+    let outgoing = outgoing(graph, node);
+
+    if outgoing.is_empty() {
+        return 0.0;
+    }
+
+    let ingoing = ingoing(graph, node);
+
+    let cluster = reciprocal_cluster_score(graph, &outgoing, &ingoing);
+    let ratio = degree_ratio_score(outgoing.len(), ingoing.len());
+
+    1.0 - (1.0 - cluster) * (1.0 - ratio)
+}
+
+/// Likelihood in `[0, 1]` that a host is part of a link farm.
+pub struct LinkSpam(BTreeMap<NodeID, f64>);
+
+impl LinkSpam {
+    pub fn calculate(graph: &Webgraph) -> Self {
+        let scores: BTreeMap<NodeID, f64> = graph
+            .par_nodes()
+            .map(|node| (node, spam_likelihood(graph, &node)))
+            .collect();
+
+        info!(
+            "{} of {} hosts are likely link spam",
+            scores.values().filter(|score| **score > 0.5).count(),
+            scores.len()
+        );
+
+        Self(scores)
+    }
+
+    pub fn get(&self, node: &NodeID) -> Option<f64> {
+        self.0.get(node).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeID, f64)> {
+        self.0.iter().map(|(node, score)| (node, *score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{Node, WebgraphWriter};
+
+    fn score(spam: &LinkSpam, node: &str) -> f64 {
+        spam.get(&Node::from(node).id()).unwrap()
+    }
+
+    #[test]
+    fn link_farm() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        let farm = ["farm1.com", "farm2.com", "farm3.com", "farm4.com"];
+
+        for a in farm {
+            for b in farm {
+                writer.insert(Node::from(a), Node::from(b), String::new());
+            }
+        }
+
+        writer.insert(Node::from("a.com"), Node::from("b.com"), String::new());
+        writer.insert(Node::from("b.com"), Node::from("a.com"), String::new());
+        writer.insert(Node::from("c.com"), Node::from("a.com"), String::new());
+
+        for i in 0..30 {
+            writer.insert(
+                Node::from("directory.com"),
+                Node::from(format!("site{i}.com")),
+                String::new(),
+            );
+        }
+
+        let graph = writer.finalize();
+        let spam = LinkSpam::calculate(&graph);
+
+        for host in farm {
+            assert!(score(&spam, host) > 0.99);
+        }
+
+        assert_eq!(score(&spam, "a.com"), 0.0);
+        assert_eq!(score(&spam, "b.com"), 0.0);
+        assert_eq!(score(&spam, "site0.com"), 0.0);
+
+        let directory = score(&spam, "directory.com");
+        assert!(directory > 0.2);
+        assert!(directory < score(&spam, "farm1.com"));
+    }
+
+    #[test]
+    fn degree_ratio() {
+        assert_eq!(degree_ratio_score(0, 100), 0.0);
+        assert_eq!(degree_ratio_score(9, 0), 0.0);
+        assert!((degree_ratio_score(999, 0) - 1.0).abs() < 1e-9);
+        assert_eq!(degree_ratio_score(100_000, 0), 1.0);
+    }
+}
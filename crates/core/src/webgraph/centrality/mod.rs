@@ -21,7 +21,11 @@ use super::NodeID;
 pub mod approx_harmonic;
 pub mod betweenness;
 pub mod derived_harmonic;
+pub mod distributed_harmonic;
 pub mod harmonic;
+pub mod link_spam;
+pub mod pagerank;
+pub mod trustrank;
 
 #[derive(Debug, Clone, Copy)]
 pub enum TopHosts {
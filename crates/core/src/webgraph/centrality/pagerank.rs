@@ -0,0 +1,301 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! PageRank of the nodes in the graph, computed by power iteration.
+//!
+//! Unlike harmonic centrality, a node's score depends on the score of the nodes
+//! linking to it and is split between all the outgoing links of a node, so a node
+//! linking to many other nodes passes on less to each of them. When weighted, the
+//! score is split in proportion to the weight of the outgoing edges instead of evenly.
+//!
+//! Only the scores are kept in memory. The outgoing edges are read from the graph
+//! in chunks of nodes in every iteration.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use tracing::info;
+
+use crate::webgraph::{NodeID, Webgraph, NODE_CHUNK_SIZE};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageRankConfig {
+    /// Probability that the random surfer follows a link instead of jumping
+    /// to a random node.
+    pub damping: f64,
+    /// The iteration stops when the L1 distance between the scores of two
+    /// consecutive iterations is below the tolerance.
+    pub tolerance: f64,
+    pub max_iterations: usize,
+    /// Split the score of a node by the weight of its outgoing edges.
+    pub weighted: bool,
+}
+
+impl Default for PageRankConfig {
+    fn default() -> Self {
+        Self {
+            damping: 0.85,
+            tolerance: 1e-6,
+            max_iterations: 100,
+            weighted: false,
+        }
+    }
+}
+
+/// Power iteration where the random surfer jumps to a random node of `seeds`, or to
+/// any node if there are no seeds. With seeds this is personalized pagerank, which
+/// is used for TrustRank.
+pub(super) fn calculate_pagerank(
+    graph: &Webgraph,
+    config: &PageRankConfig,
+    seeds: Option<&HashSet<NodeID>>,
+) -> BTreeMap<NodeID, f64> {
+    let nodes: Vec<NodeID> = graph.nodes().collect();
+    let num_nodes = nodes.len();
+
+    info!("Found {} nodes in the graph", num_nodes);
+
+    let teleport: Vec<f64> = match seeds {
+        Some(seeds) => {
+            let num_seeds = nodes.iter().filter(|node| seeds.contains(node)).count();
+            info!("Found {} seeds in the graph", num_seeds);
+
+            nodes
+                .iter()
+                .map(|node| {
+                    if seeds.contains(node) {
+                        1.0 / num_seeds as f64
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
+        }
+        None => vec![1.0 / num_nodes as f64; num_nodes],
+    };
+
+    if num_nodes == 0 || teleport.iter().all(|t| *t == 0.0) {
+        return BTreeMap::new();
+    }
+
+    let index: HashMap<NodeID, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (*node, i))
+        .collect();
+
+    let outgoing = |node: NodeID| -> Vec<(usize, f64)> {
+        if config.weighted {
+            graph
+                .raw_outgoing_edges_with_weights(&node)
+                .into_iter()
+                .filter_map(|(edge, weight)| index.get(&edge.to).map(|to| (*to, weight.value())))
+                .collect()
+        } else {
+            graph
+                .raw_outgoing_edges(&node)
+                .into_iter()
+                .filter_map(|edge| index.get(&edge.to).map(|to| (*to, 1.0)))
+                .collect()
+        }
+    };
+
+    let mut total_weights = vec![0.0; num_nodes];
+
+    for chunk in graph.par_map_node_chunks(NODE_CHUNK_SIZE, outgoing) {
+        for (node, out) in chunk {
+            if let Some(i) = index.get(&node) {
+                total_weights[*i] = out.iter().map(|(_, weight)| weight).sum();
+            }
+        }
+    }
+
+    let damping = config.damping;
+    let mut scores = teleport.clone();
+
+    for iteration in 0..config.max_iterations {
+        // nodes without outgoing edges distribute their score like a random jump,
+        // otherwise the scores would leak out of the graph.
+        let dangling: f64 = total_weights
+            .iter()
+            .zip(&scores)
+            .filter(|(total_weight, _)| **total_weight == 0.0)
+            .map(|(_, score)| score)
+            .sum();
+
+        let jump = (1.0 - damping) + damping * dangling;
+        let mut new_scores: Vec<f64> = teleport.iter().map(|t| t * jump).collect();
+
+        for chunk in graph.par_map_node_chunks(NODE_CHUNK_SIZE, outgoing) {
+            for (node, out) in chunk {
+                let Some(i) = index.get(&node).copied() else {
+                    continue;
+                };
+
+                if total_weights[i] == 0.0 {
+                    continue;
+                }
+
+                let share = damping * scores[i] / total_weights[i];
+
+                for (to, weight) in out {
+                    new_scores[to] += share * weight;
+                }
+            }
+        }
+
+        let diff: f64 = new_scores
+            .iter()
+            .zip(&scores)
+            .map(|(new, old)| (new - old).abs())
+            .sum();
+
+        scores = new_scores;
+
+        if diff < config.tolerance {
+            info!("PageRank converged after {} iterations", iteration + 1);
+            break;
+        }
+    }
+
+    nodes.into_iter().zip(scores).collect()
+}
+
+pub struct PageRank(BTreeMap<NodeID, f64>);
+
+impl PageRank {
+    pub fn calculate(graph: &Webgraph, config: &PageRankConfig) -> Self {
+        Self(calculate_pagerank(graph, config, None))
+    }
+
+    pub fn get(&self, node: &NodeID) -> Option<f64> {
+        self.0.get(node).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeID, f64)> {
+        self.0.iter().map(|(node, score)| (node, *score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{EdgeWeight, Node, WebgraphWriter};
+
+    fn graph(edges: &[(&str, &str)]) -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to) in edges {
+            writer.insert(Node::from(*from), Node::from(*to), String::new());
+        }
+
+        writer.finalize()
+    }
+
+    fn score(pagerank: &PageRank, node: &str) -> f64 {
+        pagerank.get(&Node::from(node).id()).unwrap()
+    }
+
+    #[test]
+    fn scores_sum_to_one() {
+        //     ┌────┐
+        //     │    │
+        // ┌───A◄─┐ │
+        // │      │ │
+        // ▼      │ │
+        // B─────►C◄┘
+        //        ▲
+        //        │
+        //        │
+        //        D
+        let graph = graph(&[("A", "B"), ("B", "C"), ("A", "C"), ("C", "A"), ("D", "C")]);
+        let pagerank = PageRank::calculate(&graph, &PageRankConfig::default());
+
+        let sum: f64 = pagerank.iter().map(|(_, score)| score).sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+
+        assert!(score(&pagerank, "C") > score(&pagerank, "A"));
+        assert!(score(&pagerank, "A") > score(&pagerank, "B"));
+        assert!(score(&pagerank, "B") > score(&pagerank, "D"));
+    }
+
+    #[test]
+    fn dangling_nodes() {
+        // B and C have no outgoing edges, so their score is spread over all nodes.
+        let graph = graph(&[("A", "B"), ("A", "C")]);
+        let pagerank = PageRank::calculate(&graph, &PageRankConfig::default());
+
+        let sum: f64 = pagerank.iter().map(|(_, score)| score).sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+
+        assert!((score(&pagerank, "B") - score(&pagerank, "C")).abs() < 1e-9);
+        assert!(score(&pagerank, "B") > score(&pagerank, "A"));
+    }
+
+    #[test]
+    fn weighted() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        writer.insert_with_weight(
+            Node::from("A"),
+            Node::from("C"),
+            String::new(),
+            EdgeWeight::link(true, true),
+        );
+
+        let graph = writer.finalize();
+
+        let pagerank = PageRank::calculate(&graph, &PageRankConfig::default());
+        assert!((score(&pagerank, "B") - score(&pagerank, "C")).abs() < 1e-9);
+
+        let pagerank = PageRank::calculate(
+            &graph,
+            &PageRankConfig {
+                weighted: true,
+                ..Default::default()
+            },
+        );
+
+        let sum: f64 = pagerank.iter().map(|(_, score)| score).sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+        assert!(score(&pagerank, "B") > score(&pagerank, "C"));
+    }
+
+    #[test]
+    fn no_damping_is_uniform() {
+        let graph = graph(&[("A", "B"), ("B", "C"), ("D", "C")]);
+        let pagerank = PageRank::calculate(
+            &graph,
+            &PageRankConfig {
+                damping: 0.0,
+                ..Default::default()
+            },
+        );
+
+        for (_, score) in pagerank.iter() {
+            assert!((score - 0.25).abs() < 1e-9);
+        }
+    }
+}
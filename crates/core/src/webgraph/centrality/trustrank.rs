@@ -0,0 +1,152 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! TrustRank of the hosts in the host graph.
+//!
+//! Trust starts at a manually curated set of reputable seed hosts and is propagated
+//! along the links like pagerank. Spam hosts can link to each other as much as
+//! they want, but it is hard for them to get links from trusted hosts, so they
+//! end up with little trust.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::webgraph::{Node, NodeID, Webgraph};
+
+use super::pagerank::{calculate_pagerank, PageRankConfig};
+
+/// Read the seed hosts from a file with one host (or url) per line.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn read_seeds<P: AsRef<Path>>(path: P) -> Result<Vec<Node>> {
+    let mut seeds = Vec::new();
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        seeds.push(Node::from(line).into_host());
+    }
+
+    Ok(seeds)
+}
+
+pub struct TrustRank(BTreeMap<NodeID, f64>);
+
+impl TrustRank {
+    /// The trust is scaled so the most trusted host has a trust of 1.
+    pub fn calculate(graph: &Webgraph, seeds: &[Node], config: &PageRankConfig) -> Self {
+        let seeds: HashSet<NodeID> = seeds.iter().map(|seed| seed.id()).collect();
+        let mut trust = calculate_pagerank(graph, config, Some(&seeds));
+
+        let max = trust.values().copied().fold(0.0, f64::max);
+
+        if max > 0.0 {
+            for value in trust.values_mut() {
+                *value /= max;
+            }
+        }
+
+        Self(trust)
+    }
+
+    pub fn get(&self, node: &NodeID) -> Option<f64> {
+        self.0.get(node).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeID, f64)> {
+        self.0.iter().map(|(node, trust)| (node, *trust))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::WebgraphWriter;
+
+    fn trust(trustrank: &TrustRank, host: &str) -> f64 {
+        trustrank.get(&Node::from(host).id()).unwrap_or_default()
+    }
+
+    #[test]
+    fn spam_farm_gets_no_trust() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to) in [
+            ("trusted.com", "a.com"),
+            ("a.com", "b.com"),
+            ("b.com", "trusted.com"),
+            ("spam1.com", "spam2.com"),
+            ("spam2.com", "spam3.com"),
+            ("spam3.com", "spam1.com"),
+            ("spam1.com", "spam3.com"),
+            ("spam2.com", "spam1.com"),
+            ("spam3.com", "b.com"),
+        ] {
+            writer.insert(Node::from(from), Node::from(to), String::new());
+        }
+
+        let graph = writer.finalize();
+
+        let trustrank = TrustRank::calculate(
+            &graph,
+            &[Node::from("trusted.com")],
+            &PageRankConfig::default(),
+        );
+
+        assert_eq!(trust(&trustrank, "trusted.com"), 1.0);
+        assert!(trust(&trustrank, "a.com") > trust(&trustrank, "spam1.com"));
+        assert!(trust(&trustrank, "b.com") > 0.0);
+
+        for spam in ["spam1.com", "spam2.com", "spam3.com"] {
+            assert_eq!(trust(&trustrank, spam), 0.0);
+        }
+    }
+
+    #[test]
+    fn seeds_file() {
+        let path = crate::gen_temp_path().join("seeds.txt");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            "# curated hosts\nexample.com\n\n  https://www.wikipedia.org/wiki/Main_Page \n",
+        )
+        .unwrap();
+
+        let seeds = read_seeds(&path).unwrap();
+
+        assert_eq!(
+            seeds,
+            vec![
+                Node::from("example.com").into_host(),
+                Node::from("https://www.wikipedia.org/wiki/Main_Page").into_host(),
+            ]
+        );
+    }
+}
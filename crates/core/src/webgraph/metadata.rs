@@ -0,0 +1,136 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Metadata about the nodes in the webgraph. The metadata is collected from the
+//! pages while the graph is constructed, so consumers of the graph can use it
+//! without joining against the search index.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::webpage::region::Region;
+
+use super::node_store::{Mergeable, NodeStore};
+
+pub type MetadataStore = NodeStore<NodeMetadata>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeMetadata {
+    /// Number of fetched pages for each declared language.
+    pub languages: BTreeMap<String, u64>,
+    /// Country of the top level domain, if it is a region we know about.
+    pub country: Option<Region>,
+    /// Unix timestamps (seconds) of the first and last fetch.
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Number of fetched pages. For page nodes this is the number of times
+    /// the page has been fetched.
+    pub num_pages: u64,
+}
+
+impl NodeMetadata {
+    /// Metadata of a single page fetched at `fetch_time` (unix seconds).
+    pub fn page(url: &Url, language: Option<String>, fetch_time: u64) -> Self {
+        let mut languages = BTreeMap::new();
+
+        if let Some(language) = language {
+            languages.insert(language, 1);
+        }
+
+        Self {
+            languages,
+            country: url.host_str().and_then(Region::from_tld),
+            first_seen: fetch_time,
+            last_seen: fetch_time,
+            num_pages: 1,
+        }
+    }
+
+    /// The most common language of the pages.
+    pub fn language(&self) -> Option<&str> {
+        self.languages
+            .iter()
+            .max_by(|(a_lang, a), (b_lang, b)| a.cmp(b).then(b_lang.cmp(a_lang)))
+            .map(|(lang, _)| lang.as_str())
+    }
+}
+
+impl Mergeable for NodeMetadata {
+    fn merge(&mut self, other: Self) {
+        if self.num_pages == 0 {
+            *self = other;
+            return;
+        }
+
+        if other.num_pages == 0 {
+            return;
+        }
+
+        for (lang, count) in other.languages {
+            *self.languages.entry(lang).or_default() += count;
+        }
+
+        self.country = self.country.or(other.country);
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.num_pages += other.num_pages;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webpage::Html;
+
+    use super::*;
+
+    #[test]
+    fn merge_metadata() {
+        let url = Url::parse("https://example.dk/a").unwrap();
+
+        let mut a = NodeMetadata::page(&url, Some("da".to_string()), 20);
+        a.merge(NodeMetadata::page(&url, Some("en".to_string()), 10));
+        a.merge(NodeMetadata::page(&url, Some("da".to_string()), 30));
+        a.merge(NodeMetadata::page(&url, None, 15));
+
+        assert_eq!(a.country, Some(Region::Denmark));
+        assert_eq!(a.first_seen, 10);
+        assert_eq!(a.last_seen, 30);
+        assert_eq!(a.num_pages, 4);
+        assert_eq!(a.language(), Some("da"));
+
+        let mut empty = NodeMetadata::default();
+        empty.merge(a.clone());
+        assert_eq!(empty, a);
+    }
+
+    #[test]
+    fn declared_language() {
+        let html = Html::parse_without_text(
+            r#"<html lang="en-GB"><head><title>Test</title></head><body></body></html>"#,
+            "https://example.com",
+        )
+        .unwrap();
+
+        assert_eq!(html.declared_lang(), Some("en".to_string()));
+
+        let html =
+            Html::parse_without_text("<html><body></body></html>", "https://example.com").unwrap();
+
+        assert_eq!(html.declared_lang(), None);
+    }
+}
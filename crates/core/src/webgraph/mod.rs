@@ -31,12 +31,27 @@ use crate::executor::Executor;
 use crate::intmap;
 use crate::webpage::url_ext::UrlExt;
 
+pub mod anchor_text;
 pub mod centrality;
+pub mod metadata;
+pub mod node_store;
 mod store;
+use self::anchor_text::{AnchorTextStore, AnchorTexts};
+use self::metadata::{MetadataStore, NodeMetadata};
+use self::node_store::Mergeable;
 use self::segment::{Segment, SegmentWriter};
 
 pub const MAX_LABEL_LENGTH: usize = 1024;
 
+/// Number of nodes processed together when iterating the graph in chunks.
+pub const NODE_CHUNK_SIZE: usize = 10_000;
+
+/// Maximum number of nodes the shortest path search visits before giving up.
+pub const MAX_PATH_VISITED_NODES: usize = 100_000;
+
+/// Maximum number of links of a node that are scored when ranking its links.
+pub const MAX_RANKED_LINKS: usize = 10_000;
+
 #[derive(
     Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
 )]
@@ -133,6 +148,54 @@ where
     pub from: FullNodeID,
     pub to: FullNodeID,
     pub label: L,
+    pub weight: EdgeWeight,
+}
+
+/// The links an edge represents. Every link from one node to another is counted,
+/// so the edges between hosts carry the number of links between their pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct EdgeWeight {
+    pub num_links: u32,
+    /// Followed links in the main content of the page, as opposed to navigation,
+    /// headers and footers.
+    pub num_prominent: u32,
+    /// Links marked as nofollow, sponsored or ugc.
+    pub num_nofollow: u32,
+}
+
+impl Default for EdgeWeight {
+    /// A single followed link in the main content.
+    fn default() -> Self {
+        Self::link(true, false)
+    }
+}
+
+impl EdgeWeight {
+    pub fn link(prominent: bool, nofollow: bool) -> Self {
+        Self {
+            num_links: 1,
+            num_prominent: u32::from(prominent && !nofollow),
+            num_nofollow: u32::from(nofollow),
+        }
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.num_links = self.num_links.saturating_add(other.num_links);
+        self.num_prominent = self.num_prominent.saturating_add(other.num_prominent);
+        self.num_nofollow = self.num_nofollow.saturating_add(other.num_nofollow);
+    }
+
+    /// Links in navigation count half and nofollow links a tenth of a prominent link.
+    /// The sum is log-scaled so sitewide links don't dominate.
+    pub fn value(&self) -> f64 {
+        let followed = self.num_links.saturating_sub(self.num_nofollow);
+        let boilerplate = followed.saturating_sub(self.num_prominent);
+
+        let links =
+            self.num_prominent as f64 + 0.5 * boilerplate as f64 + 0.1 * self.num_nofollow as f64;
+
+        links.ln_1p()
+    }
 }
 
 impl<L> From<InnerEdge<L>> for Edge<L>
@@ -334,6 +397,126 @@ pub trait ShortestPaths {
     fn raw_distances_with_max(&self, source: NodeID, max_dist: u8) -> BTreeMap<NodeID, u8>;
     fn raw_reversed_distances(&self, source: NodeID) -> BTreeMap<NodeID, u8>;
     fn reversed_distances(&self, source: Node) -> BTreeMap<Node, u8>;
+    fn raw_shortest_path(
+        &self,
+        source: NodeID,
+        target: NodeID,
+        max_dist: u8,
+    ) -> Option<Vec<NodeID>>;
+    fn shortest_path(&self, source: Node, target: Node, max_dist: u8) -> Option<Vec<Node>>;
+}
+
+/// Bidirectional breadth-first search between `source` and `target`. The side with
+/// the smallest frontier is expanded one level at a time, and the search gives up
+/// once more than `max_visited` nodes have been reached from either side.
+/// The returned path includes both `source` and `target`.
+fn bfs_path<F1, F2>(
+    source: NodeID,
+    target: NodeID,
+    outgoing: F1,
+    ingoing: F2,
+    max_dist: u8,
+    max_visited: usize,
+) -> Option<Vec<NodeID>>
+where
+    F1: Fn(NodeID) -> Vec<NodeID>,
+    F2: Fn(NodeID) -> Vec<NodeID>,
+{
+    if source == target {
+        return Some(vec![source]);
+    }
+
+    // the node each visited node was reached from
+    let mut parents: BTreeMap<NodeID, NodeID> = BTreeMap::default();
+    let mut children: BTreeMap<NodeID, NodeID> = BTreeMap::default();
+
+    let mut forward = vec![source];
+    let mut backward = vec![target];
+
+    for _ in 0..max_dist {
+        let meeting = if forward.len() <= backward.len() {
+            bfs_expand(
+                &mut forward,
+                &mut parents,
+                &children,
+                source,
+                target,
+                &outgoing,
+            )
+        } else {
+            bfs_expand(
+                &mut backward,
+                &mut children,
+                &parents,
+                target,
+                source,
+                &ingoing,
+            )
+        };
+
+        if let Some(node) = meeting {
+            let mut path = vec![node];
+            let mut current = node;
+
+            while let Some(parent) = parents.get(&current) {
+                path.push(*parent);
+                current = *parent;
+            }
+
+            path.reverse();
+            current = node;
+
+            while let Some(child) = children.get(&current) {
+                path.push(*child);
+                current = *child;
+            }
+
+            return Some(path);
+        }
+
+        if forward.is_empty() || backward.is_empty() || parents.len() + children.len() > max_visited
+        {
+            break;
+        }
+    }
+
+    None
+}
+
+/// Expand `frontier` by one level. Returns the first node that has already been
+/// reached from the other side of the search.
+fn bfs_expand<F>(
+    frontier: &mut Vec<NodeID>,
+    visited: &mut BTreeMap<NodeID, NodeID>,
+    other_visited: &BTreeMap<NodeID, NodeID>,
+    start: NodeID,
+    end: NodeID,
+    neighbours: &F,
+) -> Option<NodeID>
+where
+    F: Fn(NodeID) -> Vec<NodeID>,
+{
+    let mut next = Vec::new();
+
+    for node in std::mem::take(frontier) {
+        for neighbour in neighbours(node) {
+            if neighbour == start || visited.contains_key(&neighbour) {
+                continue;
+            }
+
+            visited.insert(neighbour, node);
+
+            if neighbour == end || other_visited.contains_key(&neighbour) {
+                return Some(neighbour);
+            }
+
+            next.push(neighbour);
+        }
+    }
+
+    *frontier = next;
+
+    None
 }
 
 fn dijkstra_multi<F1, F2, L>(
@@ -426,6 +609,39 @@ impl ShortestPaths for Webgraph {
             .filter_map(|(id, dist)| self.id2node(&id).map(|node| (node, dist)))
             .collect()
     }
+
+    fn raw_shortest_path(
+        &self,
+        source: NodeID,
+        target: NodeID,
+        max_dist: u8,
+    ) -> Option<Vec<NodeID>> {
+        bfs_path(
+            source,
+            target,
+            |node| {
+                self.raw_outgoing_edges(&node)
+                    .into_iter()
+                    .map(|edge| edge.to)
+                    .collect()
+            },
+            |node| {
+                self.raw_ingoing_edges(&node)
+                    .into_iter()
+                    .map(|edge| edge.from)
+                    .collect()
+            },
+            max_dist,
+            MAX_PATH_VISITED_NODES,
+        )
+    }
+
+    fn shortest_path(&self, source: Node, target: Node, max_dist: u8) -> Option<Vec<Node>> {
+        self.raw_shortest_path(source.id(), target.id(), max_dist)?
+            .into_iter()
+            .map(|id| self.id2node(&id))
+            .collect()
+    }
 }
 
 type SegmentID = String;
@@ -573,6 +789,21 @@ impl Id2NodeDb {
         }
     }
 
+    fn batch_delete(&mut self, ids: impl Iterator<Item = NodeID>) {
+        let mut batch = rocksdb::WriteBatch::default();
+
+        for id in ids {
+            batch.delete(id.as_u64().to_le_bytes());
+        }
+
+        self.db.write(batch).unwrap();
+    }
+
+    /// Rewrite the files of the store so deleted nodes no longer take up space.
+    fn compact(&self) {
+        self.db.compact_range::<&[u8], &[u8]>(None, None);
+    }
+
     fn flush(&self) {
         self.db.flush().unwrap();
     }
@@ -583,6 +814,8 @@ pub struct WebgraphWriter {
     segment: SegmentWriter,
     insert_batch: Vec<InnerEdge<String>>,
     id2node: Id2NodeDb,
+    metadata: MetadataStore,
+    anchor_texts: AnchorTextStore,
     executor: Executor,
     meta: Meta,
     compression: Compression,
@@ -615,6 +848,8 @@ impl WebgraphWriter {
             path: path.as_ref().as_os_str().to_str().unwrap().to_string(),
             segment,
             id2node: Id2NodeDb::open(path.as_ref().join("id2node")),
+            metadata: MetadataStore::open(path.as_ref().join("node_metadata")),
+            anchor_texts: AnchorTextStore::open(path.as_ref().join("anchor_texts")),
             insert_batch: Vec::with_capacity(store::MAX_BATCH_SIZE),
             executor,
             meta,
@@ -635,6 +870,12 @@ impl WebgraphWriter {
     }
 
     pub fn insert(&mut self, from: Node, to: Node, label: String) {
+        self.insert_with_weight(from, to, label, EdgeWeight::default());
+    }
+
+    /// Insert a link. Multiple links between the same nodes are merged into a
+    /// single edge whose weight is the sum of the link weights.
+    pub fn insert_with_weight(&mut self, from: Node, to: Node, label: String, weight: EdgeWeight) {
         if from == to {
             return;
         }
@@ -644,10 +885,17 @@ impl WebgraphWriter {
             self.id_or_assign(to.clone()),
         );
 
+        let label: String = label.chars().take(MAX_LABEL_LENGTH).collect();
+
+        if let Some(anchor_text) = AnchorTexts::link(&label) {
+            self.anchor_texts.insert(to_id.id, anchor_text);
+        }
+
         let edge = InnerEdge {
             from: from_id,
             to: to_id,
-            label: label.chars().take(MAX_LABEL_LENGTH).collect(),
+            label,
+            weight,
         };
 
         self.insert_batch.push(edge);
@@ -657,6 +905,12 @@ impl WebgraphWriter {
         }
     }
 
+    /// Record metadata of a fetched page for the node. Metadata inserted
+    /// for the same node multiple times is merged.
+    pub fn insert_metadata(&mut self, node: Node, metadata: NodeMetadata) {
+        self.metadata.insert(node.id(), metadata);
+    }
+
     pub fn commit(&mut self) {
         if !self.insert_batch.is_empty() {
             self.segment.insert(&self.insert_batch);
@@ -666,6 +920,8 @@ impl WebgraphWriter {
 
         self.save_metadata();
         self.id2node.flush();
+        self.metadata.commit();
+        self.anchor_texts.commit();
     }
 
     pub fn finalize(mut self) -> Webgraph {
@@ -676,6 +932,8 @@ impl WebgraphWriter {
             segments: vec![self.segment.finalize()],
             executor: self.executor.into(),
             id2node: self.id2node,
+            metadata: self.metadata,
+            anchor_texts: self.anchor_texts,
             meta: self.meta,
             compression: self.compression,
         }
@@ -687,6 +945,8 @@ pub struct Webgraph {
     segments: Vec<Segment>,
     executor: Arc<Executor>,
     id2node: Id2NodeDb,
+    metadata: MetadataStore,
+    anchor_texts: AnchorTextStore,
     meta: Meta,
     compression: Compression,
 }
@@ -722,6 +982,8 @@ impl Webgraph {
             segments,
             executor: Arc::new(executor),
             id2node: Id2NodeDb::open(path.as_ref().join("id2node")),
+            metadata: MetadataStore::open(path.as_ref().join("node_metadata")),
+            anchor_texts: AnchorTextStore::open(path.as_ref().join("anchor_texts")),
             meta,
             compression,
         }
@@ -729,6 +991,8 @@ impl Webgraph {
 
     pub fn merge(&mut self, other: Webgraph) {
         self.id2node.batch_put(other.id2node.iter());
+        self.metadata.merge(&other.metadata);
+        self.anchor_texts.merge(&other.anchor_texts);
 
         for segment in other.segments {
             let id = segment.id();
@@ -745,6 +1009,90 @@ impl Webgraph {
         self.id2node.flush();
     }
 
+    /// Rewrite all segments into a single segment. An edge is only kept once even if
+    /// it is present in several segments, which is the case for edges that were
+    /// found by more than one of the graphs that have been merged. Just like when the
+    /// edges are read, only the first label is kept for each pair of nodes.
+    /// Nodes that are not part of any edge are removed from the id-to-node store.
+    pub fn compact(&mut self) {
+        let segments_path = Path::new(&self.path).join("segments");
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut writer = SegmentWriter::open(&segments_path, id.clone(), self.compression);
+
+        let mut batch = Vec::with_capacity(store::MAX_BATCH_SIZE);
+        let mut orphans = Vec::new();
+
+        for (node_id, node) in self.id2node.iter() {
+            let outgoing = self.inner_edges(
+                |segment| segment.outgoing_edges_with_label(&node_id),
+                |edges: &mut Vec<Edge<String>>| {
+                    edges.sort_by_key(|e| e.to);
+                    edges.dedup_by_key(|e| e.to);
+                },
+            );
+
+            if outgoing.is_empty()
+                && self
+                    .segments
+                    .iter()
+                    .all(|segment| segment.ingoing_edges(&node_id).is_empty())
+            {
+                orphans.push(node_id);
+                continue;
+            }
+
+            let from = FullNodeID::from(node);
+            let weights: BTreeMap<NodeID, EdgeWeight> = self
+                .raw_outgoing_edges_with_weights(&node_id)
+                .into_iter()
+                .map(|(edge, weight)| (edge.to, weight))
+                .collect();
+
+            for edge in outgoing {
+                let Some(to) = self.id2node(&edge.to) else {
+                    continue;
+                };
+
+                batch.push(InnerEdge {
+                    from: from.clone(),
+                    to: FullNodeID::from(to),
+                    label: edge.label,
+                    weight: weights.get(&edge.to).copied().unwrap_or_default(),
+                });
+
+                if batch.len() >= store::MAX_BATCH_SIZE {
+                    writer.insert(&batch);
+                    writer.flush();
+                    batch.clear();
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            writer.insert(&batch);
+        }
+
+        let segment = writer.finalize();
+
+        // the metadata must point to the new segment before the old ones are removed,
+        // otherwise a crash in between would leave a graph without any segments.
+        self.meta.comitted_segments = vec![id];
+        self.save_metadata();
+
+        for old in std::mem::replace(&mut self.segments, vec![segment]) {
+            let path = old.path();
+            drop(old);
+            fs::remove_dir_all(path).unwrap();
+        }
+
+        if !orphans.is_empty() {
+            self.id2node.batch_delete(orphans.into_iter());
+        }
+
+        self.id2node.flush();
+        self.id2node.compact();
+    }
+
     pub fn ingoing_edges(&self, node: Node) -> Vec<FullEdge> {
         let dedup = |edges: &mut Vec<Edge<String>>| {
             edges.sort_by_key(|e| e.from);
@@ -837,6 +1185,115 @@ impl Webgraph {
         self.inner_edges(|segment| segment.outgoing_edges(node), dedup)
     }
 
+    /// Outgoing edges with the weights from all segments added up.
+    pub fn raw_outgoing_edges_with_weights(&self, node: &NodeID) -> Vec<(Edge<()>, EdgeWeight)> {
+        let mut edges: Vec<_> = self
+            .executor
+            .map(
+                |segment| segment.outgoing_edges_with_weight(node),
+                self.segments.iter(),
+            )
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+
+        edges.sort_by_key(|(e, _)| e.to);
+
+        let mut res: Vec<(Edge<()>, EdgeWeight)> = Vec::with_capacity(edges.len());
+
+        for (edge, weight) in edges {
+            match res.last_mut() {
+                Some((last, last_weight)) if last.to == edge.to => last_weight.merge(weight),
+                _ => res.push((edge, weight)),
+            }
+        }
+
+        res
+    }
+
+    pub fn raw_outgoing_edges_with_labels(&self, node: &NodeID) -> Vec<Edge<String>> {
+        let dedup = |edges: &mut Vec<Edge<String>>| {
+            edges.sort_by_key(|e| e.to);
+            edges.dedup_by_key(|e| e.to);
+        };
+
+        self.inner_edges(|segment| segment.outgoing_edges_with_label(node), dedup)
+    }
+
+    /// Links pointing to `node`, ordered by the score of the linking node (highest first).
+    /// Only the `limit` links after the first `offset` are resolved and returned.
+    /// At most [`MAX_RANKED_LINKS`] links are scored, so nodes with more links
+    /// than that only have a subset of them ranked.
+    pub fn ingoing_links<F>(
+        &self,
+        node: &Node,
+        score: F,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<FullEdge>
+    where
+        F: Fn(&NodeID) -> f64,
+    {
+        let edges = self.raw_ingoing_edges_with_labels(&node.id());
+        self.ranked_links(edges, |edge| edge.from, score, offset, limit)
+    }
+
+    /// Links from `node`, ordered by the score of the linked node (highest first).
+    /// Only the `limit` links after the first `offset` are resolved and returned.
+    pub fn outgoing_links<F>(
+        &self,
+        node: &Node,
+        score: F,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<FullEdge>
+    where
+        F: Fn(&NodeID) -> f64,
+    {
+        let edges = self.raw_outgoing_edges_with_labels(&node.id());
+        self.ranked_links(edges, |edge| edge.to, score, offset, limit)
+    }
+
+    fn ranked_links<F1, F2>(
+        &self,
+        mut edges: Vec<Edge<String>>,
+        other: F1,
+        score: F2,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<FullEdge>
+    where
+        F1: Fn(&Edge<String>) -> NodeID,
+        F2: Fn(&NodeID) -> f64,
+    {
+        edges.truncate(MAX_RANKED_LINKS);
+
+        let mut scored: Vec<_> = edges
+            .into_iter()
+            .map(|edge| (score(&other(&edge)), edge))
+            .collect();
+
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| other(a).cmp(&other(b)))
+        });
+
+        scored
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|(_, edge)| {
+                Some(FullEdge {
+                    from: self.id2node(&edge.from)?,
+                    to: self.id2node(&edge.to)?,
+                    label: edge.label,
+                })
+            })
+            .collect()
+    }
+
     fn inner_edges<F1, F2, L>(&self, loader: F1, dedup: F2) -> Vec<Edge<L>>
     where
         L: EdgeLabel,
@@ -860,6 +1317,30 @@ impl Webgraph {
         self.id2node.get(id)
     }
 
+    /// Metadata collected from the fetched pages of the node.
+    pub fn metadata(&self, id: &NodeID) -> Option<NodeMetadata> {
+        self.metadata.get(id)
+    }
+
+    /// Anchor texts of the links to the node, most frequent first.
+    /// Graphs built before the anchor texts were stored fall back to the labels
+    /// of the ingoing edges.
+    pub fn anchor_texts(&self, id: &NodeID) -> Vec<String> {
+        self.anchor_texts
+            .get(id)
+            .or_else(|| {
+                self.raw_ingoing_edges_with_labels(id)
+                    .into_iter()
+                    .filter_map(|edge| AnchorTexts::link(&edge.label))
+                    .reduce(|mut texts, other| {
+                        texts.merge(other);
+                        texts
+                    })
+            })
+            .map(|anchor_texts| anchor_texts.texts().into_iter().map(String::from).collect())
+            .unwrap_or_default()
+    }
+
     pub fn nodes(&self) -> impl Iterator<Item = NodeID> + '_ {
         self.id2node.keys()
     }
@@ -875,6 +1356,38 @@ impl Webgraph {
         self.id2node.keys().par_bridge()
     }
 
+    /// Iterate the nodes in chunks of at most `chunk_size` nodes. Only a single chunk
+    /// is kept in memory at a time, so large graphs can be processed in batches.
+    pub fn node_chunks(&self, chunk_size: usize) -> impl Iterator<Item = Vec<NodeID>> + '_ {
+        let mut nodes = self.nodes();
+
+        std::iter::from_fn(move || {
+            let chunk: Vec<_> = nodes.by_ref().take(chunk_size).collect();
+
+            if chunk.is_empty() {
+                None
+            } else {
+                Some(chunk)
+            }
+        })
+    }
+
+    /// Iterate the nodes in chunks and map the nodes of each chunk with `f` in parallel.
+    /// This is useful for reading the edges of all nodes, as the edges of a chunk can be
+    /// read concurrently while only the edges of a single chunk are in memory.
+    pub fn par_map_node_chunks<'a, T, F>(
+        &'a self,
+        chunk_size: usize,
+        f: F,
+    ) -> impl Iterator<Item = Vec<(NodeID, T)>> + 'a
+    where
+        T: Send,
+        F: Fn(NodeID) -> T + Sync + Send + 'a,
+    {
+        self.node_chunks(chunk_size)
+            .map(move |chunk| chunk.into_par_iter().map(|node| (node, f(node))).collect())
+    }
+
     pub fn node_ids(&self) -> impl Iterator<Item = (Node, NodeID)> + '_ {
         self.id2node.iter().map(|(id, node)| (node, id))
     }
@@ -973,6 +1486,179 @@ mod test {
         assert_eq!(distances.get(&Node::from("B")), Some(&2));
     }
 
+    #[test]
+    fn edge_weights() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        writer.insert_with_weight(
+            Node::from("A"),
+            Node::from("B"),
+            String::new(),
+            EdgeWeight::link(false, false),
+        );
+        writer.commit();
+        writer.insert_with_weight(
+            Node::from("A"),
+            Node::from("B"),
+            String::new(),
+            EdgeWeight::link(true, true),
+        );
+        writer.insert(Node::from("A"), Node::from("C"), String::new());
+
+        let mut graph = writer.finalize();
+
+        let mut expected = vec![
+            (
+                Node::from("B").id(),
+                EdgeWeight {
+                    num_links: 3,
+                    num_prominent: 1,
+                    num_nofollow: 1,
+                },
+            ),
+            (Node::from("C").id(), EdgeWeight::default()),
+        ];
+        expected.sort_by_key(|(id, _)| *id);
+
+        let weights = |graph: &Webgraph| {
+            graph
+                .raw_outgoing_edges_with_weights(&Node::from("A").id())
+                .into_iter()
+                .map(|(edge, weight)| (edge.to, weight))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(weights(&graph), expected);
+
+        graph.compact();
+        assert_eq!(weights(&graph), expected);
+
+        assert!(expected[0].1.value() > EdgeWeight::default().value());
+        assert!(EdgeWeight::link(false, false).value() < EdgeWeight::default().value());
+        assert!(EdgeWeight::link(true, true).value() < EdgeWeight::link(false, false).value());
+    }
+
+    #[test]
+    fn shortest_path() {
+        let graph = test_graph();
+
+        assert_eq!(
+            graph.shortest_path(Node::from("D"), Node::from("B"), 5),
+            Some(vec![
+                Node::from("D"),
+                Node::from("C"),
+                Node::from("A"),
+                Node::from("B")
+            ])
+        );
+        assert_eq!(
+            graph.shortest_path(Node::from("D"), Node::from("B"), 2),
+            None
+        );
+        assert_eq!(
+            graph.shortest_path(Node::from("B"), Node::from("D"), 5),
+            None
+        );
+        assert_eq!(
+            graph.shortest_path(Node::from("A"), Node::from("A"), 0),
+            Some(vec![Node::from("A")])
+        );
+    }
+
+    #[test]
+    fn shortest_path_visit_budget() {
+        // 0 -> 1 -> 2 -> 3 -> 4
+        let outgoing = |node: NodeID| {
+            if node.as_u64() < 4 {
+                vec![NodeID::from(node.as_u64() + 1)]
+            } else {
+                vec![]
+            }
+        };
+        let ingoing = |node: NodeID| {
+            if node.as_u64() > 0 {
+                vec![NodeID::from(node.as_u64() - 1)]
+            } else {
+                vec![]
+            }
+        };
+
+        let path = bfs_path(
+            NodeID::from(0u64),
+            NodeID::from(4u64),
+            outgoing,
+            ingoing,
+            10,
+            100,
+        );
+        assert_eq!(path, Some((0..=4u64).map(NodeID::from).collect::<Vec<_>>()));
+
+        assert_eq!(
+            bfs_path(
+                NodeID::from(0u64),
+                NodeID::from(4u64),
+                outgoing,
+                ingoing,
+                3,
+                100
+            ),
+            None
+        );
+        assert_eq!(
+            bfs_path(
+                NodeID::from(0u64),
+                NodeID::from(4u64),
+                outgoing,
+                ingoing,
+                10,
+                1
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn links_sorted_by_score() {
+        let graph = test_graph();
+
+        let score = |id: &NodeID| {
+            if *id == Node::from("D").id() {
+                3.0
+            } else if *id == Node::from("B").id() {
+                2.0
+            } else {
+                1.0
+            }
+        };
+
+        let from = |edges: Vec<FullEdge>| edges.into_iter().map(|e| e.from).collect::<Vec<_>>();
+
+        assert_eq!(
+            from(graph.ingoing_links(&Node::from("C"), score, 0, 2)),
+            vec![Node::from("D"), Node::from("B")]
+        );
+        assert_eq!(
+            from(graph.ingoing_links(&Node::from("C"), score, 2, 2)),
+            vec![Node::from("A")]
+        );
+        assert!(graph
+            .ingoing_links(&Node::from("C"), score, 3, 2)
+            .is_empty());
+
+        let to = graph
+            .outgoing_links(&Node::from("A"), |id| -score(id), 0, 10)
+            .into_iter()
+            .map(|e| e.to)
+            .collect::<Vec<_>>();
+
+        assert_eq!(to, vec![Node::from("C"), Node::from("B")]);
+    }
+
     #[test]
     fn merge() {
         let mut graphs = Vec::new();
@@ -1005,6 +1691,94 @@ mod test {
             Some(&7)
         );
     }
+
+    #[test]
+    fn node_chunks() {
+        let graph = test_graph();
+
+        let chunks: Vec<_> = graph.node_chunks(3).collect();
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+            vec![3, 1]
+        );
+        assert_eq!(chunks.concat(), graph.nodes().collect::<Vec<_>>());
+
+        let num_edges: usize = graph
+            .par_map_node_chunks(2, |node| graph.raw_outgoing_edges(&node).len())
+            .flatten()
+            .map(|(_, num_edges)| num_edges)
+            .sum();
+
+        assert_eq!(num_edges, test_edges().len());
+    }
+
+    #[test]
+    fn anchor_texts() {
+        let mut graphs = Vec::new();
+        for label in ["search engine", "search engine", "click here", "stract"] {
+            let mut wrt = WebgraphWriter::new(
+                crate::gen_temp_path(),
+                Executor::single_thread(),
+                Compression::default(),
+            );
+
+            wrt.insert(Node::from("A"), Node::from("B"), label.to_string());
+            graphs.push(wrt.finalize());
+        }
+
+        let mut graph = graphs.pop().unwrap();
+        for other in graphs {
+            graph.merge(other);
+        }
+
+        assert_eq!(
+            graph.anchor_texts(&Node::from("B").id()),
+            vec!["search engine".to_string(), "stract".to_string()]
+        );
+        assert!(graph.anchor_texts(&Node::from("A").id()).is_empty());
+    }
+
+    #[test]
+    fn node_metadata() {
+        let url = Url::parse("https://example.de/page").unwrap();
+        let page = Node::from(&url);
+        let host = page.clone().into_host();
+
+        let mut graphs = Vec::new();
+        for fetch_time in [10, 20] {
+            let mut wrt = WebgraphWriter::new(
+                crate::gen_temp_path(),
+                Executor::single_thread(),
+                Compression::default(),
+            );
+
+            wrt.insert(page.clone(), Node::from("B"), String::new());
+            wrt.insert_metadata(
+                host.clone(),
+                NodeMetadata::page(&url, Some("de".to_string()), fetch_time),
+            );
+
+            graphs.push(wrt.finalize());
+        }
+
+        let mut graph = graphs.pop().unwrap();
+        for other in graphs {
+            graph.merge(other);
+        }
+
+        let metadata = graph.metadata(&host.id()).unwrap();
+
+        assert_eq!(metadata.num_pages, 2);
+        assert_eq!(metadata.first_seen, 10);
+        assert_eq!(metadata.last_seen, 20);
+        assert_eq!(metadata.language(), Some("de"));
+        assert_eq!(
+            metadata.country,
+            Some(crate::webpage::region::Region::Germany)
+        );
+        assert_eq!(graph.metadata(&Node::from("B").id()), None);
+    }
+
     #[test]
     fn merge_cycle() {
         let mut graphs = Vec::new();
@@ -1035,6 +1809,55 @@ mod test {
         );
     }
 
+    #[test]
+    fn compact() {
+        let mut graphs = Vec::new();
+        for _ in 0..3 {
+            let mut wrt = WebgraphWriter::new(
+                crate::gen_temp_path(),
+                Executor::single_thread(),
+                Compression::default(),
+            );
+
+            for (from, to, label) in test_edges() {
+                wrt.insert(from, to, label);
+            }
+
+            wrt.id_or_assign(Node::from("E"));
+
+            graphs.push(wrt.finalize());
+        }
+
+        let mut graph = graphs.pop().unwrap();
+
+        for other in graphs {
+            graph.merge(other);
+        }
+
+        assert_eq!(graph.segments.len(), 3);
+        assert_eq!(graph.edges().count(), 3 * test_edges().len());
+
+        graph.compact();
+
+        assert_eq!(graph.segments.len(), 1);
+        assert_eq!(graph.meta.comitted_segments.len(), 1);
+        assert_eq!(graph.edges().count(), test_edges().len());
+
+        assert_eq!(graph.id2node(&Node::from("E").id()), None);
+        assert_eq!(graph.id2node(&Node::from("D").id()), Some(Node::from("D")));
+
+        let distances = graph.distances(Node::from("D"));
+        assert_eq!(distances.get(&Node::from("C")), Some(&1));
+        assert_eq!(distances.get(&Node::from("A")), Some(&2));
+        assert_eq!(distances.get(&Node::from("B")), Some(&3));
+
+        let path = graph.path.clone();
+        drop(graph);
+
+        let graph = WebgraphBuilder::new(path).single_threaded().open();
+        assert_eq!(graph.edges().count(), test_edges().len());
+    }
+
     #[test]
     fn node_lowercase_name() {
         let n = Node::from("TEST".to_string());
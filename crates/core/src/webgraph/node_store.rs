@@ -0,0 +1,129 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Stores with a value for each node in the webgraph, e.g. the metadata or anchor texts
+//! of the nodes. Values are merged when the same node is inserted multiple times.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::kv::{rocksdb_store::RocksDbStore, Kv};
+
+use super::NodeID;
+
+pub trait Mergeable: Default + Serialize + DeserializeOwned + Send + Sync + 'static {
+    fn merge(&mut self, other: Self);
+}
+
+/// Inserted values are kept in memory and merged with the stored values when
+/// the store is committed.
+pub struct NodeStore<V: Mergeable> {
+    store: RocksDbStore<NodeID, V>,
+    batch: HashMap<NodeID, V>,
+}
+
+impl<V: Mergeable> NodeStore<V> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+            batch: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, id: NodeID, value: V) {
+        self.batch.entry(id).or_default().merge(value);
+    }
+
+    pub fn commit(&mut self) {
+        for (id, value) in self.batch.drain() {
+            let merged = match self.store.get(&id) {
+                Some(mut existing) => {
+                    existing.merge(value);
+                    existing
+                }
+                None => value,
+            };
+
+            self.store.insert(id, merged);
+        }
+
+        self.store.flush();
+    }
+
+    /// Value of the node. Values that haven't been committed yet are not included.
+    pub fn get(&self, id: &NodeID) -> Option<V> {
+        self.store.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, V)> + '_ {
+        self.store.iter()
+    }
+
+    pub fn merge(&mut self, other: &NodeStore<V>) {
+        for (id, value) in other.iter() {
+            self.insert(id, value);
+
+            if self.batch.len() >= super::store::MAX_BATCH_SIZE {
+                self.commit();
+            }
+        }
+
+        self.commit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Count(u64);
+
+    impl Mergeable for Count {
+        fn merge(&mut self, other: Self) {
+            self.0 += other.0;
+        }
+    }
+
+    #[test]
+    fn merges_on_commit() {
+        let id = NodeID::from(1u64);
+
+        let mut store = NodeStore::open(crate::gen_temp_path());
+        store.insert(id, Count(1));
+        store.commit();
+
+        assert_eq!(store.get(&id), Some(Count(1)));
+
+        store.insert(id, Count(2));
+        assert_eq!(store.get(&id), Some(Count(1)));
+        store.commit();
+
+        assert_eq!(store.get(&id), Some(Count(3)));
+
+        let mut other = NodeStore::open(crate::gen_temp_path());
+        other.insert(id, Count(4));
+        other.insert(NodeID::from(2u64), Count(1));
+        other.commit();
+
+        store.merge(&other);
+
+        assert_eq!(store.get(&id), Some(Count(7)));
+        assert_eq!(store.get(&NodeID::from(2u64)), Some(Count(1)));
+    }
+}
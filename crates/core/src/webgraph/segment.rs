@@ -18,7 +18,7 @@ use std::path::{Path, PathBuf};
 
 use super::{
     store::{EdgeStore, EdgeStoreWriter},
-    Compression, Edge, InnerEdge, NodeID,
+    Compression, Edge, EdgeWeight, InnerEdge, NodeID,
 };
 
 const ADJACENCY_STORE: &str = "adjacency";
@@ -116,6 +116,10 @@ impl Segment {
         self.adjacency.get_with_label(node)
     }
 
+    pub fn outgoing_edges_with_weight(&self, node: &NodeID) -> Vec<(Edge<()>, EdgeWeight)> {
+        self.adjacency.get_with_weight(node)
+    }
+
     pub fn outgoing_edges(&self, node: &NodeID) -> Vec<Edge<()>> {
         self.adjacency.get_without_label(node)
     }
@@ -193,21 +197,25 @@ mod test {
             from: a.clone(),
             to: b.clone(),
             label: String::new(),
+            weight: EdgeWeight::default(),
         });
         edges.push(InnerEdge {
             from: b.clone(),
             to: c.clone(),
             label: String::new(),
+            weight: EdgeWeight::default(),
         });
         edges.push(InnerEdge {
             from: c.clone(),
             to: a.clone(),
             label: String::new(),
+            weight: EdgeWeight::default(),
         });
         edges.push(InnerEdge {
             from: a.clone(),
             to: c.clone(),
             label: String::new(),
+            weight: EdgeWeight::default(),
         });
 
         writer.insert(&edges);
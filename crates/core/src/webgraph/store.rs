@@ -14,19 +14,26 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{fs::File, io::Write, ops::Range, path::Path};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::File,
+    io::Write,
+    ops::Range,
+    path::Path,
+};
 
 use itertools::Itertools;
 use memmap2::Mmap;
 use rocksdb::BlockBasedOptions;
 
-use super::{Compression, Edge, EdgeLabel, FullNodeID, InnerEdge, NodeID};
+use super::{Compression, Edge, EdgeLabel, EdgeWeight, FullNodeID, InnerEdge, NodeID};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 struct SerializedEdge {
     from_prefix: NodeID,
     to_prefix: NodeID,
     label: Vec<u8>,
+    weight: EdgeWeight,
 }
 
 pub const MAX_BATCH_SIZE: usize = 100_000;
@@ -78,21 +85,23 @@ impl EdgeStoreWriter {
         }
     }
 
+    /// Links between the same nodes are merged into a single edge with the latest label,
+    /// where the weight is the sum of the weights of all the links.
     pub fn put<'a, L: EdgeLabel + 'a>(&'a self, edges: impl Iterator<Item = &'a InnerEdge<L>>) {
         let mut batch = rocksdb::WriteBatch::default();
 
         let mut opts = rocksdb::WriteOptions::default();
         opts.disable_wal(true);
 
-        for edge in edges {
-            let value_bytes = L::to_bytes(&edge.label).unwrap();
+        let mut merged: HashMap<Vec<u8>, SerializedEdge> = HashMap::new();
 
-            let value_bytes = bincode::serialize(&SerializedEdge {
+        for edge in edges {
+            let value = SerializedEdge {
                 from_prefix: edge.from.prefix,
                 to_prefix: edge.to.prefix,
-                label: value_bytes.clone(),
-            })
-            .unwrap();
+                label: L::to_bytes(&edge.label).unwrap(),
+                weight: edge.weight,
+            };
 
             let key_bytes = if self.reversed {
                 [
@@ -108,7 +117,30 @@ impl EdgeStoreWriter {
                 .concat()
             };
 
-            batch.put(key_bytes, value_bytes);
+            match merged.entry(key_bytes) {
+                Entry::Occupied(mut existing) => {
+                    let existing = existing.get_mut();
+                    existing.weight.merge(value.weight);
+                    existing.label = value.label;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+            }
+        }
+
+        let keys: Vec<_> = merged.keys().cloned().collect();
+        let stored = self.db.multi_get(&keys);
+
+        for (key_bytes, stored) in keys.into_iter().zip_eq(stored) {
+            let mut value = merged.remove(&key_bytes).unwrap();
+
+            if let Some(stored) = stored.unwrap() {
+                let stored: SerializedEdge = bincode::deserialize(&stored).unwrap();
+                value.weight.merge(stored.weight);
+            }
+
+            batch.put(key_bytes, bincode::serialize(&value).unwrap());
 
             if batch.len() >= MAX_BATCH_SIZE {
                 self.db.write_opt(batch, &opts).unwrap();
@@ -151,6 +183,7 @@ impl EdgeStoreWriter {
                         id: NodeID(to),
                     },
                     label: L::from_bytes(&val.label).unwrap(),
+                    weight: val.weight,
                 })
             })
     }
@@ -269,7 +302,7 @@ impl PrefixDb {
 
 pub struct EdgeStore {
     reversed: bool,
-    ranges: rocksdb::DB, // column[nodes] = full_nodeid -> (start, end); column[labels] = nodeid -> (start, end)
+    ranges: rocksdb::DB, // column[nodes] = full_nodeid -> (start, end); column[labels] = nodeid -> (start, end); column[weights] = nodeid -> (start, end)
     prefixes: PrefixDb,
     _cache: rocksdb::Cache,
 
@@ -277,6 +310,10 @@ pub struct EdgeStore {
     edge_labels_len: usize,
     edge_labels: Mmap,
 
+    edge_weights_file: File,
+    edge_weights_len: usize,
+    edge_weights: Mmap,
+
     edge_nodes_file: File,
     edge_nodes_len: usize,
     edge_nodes: Mmap,
@@ -288,6 +325,8 @@ impl EdgeStore {
     pub fn open<P: AsRef<Path>>(path: P, reversed: bool, compression: Compression) -> Self {
         let mut options = rocksdb::Options::default();
         options.create_if_missing(true);
+        // stores written before edges had weights don't have the weights column
+        options.create_missing_column_families(true);
 
         options.set_max_background_jobs(8);
         options.increase_parallelism(8);
@@ -323,7 +362,11 @@ impl EdgeStore {
         let ranges = match rocksdb::DB::open_cf_with_opts(
             &options,
             path.as_ref().join("ranges"),
-            [("nodes", options.clone()), ("labels", options.clone())],
+            [
+                ("nodes", options.clone()),
+                ("labels", options.clone()),
+                ("weights", options.clone()),
+            ],
         ) {
             Ok(db) => db,
             Err(_) => {
@@ -331,6 +374,7 @@ impl EdgeStore {
 
                 ranges.create_cf("nodes", &options).unwrap();
                 ranges.create_cf("labels", &options).unwrap();
+                ranges.create_cf("weights", &options).unwrap();
 
                 ranges
             }
@@ -345,6 +389,15 @@ impl EdgeStore {
         let edge_labels = unsafe { Mmap::map(&edge_labels_file).unwrap() };
         let edge_labels_len = edge_labels.len();
 
+        let edge_weights_file = File::options()
+            .read(true)
+            .create(true)
+            .write(true)
+            .open(path.as_ref().join("weights"))
+            .unwrap();
+        let edge_weights = unsafe { Mmap::map(&edge_weights_file).unwrap() };
+        let edge_weights_len = edge_weights.len();
+
         let edge_nodes_file = File::options()
             .read(true)
             .create(true)
@@ -362,6 +415,9 @@ impl EdgeStore {
             edge_labels,
             edge_labels_len,
             edge_labels_file,
+            edge_weights,
+            edge_weights_len,
+            edge_weights_file,
             edge_nodes,
             edge_nodes_file,
             edge_nodes_len,
@@ -389,15 +445,18 @@ impl EdgeStore {
 
         let node_cf = self.ranges.cf_handle("nodes").unwrap();
         let label_cf = self.ranges.cf_handle("labels").unwrap();
+        let weight_cf = self.ranges.cf_handle("weights").unwrap();
 
         debug_assert!(self.ranges.get_cf(node_cf, node_bytes).unwrap().is_none());
         debug_assert!(self.ranges.get_cf(label_cf, node_bytes).unwrap().is_none());
 
         let mut edge_labels = Vec::new();
+        let mut edge_weights = Vec::new();
         let mut edge_nodes = Vec::new();
 
         for edge in edges {
             edge_labels.push(edge.label.clone());
+            edge_weights.push(edge.weight);
             edge_nodes.push(if self.reversed {
                 edge.from.id
             } else {
@@ -406,18 +465,26 @@ impl EdgeStore {
         }
 
         let edge_labels_bytes = bincode::serialize(&edge_labels).unwrap();
+        let edge_weights_bytes = bincode::serialize(&edge_weights).unwrap();
         let edge_nodes_bytes = bincode::serialize(&edge_nodes).unwrap();
 
         let edge_labels_bytes = self.compression.compress(&edge_labels_bytes);
+        let edge_weights_bytes = self.compression.compress(&edge_weights_bytes);
         let edge_nodes_bytes = self.compression.compress(&edge_nodes_bytes);
 
         let label_range = self.edge_labels_len..(self.edge_labels_len + edge_labels_bytes.len());
+        let weight_range =
+            self.edge_weights_len..(self.edge_weights_len + edge_weights_bytes.len());
         let node_range = self.edge_nodes_len..(self.edge_nodes_len + edge_nodes_bytes.len());
 
         self.edge_labels_len += edge_labels_bytes.len();
+        self.edge_weights_len += edge_weights_bytes.len();
         self.edge_nodes_len += edge_nodes_bytes.len();
 
         self.edge_labels_file.write_all(&edge_labels_bytes).unwrap();
+        self.edge_weights_file
+            .write_all(&edge_weights_bytes)
+            .unwrap();
         self.edge_nodes_file.write_all(&edge_nodes_bytes).unwrap();
 
         let mut opt = rocksdb::WriteOptions::default();
@@ -440,6 +507,15 @@ impl EdgeStore {
                 &opt,
             )
             .unwrap();
+
+        self.ranges
+            .put_cf_opt(
+                weight_cf,
+                node_bytes,
+                bincode::serialize(&weight_range).unwrap(),
+                &opt,
+            )
+            .unwrap();
     }
 
     /// Build a new edge store from a set of edges. The edges must be sorted by
@@ -495,15 +571,21 @@ impl EdgeStore {
         self.ranges
             .flush_cf(self.ranges.cf_handle("labels").unwrap())
             .unwrap();
+        self.ranges
+            .flush_cf(self.ranges.cf_handle("weights").unwrap())
+            .unwrap();
 
         self.edge_nodes_file.flush().unwrap();
         self.edge_labels_file.flush().unwrap();
+        self.edge_weights_file.flush().unwrap();
 
         self.edge_nodes = unsafe { Mmap::map(&self.edge_nodes_file).unwrap() };
         self.edge_labels = unsafe { Mmap::map(&self.edge_labels_file).unwrap() };
+        self.edge_weights = unsafe { Mmap::map(&self.edge_weights_file).unwrap() };
 
         self.edge_nodes_len = self.edge_nodes.len();
         self.edge_labels_len = self.edge_labels.len();
+        self.edge_weights_len = self.edge_weights.len();
     }
 
     pub fn get_with_label(&self, node: &NodeID) -> Vec<Edge<String>> {
@@ -588,6 +670,31 @@ impl EdgeStore {
         }
     }
 
+    pub fn get_with_weight(&self, node: &NodeID) -> Vec<(Edge<()>, EdgeWeight)> {
+        let edges = self.get_without_label(node);
+
+        let weight_cf = self.ranges.cf_handle("weights").unwrap();
+
+        let weights: Vec<EdgeWeight> = match self
+            .ranges
+            .get_cf(weight_cf, node.as_u64().to_le_bytes())
+            .unwrap()
+        {
+            Some(weight_range_bytes) => {
+                let weight_range =
+                    bincode::deserialize::<Range<usize>>(&weight_range_bytes).unwrap();
+
+                let edge_weights = &self.edge_weights[weight_range];
+                let edge_weights = self.compression.decompress(edge_weights);
+                bincode::deserialize(&edge_weights).unwrap()
+            }
+            // the store was built before edges had weights
+            None => vec![EdgeWeight::default(); edges.len()],
+        };
+
+        edges.into_iter().zip_eq(weights).collect()
+    }
+
     pub fn nodes_by_prefix(&self, prefix: &NodeID) -> Vec<NodeID> {
         self.prefixes.get(prefix)
     }
@@ -651,6 +758,7 @@ mod tests {
                 prefix: NodeID(0),
             },
             label: "test".to_string(),
+            weight: EdgeWeight::default(),
         };
 
         kv.put([e.clone()].iter());
@@ -685,6 +793,7 @@ mod tests {
                 prefix: NodeID(0),
             },
             label: "test".to_string(),
+            weight: EdgeWeight::default(),
         };
 
         kv.put([e.clone()].iter());
@@ -0,0 +1,120 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Language variants of a page from its `<link rel="alternate" hreflang="..">` tags.
+//! All variants of a page list each other, so the alternates of any
+//! variant describe the whole language cluster.
+
+use serde::{Deserialize, Serialize};
+
+use super::region::Region;
+
+/// Max number of alternates kept for a page.
+pub const MAX_ALTERNATES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LanguageAlternate {
+    /// Lowercase language tag, e.g. `en`, `en-gb` or `x-default`.
+    pub lang: String,
+    pub url: String,
+}
+
+/// Language and country tags of the region.
+fn region_tags(region: Region) -> Option<(&'static str, &'static str)> {
+    match region {
+        Region::All => None,
+        Region::Denmark => Some(("da", "dk")),
+        Region::France => Some(("fr", "fr")),
+        Region::Germany => Some(("de", "de")),
+        Region::Spain => Some(("es", "es")),
+        Region::US => Some(("en", "us")),
+    }
+}
+
+/// The variant that best matches the region. A variant for both the
+/// language and country of the region is preferred over one that only
+/// matches the language.
+pub fn best_alternate(
+    alternates: &[LanguageAlternate],
+    region: Region,
+) -> Option<&LanguageAlternate> {
+    let (lang, country) = region_tags(region)?;
+
+    alternates
+        .iter()
+        .filter_map(|alternate| {
+            let mut parts = alternate.lang.split('-');
+
+            if parts.next()? != lang {
+                return None;
+            }
+
+            let rank = match parts.last() {
+                Some(c) if c == country => 0,
+                None => 1,
+                Some(_) => 2,
+            };
+
+            Some((rank, alternate))
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, alternate)| alternate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alternates(langs: &[&str]) -> Vec<LanguageAlternate> {
+        langs
+            .iter()
+            .map(|lang| LanguageAlternate {
+                lang: lang.to_string(),
+                url: format!("https://example.com/{lang}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prefers_country_match() {
+        let alternates = alternates(&["x-default", "en-gb", "en", "en-us", "de"]);
+
+        assert_eq!(
+            best_alternate(&alternates, Region::US).map(|a| a.lang.as_str()),
+            Some("en-us")
+        );
+        assert_eq!(
+            best_alternate(&alternates, Region::Germany).map(|a| a.lang.as_str()),
+            Some("de")
+        );
+        assert_eq!(best_alternate(&alternates, Region::Denmark), None);
+        assert_eq!(best_alternate(&alternates, Region::All), None);
+    }
+
+    #[test]
+    fn falls_back_to_other_countries() {
+        let alternates = alternates(&["es-mx", "fr-ca", "fr-be"]);
+
+        assert_eq!(
+            best_alternate(&alternates, Region::Spain).map(|a| a.lang.as_str()),
+            Some("es-mx")
+        );
+        assert_eq!(
+            best_alternate(&alternates, Region::France).map(|a| a.lang.as_str()),
+            Some("fr-ca")
+        );
+    }
+}
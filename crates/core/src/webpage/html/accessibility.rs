@@ -0,0 +1,311 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Basic accessibility indicators that can be found in the html alone.
+//! We look at how many images have alt-text, whether the headings form a sensible
+//! outline and whether inline styles use colors with enough contrast.
+//! This is by no means a replacement for a real accessibility audit.
+
+use super::Html;
+
+/// Minimum contrast ratio for normal text according to WCAG AA.
+const MIN_CONTRAST_RATIO: f64 = 4.5;
+
+/// Pages with a score of at least this are considered accessible
+/// when users filter for accessible results.
+pub const ACCESSIBLE_THRESHOLD: f64 = 0.75;
+
+const NAMED_COLORS: [(&str, (u8, u8, u8)); 12] = [
+    ("black", (0, 0, 0)),
+    ("white", (255, 255, 255)),
+    ("gray", (128, 128, 128)),
+    ("grey", (128, 128, 128)),
+    ("silver", (192, 192, 192)),
+    ("red", (255, 0, 0)),
+    ("green", (0, 128, 0)),
+    ("blue", (0, 0, 255)),
+    ("yellow", (255, 255, 0)),
+    ("orange", (255, 165, 0)),
+    ("navy", (0, 0, 128)),
+    ("lightgray", (211, 211, 211)),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Accessibility {
+    /// Fraction of images with an alt attribute.
+    pub alt_text_coverage: f64,
+    /// How well the headings form an outline (a single h1 and no skipped levels).
+    pub heading_structure: f64,
+    /// Fraction of inline styled elements where the text has enough contrast
+    /// against the background.
+    pub contrast: f64,
+}
+
+impl Accessibility {
+    pub fn score(&self) -> f64 {
+        (self.alt_text_coverage + self.heading_structure + self.contrast) / 3.0
+    }
+
+    pub fn is_accessible(&self) -> bool {
+        self.score() >= ACCESSIBLE_THRESHOLD
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+fn parse_color(color: &str) -> Option<Rgb> {
+    let color = color
+        .trim()
+        .trim_end_matches("!important")
+        .trim()
+        .to_ascii_lowercase();
+
+    if let Some(hex) = color.strip_prefix('#') {
+        let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+
+        return match hex.len() {
+            3 => {
+                let mut chars = hex.chars().map(|c| c.to_string().repeat(2));
+                Some((
+                    channel(&chars.next()?)?,
+                    channel(&chars.next()?)?,
+                    channel(&chars.next()?)?,
+                ))
+            }
+            6 => Some((
+                channel(hex.get(0..2)?)?,
+                channel(hex.get(2..4)?)?,
+                channel(hex.get(4..6)?)?,
+            )),
+            _ => None,
+        };
+    }
+
+    if let Some(args) = color
+        .strip_prefix("rgb(")
+        .or_else(|| color.strip_prefix("rgba("))
+    {
+        let mut channels = args
+            .trim_end_matches(')')
+            .split(',')
+            .map(|c| c.trim().parse::<u8>().ok());
+
+        return Some((channels.next()??, channels.next()??, channels.next()??));
+    }
+
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == color)
+        .map(|(_, rgb)| *rgb)
+}
+
+/// Relative luminance as defined by WCAG.
+fn luminance((r, g, b): Rgb) -> f64 {
+    let channel = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    0.2126 * channel(r) + 0.7152 * channel(g) + 0.0722 * channel(b)
+}
+
+fn contrast_ratio(a: Rgb, b: Rgb) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    let (light, dark) = if a > b { (a, b) } else { (b, a) };
+
+    (light + 0.05) / (dark + 0.05)
+}
+
+/// Foreground and background colors from an inline style attribute.
+fn inline_colors(style: &str) -> (Option<Rgb>, Option<Rgb>) {
+    let mut foreground = None;
+    let mut background = None;
+
+    for declaration in style.split(';') {
+        if let Some((property, value)) = declaration.split_once(':') {
+            match property.trim().to_ascii_lowercase().as_str() {
+                "color" => foreground = parse_color(value),
+                "background-color" | "background" => background = parse_color(value),
+                _ => {}
+            }
+        }
+    }
+
+    (foreground, background)
+}
+
+fn heading_structure(levels: &[usize]) -> f64 {
+    if levels.is_empty() {
+        return 0.5;
+    }
+
+    let mut score: f64 = 1.0;
+
+    match levels.iter().filter(|level| **level == 1).count() {
+        0 => score -= 0.5,
+        1 => {}
+        _ => score -= 0.25,
+    }
+
+    let skipped_levels = levels
+        .windows(2)
+        .filter(|window| window[1] > window[0] + 1)
+        .count();
+
+    score -= 0.25 * skipped_levels as f64;
+
+    score.max(0.0)
+}
+
+impl Html {
+    pub fn accessibility(&self) -> Accessibility {
+        let mut num_images = 0;
+        let mut num_with_alt = 0;
+
+        for img in self.root.select("img").unwrap() {
+            num_images += 1;
+
+            // an empty alt marks a decorative image which is fine
+            if img.attributes.borrow().get("alt").is_some() {
+                num_with_alt += 1;
+            }
+        }
+
+        let alt_text_coverage = if num_images == 0 {
+            1.0
+        } else {
+            num_with_alt as f64 / num_images as f64
+        };
+
+        let levels: Vec<usize> = self
+            .root
+            .select("h1, h2, h3, h4, h5, h6")
+            .unwrap()
+            .filter_map(|heading| heading.name.local[1..].parse().ok())
+            .collect();
+
+        let mut num_styled = 0;
+        let mut num_good_contrast = 0;
+
+        for node in self.root.select("[style]").unwrap() {
+            let attributes = node.attributes.borrow();
+
+            if let (Some(foreground), Some(background)) =
+                inline_colors(attributes.get("style").unwrap_or_default())
+            {
+                num_styled += 1;
+
+                if contrast_ratio(foreground, background) >= MIN_CONTRAST_RATIO {
+                    num_good_contrast += 1;
+                }
+            }
+        }
+
+        let contrast = if num_styled == 0 {
+            1.0
+        } else {
+            num_good_contrast as f64 / num_styled as f64
+        };
+
+        Accessibility {
+            alt_text_coverage,
+            heading_structure: heading_structure(&levels),
+            contrast,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#fff"), Some((255, 255, 255)));
+        assert_eq!(parse_color(" #1A2b3C !important"), Some((26, 43, 60)));
+        assert_eq!(parse_color("rgb(10, 20, 30)"), Some((10, 20, 30)));
+        assert_eq!(parse_color("Black"), Some((0, 0, 0)));
+        assert_eq!(parse_color("url(bg.png)"), None);
+
+        assert!((contrast_ratio((0, 0, 0), (255, 255, 255)) - 21.0).abs() < 0.01);
+        assert!(contrast_ratio((200, 200, 200), (255, 255, 255)) < MIN_CONTRAST_RATIO);
+    }
+
+    #[test]
+    fn headings() {
+        assert_eq!(heading_structure(&[1, 2, 3, 2]), 1.0);
+        assert_eq!(heading_structure(&[2, 3]), 0.5);
+        assert_eq!(heading_structure(&[1, 3, 1]), 0.5);
+    }
+
+    #[test]
+    fn accessible_page() {
+        let html = Html::parse(
+            r#"
+    <html>
+        <head>
+            <title>Apply for a permit</title>
+        </head>
+        <body>
+            <h1>Apply for a permit</h1>
+            <img src="logo.png" alt="City logo">
+            <img src="divider.png" alt="">
+            <h2>Who can apply</h2>
+            <p style="color: #000; background-color: #fff">Everyone</p>
+        </body>
+    </html>
+            "#,
+            "https://example.gov",
+        )
+        .unwrap();
+
+        let accessibility = html.accessibility();
+        assert_eq!(accessibility.alt_text_coverage, 1.0);
+        assert_eq!(accessibility.heading_structure, 1.0);
+        assert_eq!(accessibility.contrast, 1.0);
+        assert!(accessibility.is_accessible());
+    }
+
+    #[test]
+    fn inaccessible_page() {
+        let html = Html::parse(
+            r#"
+    <html>
+        <head>
+            <title>Welcome</title>
+        </head>
+        <body>
+            <h3>Welcome</h3>
+            <img src="banner.png">
+            <img src="menu.png">
+            <p style="color: #ccc; background: white">Read more</p>
+        </body>
+    </html>
+            "#,
+            "https://example.com",
+        )
+        .unwrap();
+
+        let accessibility = html.accessibility();
+        assert_eq!(accessibility.alt_text_coverage, 0.0);
+        assert_eq!(accessibility.contrast, 0.0);
+        assert!(!accessibility.is_accessible());
+    }
+}
@@ -0,0 +1,189 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Author names of articles from meta tags and schema.org markup.
+
+use super::{schema_org, Html};
+
+const AUTHOR_META: [&str; 6] = [
+    "author",
+    "article:author",
+    "dc.creator",
+    "dcterms.creator",
+    "parsely-author",
+    "sailthru.author",
+];
+
+const AUTHOR_SCHEMA_ORG: [&str; 2] = ["author", "creator"];
+
+/// Names longer than this are most likely not names but descriptions.
+const MAX_NAME_LEN: usize = 64;
+
+/// Some pages list the entire editorial staff as authors.
+const MAX_AUTHORS: usize = 8;
+
+/// Split a field like "By Jane Doe and John Smith" into the individual names.
+fn split_names(names: &str) -> impl Iterator<Item = String> + '_ {
+    names
+        .split([',', ';', '&', '|'])
+        .flat_map(|name| name.split(" and "))
+        .map(|name| {
+            let name = name.trim();
+            name.strip_prefix("By ")
+                .or_else(|| name.strip_prefix("by "))
+                .unwrap_or(name)
+                .trim()
+                .to_string()
+        })
+        .filter(|name| is_plausible_name(name))
+}
+
+fn is_plausible_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.contains("://")
+        && !name.starts_with('@')
+        && name.chars().any(|c| c.is_alphabetic())
+}
+
+fn schema_org_names(prop: &schema_org::Property) -> Vec<String> {
+    match prop {
+        schema_org::Property::String(name) => split_names(name).collect(),
+        schema_org::Property::Item(item) => item
+            .properties
+            .get("name")
+            .cloned()
+            .into_iter()
+            .flat_map(|name| name.many())
+            .filter_map(|name| name.try_into_string())
+            .flat_map(|name| split_names(&name).collect::<Vec<_>>())
+            .collect(),
+    }
+}
+
+impl Html {
+    fn meta_authors(&self) -> Vec<String> {
+        self.metadata()
+            .into_iter()
+            .filter(|meta| {
+                ["name", "property"]
+                    .iter()
+                    .filter_map(|attr| meta.get(*attr))
+                    .any(|name| AUTHOR_META.contains(&name.to_ascii_lowercase().as_str()))
+            })
+            .filter_map(|meta| meta.get("content").cloned())
+            .flat_map(|content| split_names(&content).collect::<Vec<_>>())
+            .collect()
+    }
+
+    fn schema_org_authors(&self) -> Vec<String> {
+        self.schema_org()
+            .into_iter()
+            .flat_map(|item| {
+                AUTHOR_SCHEMA_ORG
+                    .iter()
+                    .filter_map(|key| item.properties.get(*key).cloned())
+                    .flat_map(|prop| prop.many())
+                    .flat_map(|prop| schema_org_names(&prop))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The authors of the page. Structured data is preferred over meta tags
+    /// and names are deduplicated case-insensitively.
+    pub fn authors(&self) -> Vec<String> {
+        let mut res: Vec<String> = Vec::new();
+
+        for name in self
+            .schema_org_authors()
+            .into_iter()
+            .chain(self.meta_authors())
+        {
+            if res.len() >= MAX_AUTHORS {
+                break;
+            }
+
+            if !res.iter().any(|n| n.to_lowercase() == name.to_lowercase()) {
+                res.push(name);
+            }
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_tags() {
+        let html = r#"
+    <html>
+        <head>
+            <meta name="author" content="By Jane Doe and John Smith" />
+            <meta property="article:author" content="https://example.com/authors/jane-doe" />
+            <meta name="dc.creator" content="jane doe" />
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com").unwrap();
+
+        assert_eq!(
+            html.authors(),
+            vec!["Jane Doe".to_string(), "John Smith".to_string()]
+        );
+    }
+
+    #[test]
+    fn structured_data() {
+        let html = r#"
+    <html>
+        <head>
+            <script type="application/ld+json">
+            {
+                "@context": "https://schema.org",
+                "@type": "NewsArticle",
+                "author": [
+                    {"@type": "Person", "name": "David E. Sanger"},
+                    {"@type": "Person", "name": "Eric Schmitt"}
+                ]
+            }
+            </script>
+            <meta name="author" content="Eric Schmitt" />
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com").unwrap();
+
+        assert_eq!(
+            html.authors(),
+            vec!["David E. Sanger".to_string(), "Eric Schmitt".to_string()]
+        );
+    }
+
+    #[test]
+    fn no_authors() {
+        let html = Html::parse("<html></html>", "https://example.com").unwrap();
+
+        assert!(html.authors().is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of code-heavy pages (Q&A sites, documentation, source hosting etc.).
+//! We find the dominant programming language of the code blocks on the page
+//! and extract a short code snippet that can be shown in the search results.
+
+use std::collections::HashMap;
+
+use crate::webpage::url_ext::UrlExt;
+
+use super::Html;
+
+/// Pages where at least this fraction of the text is inside code blocks are code-heavy.
+const CODE_HEAVY_RATIO: f64 = 0.1;
+
+const MAX_SNIPPET_LINES: usize = 8;
+const MAX_SNIPPET_CHARS: usize = 400;
+
+/// Sites that are all about code. Any code block on these pages is enough
+/// to consider the page code-heavy.
+const CODE_SITES: [&str; 8] = [
+    "stackoverflow.com",
+    "stackexchange.com",
+    "github.com",
+    "gitlab.com",
+    "docs.rs",
+    "readthedocs.io",
+    "pkg.go.dev",
+    "developer.mozilla.org",
+];
+
+/// Name of the language followed by the aliases used in class names
+/// by syntax highlighters (`language-rs`, `lang-py`, `highlight-source-go` etc.).
+const LANGUAGES: [(&str, &[&str]); 20] = [
+    ("Rust", &["rust", "rs"]),
+    ("Python", &["python", "py", "python3"]),
+    ("JavaScript", &["javascript", "js", "jsx", "node"]),
+    ("TypeScript", &["typescript", "ts", "tsx"]),
+    ("Java", &["java"]),
+    ("C", &["c"]),
+    ("C++", &["cpp", "c++", "cxx"]),
+    ("C#", &["csharp", "cs", "c#"]),
+    ("Go", &["go", "golang"]),
+    ("Ruby", &["ruby", "rb"]),
+    ("PHP", &["php"]),
+    ("Shell", &["bash", "sh", "shell", "zsh", "console"]),
+    ("SQL", &["sql", "mysql", "postgresql", "sqlite"]),
+    ("HTML", &["html", "xml", "xhtml"]),
+    ("CSS", &["css", "scss", "sass"]),
+    ("Kotlin", &["kotlin", "kt"]),
+    ("Swift", &["swift"]),
+    ("Haskell", &["haskell", "hs"]),
+    ("Scala", &["scala"]),
+    ("Elixir", &["elixir", "ex"]),
+];
+
+/// Keywords that are (mostly) unique to a language. Used when the
+/// code blocks have no language hint in their class names.
+const KEYWORDS: [(&str, &[&str]); 11] = [
+    (
+        "Rust",
+        &[
+            "fn ",
+            "let mut ",
+            "impl ",
+            "pub fn",
+            "-> Result<",
+            "println!(",
+        ],
+    ),
+    (
+        "Python",
+        &["def ", "import ", "elif ", "self.", "print(", "None:"],
+    ),
+    (
+        "JavaScript",
+        &["function ", "const ", "console.log(", "=> {", "require("],
+    ),
+    (
+        "Java",
+        &[
+            "public class ",
+            "public static void",
+            "System.out.",
+            "private final ",
+        ],
+    ),
+    (
+        "C++",
+        &["#include <iostream>", "std::", "cout <<", "template <"],
+    ),
+    (
+        "C",
+        &["#include <stdio.h>", "printf(", "malloc(", "int main("],
+    ),
+    ("Go", &["func ", "package main", ":= ", "fmt."]),
+    ("PHP", &["<?php", "$this->", "echo "]),
+    ("Shell", &["sudo ", "$ ", "apt-get ", "export "]),
+    ("SQL", &["SELECT ", " FROM ", "WHERE ", "INSERT INTO "]),
+    ("Ruby", &["end\n", "puts ", "require '", "do |"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub language: Option<&'static str>,
+    pub text: String,
+}
+
+fn language_from_alias(alias: &str) -> Option<&'static str> {
+    let alias = alias.to_ascii_lowercase();
+
+    LANGUAGES
+        .iter()
+        .find(|(_, aliases)| aliases.contains(&alias.as_str()))
+        .map(|(name, _)| *name)
+}
+
+fn language_from_class(class: &str) -> Option<&'static str> {
+    class.split_whitespace().find_map(|class| {
+        ["language-", "lang-", "highlight-source-", "highlight-"]
+            .iter()
+            .find_map(|prefix| class.strip_prefix(prefix))
+            .and_then(language_from_alias)
+    })
+}
+
+fn language_from_keywords(code: &str) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .map(|(name, keywords)| {
+            let hits = keywords.iter().filter(|kw| code.contains(*kw)).count();
+            (*name, hits)
+        })
+        .filter(|(_, hits)| *hits >= 2)
+        .max_by_key(|(_, hits)| *hits)
+        .map(|(name, _)| name)
+}
+
+/// Shorten the code to something that fits in a search result.
+fn truncate_code(code: &str) -> String {
+    let mut res = String::new();
+
+    for line in code.trim_matches('\n').lines().take(MAX_SNIPPET_LINES) {
+        if res.len() + line.len() > MAX_SNIPPET_CHARS {
+            break;
+        }
+
+        res.push_str(line.trim_end());
+        res.push('\n');
+    }
+
+    res.trim_end().to_string()
+}
+
+impl Html {
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        let mut res = Vec::new();
+
+        for pre in self.root.select("pre").unwrap() {
+            let text = pre.text_contents();
+
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let node = pre.as_node();
+            let mut classes = Vec::new();
+
+            if let Some(class) = pre.attributes.borrow().get("class") {
+                classes.push(class.to_string());
+            }
+
+            // highlighters put the hint on the <code> element inside the <pre>
+            // or on a wrapping <div>.
+            if let Some(code) = node.select_first("code") {
+                if let Some(class) = code.attributes.borrow().get("class") {
+                    classes.push(class.to_string());
+                }
+            }
+
+            if let Some(parent) = node.parent().as_ref().and_then(|p| p.as_element()) {
+                if let Some(class) = parent.attributes.borrow().get("class") {
+                    classes.push(class.to_string());
+                }
+            }
+
+            let language = classes
+                .iter()
+                .find_map(|class| language_from_class(class))
+                .or_else(|| language_from_keywords(&text));
+
+            res.push(CodeBlock { language, text });
+        }
+
+        res
+    }
+
+    pub fn is_code_heavy(&self) -> bool {
+        self.has_code_heavy_blocks(&self.code_blocks())
+    }
+
+    fn has_code_heavy_blocks(&self, blocks: &[CodeBlock]) -> bool {
+        if blocks.is_empty() {
+            return false;
+        }
+
+        let domain = self.url().root_domain().unwrap_or_default();
+        if CODE_SITES.contains(&domain) {
+            return true;
+        }
+
+        let code_len: usize = blocks.iter().map(|b| b.text.len()).sum();
+        let text_len = self
+            .all_text
+            .as_ref()
+            .map(|text| text.len())
+            .unwrap_or_default()
+            .max(code_len);
+
+        code_len as f64 / text_len as f64 >= CODE_HEAVY_RATIO
+    }
+
+    /// The language with the most code on the page along with
+    /// a snippet of the first code block in that language.
+    /// Returns `None` if the page is not code-heavy.
+    pub fn code_summary(&self) -> Option<(&'static str, String)> {
+        let blocks = self.code_blocks();
+
+        if !self.has_code_heavy_blocks(&blocks) {
+            return None;
+        }
+
+        let mut amount: HashMap<&'static str, usize> = HashMap::new();
+        for block in &blocks {
+            if let Some(lang) = block.language {
+                *amount.entry(lang).or_default() += block.text.len();
+            }
+        }
+
+        let (language, _) = amount
+            .into_iter()
+            .max_by(|(a_lang, a), (b_lang, b)| a.cmp(b).then(b_lang.cmp(a_lang)))?;
+
+        let snippet = blocks
+            .iter()
+            .find(|block| block.language == Some(language))
+            .map(|block| truncate_code(&block.text))
+            .unwrap_or_default();
+
+        Some((language, snippet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class_hints() {
+        assert_eq!(language_from_class("hljs language-rust"), Some("Rust"));
+        assert_eq!(language_from_class("lang-py s-code-block"), Some("Python"));
+        assert_eq!(language_from_class("highlight-source-go"), Some("Go"));
+        assert_eq!(language_from_class("language-unknown"), None);
+        assert_eq!(language_from_class("highlight"), None);
+    }
+
+    #[test]
+    fn keyword_fallback() {
+        assert_eq!(
+            language_from_keywords("def foo(self):\n    print(self.x)"),
+            Some("Python")
+        );
+        assert_eq!(language_from_keywords("hello world"), None);
+    }
+
+    #[test]
+    fn stackoverflow_page() {
+        let html = r#"
+    <html>
+        <head>
+            <title>How do I exit a loop?</title>
+        </head>
+        <body>
+            <p>You can use break</p>
+            <pre class="lang-rust s-code-block"><code>fn main() {
+    loop {
+        break;
+    }
+}</code></pre>
+            <pre><code>cargo run</code></pre>
+        </body>
+    </html>
+        "#;
+
+        let html = Html::parse(html, "https://stackoverflow.com/questions/1").unwrap();
+
+        assert!(html.is_code_heavy());
+
+        let (language, snippet) = html.code_summary().unwrap();
+        assert_eq!(language, "Rust");
+        assert!(snippet.starts_with("fn main() {"));
+        assert!(snippet.ends_with('}'));
+    }
+
+    #[test]
+    fn prose_page() {
+        let html = format!(
+            r#"
+    <html>
+        <head>
+            <title>A blog post</title>
+        </head>
+        <body>
+            <p>{}</p>
+            <pre>x</pre>
+        </body>
+    </html>
+        "#,
+            "Lorem ipsum dolor sit amet. ".repeat(20)
+        );
+
+        let html = Html::parse(&html, "https://example.com").unwrap();
+
+        assert!(!html.is_code_heavy());
+        assert_eq!(html.code_summary(), None);
+    }
+
+    #[test]
+    fn truncates_long_code() {
+        let code = (0..20)
+            .map(|i| format!("let x{i} = {i};"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert_eq!(truncate_code(&code).lines().count(), MAX_SNIPPET_LINES);
+    }
+}
@@ -0,0 +1,132 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Text of the h1, h2 and h3 headings of a page. Headings in navigation,
+//! footers and other boilerplate are left out as they are the same on every
+//! page of the site.
+
+use super::{main_content::is_boilerplate, Html};
+
+/// Longer headings are most likely misused markup around regular text.
+const MAX_HEADING_LEN: usize = 256;
+
+/// Max number of headings kept for each level.
+const MAX_HEADINGS: usize = 32;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Headings {
+    pub h1: Vec<String>,
+    pub h2: Vec<String>,
+    pub h3: Vec<String>,
+}
+
+impl Html {
+    pub fn headings(&self) -> Headings {
+        let mut headings = Headings::default();
+
+        for heading in self.root.select("h1, h2, h3").unwrap() {
+            let node = heading.as_node();
+
+            if node.inclusive_ancestors().any(|node| is_boilerplate(&node)) {
+                continue;
+            }
+
+            let text = heading
+                .text_contents()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            if text.is_empty() || text.len() > MAX_HEADING_LEN {
+                continue;
+            }
+
+            let level = match &*heading.name.local {
+                "h1" => &mut headings.h1,
+                "h2" => &mut headings.h2,
+                _ => &mut headings.h3,
+            };
+
+            if level.len() < MAX_HEADINGS {
+                level.push(text);
+            }
+        }
+
+        headings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <body>
+                    <nav><h2>Menu</h2></nav>
+                    <article>
+                        <h1>Baking   bread</h1>
+                        <h2>Ingredients</h2>
+                        <h3>For the <em>dough</em></h3>
+                        <h2>Method</h2>
+                        <h4>Tips</h4>
+                        <h3></h3>
+                    </article>
+                    <footer><h3>Contact</h3></footer>
+                </body>
+            </html>
+            "#,
+            "https://example.com",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.headings(),
+            Headings {
+                h1: vec!["Baking bread".to_string()],
+                h2: vec!["Ingredients".to_string(), "Method".to_string()],
+                h3: vec!["For the dough".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn entry_header() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <body>
+                    <header class="site-header"><h2>My blog</h2></header>
+                    <div class="post">
+                        <header class="entry-header">
+                            <h1 class="entry-title">Baking bread</h1>
+                        </header>
+                        <div class="entry-content"><p>Start with the dough.</p></div>
+                    </div>
+                </body>
+            </html>
+            "#,
+            "https://example.com",
+        )
+        .unwrap();
+
+        assert_eq!(html.headings().h1, vec!["Baking bread".to_string()]);
+        assert!(html.headings().h2.is_empty());
+    }
+}
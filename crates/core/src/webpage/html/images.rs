@@ -0,0 +1,188 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Images in the content of a page together with their alt text and caption.
+
+use itertools::Itertools;
+use url::Url;
+
+use crate::floor_char_boundary;
+
+use super::{main_content::is_boilerplate, Html};
+
+/// Max number of images extracted from a page.
+const MAX_IMAGES: usize = 64;
+
+/// Alt texts and captions longer than this are cut at a word boundary.
+const MAX_TEXT_LEN: usize = 512;
+
+/// Images this small or smaller are tracking pixels or spacers.
+const MAX_PIXEL_SIZE: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentImage {
+    pub url: Url,
+    pub alt: Option<String>,
+    /// The `<figcaption>` of the figure the image is part of.
+    pub caption: Option<String>,
+}
+
+fn clean_text(text: &str) -> Option<String> {
+    let mut text = text.split_whitespace().join(" ");
+
+    if text.len() > MAX_TEXT_LEN {
+        let end = text[..floor_char_boundary(&text, MAX_TEXT_LEN)]
+            .rfind(' ')
+            .unwrap_or(0);
+        text.truncate(end);
+    }
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Alt texts that only repeat the file name of the image say nothing about it.
+fn is_file_name(alt: &str, url: &Url) -> bool {
+    let file_name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back());
+
+    match file_name {
+        Some(file_name) => !file_name.is_empty() && alt.eq_ignore_ascii_case(file_name),
+        None => false,
+    }
+}
+
+impl Html {
+    pub fn images(&self) -> Vec<ContentImage> {
+        self.root
+            .select("img")
+            .unwrap()
+            .filter(|img| {
+                !img.as_node()
+                    .inclusive_ancestors()
+                    .any(|node| is_boilerplate(&node))
+            })
+            .filter_map(|img| {
+                let attributes = img.attributes.borrow();
+
+                let is_pixel = ["width", "height"].into_iter().any(|dimension| {
+                    attributes
+                        .get(dimension)
+                        .and_then(|size| size.parse::<u32>().ok())
+                        .map(|size| size <= MAX_PIXEL_SIZE)
+                        .unwrap_or(false)
+                });
+
+                if is_pixel {
+                    return None;
+                }
+
+                // lazy loaded images often keep the real url in a data attribute
+                let src = attributes
+                    .get("data-src")
+                    .or_else(|| attributes.get("src"))?;
+
+                let url = Url::parse(src).or_else(|_| self.url().join(src)).ok()?;
+
+                if !matches!(url.scheme(), "http" | "https") {
+                    return None;
+                }
+
+                let alt = attributes
+                    .get("alt")
+                    .and_then(clean_text)
+                    .filter(|alt| !is_file_name(alt, &url));
+
+                let caption = img
+                    .as_node()
+                    .ancestors()
+                    .find(|node| {
+                        node.as_element()
+                            .map(|element| &*element.name.local == "figure")
+                            .unwrap_or(false)
+                    })
+                    .and_then(|figure| figure.select_first("figcaption"))
+                    .and_then(|caption| clean_text(&caption.text_contents()));
+
+                Some(ContentImage { url, alt, caption })
+            })
+            .unique_by(|image| image.url.clone())
+            .take(MAX_IMAGES)
+            .collect()
+    }
+
+    /// Alt texts and captions of the images, without duplicates.
+    pub fn image_text(&self) -> String {
+        self.images()
+            .into_iter()
+            .flat_map(|image| [image.alt, image.caption])
+            .flatten()
+            .unique()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_and_caption() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <body>
+                    <nav><img src="/logo.png" alt="Example logo"></nav>
+                    <figure>
+                        <img src="/images/heron.jpg" alt="A grey   heron standing in a lake">
+                        <figcaption>Herons wait <b>motionless</b> for fish.</figcaption>
+                    </figure>
+                    <img data-src="https://cdn.example.com/otter.webp" src="data:image/gif;base64,R0lG" alt="otter.webp">
+                    <img src="/pixel.gif" width="1" height="1" alt="tracking">
+                    <img src="/images/heron.jpg" alt="A grey heron standing in a lake">
+                </body>
+            </html>
+            "#,
+            "https://example.com/birds",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html.images(),
+            vec![
+                ContentImage {
+                    url: Url::parse("https://example.com/images/heron.jpg").unwrap(),
+                    alt: Some("A grey heron standing in a lake".to_string()),
+                    caption: Some("Herons wait motionless for fish.".to_string()),
+                },
+                ContentImage {
+                    url: Url::parse("https://cdn.example.com/otter.webp").unwrap(),
+                    alt: None,
+                    caption: None,
+                },
+            ]
+        );
+
+        assert_eq!(
+            html.image_text(),
+            "A grey heron standing in a lake\nHerons wait motionless for fish."
+        );
+    }
+}
@@ -19,7 +19,7 @@ use crate::{
     prehashed::hash,
     schema::{FastField, TextField},
     simhash, split_u128, tokenizer,
-    webpage::url_ext::UrlExt,
+    webpage::{language, url_ext::UrlExt},
     Error, Result,
 };
 use tantivy::{
@@ -127,6 +127,13 @@ impl Html {
         let site = self.pretokenize_site();
         let description = self.pretokenize_description();
         let microformats = self.pretokenize_microformats();
+        let authors = self.authors();
+        let headings = self.headings();
+        let h1 = self.pretokenize_string(headings.h1.join("\n"));
+        let h2 = self.pretokenize_string(headings.h2.join("\n"));
+        let h3 = self.pretokenize_string(headings.h3.join("\n"));
+        let image_text = self.pretokenize_string(self.image_text());
+        let timestamps = self.timestamps();
         let url_for_site_operator = self.pretokenize_string_with(
             self.url().to_string(),
             tokenizer::Tokenizer::SiteOperator(tokenizer::SiteOperatorUrlTokenizer),
@@ -149,6 +156,27 @@ impl Html {
 
         let schema_json = serde_json::to_string(&schemas).ok().unwrap_or_default();
 
+        let language_alternates = self.language_alternates();
+        let language_alternates = if language_alternates.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&language_alternates)
+                .ok()
+                .unwrap_or_default()
+        };
+
+        let open_graph = self.open_graph();
+        let open_graph = if open_graph.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&open_graph).ok().unwrap_or_default()
+        };
+
+        let (code_language, code_snippet) = self
+            .code_summary()
+            .map(|(language, snippet)| (language.to_string(), snippet))
+            .unwrap_or_default();
+
         let pretokenized_schema_json = match schema_org::flattened_json(schemas) {
             Ok(mut f) => {
                 let mut tokens = Vec::new();
@@ -392,9 +420,51 @@ impl Html {
                 Field::Text(TextField::RecipeFirstIngredientTagId) => {
                     doc.add_text(tantivy_field, first_ingredient_tag_id.clone());
                 }
+                Field::Text(TextField::CodeLanguage) => {
+                    doc.add_text(tantivy_field, code_language.clone());
+                }
+                Field::Text(TextField::CodeSnippet) => {
+                    doc.add_text(tantivy_field, code_snippet.clone());
+                }
+                Field::Text(TextField::LanguageAlternates) => {
+                    doc.add_text(tantivy_field, language_alternates.clone());
+                }
+                Field::Text(TextField::Language) => {
+                    doc.add_text(
+                        tantivy_field,
+                        self.lang.map(|lang| lang.code()).unwrap_or_default(),
+                    );
+                }
+                Field::Text(TextField::OpenGraph) => {
+                    doc.add_text(tantivy_field, open_graph.clone());
+                }
+                Field::Text(TextField::Author) => {
+                    if authors.is_empty() {
+                        doc.add_text(tantivy_field, "");
+                    }
+
+                    for author in &authors {
+                        doc.add_pre_tokenized_text(
+                            tantivy_field,
+                            self.pretokenize_string(author.clone()),
+                        );
+                    }
+                }
                 Field::Text(TextField::SchemaOrgJson) => {
                     doc.add_text(tantivy_field, schema_json.clone());
                 }
+                Field::Text(TextField::H1) => {
+                    doc.add_pre_tokenized_text(tantivy_field, h1.clone());
+                }
+                Field::Text(TextField::H2) => {
+                    doc.add_pre_tokenized_text(tantivy_field, h2.clone());
+                }
+                Field::Text(TextField::H3) => {
+                    doc.add_pre_tokenized_text(tantivy_field, h3.clone());
+                }
+                Field::Text(TextField::ImageText) => {
+                    doc.add_pre_tokenized_text(tantivy_field, image_text.clone());
+                }
                 Field::Text(TextField::FlattenedSchemaOrgJson) => {
                     doc.add_pre_tokenized_text(tantivy_field, pretokenized_schema_json.clone());
                 }
@@ -406,9 +476,20 @@ impl Html {
                 }
                 Field::Fast(FastField::LastUpdated) => doc.add_u64(
                     tantivy_field,
-                    self.updated_time()
+                    timestamps
+                        .freshest()
                         .map_or(0, |time| time.timestamp().max(0) as u64),
                 ),
+                Field::Fast(FastField::PublishedTime) => doc.add_u64(
+                    tantivy_field,
+                    timestamps
+                        .published
+                        .map_or(0, |time| time.timestamp().max(0) as u64),
+                ),
+                Field::Fast(FastField::TimestampConfidence) => doc.add_u64(
+                    tantivy_field,
+                    (timestamps.confidence * FLOAT_SCALING as f64) as u64,
+                ),
                 Field::Fast(FastField::TrackerScore) => {
                     doc.add_u64(tantivy_field, self.trackers().len() as u64)
                 }
@@ -518,16 +599,34 @@ impl Html {
                         (self.link_density() * FLOAT_SCALING as f64) as u64,
                     );
                 }
+                Field::Fast(FastField::Accessibility) => {
+                    doc.add_u64(
+                        tantivy_field,
+                        (self.accessibility().score() * FLOAT_SCALING as f64) as u64,
+                    );
+                }
+                Field::Fast(FastField::PageQuality) => {
+                    doc.add_u64(
+                        tantivy_field,
+                        (self.quality().score() * FLOAT_SCALING as f64) as u64,
+                    );
+                }
+                Field::Fast(FastField::Language) => {
+                    doc.add_u64(tantivy_field, language::id(self.lang.as_ref()));
+                }
                 Field::Text(TextField::BacklinkText)
                 | Field::Text(TextField::SafetyClassification)
                 | Field::Text(TextField::InsertionTimestamp)
                 | Field::Fast(FastField::HostCentrality)
                 | Field::Fast(FastField::HostCentralityRank)
+                | Field::Fast(FastField::HostTrust)
+                | Field::Fast(FastField::HostLinkSpam)
                 | Field::Fast(FastField::PageCentrality)
                 | Field::Fast(FastField::PageCentralityRank)
                 | Field::Fast(FastField::FetchTimeMs)
+                | Field::Fast(FastField::TlsStatus)
                 | Field::Fast(FastField::PreComputedScore)
-                | Field::Fast(FastField::Region)
+                | Field::Fast(FastField::Regions)
                 | Field::Fast(FastField::HostNodeID)
                 | Field::Text(TextField::DmozDescription) => {}
             }
@@ -548,6 +647,7 @@ fn stemmer_from_lang(lang: &Lang) -> rust_stemmers::Stemmer {
         Lang::Ell => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Greek),
         Lang::Hun => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Hungarian),
         Lang::Ita => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Italian),
+        Lang::Nob => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Norwegian),
         Lang::Por => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Portuguese),
         Lang::Ron => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Romanian),
         Lang::Rus => rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::Russian),
@@ -14,13 +14,39 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use itertools::Itertools;
 use kuchiki::iter::NodeEdge;
 use url::Url;
 
-use crate::webpage::{url_ext::UrlExt, Link};
+use crate::{
+    feed::{Feed, FeedKind},
+    webpage::{
+        hreflang::{LanguageAlternate, MAX_ALTERNATES},
+        url_ext::UrlExt,
+        Link,
+    },
+};
 
 use super::Html;
 
+/// Links in these elements are navigation and not part of the main content.
+const BOILERPLATE_ELEMENTS: [&str; 4] = ["nav", "header", "footer", "aside"];
+
+fn is_nofollow(rel: Option<&str>) -> bool {
+    rel.map(|rel| rel.contains("nofollow") || rel.contains("sponsored") || rel.contains("ugc"))
+        .unwrap_or(false)
+}
+
+/// An anchor link together with how it is placed on the page.
+#[derive(PartialEq, Eq, Debug)]
+pub struct AnchorLink {
+    pub link: Link,
+    /// The link is not inside navigation, a header, a footer or an aside.
+    pub prominent: bool,
+    /// The link is marked as nofollow, sponsored or ugc.
+    pub nofollow: bool,
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct FaviconLink {
     pub link: Url,
@@ -39,7 +65,18 @@ pub struct ImageLink {
 impl Html {
     pub fn favicon(&self) -> Option<FaviconLink> {
         for node in self.root.select("link").unwrap() {
-            if !matches!(node.attributes.borrow().get("rel"), Some("icon")) {
+            // `shortcut icon` is an old but still common spelling of `icon`
+            let is_icon = node
+                .attributes
+                .borrow()
+                .get("rel")
+                .map(|rel| {
+                    rel.split_whitespace()
+                        .any(|r| r.eq_ignore_ascii_case("icon"))
+                })
+                .unwrap_or(false);
+
+            if !is_icon {
                 continue;
             }
 
@@ -72,7 +109,83 @@ impl Html {
         None
     }
 
-    fn og_image(&self) -> Option<ImageLink> {
+    /// RSS and Atom feeds advertised by the page with `<link rel="alternate">`.
+    pub fn feeds(&self) -> Vec<Feed> {
+        self.root
+            .select("link")
+            .unwrap()
+            .filter_map(|node| {
+                let attributes = node.attributes.borrow();
+
+                if !attributes
+                    .get("rel")?
+                    .split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+                {
+                    return None;
+                }
+
+                let kind = match attributes.get("type")?.trim().to_ascii_lowercase().as_str() {
+                    "application/rss+xml" => FeedKind::Rss,
+                    "application/atom+xml" => FeedKind::Atom,
+                    _ => return None,
+                };
+
+                let href = attributes.get("href")?;
+                let url = Url::parse(href).or_else(|_| self.url().join(href)).ok()?;
+
+                Some(Feed { url, kind })
+            })
+            .collect()
+    }
+
+    /// Language variants of the page advertised with `<link rel="alternate" hreflang="..">`.
+    /// Any page can claim to be a variant of any other page, so only variants on the
+    /// same site as the page are trusted.
+    pub fn language_alternates(&self) -> Vec<LanguageAlternate> {
+        let site = self.url().root_domain().map(|domain| domain.to_string());
+
+        self.root
+            .select("link")
+            .unwrap()
+            .filter_map(|node| {
+                let attributes = node.attributes.borrow();
+
+                if !attributes
+                    .get("rel")?
+                    .split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+                {
+                    return None;
+                }
+
+                let lang = attributes.get("hreflang")?.trim().to_ascii_lowercase();
+                if lang.is_empty() {
+                    return None;
+                }
+
+                let href = attributes.get("href")?;
+                let url = Url::parse(href).or_else(|_| self.url().join(href)).ok()?;
+
+                if !matches!(url.scheme(), "http" | "https") {
+                    return None;
+                }
+
+                if site.is_none() || url.root_domain().map(|domain| domain.to_string()) != site {
+                    return None;
+                }
+
+                Some(LanguageAlternate {
+                    lang,
+                    url: url.to_string(),
+                })
+            })
+            .unique_by(|alternate| alternate.lang.clone())
+            .take(MAX_ALTERNATES)
+            .collect()
+    }
+
+    pub(super) fn og_image(&self) -> Option<ImageLink> {
         self.metadata()
             .into_iter()
             .find(|metadata| {
@@ -118,27 +231,53 @@ impl Html {
     }
 
     pub fn anchor_links(&self) -> Vec<Link> {
+        self.anchor_links_with_context()
+            .into_iter()
+            .map(|anchor| anchor.link)
+            .collect()
+    }
+
+    pub fn anchor_links_with_context(&self) -> Vec<AnchorLink> {
         if self.is_no_follow() {
             return Vec::new();
         }
 
         let mut links = Vec::new();
         let mut open_links = Vec::new();
+        let mut boilerplate_depth = 0;
 
         for edge in self.root.traverse() {
             match edge {
                 NodeEdge::Start(node) => {
                     if let Some(element) = node.as_element() {
-                        if &element.name.local == "a" {
-                            open_links.push((String::new(), element.attributes.clone()));
+                        let name: &str = &element.name.local;
+
+                        if name == "a" {
+                            open_links.push((
+                                String::new(),
+                                element.attributes.clone(),
+                                boilerplate_depth == 0,
+                            ));
+                        }
+
+                        if BOILERPLATE_ELEMENTS.contains(&name) {
+                            boilerplate_depth += 1;
                         }
                     }
                 }
                 NodeEdge::End(node) => {
                     if let Some(element) = node.as_element() {
-                        if &element.name.local == "a" {
-                            if let Some((text, attributes)) = open_links.pop() {
-                                if let Some(dest) = attributes.borrow().get("href") {
+                        let name: &str = &element.name.local;
+
+                        if BOILERPLATE_ELEMENTS.contains(&name) {
+                            boilerplate_depth -= 1;
+                        }
+
+                        if name == "a" {
+                            if let Some((text, attributes, prominent)) = open_links.pop() {
+                                let attributes = attributes.borrow();
+
+                                if let Some(dest) = attributes.get("href") {
                                     if dest.starts_with("mailto:") || dest.starts_with("tel:") {
                                         continue;
                                     }
@@ -146,10 +285,14 @@ impl Html {
                                     if let Ok(dest) =
                                         Url::parse(dest).or_else(|_| self.url().join(dest))
                                     {
-                                        links.push(Link {
-                                            source: self.url().clone(),
-                                            destination: dest,
-                                            text: text.trim().to_string(),
+                                        links.push(AnchorLink {
+                                            link: Link {
+                                                source: self.url().clone(),
+                                                destination: dest,
+                                                text: text.trim().to_string(),
+                                            },
+                                            prominent,
+                                            nofollow: is_nofollow(attributes.get("rel")),
                                         });
                                     }
                                 }
@@ -162,7 +305,7 @@ impl Html {
                         let text = raw_text.trim();
 
                         if !text.is_empty() {
-                            for (link_text, _) in &mut open_links {
+                            for (link_text, _, _) in &mut open_links {
                                 link_text.push('\n');
                                 link_text.push_str(text);
                             }
@@ -172,23 +315,27 @@ impl Html {
             }
         }
 
-        while let Some((text, attributes)) = open_links.pop() {
-            if let Some(rel) = attributes.borrow().get("rel") {
-                if rel.contains("nofollow") || rel.contains("sponsored") || rel.contains("ugc") {
-                    continue;
-                }
+        while let Some((text, attributes, prominent)) = open_links.pop() {
+            let attributes = attributes.borrow();
+
+            if is_nofollow(attributes.get("rel")) {
+                continue;
             }
 
-            if let Some(dest) = attributes.borrow().get("href") {
+            if let Some(dest) = attributes.get("href") {
                 if dest.starts_with("mailto:") || dest.starts_with("tel:") {
                     continue;
                 }
 
                 if let Ok(dest) = Url::parse(dest).or_else(|_| self.url().join(dest)) {
-                    links.push(Link {
-                        source: self.url().clone(),
-                        destination: dest,
-                        text: text.trim().to_string(),
+                    links.push(AnchorLink {
+                        link: Link {
+                            source: self.url().clone(),
+                            destination: dest,
+                            text: text.trim().to_string(),
+                        },
+                        prominent,
+                        nofollow: false,
                     });
                 }
             }
@@ -313,6 +460,41 @@ impl Html {
 mod tests {
     use super::*;
 
+    #[test]
+    fn anchor_link_context() {
+        let raw = r#"
+            <html>
+                <body>
+                    <nav><a href="/home">Home</a></nav>
+                    <p>
+                        <a href="https://a.com">A</a>
+                        <a href="https://b.com" rel="nofollow">B</a>
+                        <a href="https://c.com" rel="noopener sponsored">C</a>
+                    </p>
+                    <footer><div><a href="/about">About</a></div></footer>
+                </body>
+            </html>
+        "#;
+
+        let webpage = Html::parse(raw, "https://www.example.com").unwrap();
+        let links: Vec<_> = webpage
+            .anchor_links_with_context()
+            .into_iter()
+            .map(|anchor| (anchor.link.text, anchor.prominent, anchor.nofollow))
+            .collect();
+
+        assert_eq!(
+            links,
+            vec![
+                ("Home".to_string(), false, false),
+                ("A".to_string(), true, false),
+                ("B".to_string(), true, true),
+                ("C".to_string(), true, true),
+                ("About".to_string(), false, false),
+            ]
+        );
+    }
+
     #[test]
     fn simple_favicon() {
         let raw = r#"
@@ -393,6 +575,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn shortcut_icon_favicon() {
+        let raw = r#"
+            <html>
+                <head>
+                    <link rel="stylesheet" href="/style.css" />
+                    <link rel="shortcut icon" href="/favicon.ico" />
+                </head>
+            </html>
+        "#
+        .to_string();
+
+        let webpage = Html::parse(&raw, "https://www.example.com").unwrap();
+        assert_eq!(
+            webpage.favicon().map(|favicon| favicon.link),
+            Some(Url::parse("https://www.example.com/favicon.ico").unwrap())
+        );
+    }
+
     #[test]
     fn primary_image() {
         let html = r#"
@@ -460,4 +661,70 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn feeds() {
+        let raw = r#"
+            <html>
+                <head>
+                    <link rel="alternate" type="application/rss+xml" href="/feed.xml" />
+                    <link rel="alternate" type="application/atom+xml" href="https://example.com/atom" />
+                    <link rel="alternate" type="application/json+oembed" href="/oembed" />
+                    <link rel="alternate" hreflang="de" href="/de" />
+                    <link rel="stylesheet" type="application/rss+xml" href="/style.css" />
+                </head>
+            </html>
+        "#;
+
+        let webpage = Html::parse(raw, "https://www.example.com/blog/").unwrap();
+        assert_eq!(
+            webpage.feeds(),
+            vec![
+                Feed {
+                    url: Url::parse("https://www.example.com/feed.xml").unwrap(),
+                    kind: FeedKind::Rss,
+                },
+                Feed {
+                    url: Url::parse("https://example.com/atom").unwrap(),
+                    kind: FeedKind::Atom,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn language_alternates() {
+        let raw = r#"
+            <html>
+                <head>
+                    <link rel="alternate" hreflang="en-US" href="https://www.example.com/en/" />
+                    <link rel="alternate" hreflang="de" href="/de/" />
+                    <link rel="alternate" hreflang="x-default" href="/" />
+                    <link rel="alternate" hreflang="de" href="/de-duplicate/" />
+                    <link rel="alternate" hreflang="fr" href="https://other.com/fr/" />
+                    <link rel="alternate" type="application/rss+xml" href="/feed.xml" />
+                    <link rel="canonical" hreflang="fr" href="/fr/" />
+                </head>
+            </html>
+        "#;
+
+        let webpage = Html::parse(raw, "https://www.example.com/en/").unwrap();
+        assert_eq!(
+            webpage.language_alternates(),
+            vec![
+                LanguageAlternate {
+                    lang: "en-us".to_string(),
+                    url: "https://www.example.com/en/".to_string(),
+                },
+                LanguageAlternate {
+                    lang: "de".to_string(),
+                    url: "https://www.example.com/de/".to_string(),
+                },
+                LanguageAlternate {
+                    lang: "x-default".to_string(),
+                    url: "https://www.example.com/".to_string(),
+                },
+            ]
+        );
+    }
 }
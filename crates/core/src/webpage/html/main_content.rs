@@ -0,0 +1,500 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Readability-style extraction of the main content of a page. Paragraphs give
+//! points to the elements that contain them, the points are reduced by the
+//! link density of the element and the element with the most points is taken
+//! as the content. Navigation, cookie banners, footers and similar boilerplate
+//! are never part of the content.
+//!
+//! Unlike readability, comments are kept as they are the content of forums
+//! and discussion threads.
+
+use std::{collections::HashMap, rc::Rc};
+
+use kuchiki::{iter::NodeEdge, Node, NodeRef};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::Html;
+
+/// Classes and ids of elements that are rarely part of the content.
+static UNLIKELY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)-ad-|ad-break|agegate|banner|breadcrumb|combx|consent|cookie|extra|footer|gdpr|header|legends|menu|modal|newsletter|pager|pagination|popup|related|rss|share|shoutbox|sidebar|skyscraper|social|sponsor|subscribe|supplemental").unwrap()
+});
+
+/// Classes and ids that override [`UNLIKELY`], as the content is often wrapped in them.
+static MAYBE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)article|body|column|content|entry|main|shadow|title").unwrap());
+
+static POSITIVE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)article|blog|body|content|entry|hentry|h-entry|main|page|post|story|text")
+        .unwrap()
+});
+
+static NEGATIVE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)-ad-|banner|byline|comment|com-|contact|footer|footnote|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|taboola|widget").unwrap()
+});
+
+/// Aria roles of elements that are not part of the content.
+const UNLIKELY_ROLES: [&str; 9] = [
+    "alertdialog",
+    "banner",
+    "complementary",
+    "contentinfo",
+    "dialog",
+    "menu",
+    "menubar",
+    "navigation",
+    "search",
+];
+
+/// A `<div>` that contains none of these is scored as a paragraph.
+const BLOCK_TAGS: [&str; 21] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "div",
+    "dl",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "ol",
+    "p",
+    "pre",
+    "ul",
+];
+
+/// Paragraphs shorter than this don't give points to their ancestors.
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+/// The best element must have at least this much text to be used as the content.
+const MIN_CONTENT_LEN: usize = 140;
+
+/// Points given or taken based on the class and id of an element.
+const CLASS_WEIGHT: f64 = 25.0;
+
+/// The content must hold at least this share of the text in the scored paragraphs.
+const MIN_CONTENT_SHARE: f64 = 0.5;
+
+/// Navigation, banners, footers and other elements that are never part of the content.
+pub fn is_boilerplate(node: &NodeRef) -> bool {
+    let Some(element) = node.as_element() else {
+        return false;
+    };
+
+    let name: &str = &element.name.local;
+
+    if matches!(name, "nav" | "aside" | "footer" | "dialog") {
+        return true;
+    }
+
+    if matches!(name, "html" | "body" | "main" | "article" | "a") {
+        return false;
+    }
+
+    let attributes = element.attributes.borrow();
+
+    if attributes.contains("hidden") || attributes.get("aria-hidden") == Some("true") {
+        return true;
+    }
+
+    if attributes
+        .get("role")
+        .map(|role| UNLIKELY_ROLES.contains(&role))
+        .unwrap_or(false)
+    {
+        return true;
+    }
+
+    let class_and_id = format!(
+        "{} {}",
+        attributes.get("class").unwrap_or_default(),
+        attributes.get("id").unwrap_or_default()
+    );
+
+    UNLIKELY.is_match(&class_and_id) && !MAYBE.is_match(&class_and_id)
+}
+
+fn text_len(node: &NodeRef) -> usize {
+    node.text_contents()
+        .split_whitespace()
+        .map(|word| word.chars().count() + 1)
+        .sum()
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn link_density(node: &NodeRef) -> f64 {
+    let len = text_len(node);
+
+    if len == 0 {
+        return 0.0;
+    }
+
+    let link_len: usize = node
+        .select("a")
+        .unwrap()
+        .map(|link| text_len(link.as_node()))
+        .sum();
+
+    link_len as f64 / len as f64
+}
+
+fn is_paragraph(node: &NodeRef) -> bool {
+    let Some(element) = node.as_element() else {
+        return false;
+    };
+
+    match &*element.name.local {
+        "p" | "pre" | "td" => true,
+        "div" => !node.children().any(|child| {
+            child
+                .as_element()
+                .map(|child| BLOCK_TAGS.contains(&&*child.name.local))
+                .unwrap_or(false)
+        }),
+        _ => false,
+    }
+}
+
+fn initial_score(node: &NodeRef) -> f64 {
+    let Some(element) = node.as_element() else {
+        return 0.0;
+    };
+
+    let tag_score = match &*element.name.local {
+        "div" | "article" | "main" | "section" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+
+    let attributes = element.attributes.borrow();
+    let class_score: f64 = [attributes.get("class"), attributes.get("id")]
+        .into_iter()
+        .flatten()
+        .map(|value| {
+            let mut score = 0.0;
+
+            if NEGATIVE.is_match(value) {
+                score -= CLASS_WEIGHT;
+            }
+
+            if POSITIVE.is_match(value) {
+                score += CLASS_WEIGHT;
+            }
+
+            score
+        })
+        .sum();
+
+    tag_score + class_score
+}
+
+struct Candidate {
+    node: NodeRef,
+    score: f64,
+}
+
+struct Paragraph {
+    node: NodeRef,
+    len: usize,
+}
+
+#[derive(Default)]
+struct Scores {
+    candidates: HashMap<*const Node, Candidate>,
+    paragraphs: Vec<Paragraph>,
+}
+
+impl Scores {
+    /// Length of the scored paragraphs inside the node.
+    fn text_len_within(&self, node: &NodeRef) -> usize {
+        self.paragraphs
+            .iter()
+            .filter(|paragraph| paragraph.node.inclusive_ancestors().any(|a| a == *node))
+            .map(|paragraph| paragraph.len)
+            .sum()
+    }
+}
+
+/// Score the ancestors of all paragraphs outside of boilerplate elements.
+fn score(root: &NodeRef) -> Scores {
+    let mut scores = Scores::default();
+    let mut skipped: Option<NodeRef> = None;
+
+    for edge in root.traverse() {
+        let node = match edge {
+            NodeEdge::Start(node) => node,
+            NodeEdge::End(node) => {
+                if skipped.as_ref() == Some(&node) {
+                    skipped = None;
+                }
+
+                continue;
+            }
+        };
+
+        if skipped.is_some() {
+            continue;
+        }
+
+        if is_boilerplate(&node) {
+            skipped = Some(node);
+            continue;
+        }
+
+        if !is_paragraph(&node) {
+            continue;
+        }
+
+        let len = text_len(&node);
+        if len < MIN_PARAGRAPH_LEN {
+            continue;
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let score =
+            1.0 + node.text_contents().matches(',').count() as f64 + (len as f64 / 100.0).min(3.0);
+
+        // the parent gets all the points and the grandparent half
+        for (ancestor, share) in node.ancestors().take(2).zip([1.0, 0.5]) {
+            if ancestor.as_element().is_none() {
+                break;
+            }
+
+            scores
+                .candidates
+                .entry(Rc::as_ptr(&ancestor.0))
+                .or_insert_with(|| Candidate {
+                    score: initial_score(&ancestor),
+                    node: ancestor.clone(),
+                })
+                .score += score * share;
+        }
+
+        scores.paragraphs.push(Paragraph { node, len });
+    }
+
+    for candidate in scores.candidates.values_mut() {
+        candidate.score *= 1.0 - link_density(&candidate.node);
+    }
+
+    scores
+}
+
+/// The best candidate together with the siblings that look like they are
+/// part of the same content, in document order.
+#[allow(clippy::cast_precision_loss)]
+fn content_nodes(root: &NodeRef) -> Option<Vec<NodeRef>> {
+    let scores = score(root);
+    let candidates = &scores.candidates;
+
+    let best = candidates
+        .values()
+        .max_by(|a, b| a.score.total_cmp(&b.score))?;
+
+    // on forums and in discussion threads the content is spread over many
+    // small elements, so the best one is widened until it holds most of the text.
+    let min_len = scores.paragraphs.iter().map(|p| p.len).sum::<usize>() as f64 * MIN_CONTENT_SHARE;
+    let mut content = best.node.clone();
+
+    while (scores.text_len_within(&content) as f64) < min_len {
+        match content.parent() {
+            Some(parent) if parent.as_element().is_some() => content = parent,
+            _ => break,
+        }
+    }
+
+    if text_len(&content) < MIN_CONTENT_LEN {
+        return None;
+    }
+
+    let Some(parent) = content.parent() else {
+        return Some(vec![content]);
+    };
+
+    let threshold = (best.score * 0.2).max(10.0);
+
+    let nodes = parent
+        .children()
+        .filter(|sibling| {
+            if *sibling == content {
+                return true;
+            }
+
+            if is_boilerplate(sibling) {
+                return false;
+            }
+
+            if let Some(candidate) = candidates.get(&Rc::as_ptr(&sibling.0)) {
+                if candidate.score >= threshold {
+                    return true;
+                }
+            }
+
+            // text that is not wrapped in an element is never scored
+            let is_text = sibling.as_text().is_some()
+                || sibling
+                    .as_element()
+                    .map(|element| &*element.name.local == "p")
+                    .unwrap_or(false);
+
+            is_text && text_len(sibling) > 80 && link_density(sibling) < 0.25
+        })
+        .collect();
+
+    Some(nodes)
+}
+
+impl Html {
+    /// Elements with the main content of the page in document order.
+    /// Falls back to the root if no element stands out as the content.
+    pub fn main_content(&self) -> Vec<NodeRef> {
+        content_nodes(&self.root).unwrap_or_else(|| vec![self.root.clone()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = "The committee met on Tuesday to discuss the new proposal, which would change how the river is managed during the summer. After a long debate, the members agreed to publish a draft for public comments before the next meeting.";
+
+    fn page(article: &str) -> String {
+        format!(
+            r#"
+            <html>
+                <head>
+                    <title>News</title>
+                </head>
+                <body>
+                    <div class="cookie-banner">
+                        <p>We use cookies to improve your experience on our website, by continuing you accept our use of cookies.</p>
+                    </div>
+                    <nav>
+                        <a href="/">Home</a>
+                        <a href="/news">All the latest news from the region</a>
+                    </nav>
+                    <div class="layout">
+                        <article>
+                            <h1>River management</h1>
+                            {article}
+                        </article>
+                        <div class="sidebar">
+                            <p>Sign up for our newsletter and never miss a story from the region again, it is free.</p>
+                        </div>
+                    </div>
+                    <footer>
+                        <p>Copyright Example News. All rights reserved, no part may be reproduced without permission.</p>
+                    </footer>
+                </body>
+            </html>
+            "#
+        )
+    }
+
+    #[test]
+    fn boilerplate() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <body>
+                    <div id="cookie-consent">cookies</div>
+                    <div class="main-content">content</div>
+                    <div role="navigation">links</div>
+                    <div hidden>hidden</div>
+                    <aside>aside</aside>
+                    <article class="comment">article</article>
+                </body>
+            </html>
+            "#,
+            "https://example.com",
+        )
+        .unwrap();
+
+        let boilerplate: Vec<_> = html
+            .root
+            .select("div, aside, article")
+            .unwrap()
+            .filter(|element| is_boilerplate(element.as_node()))
+            .map(|element| element.text_contents())
+            .collect();
+
+        assert_eq!(boilerplate, vec!["cookies", "links", "hidden", "aside"]);
+    }
+
+    #[test]
+    fn article_is_main_content() {
+        let html = Html::parse(
+            &page(&format!("<p>{ARTICLE}</p><p>{ARTICLE}</p>")),
+            "https://example.com",
+        )
+        .unwrap();
+
+        let content = html.main_content();
+        assert_eq!(content.len(), 1);
+        assert_eq!(&*content[0].as_element().unwrap().name.local, "article");
+
+        let text = html.clean_text().unwrap();
+        assert!(text.contains("The committee met on Tuesday"));
+        assert!(!text.contains("cookies"));
+        assert!(!text.contains("newsletter"));
+        assert!(!text.contains("Copyright"));
+    }
+
+    #[test]
+    fn thread_is_main_content() {
+        let posts = (0..4)
+            .map(|_| format!(r#"<div class="post"><p>{ARTICLE}</p></div>"#))
+            .collect::<String>();
+
+        let html = Html::parse(
+            &page(&format!(r#"<div class="thread">{posts}</div>"#)),
+            "https://example.com",
+        )
+        .unwrap();
+
+        let content = html.main_content();
+        assert_eq!(content.len(), 1);
+        assert_eq!(
+            content[0]
+                .as_element()
+                .unwrap()
+                .attributes
+                .borrow()
+                .get("class"),
+            Some("thread")
+        );
+    }
+
+    #[test]
+    fn short_page_falls_back_to_root() {
+        let html = Html::parse(&page("<p>Too short.</p>"), "https://example.com").unwrap();
+
+        assert_eq!(html.main_content(), vec![html.root.clone()]);
+    }
+}
@@ -15,24 +15,34 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::{enum_map::EnumSet, Result};
-use chrono::{DateTime, FixedOffset, Utc};
+use chrono::{DateTime, FixedOffset};
 use itertools::Itertools;
 use kuchiki::{traits::TendrilSink, NodeRef};
 use regex::Regex;
 use url::Url;
 use whatlang::Lang;
 
+pub use self::accessibility::ACCESSIBLE_THRESHOLD;
 use self::robots_meta::RobotsMeta;
+pub use self::timestamps::parse_date;
 
-use super::{adservers::AD_SERVERS, schema_org, Meta, Script};
+use super::{adservers::AD_SERVERS, open_graph::OpenGraph, schema_org, Meta, Script};
 
 use super::url_ext::UrlExt;
 
+mod accessibility;
+mod authors;
+mod code;
+mod headings;
+mod images;
 mod into_tantivy;
 mod links;
+mod main_content;
 mod microformats;
 mod parse_text;
+mod quality;
 mod robots_meta;
+mod timestamps;
 
 pub static URL_REGEX: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
     Regex::new(r"(((http|ftp|https):/{2})+(([0-9a-z_-]+\.)+(aero|asia|biz|cat|com|coop|edu|gov|info|int|jobs|mil|mobi|museum|name|net|org|pro|tel|travel|ac|ad|ae|af|ag|ai|al|am|an|ao|aq|ar|as|at|au|aw|ax|az|ba|bb|bd|be|bf|bg|bh|bi|bj|bm|bn|bo|br|bs|bt|bv|bw|by|bz|ca|cc|cd|cf|cg|ch|ci|ck|cl|cm|cn|co|cr|cu|cv|cx|cy|cz|cz|de|dj|dk|dm|do|dz|ec|ee|eg|er|es|et|eu|fi|fj|fk|fm|fo|fr|ga|gb|gd|ge|gf|gg|gh|gi|gl|gm|gn|gp|gq|gr|gs|gt|gu|gw|gy|hk|hm|hn|hr|ht|hu|id|ie|il|im|in|io|iq|ir|is|it|je|jm|jo|jp|ke|kg|kh|ki|km|kn|kp|kr|kw|ky|kz|la|lb|lc|li|lk|lr|ls|lt|lu|lv|ly|ma|mc|md|me|mg|mh|mk|ml|mn|mn|mo|mp|mr|ms|mt|mu|mv|mw|mx|my|mz|na|nc|ne|nf|ng|ni|nl|no|np|nr|nu|nz|nom|pa|pe|pf|pg|ph|pk|pl|pm|pn|pr|ps|pt|pw|py|qa|re|ra|rs|ru|rw|sa|sb|sc|sd|se|sg|sh|si|sj|sj|sk|sl|sm|sn|so|sr|st|su|sv|sy|sz|tc|td|tf|tg|th|tj|tk|tl|tm|tn|to|tp|tr|tt|tv|tw|tz|ua|ug|uk|us|uy|uz|va|vc|ve|vg|vi|vn|vu|wf|ws|ye|yt|yu|za|zm|zw|arpa)(:[0-9]+)?((/([~0-9a-zA-Z\#\+%@\./_-]+))?(\?[0-9a-zA-Z\+%@/&\[\];=_-]+)?)?))\b").unwrap()
@@ -46,6 +56,8 @@ pub struct Html {
     clean_text: Option<String>,
     lang: Option<Lang>,
     robots: Option<EnumSet<RobotsMeta>>,
+    http_last_modified: Option<DateTime<FixedOffset>>,
+    sitemap_lastmod: Option<DateTime<FixedOffset>>,
 }
 
 impl Html {
@@ -75,6 +87,8 @@ impl Html {
             lang: None,
             url,
             robots: None,
+            http_last_modified: None,
+            sitemap_lastmod: None,
         };
 
         let queries: Vec<_> = res
@@ -111,6 +125,22 @@ impl Html {
         self.lang.as_ref()
     }
 
+    /// The primary language subtag from `<html lang="..">`, e.g. `en` for `en-GB`.
+    /// Unlike [`Html::lang`] this doesn't require the text of the page.
+    pub fn declared_lang(&self) -> Option<String> {
+        let html = self.root.select_first("html")?;
+        let attributes = html.attributes.borrow();
+        let lang = attributes.get("lang")?.trim();
+
+        let primary = lang.split(['-', '_']).next()?.to_ascii_lowercase();
+
+        if (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic()) {
+            Some(primary)
+        } else {
+            None
+        }
+    }
+
     pub fn canonical_url(&self) -> Option<Url> {
         let mut canonical_url = None;
 
@@ -329,40 +359,6 @@ impl Html {
         false
     }
 
-    fn article_modified_time(&self) -> Option<DateTime<FixedOffset>> {
-        self.metadata()
-            .into_iter()
-            .find(|metadata| {
-                if let Some(property) = metadata.get("property") {
-                    property == &String::from("article:modified_time")
-                } else {
-                    false
-                }
-            })
-            .and_then(|metadata| {
-                metadata
-                    .get("content")
-                    .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
-            })
-    }
-
-    fn og_updated_time(&self) -> Option<DateTime<FixedOffset>> {
-        self.metadata()
-            .into_iter()
-            .find(|metadata| {
-                if let Some(property) = metadata.get("property") {
-                    property == &String::from("og:updated_time")
-                } else {
-                    false
-                }
-            })
-            .and_then(|metadata| {
-                metadata
-                    .get("content")
-                    .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
-            })
-    }
-
     fn schema_org_images(&self) -> Vec<Url> {
         self.schema_org()
             .into_iter()
@@ -382,28 +378,15 @@ impl Html {
     }
 
     pub fn updated_time(&self) -> Option<DateTime<FixedOffset>> {
-        if let Some(time) = self
-            .og_updated_time()
-            .or_else(|| self.article_modified_time())
-        {
-            let current_time = Utc::now();
-
-            if time > current_time {
-                None
-            } else {
-                Some(time)
-            }
-        } else {
-            None
-        }
+        self.timestamps().freshest()
     }
 
-    pub fn og_description(&self) -> Option<String> {
+    fn og_property(&self, name: &str) -> Option<String> {
         self.metadata()
             .into_iter()
             .find(|metadata| {
                 if let Some(property) = metadata.get("property") {
-                    property.as_str() == "og:description"
+                    property.as_str() == name
                 } else {
                     false
                 }
@@ -411,6 +394,10 @@ impl Html {
             .and_then(|metadata| metadata.get("content").cloned())
     }
 
+    pub fn og_description(&self) -> Option<String> {
+        self.og_property("og:description")
+    }
+
     pub fn metadata_description(&self) -> Option<String> {
         self.metadata()
             .into_iter()
@@ -424,22 +411,41 @@ impl Html {
             .and_then(|metadata| metadata.get("content").cloned())
     }
 
+    /// The meta description is written for search engines, so it is preferred
+    /// over the og:description which is written for social media.
     pub fn description(&self) -> Option<String> {
-        self.og_description()
-            .or_else(|| self.metadata_description())
+        self.metadata_description()
+            .filter(|desc| !desc.trim().is_empty())
+            .or_else(|| self.og_description())
     }
 
     pub fn og_title(&self) -> Option<String> {
-        self.metadata()
-            .into_iter()
-            .find(|metadata| {
-                if let Some(property) = metadata.get("property") {
-                    property == &String::from("og:title")
-                } else {
-                    false
-                }
-            })
-            .and_then(|metadata| metadata.get("content").cloned())
+        self.og_property("og:title")
+    }
+
+    pub fn og_type(&self) -> Option<String> {
+        self.og_property("og:type")
+    }
+
+    pub fn open_graph(&self) -> OpenGraph {
+        let non_empty = |s: String| {
+            let s = s.trim().to_string();
+            if s.is_empty() {
+                None
+            } else {
+                Some(s)
+            }
+        };
+
+        OpenGraph {
+            title: self.og_title().and_then(non_empty),
+            description: self.og_description().and_then(non_empty),
+            image: self.og_image().map(|image| image.url.to_string()),
+            kind: self
+                .og_type()
+                .and_then(non_empty)
+                .map(|kind| kind.to_lowercase()),
+        }
     }
 
     pub fn is_homepage(&self) -> bool {
@@ -845,6 +851,53 @@ mod tests {
         let html = Html::parse(html, "http://example.com").unwrap();
 
         assert_eq!(html.description(), None);
+
+        let html = r#"
+    <html>
+        <head>
+            <meta property="og:description" content="Shared on social media" />
+            <meta name="description" content="Written for search engines" />
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "http://example.com").unwrap();
+
+        assert_eq!(
+            html.description(),
+            Some("Written for search engines".to_string())
+        );
+    }
+
+    #[test]
+    fn open_graph() {
+        let html = r#"
+    <html>
+        <head>
+            <meta property="og:title" content="The Rock" />
+            <meta property="og:type" content="Video.Movie" />
+            <meta property="og:image" content="/images/rock.jpg" />
+            <meta property="og:description" content="  " />
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com/movies/rock").unwrap();
+
+        assert_eq!(
+            html.open_graph(),
+            OpenGraph {
+                title: Some("The Rock".to_string()),
+                description: None,
+                image: Some("https://example.com/images/rock.jpg".to_string()),
+                kind: Some("video.movie".to_string()),
+            }
+        );
+
+        let html = Html::parse("<html></html>", "https://example.com").unwrap();
+        assert!(html.open_graph().is_empty());
     }
 
     #[test]
@@ -18,7 +18,7 @@ use whatlang::Lang;
 
 use crate::webpage::just_text::{JustText, Paragraph};
 
-use super::Html;
+use super::{main_content, Html};
 
 impl Html {
     pub fn parse_text(&mut self) {
@@ -37,8 +37,19 @@ impl Html {
                 })
             });
 
-        self.all_text = Html::calculate_all_text(&paragraphs, &self.lang.unwrap_or(Lang::Eng));
-        self.clean_text = Html::calculate_clean_text(&paragraphs, &self.lang.unwrap_or(Lang::Eng));
+        let lang = self.lang.unwrap_or(Lang::Eng);
+
+        // the clean text only comes from the main content of the page, so
+        // navigation, banners and footers don't end up in snippets or the clean body.
+        let content_paragraphs: Vec<_> = self
+            .main_content()
+            .into_iter()
+            .flat_map(|node| JustText::paragraphs_without(node, main_content::is_boilerplate))
+            .collect();
+
+        self.all_text = Html::calculate_all_text(&paragraphs, &lang);
+        self.clean_text = Html::calculate_clean_text(&content_paragraphs, &lang)
+            .or_else(|| Html::calculate_clean_text(&paragraphs, &lang));
     }
 
     fn calculate_clean_text(paragraphs: &[Paragraph], lang: &Lang) -> Option<String> {
@@ -0,0 +1,332 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Heuristic page quality classifier used to demote thin SEO spam.
+//! Pages are judged on how much of their text is actual content, whether a
+//! few keywords are repeated over and over, how many ads they embed and
+//! whether they mostly consist of (sponsored) links.
+
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::webpage::{adservers::AD_SERVERS, url_ext::UrlExt};
+
+use super::Html;
+
+/// Pages with fewer words of main content than this are considered thin.
+const MIN_CONTENT_WORDS: f64 = 300.0;
+
+/// Fraction of the text that should be main content for a page to not be penalized.
+const MIN_CONTENT_RATIO: f64 = 0.5;
+
+/// Keyword stuffing is only detected in texts with at least this many keywords.
+const MIN_STUFFING_WORDS: usize = 50;
+
+/// Share of the keywords that the most frequent keyword can make up in natural text.
+const NATURAL_TOP_WORD_SHARE: f64 = 0.05;
+
+/// Share of the keywords where a page is considered fully keyword stuffed.
+const STUFFED_TOP_WORD_SHARE: f64 = 0.2;
+
+/// Fraction of the words on a page that can be link texts before it is penalized.
+const NATURAL_LINK_TEXT_RATIO: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageQuality {
+    /// Number of words in the main content.
+    pub content_words: usize,
+    /// Fraction of the words on the page that are part of the main content.
+    pub content_ratio: f64,
+    /// Share of the keywords on the page taken up by the most frequent keyword.
+    /// This includes the boilerplate, since stuffed keywords are often hidden outside
+    /// of the main content.
+    pub top_word_share: f64,
+    /// Number of ad scripts and iframes per 100 words of main content.
+    pub ad_density: f64,
+    /// Fraction of the words on the page that are link texts.
+    pub link_text_ratio: f64,
+    /// Fraction of the links that are marked as sponsored.
+    pub sponsored_link_ratio: f64,
+}
+
+impl PageQuality {
+    fn thin_content_score(&self) -> f64 {
+        (self.content_words as f64 / MIN_CONTENT_WORDS).min(1.0)
+    }
+
+    fn boilerplate_score(&self) -> f64 {
+        (self.content_ratio / MIN_CONTENT_RATIO).min(1.0)
+    }
+
+    fn keyword_stuffing_score(&self) -> f64 {
+        let stuffing = (self.top_word_share - NATURAL_TOP_WORD_SHARE)
+            / (STUFFED_TOP_WORD_SHARE - NATURAL_TOP_WORD_SHARE);
+
+        1.0 - stuffing.clamp(0.0, 1.0)
+    }
+
+    fn ad_score(&self) -> f64 {
+        1.0 / (1.0 + self.ad_density)
+    }
+
+    fn link_score(&self) -> f64 {
+        let link_farm =
+            (self.link_text_ratio - NATURAL_LINK_TEXT_RATIO) / (1.0 - NATURAL_LINK_TEXT_RATIO);
+
+        (1.0 - link_farm.clamp(0.0, 1.0)) * (1.0 - self.sponsored_link_ratio)
+    }
+
+    /// Quality between 0 and 1 where 1 is the best.
+    pub fn score(&self) -> f64 {
+        (self.thin_content_score()
+            + self.boilerplate_score()
+            + self.keyword_stuffing_score()
+            + self.ad_score()
+            + self.link_score())
+            / 5.0
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+}
+
+/// Share of the keywords taken up by the most frequent keyword.
+/// Short words are ignored as they are mostly stopwords.
+fn top_word_share(text: &str) -> f64 {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut total = 0;
+
+    for word in words(text).filter(|word| word.chars().count() >= 4) {
+        *counts.entry(word.to_lowercase()).or_default() += 1;
+        total += 1;
+    }
+
+    if total < MIN_STUFFING_WORDS {
+        return 0.0;
+    }
+
+    counts.values().max().copied().unwrap_or_default() as f64 / total as f64
+}
+
+impl Html {
+    fn is_ad(&self, src: &str) -> bool {
+        let url = match Url::parse(src) {
+            Ok(url) => url,
+            Err(_) => return false,
+        };
+
+        if url.root_domain() == self.url().root_domain() {
+            return false;
+        }
+
+        url.root_domain()
+            .map(|domain| AD_SERVERS.is_adserver(domain))
+            .unwrap_or(false)
+            || url
+                .host_str()
+                .map(|host| AD_SERVERS.is_adserver(host))
+                .unwrap_or(false)
+    }
+
+    pub fn quality(&self) -> PageQuality {
+        let content_words = self
+            .clean_text()
+            .map(|text| words(text).count())
+            .unwrap_or(0);
+        let all_words = self
+            .all_text
+            .as_deref()
+            .map(|text| words(text).count())
+            .unwrap_or(0);
+
+        let content_ratio = if all_words == 0 {
+            0.0
+        } else {
+            (content_words as f64 / all_words as f64).min(1.0)
+        };
+
+        let top_word_share = self.all_text.as_deref().map(top_word_share).unwrap_or(0.0);
+
+        let num_ads = self
+            .root
+            .select("script[src], iframe[src]")
+            .unwrap()
+            .filter(|node| {
+                node.attributes
+                    .borrow()
+                    .get("src")
+                    .map(|src| self.is_ad(src))
+                    .unwrap_or(false)
+            })
+            .count();
+
+        let ad_density = num_ads as f64 / (1.0 + content_words as f64 / 100.0);
+
+        let mut num_links = 0;
+        let mut num_sponsored = 0;
+        let mut link_words = 0;
+
+        for link in self.root.select("a[href]").unwrap() {
+            num_links += 1;
+            link_words += words(&link.text_contents()).count();
+
+            if link
+                .attributes
+                .borrow()
+                .get("rel")
+                .map(|rel| rel.split_whitespace().any(|rel| rel == "sponsored"))
+                .unwrap_or(false)
+            {
+                num_sponsored += 1;
+            }
+        }
+
+        let link_text_ratio = if all_words == 0 {
+            0.0
+        } else {
+            (link_words as f64 / all_words as f64).min(1.0)
+        };
+
+        let sponsored_link_ratio = if num_links == 0 {
+            0.0
+        } else {
+            num_sponsored as f64 / num_links as f64
+        };
+
+        PageQuality {
+            content_words,
+            content_ratio,
+            top_word_share,
+            ad_density,
+            link_text_ratio,
+            sponsored_link_ratio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn article() -> String {
+        let paragraph = "The lighthouse keeper climbed the spiral staircase every evening \
+            to light the lamp. Ships passing through the narrow strait relied on its beam \
+            to avoid the rocks that had claimed many vessels before the tower was built. \
+            Over the years she learned to read the weather from the colour of the sky \
+            and the behaviour of the seabirds nesting on the cliffs below.";
+
+        format!(
+            r#"
+            <html>
+                <head><title>The lighthouse keeper</title></head>
+                <body>
+                    <nav><a href="/">Home</a> <a href="/about">About</a></nav>
+                    <article>
+                        <p>{paragraph}</p>
+                        <p>{paragraph}</p>
+                        <p>{paragraph}</p>
+                        <p>{paragraph}</p>
+                        <p>{paragraph}</p>
+                    </article>
+                </body>
+            </html>
+            "#
+        )
+    }
+
+    fn stuffed() -> String {
+        let paragraph = "Cheap watches for sale. Buy cheap watches online, the best cheap \
+            watches and discount watches. Our cheap watches store has cheap watches deals \
+            on cheap watches every day, so order cheap watches today.";
+
+        format!(
+            r#"
+            <html>
+                <head><title>Cheap watches</title></head>
+                <body>
+                    <p>{paragraph}</p>
+                    <p>{paragraph}</p>
+                    <p>{paragraph}</p>
+                    <p>{paragraph}</p>
+                    <p>{paragraph}</p>
+                    <ul>
+                        <li><a href="https://a.com" rel="sponsored">cheap watches deals</a></li>
+                        <li><a href="https://b.com" rel="sponsored">discount watches online</a></li>
+                        <li><a href="https://c.com" rel="nofollow sponsored">best watches store</a></li>
+                    </ul>
+                </body>
+            </html>
+            "#
+        )
+    }
+
+    #[test]
+    fn article_is_good_quality() {
+        let html = Html::parse(&article(), "https://example.com/lighthouse").unwrap();
+        let quality = html.quality();
+
+        assert!(quality.content_words > 200);
+        assert!(quality.content_ratio > 0.9);
+        assert!(quality.top_word_share < NATURAL_TOP_WORD_SHARE);
+        assert_eq!(quality.sponsored_link_ratio, 0.0);
+        assert!(quality.score() > 0.9);
+    }
+
+    #[test]
+    fn keyword_stuffing() {
+        let html = Html::parse(&stuffed(), "https://example.com/watches").unwrap();
+        let quality = html.quality();
+
+        assert!(quality.top_word_share > STUFFED_TOP_WORD_SHARE);
+        assert_eq!(quality.keyword_stuffing_score(), 0.0);
+        assert_eq!(quality.sponsored_link_ratio, 1.0);
+
+        let article = Html::parse(&article(), "https://example.com/lighthouse").unwrap();
+        assert!(quality.score() < article.quality().score());
+    }
+
+    #[test]
+    fn link_farm() {
+        let links: String = (0..100)
+            .map(|i| format!(r#"<a href="https://site{i}.com">visit site number {i}</a> "#))
+            .collect();
+        let html = Html::parse(
+            &format!("<html><body><div>{links}</div></body></html>"),
+            "https://example.com/links",
+        )
+        .unwrap();
+        let quality = html.quality();
+
+        assert!(quality.link_text_ratio > 0.9);
+        assert_eq!(quality.link_score(), 0.0);
+        assert!(quality.score() < 0.5);
+    }
+
+    #[test]
+    fn empty_page() {
+        let html = Html::parse("<html><body></body></html>", "https://example.com").unwrap();
+        let quality = html.quality();
+
+        assert_eq!(quality.content_words, 0);
+        assert_eq!(quality.content_ratio, 0.0);
+        assert_eq!(quality.ad_density, 0.0);
+        assert!(quality.score() < 0.7);
+    }
+}
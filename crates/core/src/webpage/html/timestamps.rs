@@ -0,0 +1,643 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pages often have several (sometimes conflicting) dates. This module collects
+//! the dates from the http headers, sitemaps, meta tags, structured data, visible `<time>`
+//! elements and bylines and reconciles them into a published and a modified timestamp
+//! along with a confidence score for the result.
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use super::Html;
+
+/// Dates before this are most likely parse errors or placeholders.
+const MIN_PLAUSIBLE_YEAR: i32 = 1991;
+
+/// Dates from different sources within this many seconds of each other agree.
+const AGREEMENT_WINDOW_SECS: i64 = 24 * 3600;
+
+const PUBLISHED_META: [&str; 9] = [
+    "article:published_time",
+    "og:published_time",
+    "datepublished",
+    "date",
+    "pubdate",
+    "publishdate",
+    "dc.date",
+    "dc.date.issued",
+    "dcterms.created",
+];
+
+const MODIFIED_META: [&str; 6] = [
+    "article:modified_time",
+    "og:updated_time",
+    "datemodified",
+    "last-modified",
+    "dc.date.modified",
+    "dcterms.modified",
+];
+
+const PUBLISHED_SCHEMA_ORG: [&str; 3] = ["datePublished", "dateCreated", "uploadDate"];
+const MODIFIED_SCHEMA_ORG: [&str; 1] = ["dateModified"];
+
+/// Elements that usually contain the byline of an article.
+const BYLINE_SELECTOR: &str = "[class*=byline], [class*=date], [class*=posted], \
+     [class*=published], [class*=updated], time:not([datetime])";
+
+/// Bylines longer than this are most likely containers for the entire article.
+const MAX_BYLINE_LEN: usize = 256;
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// Dates as they are usually written in bylines, e.g. "March 3, 2021",
+/// "3rd Mar 2021" or "2021-03-03".
+static TEXT_DATE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\b(?:(?P<d1>\d{1,2})(?:st|nd|rd|th)?\.?\s+(?P<m1>[a-z]{3,9})\.?,?\s+(?P<y1>\d{4})|(?P<m2>[a-z]{3,9})\.?\s+(?P<d2>\d{1,2})(?:st|nd|rd|th)?,?\s+(?P<y2>\d{4})|(?P<y3>\d{4})-(?P<m3>\d{2})-(?P<d3>\d{2}))\b",
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateSource {
+    HttpLastModified,
+    SitemapLastmod,
+    MetaTag,
+    StructuredData,
+    Visible,
+}
+
+impl DateSource {
+    /// How much we trust a date from the source. The http header is
+    /// often set to the time of the request for dynamic pages
+    /// so it is trusted the least.
+    fn confidence(&self) -> f64 {
+        match self {
+            DateSource::StructuredData => 0.9,
+            DateSource::MetaTag => 0.8,
+            DateSource::Visible => 0.6,
+            DateSource::SitemapLastmod => 0.5,
+            DateSource::HttpLastModified => 0.3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateKind {
+    Published,
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateCandidate {
+    pub time: DateTime<FixedOffset>,
+    pub kind: DateKind,
+    pub source: DateSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PageTimestamps {
+    pub published: Option<DateTime<FixedOffset>>,
+    pub modified: Option<DateTime<FixedOffset>>,
+    /// Confidence in [0, 1] of the freshest timestamp.
+    pub confidence: f64,
+}
+
+impl PageTimestamps {
+    /// The time the content was last changed.
+    pub fn freshest(&self) -> Option<DateTime<FixedOffset>> {
+        self.modified.or(self.published)
+    }
+
+    pub fn reconcile(candidates: &[DateCandidate], now: DateTime<Utc>) -> Self {
+        let plausible: Vec<_> = candidates
+            .iter()
+            .filter(|c| c.time <= now && c.time.year() >= MIN_PLAUSIBLE_YEAR)
+            .copied()
+            .collect();
+
+        // most trusted source wins. ties are broken by the earliest
+        // publish date and the latest modification date.
+        let published = plausible
+            .iter()
+            .filter(|c| c.kind == DateKind::Published)
+            .max_by(|a, b| {
+                a.source
+                    .confidence()
+                    .total_cmp(&b.source.confidence())
+                    .then(b.time.cmp(&a.time))
+            })
+            .copied();
+
+        let mut modified = plausible
+            .iter()
+            .filter(|c| c.kind == DateKind::Modified)
+            .max_by(|a, b| {
+                a.source
+                    .confidence()
+                    .total_cmp(&b.source.confidence())
+                    .then(a.time.cmp(&b.time))
+            })
+            .copied();
+
+        let mut penalty = 1.0;
+
+        if let (Some(p), Some(m)) = (published, modified) {
+            if m.time < p.time {
+                // a page cannot be modified before it was published
+                modified = None;
+                penalty = 0.5;
+            }
+        }
+
+        let chosen = match modified.or(published) {
+            Some(chosen) => chosen,
+            None => return Self::default(),
+        };
+
+        let agreeing = plausible.iter().any(|c| {
+            c.source != chosen.source
+                && (c.time - chosen.time).num_seconds().abs() <= AGREEMENT_WINDOW_SECS
+        });
+
+        let mut confidence = chosen.source.confidence() * penalty;
+        if agreeing {
+            confidence += 0.1;
+        }
+
+        Self {
+            published: published.map(|c| c.time),
+            modified: modified.map(|c| c.time),
+            confidence: confidence.min(1.0),
+        }
+    }
+}
+
+/// Parse the date formats commonly found on webpages.
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+
+    DateTime::parse_from_rfc3339(date)
+        .ok()
+        .or_else(|| DateTime::parse_from_rfc2822(date).ok())
+        .or_else(|| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f%z").ok())
+        .or_else(|| {
+            let naive = NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()?;
+            let naive = naive.and_hms_opt(0, 0, 0)?;
+
+            Some(Utc.from_utc_datetime(&naive).into())
+        })
+}
+
+fn month_from_name(name: &str) -> Option<u32> {
+    let name = name.to_ascii_lowercase();
+
+    if name.len() < 3 {
+        return None;
+    }
+
+    MONTHS
+        .iter()
+        .position(|month| month.starts_with(name.as_str()))
+        .map(|idx| idx as u32 + 1)
+}
+
+fn date_from_captures(caps: &Captures<'_>) -> Option<DateTime<FixedOffset>> {
+    let num = |name: &str| caps.name(name).and_then(|m| m.as_str().parse::<u32>().ok());
+
+    let (year, month, day) = if let Some(year) = num("y1") {
+        (
+            year,
+            month_from_name(caps.name("m1")?.as_str())?,
+            num("d1")?,
+        )
+    } else if let Some(year) = num("y2") {
+        (
+            year,
+            month_from_name(caps.name("m2")?.as_str())?,
+            num("d2")?,
+        )
+    } else {
+        (num("y3")?, num("m3")?, num("d3")?)
+    };
+
+    let naive = NaiveDate::from_ymd_opt(year as i32, month, day)?.and_hms_opt(0, 0, 0)?;
+
+    Some(Utc.from_utc_datetime(&naive).into())
+}
+
+/// Find all the dates in a byline. Whether a date is the publish or
+/// modification date is decided by the words preceding it, so
+/// "Published March 3, 2021. Updated March 5, 2021" yields both.
+fn byline_dates(text: &str) -> Vec<(DateTime<FixedOffset>, DateKind)> {
+    let mut res = Vec::new();
+    let mut prev_end = 0;
+
+    for caps in TEXT_DATE_REGEX.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        let context = text[prev_end..whole.start()].to_lowercase();
+        prev_end = whole.end();
+
+        if let Some(time) = date_from_captures(&caps) {
+            let kind = if context.contains("updat")
+                || context.contains("modif")
+                || context.contains("edited")
+            {
+                DateKind::Modified
+            } else {
+                DateKind::Published
+            };
+
+            res.push((time, kind));
+        }
+    }
+
+    res
+}
+
+fn meta_kind(name: &str) -> Option<DateKind> {
+    let name = name.to_ascii_lowercase();
+
+    if PUBLISHED_META.contains(&name.as_str()) {
+        Some(DateKind::Published)
+    } else if MODIFIED_META.contains(&name.as_str()) {
+        Some(DateKind::Modified)
+    } else {
+        None
+    }
+}
+
+impl Html {
+    pub fn set_http_last_modified(&mut self, header: &str) {
+        self.http_last_modified = parse_date(header);
+    }
+
+    /// The `<lastmod>` of the page in the sitemap of the site. Sitemaps are
+    /// usually generated from the CMS so this is a decent prior for when
+    /// the page was last changed if the page itself has no dates.
+    pub fn set_sitemap_lastmod(&mut self, lastmod: &str) {
+        self.sitemap_lastmod = parse_date(lastmod);
+    }
+
+    fn meta_date_candidates(&self) -> Vec<DateCandidate> {
+        self.metadata()
+            .into_iter()
+            .filter_map(|meta| {
+                let kind = ["property", "name", "itemprop", "http-equiv"]
+                    .iter()
+                    .filter_map(|attr| meta.get(*attr))
+                    .find_map(|name| meta_kind(name))?;
+
+                let time = parse_date(meta.get("content")?)?;
+
+                Some(DateCandidate {
+                    time,
+                    kind,
+                    source: DateSource::MetaTag,
+                })
+            })
+            .collect()
+    }
+
+    fn schema_org_date_candidates(&self) -> Vec<DateCandidate> {
+        let mut res = Vec::new();
+
+        for item in self.schema_org() {
+            for (keys, kind) in [
+                (&PUBLISHED_SCHEMA_ORG[..], DateKind::Published),
+                (&MODIFIED_SCHEMA_ORG[..], DateKind::Modified),
+            ] {
+                for key in keys {
+                    if let Some(time) = item
+                        .properties
+                        .get(*key)
+                        .and_then(|prop| prop.clone().one())
+                        .and_then(|prop| prop.try_into_string())
+                        .and_then(|s| parse_date(&s))
+                    {
+                        res.push(DateCandidate {
+                            time,
+                            kind,
+                            source: DateSource::StructuredData,
+                        });
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    fn visible_date_candidates(&self) -> Vec<DateCandidate> {
+        let mut res = Vec::new();
+
+        for node in self.root.select("time").unwrap() {
+            let attributes = node.attributes.borrow();
+
+            let Some(time) = attributes.get("datetime").and_then(parse_date) else {
+                continue;
+            };
+
+            let hints = [attributes.get("class"), attributes.get("itemprop")]
+                .into_iter()
+                .flatten()
+                .map(|s| s.to_ascii_lowercase())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let kind = if hints.contains("modif") || hints.contains("updat") {
+                DateKind::Modified
+            } else {
+                DateKind::Published
+            };
+
+            res.push(DateCandidate {
+                time,
+                kind,
+                source: DateSource::Visible,
+            });
+        }
+
+        res
+    }
+
+    fn byline_date_candidates(&self) -> Vec<DateCandidate> {
+        let mut res = Vec::new();
+
+        for node in self.root.select(BYLINE_SELECTOR).unwrap() {
+            let attributes = node.attributes.borrow();
+            let hints = attributes
+                .get("class")
+                .map(|s| s.to_ascii_lowercase())
+                .unwrap_or_default();
+
+            let text = node.text_contents();
+
+            if text.len() > MAX_BYLINE_LEN {
+                continue;
+            }
+
+            for (time, kind) in byline_dates(&text) {
+                let kind = if hints.contains("modif") || hints.contains("updat") {
+                    DateKind::Modified
+                } else {
+                    kind
+                };
+
+                res.push(DateCandidate {
+                    time,
+                    kind,
+                    source: DateSource::Visible,
+                });
+            }
+        }
+
+        res
+    }
+
+    pub fn date_candidates(&self) -> Vec<DateCandidate> {
+        let mut res = self.meta_date_candidates();
+        res.extend(self.schema_org_date_candidates());
+        res.extend(self.visible_date_candidates());
+        res.extend(self.byline_date_candidates());
+
+        if let Some(time) = self.http_last_modified {
+            res.push(DateCandidate {
+                time,
+                kind: DateKind::Modified,
+                source: DateSource::HttpLastModified,
+            });
+        }
+
+        if let Some(time) = self.sitemap_lastmod {
+            res.push(DateCandidate {
+                time,
+                kind: DateKind::Modified,
+                source: DateSource::SitemapLastmod,
+            });
+        }
+
+        res
+    }
+
+    pub fn timestamps(&self) -> PageTimestamps {
+        PageTimestamps::reconcile(&self.date_candidates(), Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(
+            parse_date("2022-06-22T19:37:34+00:00"),
+            Some(date("2022-06-22T19:37:34+00:00"))
+        );
+        assert_eq!(
+            parse_date("Wed, 22 Jun 2022 19:37:34 GMT"),
+            Some(date("2022-06-22T19:37:34+00:00"))
+        );
+        assert_eq!(
+            parse_date("2022-06-22"),
+            Some(date("2022-06-22T00:00:00+00:00"))
+        );
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn parse_text_formats() {
+        let expected = Some(date("2021-03-03T00:00:00+00:00"));
+        let parse_text_date =
+            |text: &str| byline_dates(text).into_iter().next().map(|(date, _)| date);
+
+        assert_eq!(parse_text_date("Published on March 3, 2021"), expected);
+        assert_eq!(parse_text_date("posted 3rd Mar. 2021 by john"), expected);
+        assert_eq!(parse_text_date("3 march 2021"), expected);
+        assert_eq!(parse_text_date("Sept 3, 2021").map(|d| d.month()), Some(9));
+        assert_eq!(parse_text_date("on 2021-03-03"), expected);
+        assert_eq!(parse_text_date("Maybe 3, 2021"), None);
+        assert_eq!(parse_text_date("February 30, 2021"), None);
+    }
+
+    #[test]
+    fn byline() {
+        let html = r#"
+    <html>
+        <body>
+            <div class="article-byline">
+                By Jane Doe. Published March 3, 2021. Updated 5th March 2021
+            </div>
+            <p>Some article text mentioning 1 January 2000.</p>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com").unwrap();
+
+        let timestamps = html.timestamps();
+
+        assert_eq!(
+            timestamps.published,
+            Some(date("2021-03-03T00:00:00+00:00"))
+        );
+        assert_eq!(timestamps.modified, Some(date("2021-03-05T00:00:00+00:00")));
+        assert_eq!(timestamps.confidence, 0.6);
+    }
+
+    #[test]
+    fn meta_beats_byline() {
+        let html = r#"
+    <html>
+        <head>
+            <meta property="og:published_time" content="2021-01-01T10:00:00+00:00" />
+        </head>
+        <body>
+            <span class="post-date">Jan 4, 2021</span>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com").unwrap();
+
+        assert_eq!(
+            html.timestamps().published,
+            Some(date("2021-01-01T10:00:00+00:00"))
+        );
+    }
+
+    #[test]
+    fn structured_data_beats_http_header() {
+        let html = r#"
+    <html>
+        <head>
+            <script type="application/ld+json">
+            {
+                "@context": "https://schema.org",
+                "@type": "NewsArticle",
+                "datePublished": "2021-01-01T10:00:00+00:00",
+                "dateModified": "2021-02-01T10:00:00+00:00"
+            }
+            </script>
+        </head>
+        <body>
+        </body>
+    </html>
+        "#;
+        let mut html = Html::parse(html, "https://example.com").unwrap();
+        html.set_http_last_modified("Wed, 22 Jun 2022 19:37:34 GMT");
+
+        let timestamps = html.timestamps();
+
+        assert_eq!(
+            timestamps.published,
+            Some(date("2021-01-01T10:00:00+00:00"))
+        );
+        assert_eq!(timestamps.modified, Some(date("2021-02-01T10:00:00+00:00")));
+        assert_eq!(timestamps.confidence, 0.9);
+    }
+
+    #[test]
+    fn agreeing_sources_increase_confidence() {
+        let html = r#"
+    <html>
+        <head>
+            <meta property="article:published_time" content="2021-01-01T10:00:00+00:00" />
+        </head>
+        <body>
+            <time datetime="2021-01-01">January 1st</time>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com").unwrap();
+
+        let timestamps = html.timestamps();
+
+        assert_eq!(
+            timestamps.published,
+            Some(date("2021-01-01T10:00:00+00:00"))
+        );
+        assert_eq!(timestamps.modified, None);
+        assert!((timestamps.confidence - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn modified_before_published_is_ignored() {
+        let candidates = vec![
+            DateCandidate {
+                time: date("2021-01-01T10:00:00+00:00"),
+                kind: DateKind::Published,
+                source: DateSource::MetaTag,
+            },
+            DateCandidate {
+                time: date("2020-01-01T10:00:00+00:00"),
+                kind: DateKind::Modified,
+                source: DateSource::MetaTag,
+            },
+            DateCandidate {
+                time: date("2122-01-01T10:00:00+00:00"),
+                kind: DateKind::Modified,
+                source: DateSource::StructuredData,
+            },
+        ];
+
+        let timestamps = PageTimestamps::reconcile(&candidates, Utc::now());
+
+        assert_eq!(
+            timestamps.freshest(),
+            Some(date("2021-01-01T10:00:00+00:00"))
+        );
+        assert_eq!(timestamps.modified, None);
+        assert_eq!(timestamps.confidence, 0.4);
+    }
+
+    #[test]
+    fn sitemap_lastmod_as_prior() {
+        let mut html = Html::parse("<html></html>", "https://example.com").unwrap();
+        html.set_http_last_modified("Wed, 22 Jun 2022 19:37:34 GMT");
+        html.set_sitemap_lastmod("2021-03-01T10:00:00+00:00");
+
+        let timestamps = html.timestamps();
+
+        assert_eq!(timestamps.modified, Some(date("2021-03-01T10:00:00+00:00")));
+        assert_eq!(timestamps.confidence, 0.5);
+    }
+
+    #[test]
+    fn no_dates() {
+        let html = Html::parse("<html></html>", "https://example.com").unwrap();
+
+        assert_eq!(html.timestamps(), PageTimestamps::default());
+    }
+}
@@ -261,6 +261,14 @@ struct ClassifiedParagraph {
 
 impl JustText {
     pub fn paragraphs(root: NodeRef) -> Vec<Paragraph> {
+        Self::paragraphs_without(root, |_| false)
+    }
+
+    /// Paragraphs of the tree, leaving out the subtrees of the nodes where `skip` is true.
+    pub fn paragraphs_without<F>(root: NodeRef, skip: F) -> Vec<Paragraph>
+    where
+        F: Fn(&NodeRef) -> bool,
+    {
         let mut res = Vec::new();
 
         let mut preprocessor =
@@ -271,8 +279,28 @@ impl JustText {
         let mut paragraph = Paragraph::new();
 
         let mut heading_count = 0;
+        let mut skipped: Option<NodeRef> = None;
 
         for edge in root.traverse() {
+            match (&skipped, &edge) {
+                (Some(skipped_node), NodeEdge::End(node)) if skipped_node == node => {
+                    skipped = None;
+                    continue;
+                }
+                (Some(_), _) => continue,
+                (None, NodeEdge::Start(node)) if skip(node) => {
+                    // the skipped subtree separates the text around it
+                    if paragraph.contains_text() {
+                        res.push(paragraph);
+                    }
+
+                    paragraph = Paragraph::new();
+                    skipped = Some(node.clone());
+                    continue;
+                }
+                _ => {}
+            }
+
             preprocessor.update(&edge);
             if preprocessor.is_inside_removed() {
                 continue;
@@ -0,0 +1,269 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ids of the detected languages as stored in the language fast field.
+
+use whatlang::Lang;
+
+/// Id of pages where the language could not be detected.
+pub const UNKNOWN_LANG_ID: u64 = 0;
+
+/// The ids are stored in the index, so they must never change. New languages
+/// of whatlang must be given a new id instead of taking the id of another language.
+fn lang_id(lang: Lang) -> u64 {
+    match lang {
+        Lang::Epo => 1,
+        Lang::Eng => 2,
+        Lang::Rus => 3,
+        Lang::Cmn => 4,
+        Lang::Spa => 5,
+        Lang::Por => 6,
+        Lang::Ita => 7,
+        Lang::Ben => 8,
+        Lang::Fra => 9,
+        Lang::Deu => 10,
+        Lang::Ukr => 11,
+        Lang::Kat => 12,
+        Lang::Ara => 13,
+        Lang::Hin => 14,
+        Lang::Jpn => 15,
+        Lang::Heb => 16,
+        Lang::Yid => 17,
+        Lang::Pol => 18,
+        Lang::Amh => 19,
+        Lang::Jav => 20,
+        Lang::Kor => 21,
+        Lang::Nob => 22,
+        Lang::Dan => 23,
+        Lang::Swe => 24,
+        Lang::Fin => 25,
+        Lang::Tur => 26,
+        Lang::Nld => 27,
+        Lang::Hun => 28,
+        Lang::Ces => 29,
+        Lang::Ell => 30,
+        Lang::Bul => 31,
+        Lang::Bel => 32,
+        Lang::Mar => 33,
+        Lang::Kan => 34,
+        Lang::Ron => 35,
+        Lang::Slv => 36,
+        Lang::Hrv => 37,
+        Lang::Srp => 38,
+        Lang::Mkd => 39,
+        Lang::Lit => 40,
+        Lang::Lav => 41,
+        Lang::Est => 42,
+        Lang::Tam => 43,
+        Lang::Vie => 44,
+        Lang::Urd => 45,
+        Lang::Tha => 46,
+        Lang::Guj => 47,
+        Lang::Uzb => 48,
+        Lang::Pan => 49,
+        Lang::Aze => 50,
+        Lang::Ind => 51,
+        Lang::Tel => 52,
+        Lang::Pes => 53,
+        Lang::Mal => 54,
+        Lang::Ori => 55,
+        Lang::Mya => 56,
+        Lang::Nep => 57,
+        Lang::Sin => 58,
+        Lang::Khm => 59,
+        Lang::Tuk => 60,
+        Lang::Aka => 61,
+        Lang::Zul => 62,
+        Lang::Sna => 63,
+        Lang::Afr => 64,
+        Lang::Lat => 65,
+        Lang::Slk => 66,
+        Lang::Cat => 67,
+        Lang::Tgl => 68,
+        Lang::Hye => 69,
+    }
+}
+
+pub fn id(lang: Option<&Lang>) -> u64 {
+    lang.map_or(UNKNOWN_LANG_ID, |lang| lang_id(*lang))
+}
+
+pub fn from_id(id: u64) -> Option<Lang> {
+    if id == UNKNOWN_LANG_ID {
+        return None;
+    }
+
+    Lang::all()
+        .iter()
+        .copied()
+        .find(|lang| lang_id(*lang) == id)
+}
+
+/// The language with the ISO 639-1 or ISO 639-3 code, or the english name.
+pub fn from_code(code: &str) -> Option<Lang> {
+    let code = code.trim().to_ascii_lowercase();
+
+    if code.len() == 2 {
+        return from_iso_639_1(&code);
+    }
+
+    Lang::from_code(&code).or_else(|| {
+        Lang::all()
+            .iter()
+            .find(|lang| lang.eng_name().eq_ignore_ascii_case(&code))
+            .copied()
+    })
+}
+
+fn from_iso_639_1(code: &str) -> Option<Lang> {
+    match code {
+        "eo" => Some(Lang::Epo),
+        "en" => Some(Lang::Eng),
+        "ru" => Some(Lang::Rus),
+        "zh" => Some(Lang::Cmn),
+        "es" => Some(Lang::Spa),
+        "pt" => Some(Lang::Por),
+        "it" => Some(Lang::Ita),
+        "bn" => Some(Lang::Ben),
+        "fr" => Some(Lang::Fra),
+        "de" => Some(Lang::Deu),
+        "uk" => Some(Lang::Ukr),
+        "ka" => Some(Lang::Kat),
+        "ar" => Some(Lang::Ara),
+        "hi" => Some(Lang::Hin),
+        "ja" => Some(Lang::Jpn),
+        "he" => Some(Lang::Heb),
+        "yi" => Some(Lang::Yid),
+        "pl" => Some(Lang::Pol),
+        "am" => Some(Lang::Amh),
+        "jv" => Some(Lang::Jav),
+        "ko" => Some(Lang::Kor),
+        "nb" | "no" => Some(Lang::Nob),
+        "da" => Some(Lang::Dan),
+        "sv" => Some(Lang::Swe),
+        "fi" => Some(Lang::Fin),
+        "tr" => Some(Lang::Tur),
+        "nl" => Some(Lang::Nld),
+        "hu" => Some(Lang::Hun),
+        "cs" => Some(Lang::Ces),
+        "el" => Some(Lang::Ell),
+        "bg" => Some(Lang::Bul),
+        "be" => Some(Lang::Bel),
+        "mr" => Some(Lang::Mar),
+        "kn" => Some(Lang::Kan),
+        "ro" => Some(Lang::Ron),
+        "sl" => Some(Lang::Slv),
+        "hr" => Some(Lang::Hrv),
+        "sr" => Some(Lang::Srp),
+        "mk" => Some(Lang::Mkd),
+        "lt" => Some(Lang::Lit),
+        "lv" => Some(Lang::Lav),
+        "et" => Some(Lang::Est),
+        "ta" => Some(Lang::Tam),
+        "vi" => Some(Lang::Vie),
+        "ur" => Some(Lang::Urd),
+        "th" => Some(Lang::Tha),
+        "gu" => Some(Lang::Guj),
+        "uz" => Some(Lang::Uzb),
+        "pa" => Some(Lang::Pan),
+        "az" => Some(Lang::Aze),
+        "id" => Some(Lang::Ind),
+        "te" => Some(Lang::Tel),
+        "fa" => Some(Lang::Pes),
+        "ml" => Some(Lang::Mal),
+        "or" => Some(Lang::Ori),
+        "my" => Some(Lang::Mya),
+        "ne" => Some(Lang::Nep),
+        "si" => Some(Lang::Sin),
+        "km" => Some(Lang::Khm),
+        "tk" => Some(Lang::Tuk),
+        "ak" => Some(Lang::Aka),
+        "zu" => Some(Lang::Zul),
+        "sn" => Some(Lang::Sna),
+        "af" => Some(Lang::Afr),
+        "la" => Some(Lang::Lat),
+        "sk" => Some(Lang::Slk),
+        "ca" => Some(Lang::Cat),
+        "tl" => Some(Lang::Tgl),
+        "hy" => Some(Lang::Hye),
+        _ => None,
+    }
+}
+
+/// Language of the query. Short queries are often valid in multiple languages,
+/// so the language is only returned if the detection is reliable.
+pub fn detect_query(query: &str) -> Option<Lang> {
+    whatlang::detect(query)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_roundtrip() {
+        for lang in Lang::all() {
+            let id = id(Some(lang));
+
+            assert_ne!(id, UNKNOWN_LANG_ID);
+            assert_eq!(from_id(id), Some(*lang));
+        }
+
+        assert_eq!(id(None), UNKNOWN_LANG_ID);
+        assert_eq!(from_id(UNKNOWN_LANG_ID), None);
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        let ids: std::collections::HashSet<_> =
+            Lang::all().iter().map(|lang| id(Some(lang))).collect();
+        assert_eq!(ids.len(), Lang::all().len());
+
+        assert_eq!(id(Some(&Lang::Eng)), 2);
+        assert_eq!(id(Some(&Lang::Deu)), 10);
+        assert_eq!(id(Some(&Lang::Hye)), 69);
+        assert_eq!(from_id(1000), None);
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(from_code("de"), Some(Lang::Deu));
+        assert_eq!(from_code("deu"), Some(Lang::Deu));
+        assert_eq!(from_code("German"), Some(Lang::Deu));
+        assert_eq!(from_code("nb"), Some(Lang::Nob));
+        assert_eq!(from_code("no"), Some(Lang::Nob));
+        assert_eq!(from_code("xx"), None);
+        assert_eq!(from_code("klingon"), None);
+
+        for lang in Lang::all() {
+            assert_eq!(from_code(lang.code()), Some(*lang));
+        }
+    }
+
+    #[test]
+    fn query_language() {
+        assert_eq!(
+            detect_query("what is the best way to learn how to play the guitar"),
+            Some(Lang::Eng)
+        );
+        assert_eq!(
+            detect_query("wie kann ich am besten lernen gitarre zu spielen"),
+            Some(Lang::Deu)
+        );
+    }
+}
@@ -27,16 +27,20 @@ use url::Url;
 
 use crate::schema::{Field, FLOAT_SCALING};
 
-use self::region::Region;
+use self::{protocol::ProtocolInfo, region::RegionSet};
 
 mod adservers;
+pub mod hreflang;
 mod html;
 mod just_text;
+pub mod language;
+pub mod open_graph;
+pub mod protocol;
 pub mod region;
 pub mod safety_classifier;
 pub mod schema_org;
 pub mod url_ext;
-pub use self::html::Html;
+pub use self::html::{parse_date, Html, ACCESSIBLE_THRESHOLD};
 
 #[derive(Debug)]
 pub struct Webpage {
@@ -44,6 +48,10 @@ pub struct Webpage {
     pub backlink_labels: Vec<String>,
     pub host_centrality: f64,
     pub host_centrality_rank: f64,
+    /// TrustRank of the host in `[0, 1]`.
+    pub host_trust: f64,
+    /// Likelihood in `[0, 1]` that the host is part of a link farm.
+    pub host_link_spam: f64,
     pub page_centrality: f64,
     pub page_centrality_rank: f64,
     pub fetch_time_ms: u64,
@@ -52,6 +60,10 @@ pub struct Webpage {
     pub dmoz_description: Option<String>,
     pub safety_classification: Option<safety_classifier::Label>,
     pub inserted_at: DateTime<Utc>,
+    /// Dense embedding of the page used for semantic retrieval.
+    pub embedding: Option<Vec<f32>>,
+    /// How the page was served when it was crawled.
+    pub protocol: ProtocolInfo,
 }
 
 #[cfg(test)]
@@ -62,6 +74,8 @@ impl Default for Webpage {
             backlink_labels: Default::default(),
             host_centrality: Default::default(),
             host_centrality_rank: u64::MAX as f64,
+            host_trust: Default::default(),
+            host_link_spam: Default::default(),
             page_centrality: Default::default(),
             page_centrality_rank: u64::MAX as f64,
             fetch_time_ms: Default::default(),
@@ -70,6 +84,8 @@ impl Default for Webpage {
             dmoz_description: Default::default(),
             safety_classification: Default::default(),
             inserted_at: Utc::now(),
+            embedding: None,
+            protocol: ProtocolInfo::default(),
         }
     }
 }
@@ -102,27 +118,18 @@ impl Webpage {
     }
 
     pub fn into_tantivy(self, schema: &tantivy::schema::Schema) -> Result<TantivyDocument> {
-        let region = Region::guess_from(&self);
+        let regions = RegionSet::guess_from(&self);
 
         let dmoz_description = self.dmoz_description();
 
         let mut doc = self.html.into_tantivy(schema)?;
 
-        if let Ok(region) = region {
-            doc.add_u64(
-                schema
-                    .get_field(Field::Fast(FastField::Region).name())
-                    .expect("Failed to get region field"),
-                region.id(),
-            );
-        } else {
-            doc.add_u64(
-                schema
-                    .get_field(Field::Fast(FastField::Region).name())
-                    .expect("Failed to get region field"),
-                Region::All.id(),
-            );
-        }
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::Regions).name())
+                .expect("Failed to get regions field"),
+            regions.bits(),
+        );
 
         let backlink_text: String =
             itertools::intersperse(self.backlink_labels, "\n".to_string()).collect();
@@ -169,6 +176,20 @@ impl Webpage {
             self.host_centrality_rank as u64,
         );
 
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::HostTrust).name())
+                .expect("Failed to get host_trust field"),
+            (self.host_trust * FLOAT_SCALING as f64) as u64,
+        );
+
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::HostLinkSpam).name())
+                .expect("Failed to get host_link_spam field"),
+            (self.host_link_spam * FLOAT_SCALING as f64) as u64,
+        );
+
         doc.add_u64(
             schema
                 .get_field(Field::Fast(FastField::PageCentrality).name())
@@ -190,6 +211,13 @@ impl Webpage {
             self.fetch_time_ms,
         );
 
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::TlsStatus).name())
+                .expect("Failed to get tls_status field"),
+            self.protocol.tls.as_u64(),
+        );
+
         doc.add_u64(
             schema
                 .get_field(Field::Fast(FastField::PreComputedScore).name())
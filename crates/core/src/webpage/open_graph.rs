@@ -0,0 +1,40 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The [Open Graph](https://ogp.me/) metadata of a page. Sites set these
+//! tags to control how their pages look when shared, so they are usually
+//! better suited for display than what we can extract from the page itself.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenGraph {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute url of the image.
+    pub image: Option<String>,
+    /// The `og:type` of the page, e.g. `article` or `video.movie`.
+    pub kind: Option<String>,
+}
+
+impl OpenGraph {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.description.is_none()
+            && self.image.is_none()
+            && self.kind.is_none()
+    }
+}
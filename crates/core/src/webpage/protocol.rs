@@ -0,0 +1,265 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Protocol level metadata of a fetched page, i.e. how the page was served
+//! rather than what it contains. The crawler records it for every page so
+//! hosts that are not served securely can be demoted.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub enum TlsStatus {
+    /// The page was fetched before the status was recorded.
+    #[default]
+    Unknown,
+    /// The page was only reachable over plain http.
+    None,
+    /// The host offers https, but the handshake failed (e.g. an expired or
+    /// self-signed certificate), so the page was fetched over plain http.
+    Invalid,
+    /// The page was served over https with a certificate that verified.
+    Valid,
+}
+
+impl TlsStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsStatus::Unknown => "unknown",
+            TlsStatus::None => "none",
+            TlsStatus::Invalid => "invalid",
+            TlsStatus::Valid => "valid",
+        }
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        match self {
+            TlsStatus::Unknown => 0,
+            TlsStatus::None => 1,
+            TlsStatus::Invalid => 2,
+            TlsStatus::Valid => 3,
+        }
+    }
+
+    pub fn from_u64(id: u64) -> Self {
+        match id {
+            1 => TlsStatus::None,
+            2 => TlsStatus::Invalid,
+            3 => TlsStatus::Valid,
+            _ => TlsStatus::Unknown,
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        matches!(self, TlsStatus::Valid)
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        matches!(self, TlsStatus::None | TlsStatus::Invalid)
+    }
+}
+
+impl std::str::FromStr for TlsStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unknown" => Ok(TlsStatus::Unknown),
+            "none" => Ok(TlsStatus::None),
+            "invalid" => Ok(TlsStatus::Invalid),
+            "valid" => Ok(TlsStatus::Valid),
+            _ => Err(anyhow::anyhow!("unknown tls status: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub struct ProtocolInfo {
+    /// E.g. `HTTP/1.1` or `HTTP/2.0`.
+    #[cfg_attr(
+        test,
+        proptest(strategy = "proptest::option::of(\"HTTP/[0-9][.][0-9]\")")
+    )]
+    pub http_version: Option<String>,
+    pub tls: TlsStatus,
+    /// Organization (or common name if there is no organization) of the
+    /// issuer of the certificate.
+    #[cfg_attr(
+        test,
+        proptest(strategy = "proptest::option::of(\"[A-Za-z0-9.-]+( [A-Za-z0-9.-]+)*\")")
+    )]
+    pub tls_issuer: Option<String>,
+    /// The page was requested over plain http and the server redirected to https.
+    pub redirected_to_https: bool,
+}
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0a];
+
+/// Split a DER encoded value into its tag, content and the remaining bytes.
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let len = *data.get(1)? as usize;
+
+    let (len, header) = if len < 0x80 {
+        (len, 2)
+    } else {
+        let num_bytes = len & 0x7f;
+        if num_bytes == 0 || num_bytes > 4 {
+            return None;
+        }
+
+        let bytes = data.get(2..2 + num_bytes)?;
+        let len = bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+
+        (len, 2 + num_bytes)
+    };
+
+    let content = data.get(header..header + len)?;
+    let rest = &data[header + len..];
+
+    Some((tag, content, rest))
+}
+
+/// Name of the issuer of a DER encoded x509 certificate.
+pub fn certificate_issuer(der: &[u8]) -> Option<String> {
+    let (_, certificate, _) = read_tlv(der)?;
+    let (_, tbs, _) = read_tlv(certificate)?;
+
+    let (tag, _, mut rest) = read_tlv(tbs)?;
+    if tag == 0xa0 {
+        // the explicit version was read, so skip the serial number as well
+        (_, _, rest) = read_tlv(rest)?;
+    }
+    let (_, _, rest) = read_tlv(rest)?; // signature algorithm
+    let (tag, mut name, _) = read_tlv(rest)?;
+
+    if tag != 0x30 {
+        return None;
+    }
+
+    let mut organization = None;
+    let mut common_name = None;
+
+    while !name.is_empty() {
+        let (_, mut set, rest) = read_tlv(name)?;
+        name = rest;
+
+        while !set.is_empty() {
+            let (_, attribute, rest) = read_tlv(set)?;
+            set = rest;
+
+            let (_, oid, value) = read_tlv(attribute)?;
+            let (tag, value, _) = read_tlv(value)?;
+
+            // utf8, printable, teletex and ia5 strings
+            if !matches!(tag, 0x0c | 0x13 | 0x14 | 0x16) {
+                continue;
+            }
+
+            let value = String::from_utf8_lossy(value).trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+
+            if oid == OID_ORGANIZATION {
+                organization.get_or_insert(value);
+            } else if oid == OID_COMMON_NAME {
+                common_name.get_or_insert(value);
+            }
+        }
+    }
+
+    organization.or(common_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut res = vec![tag];
+
+        if content.len() < 0x80 {
+            res.push(content.len() as u8);
+        } else {
+            res.push(0x82);
+            res.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+
+        res.extend_from_slice(content);
+        res
+    }
+
+    fn attribute(oid: &[u8], value: &str) -> Vec<u8> {
+        let attribute = [tlv(0x06, oid), tlv(0x13, value.as_bytes())].concat();
+        tlv(0x31, &tlv(0x30, &attribute))
+    }
+
+    fn certificate(issuer: &[Vec<u8>]) -> Vec<u8> {
+        let tbs = [
+            tlv(0xa0, &tlv(0x02, &[2])),
+            tlv(0x02, &[0x12, 0x34]),
+            tlv(0x30, &tlv(0x06, &[0x2a, 0x86, 0x48])),
+            tlv(0x30, &issuer.concat()),
+            tlv(0x30, &[0; 200]),
+        ]
+        .concat();
+
+        tlv(0x30, &[tlv(0x30, &tbs), tlv(0x03, &[0; 16])].concat())
+    }
+
+    #[test]
+    fn issuer_prefers_organization() {
+        let der = certificate(&[
+            attribute(&[0x55, 0x04, 0x06], "US"),
+            attribute(OID_ORGANIZATION, "Let's Encrypt"),
+            attribute(OID_COMMON_NAME, "R3"),
+        ]);
+
+        assert_eq!(certificate_issuer(&der), Some("Let's Encrypt".to_string()));
+    }
+
+    #[test]
+    fn issuer_falls_back_to_common_name() {
+        let der = certificate(&[attribute(OID_COMMON_NAME, "localhost")]);
+
+        assert_eq!(certificate_issuer(&der), Some("localhost".to_string()));
+    }
+
+    #[test]
+    fn malformed_certificate() {
+        assert_eq!(certificate_issuer(&[]), None);
+        assert_eq!(certificate_issuer(&[0x30, 0x82, 0xff]), None);
+
+        let der = certificate(&[attribute(OID_ORGANIZATION, "test")]);
+        assert_eq!(certificate_issuer(&der[..der.len() / 2]), None);
+    }
+
+    #[test]
+    fn tls_status_round_trip() {
+        for status in [
+            TlsStatus::Unknown,
+            TlsStatus::None,
+            TlsStatus::Invalid,
+            TlsStatus::Valid,
+        ] {
+            assert_eq!(TlsStatus::from_u64(status.as_u64()), status);
+            assert_eq!(status.as_str().parse::<TlsStatus>().unwrap(), status);
+        }
+    }
+}
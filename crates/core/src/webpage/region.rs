@@ -21,7 +21,7 @@ use utoipa::ToSchema;
 
 use crate::{Error, Result};
 
-use super::Webpage;
+use super::{url_ext::UrlExt, Webpage};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default, ToSchema)]
 pub enum Region {
@@ -107,6 +107,18 @@ impl Region {
         }
     }
 
+    /// Region of a country code top level domain, e.g. `dk` or `co.dk`.
+    pub fn from_tld(tld: &str) -> Option<Self> {
+        match tld.rsplit('.').next()? {
+            "dk" => Some(Region::Denmark),
+            "fr" => Some(Region::France),
+            "de" => Some(Region::Germany),
+            "es" => Some(Region::Spain),
+            "us" => Some(Region::US),
+            _ => None,
+        }
+    }
+
     pub fn from_id(doc: u64) -> Self {
         ALL_REGIONS[doc as usize]
     }
@@ -123,6 +135,81 @@ impl Region {
     }
 }
 
+/// The regions a page is relevant to. The set is stored as a bitset of the
+/// region ids, so all the regions of a page fit in a single fast field.
+/// An empty set means that the page is not specific to any region.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, Default)]
+pub struct RegionSet(u64);
+
+impl RegionSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Insert the region into the set. [`Region::All`] is not a region
+    /// in itself, so it is ignored.
+    pub fn insert(&mut self, region: Region) {
+        if region != Region::All {
+            self.0 |= 1 << region.id();
+        }
+    }
+
+    pub fn contains(&self, region: &Region) -> bool {
+        *region != Region::All && self.0 & (1 << region.id()) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn intersects(&self, other: &RegionSet) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Region> + '_ {
+        ALL_REGIONS
+            .iter()
+            .filter(|region| self.contains(region))
+            .copied()
+    }
+
+    /// Guess the regions of the page from its language and
+    /// the country code top level domain of its url.
+    pub fn guess_from(webpage: &Webpage) -> Self {
+        let mut regions = Self::new();
+
+        if let Ok(region) = Region::guess_from(webpage) {
+            regions.insert(region);
+        }
+
+        if let Some(region) = webpage.html.url().tld().and_then(Region::from_tld) {
+            regions.insert(region);
+        }
+
+        regions
+    }
+}
+
+impl FromIterator<Region> for RegionSet {
+    fn from_iter<T: IntoIterator<Item = Region>>(iter: T) -> Self {
+        let mut regions = Self::new();
+
+        for region in iter {
+            regions.insert(region);
+        }
+
+        regions
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct RegionCount {
     map: HashMap<Region, u64>,
@@ -211,6 +298,14 @@ impl RegionCount {
             })
             .unwrap_or(0.0)
     }
+
+    /// The score of the most common region in the set.
+    pub fn score_set(&self, regions: &RegionSet) -> f64 {
+        regions
+            .iter()
+            .map(|region| self.score(&region))
+            .fold(0.0, f64::max)
+    }
 }
 
 #[cfg(test)]
@@ -241,4 +336,56 @@ mod tests {
         assert_eq!(a.score(&Region::Denmark), 0.4);
         assert_eq!(a.score(&Region::France), 0.0);
     }
+
+    #[test]
+    fn region_set() {
+        let mut regions = RegionSet::new();
+        assert!(regions.is_empty());
+
+        regions.insert(Region::Denmark);
+        regions.insert(Region::US);
+        regions.insert(Region::All);
+
+        assert!(regions.contains(&Region::Denmark));
+        assert!(regions.contains(&Region::US));
+        assert!(!regions.contains(&Region::France));
+        assert!(!regions.contains(&Region::All));
+        assert_eq!(
+            regions.iter().collect::<Vec<_>>(),
+            vec![Region::Denmark, Region::US]
+        );
+
+        assert_eq!(RegionSet::from_bits(regions.bits()), regions);
+        assert!(regions.intersects(&[Region::US].into_iter().collect()));
+        assert!(!regions.intersects(&[Region::Spain].into_iter().collect()));
+    }
+
+    #[test]
+    fn guess_multiple_regions() {
+        let webpage = Webpage {
+            html: crate::webpage::Html::parse(
+                "<html><head><title>Test</title></head><body>This is an english page that is hosted on a danish domain, so it is relevant to both regions.</body></html>",
+                "https://www.example.dk",
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            RegionSet::guess_from(&webpage).iter().collect::<Vec<_>>(),
+            vec![Region::Denmark, Region::US]
+        );
+
+        let mut count = RegionCount::open(gen_temp_path().join("region_count.json"));
+        count.increment(&Region::Denmark);
+        count.increment(&Region::US);
+        count.increment(&Region::US);
+        count.commit();
+
+        assert_eq!(
+            count.score_set(&RegionSet::guess_from(&webpage)),
+            count.score(&Region::US)
+        );
+        assert_eq!(count.score_set(&RegionSet::new()), 0.0);
+    }
 }
@@ -23,7 +23,7 @@ pub fn convert_all_to_strings(json: &str) -> Result<String, serde_json::Error> {
 
     fn convert_recursively(json: &mut Value) {
         match json {
-            Value::Number(n) if n.is_u64() || n.is_i64() => {
+            Value::Number(n) => {
                 *json = Value::String(n.to_string());
             }
             Value::Bool(b) => {
@@ -157,7 +157,8 @@ mod tests {
                 {
                 "@context": "https://schema.org",
                 "@type": "test",
-                "cost": 123
+                "cost": 123,
+                "rating": 4.5
                 }
             </script>
         </head>
@@ -178,6 +179,7 @@ mod tests {
                 properties: hashmap! {
                     "@context".to_string() => RawOneOrMany::One(RawProperty::String("https://schema.org".to_string())),
                     "cost".to_string() => RawOneOrMany::One(RawProperty::String("123".to_string())),
+                    "rating".to_string() => RawOneOrMany::One(RawProperty::String("4.5".to_string())),
                 }
             }]
         );
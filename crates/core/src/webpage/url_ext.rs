@@ -28,6 +28,47 @@ static ICANN_LIST: once_cell::sync::Lazy<publicsuffix::List> = once_cell::sync::
         .expect("Failed to parse public icann suffix list")
 });
 
+/// Query parameters that only track where a visitor came from
+/// and never change the content of the page.
+const TRACKING_QUERY_PARAMS: [&str; 10] = [
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "mc_cid", "mc_eid", "igshid", "_ga", "_gl",
+];
+
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_") || TRACKING_QUERY_PARAMS.contains(&key)
+}
+
+/// Decode percent-encoded unreserved characters and uppercase the hex digits
+/// of the remaining escapes (RFC 3986 section 6.2.2).
+fn normalize_percent_encoding(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                    res.push(b);
+                } else {
+                    res.extend(format!("%{b:02X}").bytes());
+                }
+
+                i += 3;
+                continue;
+            }
+        }
+
+        res.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(res).unwrap_or_else(|_| s.to_string())
+}
+
 pub trait UrlExt {
     fn icann_domain(&self) -> Option<&str>;
     fn root_domain(&self) -> Option<&str>;
@@ -35,6 +76,7 @@ pub trait UrlExt {
     fn subdomain(&self) -> Option<&str>;
     fn is_homepage(&self) -> bool;
     fn tld(&self) -> Option<&str>;
+    fn canonicalize(&self) -> url::Url;
 }
 
 impl UrlExt for url::Url {
@@ -77,6 +119,34 @@ impl UrlExt for url::Url {
         let suffix = std::str::from_utf8(ICANN_LIST.suffix(host.as_bytes())?.as_bytes()).ok()?;
         Some(suffix)
     }
+
+    /// Normalize the url so trivially different urls for the same page
+    /// become equal. The url parser already lowercases the host, removes
+    /// default ports and resolves dot segments, so this removes the fragment
+    /// and tracking parameters, sorts the query and normalizes the
+    /// percent-encoding of the path.
+    fn canonicalize(&self) -> url::Url {
+        let mut url = self.clone();
+        url.set_fragment(None);
+
+        let path = normalize_percent_encoding(url.path());
+        url.set_path(&path);
+
+        let mut queries: Vec<_> = url
+            .query_pairs()
+            .filter(|(key, _)| !is_tracking_param(key))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        queries.sort();
+
+        if queries.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(queries);
+        }
+
+        url
+    }
 }
 
 #[cfg(test)]
@@ -105,6 +175,32 @@ mod tests {
         assert_eq!(url.icann_domain().unwrap(), "blogspot.com");
     }
 
+    #[test]
+    fn canonicalize() {
+        let url = Url::parse("HTTP://Example.COM:80/a/./b/../c?utm_source=x&b=2&a=1#frag").unwrap();
+        assert_eq!(
+            url.canonicalize().as_str(),
+            "http://example.com/a/c?a=1&b=2"
+        );
+
+        let url = Url::parse("https://example.com/%7euser/%2fpath%3a").unwrap();
+        assert_eq!(
+            url.canonicalize().as_str(),
+            "https://example.com/~user/%2Fpath%3A"
+        );
+
+        let url = Url::parse("https://example.com/?fbclid=abc&utm_medium=a").unwrap();
+        assert_eq!(url.canonicalize().as_str(), "https://example.com/");
+
+        let url = Url::parse("https://example.com/search?q=rust%20lang&page=2").unwrap();
+        let canonical = url.canonicalize();
+        assert_eq!(
+            canonical.as_str(),
+            "https://example.com/search?page=2&q=rust+lang"
+        );
+        assert_eq!(canonical.canonicalize(), canonical);
+    }
+
     #[test]
     fn suffix() {
         let url: Url = Url::parse("http://example.blogspot.com").unwrap();
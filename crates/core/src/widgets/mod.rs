@@ -18,14 +18,18 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use utoipa::ToSchema;
 
+use self::stock::{Stock, StockWidget};
 use self::thesaurus::ThesaurusWidget;
+use self::weather::{Weather, WeatherWidget};
 use crate::config::WidgetsConfig;
 
 use self::calculator::{Calculation, Calculator};
 use anyhow::{anyhow, Result};
 
 pub mod calculator;
+pub mod stock;
 pub mod thesaurus;
+pub mod weather;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -36,6 +40,8 @@ pub enum Error {
 pub struct Widgets {
     calculator: Calculator,
     thesaurus: Option<thesaurus::Dictionary>,
+    weather: Option<Weather>,
+    stock: Option<Stock>,
 }
 
 impl Widgets {
@@ -59,13 +65,16 @@ impl Widgets {
         Ok(Self {
             calculator: Calculator::new(exchange_update),
             thesaurus,
+            weather: config.weather.map(Weather::new),
+            stock: config.stock.map(Stock::new),
         })
     }
 
-    pub fn widget(&self, query: &str) -> Option<Widget> {
+    pub async fn widget(&self, query: &str) -> Option<Widget> {
         let query = query.to_lowercase();
 
-        self.calculator
+        let widget = self
+            .calculator
             .try_calculate(&query)
             .ok()
             .map(Widget::Calculator)
@@ -74,7 +83,25 @@ impl Widgets {
                     .as_ref()
                     .and_then(|thesaurus| thesaurus.lookup(&query))
                     .map(Widget::Thesaurus)
-            })
+            });
+
+        if widget.is_some() {
+            return widget;
+        }
+
+        if let Some(weather) = &self.weather {
+            if let Some(widget) = weather.lookup(&query).await {
+                return Some(Widget::Weather(widget));
+            }
+        }
+
+        if let Some(stock) = &self.stock {
+            if let Some(widget) = stock.lookup(&query).await {
+                return Some(Widget::Stock(widget));
+            }
+        }
+
+        None
     }
 }
 
@@ -83,4 +110,6 @@ impl Widgets {
 pub enum Widget {
     Calculator(Calculation),
     Thesaurus(ThesaurusWidget),
+    Weather(WeatherWidget),
+    Stock(StockWidget),
 }
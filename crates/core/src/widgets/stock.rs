@@ -0,0 +1,317 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    config::{StockConfig, StockProviderConfig},
+    ttl_cache::TTLCache,
+};
+
+const MAX_CACHED_SYMBOLS: usize = 10_000;
+
+static STOCK_PREFIX_REGEX: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
+    regex::Regex::new(r"^(?:stock|stocks|ticker|share price)\s+\$?([a-z]{1,5}(?:\.[a-z]{1,2})?)$")
+        .unwrap()
+});
+
+static STOCK_SUFFIX_REGEX: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
+    regex::Regex::new(
+        r"^\$?([a-z]{1,5}(?:\.[a-z]{1,2})?)\s+(?:stock|stocks|ticker|share price|stock price)$",
+    )
+    .unwrap()
+});
+
+static CASHTAG_REGEX: once_cell::sync::Lazy<regex::Regex> =
+    once_cell::sync::Lazy::new(|| regex::Regex::new(r"^\$([a-z]{1,5}(?:\.[a-z]{1,2})?)$").unwrap());
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StockWidget {
+    pub symbol: String,
+    pub price: f64,
+    pub change: f64,
+    pub change_percent: f64,
+    pub currency: Option<String>,
+}
+
+/// A source of stock quotes. Self-hosters can implement this
+/// to use their own market data feed.
+pub trait StockProvider: Send + Sync {
+    fn quote<'a>(&'a self, symbol: &'a str) -> BoxFuture<'a, Result<StockWidget>>;
+}
+
+pub struct Stock {
+    provider: Box<dyn StockProvider>,
+    cache: Mutex<TTLCache<String, StockWidget>>,
+    /// Symbols the provider failed to find a quote for.
+    misses: Mutex<TTLCache<String, ()>>,
+}
+
+impl Stock {
+    pub fn new(config: StockConfig) -> Self {
+        let provider: Box<dyn StockProvider> = match config.provider {
+            StockProviderConfig::AlphaVantage { api_key, url } => {
+                Box::new(AlphaVantage::new(api_key, url))
+            }
+        };
+
+        Self::with_provider(
+            provider,
+            Duration::from_secs(config.cache_ttl_sec),
+            Duration::from_secs(config.negative_cache_ttl_sec),
+        )
+    }
+
+    pub fn with_provider(
+        provider: Box<dyn StockProvider>,
+        ttl: Duration,
+        negative_ttl: Duration,
+    ) -> Self {
+        Self {
+            provider,
+            cache: Mutex::new(TTLCache::with_ttl_and_max_size(
+                ttl,
+                Some(MAX_CACHED_SYMBOLS),
+            )),
+            misses: Mutex::new(TTLCache::with_ttl_and_max_size(
+                negative_ttl,
+                Some(MAX_CACHED_SYMBOLS),
+            )),
+        }
+    }
+
+    pub async fn lookup(&self, query: &str) -> Option<StockWidget> {
+        let symbol = parse_symbol(query)?;
+
+        if let Some(cached) = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&symbol)
+        {
+            return Some(cached.clone());
+        }
+
+        if self
+            .misses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&symbol)
+            .is_some()
+        {
+            return None;
+        }
+
+        match self.provider.quote(&symbol).await {
+            Ok(widget) => {
+                self.cache
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(symbol, widget.clone());
+
+                Some(widget)
+            }
+            Err(err) => {
+                tracing::debug!("failed to get quote for {symbol}: {err}");
+
+                self.misses
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(symbol, ());
+
+                None
+            }
+        }
+    }
+}
+
+/// Extract the ticker symbol from queries like "aapl stock", "stock aapl" or "$aapl".
+fn parse_symbol(query: &str) -> Option<String> {
+    let query = query.trim();
+
+    STOCK_PREFIX_REGEX
+        .captures(query)
+        .or_else(|| STOCK_SUFFIX_REGEX.captures(query))
+        .or_else(|| CASHTAG_REGEX.captures(query))
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().to_uppercase())
+}
+
+#[derive(Deserialize)]
+struct AlphaVantageResponse {
+    #[serde(rename = "Global Quote")]
+    global_quote: Option<AlphaVantageQuote>,
+}
+
+#[derive(Deserialize)]
+struct AlphaVantageQuote {
+    #[serde(rename = "01. symbol")]
+    symbol: String,
+    #[serde(rename = "05. price")]
+    price: String,
+    #[serde(rename = "09. change")]
+    change: String,
+    #[serde(rename = "10. change percent")]
+    change_percent: String,
+}
+
+/// Stock provider backed by <https://www.alphavantage.co>.
+pub struct AlphaVantage {
+    client: reqwest::Client,
+    api_key: String,
+    url: String,
+}
+
+impl AlphaVantage {
+    pub fn new(api_key: String, url: String) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap(),
+            api_key,
+            url,
+        }
+    }
+
+    async fn fetch(&self, symbol: &str) -> Result<StockWidget> {
+        let res: AlphaVantageResponse = self
+            .client
+            .get(&self.url)
+            .query(&[
+                ("function", "GLOBAL_QUOTE"),
+                ("symbol", symbol),
+                ("apikey", self.api_key.as_str()),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        let quote = res
+            .global_quote
+            .filter(|q| !q.symbol.is_empty())
+            .ok_or_else(|| anyhow!("unknown symbol: {symbol}"))?;
+
+        Ok(StockWidget {
+            symbol: quote.symbol,
+            price: quote.price.parse()?,
+            change: quote.change.parse()?,
+            change_percent: quote.change_percent.trim_end_matches('%').parse()?,
+            currency: None,
+        })
+    }
+}
+
+impl StockProvider for AlphaVantage {
+    fn quote<'a>(&'a self, symbol: &'a str) -> BoxFuture<'a, Result<StockWidget>> {
+        self.fetch(symbol).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl StockProvider for CountingProvider {
+        fn quote<'a>(&'a self, symbol: &'a str) -> BoxFuture<'a, Result<StockWidget>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            if symbol == "ZZZZ" {
+                return async move { Err(anyhow!("unknown symbol")) }.boxed();
+            }
+
+            let widget = StockWidget {
+                symbol: symbol.to_string(),
+                price: 100.0,
+                change: -1.0,
+                change_percent: -0.99,
+                currency: Some("USD".to_string()),
+            };
+
+            async move { Ok(widget) }.boxed()
+        }
+    }
+
+    #[test]
+    fn symbol_from_query() {
+        assert_eq!(parse_symbol("aapl stock"), Some("AAPL".to_string()));
+        assert_eq!(parse_symbol("stock msft"), Some("MSFT".to_string()));
+        assert_eq!(parse_symbol("$goog"), Some("GOOG".to_string()));
+        assert_eq!(parse_symbol("brk.b share price"), Some("BRK.B".to_string()));
+        assert_eq!(parse_symbol("aapl"), None);
+        assert_eq!(parse_symbol("how to buy stock"), None);
+        assert_eq!(parse_symbol("stock market news"), None);
+    }
+
+    #[tokio::test]
+    async fn cached_lookups() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stock = Stock::with_provider(
+            Box::new(CountingProvider {
+                calls: Arc::clone(&calls),
+            }),
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+
+        let res = stock.lookup("aapl stock").await.unwrap();
+        assert_eq!(res.symbol, "AAPL");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        stock.lookup("$aapl").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert!(stock.lookup("apple pie").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_are_cached_shorter() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stock = Stock::with_provider(
+            Box::new(CountingProvider {
+                calls: Arc::clone(&calls),
+            }),
+            Duration::from_secs(60),
+            Duration::from_millis(50),
+        );
+
+        assert!(stock.lookup("$zzzz").await.is_none());
+        assert!(stock.lookup("zzzz stock").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(stock.lookup("$zzzz").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
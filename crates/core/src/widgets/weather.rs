@@ -0,0 +1,447 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    config::{WeatherConfig, WeatherProviderConfig},
+    ttl_cache::TTLCache,
+};
+
+const MAX_CACHED_LOCATIONS: usize = 10_000;
+
+static WEATHER_QUERY_REGEX: once_cell::sync::Lazy<regex::Regex> =
+    once_cell::sync::Lazy::new(|| {
+        regex::Regex::new(r"^(?:weather|forecast)\s+(?:in|for|at)\s+(.+)$").unwrap()
+    });
+
+static WEATHER_SUFFIX_REGEX: once_cell::sync::Lazy<regex::Regex> =
+    once_cell::sync::Lazy::new(|| regex::Regex::new(r"^(.+?)\s+(?:weather|forecast)$").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WeatherCondition {
+    Clear,
+    PartlyCloudy,
+    Overcast,
+    Fog,
+    Drizzle,
+    Rain,
+    Snow,
+    Showers,
+    Thunderstorm,
+    Unknown,
+}
+
+impl WeatherCondition {
+    /// Convert a WMO weather interpretation code into a condition.
+    pub fn from_wmo_code(code: u32) -> Self {
+        match code {
+            0 => Self::Clear,
+            1 | 2 => Self::PartlyCloudy,
+            3 => Self::Overcast,
+            45 | 48 => Self::Fog,
+            51..=57 => Self::Drizzle,
+            61..=67 => Self::Rain,
+            71..=77 | 85 | 86 => Self::Snow,
+            80..=82 => Self::Showers,
+            95..=99 => Self::Thunderstorm,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentWeather {
+    pub temperature_celsius: f64,
+    pub wind_speed_kmh: f64,
+    pub condition: WeatherCondition,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyForecast {
+    pub date: String,
+    pub min_celsius: f64,
+    pub max_celsius: f64,
+    pub condition: WeatherCondition,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WeatherWidget {
+    pub location: String,
+    pub country: Option<String>,
+    pub current: CurrentWeather,
+    pub daily: Vec<DailyForecast>,
+}
+
+/// A source of weather forecasts. Implement this to plug
+/// another weather service into the weather widget.
+pub trait WeatherProvider: Send + Sync {
+    fn forecast<'a>(&'a self, location: &'a str) -> BoxFuture<'a, Result<WeatherWidget>>;
+}
+
+pub struct Weather {
+    provider: Box<dyn WeatherProvider>,
+    cache: Mutex<TTLCache<String, WeatherWidget>>,
+    /// Locations the provider failed to find a forecast for.
+    misses: Mutex<TTLCache<String, ()>>,
+}
+
+impl Weather {
+    pub fn new(config: WeatherConfig) -> Self {
+        let provider: Box<dyn WeatherProvider> = match config.provider {
+            WeatherProviderConfig::OpenMeteo {
+                geocoding_url,
+                forecast_url,
+            } => Box::new(OpenMeteo::new(geocoding_url, forecast_url)),
+        };
+
+        Self::with_provider(
+            provider,
+            Duration::from_secs(config.cache_ttl_sec),
+            Duration::from_secs(config.negative_cache_ttl_sec),
+        )
+    }
+
+    pub fn with_provider(
+        provider: Box<dyn WeatherProvider>,
+        ttl: Duration,
+        negative_ttl: Duration,
+    ) -> Self {
+        Self {
+            provider,
+            cache: Mutex::new(TTLCache::with_ttl_and_max_size(
+                ttl,
+                Some(MAX_CACHED_LOCATIONS),
+            )),
+            misses: Mutex::new(TTLCache::with_ttl_and_max_size(
+                negative_ttl,
+                Some(MAX_CACHED_LOCATIONS),
+            )),
+        }
+    }
+
+    pub async fn lookup(&self, query: &str) -> Option<WeatherWidget> {
+        let location = parse_location(query)?;
+
+        if let Some(cached) = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&location)
+        {
+            return Some(cached.clone());
+        }
+
+        if self
+            .misses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&location)
+            .is_some()
+        {
+            return None;
+        }
+
+        match self.provider.forecast(&location).await {
+            Ok(widget) => {
+                self.cache
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(location, widget.clone());
+
+                Some(widget)
+            }
+            Err(err) => {
+                tracing::debug!("failed to get weather for {location}: {err}");
+
+                self.misses
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(location, ());
+
+                None
+            }
+        }
+    }
+}
+
+/// Extract the location from queries like "weather in berlin" or "berlin weather".
+fn parse_location(query: &str) -> Option<String> {
+    let query = query.trim();
+
+    let location = WEATHER_QUERY_REGEX
+        .captures(query)
+        .or_else(|| WEATHER_SUFFIX_REGEX.captures(query))
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().trim().to_string())?;
+
+    if location.is_empty() {
+        None
+    } else {
+        Some(location)
+    }
+}
+
+#[derive(Deserialize)]
+struct GeocodingResponse {
+    #[serde(default)]
+    results: Vec<GeocodingResult>,
+}
+
+#[derive(Deserialize)]
+struct GeocodingResult {
+    name: String,
+    country: Option<String>,
+    latitude: f64,
+    longitude: f64,
+}
+
+#[derive(Deserialize)]
+struct ForecastResponse {
+    current: ForecastCurrent,
+    daily: ForecastDaily,
+}
+
+#[derive(Deserialize)]
+struct ForecastCurrent {
+    temperature_2m: f64,
+    wind_speed_10m: f64,
+    weather_code: u32,
+}
+
+#[derive(Deserialize)]
+struct ForecastDaily {
+    time: Vec<String>,
+    weather_code: Vec<u32>,
+    temperature_2m_max: Vec<f64>,
+    temperature_2m_min: Vec<f64>,
+}
+
+/// Weather provider backed by <https://open-meteo.com>.
+pub struct OpenMeteo {
+    client: reqwest::Client,
+    geocoding_url: String,
+    forecast_url: String,
+}
+
+impl OpenMeteo {
+    pub fn new(geocoding_url: String, forecast_url: String) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap(),
+            geocoding_url,
+            forecast_url,
+        }
+    }
+
+    async fn fetch(&self, location: &str) -> Result<WeatherWidget> {
+        let geo: GeocodingResponse = self
+            .client
+            .get(&self.geocoding_url)
+            .query(&[("name", location), ("count", "1"), ("format", "json")])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        let place = geo
+            .results
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("unknown location: {location}"))?;
+
+        let forecast: ForecastResponse = self
+            .client
+            .get(&self.forecast_url)
+            .query(&[
+                ("latitude", place.latitude.to_string()),
+                ("longitude", place.longitude.to_string()),
+                (
+                    "current",
+                    "temperature_2m,wind_speed_10m,weather_code".to_string(),
+                ),
+                (
+                    "daily",
+                    "weather_code,temperature_2m_max,temperature_2m_min".to_string(),
+                ),
+                ("timezone", "auto".to_string()),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        let daily = forecast
+            .daily
+            .time
+            .into_iter()
+            .zip(forecast.daily.weather_code)
+            .zip(forecast.daily.temperature_2m_min)
+            .zip(forecast.daily.temperature_2m_max)
+            .map(|(((date, code), min), max)| DailyForecast {
+                date,
+                min_celsius: min,
+                max_celsius: max,
+                condition: WeatherCondition::from_wmo_code(code),
+            })
+            .collect();
+
+        Ok(WeatherWidget {
+            location: place.name,
+            country: place.country,
+            current: CurrentWeather {
+                temperature_celsius: forecast.current.temperature_2m,
+                wind_speed_kmh: forecast.current.wind_speed_10m,
+                condition: WeatherCondition::from_wmo_code(forecast.current.weather_code),
+            },
+            daily,
+        })
+    }
+}
+
+impl WeatherProvider for OpenMeteo {
+    fn forecast<'a>(&'a self, location: &'a str) -> BoxFuture<'a, Result<WeatherWidget>> {
+        self.fetch(location).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl WeatherProvider for CountingProvider {
+        fn forecast<'a>(&'a self, location: &'a str) -> BoxFuture<'a, Result<WeatherWidget>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            if location == "atlantis" {
+                return async move { Err(anyhow!("unknown location")) }.boxed();
+            }
+
+            let widget = WeatherWidget {
+                location: location.to_string(),
+                country: None,
+                current: CurrentWeather {
+                    temperature_celsius: 20.0,
+                    wind_speed_kmh: 5.0,
+                    condition: WeatherCondition::Clear,
+                },
+                daily: Vec::new(),
+            };
+
+            async move { Ok(widget) }.boxed()
+        }
+    }
+
+    #[test]
+    fn location_from_query() {
+        assert_eq!(
+            parse_location("weather in berlin"),
+            Some("berlin".to_string())
+        );
+        assert_eq!(
+            parse_location("weather for new york"),
+            Some("new york".to_string())
+        );
+        assert_eq!(
+            parse_location("copenhagen weather"),
+            Some("copenhagen".to_string())
+        );
+        assert_eq!(parse_location("weather"), None);
+        assert_eq!(parse_location("weather in"), None);
+        assert_eq!(parse_location("how does weather work"), None);
+    }
+
+    #[test]
+    fn wmo_codes() {
+        assert_eq!(WeatherCondition::from_wmo_code(0), WeatherCondition::Clear);
+        assert_eq!(WeatherCondition::from_wmo_code(63), WeatherCondition::Rain);
+        assert_eq!(WeatherCondition::from_wmo_code(75), WeatherCondition::Snow);
+        assert_eq!(
+            WeatherCondition::from_wmo_code(96),
+            WeatherCondition::Thunderstorm
+        );
+        assert_eq!(
+            WeatherCondition::from_wmo_code(1000),
+            WeatherCondition::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn cached_lookups() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let weather = Weather::with_provider(
+            Box::new(CountingProvider {
+                calls: Arc::clone(&calls),
+            }),
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+
+        let res = weather.lookup("weather in berlin").await.unwrap();
+        assert_eq!(res.location, "berlin");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        weather.lookup("weather in berlin").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert!(weather.lookup("not a weather query").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        weather.lookup("aarhus weather").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_are_cached_shorter() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let weather = Weather::with_provider(
+            Box::new(CountingProvider {
+                calls: Arc::clone(&calls),
+            }),
+            Duration::from_secs(60),
+            Duration::from_millis(50),
+        );
+
+        assert!(weather.lookup("weather in atlantis").await.is_none());
+        assert!(weather.lookup("weather in atlantis").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(weather.lookup("weather in atlantis").await.is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
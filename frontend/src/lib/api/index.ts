@@ -188,7 +188,37 @@ export const api = {
     ),
 };
 
+export type Annotation =
+  | {
+      type: 'malware';
+      value: {
+        source: string;
+      };
+    }
+  | {
+      type: 'phishing';
+      value: {
+        source: string;
+      };
+    }
+  | {
+      type: 'factCheck';
+      value: {
+        publisher: string;
+        rating: string;
+        url: string;
+      };
+    }
+  | {
+      type: 'notice';
+      value: {
+        source: string;
+        text: string;
+      };
+    };
 export type ApiSearchQuery = {
+  accessibleOnly?: boolean;
+  clientId?: string;
   countResults?: boolean;
   flattenResponse?: boolean;
   hostRankings?: HostRankings;
@@ -233,6 +263,17 @@ export type CodeOrText =
       type: 'text';
       value: string;
     };
+export type CurrentWeather = {
+  condition: WeatherCondition;
+  temperatureCelsius: number;
+  windSpeedKmh: number;
+};
+export type DailyForecast = {
+  condition: WeatherCondition;
+  date: string;
+  maxCelsius: number;
+  minCelsius: number;
+};
 export type Definition = string;
 export type DisplayedAnswer = {
   answer: string;
@@ -241,6 +282,10 @@ export type DisplayedAnswer = {
   title: string;
   url: string;
 };
+export type DisplayedCode = {
+  language: string;
+  snippet?: string;
+};
 export type DisplayedEntity = {
   imageId?: string;
   info: string & EntitySnippet[][];
@@ -262,14 +307,21 @@ export type DisplayedSidebar =
       };
     };
 export type DisplayedWebpage = {
+  annotations: Annotation[];
+  breadcrumb?: string[];
+  code?: DisplayedCode;
   domain: string;
+  insecure: boolean;
   likelyHasAds: boolean;
   likelyHasPaywall: boolean;
   prettyUrl: string;
   rankingSignals?: {};
+  relevance?: number;
+  richResult?: RichResult;
   score?: number;
   site: string;
   snippet: Snippet;
+  thumbnail?: string;
   title: string;
   url: string;
 };
@@ -287,6 +339,10 @@ export type EntitySnippetFragment =
       text: string;
     };
 export type Example = string;
+export type ExperimentArm = {
+  arm: string;
+  experiment: string;
+};
 export type ExploreExportOpticParams = {
   chosenHosts: string[];
   similarHosts: string[];
@@ -332,8 +388,39 @@ export type PartOfSpeechMeaning = {
   meanings: WordMeaning[];
   pos: PartOfSpeech;
 };
+export type Price = {
+  amount: string;
+  currency?: string;
+};
+export type Rating = {
+  best: number;
+  count?: number;
+  value: number;
+};
 export type Region = 'All' | 'Denmark' | 'France' | 'Germany' | 'Spain' | 'US';
 export const REGIONS = ['All', 'Denmark', 'France', 'Germany', 'Spain', 'US'] satisfies Region[];
+export type RelatedQuestion = {
+  answer: TextSnippet;
+  question: string;
+  title: string;
+  url: string;
+};
+export type RichResult =
+  | {
+      cookTime?: string;
+      rating?: Rating;
+      type: 'recipe';
+    }
+  | {
+      price?: Price;
+      rating?: Rating;
+      type: 'product';
+    }
+  | {
+      location?: string;
+      startDate?: string;
+      type: 'event';
+    };
 export type ScoredHost = {
   description?: string;
   host: string;
@@ -374,6 +461,13 @@ export type StackOverflowAnswer = {
 export type StackOverflowQuestion = {
   body: CodeOrText[];
 };
+export type StockWidget = {
+  change: number;
+  changePercent: number;
+  currency?: string;
+  price: number;
+  symbol: string;
+};
 export type Suggestion = {
   highlighted: string;
   raw: string;
@@ -395,9 +489,40 @@ export type ThesaurusWidget = {
   term: Lemma;
 };
 export type UrlWrapper = string;
+export type WeatherCondition =
+  | 'clear'
+  | 'partlyCloudy'
+  | 'overcast'
+  | 'fog'
+  | 'drizzle'
+  | 'rain'
+  | 'snow'
+  | 'showers'
+  | 'thunderstorm'
+  | 'unknown';
+export const WEATHER_CONDITIONS = [
+  'clear',
+  'partlyCloudy',
+  'overcast',
+  'fog',
+  'drizzle',
+  'rain',
+  'snow',
+  'showers',
+  'thunderstorm',
+  'unknown',
+] satisfies WeatherCondition[];
+export type WeatherWidget = {
+  country?: string;
+  current: CurrentWeather;
+  daily: DailyForecast[];
+  location: string;
+};
 export type WebsitesResult = {
+  experiment?: ExperimentArm;
   hasMoreResults: boolean;
   numHits?: number;
+  relatedQuestions: RelatedQuestion[];
   searchDurationMs: number;
   webpages: DisplayedWebpage[];
 };
@@ -409,6 +534,14 @@ export type Widget =
   | {
       type: 'thesaurus';
       value: ThesaurusWidget;
+    }
+  | {
+      type: 'weather';
+      value: WeatherWidget;
+    }
+  | {
+      type: 'stock';
+      value: StockWidget;
     };
 export type WidgetQuery = {
   query: string;
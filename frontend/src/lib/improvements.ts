@@ -1,20 +1,30 @@
 import type { Action } from 'svelte/action';
-import { getApiBase, type ApiOptions, type DisplayedWebpage, requestPlain } from './api';
+import {
+  getApiBase,
+  type ApiOptions,
+  type DisplayedWebpage,
+  type ExperimentArm,
+  requestPlain,
+} from './api';
 import { allowStatsStore, queryIdStore } from './stores';
 
 export const updateQueryId = async ({
   query,
   webpages,
+  experiment,
 }: {
   query: string;
   webpages: DisplayedWebpage[];
+  experiment?: ExperimentArm;
 }) => {
   let allowStats: boolean | undefined;
   allowStatsStore.subscribe((allow) => (allowStats = allow));
 
   if (!allowStats) return;
 
-  queryIdStore.set(await queryId({ query, urls: webpages.map((wp) => wp.url) }).data);
+  queryIdStore.set(
+    await queryId({ query, urls: webpages.map((wp) => wp.url), experiment }).data,
+  );
 };
 
 export const improvements: Action<HTMLAnchorElement, number> = (node, webpageIndex) => {
@@ -36,8 +46,10 @@ export const improvements: Action<HTMLAnchorElement, number> = (node, webpageInd
   };
 };
 
-const queryId = ({ query, urls }: { query: string; urls: string[] }, options?: ApiOptions) =>
-  requestPlain('POST', '/improvement/store', { query, urls }, options);
+const queryId = (
+  { query, urls, experiment }: { query: string; urls: string[]; experiment?: ExperimentArm },
+  options?: ApiOptions,
+) => requestPlain('POST', '/improvement/store', { query, urls, experiment }, options);
 
 const sendImprovementClick = (
   { queryId, clickIndex }: { queryId: string; clickIndex: number },
@@ -47,3 +59,10 @@ const sendImprovementClick = (
     `${getApiBase(options)}/improvement/click?qid=${queryId}&click=${clickIndex}`,
   );
 };
+
+export type FeedbackKind = 'spam' | 'irrelevant' | 'offensive';
+
+export const sendFeedback = (
+  { query, url, kind }: { query: string; url: string; kind: FeedbackKind },
+  options?: ApiOptions,
+) => requestPlain('POST', '/feedback', { query, url, kind }, options);
@@ -15,6 +15,7 @@ export type SearchParams = {
   optic: string | undefined;
   selectedRegion: Region | undefined;
   safeSearch: boolean;
+  accessibleOnly: boolean;
   compressedhost_rankings: string | null;
   host_rankings: RankedSites | undefined;
 };
@@ -39,6 +40,7 @@ export const extractSearchParams = (searchParams: URLSearchParams | FormData): S
     | Region
     | undefined;
   const safeSearch = (searchParams.get('ss') as string | undefined) == 'true';
+  const accessibleOnly = (searchParams.get('acc') as string | undefined) == 'true';
   const compressedhost_rankings = (searchParams.get('sr') as string | undefined) || null;
   const host_rankings = compressedhost_rankings
     ? decompressRanked(compressedhost_rankings)
@@ -50,6 +52,7 @@ export const extractSearchParams = (searchParams: URLSearchParams | FormData): S
     optic,
     selectedRegion,
     safeSearch,
+    accessibleOnly,
     compressedhost_rankings,
     host_rankings,
   };
@@ -9,7 +9,11 @@ import type { Actions } from './$types';
 import { api } from '$lib/api';
 import { fetchRemoteOptic } from '$lib/optics';
 
-export const load = async ({ locals, fetch, url, getClientAddress }) => {
+// random id that keeps the client in the same arm of the ranking experiments
+const CLIENT_ID_COOKIE = 'clientId';
+const CLIENT_ID_MAX_AGE_SEC = 60 * 60 * 24 * 30;
+
+export const load = async ({ locals, fetch, url, getClientAddress, cookies }) => {
   const searchParams: SearchParams | undefined =
     (locals['form'] && extractSearchParams(locals['form'])) || undefined;
 
@@ -28,6 +32,17 @@ export const load = async ({ locals, fetch, url, getClientAddress }) => {
     redirect(301, '/');
   }
 
+  let clientId = cookies.get(CLIENT_ID_COOKIE);
+  if (!clientId) {
+    clientId = crypto.randomUUID();
+    cookies.set(CLIENT_ID_COOKIE, clientId, {
+      path: '/',
+      maxAge: CLIENT_ID_MAX_AGE_SEC,
+      httpOnly: true,
+      sameSite: 'lax',
+    });
+  }
+
   const start = Date.now();
 
   const { data: websitesReq } = api.search(
@@ -35,10 +50,12 @@ export const load = async ({ locals, fetch, url, getClientAddress }) => {
       query: params.query,
       page: params.currentPage - 1,
       safeSearch: params.safeSearch,
+      accessibleOnly: params.accessibleOnly,
       optic: params.optic && (await fetchRemoteOptic({ opticUrl: params.optic, fetch })),
       selectedRegion: params.selectedRegion,
       hostRankings: params.host_rankings,
       countResults: true,
+      clientId,
     },
     { fetch, headers: { 'X-Forwarded-For': getClientAddress() } },
   );